@@ -0,0 +1,117 @@
+//! The local lookup hot path, timed against synthetic records of a few sizes:
+//!
+//! ```bash
+//! cargo bench --bench lookup
+//! cargo bench --bench lookup -- wildcard   # only cases whose name contains "wildcard"
+//! ```
+//!
+//! The records come from `localdns::synthetic::cache`, with 100 or 10k exact
+//! names and 10 or 1k wildcards, so every size answers the same queries.
+//! `exact`, `wildcard` and `miss` time `server::answer` on an already parsed
+//! request: the map lookups, plus building the response `Message`. `bytes`
+//! times a whole UDP query, from the request's wire format to the encoded
+//! response, which is the work a listener does per packet minus the socket;
+//! `reused` is the same round encoding into one buffer kept across calls, as
+//! the UDP listeners do with their pooled buffers, so the gap between the two
+//! is what those allocations cost.
+//!
+//! Each case prints the median time per call over 10 samples of about 100ms,
+//! with the fastest and slowest sample. Compare medians between runs on the
+//! same machine, and only trust differences larger than the min-max spread.
+//! The exact and wildcard lookups should hardly change from the small caches
+//! to the large ones; if one grows with the number of records, something
+//! has started scanning them.
+
+use hickory_proto::op::{Message, Query};
+use hickory_proto::rr::{Name, RecordType};
+use localdns::config::Config;
+use localdns::server::{answer, encode_udp, encode_udp_into, ResolveOptions};
+use localdns::synthetic;
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+const CONFIG: &str = r#"
+listen_address = "127.0.0.1"
+dhcp_lease_file = "/nonexistent/leases"
+hosts_file = "/nonexistent/hosts"
+domain_suffix = "lan"
+nsid = ""
+"#;
+
+const SIZES: [(usize, usize); 4] = [(100, 10), (100, 1000), (10_000, 10), (10_000, 1000)];
+const SAMPLES: usize = 10;
+const SAMPLE_TIME: Duration = Duration::from_millis(100);
+
+fn query(name: &str) -> Message {
+    let mut request = Message::new();
+    request.set_id(1).set_recursion_desired(true);
+    request.add_query(Query::query(Name::from_ascii(name).unwrap(), RecordType::A));
+    request
+}
+
+/// Prints the median, fastest and slowest time per call of `f`, unless a
+/// filter was given on the command line and `name` doesn't contain it.
+fn measure(filter: Option<&str>, name: &str, mut f: impl FnMut()) {
+    if filter.is_some_and(|filter| !name.contains(filter)) {
+        return;
+    }
+    // Calls per sample, from how long one takes once warmed up
+    let start = Instant::now();
+    let mut warmup = 0u32;
+    while start.elapsed() < SAMPLE_TIME {
+        f();
+        warmup += 1;
+    }
+    let iterations = warmup.max(1);
+    let mut samples: Vec<Duration> = (0..SAMPLES)
+        .map(|_| {
+            let start = Instant::now();
+            for _ in 0..iterations {
+                f();
+            }
+            start.elapsed() / iterations
+        })
+        .collect();
+    samples.sort();
+    println!("{:<36} {:>10?}  [{:?} .. {:?}]", name, samples[SAMPLES / 2], samples[0], samples[SAMPLES - 1]);
+}
+
+fn main() {
+    // `cargo bench` passes `--bench`; anything else is a filter
+    let filter = std::env::args().skip(1).find(|arg| !arg.starts_with("--"));
+    let filter = filter.as_deref();
+    let options = ResolveOptions::from_config(&toml::from_str::<Config>(CONFIG).unwrap());
+
+    for (exact, wildcards) in SIZES {
+        let records = synthetic::cache(exact, wildcards);
+        let size = format!("{}/{}", exact, wildcards);
+        let hit = query(&synthetic::exact_name(exact / 2));
+        let covered = query(&synthetic::wildcard_name(wildcards / 2));
+        let missing = query(&synthetic::missing_name());
+
+        measure(filter, &format!("exact {}", size), || {
+            black_box(answer(black_box(&hit), &records, &options).unwrap());
+        });
+        measure(filter, &format!("wildcard {}", size), || {
+            black_box(answer(black_box(&covered), &records, &options).unwrap());
+        });
+        measure(filter, &format!("miss {}", size), || {
+            black_box(answer(black_box(&missing), &records, &options).unwrap());
+        });
+        let bytes = hit.to_vec().unwrap();
+        measure(filter, &format!("bytes {}", size), || {
+            let request = Message::from_vec(black_box(&bytes)).unwrap();
+            let (response, _) = answer(&request, &records, &options).unwrap();
+            black_box(encode_udp(&response, 512).unwrap());
+        });
+        let mut buffer = bytes.clone();
+        measure(filter, &format!("reused {}", size), || {
+            let request = Message::from_vec(black_box(&buffer)).unwrap();
+            let (response, _) = answer(&request, &records, &options).unwrap();
+            encode_udp_into(&response, 512, &mut buffer).unwrap();
+            // Back to the request for the next round, as the next packet would be
+            buffer.clear();
+            buffer.extend_from_slice(&bytes);
+        });
+    }
+}
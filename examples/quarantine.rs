@@ -0,0 +1,83 @@
+//! Embeds localdns with two extra middlewares: one refusing quarantined
+//! clients, and one answering a few names from an in-memory registry before
+//! the file-based records are consulted.
+//!
+//! cargo run --example quarantine -- config.toml 192.168.1.66
+
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr};
+use std::path::PathBuf;
+use std::sync::Arc;
+use tokio::sync::RwLock;
+use hickory_proto::op::ResponseCode;
+use hickory_proto::rr::rdata::A;
+use hickory_proto::rr::{RData, Record, RecordType};
+use localdns::config::Config;
+use localdns::loader::{self, name_key};
+use localdns::middleware::{BoxFuture, Chain, Middleware, Next, QueryContext};
+use localdns::server::{self, response_for, AnswerSource, ResolveOptions};
+
+/// Refuses every query from the listed clients.
+struct Quarantine(Vec<IpAddr>);
+
+impl Middleware for Quarantine {
+    fn handle<'a>(&'a self, ctx: &'a QueryContext<'a>, next: Next<'a>) -> BoxFuture<'a> {
+        Box::pin(async move {
+            match ctx.client {
+                Some(client) if self.0.contains(&client.ip()) => {
+                    let mut response = response_for(ctx.request);
+                    response.set_response_code(ResponseCode::Refused);
+                    Ok((response, AnswerSource::Middleware))
+                }
+                _ => next.run(ctx).await,
+            }
+        })
+    }
+}
+
+/// Answers A queries for registered names, passing everything else on.
+struct Registry(HashMap<String, Ipv4Addr>);
+
+impl Middleware for Registry {
+    fn handle<'a>(&'a self, ctx: &'a QueryContext<'a>, next: Next<'a>) -> BoxFuture<'a> {
+        Box::pin(async move {
+            let Some(query) = ctx.request.queries().first() else {
+                return next.run(ctx).await;
+            };
+            match self.0.get(&name_key(query.name())) {
+                Some(ip) if query.query_type() == RecordType::A => {
+                    let mut response = response_for(ctx.request);
+                    response.add_answer(Record::from_rdata(query.name().clone(), ctx.options.ttl, RData::A(A(*ip))));
+                    Ok((response, AnswerSource::Middleware))
+                }
+                _ => next.run(ctx).await,
+            }
+        })
+    }
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    let mut args = std::env::args().skip(1);
+    let config_path = PathBuf::from(args.next().unwrap_or_else(|| "config.toml".to_string()));
+    let quarantined = args.map(|ip| ip.parse()).collect::<Result<Vec<IpAddr>, _>>()?;
+
+    let config = Config::load(&config_path)?;
+    let records = loader::load_records(&config.load_options(&config_path))?;
+
+    let registry = HashMap::from([("registry.service.lan.".to_string(), Ipv4Addr::new(10, 0, 0, 10))]);
+    let mut middlewares = Chain::builtin();
+    // After NSID, so refused and registry answers still carry it; before the file lookup
+    let lookup = middlewares.len() - 1;
+    middlewares.insert(lookup, Arc::new(Quarantine(quarantined)));
+    middlewares.insert(lookup + 1, Arc::new(Registry(registry)));
+
+    let mut options = ResolveOptions::from_config(&config);
+    options.middleware = Chain::new(middlewares);
+
+    let addr = config.listen_addrs()[0].to_string();
+    let socket = server::bind(&addr, config.dscp, config.listen_interface.as_deref()).await?;
+    println!("Listening on {}", addr);
+    server::serve(socket, Arc::new(RwLock::new(records)), options).await?;
+    Ok(())
+}
@@ -0,0 +1,81 @@
+use hmac::{Hmac, Mac};
+use serde::Deserialize;
+use sha2::Sha256;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum AnonymizeMode {
+    /// Record full client addresses
+    #[default]
+    Off,
+    /// Zero the last octet of IPv4 and the last 80 bits of IPv6 addresses
+    Truncate,
+    /// Replace addresses with a keyed HMAC; the key rotates periodically
+    Hash,
+    /// Don't record client addresses at all
+    Drop,
+}
+
+/// Turns client addresses into what may be recorded in logs and stats.
+/// Access control and rate limiting keep using the real address.
+#[derive(Debug)]
+pub struct Anonymizer {
+    mode: AnonymizeMode,
+    rotation: Duration,
+    key: Mutex<(Instant, [u8; 32])>,
+}
+
+impl Anonymizer {
+    pub fn new(mode: AnonymizeMode, rotation: Duration) -> Self {
+        Self {
+            mode,
+            rotation,
+            key: Mutex::new((Instant::now(), rand::random())),
+        }
+    }
+
+    /// The form of `addr` to record, or `None` in `drop` mode.
+    pub fn client(&self, addr: IpAddr) -> Option<String> {
+        match self.mode {
+            AnonymizeMode::Off => Some(addr.to_string()),
+            AnonymizeMode::Truncate => Some(truncate(addr).to_string()),
+            AnonymizeMode::Hash => Some(self.hash(addr)),
+            AnonymizeMode::Drop => None,
+        }
+    }
+
+    pub fn mode(&self) -> AnonymizeMode {
+        self.mode
+    }
+
+    fn hash(&self, addr: IpAddr) -> String {
+        let key = {
+            let mut key = self.key.lock().unwrap();
+            if key.0.elapsed() >= self.rotation {
+                *key = (Instant::now(), rand::random());
+            }
+            key.1
+        };
+        let mut mac = Hmac::<Sha256>::new_from_slice(&key).expect("HMAC accepts any key length");
+        match addr {
+            IpAddr::V4(v4) => mac.update(&v4.octets()),
+            IpAddr::V6(v6) => mac.update(&v6.octets()),
+        }
+        let digest = mac.finalize().into_bytes();
+        // 64 bits are plenty to tell clients apart without being reversible
+        digest[..8].iter().map(|b| format!("{:02x}", b)).collect()
+    }
+}
+
+fn truncate(addr: IpAddr) -> IpAddr {
+    match addr {
+        IpAddr::V4(v4) => {
+            let [a, b, c, _] = v4.octets();
+            IpAddr::V4(Ipv4Addr::new(a, b, c, 0))
+        }
+        IpAddr::V6(v6) => IpAddr::V6(Ipv6Addr::from(u128::from(v6) & !((1u128 << 80) - 1))),
+    }
+}
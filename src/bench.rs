@@ -0,0 +1,311 @@
+use clap::Args;
+use hickory_proto::op::{Message, MessageType, Query};
+use hickory_proto::rr::{Name, RecordType};
+use rand::{Rng, SeedableRng};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::Arc;
+use tokio::net::UdpSocket;
+use tokio::time::{timeout, Duration, Instant};
+
+#[derive(Args, Debug)]
+pub struct BenchArgs {
+    /// Address of the resolver under test
+    #[arg(long, default_value = "127.0.0.1:53")]
+    server: SocketAddr,
+    /// How long to run, e.g. "30s", "500ms" or "2m"
+    #[arg(long, default_value = "10s", value_parser = parse_duration)]
+    duration: Duration,
+    /// Number of queries kept in flight at once
+    #[arg(long, default_value_t = 64)]
+    concurrency: usize,
+    /// Target rate across all workers; unlimited when absent
+    #[arg(long)]
+    qps: Option<u32>,
+    /// File with one query name per line, sent in random order
+    #[arg(long, conflicts_with = "random_subdomains")]
+    names_file: Option<PathBuf>,
+    /// Query random labels under this zone to exercise the wildcard/NXDomain paths
+    #[arg(long)]
+    random_subdomains: Option<String>,
+    /// Query type mix, e.g. "A=80,AAAA=15,TXT=5"
+    #[arg(long, default_value = "A=100", value_parser = parse_qtypes)]
+    qtypes: QtypeMix,
+    /// Per-query timeout
+    #[arg(long, default_value = "2s", value_parser = parse_duration)]
+    timeout: Duration,
+    /// Print the report as JSON
+    #[arg(long)]
+    json: bool,
+}
+
+#[derive(Debug, Clone)]
+struct QtypeMix(Vec<(RecordType, u32)>);
+
+impl QtypeMix {
+    fn pick(&self, rng: &mut impl Rng) -> RecordType {
+        let total: u32 = self.0.iter().map(|(_, w)| w).sum();
+        let mut n = rng.gen_range(0..total);
+        for (qtype, weight) in &self.0 {
+            if n < *weight {
+                return *qtype;
+            }
+            n -= weight;
+        }
+        self.0[0].0
+    }
+}
+
+fn parse_duration(s: &str) -> Result<Duration, String> {
+    let (number, unit) = match s.find(|c: char| !c.is_ascii_digit()) {
+        Some(i) => s.split_at(i),
+        None => (s, "s"),
+    };
+    let n: u64 = number.parse().map_err(|_| format!("invalid duration: {:?}", s))?;
+    match unit {
+        "ms" => Ok(Duration::from_millis(n)),
+        "s" => Ok(Duration::from_secs(n)),
+        "m" => Ok(Duration::from_secs(n * 60)),
+        _ => Err(format!("unknown duration unit in {:?}", s)),
+    }
+}
+
+fn parse_qtypes(s: &str) -> Result<QtypeMix, String> {
+    let mut mix = Vec::new();
+    for part in s.split(',') {
+        let (name, weight) = part.split_once('=').unwrap_or((part, "1"));
+        let qtype = RecordType::from_str(&name.trim().to_uppercase())
+            .map_err(|_| format!("unknown query type: {:?}", name))?;
+        let weight: u32 = weight.trim().parse().map_err(|_| format!("invalid weight in {:?}", part))?;
+        if weight > 0 {
+            mix.push((qtype, weight));
+        }
+    }
+    if mix.is_empty() {
+        return Err("at least one query type needs a non-zero weight".to_string());
+    }
+    Ok(QtypeMix(mix))
+}
+
+enum NameSource {
+    List(Vec<Name>),
+    RandomUnder(Name),
+}
+
+impl NameSource {
+    fn pick(&self, rng: &mut impl Rng) -> Name {
+        match self {
+            NameSource::List(names) => names[rng.gen_range(0..names.len())].clone(),
+            NameSource::RandomUnder(zone) => {
+                let label = format!("{:08x}", rng.gen::<u32>());
+                Name::from_ascii(label).and_then(|n| n.append_domain(zone)).unwrap_or_else(|_| zone.clone())
+            }
+        }
+    }
+}
+
+#[derive(Default)]
+struct WorkerStats {
+    latencies_us: Vec<u64>,
+    rcodes: BTreeMap<String, u64>,
+    timeouts: u64,
+    errors: u64,
+    mismatched: u64,
+}
+
+#[derive(Serialize)]
+struct Report {
+    server: SocketAddr,
+    duration_secs: f64,
+    concurrency: usize,
+    sent: u64,
+    answered: u64,
+    qps: f64,
+    latency_us: Latency,
+    rcodes: BTreeMap<String, u64>,
+    timeouts: u64,
+    errors: u64,
+    mismatched_ids: u64,
+}
+
+#[derive(Serialize)]
+struct Latency {
+    p50: u64,
+    p90: u64,
+    p99: u64,
+    max: u64,
+}
+
+pub async fn run(args: BenchArgs) -> anyhow::Result<()> {
+    let names = if let Some(zone) = &args.random_subdomains {
+        NameSource::RandomUnder(Name::from_ascii(zone)?)
+    } else if let Some(path) = &args.names_file {
+        let content = std::fs::read_to_string(path)?;
+        let names = content
+            .lines()
+            .map(str::trim)
+            .filter(|l| !l.is_empty() && !l.starts_with('#'))
+            .map(Name::from_ascii)
+            .collect::<Result<Vec<_>, _>>()?;
+        if names.is_empty() {
+            anyhow::bail!("{:?} contains no names", path);
+        }
+        NameSource::List(names)
+    } else {
+        anyhow::bail!("either --names-file or --random-subdomains is required");
+    };
+
+    let names = Arc::new(names);
+    let qtypes = Arc::new(args.qtypes.clone());
+    let concurrency = args.concurrency.max(1);
+    // Each worker keeps one query in flight, so pacing is split evenly between them
+    let interval = args
+        .qps
+        .filter(|qps| *qps > 0)
+        .map(|qps| Duration::from_secs_f64(concurrency as f64 / qps as f64));
+
+    let started = Instant::now();
+    let deadline = started + args.duration;
+    let mut workers = Vec::with_capacity(concurrency);
+    for _ in 0..concurrency {
+        let socket = UdpSocket::bind(if args.server.is_ipv4() { "0.0.0.0:0" } else { "[::]:0" }).await?;
+        socket.connect(args.server).await?;
+        workers.push(tokio::spawn(worker(
+            socket,
+            names.clone(),
+            qtypes.clone(),
+            deadline,
+            interval,
+            args.timeout,
+        )));
+    }
+
+    let mut total = WorkerStats::default();
+    for worker in workers {
+        let stats = worker.await?;
+        total.latencies_us.extend(stats.latencies_us);
+        for (rcode, count) in stats.rcodes {
+            *total.rcodes.entry(rcode).or_default() += count;
+        }
+        total.timeouts += stats.timeouts;
+        total.errors += stats.errors;
+        total.mismatched += stats.mismatched;
+    }
+    let elapsed = started.elapsed().as_secs_f64();
+
+    total.latencies_us.sort_unstable();
+    let percentile = |p: f64| -> u64 {
+        if total.latencies_us.is_empty() {
+            return 0;
+        }
+        let idx = ((total.latencies_us.len() - 1) as f64 * p).round() as usize;
+        total.latencies_us[idx]
+    };
+    let answered = total.latencies_us.len() as u64;
+    let report = Report {
+        server: args.server,
+        duration_secs: elapsed,
+        concurrency,
+        sent: answered + total.timeouts + total.errors,
+        answered,
+        qps: answered as f64 / elapsed,
+        latency_us: Latency {
+            p50: percentile(0.50),
+            p90: percentile(0.90),
+            p99: percentile(0.99),
+            max: total.latencies_us.last().copied().unwrap_or(0),
+        },
+        rcodes: total.rcodes,
+        timeouts: total.timeouts,
+        errors: total.errors,
+        mismatched_ids: total.mismatched,
+    };
+
+    if args.json {
+        println!("{}", serde_json::to_string_pretty(&report)?);
+    } else {
+        print_report(&report);
+    }
+    Ok(())
+}
+
+async fn worker(
+    socket: UdpSocket,
+    names: Arc<NameSource>,
+    qtypes: Arc<QtypeMix>,
+    deadline: Instant,
+    interval: Option<Duration>,
+    query_timeout: Duration,
+) -> WorkerStats {
+    let mut stats = WorkerStats::default();
+    let mut rng = rand::rngs::StdRng::from_entropy();
+    let mut ticker = interval.map(tokio::time::interval);
+    let mut buf = [0u8; 4096];
+
+    while Instant::now() < deadline {
+        if let Some(ticker) = ticker.as_mut() {
+            ticker.tick().await;
+        }
+
+        let id: u16 = rng.gen();
+        let mut request = Message::new();
+        request.set_id(id);
+        request.set_recursion_desired(true);
+        request.add_query(Query::query(names.pick(&mut rng), qtypes.pick(&mut rng)));
+        let bytes = match request.to_vec() {
+            Ok(b) => b,
+            Err(_) => {
+                stats.errors += 1;
+                continue;
+            }
+        };
+
+        let sent_at = Instant::now();
+        if socket.send(&bytes).await.is_err() {
+            stats.errors += 1;
+            continue;
+        }
+
+        // Late answers to earlier, timed-out queries are skipped until ours arrives
+        let outcome = timeout(query_timeout, async {
+            loop {
+                let len = socket.recv(&mut buf).await?;
+                match Message::from_vec(&buf[..len]) {
+                    Ok(m) if m.id() == id && m.message_type() == MessageType::Response => return Ok(m),
+                    _ => stats.mismatched += 1,
+                }
+            }
+        })
+        .await;
+
+        match outcome {
+            Ok(Ok(response)) => {
+                stats.latencies_us.push(sent_at.elapsed().as_micros() as u64);
+                *stats.rcodes.entry(response.response_code().to_string()).or_default() += 1;
+            }
+            Ok(Err::<_, std::io::Error>(_)) => stats.errors += 1,
+            Err(_) => stats.timeouts += 1,
+        }
+    }
+    stats
+}
+
+fn print_report(r: &Report) {
+    println!("Server:       {}", r.server);
+    println!("Duration:     {:.2}s", r.duration_secs);
+    println!("Concurrency:  {}", r.concurrency);
+    println!("Sent:         {}", r.sent);
+    println!("Answered:     {} ({:.0} qps)", r.answered, r.qps);
+    println!(
+        "Latency (us): p50={} p90={} p99={} max={}",
+        r.latency_us.p50, r.latency_us.p90, r.latency_us.p99, r.latency_us.max
+    );
+    let rcodes: Vec<String> = r.rcodes.iter().map(|(k, v)| format!("{}={}", k, v)).collect();
+    println!("Rcodes:       {}", rcodes.join(" "));
+    println!("Timeouts:     {}", r.timeouts);
+    println!("Errors:       {}", r.errors);
+    println!("Mismatched:   {}", r.mismatched_ids);
+}
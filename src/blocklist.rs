@@ -0,0 +1,237 @@
+use hickory_proto::op::{Message, ResponseCode};
+use hickory_proto::rr::rdata::{A, AAAA};
+use hickory_proto::rr::{DNSClass, LowerName, RData, Record, RecordType};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::loader::{name_key, wildcard_covers, DnsCache, Entry};
+use crate::server::{response_for, ResolveOptions};
+
+/// How names listed at `0.0.0.0` or `::`, as in adblock hosts lists, are answered.
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum BlocklistMode {
+    /// Not blocks at all: the unspecified address is an ordinary record
+    #[default]
+    Off,
+    /// The name doesn't exist
+    Nxdomain,
+    /// The name exists but has no records
+    Nodata,
+    /// A and AAAA queries get the `blocklist_sinkhole` addresses of their family
+    Sinkhole,
+}
+
+/// How many of the latest blocks `Blocks::recent` keeps.
+pub const RECENT_BLOCKS: usize = 256;
+
+/// The blocklist settings every query is checked against.
+#[derive(Debug, Clone, Default)]
+pub struct Blocklist {
+    pub mode: BlocklistMode,
+    pub sinkhole: Vec<IpAddr>,
+    /// Shared by every view, and kept across reloads
+    pub blocks: Arc<Blocks>,
+}
+
+/// One query answered as a block.
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct BlockEvent {
+    /// Seconds since the epoch
+    pub at: u64,
+    /// As the query log would show it
+    pub client: String,
+    pub name: String,
+    /// The file the matching entry was listed in
+    pub list: String,
+    /// The matching entry, `*.` first for a wildcard, and where in `list` it is
+    pub rule: String,
+    pub line: usize,
+}
+
+/// What blocks have been answered, and the exceptions made to them at
+/// runtime. Exceptions lapse on their own, and neither survives a restart.
+#[derive(Debug, Default)]
+pub struct Blocks {
+    hits: Mutex<BTreeMap<String, u64>>,
+    recent: Mutex<VecDeque<BlockEvent>>,
+    disabled: Mutex<HashMap<String, Instant>>,
+    allowed: Mutex<HashMap<String, Instant>>,
+}
+
+impl Blocks {
+    pub fn record(&self, event: BlockEvent) {
+        *self.hits.lock().unwrap().entry(event.list.clone()).or_insert(0) += 1;
+        let mut recent = self.recent.lock().unwrap();
+        if recent.len() == RECENT_BLOCKS {
+            recent.pop_front();
+        }
+        recent.push_back(event);
+    }
+
+    /// Blocks answered per list since startup.
+    pub fn hits(&self) -> BTreeMap<String, u64> {
+        self.hits.lock().unwrap().clone()
+    }
+
+    /// The latest blocks, newest first.
+    pub fn recent(&self) -> Vec<BlockEvent> {
+        self.recent.lock().unwrap().iter().rev().cloned().collect()
+    }
+
+    /// Stops the entries of `list` from blocking for `ttl`, or again at once for a zero `ttl`.
+    pub fn disable(&self, list: &str, ttl: Duration) {
+        set_until(&self.disabled, list, ttl);
+    }
+
+    /// Answers the name `key` as if it weren't listed for `ttl`, or blocks it again for a zero `ttl`.
+    pub fn allow(&self, key: &str, ttl: Duration) {
+        set_until(&self.allowed, key, ttl);
+    }
+
+    /// The disabled lists, with how long until each blocks again.
+    pub fn disabled(&self) -> BTreeMap<String, Duration> {
+        remaining(&self.disabled)
+    }
+
+    /// The allowed names, with how long until each is blocked again.
+    pub fn allowed(&self) -> BTreeMap<String, Duration> {
+        remaining(&self.allowed)
+    }
+
+    fn is_disabled(&self, list: &str) -> bool {
+        is_live(&self.disabled, list)
+    }
+
+    fn is_allowed(&self, key: &str) -> bool {
+        is_live(&self.allowed, key)
+    }
+}
+
+fn set_until(map: &Mutex<HashMap<String, Instant>>, key: &str, ttl: Duration) {
+    let mut map = map.lock().unwrap();
+    match ttl.is_zero() {
+        true => map.remove(key),
+        false => map.insert(key.to_string(), Instant::now() + ttl),
+    };
+}
+
+/// Whether `key` has an exception that hasn't lapsed, dropping it once it has.
+fn is_live(map: &Mutex<HashMap<String, Instant>>, key: &str) -> bool {
+    let mut map = map.lock().unwrap();
+    match map.get(key) {
+        Some(until) if *until > Instant::now() => true,
+        Some(_) => {
+            map.remove(key);
+            false
+        }
+        None => false,
+    }
+}
+
+fn remaining(map: &Mutex<HashMap<String, Instant>>) -> BTreeMap<String, Duration> {
+    let now = Instant::now();
+    let mut map = map.lock().unwrap();
+    map.retain(|_, until| *until > now);
+    map.iter().map(|(key, until)| (key.clone(), *until - now)).collect()
+}
+
+impl Blocklist {
+    /// Whether loading should set blocks aside, rather than keep them as addresses.
+    pub fn enabled(&self) -> bool {
+        self.mode != BlocklistMode::Off
+    }
+
+    /// The answer to `request` if it names a block: an exact block, or a
+    /// wildcard block more specific than any wildcard with real addresses.
+    /// Names with records of their own are never blocked by a wildcard, and
+    /// neither disabled lists nor allowed names block. Each block is recorded
+    /// in `blocks`, against `client`.
+    pub fn answer(&self, request: &Message, client: Option<SocketAddr>, cache: &DnsCache, options: &ResolveOptions) -> Option<Message> {
+        let query = request.queries().first().filter(|query| query.query_class() == DNSClass::IN)?;
+        let key = LowerName::new(query.name());
+        let view = &options.view;
+        let listed = |entry: &Entry| view.shows(entry) && !self.blocks.is_disabled(&list_name(entry));
+        let exact = cache.blocked.get(&key).and_then(|entries| entries.iter().find(|e| listed(e)));
+        if exact.is_none() {
+            let has_records = cache.exact_matches.get(&key).is_some_and(|entries| entries.iter().any(|e| view.shows(e)))
+                || cache.cnames.get(&key).is_some_and(|alias| view.shows_alias(alias))
+                || cache.txts.get(&key).is_some_and(|txts| txts.iter().any(|txt| view.shows_txt(txt)))
+                || cache.mxs.get(&key).is_some_and(|mxs| mxs.iter().any(|mx| view.shows_mx(mx)))
+                || cache.srvs.get(&key).is_some_and(|srvs| srvs.iter().any(|srv| view.shows_srv(srv)));
+            if has_records || cache.blocked_wildcards.is_empty() {
+                return None;
+            }
+        }
+        // Wildcard blocks and the accounting go by the key form, only built for a candidate
+        let name = name_key(query.name());
+        let (rule, block) = match exact {
+            Some(entry) => (name.clone(), entry),
+            None => {
+                let (pattern, entry) = longest_match(&cache.blocked_wildcards, &name, listed)?;
+                let real = cache.covering_wildcards(&key).find(|(_, entry)| view.shows(entry));
+                if real.is_some_and(|(real, _)| real.len() >= pattern.len()) {
+                    return None;
+                }
+                (pattern.clone(), entry)
+            }
+        };
+        if self.blocks.is_allowed(&name) {
+            tracing::debug!("{} allowed, though listed (from {})", name, block.source);
+            return None;
+        }
+        tracing::debug!("{} blocked (from {})", name, block.source);
+        self.blocks.record(BlockEvent {
+            at: SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs(),
+            client: options.query_log.client(client),
+            name,
+            list: list_name(block),
+            rule,
+            line: block.source.location,
+        });
+
+        let ttl = block
+            .ttl
+            .unwrap_or_else(|| cache.settings.as_ref().map_or(options.ttl, |settings| settings.ttl));
+        let mut response = response_for(request);
+        response.set_response_code(ResponseCode::NoError);
+        match (self.mode, query.query_type()) {
+            (BlocklistMode::Nxdomain, _) => {
+                response.set_response_code(ResponseCode::NXDomain);
+            }
+            (BlocklistMode::Sinkhole, qtype @ (RecordType::A | RecordType::AAAA)) => {
+                for ip in self.sinkhole.iter().filter(|ip| ip.is_ipv6() == (qtype == RecordType::AAAA)) {
+                    let rdata = match ip {
+                        IpAddr::V4(ip) => RData::A(A(*ip)),
+                        IpAddr::V6(ip) => RData::AAAA(AAAA(*ip)),
+                    };
+                    response.add_answer(Record::from_rdata(query.name().clone(), ttl, rdata));
+                }
+            }
+            // NODATA, and the sinkhole's answer to every other type
+            _ => {}
+        }
+        Some(response)
+    }
+}
+
+/// The list `entry` was loaded from, as blocks are counted and disabled by.
+pub fn list_name(entry: &Entry) -> String {
+    entry.source.path.display().to_string()
+}
+
+/// The longest wildcard block covering `name` that is `listed`. Blocks aren't
+/// indexed like real wildcards, as lists rarely have many.
+fn longest_match<'c>(
+    wildcards: &'c [(String, Entry)],
+    name: &str,
+    listed: impl Fn(&Entry) -> bool,
+) -> Option<&'c (String, Entry)> {
+    wildcards
+        .iter()
+        .filter(|(pattern, entry)| wildcard_covers(pattern, name) && listed(entry))
+        .max_by_key(|(pattern, _)| pattern.len())
+}
@@ -0,0 +1,76 @@
+use std::ops::{Deref, DerefMut};
+use std::sync::{Arc, Mutex};
+
+/// Packet buffers kept for reuse, so the UDP receive loop doesn't allocate
+/// one per query. A buffer taken from the pool goes back when it's dropped,
+/// unless `keep` buffers are already idle.
+#[derive(Debug)]
+pub struct BufferPool {
+    free: Mutex<Vec<Vec<u8>>>,
+    size: usize,
+    keep: usize,
+}
+
+impl BufferPool {
+    pub fn new(size: usize, keep: usize) -> Arc<Self> {
+        Arc::new(BufferPool { free: Mutex::new(Vec::with_capacity(keep)), size, keep })
+    }
+
+    /// A buffer `size` bytes long, idle or new. Its contents are whatever the
+    /// last packet left in it.
+    pub fn take(self: &Arc<Self>) -> Buffer {
+        let mut bytes = self.free.lock().unwrap().pop().unwrap_or_default();
+        // Only grows what a shorter response left behind
+        bytes.resize(self.size, 0);
+        Buffer { bytes, pool: Some(self.clone()) }
+    }
+
+    /// Buffers waiting to be taken again.
+    pub fn idle(&self) -> usize {
+        self.free.lock().unwrap().len()
+    }
+
+    fn put(&self, bytes: Vec<u8>) {
+        let mut free = self.free.lock().unwrap();
+        if free.len() < self.keep {
+            free.push(bytes);
+        }
+    }
+}
+
+/// A packet's bytes: a request as received, then the response encoded over
+/// it. Derefs to the `Vec`, and returns it to its pool on drop.
+#[derive(Debug)]
+pub struct Buffer {
+    bytes: Vec<u8>,
+    pool: Option<Arc<BufferPool>>,
+}
+
+impl Deref for Buffer {
+    type Target = Vec<u8>;
+
+    fn deref(&self) -> &Vec<u8> {
+        &self.bytes
+    }
+}
+
+impl DerefMut for Buffer {
+    fn deref_mut(&mut self) -> &mut Vec<u8> {
+        &mut self.bytes
+    }
+}
+
+impl Drop for Buffer {
+    fn drop(&mut self) {
+        if let Some(pool) = self.pool.take() {
+            pool.put(std::mem::take(&mut self.bytes));
+        }
+    }
+}
+
+/// Bytes from elsewhere, which are freed as usual rather than pooled.
+impl From<Vec<u8>> for Buffer {
+    fn from(bytes: Vec<u8>) -> Self {
+        Buffer { bytes, pool: None }
+    }
+}
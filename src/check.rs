@@ -0,0 +1,292 @@
+use std::fmt;
+use std::path::{Path, PathBuf};
+use serde::de::{self, Deserialize, Deserializer, MapAccess, SeqAccess, Visitor};
+use toml::Spanned;
+
+use crate::config::{BindRetryConfig, Config, EmbeddedConfig, HttpConfig, TcpConfig, UnixConfig};
+use crate::connectivity::ConnectivityCheckConfig;
+use crate::ddr::DdrConfig;
+use crate::loader::{load_records, PtrRecordConfig, RecordConfig, RecordLimits};
+use crate::querylog::QueryLogConfig;
+use crate::soa::SoaConfig;
+use crate::view::{ListenerConfig, ViewConfig};
+use crate::watchdog::WatchdogConfig;
+
+/// How much a problem found by `localdns check` matters.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    /// The server would refuse to start, or serve without a source or setting
+    Error,
+    /// Something was left out, and the rest still loads
+    Warning,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Problem {
+    pub severity: Severity,
+    pub message: String,
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.severity {
+            Severity::Error => write!(f, "error: {}", self.message),
+            Severity::Warning => write!(f, "warning: {}", self.message),
+        }
+    }
+}
+
+/// What `check` found, with what loaded when the config did.
+#[derive(Debug, Default)]
+pub struct Report {
+    pub problems: Vec<Problem>,
+    /// Exact names and wildcard patterns loaded, once the config parsed
+    pub loaded: Option<(usize, usize)>,
+}
+
+impl Report {
+    pub fn errors(&self) -> usize {
+        self.problems.iter().filter(|p| p.severity == Severity::Error).count()
+    }
+
+    pub fn warnings(&self) -> usize {
+        self.problems.iter().filter(|p| p.severity == Severity::Warning).count()
+    }
+
+    fn push(&mut self, severity: Severity, message: String) {
+        self.problems.push(Problem { severity, message });
+    }
+}
+
+/// Parses the config at `path` and loads its sources exactly as serving
+/// would, once, collecting every problem instead of only logging it: config
+/// errors and unknown keys, sources that are missing or fail to parse, and
+/// the lines, rows and entries that were skipped.
+pub fn check(path: &PathBuf) -> Report {
+    let mut report = Report::default();
+    let config = match Config::load(path) {
+        Ok(config) => config,
+        Err(e) => {
+            report.push(Severity::Error, e.to_string());
+            return report;
+        }
+    };
+    // It just parsed, so it reads
+    let content = std::fs::read_to_string(path).unwrap_or_default();
+    for (key, line, suggestion) in unknown_keys(&content) {
+        let hint = suggestion.map(|known| format!("; did you mean `{}`?", known)).unwrap_or_default();
+        report.push(Severity::Error, format!("{}:{}: unknown key `{}`, which is ignored{}", path.display(), line, key, hint));
+    }
+
+    let records = match load_records(&config.load_options(path)) {
+        Ok(records) => records,
+        Err(e) => {
+            report.push(Severity::Error, format!("{} (limits_policy is \"fail\")", e));
+            return report;
+        }
+    };
+    for failure in &records.failures {
+        report.push(Severity::Error, failure.to_string());
+    }
+    for skipped in &records.skipped {
+        report.push(Severity::Warning, format!("skipped {}", skipped));
+    }
+    for conflict in &records.conflicts {
+        report.push(Severity::Warning, format!("conflicting addresses for {}", conflict));
+    }
+    report.loaded = Some((records.exact_matches.len(), records.wildcards.len()));
+    report
+}
+
+/// Keys in `content` that no setting reads, as `(dotted key, line, closest
+/// known key)`. Tables whose keys are names, like `[cnames]` and `[views]`,
+/// only have their values checked.
+pub fn unknown_keys(content: &str) -> Vec<(String, usize, Option<&'static str>)> {
+    // A TOML datetime can't be walked with spans, and no setting takes one
+    let Ok(Node::Table(root)) = toml::from_str::<Node>(content) else {
+        return Vec::new();
+    };
+    let mut unknown = Vec::new();
+    let mut check = |prefix: &str, table: &[(Spanned<String>, Node)], known: &'static [&'static str]| {
+        for (key, _) in table {
+            if !known.contains(&key.get_ref().as_str()) {
+                let name = if prefix.is_empty() { key.get_ref().clone() } else { format!("{}.{}", prefix, key.get_ref()) };
+                unknown.push((name, line_of(content, key.span().start), closest(key.get_ref(), known)));
+            }
+        }
+    };
+    check("", &root, fields::<Config>());
+    for (key, node) in &root {
+        let key = key.get_ref().as_str();
+        let known = match key {
+            "bind_retry" => fields::<BindRetryConfig>(),
+            "limits" => fields::<RecordLimits>(),
+            "tcp" => fields::<TcpConfig>(),
+            "embedded" => fields::<EmbeddedConfig>(),
+            "unix" => fields::<UnixConfig>(),
+            "http" => fields::<HttpConfig>(),
+            "query_log" => fields::<QueryLogConfig>(),
+            "connectivity_checks" => fields::<ConnectivityCheckConfig>(),
+            "watchdog" => fields::<WatchdogConfig>(),
+            "ddr" => fields::<DdrConfig>(),
+            "soa" => fields::<SoaConfig>(),
+            "records" => fields::<RecordConfig>(),
+            "ptr_records" => fields::<PtrRecordConfig>(),
+            "listeners" => fields::<ListenerConfig>(),
+            "views" => {
+                if let Node::Table(views) = node {
+                    for (view, node) in views {
+                        if let Node::Table(table) = node {
+                            check(&format!("views.{}", view.get_ref()), table, fields::<ViewConfig>());
+                        }
+                    }
+                }
+                continue;
+            }
+            _ => continue,
+        };
+        match node {
+            Node::Table(table) => check(key, table, known),
+            Node::Array(items) => {
+                for item in items {
+                    if let Node::Table(table) = item {
+                        check(key, table, known);
+                    }
+                }
+            }
+            Node::Value => {}
+        }
+    }
+    unknown
+}
+
+fn line_of(content: &str, offset: usize) -> usize {
+    content[..offset.min(content.len())].matches('\n').count() + 1
+}
+
+/// The known key within two edits of `key`, for typos like `domain_sufix`.
+fn closest(key: &str, known: &[&'static str]) -> Option<&'static str> {
+    known
+        .iter()
+        .map(|candidate| (edit_distance(key, candidate), *candidate))
+        .filter(|(distance, _)| *distance <= 2)
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, candidate)| candidate)
+}
+
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(ca != *cb);
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+/// A TOML document reduced to its keys, with where each one is.
+enum Node {
+    Table(Vec<(Spanned<String>, Node)>),
+    Array(Vec<Node>),
+    Value,
+}
+
+impl<'de> Deserialize<'de> for Node {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct NodeVisitor;
+
+        impl<'de> Visitor<'de> for NodeVisitor {
+            type Value = Node;
+
+            fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                f.write_str("any TOML value")
+            }
+
+            fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Node, A::Error> {
+                let mut table = Vec::new();
+                while let Some(key) = map.next_key::<Spanned<String>>()? {
+                    table.push((key, map.next_value()?));
+                }
+                Ok(Node::Table(table))
+            }
+
+            fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Node, A::Error> {
+                let mut items = Vec::new();
+                while let Some(item) = seq.next_element()? {
+                    items.push(item);
+                }
+                Ok(Node::Array(items))
+            }
+
+            fn visit_bool<E>(self, _: bool) -> Result<Node, E> {
+                Ok(Node::Value)
+            }
+
+            fn visit_i64<E>(self, _: i64) -> Result<Node, E> {
+                Ok(Node::Value)
+            }
+
+            fn visit_u64<E>(self, _: u64) -> Result<Node, E> {
+                Ok(Node::Value)
+            }
+
+            fn visit_f64<E>(self, _: f64) -> Result<Node, E> {
+                Ok(Node::Value)
+            }
+
+            fn visit_str<E>(self, _: &str) -> Result<Node, E> {
+                Ok(Node::Value)
+            }
+        }
+
+        deserializer.deserialize_any(NodeVisitor)
+    }
+}
+
+/// The keys `T` reads, as serde's derive lists them for a struct.
+fn fields<T: for<'de> Deserialize<'de>>() -> &'static [&'static str] {
+    let mut fields: &'static [&'static str] = &[];
+    let _ = T::deserialize(FieldNames(&mut fields));
+    fields
+}
+
+/// A deserializer that only notes the field names it is asked for.
+struct FieldNames<'a>(&'a mut &'static [&'static str]);
+
+impl<'de> Deserializer<'de> for FieldNames<'_> {
+    type Error = de::value::Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, _: V) -> Result<V::Value, Self::Error> {
+        Err(de::Error::custom("not a struct"))
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(
+        self,
+        _: &'static str,
+        fields: &'static [&'static str],
+        _: V,
+    ) -> Result<V::Value, Self::Error> {
+        *self.0 = fields;
+        Err(de::Error::custom("only the field names are wanted"))
+    }
+
+    serde::forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf option unit unit_struct newtype_struct seq tuple
+        tuple_struct map enum identifier ignored_any
+    }
+}
+
+/// The line `localdns check` ends with.
+pub fn summary(path: &Path, report: &Report) -> String {
+    match (report.errors(), report.warnings(), report.loaded) {
+        (0, 0, Some((exact, wildcards))) => {
+            format!("{}: OK, {} names and {} wildcards", path.display(), exact, wildcards)
+        }
+        (errors, warnings, _) => format!("{}: {} error(s), {} warning(s)", path.display(), errors, warnings),
+    }
+}
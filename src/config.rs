@@ -1,31 +1,887 @@
-use serde::Deserialize;
-use std::path::PathBuf;
+use serde::{Deserialize, Deserializer};
+use std::collections::{BTreeMap, HashMap};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
 use std::fs;
-use std::net::Ipv4Addr;
-use anyhow::Context;
+use std::net::{IpAddr, Ipv4Addr};
+use std::sync::Arc;
+use std::time::Duration;
+use hickory_proto::rr::RData;
+
+use crate::anonymize::AnonymizeMode;
+use crate::blocklist::{Blocklist, BlocklistMode};
+use crate::database;
+use crate::connectivity::ConnectivityCheckConfig;
+use crate::ddr::{Ddr, DdrConfig};
+use crate::dscp::Dscp;
+use crate::forward::{self, Forwarder, Upstream, Upstreams};
+use crate::leases::LeaseFormat;
+use crate::loader::{self, LimitsPolicy, LoadOptions, PtrRecordConfig, RecordConfig, RecordLimits, ReservationPrecedence, RetryPolicy, HostnamePolicy, HostnameRules, SynthesizeUnnamed, TtlPolicy};
+use crate::querylog::QueryLogConfig;
+use crate::ratelimit::RateLimitAction;
+use crate::rebind::{RebindAction, RebindGuard};
+use crate::reverse::{ClasslessZone, PtrNames};
+use crate::soa::{Soa, SoaConfig};
+use crate::server::{MultipleQuestions, NegativeResponse};
+use crate::view::{ListenerConfig, ViewConfig, DEFAULT_LISTENER, UNIX_LISTENER};
+use crate::watch::WatchMode;
+use crate::watchdog::WatchdogConfig;
+
+#[derive(Debug, thiserror::Error)]
+pub enum ConfigError {
+    #[error("Failed to read config file {path:?}: {source}")]
+    Io {
+        path: PathBuf,
+        #[source]
+        source: std::io::Error,
+    },
+    #[error("Failed to parse config file {path:?} at line {line}, column {column}: {message}")]
+    Parse {
+        path: PathBuf,
+        line: usize,
+        column: usize,
+        message: String,
+    },
+    #[error("Invalid value for `{field}`: {message}")]
+    Validation {
+        field: &'static str,
+        message: String,
+    },
+}
 
 #[derive(Deserialize, Debug, Clone)]
 pub struct Config {
-    pub listen_address: String,
+    /// One address or a list, each an IP (on `listen_port`) or a socket address
+    /// such as `"[fd00::1]:53"`; see `listen_addrs`
+    #[serde(deserialize_with = "one_or_many")]
+    pub listen_address: Vec<String>,
+    #[serde(default = "default_listen_port")]
     pub listen_port: u16,
+    /// Network interface the UDP and TCP listeners only see traffic through,
+    /// e.g. `"br-lan"` (SO_BINDTODEVICE, Linux only)
+    pub listen_interface: Option<String>,
+    /// UDP receive loops per listen address, each on its own SO_REUSEPORT
+    /// socket on Linux; see `server::bind_workers`
+    #[serde(default = "default_workers")]
+    pub workers: usize,
     pub dhcp_lease_file: PathBuf,
+    #[serde(default)]
+    pub dhcp_lease_format: LeaseFormat,
+    /// Keep serving leases whose expiry has passed, for lease files with bogus timestamps
+    #[serde(default)]
+    pub dhcp_ignore_expiry: bool,
     pub hosts_file: PathBuf,
+    /// More hosts files, loaded after `hosts_file`
+    #[serde(default)]
+    pub hosts_files: Vec<PathBuf>,
+    /// Directory whose `*.hosts` files are loaded after those, in name order
+    pub hosts_dir: Option<PathBuf>,
+    /// RFC 1035 zone files, relative names under `domain_suffix` unless they set `$ORIGIN`
+    #[serde(default)]
+    pub zone_files: Vec<PathBuf>,
     pub domain_suffix: String,
+    /// Unix datagram socket to answer on as well, e.g. for local containers
+    pub listen_unix: Option<PathBuf>,
+    /// Unix stream socket taking `localdns ctl` commands; nothing is bound when unset
+    pub control_socket: Option<PathBuf>,
+    /// Where SIGUSR1 writes a dump of the served records; stdout when unset
+    pub dump_path: Option<PathBuf>,
     #[serde(default = "default_ttl")]
     pub ttl: u32,
+    /// TTL for names from DHCP leases, which churn; `ttl` when unset
+    pub ttl_dhcp: Option<u32>,
+    /// TTL for names from the hosts file; `ttl` when unset
+    pub ttl_hosts: Option<u32>,
     pub fallback_ip: Option<Ipv4Addr>,
+    #[serde(default = "default_read_retry_attempts")]
+    pub read_retry_attempts: u32,
+    #[serde(default = "default_read_retry_backoff_ms")]
+    pub read_retry_backoff_ms: u64,
+    #[serde(default)]
+    pub startup_wait_for_sources: u64,
+    #[serde(default)]
+    pub startup_policy: StartupPolicy,
+    pub snapshot_path: Option<PathBuf>,
+    #[serde(default = "default_max_snapshot_age")]
+    pub max_snapshot_age: u64,
+    /// JSON lines file keeping runtime record changes across restarts
+    pub runtime_journal: Option<PathBuf>,
+    /// Seconds a runtime record lives after it was last added; 0 for ever
+    #[serde(default)]
+    pub runtime_lifetime: u64,
+    #[serde(default)]
+    pub limits_policy: LimitsPolicy,
+    #[serde(default)]
+    pub anonymize_clients: AnonymizeMode,
+    #[serde(default = "default_anonymize_key_rotation")]
+    pub anonymize_key_rotation: u64,
+    #[serde(default)]
+    pub strict_hosts: bool,
+    #[serde(default)]
+    pub synthesize_unnamed: SynthesizeUnnamed,
+    /// Whether DHCP hostnames that aren't valid DNS names are sanitized or skipped
+    #[serde(default)]
+    pub hostname_policy: HostnamePolicy,
+    /// What runs of illegal characters in a DHCP hostname become when sanitized
+    #[serde(default = "default_hostname_replacement")]
+    pub hostname_replacement: String,
+    /// Static DHCP reservations, as Kea JSON or dnsmasq `dhcp-host` lines
+    pub dhcp_reservations_file: Option<PathBuf>,
+    #[serde(default)]
+    pub reservation_precedence: ReservationPrecedence,
+    /// SQLite database to load records from, see `database::DEFAULT_QUERY`
+    pub records_db: Option<PathBuf>,
+    pub records_db_query: Option<String>,
+    /// Seconds between unconditional database reloads, 0 to reload only when it changes
+    #[serde(default)]
+    pub records_db_poll: u64,
+    #[serde(default)]
+    pub ptr_names: PtrNames,
+    #[serde(default)]
+    pub records: Vec<RecordConfig>,
+    #[serde(default)]
+    pub ptr_records: Vec<PtrRecordConfig>,
+    /// Alias name to target, answered with CNAME records
+    #[serde(default)]
+    pub cnames: BTreeMap<String, String>,
+    /// dnsmasq's `address=/domain/ip` lines, without `address=`: the domains
+    /// and everything under them answer with the address, or, with none,
+    /// are never forwarded
+    #[serde(default)]
+    pub addresses: Vec<String>,
+    #[serde(default)]
+    pub classless_reverse: Vec<ClasslessZone>,
+    pub dscp: Option<Dscp>,
+    /// Marks the queries sent to upstreams, which `dscp` leaves alone
+    pub upstream_dscp: Option<Dscp>,
+    pub nsid: Option<String>,
+    /// Answer CHAOS-class `version.bind` and `hostname.bind` queries
+    #[serde(default = "default_expose_version")]
+    pub expose_version: bool,
+    /// Seconds resolvers may cache NXDOMAIN and NODATA answers in our zone; overrides `soa.minimum`
+    pub negative_ttl: Option<u32>,
+    /// Answer with the addresses of every wildcard covering a name, on top of its own,
+    /// instead of only the most specific wildcard for names without addresses
+    #[serde(default)]
+    pub wildcard_merge: bool,
+    /// Answer ANY queries with all of a name's records instead of a single HINFO (RFC 8482)
+    #[serde(default)]
+    pub full_any_answers: bool,
+    /// Start each multi-address answer one place further along, instead of in address order
+    #[serde(default)]
+    pub rotate_answers: bool,
+    /// Most addresses in one answer, 0 for no limit; see `server::cap_answers`
+    #[serde(default)]
+    pub max_answers: usize,
+    /// How names listed at 0.0.0.0 or :: are answered
+    #[serde(default)]
+    pub blocklist_mode: BlocklistMode,
+    /// Addresses blocked names answer with when `blocklist_mode` is `sinkhole`
+    #[serde(default)]
+    pub blocklist_sinkhole: Vec<IpAddr>,
+    /// Queries per second each client address may send over UDP; 0 turns limiting off
+    #[serde(default)]
+    pub rate_limit_qps: u32,
+    /// Queries a client may send at once before `rate_limit_qps` applies; 0 means one second's worth
+    #[serde(default)]
+    pub rate_limit_burst: u32,
+    /// What to do with queries over the rate
+    #[serde(default)]
+    pub rate_limit_action: RateLimitAction,
+    /// UDP queries each receive loop answers at once; ones arriving past it are dropped
+    #[serde(default = "default_max_inflight_queries")]
+    pub max_inflight_queries: usize,
+    /// Resolvers that names outside our zone without records are forwarded to,
+    /// each an IP (on port 53) or a socket address, optionally behind `udp://`
+    /// or `tcp://`; forwarding is off when empty
+    #[serde(default)]
+    pub upstream: Vec<String>,
+    /// Zone name to the resolvers asked for names in it instead of `upstream`, e.g. a VPN's
+    #[serde(default)]
+    pub forward_zones: BTreeMap<String, Vec<String>>,
+    /// Milliseconds an upstream has to answer before the next one is asked
+    #[serde(default = "default_upstream_timeout_ms")]
+    pub upstream_timeout_ms: u64,
+    /// Upstream answers kept in memory, least recently used dropped first; 0 turns caching off
+    #[serde(default = "default_cache_max_entries")]
+    pub cache_max_entries: usize,
+    /// Check upstream answers for private, loopback, link-local and unspecified
+    /// addresses, which DNS rebinding uses to reach devices on the LAN
+    #[serde(default)]
+    pub block_private_upstream: bool,
+    /// What an upstream answer with such an address becomes
+    #[serde(default)]
+    pub rebind_action: RebindAction,
+    /// Zones whose names upstreams may resolve to private addresses, e.g. `plex.direct`
+    #[serde(default)]
+    pub rebind_allow: Vec<String>,
+    /// Answer multicast DNS queries for our `.local` names on 224.0.0.251:5353,
+    /// and keep `.local` names away from `upstream`
+    #[serde(default)]
+    pub mdns: bool,
+    /// How a request asking several questions is answered
+    #[serde(default)]
+    pub multiple_questions: MultipleQuestions,
+    /// What names under `domain_suffix` without any records get: NXDOMAIN or NODATA
+    #[serde(default)]
+    pub negative_response: NegativeResponse,
+    /// UDP payload size offered to EDNS clients, which also caps UDP responses
+    #[serde(default = "default_edns_udp_payload")]
+    pub edns_udp_payload: u16,
+    /// Seconds between `summary` log lines, 0 for none; `stats_interval` works too
+    #[serde(default = "default_summary_interval", alias = "stats_interval")]
+    pub summary_interval: u64,
+    /// `RUST_LOG`-style filter used when that variable isn't set, e.g. `"info,localdns::query=off"`
+    #[serde(default = "default_log_level")]
+    pub log_level: String,
+    /// Seconds between retries of failed sources, and between checks for changes when polling
+    #[serde(default = "default_watch_interval")]
+    pub watch_interval: u64,
+    /// How changes to the source and config files are noticed
+    #[serde(default)]
+    pub watch_mode: WatchMode,
+    #[serde(default)]
+    pub bind_retry: BindRetryConfig,
+    #[serde(default)]
+    pub limits: RecordLimits,
+    /// TTLs for a name and everything under it, over the per-source ones
+    #[serde(default)]
+    pub ttl_overrides: HashMap<String, u32>,
+    #[serde(default)]
+    pub tcp: TcpConfig,
+    #[serde(default)]
+    pub embedded: EmbeddedConfig,
+    #[serde(default)]
+    pub unix: UnixConfig,
+    #[serde(default)]
+    pub http: HttpConfig,
+    /// Address for the Prometheus `/metrics` endpoint; nothing is bound when unset
+    pub metrics_listen: Option<String>,
+    #[serde(default)]
+    pub query_log: QueryLogConfig,
+    #[serde(default)]
+    pub connectivity_checks: ConnectivityCheckConfig,
+    #[serde(default)]
+    pub watchdog: WatchdogConfig,
+    #[serde(default)]
+    pub ddr: DdrConfig,
+    /// Only read so that a config asking for a DNS-over-TLS listener is refused
+    /// instead of served in plain text
+    pub tls: Option<toml::Table>,
+    #[serde(default)]
+    pub soa: SoaConfig,
+    #[serde(default)]
+    pub listeners: Vec<ListenerConfig>,
+    #[serde(default)]
+    pub views: HashMap<String, ViewConfig>,
+}
+
+/// The HTTP listener for the JSON resolver API. Nothing is bound unless `listen` is set.
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct HttpConfig {
+    pub listen: Option<String>,
+    /// Value for `Access-Control-Allow-Origin`, so browser dashboards can call the API
+    pub cors_allow_origin: Option<String>,
+    /// Bearer token that `PUT` and `DELETE /records/{name}` require; they are
+    /// refused while it is unset
+    pub api_token: Option<String>,
+}
+
+/// What to do when a source is missing or fails to read or parse at startup.
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum StartupPolicy {
+    /// Refuse to start
+    Strict,
+    /// Start with whatever loaded and keep retrying the failed sources
+    #[default]
+    Degraded,
+}
+
+/// Retries for binding listeners whose address is still held by something
+/// else, e.g. a previous instance shutting down during boot.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct BindRetryConfig {
+    /// Bind attempts per listener before giving up
+    pub attempts: u32,
+    /// Seconds between attempts
+    pub interval: u64,
+}
+
+impl Default for BindRetryConfig {
+    fn default() -> Self {
+        Self {
+            attempts: 5,
+            interval: 2,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct TcpConfig {
+    pub enabled: bool,
+    /// Seconds without a query before a connection is closed
+    pub idle_timeout: u64,
+    /// Seconds a connection may stay open in total, 0 for no limit
+    pub max_lifetime: u64,
+    /// Queries answered per connection before it is closed, 0 for no limit
+    pub max_queries: u32,
+    /// Open connections across all clients; the idlest is closed when full
+    pub max_connections: usize,
+}
+
+impl Default for TcpConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            idle_timeout: 10,
+            max_lifetime: 300,
+            max_queries: 0,
+            max_connections: 256,
+        }
+    }
+}
+
+/// How the `listen_unix` socket file is set up, and what its clients see.
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct UnixConfig {
+    /// Permission bits, e.g. `0o660`; left to the umask when unset
+    pub mode: Option<u32>,
+    pub uid: Option<u32>,
+    pub gid: Option<u32>,
+    /// Key into `[views]`; the full, default view when unset
+    pub view: Option<String>,
+}
+
+/// A low-footprint profile for small routers: one runtime thread, queries
+/// answered in the receive loop, and smaller buffers.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct EmbeddedConfig {
+    pub enabled: bool,
+    /// Queries per UDP listener answered at once before reading pauses
+    pub max_inflight: usize,
+}
+
+impl Default for EmbeddedConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            max_inflight: 4,
+        }
+    }
+}
+
+/// Accepts a single string as a list of one, as `listen_address` was before lists.
+fn one_or_many<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<String>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany {
+        One(String),
+        Many(Vec<String>),
+    }
+    Ok(match OneOrMany::deserialize(deserializer)? {
+        OneOrMany::One(address) => vec![address],
+        OneOrMany::Many(addresses) => addresses,
+    })
+}
+
+fn default_listen_port() -> u16 {
+    53
+}
+
+fn default_hostname_replacement() -> String {
+    "-".to_string()
 }
 
 fn default_ttl() -> u32 {
     60
 }
 
+fn default_anonymize_key_rotation() -> u64 {
+    86400
+}
+
+fn default_expose_version() -> bool {
+    true
+}
+
+fn default_edns_udp_payload() -> u16 {
+    1232
+}
+
+fn default_upstream_timeout_ms() -> u64 {
+    2000
+}
+
+fn default_cache_max_entries() -> usize {
+    10_000
+}
+
+fn default_summary_interval() -> u64 {
+    300
+}
+
+/// What is logged when neither `log_level` nor `RUST_LOG` says otherwise.
+pub const DEFAULT_LOG_LEVEL: &str = "info";
+
+fn default_log_level() -> String {
+    DEFAULT_LOG_LEVEL.to_string()
+}
+
+fn default_workers() -> usize {
+    1
+}
+
+fn default_max_inflight_queries() -> usize {
+    1024
+}
+
+fn default_watch_interval() -> u64 {
+    5
+}
+
+fn default_max_snapshot_age() -> u64 {
+    7 * 86400
+}
+
+fn default_read_retry_attempts() -> u32 {
+    3
+}
+
+fn default_read_retry_backoff_ms() -> u64 {
+    500
+}
+
+/// `address` as a socket address, taking `port` when it names only an IP.
+fn listen_addr(address: &str, port: u16) -> Option<SocketAddr> {
+    address
+        .parse::<SocketAddr>()
+        .ok()
+        .or_else(|| address.parse::<IpAddr>().ok().map(|ip| SocketAddr::new(ip, port)))
+}
+
+/// The system hostname, as the Linux kernel reports it.
+pub(crate) fn hostname() -> Option<String> {
+    ["/proc/sys/kernel/hostname", "/etc/hostname"]
+        .iter()
+        .filter_map(|path| fs::read_to_string(path).ok())
+        .map(|name| name.trim().to_string())
+        .find(|name| !name.is_empty())
+}
+
 impl Config {
-    pub fn load(path: &PathBuf) -> anyhow::Result<Self> {
-        let content = fs::read_to_string(path)
-            .with_context(|| format!("Failed to read config file: {:?}", path))?;
-        let config: Config = toml::from_str(&content)
-            .context("Failed to parse config file")?;
+    /// The RFC 5001 NSID to answer with: `nsid` if set, else the hostname.
+    /// An empty `nsid` turns the option off.
+    pub fn nsid(&self) -> Option<Vec<u8>> {
+        match &self.nsid {
+            Some(nsid) if nsid.is_empty() => None,
+            Some(nsid) => Some(nsid.as_bytes().to_vec()),
+            None => hostname().map(String::into_bytes),
+        }
+    }
+
+    pub fn load(path: &PathBuf) -> Result<Self, ConfigError> {
+        let content = fs::read_to_string(path).map_err(|source| ConfigError::Io {
+            path: path.clone(),
+            source,
+        })?;
+        let config = Self::parse(path, &content)?;
+        config.validate()?;
         Ok(config)
     }
+
+    /// How to load the record sources; `config_path` is where this config came from.
+    pub fn load_options(&self, config_path: &Path) -> LoadOptions {
+        LoadOptions {
+            dhcp_path: self.dhcp_lease_file.clone(),
+            dhcp_format: self.dhcp_lease_format,
+            dhcp_ignore_expiry: self.dhcp_ignore_expiry,
+            hosts_path: self.hosts_file.clone(),
+            hosts_paths: self.hosts_files.clone(),
+            hosts_dir: self.hosts_dir.clone(),
+            zone_paths: self.zone_files.clone(),
+            suffix: self.domain_suffix.clone(),
+            retry: self.retry_policy(),
+            limits: self.limits.clone(),
+            limits_policy: self.limits_policy,
+            config_path: config_path.to_path_buf(),
+            ptr_records: self.ptr_records.clone(),
+            records: self.records.clone(),
+            cnames: self.cnames.iter().map(|(name, target)| (name.clone(), target.clone())).collect(),
+            addresses: self.addresses.clone(),
+            overlay: Arc::default(),
+            strict_hosts: self.strict_hosts,
+            synthesize_unnamed: self.synthesize_unnamed,
+            hostnames: HostnameRules { policy: self.hostname_policy, replacement: self.hostname_replacement.clone() },
+            records_db: self.records_db.clone(),
+            records_db_query: self.records_db_query.clone().unwrap_or_else(|| database::DEFAULT_QUERY.to_string()),
+            reservations_path: self.dhcp_reservations_file.clone(),
+            reservation_precedence: self.reservation_precedence,
+            ttl: self.ttl_policy(),
+            blocklist: self.blocklist().enabled(),
+        }
+    }
+
+    /// The zone's SOA, with `negative_ttl` as its MINIMUM when set.
+    pub fn soa(&self) -> Result<Soa, String> {
+        let mut soa = self.soa.clone();
+        if let Some(negative_ttl) = self.negative_ttl {
+            soa.minimum = negative_ttl;
+        }
+        Soa::new(&soa, &self.domain_suffix)
+    }
+
+    pub fn blocklist(&self) -> Blocklist {
+        Blocklist { mode: self.blocklist_mode, sinkhole: self.blocklist_sinkhole.clone(), ..Default::default() }
+    }
+
+    /// Validation has already rejected override names that don't parse.
+    fn ttl_policy(&self) -> TtlPolicy {
+        let mut overrides: Vec<(String, u32)> = self
+            .ttl_overrides
+            .iter()
+            .filter_map(|(domain, ttl)| Some((loader::name_key(&loader::parse_presentation(domain).ok()?), *ttl)))
+            .collect();
+        overrides.sort();
+        TtlPolicy {
+            dhcp: self.ttl_dhcp,
+            hosts: self.ttl_hosts,
+            overrides,
+        }
+    }
+
+    pub fn forwarder(&self) -> Forwarder {
+        Forwarder::new(Duration::from_millis(self.upstream_timeout_ms), self.cache_max_entries, self.rebind_guard(), self.upstream_dscp)
+    }
+
+    /// The `block_private_upstream` check, if it is on. Validation has
+    /// already rejected `rebind_allow` zones that don't parse.
+    pub fn rebind_guard(&self) -> Option<RebindGuard> {
+        if !self.block_private_upstream {
+            return None;
+        }
+        let allow = self.rebind_allow.iter().filter_map(|zone| Some(loader::name_key(&loader::parse_presentation(zone).ok()?))).collect();
+        Some(RebindGuard::new(self.rebind_action, allow))
+    }
+
+    /// `upstream` and `[forward_zones]`, with the domains of `addresses`
+    /// entries without an address as zones of no upstreams. Validation has
+    /// already rejected names and addresses that don't parse.
+    pub fn upstreams(&self) -> Upstreams {
+        let parse = |list: &[String]| list.iter().filter_map(|upstream| Upstream::parse(upstream).ok()).collect();
+        let mut zones: HashMap<String, Vec<Upstream>> = self
+            .forward_zones
+            .iter()
+            .filter_map(|(zone, list)| Some((loader::name_key(&loader::parse_presentation(zone).ok()?), parse(list))))
+            .collect();
+        for directive in &self.addresses {
+            if let Ok((domains, None)) = loader::parse_address_directive(directive) {
+                zones.extend(domains.into_iter().map(|domain| (domain, Vec::new())));
+            }
+        }
+        Upstreams { default: parse(&self.upstream), zones }
+    }
+
+    /// The DDR records to serve, none when DDR is off. Validation has
+    /// already rejected configs this would fail on.
+    pub fn ddr(&self) -> Vec<RData> {
+        Ddr::encode(&self.ddr, self.http.listen.as_deref(), self.default_ddr_hint()).unwrap_or_default()
+    }
+
+    /// The first listen address that isn't a wildcard clients can't connect to.
+    fn default_ddr_hint(&self) -> Option<IpAddr> {
+        self.listen_addrs().iter().map(SocketAddr::ip).find(|ip| !ip.is_unspecified())
+    }
+
+    /// The sockets the default listener binds, in `listen_address` order.
+    /// Entries without a port use `listen_port`; ones that don't parse are
+    /// left out, which validation has already rejected.
+    pub fn listen_addrs(&self) -> Vec<SocketAddr> {
+        self.listen_address.iter().filter_map(|address| listen_addr(address, self.listen_port)).collect()
+    }
+
+    pub fn retry_policy(&self) -> RetryPolicy {
+        RetryPolicy {
+            attempts: self.read_retry_attempts,
+            backoff: Duration::from_millis(self.read_retry_backoff_ms),
+            ..RetryPolicy::default()
+        }
+    }
+
+    fn parse(path: &Path, content: &str) -> Result<Self, ConfigError> {
+        toml::from_str(content).map_err(|e| {
+            // toml reports a byte span; convert its start into a 1-based line/column.
+            let offset = e.span().map(|s| s.start).unwrap_or(0).min(content.len());
+            let before = &content[..offset];
+            let line = before.matches('\n').count() + 1;
+            let column = before.len() - before.rfind('\n').map(|i| i + 1).unwrap_or(0) + 1;
+            ConfigError::Parse {
+                path: path.to_path_buf(),
+                line,
+                column,
+                message: e.message().to_string(),
+            }
+        })
+    }
+
+    fn validate(&self) -> Result<(), ConfigError> {
+        if self.listen_address.is_empty() {
+            return Err(ConfigError::Validation {
+                field: "listen_address",
+                message: "needs at least one address".to_string(),
+            });
+        }
+        let mut bound = Vec::new();
+        for address in &self.listen_address {
+            let Some(addr) = listen_addr(address, self.listen_port) else {
+                return Err(ConfigError::Validation {
+                    field: "listen_address",
+                    message: format!("{:?} is neither an IP address nor a socket address", address),
+                });
+            };
+            if bound.contains(&addr) {
+                return Err(ConfigError::Validation {
+                    field: "listen_address",
+                    message: format!("{} is listed more than once", addr),
+                });
+            }
+            bound.push(addr);
+        }
+        if let Some(Err(message)) = self.upstream.iter().map(|upstream| Upstream::parse(upstream)).find(Result::is_err) {
+            return Err(ConfigError::Validation { field: "upstream", message });
+        }
+        for (zone, list) in &self.forward_zones {
+            if let Err(e) = loader::parse_presentation(zone) {
+                return Err(ConfigError::Validation {
+                    field: "forward_zones",
+                    message: format!("invalid zone {:?}: {}", zone, e),
+                });
+            }
+            if list.is_empty() {
+                return Err(ConfigError::Validation {
+                    field: "forward_zones",
+                    message: format!("{:?} needs at least one upstream", zone),
+                });
+            }
+            if let Some(Err(message)) = list.iter().map(|upstream| Upstream::parse(upstream)).find(Result::is_err) {
+                return Err(ConfigError::Validation { field: "forward_zones", message: format!("{}: {}", zone, message) });
+            }
+        }
+        if let Some((zone, e)) = self
+            .rebind_allow
+            .iter()
+            .find_map(|zone| loader::parse_presentation(zone).err().map(|e| (zone, e)))
+        {
+            return Err(ConfigError::Validation { field: "rebind_allow", message: format!("invalid zone {:?}: {}", zone, e) });
+        }
+        if !self.hostname_replacement.chars().all(|c| c.is_ascii_alphanumeric() || c == '-') {
+            return Err(ConfigError::Validation {
+                field: "hostname_replacement",
+                message: format!("{:?} may only hold letters, digits and hyphens", self.hostname_replacement),
+            });
+        }
+        if let Some(interface) = &self.listen_interface {
+            // IFNAMSIZ, with room for the terminating NUL
+            if interface.is_empty() || interface.len() > 15 || interface.contains(['/', '\0']) {
+                return Err(ConfigError::Validation {
+                    field: "listen_interface",
+                    message: format!("{:?} is not an interface name", interface),
+                });
+            }
+            if !cfg!(any(target_os = "linux", target_os = "android")) {
+                return Err(ConfigError::Validation {
+                    field: "listen_interface",
+                    message: "binding to an interface needs SO_BINDTODEVICE, which only Linux has".to_string(),
+                });
+            }
+        }
+        if let Some((directive, e)) = self
+            .addresses
+            .iter()
+            .find_map(|directive| loader::parse_address_directive(directive).err().map(|e| (directive, e)))
+        {
+            return Err(ConfigError::Validation { field: "addresses", message: format!("{:?}: {}", directive, e) });
+        }
+        if self.limits.max_alias_chain == 0 {
+            return Err(ConfigError::Validation {
+                field: "limits.max_alias_chain",
+                message: "must be at least 1".to_string(),
+            });
+        }
+        if !(self.upstream.is_empty() && self.forward_zones.is_empty()) && self.upstream_timeout_ms == 0 {
+            return Err(ConfigError::Validation {
+                field: "upstream_timeout_ms",
+                message: "must be at least 1".to_string(),
+            });
+        }
+        if !(0.0..=1.0).contains(&self.query_log.sample_rate) {
+            return Err(ConfigError::Validation {
+                field: "query_log.sample_rate",
+                message: format!("{} is not between 0.0 and 1.0", self.query_log.sample_rate),
+            });
+        }
+        if self.anonymize_clients == AnonymizeMode::Hash && self.anonymize_key_rotation == 0 {
+            return Err(ConfigError::Validation {
+                field: "anonymize_key_rotation",
+                message: "must be at least 1 second when hashing client addresses".to_string(),
+            });
+        }
+        if self.blocklist_mode == BlocklistMode::Sinkhole && self.blocklist_sinkhole.is_empty() {
+            return Err(ConfigError::Validation {
+                field: "blocklist_sinkhole",
+                message: "needs at least one address when blocklist_mode is \"sinkhole\"".to_string(),
+            });
+        }
+        if let Some(ip) = self.blocklist_sinkhole.iter().find(|ip| ip.is_unspecified()) {
+            return Err(ConfigError::Validation {
+                field: "blocklist_sinkhole",
+                message: format!("{} would be blocked itself", ip),
+            });
+        }
+        if self.edns_udp_payload < 512 {
+            return Err(ConfigError::Validation {
+                field: "edns_udp_payload",
+                message: format!("{} is below the 512 bytes every client accepts", self.edns_udp_payload),
+            });
+        }
+        if self.workers == 0 {
+            return Err(ConfigError::Validation {
+                field: "workers",
+                message: "must be at least 1".to_string(),
+            });
+        }
+        if self.max_inflight_queries == 0 {
+            return Err(ConfigError::Validation {
+                field: "max_inflight_queries",
+                message: "must be at least 1".to_string(),
+            });
+        }
+        if self.bind_retry.attempts == 0 {
+            return Err(ConfigError::Validation {
+                field: "bind_retry.attempts",
+                message: "must be at least 1".to_string(),
+            });
+        }
+        if self.watch_interval == 0 {
+            return Err(ConfigError::Validation {
+                field: "watch_interval",
+                message: "must be at least 1 second".to_string(),
+            });
+        }
+        if self.watchdog.enabled && (self.watchdog.interval == 0 || self.watchdog.failure_threshold == 0) {
+            return Err(ConfigError::Validation {
+                field: "watchdog",
+                message: "interval and failure_threshold must be at least 1".to_string(),
+            });
+        }
+        for (index, record) in self.records.iter().enumerate() {
+            if let Err(e) = record.parse() {
+                return Err(ConfigError::Validation {
+                    field: "records",
+                    message: format!("entry {} ({:?} {} {:?}): {}", index + 1, record.name, record.kind, record.value, e),
+                });
+            }
+        }
+        for record in &self.ptr_records {
+            if let Err(e) = loader::parse_presentation(&record.name) {
+                return Err(ConfigError::Validation {
+                    field: "ptr_records",
+                    message: format!("invalid name {:?} for {}: {}", record.name, record.address, e),
+                });
+            }
+        }
+        for (name, target) in &self.cnames {
+            if let Err(message) = loader::parse_cname_line(&[name, target]) {
+                return Err(ConfigError::Validation {
+                    field: "cnames",
+                    message: format!("{} -> {}: {}", name, target, message),
+                });
+            }
+        }
+        for domain in self.ttl_overrides.keys() {
+            if let Err(e) = loader::parse_presentation(domain) {
+                return Err(ConfigError::Validation {
+                    field: "ttl_overrides",
+                    message: format!("invalid name {:?}: {}", domain, e),
+                });
+            }
+        }
+        if let Err(message) = self.soa() {
+            return Err(ConfigError::Validation { field: "soa", message });
+        }
+        if let Err(message) = Ddr::encode(&self.ddr, self.http.listen.as_deref(), self.default_ddr_hint()) {
+            return Err(ConfigError::Validation { field: "ddr", message });
+        }
+        if self.runtime_lifetime > 0 && self.runtime_journal.is_none() {
+            return Err(ConfigError::Validation {
+                field: "runtime_lifetime",
+                message: "only applies to records kept in runtime_journal".to_string(),
+            });
+        }
+        if self.tls.is_some() {
+            return Err(ConfigError::Validation {
+                field: "tls",
+                message: "DNS-over-TLS listeners aren't supported, as this build has no TLS stack; \
+                    terminate TLS in a proxy in front of the [tcp] listener instead"
+                    .to_string(),
+            });
+        }
+        if self.listen_unix.is_some() && !cfg!(unix) {
+            return Err(ConfigError::Validation {
+                field: "listen_unix",
+                message: "unix sockets aren't available on this platform".to_string(),
+            });
+        }
+        if let Some(view) = self.unix.view.as_ref().filter(|v| !self.views.contains_key(*v)) {
+            return Err(ConfigError::Validation {
+                field: "unix.view",
+                message: format!("{:?} is not a defined view", view),
+            });
+        }
+        let mut names = vec![DEFAULT_LISTENER];
+        if self.listen_unix.is_some() {
+            names.push(UNIX_LISTENER);
+        }
+        for listener in &self.listeners {
+            let invalid = |message: String| ConfigError::Validation { field: "listeners", message };
+            if names.contains(&listener.name.as_str()) {
+                return Err(invalid(format!("the name {:?} is used more than once", listener.name)));
+            }
+            names.push(&listener.name);
+            if listener.address.parse::<SocketAddr>().is_err() {
+                return Err(invalid(format!("{:?} is not a socket address", listener.address)));
+            }
+            if let Some(view) = listener.view.as_ref().filter(|v| !self.views.contains_key(*v)) {
+                return Err(invalid(format!("listener {:?} uses undefined view {:?}", listener.name, view)));
+            }
+        }
+        // Every address queries reach us on, so that no upstream points back at one
+        let ours: Vec<SocketAddr> = bound.iter().copied().chain(self.listeners.iter().filter_map(|l| l.address.parse().ok())).collect();
+        let upstreams = self.upstream.iter().map(|u| ("upstream", u)).chain(self.forward_zones.values().flatten().map(|u| ("forward_zones", u)));
+        for (field, text) in upstreams {
+            let Ok(upstream) = Upstream::parse(text) else { continue };
+            if let Some(listen) = forward::loops_back(upstream.addr, &ours) {
+                return Err(ConfigError::Validation {
+                    field,
+                    message: format!("{:?} is our own listen address {}, so forwarded queries would come straight back", text, listen),
+                });
+            }
+        }
+        if self.records_db.is_some() && !database::SUPPORTED {
+            return Err(ConfigError::Validation {
+                field: "records_db",
+                message: "this build has no SQLite support; rebuild with `--features sqlite`".to_string(),
+            });
+        }
+        if self.read_retry_attempts == 0 {
+            return Err(ConfigError::Validation {
+                field: "read_retry_attempts",
+                message: "must be at least 1".to_string(),
+            });
+        }
+        Ok(())
+    }
 }
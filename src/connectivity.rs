@@ -0,0 +1,90 @@
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::net::Ipv4Addr;
+
+/// Hostnames operating systems and browsers probe to detect captive portals.
+/// Answering these with a catch-all address makes devices report "no internet".
+pub const BUILTIN_DOMAINS: &[&str] = &[
+    "connectivitycheck.gstatic.com",
+    "connectivitycheck.android.com",
+    "clients3.google.com",
+    "www.msftconnecttest.com",
+    "ipv6.msftconnecttest.com",
+    "www.msftncsi.com",
+    "dns.msftncsi.com",
+    "captive.apple.com",
+    "detectportal.firefox.com",
+    "nmcheck.gnome.org",
+    "connectivity-check.ubuntu.com",
+    "network-test.debian.org",
+];
+
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum CheckPolicy {
+    /// Never answered by the catch-all; unknown names get NXDomain as usual
+    #[default]
+    Exempt,
+    /// Answered with the portal IP, for networks that run a captive portal
+    Portal,
+}
+
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct ConnectivityCheckConfig {
+    /// Policy for every listed domain without an override
+    pub policy: CheckPolicy,
+    /// Address returned under the `portal` policy; defaults to `fallback_ip`
+    pub portal_ip: Option<Ipv4Addr>,
+    /// Domains added to the built-in list
+    pub add: Vec<String>,
+    /// Built-in domains to drop from the list
+    pub remove: Vec<String>,
+    /// Per-domain policies, for listed or additional domains
+    pub overrides: HashMap<String, CheckPolicy>,
+}
+
+#[derive(Debug, Clone)]
+pub struct ConnectivityChecks {
+    domains: HashMap<String, CheckPolicy>, // Lowercase, with trailing dot
+    pub portal_ip: Option<Ipv4Addr>,
+}
+
+fn normalize(domain: &str) -> String {
+    format!("{}.", domain.trim().trim_end_matches('.').to_lowercase())
+}
+
+impl ConnectivityChecks {
+    pub fn new(config: &ConnectivityCheckConfig, fallback_ip: Option<Ipv4Addr>) -> Self {
+        let removed: Vec<String> = config.remove.iter().map(|d| normalize(d)).collect();
+        let mut domains: HashMap<String, CheckPolicy> = BUILTIN_DOMAINS
+            .iter()
+            .copied()
+            .chain(config.add.iter().map(String::as_str))
+            .map(normalize)
+            .filter(|d| !removed.contains(d))
+            .map(|d| (d, config.policy))
+            .collect();
+        for (domain, policy) in &config.overrides {
+            domains.insert(normalize(domain), *policy);
+        }
+        Self {
+            domains,
+            portal_ip: config.portal_ip.or(fallback_ip),
+        }
+    }
+
+    /// The policy for a lowercase, dot-terminated name that is a listed domain or below one.
+    pub fn lookup(&self, lookup_name: &str) -> Option<CheckPolicy> {
+        let mut name = lookup_name;
+        loop {
+            if let Some(policy) = self.domains.get(name) {
+                return Some(*policy);
+            }
+            name = &name[name.find('.')? + 1..];
+            if name.is_empty() {
+                return None;
+            }
+        }
+    }
+}
@@ -0,0 +1,279 @@
+use std::io::{BufRead, Write};
+use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{UnixListener, UnixStream};
+use tokio::sync::{mpsc, oneshot, RwLock};
+
+use crate::blocklist::Blocks;
+use crate::dump::{self, DumpFormat};
+use crate::events::{Event, Events};
+use crate::loader::{name_key, parse_address_record, table_key, DnsCache, Entry};
+use crate::overlay::{runtime_key, Overlay, RuntimeRecord};
+use crate::server::ServeError;
+
+/// Sent to the reload loop, which answers once the reloaded records are
+/// served and drops it when they couldn't replace the current ones.
+pub type ReloadRequest = oneshot::Sender<()>;
+
+/// One line of the control protocol.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Request {
+    /// `add-record [--persist-to-hosts] <name> <A|AAAA> <address> [ttl]`
+    AddRecord {
+        name: String,
+        kind: String,
+        value: String,
+        ttl: Option<u32>,
+        /// Append the record to the hosts file instead of keeping it in memory
+        persist: bool,
+    },
+    /// `remove-record <name>`
+    RemoveRecord { name: String },
+    /// `list`
+    List,
+    /// `reload`
+    Reload,
+    /// `dump [text|json]`
+    Dump { format: DumpFormat },
+    /// `blocks`
+    Blocks,
+}
+
+impl Request {
+    pub fn parse(line: &str) -> Result<Self, String> {
+        let mut words: Vec<&str> = line.split_whitespace().collect();
+        let command = if words.is_empty() { "" } else { words.remove(0) };
+        match command {
+            "add-record" => {
+                let persist = words.contains(&"--persist-to-hosts");
+                words.retain(|word| *word != "--persist-to-hosts");
+                let (name, kind, value, ttl) = match words[..] {
+                    [name, kind, value] => (name, kind, value, None),
+                    [name, kind, value, ttl] => {
+                        let ttl = ttl.parse().map_err(|_| format!("{:?} is not a TTL in seconds", ttl))?;
+                        (name, kind, value, Some(ttl))
+                    }
+                    _ => return Err("expected `add-record [--persist-to-hosts] <name> <A|AAAA> <address> [ttl]`".to_string()),
+                };
+                let kind = kind.to_uppercase();
+                if kind != "A" && kind != "AAAA" {
+                    return Err(format!("only A and AAAA records can be added, not {}", kind));
+                }
+                if persist && ttl.is_some() {
+                    return Err("a TTL can't be persisted to the hosts file".to_string());
+                }
+                Ok(Request::AddRecord { name: name.to_string(), kind, value: value.to_string(), ttl, persist })
+            }
+            "remove-record" => match words[..] {
+                [name] => Ok(Request::RemoveRecord { name: name.to_string() }),
+                _ => Err("expected `remove-record <name>`".to_string()),
+            },
+            "dump" => match words[..] {
+                [] => Ok(Request::Dump { format: DumpFormat::Text }),
+                [format] => Ok(Request::Dump { format: format.parse()? }),
+                _ => Err("expected `dump [text|json]`".to_string()),
+            },
+            "list" if words.is_empty() => Ok(Request::List),
+            "reload" if words.is_empty() => Ok(Request::Reload),
+            "blocks" if words.is_empty() => Ok(Request::Blocks),
+            "list" | "reload" | "blocks" => Err(format!("`{}` takes no arguments", command)),
+            _ => Err(format!("unknown command {:?}; expected add-record, remove-record, list, dump, blocks or reload", command)),
+        }
+    }
+}
+
+/// What control commands act on.
+pub struct Control {
+    pub overlay: Arc<Overlay>,
+    pub records: Arc<RwLock<DnsCache>>,
+    pub reloads: mpsc::UnboundedSender<ReloadRequest>,
+    /// Where `--persist-to-hosts` appends records
+    pub hosts_path: PathBuf,
+    pub blocks: Arc<Blocks>,
+    /// Where runtime record changes are published for `/events`
+    pub events: Arc<Events>,
+}
+
+impl Control {
+    /// Carries out `request`, returning the lines to reply with. Changes are
+    /// served by the time it returns: runtime ones are applied to the records
+    /// in place, and the others once a reload has picked them up.
+    pub async fn execute(&self, request: Request) -> Result<Vec<String>, String> {
+        match request {
+            Request::AddRecord { name, kind, value, ttl, persist } => {
+                let (wildcard, key, ip) = parse_address_record(&kind, &name, &value)?;
+                if persist {
+                    self.append_to_hosts(&value, &name).map_err(|e| format!("failed to append to {}: {}", self.hosts_path.display(), e))?;
+                    self.reload().await?;
+                    return Ok(Vec::new());
+                }
+                let name = if wildcard { format!("*.{}", key) } else { key };
+                if !self.overlay.add(RuntimeRecord { name: name.clone(), ip, ttl }) {
+                    return Err(format!("{} {} was already added", name, ip));
+                }
+                self.records.write().await.apply_overlay(&self.overlay);
+                self.events.publish(Event::Record { name, change: "added", addresses: vec![ip] });
+                Ok(Vec::new())
+            }
+            Request::RemoveRecord { name } => {
+                let key = runtime_key(&name)?;
+                let present = {
+                    let records = self.records.read().await;
+                    records.exact_matches.contains_key(&table_key(&key)) || records.wildcards.iter().any(|(pattern, _)| *pattern == key)
+                };
+                if !present {
+                    return Err(format!("no address records for {}", key));
+                }
+                self.overlay.remove(&key);
+                self.records.write().await.apply_overlay(&self.overlay);
+                self.events.publish(Event::Record { name: key, change: "removed", addresses: Vec::new() });
+                Ok(Vec::new())
+            }
+            Request::List => {
+                let records = self.records.read().await;
+                let line = |name: &str, entry: &Entry| {
+                    let kind = if entry.ip.is_ipv4() { "A" } else { "AAAA" };
+                    format!("{} {} {} {}", name, kind, entry.ip, entry.source)
+                };
+                let mut lines: Vec<String> = records
+                    .exact_matches
+                    .iter()
+                    .flat_map(|(name, entries)| entries.iter().map(move |entry| (name_key(name), entry)))
+                    .chain(records.wildcards.iter().map(|(pattern, entry)| (pattern.clone(), entry)))
+                    .map(|(name, entry)| line(&name, entry))
+                    .collect();
+                lines.sort();
+                Ok(lines)
+            }
+            Request::Reload => {
+                self.reload().await?;
+                Ok(Vec::new())
+            }
+            Request::Dump { format } => {
+                let dump = dump::render(&*self.records.read().await, format);
+                Ok(dump.lines().map(str::to_string).collect())
+            }
+            Request::Blocks => {
+                let mut lines: Vec<String> = self.blocks.hits().iter().map(|(list, hits)| format!("hits {} {}", list, hits)).collect();
+                let left = |until: std::time::Duration| until.as_secs_f64().ceil() as u64;
+                lines.extend(self.blocks.disabled().into_iter().map(|(list, until)| format!("disabled {} {}s", list, left(until))));
+                lines.extend(self.blocks.allowed().into_iter().map(|(name, until)| format!("allowed {} {}s", name, left(until))));
+                lines.extend(self.blocks.recent().into_iter().map(|block| {
+                    format!("blocked {} {} {} {}:{} {}", block.at, block.client, block.name, block.list, block.line, block.rule)
+                }));
+                Ok(lines)
+            }
+        }
+    }
+
+    async fn reload(&self) -> Result<(), String> {
+        let (done, reloaded) = oneshot::channel();
+        self.reloads.send(done).map_err(|_| "the reload loop has stopped".to_string())?;
+        reloaded
+            .await
+            .map_err(|_| "the reload didn't replace the served records; see the server log".to_string())
+    }
+
+    fn append_to_hosts(&self, value: &str, name: &str) -> std::io::Result<()> {
+        let ends_with_newline = match std::fs::read(&self.hosts_path) {
+            Ok(content) => content.last().is_none_or(|&byte| byte == b'\n'),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => true,
+            Err(e) => return Err(e),
+        };
+        let mut file = std::fs::OpenOptions::new().create(true).append(true).open(&self.hosts_path)?;
+        let separator = if ends_with_newline { "" } else { "\n" };
+        writeln!(file, "{}{} {}", separator, value, name)
+    }
+}
+
+/// Binds the control socket at `path`, replacing a socket file left behind by
+/// a previous instance. Only its owner may connect, since commands change
+/// what is served.
+pub fn bind(path: &Path) -> Result<UnixListener, ServeError> {
+    let listen = || -> std::io::Result<UnixListener> {
+        match std::fs::symlink_metadata(path) {
+            Ok(metadata) if metadata.file_type().is_socket() => std::fs::remove_file(path)?,
+            Ok(_) => {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::AlreadyExists,
+                    "the path exists and is not a socket",
+                ))
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+        let listener = UnixListener::bind(path)?;
+        std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600))?;
+        Ok(listener)
+    };
+    listen().map_err(|source| ServeError::Bind {
+        addr: path.display().to_string(),
+        source,
+    })
+}
+
+/// Takes commands on `listener`, one per line. Each is answered with its
+/// output lines, then `ok`, or with a single `error: <reason>` line.
+pub async fn serve(listener: UnixListener, control: Arc<Control>) -> Result<(), ServeError> {
+    loop {
+        let (stream, _) = listener.accept().await?;
+        let control = control.clone();
+        tokio::spawn(async move {
+            if let Err(e) = handle_connection(stream, &control).await {
+                tracing::warn!("Control connection failed: {}", e);
+            }
+        });
+    }
+}
+
+async fn handle_connection(stream: UnixStream, control: &Control) -> std::io::Result<()> {
+    let (read, mut write) = stream.into_split();
+    let mut lines = BufReader::new(read).lines();
+    while let Some(line) = lines.next_line().await? {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let reply = match Request::parse(&line) {
+            Ok(request) => {
+                tracing::info!("Control command: {}", line.trim());
+                control.execute(request).await
+            }
+            Err(e) => Err(e),
+        };
+        let mut text = String::new();
+        match reply {
+            Ok(output) => {
+                for line in output {
+                    text.push_str(&line);
+                    text.push('\n');
+                }
+                text.push_str("ok\n");
+            }
+            Err(e) => text.push_str(&format!("error: {}\n", e)),
+        }
+        write.write_all(text.as_bytes()).await?;
+    }
+    Ok(())
+}
+
+/// Sends one command to the control socket at `path`, for `localdns ctl`:
+/// the output lines on success, or the server's reason for failing.
+pub fn request(path: &Path, command: &str) -> std::io::Result<Result<Vec<String>, String>> {
+    let mut stream = std::os::unix::net::UnixStream::connect(path)?;
+    writeln!(stream, "{}", command)?;
+    stream.shutdown(std::net::Shutdown::Write)?;
+    let mut output = Vec::new();
+    for line in std::io::BufReader::new(stream).lines() {
+        let line = line?;
+        if line == "ok" {
+            return Ok(Ok(output));
+        }
+        if let Some(reason) = line.strip_prefix("error: ") {
+            return Ok(Err(reason.to_string()));
+        }
+        output.push(line);
+    }
+    Err(std::io::Error::new(std::io::ErrorKind::UnexpectedEof, "the control socket closed without replying"))
+}
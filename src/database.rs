@@ -0,0 +1,70 @@
+use std::path::Path;
+
+/// Used when `records_db_query` is unset. The documented schema is
+///
+/// ```sql
+/// CREATE TABLE records (
+///     name  TEXT NOT NULL,             -- e.g. 'nas.lan' or '*.dev.lan'
+///     type  TEXT NOT NULL DEFAULT 'A', -- 'A', 'AAAA' or 'PTR'
+///     value TEXT NOT NULL,             -- The address; for PTR, the target name
+///     ttl   INTEGER                    -- Optional, the record's TTL in seconds
+/// );
+/// ```
+///
+/// For a `PTR` row, `name` is the IPv4 address and `value` the name it reverses to.
+pub const DEFAULT_QUERY: &str = "SELECT name, type, value, ttl FROM records";
+
+/// One row of the records query. `id` identifies it in warnings and logs.
+#[derive(Debug, Clone)]
+pub struct Row {
+    pub id: usize,
+    pub name: String,
+    pub kind: String,
+    pub value: String,
+    pub ttl: Option<u32>,
+}
+
+/// Whether this build can read `records_db` at all.
+pub const SUPPORTED: bool = cfg!(feature = "sqlite");
+
+/// Runs `query` against the database at `path`, opened read-only. Rows are
+/// numbered from 1 in the order the query returns them.
+#[cfg(feature = "sqlite")]
+pub fn read_rows(path: &Path, query: &str) -> Result<Vec<Row>, String> {
+    use rusqlite::{Connection, OpenFlags};
+
+    let flags = OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_NO_MUTEX;
+    let connection = Connection::open_with_flags(path, flags).map_err(|e| e.to_string())?;
+    // A writer holding the lock briefly shouldn't fail the load
+    connection
+        .busy_timeout(std::time::Duration::from_secs(2))
+        .map_err(|e| e.to_string())?;
+    let mut statement = connection.prepare(query).map_err(|e| e.to_string())?;
+    let rows = statement
+        .query_map([], |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, Option<String>>(1)?,
+                row.get::<_, String>(2)?,
+                row.get::<_, Option<u32>>(3)?,
+            ))
+        })
+        .map_err(|e| e.to_string())?;
+    let mut loaded = Vec::new();
+    for (index, row) in rows.enumerate() {
+        let (name, kind, value, ttl) = row.map_err(|e| e.to_string())?;
+        loaded.push(Row {
+            id: index + 1,
+            name,
+            kind: kind.unwrap_or_else(|| "A".to_string()).to_uppercase(),
+            value,
+            ttl,
+        });
+    }
+    Ok(loaded)
+}
+
+#[cfg(not(feature = "sqlite"))]
+pub fn read_rows(_path: &Path, _query: &str) -> Result<Vec<Row>, String> {
+    Err("this build has no SQLite support; rebuild with `--features sqlite`".to_string())
+}
@@ -0,0 +1,128 @@
+use hickory_proto::rr::rdata::NULL;
+use hickory_proto::rr::{Name, RData, RecordType};
+use serde::Deserialize;
+use std::net::IpAddr;
+use std::sync::RwLock;
+
+/// The name DDR clients (RFC 9462) query for SVCB records.
+pub const DDR_NAME: &str = "_dns.resolver.arpa.";
+
+/// The DoH URI template advertised, matching the route `json_api` serves.
+pub const DOH_PATH: &str = "/dns-query{?dns}";
+
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct DdrConfig {
+    pub enabled: bool,
+    /// Name the TLS proxy's certificate is issued for; clients check it
+    pub target: Option<String>,
+    /// Addresses sent as ipv4hint/ipv6hint; defaults to the listen address
+    pub hints: Vec<IpAddr>,
+}
+
+/// The SVCB records answered for `_dns.resolver.arpa.`, empty while DDR is
+/// off. Listeners share them, and a config change re-encodes them in place.
+#[derive(Debug, Default)]
+pub struct Ddr {
+    records: RwLock<Vec<RData>>,
+}
+
+impl Ddr {
+    pub fn new(records: Vec<RData>) -> Self {
+        Self { records: RwLock::new(records) }
+    }
+
+    pub fn records(&self) -> Vec<RData> {
+        self.records.read().unwrap().clone()
+    }
+
+    pub fn replace(&self, records: Vec<RData>) {
+        *self.records.write().unwrap() = records;
+    }
+
+    /// Encodes the records advertising `/dns-query` on the port of
+    /// `http_listen`, the only encrypted transport a TLS proxy can put in
+    /// front of this build. Nothing is advertised unless DDR is enabled.
+    /// `default_hint` is used when no hints are configured.
+    pub fn encode(config: &DdrConfig, http_listen: Option<&str>, default_hint: Option<IpAddr>) -> Result<Vec<RData>, String> {
+        if !config.enabled {
+            return Ok(Vec::new());
+        }
+        let target = config.target.as_deref().ok_or("`target` is required when DDR is enabled")?;
+        let target = Name::from_ascii(target).map_err(|e| format!("invalid target {:?}: {}", target, e))?;
+        let listen = http_listen.ok_or("nothing to advertise: DDR points clients at /dns-query, which needs `http.listen`")?;
+        let port = listen
+            .rsplit_once(':')
+            .and_then(|(_, port)| port.parse::<u16>().ok())
+            .filter(|&port| port != 0)
+            .ok_or_else(|| format!("can't advertise `http.listen` {:?}, as it has no fixed port", listen))?;
+        let hints: Vec<IpAddr> = if config.hints.is_empty() {
+            default_hint.into_iter().collect()
+        } else {
+            config.hints.clone()
+        };
+        let rdata = encode_svcb(&target, port, &hints);
+        Ok(vec![RData::Unknown {
+            code: RecordType::SVCB,
+            rdata: NULL::with(rdata),
+        }])
+    }
+}
+
+// SvcParamKeys from RFC 9460 and RFC 9461
+const KEY_ALPN: u16 = 1;
+const KEY_PORT: u16 = 3;
+const KEY_IPV4HINT: u16 = 4;
+const KEY_IPV6HINT: u16 = 6;
+const KEY_DOHPATH: u16 = 7;
+
+/// `/dns-query` speaks HTTP/1.1 only, so that is all a proxy can pass on
+const ALPN: &[u8] = b"http/1.1";
+
+/// Encodes SVCB RDATA by hand: hickory's generic SvcParamValue writes unknown
+/// keys such as `dohpath` with a spurious length octet.
+fn encode_svcb(target: &Name, port: u16, hints: &[IpAddr]) -> Vec<u8> {
+    let mut out = 1u16.to_be_bytes().to_vec(); // SvcPriority; 0 would make it an alias
+    for label in target.iter() {
+        out.push(label.len() as u8);
+        out.extend_from_slice(label);
+    }
+    out.push(0);
+
+    // Parameters must appear in ascending key order
+    let mut alpn_value = vec![ALPN.len() as u8];
+    alpn_value.extend_from_slice(ALPN);
+    push_param(&mut out, KEY_ALPN, &alpn_value);
+    push_param(&mut out, KEY_PORT, &port.to_be_bytes());
+
+    let v4: Vec<u8> = hints
+        .iter()
+        .filter_map(|ip| match ip {
+            IpAddr::V4(v4) => Some(v4.octets()),
+            IpAddr::V6(_) => None,
+        })
+        .flatten()
+        .collect();
+    if !v4.is_empty() {
+        push_param(&mut out, KEY_IPV4HINT, &v4);
+    }
+    let v6: Vec<u8> = hints
+        .iter()
+        .filter_map(|ip| match ip {
+            IpAddr::V6(v6) => Some(v6.octets()),
+            IpAddr::V4(_) => None,
+        })
+        .flatten()
+        .collect();
+    if !v6.is_empty() {
+        push_param(&mut out, KEY_IPV6HINT, &v6);
+    }
+    push_param(&mut out, KEY_DOHPATH, DOH_PATH.as_bytes());
+    out
+}
+
+fn push_param(out: &mut Vec<u8>, key: u16, value: &[u8]) {
+    out.extend_from_slice(&key.to_be_bytes());
+    out.extend_from_slice(&(value.len() as u16).to_be_bytes());
+    out.extend_from_slice(value);
+}
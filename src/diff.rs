@@ -0,0 +1,129 @@
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet};
+use std::net::IpAddr;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::loader::{name_key, DnsCache, Entry, Source};
+
+/// Lines of a diff printed per reload; the rest are summarised as "+N more".
+pub const LOG_LIMIT: usize = 50;
+
+/// A name whose addresses differ between two record sets. `source` is where
+/// the new addresses came from, or the old ones for a removed name.
+#[derive(Debug, Clone, Serialize)]
+pub struct NameChange {
+    pub name: String,
+    pub before: Vec<IpAddr>,
+    pub after: Vec<IpAddr>,
+    pub kind: &'static str, // "dhcp", "hosts" or "config"
+    pub source: String,
+}
+
+/// What a reload changed: names that appeared, disappeared, or now
+/// resolve to a different set of addresses. Wildcards count as names.
+#[derive(Debug, Clone, Serialize)]
+pub struct RecordDiff {
+    pub at: u64, // Seconds since the epoch
+    pub serial: u32,
+    pub added: Vec<NameChange>,
+    pub removed: Vec<NameChange>,
+    pub changed: Vec<NameChange>,
+}
+
+impl RecordDiff {
+    pub fn between(old: &DnsCache, new: &DnsCache) -> Self {
+        let (old_names, new_names) = (addresses(old), addresses(new));
+        let mut diff = Self {
+            at: SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0),
+            serial: new.serial,
+            added: Vec::new(),
+            removed: Vec::new(),
+            changed: Vec::new(),
+        };
+        for (name, (after, source)) in &new_names {
+            let change = |before: &BTreeSet<IpAddr>| NameChange {
+                name: name.clone(),
+                before: before.iter().copied().collect(),
+                after: after.iter().copied().collect(),
+                kind: source.kind.as_str(),
+                source: source.to_string(),
+            };
+            match old_names.get(name) {
+                None => diff.added.push(change(&BTreeSet::new())),
+                Some((before, _)) if before != after => diff.changed.push(change(before)),
+                Some(_) => {}
+            }
+        }
+        for (name, (before, source)) in &old_names {
+            if !new_names.contains_key(name) {
+                diff.removed.push(NameChange {
+                    name: name.clone(),
+                    before: before.iter().copied().collect(),
+                    after: Vec::new(),
+                    kind: source.kind.as_str(),
+                    source: source.to_string(),
+                });
+            }
+        }
+        diff
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+
+    /// Prints a summary line and then up to `limit` changes.
+    pub fn log(&self, limit: usize) {
+        if self.is_empty() {
+            tracing::info!("Reload changed no records.");
+            return;
+        }
+        tracing::info!(
+            "Reload diff: {} added, {} removed, {} changed.",
+            self.added.len(),
+            self.removed.len(),
+            self.changed.len()
+        );
+        let lines = self.added.iter().map(|c| ('+', c))
+            .chain(self.removed.iter().map(|c| ('-', c)))
+            .chain(self.changed.iter().map(|c| ('~', c)));
+        for (sign, change) in lines.take(limit) {
+            let addresses = match sign {
+                '+' => join(&change.after),
+                '-' => join(&change.before),
+                _ => format!("{} -> {}", join(&change.before), join(&change.after)),
+            };
+            tracing::info!("  {} {} {} ({})", sign, change.name, addresses, change.source);
+        }
+        let total = self.added.len() + self.removed.len() + self.changed.len();
+        if total > limit {
+            tracing::info!("  ... +{} more", total - limit);
+        }
+    }
+}
+
+/// Every name and wildcard with its addresses and the source of its first entry.
+fn addresses(cache: &DnsCache) -> BTreeMap<String, (BTreeSet<IpAddr>, Source)> {
+    let mut names: BTreeMap<String, (BTreeSet<IpAddr>, Source)> = BTreeMap::new();
+    let mut add = |name: &str, entry: &Entry| {
+        names
+            .entry(name.to_string())
+            .or_insert_with(|| (BTreeSet::new(), entry.source.clone()))
+            .0
+            .insert(entry.ip);
+    };
+    for (name, entries) in &cache.exact_matches {
+        let name = name_key(name);
+        for entry in entries {
+            add(&name, entry);
+        }
+    }
+    for (pattern, entry) in &cache.wildcards {
+        add(pattern, entry);
+    }
+    names
+}
+
+fn join(ips: &[IpAddr]) -> String {
+    ips.iter().map(|ip| ip.to_string()).collect::<Vec<_>>().join(",")
+}
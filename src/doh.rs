@@ -0,0 +1,62 @@
+use data_encoding::BASE64URL_NOPAD;
+use tokio::sync::RwLock;
+use hickory_proto::op::{Message, MessageType};
+use hickory_proto::rr::Record;
+
+use crate::http::{Request, Response};
+use crate::loader::DnsCache;
+use crate::server::{resolve, ResolveOptions};
+
+/// RFC 8484's media type, for both the query and the answer.
+pub const CONTENT_TYPE: &str = "application/dns-message";
+
+/// The largest DNS message there is, as TCP frames them.
+const MAX_MESSAGE_BYTES: usize = 65535;
+
+/// Answers RFC 8484 DNS-over-HTTPS requests on `/dns-query`: a wire-format
+/// query as the base64url `dns` parameter of a GET, or as the body of a POST.
+/// The answer is sent back in wire format, cacheable for its lowest TTL.
+pub async fn handle(request: &Request, records: &RwLock<DnsCache>, options: &ResolveOptions) -> Response {
+    let wire = match request.method.as_str() {
+        "GET" => match request.param("dns") {
+            // Decoders may see padding, though RFC 8484 leaves it out
+            Some(encoded) => match BASE64URL_NOPAD.decode(encoded.trim_end_matches('=').as_bytes()) {
+                Ok(wire) => wire,
+                Err(_) => return Response::text(400, "dns parameter is not base64url\n"),
+            },
+            None => return Response::text(400, "missing dns parameter\n"),
+        },
+        "POST" => {
+            let content_type = request.header("content-type").unwrap_or_default();
+            if !content_type.split(';').next().unwrap_or_default().trim().eq_ignore_ascii_case(CONTENT_TYPE) {
+                return Response::text(415, "Content-Type must be application/dns-message\n");
+            }
+            request.body.clone()
+        }
+        _ => return Response::text(405, "only GET and POST are supported\n").with_header("Allow", "GET, POST"),
+    };
+    if wire.len() > MAX_MESSAGE_BYTES {
+        return Response::text(413, "DNS messages are at most 65535 bytes\n");
+    }
+    let query = match Message::from_vec(&wire) {
+        Ok(query) if query.message_type() == MessageType::Query => query,
+        Ok(_) => return Response::text(400, "not a DNS query\n"),
+        Err(e) => return Response::text(400, &format!("malformed DNS message: {}\n", e)),
+    };
+
+    let answer = match resolve(&query, None, records, options).await {
+        Ok((answer, _)) => answer,
+        Err(e) => return Response::text(500, &format!("{}\n", e)),
+    };
+    let bytes = match answer.to_vec() {
+        Ok(bytes) => bytes,
+        Err(e) => return Response::text(500, &format!("{}\n", e)),
+    };
+    let response = Response::new(200, CONTENT_TYPE, bytes);
+    // Negative answers carry the SOA, whose TTL is the negative TTL (RFC 8484 5.1)
+    let ttl = answer.answers().iter().chain(answer.name_servers()).map(Record::ttl).min();
+    match ttl {
+        Some(ttl) => response.with_header("Cache-Control", &format!("max-age={}", ttl)),
+        None => response,
+    }
+}
@@ -0,0 +1,73 @@
+use serde::Deserialize;
+use socket2::SockRef;
+use std::net::SocketAddr;
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum RawDscp {
+    Number(u8),
+    Name(String),
+}
+
+/// A DiffServ code point, configured as a number (0-63) or a class name such
+/// as `cs6`, `af31` or `ef`.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(try_from = "RawDscp")]
+pub struct Dscp(u8);
+
+impl TryFrom<RawDscp> for Dscp {
+    type Error = String;
+
+    fn try_from(raw: RawDscp) -> Result<Self, Self::Error> {
+        let value = match raw {
+            RawDscp::Number(n) => n,
+            RawDscp::Name(name) => match parse_class(&name.to_lowercase()) {
+                Some(n) => n,
+                None => return Err(format!("{:?} is not a DSCP class name", name)),
+            },
+        };
+        if value > 63 {
+            return Err(format!("DSCP value {} is out of range 0-63", value));
+        }
+        Ok(Self(value))
+    }
+}
+
+/// `csN` (N = 0-7), `afXY` (X = 1-4, Y = 1-3), `ef` and `va`.
+fn parse_class(name: &str) -> Option<u8> {
+    match name {
+        "ef" => return Some(46),
+        "va" => return Some(44),
+        _ => {}
+    }
+    if let Some(n) = name.strip_prefix("cs") {
+        let n: u8 = n.parse().ok()?;
+        return (n <= 7).then_some(n << 3);
+    }
+    let digits = name.strip_prefix("af")?.as_bytes();
+    match digits {
+        [class @ b'1'..=b'4', drop @ b'1'..=b'3'] => Some(((class - b'0') << 3) | ((drop - b'0') << 1)),
+        _ => None,
+    }
+}
+
+impl Dscp {
+    pub fn value(&self) -> u8 {
+        self.0
+    }
+
+    /// Marks packets sent from `socket`: IP_TOS for IPv4, IPV6_TCLASS for IPv6.
+    /// Failure only costs the marking, so it is logged rather than returned.
+    pub fn apply<'a>(&self, socket: impl Into<SockRef<'a>>, addr: SocketAddr) {
+        let socket = socket.into();
+        let tos = u32::from(self.0) << 2; // DSCP is the top six bits of the byte
+        let result = if addr.is_ipv4() {
+            socket.set_tos_v4(tos)
+        } else {
+            socket.set_tclass_v6(tos)
+        };
+        if let Err(e) = result {
+            tracing::warn!("Failed to set DSCP {} on {}: {}", self.0, addr, e);
+        }
+    }
+}
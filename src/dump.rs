@@ -0,0 +1,223 @@
+use std::collections::{BTreeMap, HashMap};
+use std::str::FromStr;
+use hickory_proto::rr::LowerName;
+use serde_json::json;
+
+use crate::loader::{name_key, DnsCache, Entry, SourceKind};
+
+/// How `localdns dump`, SIGUSR1 and `GET /records` write out the records.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DumpFormat {
+    /// One record per line, sorted
+    #[default]
+    Text,
+    /// A single JSON object, for scripts that diff successive dumps
+    Json,
+}
+
+impl FromStr for DumpFormat {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "text" => Ok(DumpFormat::Text),
+            "json" => Ok(DumpFormat::Json),
+            _ => Err(format!("unknown dump format {:?}; expected text or json", value)),
+        }
+    }
+}
+
+/// What `records` holds, as served: every line or key is taken from memory,
+/// so a name that differs from the files shows up as it is answered.
+pub fn render(records: &DnsCache, format: DumpFormat) -> String {
+    match format {
+        DumpFormat::Text => text(records),
+        DumpFormat::Json => json(records).to_string(),
+    }
+}
+
+/// `<section> <name> <type> <data> ttl=<seconds|-> <source>` per record, with
+/// `dynamic` after records added at runtime and `generated` after names made
+/// up for leases without a hostname, after
+/// `#` comment lines with the serial and any failing sources. Sections come in
+/// a fixed order and the lines within each are sorted, so two dumps diff cleanly.
+fn text(records: &DnsCache) -> String {
+    let mut out = format!(
+        "# serial {}, {} exact names, {} wildcards\n",
+        records.serial,
+        records.exact_matches.len(),
+        records.wildcards.len()
+    );
+    let mut failures: Vec<String> = records.failures.iter().map(|e| e.to_string()).collect();
+    failures.sort();
+    for failure in failures {
+        out.push_str(&format!("# failing: {}\n", failure));
+    }
+
+    let ttl = |ttl: Option<u32>| ttl.map_or("-".to_string(), |ttl| ttl.to_string());
+    let address = |section: &str, name: &str, entry: &Entry, generated: bool| {
+        let mut line = format!("{} {} {} {} ttl={} {}", section, name, address_type(entry), entry.ip, ttl(entry.ttl), entry.source);
+        if is_dynamic(entry) {
+            line.push_str(" dynamic");
+        }
+        if generated {
+            line.push_str(" generated");
+        }
+        line
+    };
+    let mut sections: Vec<Vec<String>> = vec![
+        records
+            .exact_matches
+            .iter()
+            .flat_map(|(name, entries)| {
+                let (key, generated) = (name_key(name), records.synthesized.contains(name));
+                entries.iter().map(move |e| address("exact", &key, e, generated))
+            })
+            .collect(),
+        records.wildcards.iter().map(|(pattern, e)| address("wildcard", pattern, e, false)).collect(),
+        records
+            .blocked
+            .iter()
+            .flat_map(|(name, entries)| {
+                let key = name_key(name);
+                entries.iter().map(move |e| address("blocked", &key, e, false))
+            })
+            .collect(),
+        records.blocked_wildcards.iter().map(|(pattern, e)| address("blocked", pattern, e, false)).collect(),
+        records.cnames.iter().map(|(alias, a)| format!("cname {} CNAME {} ttl=- {}", name_key(alias), a.target, a.source)).collect(),
+        records.ptrs.iter().map(|p| format!("ptr {} PTR {} ttl=- {}", p.ip, p.name, p.source)).collect(),
+        records
+            .txts
+            .iter()
+            .flat_map(|(name, txts)| {
+                let name = name_key(name);
+                txts.iter().map(move |t| {
+                    let strings: Vec<String> = t.strings.iter().map(|s| format!("{:?}", String::from_utf8_lossy(s))).collect();
+                    format!("txt {} TXT {} ttl={} {}", name, strings.join(" "), ttl(t.ttl), t.source)
+                })
+            })
+            .collect(),
+        records
+            .mxs
+            .iter()
+            .flat_map(|(name, mxs)| {
+                let name = name_key(name);
+                mxs.iter().map(move |m| format!("mx {} MX {} {} ttl={} {}", name, m.preference, m.exchange, ttl(m.ttl), m.source))
+            })
+            .collect(),
+        records
+            .srvs
+            .iter()
+            .flat_map(|(name, srvs)| {
+                let name = name_key(name);
+                srvs.iter().map(move |s| {
+                    format!("srv {} SRV {} {} {} {} ttl={} {}", name, s.priority, s.weight, s.port, s.target, ttl(s.ttl), s.source)
+                })
+            })
+            .collect(),
+    ];
+    for lines in &mut sections {
+        lines.sort();
+        for line in lines.iter() {
+            out.push_str(line);
+            out.push('\n');
+        }
+    }
+    out
+}
+
+/// The address records by name and by wildcard pattern, along with the
+/// blocked names, aliases, explicit PTRs, TXT, MX and SRV records and failing
+/// sources. Map keys are sorted.
+pub fn json(records: &DnsCache) -> serde_json::Value {
+    let by_name = |pairs: &mut dyn Iterator<Item = (String, &Entry, bool)>| {
+        let mut map: BTreeMap<String, Vec<serde_json::Value>> = BTreeMap::new();
+        for (name, entry, generated) in pairs {
+            map.entry(name).or_default().push(entry_json(entry, generated));
+        }
+        map
+    };
+    let exact = by_name(&mut records.exact_matches.iter().flat_map(|(name, entries)| {
+        let generated = records.synthesized.contains(name);
+        entries.iter().map(move |e| (name_key(name), e, generated))
+    }));
+    let wildcards = by_name(&mut records.wildcards.iter().map(|(pattern, e)| (pattern.clone(), e, false)));
+    let blocked = by_name(
+        &mut records
+            .blocked
+            .iter()
+            .flat_map(|(name, entries)| entries.iter().map(move |e| (name_key(name), e, false)))
+            .chain(records.blocked_wildcards.iter().map(|(pattern, e)| (pattern.clone(), e, false))),
+    );
+    let cnames: BTreeMap<String, serde_json::Value> = records
+        .cnames
+        .iter()
+        .map(|(alias, a)| (name_key(alias), json!({ "target": a.target, "source": a.source.kind.as_str(), "location": a.source.to_string() })))
+        .collect();
+    let mut ptrs: Vec<(String, serde_json::Value)> = records
+        .ptrs
+        .iter()
+        .map(|p| (format!("{} {}", p.ip, p.name), json!({ "address": p.ip.to_string(), "name": p.name, "source": p.source.kind.as_str(), "location": p.source.to_string() })))
+        .collect();
+    ptrs.sort_by(|a, b| a.0.cmp(&b.0));
+    let txts = keyed(&records.txts, |t| {
+        let strings: Vec<String> = t.strings.iter().map(|s| String::from_utf8_lossy(s).into_owned()).collect();
+        json!({ "strings": strings, "ttl": t.ttl, "source": t.source.kind.as_str(), "location": t.source.to_string() })
+    });
+    let mxs = keyed(&records.mxs, |m| {
+        json!({ "preference": m.preference, "exchange": m.exchange, "ttl": m.ttl, "source": m.source.kind.as_str(), "location": m.source.to_string() })
+    });
+    let srvs = keyed(&records.srvs, |s| {
+        json!({
+            "priority": s.priority,
+            "weight": s.weight,
+            "port": s.port,
+            "target": s.target,
+            "ttl": s.ttl,
+            "source": s.source.kind.as_str(),
+            "location": s.source.to_string(),
+        })
+    });
+    let mut failures: Vec<String> = records.failures.iter().map(|e| e.to_string()).collect();
+    failures.sort();
+    json!({
+        "serial": records.serial,
+        "exact": exact,
+        "wildcards": wildcards,
+        "blocked": blocked,
+        "cnames": cnames,
+        "ptrs": ptrs.into_iter().map(|(_, ptr)| ptr).collect::<Vec<_>>(),
+        "txts": txts,
+        "mxs": mxs,
+        "srvs": srvs,
+        "failures": failures,
+    })
+}
+
+/// A table's records by name, each name's in the order they were loaded.
+fn keyed<T>(table: &HashMap<LowerName, Vec<T>>, record: impl Fn(&T) -> serde_json::Value) -> BTreeMap<String, Vec<serde_json::Value>> {
+    table.iter().map(|(name, records)| (name_key(name), records.iter().map(&record).collect())).collect()
+}
+
+/// `{address, type, source, location, ttl, dynamic, generated}`, as `GET /records`
+/// lists entries; `generated` says whether the name was made up for a lease without one.
+pub fn entry_json(entry: &Entry, generated: bool) -> serde_json::Value {
+    json!({
+        "address": entry.ip.to_string(),
+        "type": address_type(entry),
+        "source": entry.source.kind.as_str(),
+        "location": entry.source.to_string(),
+        "ttl": entry.ttl,
+        "dynamic": is_dynamic(entry),
+        "generated": generated,
+    })
+}
+
+/// Added at runtime, over the control socket or the HTTP API, rather than loaded from a source.
+fn is_dynamic(entry: &Entry) -> bool {
+    entry.source.kind == SourceKind::Runtime
+}
+
+fn address_type(entry: &Entry) -> &'static str {
+    if entry.ip.is_ipv4() { "A" } else { "AAAA" }
+}
@@ -0,0 +1,154 @@
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::net::IpAddr;
+use tokio::sync::broadcast;
+
+use crate::diff::{NameChange, RecordDiff};
+use crate::loader::DnsCache;
+use crate::overlay::RuntimeRecord;
+
+/// Events buffered per subscriber; a subscriber further behind loses the
+/// oldest and is sent a `lossy` event in their place.
+pub const BUFFER: usize = 256;
+
+/// `BUFFER` in the embedded profile.
+pub const EMBEDDED_BUFFER: usize = 32;
+
+/// What `GET /events` streams, one JSON object per Server-Sent Event.
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Event {
+    /// The current state, sent first to every new subscriber
+    Snapshot {
+        serial: u32,
+        exact: usize,
+        wildcards: usize,
+        conflicts: usize,
+        failures: Vec<String>,
+    },
+    /// A reload was swapped in
+    Reload {
+        serial: u32,
+        exact: usize,
+        wildcards: usize,
+        added: usize,
+        removed: usize,
+        changed: usize,
+    },
+    /// A DHCP host appeared, disappeared, or changed address
+    Host {
+        change: &'static str,
+        name: String,
+        before: Vec<IpAddr>,
+        after: Vec<IpAddr>,
+        source: String,
+    },
+    /// The set of failing sources changed; empty once every source loads again
+    Sources { failures: Vec<String> },
+    /// A runtime record was `added`, `replaced`, `removed` or `expired`, with
+    /// the addresses that came or went; empty when a whole name was removed
+    Record {
+        name: String,
+        change: &'static str,
+        addresses: Vec<IpAddr>,
+    },
+    /// This subscriber fell behind and `dropped` events were skipped
+    Lossy { dropped: u64 },
+}
+
+impl Event {
+    pub fn snapshot(cache: &DnsCache) -> Self {
+        Event::Snapshot {
+            serial: cache.serial,
+            exact: cache.exact_matches.len(),
+            wildcards: cache.wildcards.len(),
+            conflicts: cache.conflicts.len(),
+            failures: cache.failures.iter().map(|e| e.to_string()).collect(),
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            Event::Snapshot { .. } => "snapshot",
+            Event::Reload { .. } => "reload",
+            Event::Host { .. } => "host",
+            Event::Sources { .. } => "sources",
+            Event::Record { .. } => "record",
+            Event::Lossy { .. } => "lossy",
+        }
+    }
+
+    /// The event in `text/event-stream` framing.
+    pub fn to_sse(&self) -> String {
+        let data = serde_json::to_string(self).unwrap_or_default();
+        format!("event: {}\ndata: {}\n\n", self.name(), data)
+    }
+}
+
+/// Fans events out to every `/events` subscriber without ever waiting on one.
+#[derive(Debug)]
+pub struct Events {
+    sender: broadcast::Sender<Event>,
+}
+
+impl Default for Events {
+    fn default() -> Self {
+        Self::with_buffer(BUFFER)
+    }
+}
+
+impl Events {
+    /// Keeps `buffer` events per subscriber instead of `BUFFER`.
+    pub fn with_buffer(buffer: usize) -> Self {
+        Self { sender: broadcast::channel(buffer).0 }
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<Event> {
+        self.sender.subscribe()
+    }
+
+    pub fn publish(&self, event: Event) {
+        // Fails only when nobody is subscribed
+        let _ = self.sender.send(event);
+    }
+
+    /// A `reload` event for `cache`, then a `host` event per changed DHCP name.
+    /// The wildcard generated alongside each lease name isn't reported separately.
+    pub fn publish_reload(&self, cache: &DnsCache, diff: &RecordDiff) {
+        self.publish(Event::Reload {
+            serial: cache.serial,
+            exact: cache.exact_matches.len(),
+            wildcards: cache.wildcards.len(),
+            added: diff.added.len(),
+            removed: diff.removed.len(),
+            changed: diff.changed.len(),
+        });
+        let hosts = [("appeared", &diff.added), ("disappeared", &diff.removed), ("changed", &diff.changed)];
+        for (change, names) in hosts {
+            for name in names.iter().filter(|n| n.kind == "dhcp" && !n.name.starts_with("*.")) {
+                self.publish(host_event(change, name));
+            }
+        }
+    }
+
+    /// A `record` event per name whose runtime records expired.
+    pub fn publish_expired(&self, records: &[RuntimeRecord]) {
+        let mut names: BTreeMap<&str, Vec<IpAddr>> = BTreeMap::new();
+        for record in records {
+            names.entry(&record.name).or_default().push(record.ip);
+        }
+        for (name, addresses) in names {
+            self.publish(Event::Record { name: name.to_string(), change: "expired", addresses });
+        }
+    }
+}
+
+fn host_event(change: &'static str, name: &NameChange) -> Event {
+    Event::Host {
+        change,
+        name: name.name.clone(),
+        before: name.before.clone(),
+        after: name.after.clone(),
+        source: name.source.clone(),
+    }
+}
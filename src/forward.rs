@@ -0,0 +1,461 @@
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpSocket, TcpStream, UdpSocket};
+use tokio::time::{timeout, Duration, Instant};
+use hickory_proto::op::{Edns, Message, MessageType, Query, ResponseCode};
+use hickory_proto::rr::rdata::opt::{EdnsCode, EdnsOption};
+use hickory_proto::rr::Record;
+
+use crate::dscp::Dscp;
+use crate::loader::{name_key, parents};
+use crate::querylog;
+use crate::rebind::RebindGuard;
+use crate::response_cache::ResponseCache;
+use crate::server::{response_for, ServeError};
+use crate::stats::Stats;
+
+/// Largest UDP response we accept from an upstream.
+const UPSTREAM_PAYLOAD: u16 = 4096;
+
+/// EDNS option, from the local and experimental range (RFC 6891), listing the
+/// instances a forwarded query has passed through, each as a length byte
+/// followed by its identifier.
+pub const LOOP_OPTION: u16 = 65001;
+
+/// Seconds between two forwarding loop errors in the log.
+const LOOP_LOG_INTERVAL: u64 = 10;
+
+/// Open TCP connections kept per upstream for later queries to reuse.
+const IDLE_CONNECTIONS: usize = 4;
+
+/// How long a kept TCP connection is trusted to still be open. Resolvers
+/// close idle ones after some seconds (RFC 7766 suggests at least 10).
+const IDLE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How long an upstream that failed is asked last, doubled for each failure
+/// in a row up to `BACKOFF_MAX`.
+const BACKOFF_BASE: Duration = Duration::from_secs(1);
+const BACKOFF_MAX: Duration = Duration::from_secs(60);
+
+/// How queries reach an upstream.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Transport {
+    /// UDP, retried over TCP when the answer is truncated
+    Udp,
+    Tcp,
+}
+
+/// One entry of `upstream`: an IP (on port 53) or a socket address, optionally
+/// behind `udp://` or `tcp://`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Upstream {
+    pub addr: SocketAddr,
+    pub transport: Transport,
+}
+
+impl Upstream {
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let (transport, address) = match text.split_once("://") {
+            None => (Transport::Udp, text),
+            Some(("udp", address)) => (Transport::Udp, address),
+            Some(("tcp", address)) => (Transport::Tcp, address),
+            Some(("tls" | "https", _)) => {
+                return Err(format!("{:?} needs DNS-over-TLS or HTTPS, which this build doesn't support", text));
+            }
+            Some((scheme, _)) => return Err(format!("{:?} has an unknown scheme {:?}; use udp:// or tcp://", text, scheme)),
+        };
+        let addr = address
+            .parse::<SocketAddr>()
+            .ok()
+            .or_else(|| address.parse::<IpAddr>().ok().map(|ip| SocketAddr::new(ip, 53)))
+            .ok_or_else(|| format!("{:?} is neither an IP address nor a socket address", text))?;
+        Ok(Self { addr, transport })
+    }
+}
+
+/// The address in `ours` that queries sent to `upstream` would arrive back on.
+/// A `0.0.0.0` listener takes in every local IPv4 address on its port, and a
+/// `::` listener every local address, as its socket is dual-stack.
+pub fn loops_back(upstream: SocketAddr, ours: &[SocketAddr]) -> Option<SocketAddr> {
+    ours.iter().copied().find(|listen| {
+        let covers = listen.ip().is_unspecified() && (listen.is_ipv6() || upstream.is_ipv4()) && is_local(upstream.ip());
+        listen.port() == upstream.port() && (listen.ip() == upstream.ip() || covers)
+    })
+}
+
+/// Whether `ip` is one of this host's addresses, which only those can be bound to.
+fn is_local(ip: IpAddr) -> bool {
+    ip.is_loopback() || ip.is_unspecified() || std::net::UdpSocket::bind(SocketAddr::new(ip, 0)).is_ok()
+}
+
+/// Where names we have no records for are sent: `forward_zones` by the
+/// longest zone containing the name, anything else to `upstream`.
+#[derive(Debug, Clone, Default)]
+pub struct Upstreams {
+    pub default: Vec<Upstream>,
+    /// By zone key (see `name_key`); a zone without upstreams is answered locally
+    pub zones: HashMap<String, Vec<Upstream>>,
+}
+
+/// The upstreams picked for one name.
+#[derive(Debug, Clone, Copy)]
+pub struct Route<'a> {
+    /// The `forward_zones` entry that matched, or `None` for `upstream`
+    pub zone: Option<&'a str>,
+    pub upstreams: &'a [Upstream],
+}
+
+impl Upstreams {
+    pub fn is_empty(&self) -> bool {
+        self.default.is_empty() && self.zones.is_empty()
+    }
+
+    /// The upstreams of the most specific forward zone that is the key `name`
+    /// or one of its parents, on label boundaries, as wildcards are matched.
+    pub fn zone_for<'a>(&'a self, name: &'a str) -> Option<Route<'a>> {
+        if self.zones.is_empty() {
+            return None;
+        }
+        std::iter::once(name)
+            .chain(parents(name).map(|parent| if parent.is_empty() { "." } else { parent }))
+            .find_map(|zone| self.zones.get_key_value(zone))
+            .map(|(zone, upstreams)| Route { zone: Some(zone), upstreams })
+    }
+
+    /// `upstream`, unless it is empty.
+    pub fn fallback(&self) -> Option<Route<'_>> {
+        (!self.default.is_empty()).then_some(Route { zone: None, upstreams: &self.default })
+    }
+}
+
+/// Sends queries on to upstreams and caches what they answered. The upstreams
+/// themselves are chosen per query, since they change on SIGHUP.
+#[derive(Debug)]
+pub struct Forwarder {
+    /// How long each upstream gets to answer before the next one is asked
+    timeout: Duration,
+    pub cache: ResponseCache,
+    /// Set with `block_private_upstream`
+    rebind: Option<RebindGuard>,
+    /// Set with `upstream_dscp`
+    dscp: Option<Dscp>,
+    /// Ours in `LOOP_OPTION`
+    identifier: Vec<u8>,
+    /// When a loop was last logged, in seconds since the epoch, and how many weren't since
+    loop_logged: AtomicU64,
+    loops_unlogged: AtomicU64,
+    /// TCP connections upstreams answered on and left open, with when each was last used
+    connections: Mutex<HashMap<SocketAddr, Vec<(TcpStream, Instant)>>>,
+    /// Upstreams that failed lately, by how many times in a row and until when they are asked last
+    backoff: Mutex<HashMap<SocketAddr, (u32, Instant)>>,
+}
+
+impl Forwarder {
+    /// Forwarded queries are tagged with an identifier drawn at random for
+    /// this forwarder, so a query that comes back can be recognised. It is
+    /// never the NSID, which upstreams have no business learning and which
+    /// instances on one host share.
+    pub fn new(timeout: Duration, cache_max_entries: usize, rebind: Option<RebindGuard>, dscp: Option<Dscp>) -> Self {
+        let identifier = rand::random::<[u8; 8]>().to_vec();
+        Self {
+            timeout,
+            cache: ResponseCache::new(cache_max_entries),
+            rebind,
+            dscp,
+            identifier,
+            loop_logged: AtomicU64::new(0),
+            loops_unlogged: AtomicU64::new(0),
+            connections: Mutex::new(HashMap::new()),
+            backoff: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// The answer to `request`'s first question, from the cache or else from
+    /// the first of `route`'s upstreams to respond. SERVFAIL when none does,
+    /// and when the query is one we forwarded ourselves that came back.
+    /// Private addresses are caught before the answer is cached.
+    pub async fn resolve(&self, request: &Message, route: Route<'_>, stats: &Stats) -> Result<Message, ServeError> {
+        let mut response = response_for(request);
+        let Some(query) = request.queries().first() else {
+            response.set_response_code(ResponseCode::FormErr);
+            return Ok(response);
+        };
+        let key = name_key(query.name());
+        if let Some(answers) = self.cache.get(&key, query.query_type()) {
+            stats.upstream_cache_hits.fetch_add(1, Ordering::Relaxed);
+            log_forward(query, route, "cache");
+            for record in answers {
+                response.add_answer(with_owner_case(record, query));
+            }
+            response.set_response_code(ResponseCode::NoError);
+            return Ok(response);
+        }
+        stats.upstream_cache_misses.fetch_add(1, Ordering::Relaxed);
+
+        let seen = seen_by(request);
+        if seen.iter().any(|identifier| *identifier == self.identifier) {
+            self.log_loop(query);
+            response.set_response_code(ResponseCode::ServFail);
+            return Ok(response);
+        }
+        let Some((mut upstream, answered_by)) = self.ask(query, route.upstreams, &seen).await else {
+            log_forward(query, route, "-");
+            response.set_response_code(ResponseCode::ServFail);
+            return Ok(response);
+        };
+        log_forward(query, route, &answered_by.to_string());
+        if let Some(guard) = &self.rebind {
+            guard.check(query, &mut upstream);
+        }
+        if upstream.response_code() == ResponseCode::NoError && !upstream.answers().is_empty() {
+            self.cache.insert(&key, query.query_type(), upstream.answers().to_vec());
+        }
+        response.set_response_code(upstream.response_code());
+        response.set_authoritative(false);
+        for record in upstream.answers() {
+            response.add_answer(with_owner_case(record.clone(), query));
+        }
+        for record in upstream.name_servers() {
+            response.add_name_server(record.clone());
+        }
+        for record in upstream.additionals() {
+            response.add_additional(record.clone());
+        }
+        Ok(response)
+    }
+
+    /// Sends `query` to each of `upstreams` in turn until one answers it, tagged
+    /// with the instances in `seen` and then us. Upstreams backing off after
+    /// failing are asked after the others, rather than not at all.
+    async fn ask(&self, query: &Query, upstreams: &[Upstream], seen: &[&[u8]]) -> Option<(Message, SocketAddr)> {
+        let mut request = Message::new();
+        request.set_id(rand::random());
+        request.set_message_type(MessageType::Query);
+        request.set_recursion_desired(true);
+        request.add_query(query.clone());
+        let mut edns = Edns::new();
+        edns.set_max_payload(UPSTREAM_PAYLOAD);
+        let mut path = Vec::new();
+        for identifier in seen.iter().copied().chain([self.identifier.as_slice()]) {
+            path.push(identifier.len() as u8);
+            path.extend_from_slice(identifier);
+        }
+        edns.options_mut().insert(EdnsOption::Unknown(LOOP_OPTION, path));
+        request.set_edns(edns);
+        let bytes = request.to_vec().ok()?;
+        let (ready, resting): (Vec<&Upstream>, Vec<&Upstream>) = {
+            let now = Instant::now();
+            let backoff = self.backoff.lock().unwrap();
+            upstreams.iter().partition(|upstream| backoff.get(&upstream.addr).is_none_or(|(_, until)| *until <= now))
+        };
+        for upstream in ready.into_iter().chain(resting) {
+            let exchange = async {
+                match upstream.transport {
+                    Transport::Udp => match udp_exchange(upstream.addr, self.dscp, &request, &bytes).await? {
+                        response if response.truncated() => self.tcp_exchange(upstream.addr, &request, &bytes).await,
+                        response => Ok(response),
+                    },
+                    Transport::Tcp => self.tcp_exchange(upstream.addr, &request, &bytes).await,
+                }
+            };
+            match timeout(self.timeout, exchange).await {
+                Ok(Ok(response)) => {
+                    self.succeeded(upstream.addr);
+                    return Some((response, upstream.addr));
+                }
+                Ok(Err(e)) => tracing::warn!("Upstream {} failed for {}: {}", upstream.addr, query.name(), e),
+                Err(_) => tracing::warn!("Upstream {} timed out for {}", upstream.addr, query.name()),
+            }
+            self.failed(upstream.addr);
+        }
+        None
+    }
+
+    /// One query to `upstream` over TCP, on a connection it left open if
+    /// there is one. A kept connection the upstream has closed since fails
+    /// at once, and the query is sent again on a new one.
+    async fn tcp_exchange(&self, upstream: SocketAddr, request: &Message, bytes: &[u8]) -> std::io::Result<Message> {
+        if let Some(mut stream) = self.idle_connection(upstream) {
+            if let Ok(response) = tcp_query(&mut stream, request, bytes).await {
+                self.keep_connection(upstream, stream);
+                return Ok(response);
+            }
+        }
+        let mut stream = tcp_connect(upstream, self.dscp).await?;
+        let response = tcp_query(&mut stream, request, bytes).await?;
+        self.keep_connection(upstream, stream);
+        Ok(response)
+    }
+
+    /// The most recently used kept connection to `upstream`, dropping those idle too long.
+    fn idle_connection(&self, upstream: SocketAddr) -> Option<TcpStream> {
+        let mut connections = self.connections.lock().unwrap();
+        let idle = connections.get_mut(&upstream)?;
+        idle.retain(|(_, used)| used.elapsed() < IDLE_TIMEOUT);
+        idle.pop().map(|(stream, _)| stream)
+    }
+
+    fn keep_connection(&self, upstream: SocketAddr, stream: TcpStream) {
+        let mut connections = self.connections.lock().unwrap();
+        let idle = connections.entry(upstream).or_default();
+        if idle.len() == IDLE_CONNECTIONS {
+            idle.remove(0);
+        }
+        idle.push((stream, Instant::now()));
+    }
+
+    /// Asks `upstream` last for a while, longer each time it fails again.
+    fn failed(&self, upstream: SocketAddr) {
+        let mut backoff = self.backoff.lock().unwrap();
+        let failures = backoff.get(&upstream).map_or(1, |(failures, _)| failures.saturating_add(1));
+        let wait = BACKOFF_BASE.saturating_mul(1 << (failures - 1).min(16)).min(BACKOFF_MAX);
+        if failures == 1 {
+            tracing::warn!("Upstream {} is asked after the others for {:?}, as it failed", upstream, wait);
+        }
+        backoff.insert(upstream, (failures, Instant::now() + wait));
+    }
+
+    fn succeeded(&self, upstream: SocketAddr) {
+        if let Some((failures, _)) = self.backoff.lock().unwrap().remove(&upstream) {
+            tracing::info!("Upstream {} answered again after failing {} time(s) in a row", upstream, failures);
+        }
+    }
+}
+
+impl Forwarder {
+    /// An error for a query that came back to us, at most once per
+    /// `LOOP_LOG_INTERVAL` however fast the loop goes round.
+    fn log_loop(&self, query: &Query) {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+        let last = self.loop_logged.load(Ordering::Relaxed);
+        let due = last == 0 || now >= last + LOOP_LOG_INTERVAL;
+        if !due || self.loop_logged.compare_exchange(last, now, Ordering::Relaxed, Ordering::Relaxed).is_err() {
+            self.loops_unlogged.fetch_add(1, Ordering::Relaxed);
+            return;
+        }
+        tracing::error!(
+            "Forwarding loop: {} {} came back to us after we forwarded it, so it was answered with SERVFAIL ({} more since the last report). Check that no upstream forwards to this server.",
+            query.name(),
+            query.query_type(),
+            self.loops_unlogged.swap(0, Ordering::Relaxed)
+        );
+    }
+}
+
+/// The identifiers in `request`'s `LOOP_OPTION`, in the order they forwarded it.
+fn seen_by(request: &Message) -> Vec<&[u8]> {
+    let mut seen = Vec::new();
+    let option = request.extensions().as_ref().and_then(|edns| edns.option(EdnsCode::from(LOOP_OPTION)));
+    let Some(EdnsOption::Unknown(_, value)) = option else {
+        return seen;
+    };
+    let mut rest = value.as_slice();
+    // A truncated last entry is dropped; the ones before it still count
+    while let Some((&len, tail)) = rest.split_first() {
+        if tail.len() < usize::from(len) {
+            break;
+        }
+        let (identifier, tail) = tail.split_at(usize::from(len));
+        seen.push(identifier);
+        rest = tail;
+    }
+    seen
+}
+
+/// Names the zone and upstream behind a forwarded answer, on the query log
+/// target at debug level, so the sampled `query` lines stay as they are.
+fn log_forward(query: &Query, route: Route<'_>, upstream: &str) {
+    tracing::debug!(
+        target: querylog::TARGET,
+        name = %query.name(),
+        r#type = %query.query_type(),
+        zone = %route.zone.unwrap_or("-"),
+        upstream = %upstream,
+        "forward"
+    );
+}
+
+/// One query to `upstream` over UDP.
+async fn udp_exchange(upstream: SocketAddr, dscp: Option<Dscp>, request: &Message, bytes: &[u8]) -> std::io::Result<Message> {
+    let socket = udp_connect(upstream, dscp).await?;
+    socket.send(bytes).await?;
+    let mut buffer = vec![0u8; usize::from(UPSTREAM_PAYLOAD)];
+    loop {
+        let len = socket.recv(&mut buffer).await?;
+        // Anything that isn't the answer to this query, such as a late reply to an earlier one, is ignored
+        match Message::from_vec(&buffer[..len]) {
+            Ok(response) if answers(request, &response) => return Ok(response),
+            _ => continue,
+        }
+    }
+}
+
+/// A UDP socket of its own for a query to `upstream`, marked with `dscp`.
+pub async fn udp_connect(upstream: SocketAddr, dscp: Option<Dscp>) -> std::io::Result<UdpSocket> {
+    let local = match upstream {
+        SocketAddr::V4(_) => SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0)),
+        SocketAddr::V6(_) => SocketAddr::from((Ipv6Addr::UNSPECIFIED, 0)),
+    };
+    let socket = UdpSocket::bind(local).await?;
+    if let Some(dscp) = dscp {
+        dscp.apply(&socket, upstream);
+    }
+    socket.connect(upstream).await?;
+    Ok(socket)
+}
+
+/// A new TCP connection to `upstream`, marked with `dscp` before the
+/// handshake so every segment carries it.
+pub async fn tcp_connect(upstream: SocketAddr, dscp: Option<Dscp>) -> std::io::Result<TcpStream> {
+    let socket = match upstream {
+        SocketAddr::V4(_) => TcpSocket::new_v4()?,
+        SocketAddr::V6(_) => TcpSocket::new_v6()?,
+    };
+    if let Some(dscp) = dscp {
+        dscp.apply(&socket, upstream);
+    }
+    socket.connect(upstream).await
+}
+
+/// One query over a TCP connection, which is left open for the next.
+async fn tcp_query(stream: &mut TcpStream, request: &Message, bytes: &[u8]) -> std::io::Result<Message> {
+    let len = u16::try_from(bytes.len()).map_err(|_| invalid("query exceeds 65535 bytes"))?;
+    stream.write_all(&len.to_be_bytes()).await?;
+    stream.write_all(bytes).await?;
+    let mut len = [0u8; 2];
+    stream.read_exact(&mut len).await?;
+    let mut frame = vec![0u8; usize::from(u16::from_be_bytes(len))];
+    stream.read_exact(&mut frame).await?;
+    let response = Message::from_vec(&frame).map_err(|e| invalid(&e.to_string()))?;
+    if !answers(request, &response) {
+        return Err(invalid("response over TCP doesn't match the query"));
+    }
+    Ok(response)
+}
+
+/// Whether `response` carries `request`'s ID and question.
+fn answers(request: &Message, response: &Message) -> bool {
+    response.message_type() == MessageType::Response
+        && response.id() == request.id()
+        && response.queries().len() == 1
+        && request.queries().first().map(|q| name_key(q.name())) == response.queries().first().map(|q| name_key(q.name()))
+        && request.queries().first().map(Query::query_type) == response.queries().first().map(Query::query_type)
+}
+
+/// `record`, owned by the name exactly as the client spelled it when it is the
+/// question's name, since answers are cached and forwarded by the lowercased one.
+fn with_owner_case(mut record: Record, query: &Query) -> Record {
+    // Name comparison ignores case
+    if record.name() == query.name() {
+        record.set_name(query.name().clone());
+    }
+    record
+}
+
+fn invalid(message: &str) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, message.to_string())
+}
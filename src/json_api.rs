@@ -0,0 +1,433 @@
+use std::str::FromStr;
+use std::sync::atomic::Ordering;
+use std::sync::{Arc, Mutex};
+use serde::Deserialize;
+use serde_json::json;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::{mpsc, RwLock};
+use tokio::time::{interval, Duration};
+use hickory_proto::op::{Message, Query};
+use hickory_proto::rr::{Name, RData, Record, RecordType};
+
+use crate::blocklist::list_name;
+use crate::diff::RecordDiff;
+use crate::doh;
+use crate::dump::{self, entry_json};
+use crate::events::{Event, Events};
+use crate::http::{Request, Response};
+use crate::listeners::{self, Listeners};
+use crate::loader::{parse_address_record, table_key, DnsCache, LimitsPolicy, RecordLimits};
+use crate::overlay::{runtime_key, Overlay, RuntimeRecord};
+use crate::server::{resolve, ResolveOptions};
+use crate::watchdog::Health;
+
+/// Serves the de-facto standard `application/dns-json` resolver API
+/// (as offered by Google and Cloudflare) on `GET /resolve` and RFC 8484
+/// DNS-over-HTTPS on `/dns-query`, plus `GET /status`
+/// `GET /healthz`, `GET /reloads/last` and the `GET /events` stream for monitoring.
+/// `/records` lists what is served, and `PUT` and `DELETE /records/{name}`
+/// change it at runtime through the same overlay as the control socket.
+/// `GET /blocks` reports the blocks answered, and `POST /blocks/disable` and
+/// `/blocks/allow` make exceptions to them for a while.
+#[derive(Clone)]
+pub struct JsonApi {
+    pub records: Arc<RwLock<DnsCache>>,
+    pub options: Arc<ResolveOptions>,
+    pub cors_allow_origin: Option<String>,
+    pub limits: RecordLimits,
+    pub limits_policy: LimitsPolicy,
+    pub health: Arc<Health>,
+    pub last_reload: Arc<Mutex<Option<RecordDiff>>>,
+    pub events: Arc<Events>,
+    pub listeners: Arc<Listeners>,
+    pub overlay: Arc<Overlay>,
+    /// Required as a bearer token by the `/records` and `/blocks` mutations
+    pub api_token: Option<String>,
+    /// Seconds since the epoch
+    pub started_at: u64,
+}
+
+/// The body of `PUT /records/{name}`.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RecordUpdate {
+    addresses: Vec<String>,
+    ttl: Option<u32>,
+}
+
+/// The body of `POST /blocks/disable`.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ListException {
+    list: String,
+    /// Seconds; 0 ends the exception
+    ttl: u64,
+}
+
+/// The body of `POST /blocks/allow`.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct NameException {
+    name: String,
+    /// Seconds; 0 ends the exception
+    ttl: u64,
+}
+
+/// How often an idle event stream gets a comment, so dead clients are noticed.
+const KEEPALIVE: Duration = Duration::from_secs(30);
+
+impl JsonApi {
+    pub async fn handle(&self, request: Request) -> Response {
+        let response = match (request.method.as_str(), request.path.as_str()) {
+            ("GET", "/resolve") => self.resolve(&request).await,
+            // CORS preflight
+            ("OPTIONS", "/resolve") => Response::new(204, "text/plain", Vec::new())
+                .with_header("Access-Control-Allow-Methods", "GET, OPTIONS")
+                .with_header("Access-Control-Allow-Headers", "Accept"),
+            (_, "/resolve") => error(405, "only GET is supported"),
+            (_, "/dns-query") => doh::handle(&request, &self.records, &self.options).await,
+            ("GET", "/status") => self.status().await,
+            (_, "/status") => error(405, "only GET is supported"),
+            ("GET", "/healthz") => self.healthz(),
+            (_, "/healthz") => error(405, "only GET is supported"),
+            ("GET", "/reloads/last") => self.last_reload(),
+            (_, "/reloads/last") => error(405, "only GET is supported"),
+            ("GET", "/events") => self.events().await,
+            (_, "/events") => error(405, "only GET is supported"),
+            ("GET", "/records") => self.records().await,
+            (_, "/records") => error(405, "only GET is supported"),
+            (_, path) if path.starts_with("/records/") => self.record(&request).await,
+            ("GET", "/blocks") => self.blocks(),
+            (_, "/blocks") => error(405, "only GET is supported"),
+            ("POST", "/blocks/disable") => self.disable_list(&request).await,
+            ("POST", "/blocks/allow") => self.allow_name(&request),
+            (_, "/blocks/disable" | "/blocks/allow") => error(405, "only POST is supported").with_header("Allow", "POST"),
+            _ => error(404, "not found"),
+        };
+        match &self.cors_allow_origin {
+            Some(origin) => response.with_header("Access-Control-Allow-Origin", origin),
+            None => response,
+        }
+    }
+
+    async fn resolve(&self, request: &Request) -> Response {
+        let mut name = match request.param("name").map(Name::from_utf8) {
+            Some(Ok(name)) => name,
+            Some(Err(e)) => return error(400, &format!("invalid name: {}", e)),
+            None => return error(400, "missing name parameter"),
+        };
+        // Names in the API are given without the trailing dot; lookups are keyed on FQDNs
+        name.set_fqdn(true);
+        let qtype = match parse_type(request.param("type").unwrap_or("A")) {
+            Some(qtype) => qtype,
+            None => return error(400, "invalid type parameter"),
+        };
+
+        let mut query = Message::new();
+        query.set_recursion_desired(request.param("rd") != Some("0"));
+        query.add_query(Query::query(name, qtype));
+
+        match resolve(&query, None, &self.records, &self.options).await {
+            Ok((answer, _)) => Response::new(200, "application/dns-json", to_json(&answer).to_string()),
+            Err(e) => error(500, &e.to_string()),
+        }
+    }
+
+    /// 200 while the watchdog's self-queries are answered, 503 once they fail
+    /// past the threshold.
+    fn healthz(&self) -> Response {
+        let health = &self.health;
+        let last_success = *health.last_success.lock().unwrap();
+        let body = json!({
+            "healthy": health.is_healthy(),
+            "watchdog": health.enabled,
+            "consecutive_failures": health.consecutive_failures.load(Ordering::Relaxed),
+            "probes": health.probes.load(Ordering::Relaxed),
+            "failures": health.failures.load(Ordering::Relaxed),
+            "rebinds": health.rebinds.load(Ordering::Relaxed),
+            "last_success_secs_ago": last_success.map(|t| t.elapsed().as_secs()),
+        });
+        Response::json(if health.is_healthy() { 200 } else { 503 }, &body)
+    }
+
+    /// Server-Sent Events: a `snapshot` of the current state, then every event as
+    /// it happens. A subscriber that can't keep up is sent `lossy` instead of
+    /// the events it missed, so nothing queues up without bound.
+    async fn events(&self) -> Response {
+        // Subscribe before taking the snapshot, so nothing falls in between
+        let mut events = self.events.subscribe();
+        let snapshot = Event::snapshot(&*self.records.read().await);
+        let (sender, chunks) = mpsc::channel(16);
+        tokio::spawn(async move {
+            if sender.send(snapshot.to_sse().into_bytes()).await.is_err() {
+                return;
+            }
+            let mut keepalive = interval(KEEPALIVE);
+            keepalive.tick().await;
+            loop {
+                let chunk = tokio::select! {
+                    event = events.recv() => match event {
+                        Ok(event) => event.to_sse(),
+                        Err(RecvError::Lagged(dropped)) => Event::Lossy { dropped }.to_sse(),
+                        Err(RecvError::Closed) => break,
+                    },
+                    _ = keepalive.tick() => ": keepalive\n\n".to_string(),
+                };
+                if sender.send(chunk.into_bytes()).await.is_err() {
+                    break; // The client went away
+                }
+            }
+        });
+        Response::stream(200, "text/event-stream", chunks).with_header("Cache-Control", "no-cache")
+    }
+
+    /// Every record as served, with where it came from; see `dump::json`.
+    async fn records(&self) -> Response {
+        Response::json(200, &dump::json(&*self.records.read().await))
+    }
+
+    /// `GET`, `PUT` and `DELETE` on one name; `*.` in front names a wildcard.
+    async fn record(&self, request: &Request) -> Response {
+        let name = &request.path["/records/".len()..];
+        let key = match runtime_key(name) {
+            Ok(key) => key,
+            Err(e) => return error(400, &e),
+        };
+        match request.method.as_str() {
+            "GET" => self.record_entries(&key).await,
+            "PUT" => {
+                if let Err(response) = self.authorize(request) {
+                    return response;
+                }
+                let update: RecordUpdate = match serde_json::from_slice(&request.body) {
+                    Ok(update) => update,
+                    Err(e) => return error(400, &format!("invalid body: {}", e)),
+                };
+                if update.addresses.is_empty() {
+                    return error(400, "addresses must not be empty; DELETE removes a name");
+                }
+                let mut added = Vec::new();
+                for value in &update.addresses {
+                    let kind = if value.contains(':') { "AAAA" } else { "A" };
+                    match parse_address_record(kind, name, value) {
+                        Ok((_, _, ip)) => added.push(RuntimeRecord { name: key.clone(), ip, ttl: update.ttl }),
+                        Err(e) => return error(400, &e),
+                    }
+                }
+                tracing::info!("HTTP API: replacing {} with {}", key, update.addresses.join(", "));
+                let addresses = added.iter().map(|record| record.ip).collect();
+                self.overlay.replace(&key, added);
+                self.records.write().await.apply_overlay(&self.overlay);
+                self.events.publish(Event::Record { name: key.clone(), change: "replaced", addresses });
+                self.record_entries(&key).await
+            }
+            "DELETE" => {
+                if let Err(response) = self.authorize(request) {
+                    return response;
+                }
+                if !has_entries(&*self.records.read().await, &key) {
+                    return error(404, &format!("no address records for {}", key));
+                }
+                tracing::info!("HTTP API: removing {}", key);
+                self.overlay.remove(&key);
+                self.records.write().await.apply_overlay(&self.overlay);
+                self.events.publish(Event::Record { name: key, change: "removed", addresses: Vec::new() });
+                Response::new(204, "text/plain", Vec::new())
+            }
+            _ => error(405, "only GET, PUT and DELETE are supported").with_header("Allow", "GET, PUT, DELETE"),
+        }
+    }
+
+    async fn record_entries(&self, key: &str) -> Response {
+        let records = self.records.read().await;
+        let entries: Vec<serde_json::Value> = match key.starts_with("*.") {
+            true => records.wildcards.iter().filter(|(pattern, _)| pattern == key).map(|(_, entry)| entry_json(entry, false)).collect(),
+            false => {
+                let key = table_key(key);
+                let generated = records.synthesized.contains(&key);
+                records.exact_matches.get(&key).map(|entries| entries.iter().map(|e| entry_json(e, generated)).collect()).unwrap_or_default()
+            }
+        };
+        if entries.is_empty() {
+            return error(404, &format!("no address records for {}", key));
+        }
+        Response::json(200, &json!({ "name": key, "entries": entries }))
+    }
+
+    /// Mutations are refused outright without a configured token, and need
+    /// `Authorization: Bearer <token>` with it.
+    fn authorize(&self, request: &Request) -> Result<(), Response> {
+        let Some(token) = &self.api_token else {
+            return Err(error(403, "changing records or blocks needs http.api_token to be set"));
+        };
+        let given = request.header("Authorization").and_then(|value| value.strip_prefix("Bearer "));
+        match given {
+            Some(given) if constant_time_eq(given.trim().as_bytes(), token.as_bytes()) => Ok(()),
+            _ => Err(error(401, "missing or wrong bearer token").with_header("WWW-Authenticate", "Bearer")),
+        }
+    }
+
+    /// Blocks answered per list, the exceptions in force and the latest blocks.
+    fn blocks(&self) -> Response {
+        let blocks = &self.options.blocklist.blocks;
+        let seconds = |until: std::collections::BTreeMap<String, Duration>| -> serde_json::Map<String, serde_json::Value> {
+            until.into_iter().map(|(key, left)| (key, json!(left.as_secs_f64().ceil() as u64))).collect()
+        };
+        Response::json(
+            200,
+            &json!({
+                "hits": blocks.hits(),
+                "disabled": seconds(blocks.disabled()),
+                "allowed": seconds(blocks.allowed()),
+                "recent": blocks.recent(),
+            }),
+        )
+    }
+
+    async fn disable_list(&self, request: &Request) -> Response {
+        if let Err(response) = self.authorize(request) {
+            return response;
+        }
+        let exception: ListException = match serde_json::from_slice(&request.body) {
+            Ok(exception) => exception,
+            Err(e) => return error(400, &format!("invalid body: {}", e)),
+        };
+        let listed = {
+            let records = self.records.read().await;
+            let mut entries = records.blocked.values().flatten().chain(records.blocked_wildcards.iter().map(|(_, entry)| entry));
+            entries.any(|entry| list_name(entry) == exception.list)
+        };
+        if !listed {
+            return error(404, &format!("no blocks are listed in {}", exception.list));
+        }
+        tracing::info!("HTTP API: disabling blocks from {} for {}s", exception.list, exception.ttl);
+        self.options.blocklist.blocks.disable(&exception.list, Duration::from_secs(exception.ttl));
+        self.blocks()
+    }
+
+    fn allow_name(&self, request: &Request) -> Response {
+        if let Err(response) = self.authorize(request) {
+            return response;
+        }
+        let exception: NameException = match serde_json::from_slice(&request.body) {
+            Ok(exception) => exception,
+            Err(e) => return error(400, &format!("invalid body: {}", e)),
+        };
+        let key = match runtime_key(&exception.name) {
+            Ok(key) if key.starts_with("*.") => return error(400, "only single names can be allowed; disable the list to lift a wildcard"),
+            Ok(key) => key,
+            Err(e) => return error(400, &e),
+        };
+        tracing::info!("HTTP API: allowing {} for {}s", key, exception.ttl);
+        self.options.blocklist.blocks.allow(&key, Duration::from_secs(exception.ttl));
+        self.blocks()
+    }
+
+    /// The names added, removed and changed by the most recent reload.
+    fn last_reload(&self) -> Response {
+        match &*self.last_reload.lock().unwrap() {
+            Some(diff) => Response::json(200, &json!(diff)),
+            None => error(404, "no reload since startup"),
+        }
+    }
+
+    /// What is loaded, alongside each record limit and how much of it is used.
+    async fn status(&self) -> Response {
+        let records = self.records.read().await;
+        let largest_name = records.exact_matches.values().map(Vec::len).max().unwrap_or(0);
+        let failures: Vec<String> = records.failures.iter().map(|e| e.to_string()).collect();
+        let snapshot_age = records.snapshot_written.and_then(|t| t.elapsed().ok()).map(|age| age.as_secs());
+        let limit = |max: usize, used: usize| json!({ "limit": max, "used": used });
+        let body = json!({
+            "records": {
+                "exact": records.exact_matches.len(),
+                "wildcards": records.wildcards.len(),
+                "conflicts": records.conflicts.len(),
+                // Generated for leases without a hostname
+                "synthesized": records.synthesized.len(),
+                "failures": failures,
+                // Served from the snapshot because a source is still failing
+                "stale": snapshot_age.is_some(),
+                "snapshot_age_secs": snapshot_age,
+            },
+            "limits": {
+                "policy": match self.limits_policy {
+                    LimitsPolicy::Truncate => "truncate",
+                    LimitsPolicy::Fail => "fail",
+                },
+                "max_records": limit(self.limits.max_records, records.exact_matches.len()),
+                "max_wildcards": limit(self.limits.max_wildcards, records.wildcards.len()),
+                "max_addresses_per_name": limit(self.limits.max_addresses_per_name, largest_name),
+            },
+            "listeners": self.listeners.status(),
+            "started_at": self.started_at,
+            "uptime_secs": listeners::now().saturating_sub(self.started_at),
+            "last_reload_at": self.last_reload.lock().unwrap().as_ref().map(|diff| diff.at),
+        });
+        Response::json(200, &body)
+    }
+}
+
+/// Accepts mnemonics (`AAAA`) as well as numeric types (`28`).
+fn parse_type(value: &str) -> Option<RecordType> {
+    match value.parse::<u16>() {
+        Ok(n) => Some(RecordType::from(n)),
+        Err(_) => RecordType::from_str(&value.to_uppercase()).ok(),
+    }
+}
+
+fn has_entries(records: &DnsCache, key: &str) -> bool {
+    records.exact_matches.contains_key(&table_key(key)) || records.wildcards.iter().any(|(pattern, _)| pattern == key)
+}
+
+/// Compares without stopping at the first difference, so response times don't
+/// reveal how much of a guessed token was right.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+fn error(status: u16, message: &str) -> Response {
+    Response::json(status, &json!({ "error": message }))
+}
+
+fn to_json(message: &Message) -> serde_json::Value {
+    let questions: Vec<_> = message
+        .queries()
+        .iter()
+        .map(|q| json!({ "name": q.name().to_string(), "type": u16::from(q.query_type()) }))
+        .collect();
+    let mut body = json!({
+        "Status": u16::from(message.response_code()),
+        "TC": message.truncated(),
+        "RD": message.recursion_desired(),
+        "RA": message.recursion_available(),
+        "AD": message.authentic_data(),
+        "CD": message.checking_disabled(),
+        "Question": questions,
+    });
+    if !message.answers().is_empty() {
+        body["Answer"] = message.answers().iter().map(record_json).collect();
+    }
+    if !message.name_servers().is_empty() {
+        body["Authority"] = message.name_servers().iter().map(record_json).collect();
+    }
+    body
+}
+
+fn record_json(record: &Record) -> serde_json::Value {
+    let data = match record.data() {
+        Some(RData::TXT(txt)) => txt
+            .iter()
+            .map(|s| format!("\"{}\"", String::from_utf8_lossy(s)))
+            .collect::<Vec<_>>()
+            .join(" "),
+        Some(rdata) => rdata.to_string(),
+        None => String::new(),
+    };
+    json!({
+        "name": record.name().to_string(),
+        "type": u16::from(record.record_type()),
+        "TTL": record.ttl(),
+        "data": data,
+    })
+}
@@ -0,0 +1,416 @@
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// How `dhcp_lease_file` is laid out.
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum LeaseFormat {
+    /// Told apart by the first non-empty line
+    #[default]
+    Auto,
+    /// systemd-networkd's `{"Leases": [...]}`
+    Json,
+    /// `dnsmasq.leases` lines: `expiry mac ip hostname client-id`
+    Dnsmasq,
+    /// isc-dhcp-server's `dhcpd.leases`, a log of `lease <ip> { ... }` stanzas
+    Isc,
+    /// Kea's memfile CSV (`kea-leases4.csv`, `kea-leases6.csv`)
+    Kea,
+}
+
+impl LeaseFormat {
+    /// The format `content` is in, when set to `Auto`.
+    pub fn detect(self, content: &str) -> LeaseFormat {
+        if self != LeaseFormat::Auto {
+            return self;
+        }
+        let first = content.lines().map(str::trim).find(|line| !line.is_empty()).unwrap_or_default();
+        match first.chars().next() {
+            Some('{') => LeaseFormat::Json,
+            _ if first.starts_with("address,") => LeaseFormat::Kea,
+            // dhcpd writes a comment header, then stanzas and statements such as `authoring-byte-order`
+            Some('#') => LeaseFormat::Isc,
+            _ if first.starts_with("lease ") || first.ends_with(';') || first.ends_with('{') => LeaseFormat::Isc,
+            _ => LeaseFormat::Dnsmasq,
+        }
+    }
+}
+
+/// A DHCP lease, whatever file it came from. `location` is the 1-based
+/// position of a JSON lease, or the 1-based line of a dnsmasq or Kea one or
+/// of the `lease` keyword of an ISC stanza.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Lease {
+    pub location: usize,
+    pub ip: IpAddr,
+    /// Empty when the client sent none
+    pub hostname: String,
+    pub mac: Option<[u8; 6]>,
+    /// Seconds since the epoch; `None` for leases that never expire or don't say
+    pub expires: Option<u64>,
+}
+
+/// Parses `content` as `format`. Malformed dnsmasq, ISC and Kea entries are
+/// skipped with a warning naming `origin`; a JSON file has to parse as a whole.
+pub fn parse(content: &str, format: LeaseFormat, origin: &str) -> Result<Vec<Lease>, serde_json::Error> {
+    match format.detect(content) {
+        LeaseFormat::Dnsmasq => Ok(parse_dnsmasq(content, origin)),
+        LeaseFormat::Isc => Ok(parse_isc(content, origin, now())),
+        LeaseFormat::Kea => Ok(parse_kea(content, origin, now())),
+        LeaseFormat::Json | LeaseFormat::Auto => parse_json(content),
+    }
+}
+
+#[derive(Deserialize, Debug)]
+struct DhcpData {
+    #[serde(rename = "Leases")]
+    leases: Vec<DhcpLease>,
+}
+
+#[derive(Deserialize, Debug)]
+struct DhcpLease {
+    #[serde(rename = "Address")]
+    address: [u8; 4],
+    #[serde(rename = "Hostname", default)]
+    hostname: String,
+    #[serde(rename = "HardwareAddress")]
+    hardware_address: Option<Vec<u8>>,
+    #[serde(rename = "ClientId")]
+    client_id: Option<Vec<u8>>,
+    /// Microseconds since the epoch; `ExpirationUSec` beside it counts from boot
+    #[serde(rename = "ExpirationRealtimeUSec")]
+    expiration_realtime_usec: Option<u64>,
+}
+
+impl DhcpLease {
+    /// The client's MAC, from `HardwareAddress` or else an Ethernet `ClientId`
+    /// (hardware type 1 followed by the address).
+    fn mac(&self) -> Option<[u8; 6]> {
+        if let Some(mac) = self.hardware_address.as_deref().and_then(|a| a.try_into().ok()) {
+            return Some(mac);
+        }
+        match self.client_id.as_deref() {
+            Some([1, mac @ ..]) => mac.try_into().ok(),
+            _ => None,
+        }
+    }
+
+    /// Seconds since the epoch, with networkd's `USEC_INFINITY` as no expiry.
+    fn expires(&self) -> Option<u64> {
+        self.expiration_realtime_usec.filter(|&usec| usec != 0 && usec != u64::MAX).map(|usec| usec / 1_000_000)
+    }
+}
+
+fn parse_json(content: &str) -> Result<Vec<Lease>, serde_json::Error> {
+    let data: DhcpData = serde_json::from_str(content)?;
+    Ok(data
+        .leases
+        .into_iter()
+        .enumerate()
+        .map(|(index, lease)| Lease {
+            location: index + 1,
+            ip: IpAddr::from(lease.address),
+            mac: lease.mac(),
+            expires: lease.expires(),
+            hostname: lease.hostname,
+        })
+        .collect())
+}
+
+/// IPv4 lines carry the MAC in the second column; IPv6 ones carry the IAID
+/// there instead, and the `duid` line gives the server's DUID.
+fn parse_dnsmasq(content: &str, origin: &str) -> Vec<Lease> {
+    let mut leases = Vec::new();
+    for (index, line) in content.lines().enumerate() {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        match fields.as_slice() {
+            [] | ["duid", ..] => continue,
+            [expiry, mac, ip, hostname, ..] => {
+                let (Ok(expiry), Ok(ip)) = (expiry.parse::<u64>(), ip.parse::<IpAddr>()) else {
+                    tracing::warn!("Skipping malformed dnsmasq lease at {}:{}: {:?}", origin, index + 1, line);
+                    continue;
+                };
+                leases.push(Lease {
+                    location: index + 1,
+                    ip,
+                    // dnsmasq writes `*` for clients that sent no hostname
+                    hostname: if *hostname == "*" { String::new() } else { hostname.to_string() },
+                    mac: parse_mac(mac),
+                    expires: (expiry != 0).then_some(expiry),
+                });
+            }
+            _ => tracing::warn!("Skipping malformed dnsmasq lease at {}:{}: {:?}", origin, index + 1, line),
+        }
+    }
+    leases
+}
+
+/// `aa:bb:cc:dd:ee:ff`; dnsmasq writes other hardware types with more or fewer octets.
+fn parse_mac(field: &str) -> Option<[u8; 6]> {
+    let octets: Vec<u8> = field.split(':').map(|octet| u8::from_str_radix(octet, 16).ok()).collect::<Option<_>>()?;
+    octets.try_into().ok()
+}
+
+pub(crate) fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+#[derive(Debug, PartialEq)]
+enum Token {
+    Word(String),
+    Quoted(String),
+    Open,
+    Close,
+    End,
+}
+
+/// Splits `dhcpd.leases` into tokens, each with its 1-based line. Comments
+/// run from `#` to the end of the line; quoted strings take `\"`, `\\` and
+/// octal `\ooo` escapes.
+fn tokenize(content: &str) -> Vec<(usize, Token)> {
+    let mut tokens = Vec::new();
+    let mut chars = content.chars().peekable();
+    let mut line = 1;
+    while let Some(c) = chars.next() {
+        match c {
+            '\n' => line += 1,
+            c if c.is_whitespace() => {}
+            '#' => {
+                while chars.next_if(|&c| c != '\n').is_some() {}
+            }
+            '{' => tokens.push((line, Token::Open)),
+            '}' => tokens.push((line, Token::Close)),
+            ';' => tokens.push((line, Token::End)),
+            '"' => {
+                let (start, mut bytes) = (line, Vec::new());
+                while let Some(c) = chars.next() {
+                    match c {
+                        '"' => break,
+                        '\\' => match chars.next() {
+                            Some(d @ '0'..='7') => {
+                                let mut value = d.to_digit(8).unwrap_or(0);
+                                for _ in 0..2 {
+                                    match chars.next_if(|c| ('0'..='7').contains(c)) {
+                                        Some(d) => value = value * 8 + d.to_digit(8).unwrap_or(0),
+                                        None => break,
+                                    }
+                                }
+                                bytes.push(value as u8);
+                            }
+                            Some(other) => bytes.extend(other.to_string().as_bytes()),
+                            None => break,
+                        },
+                        c => {
+                            line += usize::from(c == '\n');
+                            bytes.extend(c.to_string().as_bytes());
+                        }
+                    }
+                }
+                tokens.push((start, Token::Quoted(String::from_utf8_lossy(&bytes).into_owned())));
+            }
+            c => {
+                let mut word = c.to_string();
+                while let Some(c) = chars.next_if(|c| !c.is_whitespace() && !matches!(c, '{' | '}' | ';' | '"' | '#')) {
+                    word.push(c);
+                }
+                tokens.push((line, Token::Word(word)));
+            }
+        }
+    }
+    tokens
+}
+
+/// What an ISC lease stanza says, before superseded and inactive ones are dropped.
+#[derive(Default)]
+struct IscLease {
+    location: usize,
+    hostname: String,
+    mac: Option<[u8; 6]>,
+    ends: Option<u64>,
+    active: bool,
+}
+
+/// The file is a log: each stanza replaces earlier ones for the same address,
+/// so only the last counts. Leases that aren't active, or whose `ends` passed
+/// before `now`, are dropped.
+fn parse_isc(content: &str, origin: &str, now: u64) -> Vec<Lease> {
+    let tokens = tokenize(content);
+    let mut latest: HashMap<Ipv4Addr, IscLease> = HashMap::new();
+    let mut at = 0;
+    while at < tokens.len() {
+        let (line, token) = &tokens[at];
+        let address = match (token, tokens.get(at + 1), tokens.get(at + 2)) {
+            (Token::Word(keyword), Some((_, Token::Word(address))), Some((_, Token::Open))) if keyword == "lease" => {
+                address.parse::<Ipv4Addr>().ok()
+            }
+            _ => None,
+        };
+        let Some(address) = address else {
+            // Anything else, `lease6` and `failover peer` blocks included, is skipped whole
+            at = skip_statement(&tokens, at);
+            continue;
+        };
+        let (lease, next) = parse_isc_stanza(&tokens, at + 3, *line, origin);
+        latest.insert(address, lease);
+        at = next;
+    }
+
+    let mut leases: Vec<Lease> = latest
+        .into_iter()
+        .filter(|(_, lease)| lease.active && lease.ends.is_none_or(|ends| ends > now))
+        .map(|(ip, lease)| Lease {
+            location: lease.location,
+            ip: IpAddr::V4(ip),
+            hostname: lease.hostname,
+            mac: lease.mac,
+            expires: lease.ends,
+        })
+        .collect();
+    leases.sort_by_key(|lease| lease.location);
+    leases
+}
+
+/// Reads the statements of a stanza from `at`, just past its `{`, returning
+/// the lease and where the stanza ends.
+fn parse_isc_stanza(tokens: &[(usize, Token)], mut at: usize, location: usize, origin: &str) -> (IscLease, usize) {
+    // Without a `binding state`, as written by old servers, a lease counts as active
+    let mut lease = IscLease { location, active: true, ..IscLease::default() };
+    while let Some((line, token)) = tokens.get(at) {
+        if *token == Token::Close {
+            return (lease, at + 1);
+        }
+        let next = skip_statement(tokens, at);
+        let words: Vec<&str> = tokens[at..next]
+            .iter()
+            .filter_map(|(_, token)| match token {
+                Token::Word(word) | Token::Quoted(word) => Some(word.as_str()),
+                _ => None,
+            })
+            .collect();
+        match words.as_slice() {
+            ["client-hostname", hostname] => lease.hostname = hostname.to_string(),
+            ["hardware", "ethernet", mac] => lease.mac = parse_mac(mac),
+            ["binding", "state", state] => lease.active = *state == "active",
+            ["abandoned"] => lease.active = false,
+            ["ends", "never"] => lease.ends = None,
+            ["ends", "epoch", seconds] => lease.ends = seconds.parse().ok(),
+            ["ends", _, date, time] => match parse_isc_time(date, time) {
+                Some(ends) => lease.ends = Some(ends),
+                None => tracing::warn!("Ignoring unreadable lease end {} {} at {}:{}", date, time, origin, line),
+            },
+            _ => {}
+        }
+        at = next;
+    }
+    tracing::warn!("Unterminated lease stanza at {}:{}", origin, location);
+    (lease, at)
+}
+
+/// The index just past the statement or block starting at `at`.
+fn skip_statement(tokens: &[(usize, Token)], mut at: usize) -> usize {
+    let mut depth = 0usize;
+    while let Some((_, token)) = tokens.get(at) {
+        at += 1;
+        match token {
+            Token::Open => depth += 1,
+            Token::Close if depth <= 1 => return at,
+            Token::Close => depth -= 1,
+            Token::End if depth == 0 => return at,
+            _ => {}
+        }
+    }
+    at
+}
+
+/// `2024/01/10 12:00:00`, which dhcpd writes in UTC, as seconds since the epoch.
+fn parse_isc_time(date: &str, time: &str) -> Option<u64> {
+    let numbers = |field: &str, separator: char| -> Option<Vec<i64>> {
+        field.split(separator).map(|n| n.parse().ok()).collect()
+    };
+    let [year, month, day] = <[i64; 3]>::try_from(numbers(date, '/')?).ok()?;
+    let [hour, minute, second] = <[i64; 3]>::try_from(numbers(time, ':')?).ok()?;
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) || hour > 23 || minute > 59 || second > 60 {
+        return None;
+    }
+    // Days from the civil date, after Howard Hinnant's `days_from_civil`
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    let days = era * 146097 + day_of_era - 719468;
+    u64::try_from(days * 86400 + hour * 3600 + minute * 60 + second).ok()
+}
+
+/// Kea's `valid_lifetime` for leases that never expire.
+const KEA_INFINITE: u64 = u32::MAX as u64;
+
+/// The file is appended to, so the last row for an address is the current
+/// one. Released rows (lifetime 0), rows in any state but the default one,
+/// prefix delegations and leases that expired before `now` are dropped.
+fn parse_kea(content: &str, origin: &str, now: u64) -> Vec<Lease> {
+    let mut lines = content.lines().enumerate().filter(|(_, line)| !line.trim().is_empty());
+    let Some((_, header)) = lines.next() else {
+        return Vec::new();
+    };
+    let header = split_csv(header);
+    let column = |name: &str| header.iter().position(|h| h.trim() == name);
+    let Some(address_column) = column("address") else {
+        tracing::warn!("No address column in the Kea lease header of {}", origin);
+        return Vec::new();
+    };
+    let (hwaddr, lifetime, expire) = (column("hwaddr"), column("valid_lifetime"), column("expire"));
+    let (hostname, state, lease_type) = (column("hostname"), column("state"), column("lease_type"));
+
+    let mut latest: HashMap<IpAddr, Option<Lease>> = HashMap::new();
+    for (index, line) in lines {
+        let fields = split_csv(line);
+        let field = |column: Option<usize>| column.and_then(|c| fields.get(c)).map(|f| f.trim()).unwrap_or_default();
+        let Ok(ip) = field(Some(address_column)).parse::<IpAddr>() else {
+            tracing::warn!("Skipping malformed Kea lease at {}:{}: {:?}", origin, index + 1, line);
+            continue;
+        };
+        let lifetime: u64 = field(lifetime).parse().unwrap_or(0);
+        let expires: Option<u64> = field(expire).parse().ok();
+        // A later row replaces this address's lease, even one saying it was released
+        let current = lifetime != 0
+            && matches!(field(state), "" | "0")
+            && matches!(field(lease_type), "" | "0")
+            && (lifetime == KEA_INFINITE || expires.is_none_or(|expires| expires > now));
+        let lease = current.then(|| Lease {
+            location: index + 1,
+            ip,
+            hostname: field(hostname).to_string(),
+            mac: parse_mac(field(hwaddr)),
+            expires: expires.filter(|_| lifetime != KEA_INFINITE),
+        });
+        latest.insert(ip, lease);
+    }
+
+    let mut leases: Vec<Lease> = latest.into_values().flatten().collect();
+    leases.sort_by_key(|lease| lease.location);
+    leases
+}
+
+/// The fields of a CSV row. Fields may be quoted, with `""` for a quote, and
+/// Kea itself writes commas inside a field as `&#x2c`.
+fn split_csv(line: &str) -> Vec<String> {
+    let mut fields = Vec::new();
+    let (mut field, mut quoted) = (String::new(), false);
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                chars.next();
+                field.push('"');
+            }
+            '"' => quoted = !quoted,
+            ',' if !quoted => fields.push(std::mem::take(&mut field).replace("&#x2c", ",")),
+            c => field.push(c),
+        }
+    }
+    fields.push(field.replace("&#x2c", ","));
+    fields
+}
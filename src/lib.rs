@@ -0,0 +1,44 @@
+pub mod anonymize;
+pub mod blocklist;
+pub mod buffers;
+pub mod check;
+pub mod config;
+pub mod database;
+pub mod connectivity;
+#[cfg(unix)]
+pub mod control;
+pub mod ddr;
+pub mod forward;
+pub mod diff;
+pub mod doh;
+pub mod dscp;
+pub mod dump;
+pub mod events;
+pub mod http;
+pub mod json_api;
+pub mod leases;
+pub mod listeners;
+pub mod loader;
+pub mod mdns;
+pub mod metrics;
+pub mod middleware;
+pub mod overlay;
+pub mod querylog;
+pub mod ratelimit;
+pub mod rebind;
+pub mod reservations;
+pub mod response_cache;
+pub mod reverse;
+pub mod run;
+pub mod server;
+pub mod snapshot;
+pub mod soa;
+pub mod stats;
+pub mod synthetic;
+pub mod tcp;
+#[cfg(unix)]
+pub mod unix;
+pub mod view;
+pub mod watch;
+pub mod watchdog;
+pub mod zone;
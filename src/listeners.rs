@@ -0,0 +1,285 @@
+use hickory_proto::rr::RData;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::net::{TcpListener, UdpSocket};
+use tokio::sync::{oneshot, watch, RwLock};
+use tokio::task::{JoinHandle, JoinSet};
+
+use crate::config::{Config, EmbeddedConfig, TcpConfig};
+use crate::dscp::Dscp;
+use crate::loader::DnsCache;
+use crate::server::{self, ResolveOptions, ServeError};
+use crate::stats::Stats;
+use crate::tcp;
+use crate::view::{ListenerConfig, ViewConfig};
+
+/// Serves UDP on `socket` until `stop`, inline when the embedded profile is on.
+pub async fn serve_udp(
+    socket: impl Into<Arc<UdpSocket>>,
+    records: Arc<RwLock<DnsCache>>,
+    options: ResolveOptions,
+    embedded: &EmbeddedConfig,
+    stop: impl Future<Output = ()>,
+) -> Result<(), ServeError> {
+    if embedded.enabled {
+        server::serve_inline(socket, records, options, embedded.max_inflight, stop).await
+    } else {
+        server::serve_until(socket, records, options, stop).await
+    }
+}
+
+/// Serves UDP with `workers` receive loops until `stop`: one per socket when
+/// `server::bind_workers` bound several, otherwise all reading the one
+/// socket. Should a loop fail, the others are stopped and its error returned.
+pub async fn serve_udp_workers(
+    sockets: Vec<UdpSocket>,
+    workers: usize,
+    records: Arc<RwLock<DnsCache>>,
+    options: ResolveOptions,
+    embedded: &EmbeddedConfig,
+    stop: impl Future<Output = ()>,
+) -> Result<(), ServeError> {
+    let mut sockets: Vec<Arc<UdpSocket>> = sockets.into_iter().map(Arc::new).collect();
+    if sockets.len() == 1 {
+        if workers <= 1 {
+            return serve_udp(sockets.remove(0), records, options, embedded, stop).await;
+        }
+        sockets = vec![sockets[0].clone(); workers];
+    }
+    let (stopping, stopped) = watch::channel(false);
+    let mut loops = JoinSet::new();
+    for socket in sockets {
+        let (records, options, embedded, mut stopped) = (records.clone(), options.clone(), embedded.clone(), stopped.clone());
+        loops.spawn(async move {
+            let stop = async move {
+                let _ = stopped.wait_for(|stop| *stop).await;
+            };
+            serve_udp(socket, records, options, &embedded, stop).await
+        });
+    }
+    tokio::pin!(stop);
+    let result = tokio::select! {
+        _ = &mut stop => Ok(()),
+        Some(finished) = loops.join_next() => finished.unwrap_or_else(|e| Err(std::io::Error::other(e).into())),
+    };
+    let _ = stopping.send(true);
+    while loops.join_next().await.is_some() {}
+    result
+}
+
+/// A bound listener, as reported by `/status`.
+#[derive(Debug, Clone, Serialize)]
+pub struct ListenerStatus {
+    pub name: String,
+    pub address: String,
+    pub transports: Vec<&'static str>,
+    pub view: Option<String>,
+    pub bound_at: u64, // Seconds since the epoch
+}
+
+struct Active {
+    status: ListenerStatus,
+    stats: Arc<Stats>,
+    /// `None` for the listeners bound at startup, which reloads leave alone
+    running: Option<Running>,
+}
+
+/// Stopping an extra listener: send on `stop`, then wait for both tasks.
+struct Running {
+    config: ListenerConfig,
+    view: ViewConfig,
+    stop: Vec<oneshot::Sender<()>>,
+    tasks: Vec<JoinHandle<()>>,
+}
+
+impl Running {
+    async fn stop(self) {
+        for stop in self.stop {
+            let _ = stop.send(());
+        }
+        for task in self.tasks {
+            let _ = task.await;
+        }
+    }
+}
+
+/// The listeners currently bound. The `[[listeners]]` set can be swapped for a
+/// new one at runtime; see `apply`.
+pub struct Listeners {
+    records: Arc<RwLock<DnsCache>>,
+    options: ResolveOptions,
+    tcp: TcpConfig,
+    dscp: Option<Dscp>,
+    interface: Option<String>,
+    embedded: EmbeddedConfig,
+    active: Mutex<Vec<Active>>,
+}
+
+impl Listeners {
+    /// `options` are those of the default listener, which views are applied to.
+    pub fn new(records: Arc<RwLock<DnsCache>>, options: ResolveOptions, config: &Config) -> Self {
+        Self {
+            records,
+            options,
+            tcp: config.tcp.clone(),
+            dscp: config.dscp,
+            interface: config.listen_interface.clone(),
+            embedded: config.embedded.clone(),
+            active: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Records a listener bound outside of `apply`, e.g. the default one.
+    pub fn register(&self, status: ListenerStatus, stats: Arc<Stats>) {
+        self.active.lock().unwrap().push(Active { status, stats, running: None });
+    }
+
+    /// Marks `name` as bound again just now, after the watchdog rebound it.
+    pub fn rebound(&self, name: &str) {
+        if let Some(active) = self.active.lock().unwrap().iter_mut().find(|a| a.status.name == name) {
+            active.status.bound_at = now();
+        }
+    }
+
+    /// Reports `name` as bound on `address` since just now, after it moved.
+    pub fn moved(&self, name: &str, address: String) {
+        if let Some(active) = self.active.lock().unwrap().iter_mut().find(|a| a.status.name == name) {
+            active.status.address = address;
+            active.status.bound_at = now();
+        }
+    }
+
+    /// Swaps the DDR records every listener advertises, for a changed `[ddr]`
+    /// or `http.listen`.
+    pub fn set_ddr(&self, records: Vec<RData>) {
+        if let Some(ddr) = &self.options.ddr {
+            ddr.replace(records);
+        }
+    }
+
+    pub fn status(&self) -> Vec<ListenerStatus> {
+        self.active.lock().unwrap().iter().map(|a| a.status.clone()).collect()
+    }
+
+    /// Each listener's counters, in the order they were bound.
+    pub fn stats(&self) -> Vec<(String, Arc<Stats>)> {
+        self.active.lock().unwrap().iter().map(|a| (a.status.name.clone(), a.stats.clone())).collect()
+    }
+
+    /// Makes the extra listeners match `desired`. Removed and changed listeners
+    /// stop reading and finish the queries they already received; new and
+    /// changed ones are bound and started. Listeners that fail to bind are
+    /// returned with their error, and the others are unaffected.
+    pub async fn apply(
+        &self,
+        desired: &[ListenerConfig],
+        views: &HashMap<String, ViewConfig>,
+    ) -> Vec<(String, ServeError)> {
+        let view_of = |config: &ListenerConfig| config.view.as_ref().and_then(|v| views.get(v)).cloned().unwrap_or_default();
+        let unchanged = |running: &Running| {
+            desired.iter().any(|d| {
+                d.name == running.config.name
+                    && d.address == running.config.address
+                    && d.view == running.config.view
+                    && view_of(d) == running.view
+            })
+        };
+        let stopping: Vec<Running> = {
+            let mut active = self.active.lock().unwrap();
+            let mut stopping = Vec::new();
+            active.retain_mut(|a| match &a.running {
+                Some(running) if !unchanged(running) => {
+                    stopping.extend(a.running.take());
+                    false
+                }
+                _ => true,
+            });
+            stopping
+        };
+        for running in stopping {
+            tracing::info!("Stopping listener {:?} on {}", running.config.name, running.config.address);
+            running.stop().await;
+        }
+
+        let mut errors = Vec::new();
+        for config in desired {
+            let running = self.active.lock().unwrap().iter().any(|a| a.status.name == config.name);
+            if running {
+                continue;
+            }
+            if let Err(e) = self.start(config, &view_of(config)).await {
+                tracing::error!("Failed to start listener {:?}: {}", config.name, e);
+                errors.push((config.name.clone(), e));
+            }
+        }
+        errors
+    }
+
+    async fn start(&self, config: &ListenerConfig, view: &ViewConfig) -> Result<(), ServeError> {
+        let socket = server::bind(&config.address, self.dscp, self.interface.as_deref()).await?;
+        // Both are bound before anything starts, so a failure leaves nothing running
+        let tcp = if self.tcp.enabled { Some(tcp::bind(&config.address, self.dscp, self.interface.as_deref()).await?) } else { None };
+        self.launch(config, view, socket, tcp);
+        Ok(())
+    }
+
+    /// Starts serving the extra listener `config` on sockets bound for it,
+    /// TCP included when `tcp` is given.
+    pub fn launch(&self, config: &ListenerConfig, view: &ViewConfig, socket: UdpSocket, tcp: Option<TcpListener>) {
+        let options = view.apply(&config.name, &self.options);
+        let address = &config.address;
+        tracing::info!("DNS Server listening on {} (listener {:?})", address, config.name);
+
+        let (mut stop, mut tasks, mut transports) = (Vec::new(), Vec::new(), vec!["udp"]);
+        if let Some(listener) = tcp {
+            tracing::info!("DNS Server listening on {} (TCP)", address);
+            let (tx, rx) = oneshot::channel::<()>();
+            let (records, options, tcp_config) = (self.records.clone(), Arc::new(options.clone()), self.tcp.clone());
+            tasks.push(tokio::spawn(async move {
+                let stats = options.stats.clone();
+                // Open connections keep being served until they go idle
+                tokio::select! {
+                    result = tcp::serve(listener, records, options, tcp_config, stats) => {
+                        if let Err(e) = result {
+                            tracing::error!("TCP listener stopped: {}", e);
+                        }
+                    }
+                    _ = rx => {}
+                }
+            }));
+            stop.push(tx);
+            transports.push("tcp");
+        }
+        let (tx, rx) = oneshot::channel::<()>();
+        let (records, name, embedded) = (self.records.clone(), config.name.clone(), self.embedded.clone());
+        let stats = options.stats.clone();
+        tasks.push(tokio::spawn(async move {
+            let stopped = async {
+                let _ = rx.await;
+            };
+            if let Err(e) = serve_udp(socket, records, options, &embedded, stopped).await {
+                tracing::error!("Listener {:?} stopped: {}", name, e);
+            }
+        }));
+        stop.push(tx);
+
+        self.active.lock().unwrap().push(Active {
+            status: ListenerStatus {
+                name: config.name.clone(),
+                address: address.clone(),
+                transports,
+                view: config.view.clone(),
+                bound_at: now(),
+            },
+            stats,
+            running: Some(Running { config: config.clone(), view: view.clone(), stop, tasks }),
+        });
+    }
+}
+
+pub fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
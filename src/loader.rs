@@ -1,131 +1,2210 @@
-use std::collections::{HashMap, HashSet};
+use std::borrow::Borrow;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::fmt;
 use std::fs;
-use std::net::Ipv4Addr;
-use std::path::Path;
-use anyhow::{Context, Result};
+use std::net::{IpAddr, Ipv4Addr};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+use hickory_proto::error::ProtoError;
+use hickory_proto::rr::domain::Label;
+use hickory_proto::rr::{LowerName, Name};
 use serde::Deserialize;
 
-#[derive(Deserialize, Debug)]
-struct DhcpData {
-    #[serde(rename = "Leases")]
-    leases: Vec<DhcpLease>,
+use crate::database;
+use crate::leases::{self, LeaseFormat};
+use crate::overlay::{Overlay, RuntimeRecord};
+use crate::reservations;
+use crate::reverse::parse_in_addr_arpa;
+use crate::zone::{self, ZoneData};
+use crate::server::ReloadedSettings;
+
+#[derive(Debug, thiserror::Error)]
+pub enum LoadError {
+    #[error("Failed to read DHCP file {path:?}: {source}")]
+    Dhcp {
+        path: PathBuf,
+        #[source]
+        source: std::io::Error,
+    },
+    #[error("Failed to parse DHCP JSON in {path:?}: {source}")]
+    DhcpParse {
+        path: PathBuf,
+        #[source]
+        source: serde_json::Error,
+    },
+    #[error("Failed to read Hosts file {path:?}: {source}")]
+    Hosts {
+        path: PathBuf,
+        #[source]
+        source: std::io::Error,
+    },
+    #[error("Failed to read zone file {path:?}: {source}")]
+    Zone {
+        path: PathBuf,
+        #[source]
+        source: std::io::Error,
+    },
+    #[error("Failed to read DHCP reservations {path:?}: {source}")]
+    Reservations {
+        path: PathBuf,
+        #[source]
+        source: std::io::Error,
+    },
+    #[error("Failed to parse DHCP reservations JSON in {path:?}: {source}")]
+    ReservationsParse {
+        path: PathBuf,
+        #[source]
+        source: serde_json::Error,
+    },
+    #[error("Failed to read records database {path:?}: {message}")]
+    Database { path: PathBuf, message: String },
+    #[error("{} file not found at {path:?}", kind.label())]
+    Missing { kind: SourceKind, path: PathBuf },
+    #[error("Invalid hostname {name:?} at {path:?} line {line}: {reason}")]
+    InvalidHostname {
+        path: PathBuf,
+        line: usize,
+        name: String,
+        reason: String,
+    },
+    #[error("Invalid {kind} entry at {path:?} line {line}: {reason}")]
+    InvalidEntry {
+        path: PathBuf,
+        line: usize,
+        kind: &'static str, // The directive: `ptr`, `cname`, `txt` or `mx`
+        reason: String,
+    },
+}
+
+impl LoadError {
+    pub fn kind(&self) -> SourceKind {
+        match self {
+            LoadError::Dhcp { .. } | LoadError::DhcpParse { .. } => SourceKind::Dhcp,
+            LoadError::Hosts { .. } | LoadError::InvalidHostname { .. } | LoadError::InvalidEntry { .. } => SourceKind::Hosts,
+            LoadError::Zone { .. } => SourceKind::Zone,
+            LoadError::Database { .. } => SourceKind::Database,
+            LoadError::Reservations { .. } | LoadError::ReservationsParse { .. } => SourceKind::Reservation,
+            LoadError::Missing { kind, .. } => *kind,
+        }
+    }
+
+    /// Read errors may clear up on their own; missing or unparsable files only change when edited.
+    pub fn is_read_error(&self) -> bool {
+        matches!(
+            self,
+            LoadError::Dhcp { .. }
+                | LoadError::Hosts { .. }
+                | LoadError::Zone { .. }
+                | LoadError::Database { .. }
+                | LoadError::Reservations { .. }
+        )
+    }
+
+    pub fn path(&self) -> &Path {
+        match self {
+            LoadError::Dhcp { path, .. }
+            | LoadError::DhcpParse { path, .. }
+            | LoadError::Hosts { path, .. }
+            | LoadError::Zone { path, .. }
+            | LoadError::Missing { path, .. }
+            | LoadError::InvalidHostname { path, .. }
+            | LoadError::InvalidEntry { path, .. }
+            | LoadError::Database { path, .. }
+            | LoadError::Reservations { path, .. }
+            | LoadError::ReservationsParse { path, .. } => path,
+        }
+    }
+}
+
+/// A record limit that was exceeded while `limits_policy` is `"fail"`.
+#[derive(Debug, thiserror::Error)]
+#[error("{limit} exceeded{}: found {found}, limit is {max}", name.as_ref().map(|n| format!(" for {}", n)).unwrap_or_default())]
+pub struct LimitExceeded {
+    pub limit: &'static str,
+    pub name: Option<String>,
+    pub found: usize,
+    pub max: usize,
+}
+
+/// Names generated for leases whose client sent no hostname.
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum SynthesizeUnnamed {
+    /// Unnamed leases get no records
+    #[default]
+    Off,
+    /// `mac-a1b2c3d4e5f6.<suffix>`; leases without a known MAC are skipped
+    Mac,
+    /// `ip-192-168-1-77.<suffix>`
+    Ip,
+}
+
+/// What happens to a DHCP hostname that isn't a valid DNS name, such as
+/// `Tom's iPad` or one with an emoji that has no IDNA form.
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum HostnamePolicy {
+    /// Characters other than letters, digits and hyphens become the replacement
+    #[default]
+    Sanitize,
+    /// The lease gets no name, with a warning
+    Skip,
+}
+
+/// How DHCP hostnames are turned into names; see `sanitize_hostname`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HostnameRules {
+    pub policy: HostnamePolicy,
+    /// What runs of illegal characters become, `-` by default; may be empty
+    pub replacement: String,
+}
+
+impl Default for HostnameRules {
+    fn default() -> Self {
+        HostnameRules { policy: HostnamePolicy::default(), replacement: "-".to_string() }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum SourceKind {
+    Dhcp,
+    Hosts,
+    Config,
+    Database,
+    Reservation,
+    Zone,
+    /// Added over the control socket or the HTTP API
+    Runtime,
+}
+
+impl SourceKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SourceKind::Dhcp => "dhcp",
+            SourceKind::Hosts => "hosts",
+            SourceKind::Config => "config",
+            SourceKind::Database => "database",
+            SourceKind::Reservation => "reservation",
+            SourceKind::Zone => "zone",
+            SourceKind::Runtime => "runtime",
+        }
+    }
+
+    /// Which source's name an address reverses to first: leases, then
+    /// reservations, then static files.
+    fn ptr_rank(&self) -> u8 {
+        match self {
+            SourceKind::Dhcp => 0,
+            SourceKind::Reservation => 1,
+            SourceKind::Hosts => 2,
+            SourceKind::Zone => 3,
+            SourceKind::Database => 4,
+            SourceKind::Config => 5,
+            SourceKind::Runtime => 6,
+        }
+    }
+
+    fn label(&self) -> &'static str {
+        match self {
+            SourceKind::Dhcp => "DHCP",
+            SourceKind::Hosts => "Hosts",
+            SourceKind::Config => "Config",
+            SourceKind::Database => "Database",
+            SourceKind::Reservation => "DHCP reservations",
+            SourceKind::Zone => "Zone",
+            SourceKind::Runtime => "Runtime",
+        }
+    }
+}
+
+/// Where a record came from. `location` is 1-based, and counts lines when
+/// `by_line` is set and entries otherwise:
+///
+/// - `Hosts`, `Zone`: the line
+/// - `Dhcp`: the line of a dnsmasq or Kea lease or of an ISC stanza's
+///   `lease` keyword, or the entry of a JSON `Leases` array
+/// - `Config`: the entry in the array of tables; for `[cnames]`, among the
+///   aliases sorted by name
+/// - `Database`: the row
+/// - `Reservation`: the line of a dnsmasq entry, or the entry of a Kea one
+/// - `Runtime`: the order of addition
+#[derive(Debug, Clone)]
+pub struct Source {
+    pub kind: SourceKind,
+    pub path: Arc<Path>, // Shared by every record loaded from the same file
+    pub location: usize,
+    pub by_line: bool,
+}
+
+impl fmt::Display for Source {
+    /// `kind path:line` for lines and `kind path#entry` for entries.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.kind {
+            // Not read from any file
+            SourceKind::Runtime => write!(f, "runtime #{}", self.location),
+            _ if self.by_line => write!(f, "{} {}:{}", self.kind.as_str(), self.path.display(), self.location),
+            _ => write!(f, "{} {}#{}", self.kind.as_str(), self.path.display(), self.location),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Entry {
+    pub ip: IpAddr, // An A record, or AAAA for IPv6
+    pub source: Source,
+    /// Seconds, when the record, its name, or its source sets one; otherwise the `ttl` setting
+    pub ttl: Option<u32>,
+}
+
+/// An explicitly configured reverse record, from a hosts `ptr` line or `[[ptr_records]]`.
+#[derive(Debug, Clone)]
+pub struct PtrRecord {
+    pub ip: Ipv4Addr,
+    pub name: String, // Key form, see `name_key`
+    pub source: Source,
+}
+
+/// A CNAME, from a hosts `cname` line or the `[cnames]` table.
+#[derive(Debug, Clone)]
+pub struct Alias {
+    pub target: String, // Key form, see `name_key`
+    pub source: Source,
+}
+
+/// A TXT record, from a zone file, a hosts `txt` line or `[[records]]`.
+#[derive(Debug, Clone)]
+pub struct TxtRecord {
+    /// The character-strings, each at most 255 bytes
+    pub strings: Vec<Vec<u8>>,
+    pub source: Source,
+    pub ttl: Option<u32>,
+}
+
+/// An MX record, from a hosts `mx` line or `[[records]]`.
+#[derive(Debug, Clone)]
+pub struct MxRecord {
+    pub preference: u16,
+    pub exchange: String, // Key form, see `name_key`
+    pub source: Source,
+    pub ttl: Option<u32>,
+}
+
+/// An SRV record, from `[[records]]`.
+#[derive(Debug, Clone)]
+pub struct SrvRecord {
+    pub priority: u16,
+    pub weight: u16,
+    pub port: u16,
+    pub target: String, // Key form, see `name_key`
+    pub source: Source,
+    pub ttl: Option<u32>,
+}
+
+/// A `[[records]]` entry in the config file.
+#[derive(Deserialize, Debug, Clone)]
+pub struct RecordConfig {
+    pub name: String, // `*.` in front makes a wildcard, as in the hosts file
+    #[serde(rename = "type", default = "default_record_type")]
+    pub kind: String,
+    /// The address, or the text of a TXT record; MX and SRV records use the fields below
+    #[serde(default)]
+    pub value: String,
+    pub preference: Option<u16>,
+    pub exchange: Option<String>,
+    pub priority: Option<u16>,
+    pub weight: Option<u16>,
+    pub port: Option<u16>,
+    pub target: Option<String>,
+    pub ttl: Option<u32>,
+}
+
+fn default_record_type() -> String {
+    "A".to_string()
+}
+
+/// A `[[records]]` entry as `load_records` reads it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConfigRecord {
+    /// `(is_wildcard, key, address)`
+    Address(bool, String, IpAddr),
+    /// The name's key and its character-strings
+    Txt(String, Vec<Vec<u8>>),
+    /// `(key, preference, exchange key)`
+    Mx(String, u16, String),
+    /// `(key, priority, weight, port, target key)`
+    Srv(String, u16, u16, u16, String),
+}
+
+impl RecordConfig {
+    /// Checks the entry as `load_records` will read it. A TXT `value` is the
+    /// text itself, split into 255-byte character-strings when longer.
+    pub fn parse(&self) -> Result<ConfigRecord, String> {
+        match self.kind.to_uppercase().as_str() {
+            kind @ ("A" | "AAAA") => parse_address_record(kind, &self.name, &self.value)
+                .map(|(wildcard, name, ip)| ConfigRecord::Address(wildcard, name, ip)),
+            "TXT" => Ok(ConfigRecord::Txt(parse_name(&self.name)?, character_strings(self.value.as_bytes()))),
+            "MX" => match (self.preference, &self.exchange) {
+                (Some(preference), Some(exchange)) => {
+                    Ok(ConfigRecord::Mx(parse_name(&self.name)?, preference, parse_name(exchange)?))
+                }
+                _ => Err("MX records need `preference` and `exchange`".to_string()),
+            },
+            "SRV" => match (self.priority, self.weight, self.port, &self.target) {
+                (Some(priority), Some(weight), Some(port), Some(target)) => {
+                    Ok(ConfigRecord::Srv(parse_name(&self.name)?, priority, weight, port, parse_name(target)?))
+                }
+                _ => Err("SRV records need `priority`, `weight`, `port` and `target`".to_string()),
+            },
+            _ => Err(format!("unsupported record type {:?}", self.kind)),
+        }
+    }
 }
 
-#[derive(Deserialize, Debug)]
-struct DhcpLease {
-    #[serde(rename = "Address")]
-    address: [u8; 4],
-    #[serde(rename = "Hostname")]
-    hostname: String,
+/// A `[[ptr_records]]` entry in the config file.
+#[derive(Deserialize, Debug, Clone)]
+pub struct PtrRecordConfig {
+    pub address: Ipv4Addr,
+    pub name: String,
 }
 
+/// A line, row or entry a source couldn't use, left out with a warning.
+#[derive(Debug, Clone)]
+pub struct Skipped {
+    /// What was left out, e.g. `invalid name "nas_1"`
+    pub what: String,
+    pub source: Source,
+    pub reason: String,
+}
+
+impl fmt::Display for Skipped {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ({}): {}", self.what, self.source, self.reason)
+    }
+}
+
+/// A name for which two or more source files list different address sets.
+#[derive(Debug, Clone)]
+pub struct Conflict {
+    pub name: String,
+    pub sources: Vec<(SourceKind, Arc<Path>, Vec<IpAddr>)>,
+}
+
+impl fmt::Display for Conflict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:", self.name)?;
+        for (i, (kind, path, ips)) in self.sources.iter().enumerate() {
+            let kind = kind.as_str();
+            let ips: Vec<String> = ips.iter().map(|ip| ip.to_string()).collect();
+            let sep = if i == 0 { " " } else { "; " };
+            write!(f, "{}{} {} => [{}]", sep, kind, path.display(), ips.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Default)]
 pub struct DnsCache {
-    pub exact_matches: HashMap<String, Vec<Ipv4Addr>>,
-    pub wildcards: Vec<(String, Ipv4Addr)>, // Stores patterns like "*.example.com."
+    pub exact_matches: HashMap<LowerName, Vec<Entry>>, // Keyed as `parse_key` gives, so queries look up their own `Name`
+    pub wildcards: Vec<(String, Entry)>, // Stores patterns like "*.example.com."; see `index_wildcards`
+    pub conflicts: Vec<Conflict>,
+    pub skipped: Vec<Skipped>, // Left out of the sources with a warning, in source order
+    pub ptrs: Vec<PtrRecord>,
+    pub reverse: HashMap<Ipv4Addr, Vec<String>>, // PTR names per IPv4 address, the primary one first
+    pub failures: Vec<LoadError>, // Sources that were missing or failed to read or parse
+    pub snapshot_written: Option<SystemTime>, // Set when the records came from a snapshot
+    pub serial: u32, // Zone serial, assigned by `SerialTracker` before the cache is served
+    pub synthesized: HashSet<LowerName>, // Exact names generated for leases without a hostname
+    pub cnames: HashMap<LowerName, Alias>, // Alias name to its target; aliases have no addresses
+    pub txts: HashMap<LowerName, Vec<TxtRecord>>, // In the order their sources list them
+    pub mxs: HashMap<LowerName, Vec<MxRecord>>, // Likewise; answers are sorted by preference
+    pub srvs: HashMap<LowerName, Vec<SrvRecord>>, // Likewise; answers are sorted by priority
+    pub blocked: HashMap<LowerName, Vec<Entry>>, // Names listed only at 0.0.0.0 or ::, set aside when blocklist_mode is on
+    pub blocked_wildcards: Vec<(String, Entry)>,
+    pub settings: Option<Arc<ReloadedSettings>>, // The TTL and SOA as of the last SIGHUP, if there was one
+    pub next_expiry: Option<u64>, // When the first served DHCP lease expires, in seconds since the epoch
+    pub included: Vec<PathBuf>, // Files hosts files include, found or not, so they can be watched too
+    pub(crate) wildcard_index: HashMap<LowerName, Vec<usize>>, // Pattern parent (`example.com.`) to positions in `wildcards`
+}
+
+impl DnsCache {
+    /// Rebuilds the index `covering_wildcards` looks patterns up in. Loading
+    /// does this; call it again after changing `wildcards` by hand.
+    pub fn index_wildcards(&mut self) {
+        self.wildcard_index.clear();
+        for (position, (pattern, _)) in self.wildcards.iter().enumerate() {
+            if pattern.starts_with("*.") {
+                self.wildcard_index.entry(table_key(pattern).base_name()).or_default().push(position);
+            }
+        }
+    }
+
+    /// The wildcards covering `name`, the most specific pattern first
+    /// (`*.b.lan.` before `*.lan.` for `a.b.lan.`) and each pattern's entries
+    /// in load order. This is one map lookup per label of `name`, however
+    /// many wildcards there are.
+    pub fn covering_wildcards<'c>(&'c self, name: &LowerName) -> impl Iterator<Item = &'c (String, Entry)> + 'c {
+        let parent = (!name.is_root()).then(|| name.base_name());
+        std::iter::successors(parent, |parent| (!parent.is_root()).then(|| parent.base_name()))
+            .filter_map(|parent| self.wildcard_index.get(&parent))
+            .flatten()
+            .map(|&position| &self.wildcards[position])
+    }
+
+    /// Brings the runtime records in line with `overlay` without reloading the
+    /// sources, so the next query sees a change made over the control socket or
+    /// the HTTP API. `[ttl_overrides]` reach the added records on the next load.
+    pub fn apply_overlay(&mut self, overlay: &Overlay) {
+        let removed = overlay.removed();
+        for (name, entries) in self.exact_matches.iter_mut() {
+            let removed = !removed.is_empty() && removed.contains(&name_key(name));
+            entries.retain(|entry| entry.source.kind != SourceKind::Runtime && !removed);
+        }
+        self.wildcards
+            .retain(|(pattern, entry)| entry.source.kind != SourceKind::Runtime && !removed.contains(pattern));
+        for (entry, record) in runtime_entries(overlay.records()) {
+            if record.name.starts_with("*.") {
+                self.wildcards.push((record.name, entry));
+                continue;
+            }
+            let entries = self.exact_matches.entry(table_key(&record.name)).or_default();
+            if !entries.iter().any(|existing| existing.ip == entry.ip) {
+                entries.push(entry);
+                entries.sort_by_key(|entry| entry.ip);
+            }
+        }
+        self.exact_matches.retain(|_, entries| !entries.is_empty());
+        self.synthesized.retain(|name| self.exact_matches.contains_key(name));
+        self.build_reverse();
+        self.index_wildcards();
+    }
+
+    /// Rebuilds `reverse` from `exact_matches` and `ptrs`. An address with
+    /// explicit PTRs reverses to those alone; the generated names it replaced
+    /// are returned as `(address, explicit, generated)`. Each address's names
+    /// are kept primary first, which is all `PtrNames::Primary` answers with.
+    pub fn build_reverse(&mut self) -> Vec<(Ipv4Addr, Vec<String>, Vec<String>)> {
+        self.reverse.clear();
+        let mut generated: HashMap<Ipv4Addr, Vec<(_, String)>> = HashMap::new();
+        for (domain, entries) in &self.exact_matches {
+            for entry in entries {
+                if let IpAddr::V4(ip) = entry.ip {
+                    // Real names before generated ones, then leases in file order, then the hosts file
+                    let rank = (self.synthesized.contains(domain), entry.source.kind.ptr_rank(), &entry.source.path, entry.source.location);
+                    generated.entry(ip).or_default().push((rank, name_key(domain)));
+                }
+            }
+        }
+        for (ip, mut names) in generated {
+            names.sort();
+            self.reverse.insert(ip, names.into_iter().map(|(_, name)| name).collect());
+        }
+        // Explicit PTRs keep the order they are listed in
+        let mut explicit: HashMap<Ipv4Addr, Vec<String>> = HashMap::new();
+        for ptr in &self.ptrs {
+            let names = explicit.entry(ptr.ip).or_default();
+            if !names.contains(&ptr.name) {
+                names.push(ptr.name.clone());
+            }
+        }
+
+        let mut overridden = Vec::new();
+        for (ip, names) in explicit {
+            if let Some(mut generated) = self.reverse.remove(&ip) {
+                let mut sorted = names.clone();
+                sorted.sort();
+                generated.sort();
+                if generated != sorted {
+                    overridden.push((ip, sorted, generated));
+                }
+            }
+            self.reverse.insert(ip, names);
+        }
+        overridden.sort();
+        overridden
+    }
+
+    /// Whether a fresh load should be swapped in for `current`. Records restored
+    /// from a snapshot are only replaced once every source loads again.
+    pub fn can_replace(&self, current: &DnsCache) -> bool {
+        if current.snapshot_written.is_some() {
+            return self.failures.is_empty();
+        }
+        self.fails_no_more_than(current)
+    }
+
+    /// True if `other` loaded every source that failed here, i.e. swapping `self`
+    /// in for `other` would not lose a source that was working.
+    pub fn fails_no_more_than(&self, other: &DnsCache) -> bool {
+        self.failures.iter().all(|failure| {
+            other
+                .failures
+                .iter()
+                .any(|f| f.kind() == failure.kind() && f.path() == failure.path())
+        })
+    }
 }
 
-impl Default for DnsCache {
+/// Caps on what a load may produce, so a runaway generated file can't exhaust memory.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct RecordLimits {
+    /// Distinct exact names across all sources
+    pub max_records: usize,
+    /// Wildcard patterns across all sources
+    pub max_wildcards: usize,
+    /// Distinct addresses for a single name
+    pub max_addresses_per_name: usize,
+    /// CNAMEs followed within one answer, so a loop in the records can't run forever
+    pub max_alias_chain: usize,
+}
+
+impl Default for RecordLimits {
     fn default() -> Self {
         Self {
-            exact_matches: HashMap::new(),
-            wildcards: Vec::new(),
+            max_records: 1_000_000,
+            max_wildcards: 100_000,
+            max_addresses_per_name: 256,
+            max_alias_chain: 8,
+        }
+    }
+}
+
+/// What to do when a load exceeds a `RecordLimits` cap.
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum LimitsPolicy {
+    /// Keep the records listed first, up to the limit, and warn
+    #[default]
+    Truncate,
+    /// Reject the whole load
+    Fail,
+}
+
+/// Records produced by a single source file, merged by `load_records`.
+#[derive(Default)]
+struct Parsed {
+    exact: Vec<(String, Entry)>,
+    wildcards: Vec<(String, Entry)>,
+    ptrs: Vec<PtrRecord>,
+    synthesized: Vec<(String, Entry)>, // Names generated for unnamed leases
+    cnames: Vec<(String, Alias)>,
+    txts: Vec<(String, TxtRecord)>,
+    mxs: Vec<(String, MxRecord)>,
+    srvs: Vec<(String, SrvRecord)>,
+    skipped: Vec<Skipped>,
+    next_expiry: Option<u64>,
+    included: Vec<PathBuf>,
+}
+
+impl Parsed {
+    /// Leaves `what` out with a warning, keeping it for `localdns check`.
+    fn skip(&mut self, what: String, source: &Source, reason: impl fmt::Display) {
+        skip(&mut self.skipped, what, source, reason);
+    }
+}
+
+fn skip(skipped: &mut Vec<Skipped>, what: String, source: &Source, reason: impl fmt::Display) {
+    let entry = Skipped { what, source: source.clone(), reason: reason.to_string() };
+    tracing::warn!("Skipping {}", entry);
+    skipped.push(entry);
+}
+
+/// Everything `load_records` needs to know about the sources.
+#[derive(Debug, Clone)]
+pub struct LoadOptions {
+    pub dhcp_path: PathBuf,
+    pub dhcp_format: LeaseFormat,
+    /// Serve leases past their expiry, as if the file carried no timestamps
+    pub dhcp_ignore_expiry: bool,
+    pub hosts_path: PathBuf,
+    /// More hosts files, loaded after `hosts_path` in this order
+    pub hosts_paths: Vec<PathBuf>,
+    /// A directory whose `*.hosts` files are loaded last, sorted by name
+    pub hosts_dir: Option<PathBuf>,
+    /// RFC 1035 zone files, loaded after the hosts files in this order
+    pub zone_paths: Vec<PathBuf>,
+    pub suffix: String,
+    pub retry: RetryPolicy,
+    pub limits: RecordLimits,
+    pub limits_policy: LimitsPolicy,
+    pub config_path: PathBuf, // Reported as the source of config-defined records
+    pub ptr_records: Vec<PtrRecordConfig>,
+    /// `[[records]]`, re-added on every load
+    pub records: Vec<RecordConfig>,
+    /// `[cnames]` as `(alias, target)`, sorted by alias
+    pub cnames: Vec<(String, String)>,
+    /// dnsmasq-style `/domain/address` entries, see `parse_address_directive`
+    pub addresses: Vec<String>,
+    /// Shared with the control socket, so every load sees its latest changes
+    pub overlay: Arc<Overlay>,
+    /// Fail the hosts source on an invalid hostname instead of skipping the name
+    pub strict_hosts: bool,
+    pub synthesize_unnamed: SynthesizeUnnamed,
+    pub hostnames: HostnameRules,
+    pub records_db: Option<PathBuf>,
+    pub records_db_query: String,
+    pub reservations_path: Option<PathBuf>,
+    pub reservation_precedence: ReservationPrecedence,
+    pub ttl: TtlPolicy,
+    /// Set names listed at 0.0.0.0 or :: aside as blocks instead of serving the address
+    pub blocklist: bool,
+}
+
+impl LoadOptions {
+    /// Just the lease and hosts files under `suffix`, with every optional
+    /// source off and every policy at its default.
+    pub fn new(dhcp_path: PathBuf, hosts_path: PathBuf, suffix: &str, config_path: PathBuf) -> Self {
+        Self {
+            dhcp_path,
+            dhcp_format: LeaseFormat::Auto,
+            dhcp_ignore_expiry: false,
+            hosts_path,
+            hosts_paths: Vec::new(),
+            hosts_dir: None,
+            zone_paths: Vec::new(),
+            suffix: suffix.to_string(),
+            retry: RetryPolicy::default(),
+            limits: RecordLimits::default(),
+            limits_policy: LimitsPolicy::Truncate,
+            config_path,
+            ptr_records: Vec::new(),
+            records: Vec::new(),
+            cnames: Vec::new(),
+            addresses: Vec::new(),
+            overlay: Arc::default(),
+            strict_hosts: false,
+            synthesize_unnamed: SynthesizeUnnamed::Off,
+            hostnames: HostnameRules::default(),
+            records_db: None,
+            records_db_query: database::DEFAULT_QUERY.to_string(),
+            reservations_path: None,
+            reservation_precedence: ReservationPrecedence::Lease,
+            ttl: TtlPolicy::default(),
+            blocklist: false,
+        }
+    }
+}
+
+/// Which address wins when a reservation and a live lease share a hostname.
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ReservationPrecedence {
+    /// The lease, since it is what the device is actually using
+    #[default]
+    Lease,
+    Reservation,
+}
+
+/// TTLs that apply to whole sources or names. A TTL set on the record itself,
+/// such as a database row's, wins over both.
+#[derive(Debug, Clone, Default)]
+pub struct TtlPolicy {
+    pub dhcp: Option<u32>,
+    pub hosts: Option<u32>,
+    /// Name keys (`nas.lan.`), each covering the name and everything under it.
+    /// The longest matching name wins over the source TTLs.
+    pub overrides: Vec<(String, u32)>,
+}
+
+impl TtlPolicy {
+    fn ttl(&self, name: &str, entry: &Entry) -> Option<u32> {
+        let by_name = self
+            .overrides
+            .iter()
+            .filter(|(domain, _)| name == domain || is_subdomain(name, domain))
+            .max_by_key(|(domain, _)| domain.len())
+            .map(|(_, ttl)| *ttl);
+        let by_source = match entry.source.kind {
+            SourceKind::Dhcp => self.dhcp,
+            SourceKind::Hosts => self.hosts,
+            _ => None,
+        };
+        entry.ttl.or(by_name).or(by_source)
+    }
+
+    fn apply(&self, cache: &mut DnsCache) {
+        for (name, entries) in cache.exact_matches.iter_mut() {
+            let name = name_key(name);
+            for entry in entries {
+                entry.ttl = self.ttl(&name, entry);
+            }
+        }
+        // A wildcard's name is its pattern, so `dev.lan` covers `*.dev.lan`
+        for (pattern, entry) in cache.wildcards.iter_mut() {
+            entry.ttl = self.ttl(pattern, entry);
+        }
+    }
+}
+
+/// How often to retry a source file read that failed with a transient error.
+/// The delay doubles after every failed attempt.
+#[derive(Debug, Clone, Copy)]
+pub struct RetryPolicy {
+    pub attempts: u32,
+    pub backoff: Duration,
+    /// Makes one attempt; `fs::read_to_string` unless a test stands in a flaky mount
+    pub read: fn(&Path) -> std::io::Result<String>,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            attempts: 3,
+            backoff: Duration::from_millis(500),
+            read: |path| fs::read_to_string(path),
+        }
+    }
+}
+
+/// Errors that are worth retrying, e.g. an NFS mount returning ESTALE or timing out.
+fn is_transient(e: &std::io::Error) -> bool {
+    use std::io::ErrorKind::*;
+    matches!(
+        e.kind(),
+        Interrupted | TimedOut | WouldBlock | StaleNetworkFileHandle | ResourceBusy
+    ) || is_sharing_violation(e)
+}
+
+/// On Windows, a file an editor such as Notepad briefly holds open while saving
+/// can't be read (ERROR_SHARING_VIOLATION, ERROR_LOCK_VIOLATION).
+fn is_sharing_violation(e: &std::io::Error) -> bool {
+    cfg!(windows) && matches!(e.raw_os_error(), Some(32 | 33))
+}
+
+/// Reads a source file, without the UTF-8 byte order mark Windows editors tend to add.
+pub fn read_with_retry(path: &Path, retry: RetryPolicy) -> std::io::Result<String> {
+    let mut delay = retry.backoff;
+    let mut attempt = 1;
+    loop {
+        match (retry.read)(path) {
+            Ok(content) => {
+                return Ok(match content.strip_prefix('\u{feff}') {
+                    Some(stripped) => stripped.to_string(),
+                    None => content,
+                })
+            }
+            Err(e) if is_sharing_violation(&e) && attempt < retry.attempts => {
+                // Expected while the file is being saved, so not worth a warning
+                thread::sleep(delay);
+                delay *= 2;
+                attempt += 1;
+            }
+            Err(e) if is_transient(&e) && attempt < retry.attempts => {
+                tracing::warn!(
+                    "Transient error reading {:?} (attempt {}/{}): {}. Retrying in {:?}",
+                    path, attempt, retry.attempts, e, delay
+                );
+                thread::sleep(delay);
+                delay *= 2;
+                attempt += 1;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Loads every source. A source that is missing or fails to read or parse is
+/// recorded in `DnsCache::failures` and the other sources are still loaded.
+/// Exceeding a limit is only an error under `LimitsPolicy::Fail`.
+pub fn load_records(options: &LoadOptions) -> Result<DnsCache, LimitExceeded> {
+    let dhcp_path = options.dhcp_path.as_path();
+    let (hosts_paths, hosts_dir_failure) = hosts_files(options);
+    let (suffix, retry) = (options.suffix.as_str(), options.retry);
+    let (limits, policy) = (&options.limits, options.limits_policy);
+    let mut cache = DnsCache::default();
+    // Every entry is kept until the end so disagreeing sources can be detected
+    let mut exact_records_temp: HashMap<String, Vec<Entry>> = HashMap::new();
+    let mut first_seen: Vec<String> = Vec::new(); // Names in the order the sources list them
+
+    // Sources parse independently, so each gets a thread. Results are merged in
+    // this fixed order, whichever finishes first, to keep the outcome deterministic.
+    let started = Instant::now();
+    let results = thread::scope(|scope| {
+        let (format, unnamed, ignore_expiry) = (options.dhcp_format, options.synthesize_unnamed, options.dhcp_ignore_expiry);
+        let hostnames = &options.hostnames;
+        let dhcp = scope.spawn(move || {
+            timed(SourceKind::Dhcp, dhcp_path, || load_dhcp(dhcp_path, format, suffix, retry, unnamed, ignore_expiry, hostnames))
+        });
+        let strict = options.strict_hosts;
+        let hosts: Vec<_> = hosts_paths
+            .iter()
+            .map(|hosts_path| scope.spawn(move || timed(SourceKind::Hosts, hosts_path, || load_hosts(hosts_path, retry, strict))))
+            .collect();
+        let zones: Vec<_> = options
+            .zone_paths
+            .iter()
+            .map(|zone_path| scope.spawn(move || timed(SourceKind::Zone, zone_path, || load_zone(zone_path, suffix, retry))))
+            .collect();
+        let reservations = options.reservations_path.as_deref().map(|res_path| {
+            scope.spawn(move || {
+                timed(SourceKind::Reservation, res_path, || load_reservations(res_path, suffix, retry))
+            })
+        });
+        let database = options.records_db.as_deref().map(|db_path| {
+            let query = options.records_db_query.as_str();
+            scope.spawn(move || timed(SourceKind::Database, db_path, || load_database(db_path, query)))
+        });
+        let mut results = std::iter::once(dhcp)
+            .chain(hosts)
+            .chain(zones)
+            .chain(reservations)
+            .chain(database)
+            .map(|handle| handle.join().expect("source parser panicked"))
+            .collect::<Vec<_>>();
+        results.extend(hosts_dir_failure.map(Err));
+        // Already validated with the config, so there is nothing to read or fail
+        if !options.records.is_empty() || !options.addresses.is_empty() {
+            results.push(Ok(config_records(&options.config_path, &options.records, &options.addresses)));
+        }
+        let runtime = options.overlay.records();
+        if !runtime.is_empty() {
+            results.push(Ok(runtime_records(runtime)));
+        }
+        results
+    });
+    let source_count = results.len();
+    // The hosts files come right after the DHCP file
+    let hosts_entries: usize = results[1..=hosts_paths.len()]
+        .iter()
+        .flatten()
+        .map(|parsed| {
+            parsed.exact.len() + parsed.wildcards.len() + parsed.ptrs.len() + parsed.cnames.len() + parsed.txts.len() + parsed.mxs.len()
+        })
+        .sum();
+    let mut synthesized = Vec::new();
+    let mut aliases = Vec::new();
+    for result in results {
+        match result {
+            Ok(parsed) => {
+                synthesized.extend(parsed.synthesized);
+                for (domain, entry) in parsed.exact {
+                    let entries = exact_records_temp.entry(domain).or_insert_with_key(|domain| {
+                        first_seen.push(domain.clone());
+                        Vec::new()
+                    });
+                    entries.push(entry);
+                }
+                cache.wildcards.extend(parsed.wildcards);
+                cache.ptrs.extend(parsed.ptrs);
+                aliases.extend(parsed.cnames);
+                for (name, txt) in parsed.txts {
+                    cache.txts.entry(table_key(&name)).or_default().push(txt);
+                }
+                for (name, mx) in parsed.mxs {
+                    cache.mxs.entry(table_key(&name)).or_default().push(mx);
+                }
+                for (name, srv) in parsed.srvs {
+                    cache.srvs.entry(table_key(&name)).or_default().push(srv);
+                }
+                cache.skipped.extend(parsed.skipped);
+                for path in parsed.included {
+                    if !cache.included.contains(&path) {
+                        cache.included.push(path);
+                    }
+                }
+                cache.next_expiry = cache.next_expiry.into_iter().chain(parsed.next_expiry).min();
+            }
+            Err(e) => {
+                tracing::warn!("{}", e);
+                cache.failures.push(e);
+            }
+        }
+    }
+
+    // Names removed over the control socket keep only what was added there since
+    let removed = options.overlay.removed();
+    if !removed.is_empty() {
+        for (domain, entries) in exact_records_temp.iter_mut() {
+            if removed.contains(domain) {
+                entries.retain(|entry| entry.source.kind == SourceKind::Runtime);
+            }
+        }
+        exact_records_temp.retain(|_, entries| !entries.is_empty());
+        first_seen.retain(|domain| exact_records_temp.contains_key(domain));
+        cache.wildcards.retain(|(pattern, entry)| entry.source.kind == SourceKind::Runtime || !removed.contains(pattern));
+    }
+
+    // Generated names come last and never join or replace a real one
+    let mut generated = HashSet::new();
+    for (domain, entry) in synthesized {
+        if exact_records_temp.contains_key(&domain) && !generated.contains(&domain) {
+            tracing::warn!("Not generating {} ({}), a real record has that name", domain, entry.source);
+            continue;
+        }
+        generated.insert(domain.clone());
+        exact_records_temp.entry(domain).or_insert_with_key(|domain| {
+            first_seen.push(domain.clone());
+            Vec::new()
+        }).push(entry);
+    }
+
+    // A reservation and a live lease for the same host keep only the preferred address
+    let loser = match options.reservation_precedence {
+        ReservationPrecedence::Lease => SourceKind::Reservation,
+        ReservationPrecedence::Reservation => SourceKind::Dhcp,
+    };
+    for entries in exact_records_temp.values_mut() {
+        drop_superseded(entries, loser);
+    }
+    let kinds_of = |kind| -> HashSet<String> {
+        cache.wildcards.iter().filter(|(_, e)| e.source.kind == kind).map(|(p, _)| p.clone()).collect()
+    };
+    let (leased, reserved) = (kinds_of(SourceKind::Dhcp), kinds_of(SourceKind::Reservation));
+    cache
+        .wildcards
+        .retain(|(pattern, e)| e.source.kind != loser || !(leased.contains(pattern) && reserved.contains(pattern)));
+
+    check_limit(policy, "max_records", None, first_seen.len(), limits.max_records)?;
+    check_limit(policy, "max_wildcards", None, cache.wildcards.len(), limits.max_wildcards)?;
+    for domain in first_seen.drain(limits.max_records.min(first_seen.len())..) {
+        exact_records_temp.remove(&domain);
+    }
+    cache.wildcards.truncate(limits.max_wildcards);
+
+    for domain in first_seen {
+        let mut entries = exact_records_temp.remove(&domain).unwrap_or_default();
+        if let Some(conflict) = find_conflict(&domain, &entries) {
+            tracing::warn!("Conflicting addresses for {}", conflict);
+            cache.conflicts.push(conflict);
+        }
+
+        // Drop duplicate addresses, keeping the first source to list each one
+        let mut seen = HashSet::new();
+        entries.retain(|entry| seen.insert(entry.ip));
+        check_limit(policy, "max_addresses_per_name", Some(&domain), entries.len(), limits.max_addresses_per_name)?;
+        entries.truncate(limits.max_addresses_per_name);
+        entries.sort_by_key(|entry| entry.ip);
+        if generated.contains(&domain) {
+            cache.synthesized.insert(table_key(&domain));
+        }
+        cache.exact_matches.insert(table_key(&domain), entries);
+    }
+    cache.conflicts.sort_by(|a, b| a.name.cmp(&b.name));
+    options.ttl.apply(&mut cache);
+    if options.blocklist {
+        split_blocks(&mut cache);
+    }
+    cache.synthesized.retain(|domain| cache.exact_matches.contains_key(domain));
+
+    aliases.extend(config_cnames(&options.config_path, &options.cnames));
+    add_cnames(&mut cache, aliases);
+
+    cache.ptrs.extend(config_ptrs(&options.config_path, &options.ptr_records));
+    for (ip, explicit, generated) in cache.build_reverse() {
+        tracing::warn!(
+            "Explicit PTR for {} ({}) overrides the generated name(s) {}",
+            ip,
+            explicit.join(", "),
+            generated.join(", ")
+        );
+    }
+
+    tracing::info!(
+        "Loaded {} sources in {:?} ({} hosts file(s) with {} entries)",
+        source_count,
+        started.elapsed(),
+        hosts_paths.len(),
+        hosts_entries
+    );
+    cache.index_wildcards();
+    Ok(cache)
+}
+
+/// Moves names whose only addresses are 0.0.0.0 or `::` into `blocked`, and
+/// such wildcards into `blocked_wildcards`. A block listed beside a real
+/// address for the same name is dropped, so the address keeps answering.
+fn split_blocks(cache: &mut DnsCache) {
+    let mut blocked = Vec::new();
+    for (domain, entries) in cache.exact_matches.iter_mut() {
+        if !entries.iter().any(|entry| entry.ip.is_unspecified()) {
+            continue;
+        }
+        if entries.iter().all(|entry| entry.ip.is_unspecified()) {
+            blocked.push(domain.clone());
+            continue;
+        }
+        let ignored: Vec<String> =
+            entries.iter().filter(|entry| entry.ip.is_unspecified()).map(|entry| entry.source.to_string()).collect();
+        tracing::warn!("Ignoring the block on {} ({}): the name has addresses as well", name_key(domain), ignored.join(", "));
+        entries.retain(|entry| !entry.ip.is_unspecified());
+    }
+    for domain in blocked {
+        let entries = cache.exact_matches.remove(&domain).unwrap_or_default();
+        cache.blocked.insert(domain, entries);
+    }
+    let (blocks, wildcards) = std::mem::take(&mut cache.wildcards)
+        .into_iter()
+        .partition(|(_, entry)| entry.ip.is_unspecified());
+    cache.blocked_wildcards = blocks;
+    cache.wildcards = wildcards;
+}
+
+/// Keeps the first CNAME for each alias. An alias can't have addresses as
+/// well (RFC 1034 3.6.2), so a CNAME for a name that has them is dropped.
+fn add_cnames(cache: &mut DnsCache, aliases: Vec<(String, Alias)>) {
+    for (text, alias) in aliases {
+        let name = table_key(&text);
+        let reason = if let Some(entries) = cache.exact_matches.get(&name) {
+            let sources: Vec<String> = entries.iter().map(|e| e.source.to_string()).collect();
+            format!("the name already has addresses from {}", sources.join(", "))
+        } else if let Some(txts) = cache.txts.get(&name) {
+            format!("the name already has a TXT record from {}", txts[0].source)
+        } else if let Some(mxs) = cache.mxs.get(&name) {
+            format!("the name already has an MX record from {}", mxs[0].source)
+        } else if let Some(srvs) = cache.srvs.get(&name) {
+            format!("the name already has an SRV record from {}", srvs[0].source)
+        } else if text == alias.target {
+            "it points at itself".to_string()
+        } else if let Some(first) = cache.cnames.get(&name) {
+            format!("already defined as {} ({})", first.target, first.source)
+        } else {
+            cache.cnames.insert(name, alias);
+            continue;
+        };
+        skip(&mut cache.skipped, format!("CNAME {} -> {}", text, alias.target), &alias.source, reason);
+    }
+}
+
+fn config_records(config_path: &Path, records: &[RecordConfig], addresses: &[String]) -> Parsed {
+    let path: Arc<Path> = Arc::from(config_path);
+    let mut parsed = Parsed::default();
+    for (index, record) in records.iter().enumerate() {
+        let source = Source {
+            kind: SourceKind::Config,
+            path: path.clone(),
+            location: index + 1,
+            by_line: false,
+        };
+        match record.parse() {
+            Ok(ConfigRecord::Address(wildcard, domain, ip)) => {
+                let entry = Entry { ip, source, ttl: record.ttl };
+                if wildcard {
+                    parsed.wildcards.push((format!("*.{}", domain), entry));
+                } else {
+                    parsed.exact.push((domain, entry));
+                }
+            }
+            Ok(ConfigRecord::Txt(name, strings)) => {
+                parsed.txts.push((name, TxtRecord { strings, source, ttl: record.ttl }));
+            }
+            Ok(ConfigRecord::Mx(name, preference, exchange)) => {
+                parsed.mxs.push((name, MxRecord { preference, exchange, source, ttl: record.ttl }));
+            }
+            Ok(ConfigRecord::Srv(name, priority, weight, port, target)) => {
+                let srv = SrvRecord { priority, weight, port, target, source, ttl: record.ttl };
+                parsed.srvs.push((name, srv));
+            }
+            Err(e) => parsed.skip(format!("record {:?}", record.name), &source, e),
+        }
+    }
+    // Numbered on from `[[records]]`, so every config source stays distinct
+    for (index, directive) in addresses.iter().enumerate() {
+        let source = Source {
+            kind: SourceKind::Config,
+            path: path.clone(),
+            location: records.len() + index + 1,
+            by_line: false,
+        };
+        match parse_address_directive(directive) {
+            Ok((domains, Some(ip))) => {
+                for domain in domains {
+                    let entry = Entry { ip, source: source.clone(), ttl: None };
+                    parsed.wildcards.push((format!("*.{}", domain), entry.clone()));
+                    parsed.exact.push((domain, entry));
+                }
+            }
+            // Kept local by `Config::upstreams` instead
+            Ok((_, None)) => {}
+            Err(e) => parsed.skip(format!("address {:?}", directive), &source, e),
+        }
+    }
+    parsed
+}
+
+/// Reads an `addresses` entry, dnsmasq's `address=/domain/.../ip` with or
+/// without the `address=`: the keys of its domains, which answer with the
+/// address along with all their subdomains, and the address. `None` is the
+/// `address=/domain/` form, whose names are answered locally or not at all.
+pub fn parse_address_directive(directive: &str) -> Result<(Vec<String>, Option<IpAddr>), String> {
+    let directive = directive.trim();
+    let rest = directive
+        .strip_prefix("address=")
+        .unwrap_or(directive)
+        .strip_prefix('/')
+        .ok_or_else(|| "expected `/domain/[address]`".to_string())?;
+    let Some((domains, address)) = rest.rsplit_once('/') else {
+        return Err("expected `/domain/[address]`".to_string());
+    };
+    let ip = match address {
+        "" => None,
+        address => Some(address.parse::<IpAddr>().map_err(|_| format!("{:?} is not an IP address", address))?),
+    };
+    let domains = domains
+        .split('/')
+        .map(|domain| match domain {
+            "" => Err("empty domain".to_string()),
+            "#" => Err("`#` (every domain) isn't supported".to_string()),
+            domain => parse_name(domain),
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok((domains, ip))
+}
+
+fn runtime_records(records: Vec<RuntimeRecord>) -> Parsed {
+    let mut parsed = Parsed::default();
+    for (entry, record) in runtime_entries(records) {
+        if record.name.starts_with("*.") {
+            parsed.wildcards.push((record.name, entry));
+        } else {
+            parsed.exact.push((record.name, entry));
         }
     }
+    parsed
+}
+
+/// `records` as entries, numbered in the order they were added.
+fn runtime_entries(records: Vec<RuntimeRecord>) -> impl Iterator<Item = (Entry, RuntimeRecord)> {
+    let path: Arc<Path> = Arc::from(Path::new(""));
+    records.into_iter().enumerate().map(move |(index, record)| {
+        let source = Source {
+            kind: SourceKind::Runtime,
+            path: path.clone(),
+            location: index + 1,
+            by_line: false,
+        };
+        (Entry { ip: record.ip, source, ttl: record.ttl }, record)
+    })
 }
 
-pub fn load_records(
+fn config_cnames(config_path: &Path, cnames: &[(String, String)]) -> Vec<(String, Alias)> {
+    let path: Arc<Path> = Arc::from(config_path);
+    let mut aliases = Vec::new();
+    for (index, (name, target)) in cnames.iter().enumerate() {
+        let source = Source {
+            kind: SourceKind::Config,
+            path: path.clone(),
+            location: index + 1,
+            by_line: false,
+        };
+        match parse_cname_line(&[name.as_str(), target.as_str()]) {
+            Ok((name, target)) => aliases.push((name, Alias { target, source })),
+            Err(e) => tracing::warn!("Skipping CNAME ({}): {}", source, e),
+        }
+    }
+    aliases
+}
+
+fn config_ptrs(config_path: &Path, records: &[PtrRecordConfig]) -> Vec<PtrRecord> {
+    let path: Arc<Path> = Arc::from(config_path);
+    let mut ptrs = Vec::new();
+    for (index, record) in records.iter().enumerate() {
+        let source = Source {
+            kind: SourceKind::Config,
+            path: path.clone(),
+            location: index + 1,
+            by_line: false,
+        };
+        match parse_presentation(&record.name) {
+            Ok(name) => ptrs.push(PtrRecord { ip: record.address, name: name_key(&name), source }),
+            Err(e) => tracing::warn!("Skipping invalid name {:?} ({}): {}", record.name, source, e),
+        }
+    }
+    ptrs
+}
+
+/// Runs a source parser, printing how long it took.
+fn timed<F>(kind: SourceKind, path: &Path, load: F) -> Result<Parsed, LoadError>
+where
+    F: FnOnce() -> Result<Parsed, LoadError>,
+{
+    let started = Instant::now();
+    let result = load();
+    tracing::info!("Parsed {} {:?} in {:?}", kind.as_str(), path, started.elapsed());
+    result
+}
+
+/// Errors under `LimitsPolicy::Fail`; otherwise warns that the excess will be dropped.
+fn check_limit(
+    policy: LimitsPolicy,
+    limit: &'static str,
+    name: Option<&str>,
+    found: usize,
+    max: usize,
+) -> Result<(), LimitExceeded> {
+    if found <= max {
+        return Ok(());
+    }
+    let exceeded = LimitExceeded {
+        limit,
+        name: name.map(str::to_string),
+        found,
+        max,
+    };
+    match policy {
+        LimitsPolicy::Fail => Err(exceeded),
+        LimitsPolicy::Truncate => {
+            tracing::warn!("{}; dropping the excess", exceeded);
+            Ok(())
+        }
+    }
+}
+
+fn load_dhcp(
     dhcp_path: &Path,
-    hosts_path: &Path,
+    format: LeaseFormat,
     suffix: &str,
-) -> Result<DnsCache> {
-    let mut cache = DnsCache::default();
-    let mut exact_records_temp: HashMap<String, HashSet<Ipv4Addr>> = HashMap::new();
-
-    // 1. Load DHCP records
-    if dhcp_path.exists() {
-        let content = fs::read_to_string(dhcp_path)
-            .with_context(|| format!("Failed to read DHCP file: {:?}", dhcp_path))?;
-        
-        if !content.trim().is_empty() {
-             let dhcp_data: Result<DhcpData, _> = serde_json::from_str(&content);
-             match dhcp_data {
-                 Ok(data) => {
-                     for lease in data.leases {
-                         if lease.hostname.is_empty() {
-                             continue;
-                         }
-                         let ip = Ipv4Addr::new(
-                             lease.address[0],
-                             lease.address[1],
-                             lease.address[2],
-                             lease.address[3],
-                         );
-                         
-                         let safe_suffix = if suffix.starts_with('.') || suffix.is_empty() {
-                             suffix.to_string()
-                         } else {
-                             format!(".{}", suffix)
-                         };
-                         
-                         let fqdn = format!("{}{}.", lease.hostname, safe_suffix).to_lowercase();
-                         exact_records_temp.entry(fqdn.clone()).or_default().insert(ip);
-                         
-                         // Add wildcard for DHCP entry
-                         let wildcard_pattern = format!("*.{}.", fqdn.trim_end_matches('.')); // Remove trailing dot, then add *. and a dot
-                         cache.wildcards.push((wildcard_pattern, ip));
-                     }
-                 },
-                 Err(e) => eprintln!("Warning: Failed to parse DHCP JSON: {}", e),
-             }
-        }
-    } else {
-        eprintln!("Warning: DHCP file not found at {:?}", dhcp_path);
-    }
-
-    // 2. Load Hosts records
-    if hosts_path.exists() {
-        let content = fs::read_to_string(hosts_path)
-            .with_context(|| format!("Failed to read Hosts file: {:?}", hosts_path))?;
-        
-        for line in content.lines() {
-            let line = line.trim();
-            if line.is_empty() || line.starts_with('#') {
-                continue;
+    retry: RetryPolicy,
+    unnamed: SynthesizeUnnamed,
+    ignore_expiry: bool,
+    hostnames: &HostnameRules,
+) -> Result<Parsed, LoadError> {
+    let mut parsed = Parsed::default();
+    if !dhcp_path.exists() {
+        return Err(LoadError::Missing {
+            kind: SourceKind::Dhcp,
+            path: dhcp_path.to_path_buf(),
+        });
+    }
+
+    let content = read_with_retry(dhcp_path, retry).map_err(|source| LoadError::Dhcp {
+        path: dhcp_path.to_path_buf(),
+        source,
+    })?;
+    if content.trim().is_empty() {
+        return Ok(parsed);
+    }
+
+    let path: Arc<Path> = Arc::from(dhcp_path);
+    let origin = format!("{} {}", SourceKind::Dhcp.as_str(), dhcp_path.display());
+    // JSON leases are counted by entry, the other formats by line
+    let by_line = format.detect(&content) != LeaseFormat::Json;
+    let mut leases = leases::parse(&content, format, &origin).map_err(|source| LoadError::DhcpParse {
+        path: dhcp_path.to_path_buf(),
+        source,
+    })?;
+    if !ignore_expiry {
+        let now = leases::now();
+        let before = leases.len();
+        leases.retain(|lease| lease.expires.is_none_or(|expires| expires > now));
+        if leases.len() < before {
+            tracing::debug!("Left out {} expired leases of {}", before - leases.len(), origin);
+        }
+        parsed.next_expiry = leases.iter().filter_map(|lease| lease.expires).min();
+    }
+
+    let mut named = Vec::new();
+    for lease in leases {
+        let ip = lease.ip;
+        let source = Source {
+            kind: SourceKind::Dhcp,
+            path: path.clone(),
+            location: lease.location,
+            by_line,
+        };
+
+        if lease.hostname.is_empty() {
+            let generated = match (unnamed, lease.mac) {
+                (SynthesizeUnnamed::Mac, Some(mac)) => {
+                    format!("mac-{}", mac.iter().map(|b| format!("{:02x}", b)).collect::<String>())
+                }
+                (SynthesizeUnnamed::Ip, _) => format!("ip-{}", ip.to_string().replace(['.', ':'], "-")),
+                _ => continue,
+            };
+            if let Ok(name) = dhcp_name(&generated, suffix) {
+                parsed.synthesized.push((name_key(&name), Entry { ip, source, ttl: None }));
+            }
+            continue;
+        }
+
+        let name = sanitize_hostname(&lease.hostname, hostnames).and_then(|hostname| {
+            if hostname != lease.hostname {
+                tracing::debug!("DHCP hostname {:?} of {} is served as {:?}", lease.hostname, ip, hostname);
+            }
+            dhcp_name(&hostname, suffix).map_err(|e| e.to_string())
+        });
+        match name {
+            Ok(name) => named.push((name_key(&name), ip, source, lease.expires)),
+            Err(e) => {
+                let client = match lease.mac {
+                    Some(mac) => format!("{} ({})", ip, mac.iter().map(|b| format!("{:02x}", b)).collect::<Vec<_>>().join(":")),
+                    None => ip.to_string(),
+                };
+                parsed.skip(format!("DHCP hostname {:?} of {}", lease.hostname, client), &source, e);
             }
+        }
+    }
 
-            let parts: Vec<&str> = line.split_whitespace().collect();
-            if parts.len() < 2 {
+    // A device that moved keeps its old lease until that runs out, so of the
+    // leases of one family for a name, only the one lasting longest is current
+    let lasts = |expires: Option<u64>| expires.unwrap_or(u64::MAX);
+    let mut longest: HashMap<(String, bool), (u64, IpAddr)> = HashMap::new();
+    if !ignore_expiry {
+        for (fqdn, ip, _, expires) in &named {
+            let best = longest.entry((fqdn.clone(), ip.is_ipv4())).or_insert((0, *ip));
+            if lasts(*expires) > best.0 {
+                *best = (lasts(*expires), *ip);
+            }
+        }
+    }
+    for (fqdn, ip, source, expires) in named {
+        if let Some(&(until, current)) = longest.get(&(fqdn.clone(), ip.is_ipv4())) {
+            if lasts(expires) < until {
+                parsed.skip(format!("DHCP lease of {} for {}", ip, fqdn), &source, format!("the lease of {} expires later", current));
                 continue;
             }
+        }
+        parsed.exact.push((fqdn.clone(), Entry { ip, source: source.clone(), ttl: None }));
 
-            if let Ok(ip) = parts[0].parse::<Ipv4Addr>() {
-                for hostname in &parts[1..] {
-                    if hostname.starts_with('#') {
-                        break;
-                    }
-                    let mut domain = hostname.to_lowercase();
-                    if !domain.ends_with('.') {
-                        domain.push('.');
+        // Add wildcard for DHCP entry
+        let wildcard_pattern = format!("*.{}", fqdn);
+        parsed.wildcards.push((wildcard_pattern, Entry { ip, source, ttl: None }));
+    }
+    Ok(parsed)
+}
+
+/// Whether the wildcard key `pattern` (`*.example.com.`) covers the key
+/// `name`, which must be below `example.com.` by at least one whole label.
+/// The parent itself isn't covered (RFC 4592).
+pub fn wildcard_covers(pattern: &str, name: &str) -> bool {
+    match pattern.strip_prefix("*.") {
+        Some("") => name != ".",
+        Some(parent) => is_subdomain(name, parent),
+        None => false,
+    }
+}
+
+/// The keys strictly above the key `name`, nearest first: `b.lan.`, `lan.`
+/// and then `` for the root, the parent of a `*.` pattern, for `a.b.lan.`.
+/// Escaped dots don't end a label.
+pub(crate) fn parents(name: &str) -> impl Iterator<Item = &str> {
+    let bytes = name.as_bytes();
+    let mut i = if name == "." { bytes.len() } else { 0 };
+    std::iter::from_fn(move || {
+        while i < bytes.len() {
+            match bytes[i] {
+                b'\\' => i += 2,
+                b'.' => {
+                    i += 1;
+                    return Some(&name[i..]);
+                }
+                _ => i += 1,
+            }
+        }
+        None
+    })
+}
+
+/// Whether the key `name` is strictly below the key `parent`, ending on a
+/// label boundary: `a.b.lan.` is below `lan.`, `notlan.` isn't, and neither
+/// is `a\.lan.`, whose escaped dot is part of its single label.
+pub fn is_subdomain(name: &str, parent: &str) -> bool {
+    if parent == "." {
+        return name != ".";
+    }
+    let Some(labels) = name.strip_suffix(parent).and_then(|rest| rest.strip_suffix('.')) else {
+        return false;
+    };
+    // An odd run of backslashes before the dot escapes it
+    let backslashes = labels.bytes().rev().take_while(|&b| b == b'\\').count();
+    !labels.is_empty() && backslashes % 2 == 0
+}
+
+/// The key form of `name`: its RFC 1035 presentation form, ASCII-lowercased,
+/// with a trailing dot. Bytes outside letters, digits, `-`, `_` and `*` are
+/// written as `\DDD` (and `.` and `\` as `\.` and `\\`), so the key parses back
+/// with `parse_presentation`. Names with odd bytes compare equal no matter
+/// where they came from. This is how names are written in dumps, snapshots and
+/// the APIs; the `DnsCache` tables are keyed by `LowerName` instead.
+pub fn name_key<N: Borrow<Name> + ?Sized>(name: &N) -> String {
+    let name = name.borrow();
+    let mut key = String::with_capacity(name.len() + 1);
+    for label in name.iter() {
+        for &byte in label {
+            match byte.to_ascii_lowercase() {
+                b @ (b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'*') => key.push(char::from(b)),
+                b @ (b'.' | b'\\') => {
+                    key.push('\\');
+                    key.push(char::from(b));
+                }
+                b => key.push_str(&format!("\\{:03}", b)),
+            }
+        }
+        key.push('.');
+    }
+    if key.is_empty() {
+        key.push('.');
+    }
+    key
+}
+
+/// The `DnsCache` table key for the key form `key`. `LowerName` hashes and
+/// compares the lowercased labels, so a query is looked up by its own name
+/// without building a string first.
+pub fn parse_key(key: &str) -> Result<LowerName, ProtoError> {
+    parse_presentation(key).map(LowerName::from)
+}
+
+/// `parse_key` for keys `name_key` made, which always parse back.
+pub(crate) fn table_key(key: &str) -> LowerName {
+    parse_key(key).expect("name keys parse back")
+}
+
+/// Parses an RFC 1035 presentation-format name: `\X` is a literal `X` and
+/// `\DDD` a decimal byte. A label written with non-ASCII characters, like
+/// `büro`, becomes its IDNA form (`xn--bro-hoa`), which is what resolvers ask
+/// for; bytes given as escapes are kept as they are.
+pub fn parse_presentation(name: &str) -> Result<Name, ProtoError> {
+    let mut labels: Vec<Vec<u8>> = vec![Vec::new()];
+    let mut unicode = vec![false]; // Whether each label has literal non-ASCII characters
+    let mut bytes = name.bytes();
+    while let Some(byte) = bytes.next() {
+        match byte {
+            b'.' => {
+                labels.push(Vec::new());
+                unicode.push(false);
+            }
+            b'\\' => {
+                let escaped = bytes.next().ok_or("name ends with a lone backslash")?;
+                let byte = if escaped.is_ascii_digit() {
+                    let digits = [escaped, bytes.next().unwrap_or(0), bytes.next().unwrap_or(0)];
+                    if !digits.iter().all(u8::is_ascii_digit) {
+                        return Err("\\DDD escapes need three digits".into());
                     }
+                    let value = digits.iter().fold(0u32, |n, d| n * 10 + u32::from(d - b'0'));
+                    u8::try_from(value).map_err(|_| format!("\\{} is not a byte", value))?
+                } else {
+                    escaped
+                };
+                labels.last_mut().expect("at least one label").push(byte);
+            }
+            byte => {
+                *unicode.last_mut().expect("at least one label") |= !byte.is_ascii();
+                labels.last_mut().expect("at least one label").push(byte);
+            }
+        }
+    }
+    // A trailing dot only marks the name as fully qualified
+    if labels.len() > 1 && labels.last().is_some_and(Vec::is_empty) {
+        labels.pop();
+    }
+    if labels.iter().any(Vec::is_empty) {
+        return Err("empty label".into());
+    }
+    for (label, _) in labels.iter_mut().zip(unicode).filter(|(_, unicode)| *unicode) {
+        // Escaped bytes next to the characters have to make UTF-8 too
+        let text = std::str::from_utf8(label).map_err(|_| "label mixes characters with bytes that aren't UTF-8")?;
+        *label = idna_label(text)?;
+    }
+    let mut name = Name::from_labels(labels)?;
+    name.set_fqdn(true);
+    Ok(name)
+}
+
+/// A DHCP hostname as a valid DNS name, or why it can't be one. Surrounding
+/// whitespace and a trailing dot are dropped. Each label keeps letters, digits
+/// and inner hyphens, and a label with non-ASCII characters gets its IDNA form.
+/// Under `HostnamePolicy::Sanitize` every run of other characters becomes the
+/// replacement, and hyphens left at either end of a label and labels left empty
+/// are dropped; under `Skip` any of them makes the hostname invalid. Labels
+/// over 63 bytes are invalid either way.
+pub fn sanitize_hostname(hostname: &str, rules: &HostnameRules) -> Result<String, String> {
+    let sanitize = rules.policy == HostnamePolicy::Sanitize;
+    let trimmed = hostname.trim();
+    let trimmed = trimmed.strip_suffix('.').unwrap_or(trimmed);
+    // Runs of characters `keep` rejects become one replacement
+    let replace = |label: &str, keep: &dyn Fn(char) -> bool| {
+        let mut out = String::with_capacity(label.len());
+        let mut replaced = false;
+        for c in label.chars() {
+            if keep(c) {
+                out.push(c);
+                replaced = false;
+            } else if !replaced {
+                out.push_str(&rules.replacement);
+                replaced = true;
+            }
+        }
+        out
+    };
+    let mut labels = Vec::new();
+    for label in trimmed.split('.') {
+        let legal = |c: char| c.is_ascii_alphanumeric() || c == '-' || !c.is_ascii();
+        let mut label = match label.chars().find(|&c| !legal(c)) {
+            Some(c) if !sanitize => return Err(format!("label {:?} contains {:?}", label, c)),
+            Some(_) => replace(label, &legal),
+            None => label.to_string(),
+        };
+        if !label.is_ascii() {
+            label = match idna_label(&label) {
+                Ok(ascii) => String::from_utf8(ascii).expect("IDNA labels are ASCII"),
+                Err(e) if !sanitize => return Err(e.to_string()),
+                Err(_) => replace(&label, &|c| c.is_ascii_alphanumeric() || c == '-'),
+            };
+        }
+        if sanitize {
+            label = label.trim_matches('-').to_string();
+            if label.is_empty() {
+                continue;
+            }
+        } else if label.is_empty() {
+            return Err("empty label".to_string());
+        } else if label.starts_with('-') || label.ends_with('-') {
+            return Err(format!("label {:?} starts or ends with a hyphen", label));
+        }
+        if label.len() > 63 {
+            return Err(format!("label {:?} is {} bytes long, the limit is 63", label, label.len()));
+        }
+        labels.push(label);
+    }
+    if labels.is_empty() {
+        return Err("nothing is left of it once sanitized".to_string());
+    }
+    Ok(labels.join("."))
+}
+
+/// The IDNA (punycode) form of a label with non-ASCII characters.
+fn idna_label(label: &str) -> Result<Vec<u8>, ProtoError> {
+    Label::from_utf8(label)
+        .map(|label| label.as_bytes().to_vec())
+        .map_err(|_| format!("label {:?} is not a valid internationalized name", label).into())
+}
+
+/// `hostname`, split on dots into raw labels, under `suffix`. Hostnames that
+/// are already under `suffix`, as Kea often writes them, are kept as they are,
+/// so `laptop.lan.` stays `laptop.lan.` rather than becoming `laptop.lan.lan.`.
+/// Labels with non-ASCII characters get their IDNA form when they have one,
+/// and otherwise stay raw bytes like the rest.
+fn dhcp_name(hostname: &str, suffix: &str) -> Result<Name, ProtoError> {
+    let hostname = hostname.strip_suffix('.').unwrap_or(hostname);
+    let labels = hostname.split('.').map(|label| {
+        if label.is_ascii() {
+            label.as_bytes().to_vec()
+        } else {
+            idna_label(label).unwrap_or_else(|_| label.as_bytes().to_vec())
+        }
+    });
+    let mut host = Name::from_labels(labels)?;
+    let suffix = match suffix.trim_matches('.') {
+        "" => Name::root(),
+        suffix => Name::from_ascii(suffix)?,
+    };
+    if !suffix.is_root() && host.num_labels() > suffix.num_labels() && suffix.zone_of(&host) {
+        host.set_fqdn(true);
+        return Ok(host);
+    }
+    host.append_domain(&suffix)
+}
+
+/// Include directives followed from a hosts file before the rest are skipped.
+const MAX_INCLUDE_DEPTH: usize = 8;
+
+/// `hosts_path`, the `hosts_paths`, then the `*.hosts` files in `hosts_dir`
+/// sorted by name, along with the error if that directory can't be listed.
+fn hosts_files(options: &LoadOptions) -> (Vec<PathBuf>, Option<LoadError>) {
+    let mut paths = vec![options.hosts_path.clone()];
+    paths.extend(options.hosts_paths.iter().cloned());
+    let Some(dir) = &options.hosts_dir else {
+        return (paths, None);
+    };
+    if !dir.exists() {
+        return (paths, Some(LoadError::Missing { kind: SourceKind::Hosts, path: dir.clone() }));
+    }
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(source) => return (paths, Some(LoadError::Hosts { path: dir.clone(), source })),
+    };
+    let mut found: Vec<PathBuf> = entries
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| path.extension().is_some_and(|e| e == "hosts") && path.is_file())
+        .collect();
+    found.sort();
+    paths.extend(found);
+    (paths, None)
+}
+
+/// Reads a zone file, whose relative names are under the domain suffix until
+/// its `$ORIGIN` says otherwise.
+fn load_zone(zone_path: &Path, suffix: &str, retry: RetryPolicy) -> Result<Parsed, LoadError> {
+    if !zone_path.exists() {
+        return Err(LoadError::Missing {
+            kind: SourceKind::Zone,
+            path: zone_path.to_path_buf(),
+        });
+    }
+    let content = read_with_retry(zone_path, retry).map_err(|source| LoadError::Zone {
+        path: zone_path.to_path_buf(),
+        source,
+    })?;
+    let origin = match suffix.trim_matches('.') {
+        "" => ".".to_string(),
+        suffix => format!("{}.", suffix.to_ascii_lowercase()),
+    };
 
-                    if domain.starts_with("*.") {
-                        cache.wildcards.push((domain, ip));
-                    } else {
-                        exact_records_temp.entry(domain).or_default().insert(ip);
+    let path: Arc<Path> = Arc::from(zone_path);
+    let described = format!("{} {}", SourceKind::Zone.as_str(), zone_path.display());
+    let mut parsed = Parsed::default();
+    for record in zone::parse(&content, &origin, &described) {
+        let source = Source {
+            kind: SourceKind::Zone,
+            path: path.clone(),
+            location: record.line,
+            by_line: true,
+        };
+        match record.data {
+            ZoneData::Address(ip) => {
+                let entry = Entry { ip, source, ttl: record.ttl };
+                if record.name.starts_with("*.") {
+                    parsed.wildcards.push((record.name, entry));
+                } else {
+                    parsed.exact.push((record.name, entry));
+                }
+            }
+            ZoneData::Cname(target) => parsed.cnames.push((record.name, Alias { target, source })),
+            ZoneData::Ptr(name) => match parse_in_addr_arpa(&record.name) {
+                Some(ip) => parsed.ptrs.push(PtrRecord { ip, name, source }),
+                None => parsed.skip(format!("PTR record {}", record.name), &source, "not an in-addr.arpa name"),
+            },
+            ZoneData::Txt(strings) => parsed.txts.push((record.name, TxtRecord { strings, source, ttl: record.ttl })),
+        }
+    }
+    Ok(parsed)
+}
+
+fn load_hosts(hosts_path: &Path, retry: RetryPolicy, strict: bool) -> Result<Parsed, LoadError> {
+    if !hosts_path.exists() {
+        return Err(LoadError::Missing {
+            kind: SourceKind::Hosts,
+            path: hosts_path.to_path_buf(),
+        });
+    }
+    let mut parsed = Parsed::default();
+    read_hosts(hosts_path, retry, strict, &mut Vec::new(), &mut parsed)?;
+    Ok(parsed)
+}
+
+/// Parses one hosts file into `parsed`, along with the files it includes.
+/// `including` holds the files whose includes led here, outermost first.
+fn read_hosts(
+    hosts_path: &Path,
+    retry: RetryPolicy,
+    strict: bool,
+    including: &mut Vec<PathBuf>,
+    parsed: &mut Parsed,
+) -> Result<(), LoadError> {
+    let content = read_with_retry(hosts_path, retry).map_err(|source| LoadError::Hosts {
+        path: hosts_path.to_path_buf(),
+        source,
+    })?;
+    including.push(fs::canonicalize(hosts_path).unwrap_or_else(|_| hosts_path.to_path_buf()));
+
+    let path: Arc<Path> = Arc::from(hosts_path);
+    // `lines` leaves no `\r` behind for CRLF files, and `trim` catches a stray one
+    for (index, line) in content.lines().enumerate() {
+        let line = line.trim();
+        let source = Source {
+            kind: SourceKind::Hosts,
+            path: path.clone(),
+            location: index + 1,
+            by_line: true,
+        };
+        if let Some(target) = include_directive(line) {
+            include_hosts(hosts_path, target, &source, retry, strict, including, parsed)?;
+            continue;
+        }
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let parts: Vec<&str> = line.split_whitespace().collect();
+        if parts.len() < 2 || parts[1].starts_with('#') {
+            parsed.skip(format!("line {:?}", line), &source, "expected an address followed by names");
+            continue;
+        }
+
+        // `ptr <address> <name>` defines a reverse record with no forward record
+        if parts[0].eq_ignore_ascii_case("ptr") {
+            match parse_ptr_line(&parts[1..]) {
+                Ok((ip, name)) => parsed.ptrs.push(PtrRecord { ip, name, source: source.clone() }),
+                Err(e) if strict => {
+                    return Err(LoadError::InvalidEntry {
+                        path: hosts_path.to_path_buf(),
+                        line: source.location,
+                        kind: "ptr",
+                        reason: e,
+                    });
+                }
+                Err(e) => parsed.skip("ptr entry".to_string(), &source, e),
+            }
+            continue;
+        }
+
+        // `cname <alias> <target>` makes an alias, answered with a CNAME
+        if parts[0].eq_ignore_ascii_case("cname") {
+            match parse_cname_line(&parts[1..]) {
+                Ok((name, target)) => parsed.cnames.push((name, Alias { target, source: source.clone() })),
+                Err(e) if strict => {
+                    return Err(LoadError::InvalidEntry {
+                        path: hosts_path.to_path_buf(),
+                        line: source.location,
+                        kind: "cname",
+                        reason: e,
+                    });
+                }
+                Err(e) => parsed.skip("cname entry".to_string(), &source, e),
+            }
+            continue;
+        }
+
+        // `txt <name> "<text>"` adds a TXT record; the text may hold spaces and `#`
+        if parts[0].eq_ignore_ascii_case("txt") {
+            match parse_txt_line(&line[parts[0].len()..]) {
+                Ok((name, strings)) => parsed.txts.push((name, TxtRecord { strings, source: source.clone(), ttl: None })),
+                Err(e) if strict => {
+                    return Err(LoadError::InvalidEntry {
+                        path: hosts_path.to_path_buf(),
+                        line: source.location,
+                        kind: "txt",
+                        reason: e,
+                    });
+                }
+                Err(e) => parsed.skip("txt entry".to_string(), &source, e),
+            }
+            continue;
+        }
+
+        // `mx <name> <preference> <exchange>` adds an MX record
+        if parts[0].eq_ignore_ascii_case("mx") {
+            match parse_mx_line(&parts[1..]) {
+                Ok((name, preference, exchange)) => {
+                    parsed.mxs.push((name, MxRecord { preference, exchange, source: source.clone(), ttl: None }));
+                }
+                Err(e) if strict => {
+                    return Err(LoadError::InvalidEntry {
+                        path: hosts_path.to_path_buf(),
+                        line: source.location,
+                        kind: "mx",
+                        reason: e,
+                    });
+                }
+                Err(e) => parsed.skip("mx entry".to_string(), &source, e),
+            }
+            continue;
+        }
+
+        // IPv6 lines give AAAA records; scoped addresses like `fe80::1%eth0` can't be served
+        let ip = match parts[0].parse::<IpAddr>() {
+            Ok(ip) => ip,
+            Err(_) => {
+                let reason = match parts[0].split_once('%') {
+                    Some((address, _)) if address.parse::<std::net::Ipv6Addr>().is_ok() => {
+                        format!("{:?} is a scoped address, which can't be served", parts[0])
                     }
+                    _ => format!("{:?} is not an IP address", parts[0]),
+                };
+                parsed.skip(format!("line {:?}", line), &source, reason);
+                continue;
+            }
+        };
+        let (names, annotation) = split_hosts_annotation(line);
+        if names.split_whitespace().nth(1).is_none() {
+            parsed.skip(format!("line {:?}", line), &source, "expected an address followed by names");
+            continue;
+        }
+        let ttl = match annotation.map(|(text, in_comment)| (text, parse_hosts_annotation(text, in_comment))) {
+            Some((text, Err(reason))) => {
+                parsed.skip(format!("annotation {:?}", text), &source, reason);
+                None
+            }
+            Some((_, Ok(ttl))) => ttl,
+            None => None,
+        };
+        for hostname in names.split_whitespace().skip(1) {
+            let source = source.clone();
+
+            // Hosts entries are in presentation format, so escapes like `\.` are decoded
+            let (wildcard, name) = match hostname.strip_prefix("*.") {
+                Some(rest) => (true, rest),
+                None => (false, hostname),
+            };
+            let checked = check_hostname(name).and_then(|()| parse_presentation(name).map_err(|e| e.to_string()));
+            let domain = match checked {
+                Ok(name) => name_key(&name),
+                // Skipped with a warning, or failing the whole file when strict
+                Err(reason) if strict => {
+                    return Err(LoadError::InvalidHostname {
+                        path: hosts_path.to_path_buf(),
+                        line: source.location,
+                        name: hostname.to_string(),
+                        reason,
+                    });
+                }
+                Err(reason) => {
+                    parsed.skip(format!("invalid name {:?}", hostname), &source, reason);
+                    continue;
                 }
+            };
+
+            if wildcard {
+                parsed.wildcards.push((format!("*.{}", domain), Entry { ip, source, ttl }));
+            } else {
+                parsed.exact.push((domain, Entry { ip, source, ttl }));
             }
         }
-    } else {
-        eprintln!("Warning: Hosts file not found at {:?}", hosts_path);
     }
+    including.pop();
+    Ok(())
+}
+
+/// The path of a `#include <path>` or `$INCLUDE <path>` line, which may be quoted.
+fn include_directive(line: &str) -> Option<&str> {
+    let (directive, target) = line.split_once(char::is_whitespace)?;
+    let target = target.trim().trim_matches('"');
+    (directive == "#include" || directive.eq_ignore_ascii_case("$include")).then_some(target).filter(|target| !target.is_empty())
+}
 
-    // Convert HashSet to Sorted Vec for exact matches
-    for (domain, ips) in exact_records_temp {
-        let mut ip_vec: Vec<Ipv4Addr> = ips.into_iter().collect();
-        ip_vec.sort();
-        cache.exact_matches.insert(domain, ip_vec);
+/// Reads the file an include directive at `source` in `from` names, relative
+/// to `from`'s directory unless absolute. A missing file, one nested more than
+/// `MAX_INCLUDE_DEPTH` includes deep, or one already being read further up
+/// (a cycle) is skipped with a warning, as a bad line would be.
+fn include_hosts(
+    from: &Path,
+    target: &str,
+    source: &Source,
+    retry: RetryPolicy,
+    strict: bool,
+    including: &mut Vec<PathBuf>,
+    parsed: &mut Parsed,
+) -> Result<(), LoadError> {
+    let what = format!("include {:?}", target);
+    let path = from.parent().unwrap_or(Path::new("")).join(target);
+    if including.len() > MAX_INCLUDE_DEPTH {
+        parsed.skip(what, source, format!("includes nest more than {} deep", MAX_INCLUDE_DEPTH));
+        return Ok(());
+    }
+    // Watched even while missing, so creating it reloads
+    if !parsed.included.contains(&path) {
+        parsed.included.push(path.clone());
     }
+    if !path.exists() {
+        parsed.skip(what, source, format!("{:?} doesn't exist", path));
+        return Ok(());
+    }
+    if including.contains(&fs::canonicalize(&path).unwrap_or_else(|_| path.clone())) {
+        parsed.skip(what, source, "the file is already being read, so this would include it in itself");
+        return Ok(());
+    }
+    read_hosts(&path, retry, strict, including, parsed)
+}
 
-    Ok(cache)
+/// Splits a hosts address line into the address and names, and the
+/// annotation after them with whether it is in a comment: everything after a
+/// `;`, or a comment whose first word is a `key=value` pair, as in
+/// `192.168.1.9 flaky.lan # ttl=5`. Any other comment is only a comment.
+fn split_hosts_annotation(line: &str) -> (&str, Option<(&str, bool)>) {
+    let (body, comment) = match line.split_once('#') {
+        Some((body, comment)) => (body, Some(comment)),
+        None => (line, None),
+    };
+    if let Some((names, annotation)) = body.split_once(';') {
+        return (names, Some((annotation.trim(), false)));
+    }
+    let annotation = comment
+        .map(str::trim)
+        .filter(|comment| comment.split_whitespace().next().is_some_and(|word| word.contains('=')));
+    (body, annotation.map(|annotation| (annotation, true)))
+}
+
+/// The TTL a hosts annotation sets. In a comment, the pairs end at the first
+/// word that isn't one, so a note can follow them (`# ttl=5 fails over`);
+/// after a `;`, every word has to be a pair.
+fn parse_hosts_annotation(annotation: &str, in_comment: bool) -> Result<Option<u32>, String> {
+    let mut ttl = None;
+    for word in annotation.split_whitespace() {
+        let Some((key, value)) = word.split_once('=') else {
+            if in_comment {
+                break;
+            }
+            return Err(format!("expected `key=value`, not {:?}", word));
+        };
+        match key {
+            // RFC 2181 8: TTLs are at most 2^31 - 1
+            "ttl" => match value.parse::<u32>() {
+                Ok(seconds) if seconds <= i32::MAX as u32 => ttl = Some(seconds),
+                _ => return Err(format!("{:?} is not a TTL in seconds", value)),
+            },
+            _ => return Err(format!("unknown key {:?}; only ttl is supported", key)),
+        }
+    }
+    Ok(ttl)
+}
+
+/// Checks a hosts file name against RFC 1123: labels of 1 to 63 letters,
+/// digits and hyphens, not starting or ending with a hyphen, 253 characters
+/// in all. Underscores are allowed too, for SRV and DNS-SD service labels,
+/// and labels with non-ASCII characters that have an IDNA form.
+/// Names written with backslash escapes are deliberate and only checked for
+/// label and name length; `parse_presentation` decodes them.
+pub fn check_hostname(name: &str) -> Result<(), String> {
+    if name.contains('\\') {
+        return Ok(());
+    }
+    let name = name.strip_suffix('.').unwrap_or(name);
+    if name.len() > 253 {
+        return Err(format!("name is {} characters long, the limit is 253", name.len()));
+    }
+    for label in name.split('.') {
+        if label.is_empty() {
+            return Err("empty label".to_string());
+        }
+        if label.len() > 63 {
+            return Err(format!("label {:?} is {} characters long, the limit is 63", label, label.len()));
+        }
+        if !label.is_ascii() {
+            // Checked as the IDNA label it is served under
+            idna_label(label).map_err(|e| e.to_string())?;
+            continue;
+        }
+        if let Some(c) = label.chars().find(|c| !(c.is_ascii_alphanumeric() || *c == '-' || *c == '_')) {
+            return Err(format!("label {:?} contains {:?}", label, c));
+        }
+        if label.starts_with('-') || label.ends_with('-') {
+            return Err(format!("label {:?} starts or ends with a hyphen", label));
+        }
+    }
+    Ok(())
+}
+
+/// Drops the `loser` entries when both DHCP leases and reservations list the name.
+fn drop_superseded(entries: &mut Vec<Entry>, loser: SourceKind) {
+    let has = |kind| entries.iter().any(|e: &Entry| e.source.kind == kind);
+    if has(SourceKind::Dhcp) && has(SourceKind::Reservation) {
+        entries.retain(|e| e.source.kind != loser);
+    }
+}
+
+fn load_reservations(res_path: &Path, suffix: &str, retry: RetryPolicy) -> Result<Parsed, LoadError> {
+    let mut parsed = Parsed::default();
+    if !res_path.exists() {
+        return Err(LoadError::Missing {
+            kind: SourceKind::Reservation,
+            path: res_path.to_path_buf(),
+        });
+    }
+    let content = read_with_retry(res_path, retry).map_err(|source| LoadError::Reservations {
+        path: res_path.to_path_buf(),
+        source,
+    })?;
+    let by_line = !reservations::is_kea(&content);
+    let reservations = reservations::parse(&content).map_err(|source| LoadError::ReservationsParse {
+        path: res_path.to_path_buf(),
+        source,
+    })?;
+
+    let path: Arc<Path> = Arc::from(res_path);
+    for reservation in reservations {
+        let source = Source {
+            kind: SourceKind::Reservation,
+            path: path.clone(),
+            location: reservation.location,
+            by_line,
+        };
+        // Registered exactly like a lease for the same hostname
+        let fqdn = match dhcp_name(&reservation.hostname, suffix) {
+            Ok(name) => name_key(&name),
+            Err(e) => {
+                parsed.skip(format!("reserved hostname {:?}", reservation.hostname), &source, e);
+                continue;
+            }
+        };
+        let ip = IpAddr::V4(reservation.ip);
+        parsed.wildcards.push((format!("*.{}", fqdn), Entry { ip, source: source.clone(), ttl: None }));
+        parsed.exact.push((fqdn, Entry { ip, source, ttl: None }));
+    }
+    Ok(parsed)
+}
+
+fn load_database(db_path: &Path, query: &str) -> Result<Parsed, LoadError> {
+    let mut parsed = Parsed::default();
+    if !db_path.exists() {
+        return Err(LoadError::Missing {
+            kind: SourceKind::Database,
+            path: db_path.to_path_buf(),
+        });
+    }
+    let rows = database::read_rows(db_path, query).map_err(|message| LoadError::Database {
+        path: db_path.to_path_buf(),
+        message,
+    })?;
+
+    let path: Arc<Path> = Arc::from(db_path);
+    for row in rows {
+        let source = Source {
+            kind: SourceKind::Database,
+            path: path.clone(),
+            location: row.id,
+            by_line: false,
+        };
+        let result = match row.kind.as_str() {
+            "A" | "AAAA" => parse_address_record(&row.kind, &row.name, &row.value).map(|(wildcard, domain, ip)| {
+                let entry = Entry { ip, source: source.clone(), ttl: row.ttl };
+                if wildcard {
+                    parsed.wildcards.push((format!("*.{}", domain), entry));
+                } else {
+                    parsed.exact.push((domain, entry));
+                }
+            }),
+            "PTR" => parse_ptr_line(&[row.name.as_str(), row.value.as_str()]).map(|(ip, name)| {
+                parsed.ptrs.push(PtrRecord { ip, name, source: source.clone() });
+            }),
+            other => Err(format!("unsupported record type {:?}", other)),
+        };
+        if let Err(e) = result {
+            parsed.skip("database row".to_string(), &source, e);
+        }
+    }
+    Ok(parsed)
+}
+
+/// An `A` or `AAAA` record, from a database row or `[[records]]`, as
+/// `(is_wildcard, key, address)`. `kind` is uppercase.
+pub(crate) fn parse_address_record(kind: &str, name: &str, value: &str) -> Result<(bool, String, IpAddr), String> {
+    let ip = match kind {
+        "A" => value.parse::<Ipv4Addr>().map(IpAddr::V4).map_err(|_| "IPv4"),
+        _ => value.parse::<std::net::Ipv6Addr>().map(IpAddr::V6).map_err(|_| "IPv6"),
+    }
+    .map_err(|family| format!("{:?} is not an {} address", value, family))?;
+    let (wildcard, name) = match name.strip_prefix("*.") {
+        Some(rest) => (true, rest),
+        None => (false, name),
+    };
+    check_hostname(name)?;
+    let name = parse_presentation(name).map_err(|e| e.to_string())?;
+    Ok((wildcard, name_key(&name), ip))
+}
+
+/// `<alias> <target>` as name keys.
+pub(crate) fn parse_cname_line(fields: &[&str]) -> Result<(String, String), String> {
+    let fields: Vec<&str> = fields.iter().copied().take_while(|f| !f.starts_with('#')).collect();
+    let [alias, target] = fields[..] else {
+        return Err("expected `cname <alias> <target>`".to_string());
+    };
+    let parse = |name: &str| {
+        check_hostname(name).map_err(|e| format!("invalid name {:?}: {}", name, e))?;
+        let parsed = parse_presentation(name).map_err(|e| format!("invalid name {:?}: {}", name, e))?;
+        Ok::<_, String>(name_key(&parsed))
+    };
+    Ok((parse(alias)?, parse(target)?))
+}
+
+/// `<name> "<text>" ...`, the rest of a hosts `txt` line, as the name's key
+/// and its character-strings. Each quoted string (or bare word) is one or
+/// more of them, split every 255 bytes; `\"` and `\\` escape inside quotes.
+fn parse_txt_line(rest: &str) -> Result<(String, Vec<Vec<u8>>), String> {
+    let usage = || "expected `txt <name> \"<text>\"`".to_string();
+    let (name, mut text) = rest.trim_start().split_once(char::is_whitespace).ok_or_else(usage)?;
+    let name = parse_name(name)?;
+    let mut strings = Vec::new();
+    loop {
+        text = text.trim_start();
+        let value = if let Some(quoted) = text.strip_prefix('"') {
+            let mut value = String::new();
+            let mut chars = quoted.char_indices();
+            let end = loop {
+                match chars.next() {
+                    Some((i, '"')) => break i + 1,
+                    Some((_, '\\')) => value.extend(chars.next().map(|(_, c)| c)),
+                    Some((_, c)) => value.push(c),
+                    None => return Err("unterminated quoted text".to_string()),
+                }
+            };
+            text = &quoted[end..];
+            value
+        } else if text.is_empty() || text.starts_with('#') {
+            break;
+        } else {
+            let (word, after) = text.split_at(text.find(char::is_whitespace).unwrap_or(text.len()));
+            text = after;
+            word.to_string()
+        };
+        strings.extend(character_strings(value.as_bytes()));
+    }
+    if strings.is_empty() {
+        return Err(usage());
+    }
+    Ok((name, strings))
+}
+
+/// `<name> <preference> <exchange>` as keys and the preference.
+fn parse_mx_line(fields: &[&str]) -> Result<(String, u16, String), String> {
+    let fields: Vec<&str> = fields.iter().copied().take_while(|f| !f.starts_with('#')).collect();
+    let [name, preference, exchange] = fields[..] else {
+        return Err("expected `mx <name> <preference> <exchange>`".to_string());
+    };
+    let preference = preference
+        .parse::<u16>()
+        .map_err(|_| format!("{:?} is not a preference from 0 to 65535", preference))?;
+    Ok((parse_name(name)?, preference, parse_name(exchange)?))
+}
+
+/// `text` as TXT character-strings of at most 255 bytes (RFC 1035 3.3.14).
+fn character_strings(text: &[u8]) -> Vec<Vec<u8>> {
+    if text.is_empty() {
+        return vec![Vec::new()];
+    }
+    text.chunks(255).map(<[u8]>::to_vec).collect()
+}
+
+/// A hosts-style name as a key, checked as `check_hostname` does.
+fn parse_name(name: &str) -> Result<String, String> {
+    check_hostname(name).map_err(|e| format!("invalid name {:?}: {}", name, e))?;
+    let parsed = parse_presentation(name).map_err(|e| format!("invalid name {:?}: {}", name, e))?;
+    Ok(name_key(&parsed))
+}
+
+fn parse_ptr_line(fields: &[&str]) -> Result<(Ipv4Addr, String), String> {
+    let fields: Vec<&str> = fields.iter().copied().take_while(|f| !f.starts_with('#')).collect();
+    let [address, name] = fields[..] else {
+        return Err("expected `ptr <address> <name>`".to_string());
+    };
+    let ip = address
+        .parse::<Ipv4Addr>()
+        .map_err(|_| format!("{:?} is not an IPv4 address", address))?;
+    check_hostname(name).map_err(|e| format!("invalid name {:?}: {}", name, e))?;
+    let name = parse_presentation(name).map_err(|e| format!("invalid name {:?}: {}", name, e))?;
+    Ok((ip, name_key(&name)))
+}
+
+/// Files disagree when they list different addresses of the same family, so
+/// one giving the IPv4 and another the IPv6 address of a host is no conflict.
+fn find_conflict(name: &str, entries: &[Entry]) -> Option<Conflict> {
+    let mut by_file: BTreeMap<&Path, (SourceKind, BTreeSet<IpAddr>)> = BTreeMap::new();
+    for entry in entries {
+        by_file
+            .entry(&entry.source.path)
+            .or_insert_with(|| (entry.source.kind, BTreeSet::new()))
+            .1
+            .insert(entry.ip);
+    }
+
+    let disagree = |ipv6: bool| {
+        let mut sets = by_file
+            .values()
+            .map(|(_, ips)| ips.iter().filter(|ip| ip.is_ipv6() == ipv6).collect::<Vec<_>>())
+            .filter(|ips| !ips.is_empty());
+        let first = sets.next();
+        first.is_some_and(|first| sets.any(|ips| ips != first))
+    };
+    if !disagree(false) && !disagree(true) {
+        return None;
+    }
+
+    let sources = by_file
+        .iter()
+        .map(|(path, (kind, ips))| (*kind, Arc::from(*path), ips.iter().copied().collect()))
+        .collect();
+    Some(Conflict { name: name.to_string(), sources })
 }
@@ -1,238 +1,152 @@
-mod config;
-mod loader;
+mod bench;
 
-use clap::Parser;
-use std::io::Write;
-use std::net::Ipv4Addr;
+use clap::{Parser, Subcommand};
+use anyhow::Context;
+use localdns::config;
 use std::path::PathBuf;
-use std::net::SocketAddr;
-use std::sync::Arc;
-use tokio::net::UdpSocket;
-use tokio::sync::RwLock;
-use tokio::time::{sleep, Duration};
-use hickory_proto::op::{Message, MessageType, ResponseCode};
-use hickory_proto::rr::{RData, Record, RecordType};
-use hickory_proto::rr::rdata::A;
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
 struct Args {
     /// Path to the configuration file
-    #[arg(short, long, default_value = "config.toml")]
+    #[arg(short, long, default_value = "config.toml", global = true)]
     config: PathBuf,
-}
-
-#[tokio::main]
-async fn main() -> anyhow::Result<()> {
-    env_logger::init();
-    
-    std::panic::set_hook(Box::new(|info| {
-        eprintln!("Panic: {:?}", info);
-    }));
-
-    let args = Args::parse();
 
-    // 1. Load Config
-    println!("Loading config from {:?}", args.config);
-    let config = config::Config::load(&args.config)?;
-    
-    // 2. Load DNS Records (Initial)
-    println!("Loading DNS records...");
-    let initial_records = loader::load_records(
-        &config.dhcp_lease_file, 
-        &config.hosts_file, 
-        &config.domain_suffix
-    )?;
-    
-    println!("Loaded {} exact domains and {} wildcard patterns.", initial_records.exact_matches.len(), initial_records.wildcards.len());
-
-    let records = Arc::new(RwLock::new(initial_records));
-
-    // Start file watcher task
-    let records_clone = records.clone();
-    let dhcp_path = config.dhcp_lease_file.clone();
-    let hosts_path = config.hosts_file.clone();
-    let suffix = config.domain_suffix.clone();
+    #[command(subcommand)]
+    command: Option<Command>,
+}
 
-    tokio::spawn(async move {
-        let mut last_dhcp_mtime = std::fs::metadata(&dhcp_path).and_then(|m| m.modified()).ok();
-        let mut last_hosts_mtime = std::fs::metadata(&hosts_path).and_then(|m| m.modified()).ok();
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Generate query load against a resolver and report latency and throughput
+    Bench(bench::BenchArgs),
+    /// Parse the config and load every source once, reporting each problem; exits non-zero on errors
+    Check(CheckArgs),
+    /// Send a command to a running server's control socket, e.g. `ctl add-record nas.lan A 10.0.0.9`
+    #[cfg(unix)]
+    Ctl(CtlArgs),
+    /// Print the records a running server is answering with, through its control socket
+    #[cfg(unix)]
+    Dump(DumpArgs),
+}
 
-        loop {
-            sleep(Duration::from_secs(5)).await;
+#[derive(clap::Args, Debug)]
+struct CheckArgs {
+    /// Fail on warnings, such as skipped lines, as well as on errors
+    #[arg(long)]
+    strict: bool,
+}
 
-            let current_dhcp_mtime = std::fs::metadata(&dhcp_path).and_then(|m| m.modified()).ok();
-            let current_hosts_mtime = std::fs::metadata(&hosts_path).and_then(|m| m.modified()).ok();
+#[cfg(unix)]
+#[derive(clap::Args, Debug)]
+struct CtlArgs {
+    /// The control socket; `control_socket` from the config file when unset
+    #[arg(long)]
+    socket: Option<PathBuf>,
+    /// add-record [--persist-to-hosts] <name> <A|AAAA> <address> [ttl], remove-record <name>, list, dump [text|json], blocks or reload
+    #[arg(required = true, trailing_var_arg = true, allow_hyphen_values = true)]
+    command: Vec<String>,
+}
 
-            let mut reload_needed = false;
+#[cfg(unix)]
+#[derive(clap::Args, Debug)]
+struct DumpArgs {
+    /// The control socket; `control_socket` from the config file when unset
+    #[arg(long)]
+    socket: Option<PathBuf>,
+    /// `text`, one record per line, or `json`
+    #[arg(long, default_value = "text")]
+    format: localdns::dump::DumpFormat,
+}
 
-            if current_dhcp_mtime != last_dhcp_mtime {
-                println!("DHCP file changed. Reloading...");
-                last_dhcp_mtime = current_dhcp_mtime;
-                reload_needed = true;
-            }
+fn main() -> anyhow::Result<()> {
+    std::panic::set_hook(Box::new(|info| {
+        eprintln!("Panic: {:?}", info);
+    }));
 
-            if current_hosts_mtime != last_hosts_mtime {
-                println!("Hosts file changed. Reloading...");
-                last_hosts_mtime = current_hosts_mtime;
-                reload_needed = true;
-            }
+    let args = Args::parse();
 
-            if reload_needed {
-                match loader::load_records(&dhcp_path, &hosts_path, &suffix) {
-                    Ok(new_cache) => {
-                        let exact_count = new_cache.exact_matches.len();
-                        let wildcard_count = new_cache.wildcards.len();
-                        {
-                            let mut writer = records_clone.write().await;
-                            *writer = new_cache;
-                        }
-                        println!("Reloaded records. Now serving {} exact domains and {} wildcard patterns.", exact_count, wildcard_count);
-                    },
-                    Err(e) => eprintln!("Failed to reload records: {}", e),
-                }
-            }
+    match args.command {
+        Some(Command::Bench(bench_args)) => {
+            init_logging(config::DEFAULT_LOG_LEVEL);
+            runtime(false)?.block_on(bench::run(bench_args))
         }
-    });
-
-    // 3. Bind UDP Socket
-    let addr = format!("{}:{}", config.listen_address, config.listen_port);
-    let socket = UdpSocket::bind(&addr).await?;
-    println!("DNS Server listening on {}", addr);
-    std::io::stdout().flush().unwrap();
-    
-    let socket = Arc::new(socket);
-
-    // 4. Server Loop
-    let mut buf = [0u8; 4096];
-    println!("Entering server loop...");
-    std::io::stdout().flush().unwrap();
-    loop {
-        let (len, src) = match socket.recv_from(&mut buf).await {
-            Ok(x) => x,
-            Err(e) => {
-                eprintln!("Failed to receive UDP packet: {}", e);
-                continue;
-            }
-        };
-
-        let data = buf[..len].to_vec();
-        let records = records.clone();
-        let socket = socket.clone();
-        let ttl = config.ttl;
-        let fallback_ip = config.fallback_ip;
+        Some(Command::Check(check_args)) => {
+            // Problems the loader finds are in the report; the lease and zone
+            // parsers still log theirs
+            init_logging("warn,localdns::loader=error");
+            check(&args.config, check_args)
+        }
+        #[cfg(unix)]
+        Some(Command::Ctl(ctl_args)) => ctl(&args.config, ctl_args.socket, &ctl_args.command.join(" ")),
+        #[cfg(unix)]
+        Some(Command::Dump(dump_args)) => {
+            let format = match dump_args.format {
+                localdns::dump::DumpFormat::Text => "text",
+                localdns::dump::DumpFormat::Json => "json",
+            };
+            ctl(&args.config, dump_args.socket, &format!("dump {}", format))
+        }
+        None => {
+            // 1. Load Config, which decides what runtime to serve on and how much to log
+            let config = config::Config::load(&args.config)?;
+            init_logging(&config.log_level);
+            tracing::info!("Loaded config from {:?}", args.config);
+            runtime(config.embedded.enabled)?.block_on(async {
+                localdns::run::run_server(&args.config, config).await?.wait().await
+            })
+        }
+    }
+}
 
-        tokio::spawn(async move {
-            if let Err(e) = handle_query(socket, data, src, records, ttl, fallback_ip).await {
-                eprintln!("Error handling query from {}: {}", src, e);
-            }
-        });
+/// Prints every problem `check::check` finds and a summary line, exiting 1
+/// when there are errors, or warnings with `--strict`.
+fn check(config_path: &PathBuf, args: CheckArgs) -> anyhow::Result<()> {
+    let report = localdns::check::check(config_path);
+    for problem in &report.problems {
+        println!("{}", problem);
+    }
+    println!("{}", localdns::check::summary(config_path, &report));
+    if report.errors() > 0 || (args.strict && report.warnings() > 0) {
+        std::process::exit(1);
     }
+    Ok(())
 }
 
-async fn handle_query(
-    socket: Arc<UdpSocket>,
-    data: Vec<u8>,
-    src: SocketAddr,
-    records: Arc<RwLock<loader::DnsCache>>,
-    ttl: u32,
-    fallback_ip: Option<Ipv4Addr>,
-) -> anyhow::Result<()> {
-    // Parse the query
-    let request = match Message::from_vec(&data) {
-        Ok(m) => m,
-        Err(e) => {
-            return Err(anyhow::anyhow!("Failed to parse message: {}", e));
-        }
+/// Runs one control command and prints its output, failing with the server's reason.
+#[cfg(unix)]
+fn ctl(config_path: &PathBuf, socket: Option<PathBuf>, command: &str) -> anyhow::Result<()> {
+    let socket = match socket {
+        Some(socket) => socket,
+        None => config::Config::load(config_path)?
+            .control_socket
+            .with_context(|| format!("{:?} sets no control_socket; pass --socket", config_path))?,
     };
-
-    // Create a response based on the request ID and settings
-    let mut response = Message::new();
-    response.set_id(request.id());
-    response.set_message_type(MessageType::Response);
-    response.set_op_code(request.op_code());
-    response.set_recursion_desired(request.recursion_desired());
-    response.set_recursion_available(true);
-
-    if let Some(query) = request.queries().first() {
-        response.add_query(query.clone());
-        
-        let name = query.name();
-        let lookup_name = name.to_string().to_lowercase();
-        
-        let records_guard = records.read().await;
-        
-        let mut found_ips: Vec<Ipv4Addr> = Vec::new();
-
-        if query.query_type() == RecordType::A {
-            // 1. Try exact match
-            if let Some(ips) = records_guard.exact_matches.get(&lookup_name) {
-                found_ips.extend(ips);
-            }
-
-            // 2. Try wildcard match (always check to merge results)
-            for (pattern, ip) in &records_guard.wildcards {
-                // Pattern is like "*.example.com."
-                // lookup_name is like "sub.example.com." or "sub.sub.example.com."
-                if pattern.starts_with("*.") {
-                    // Check if lookup_name ends with .root_domain (e.g. ".example.com.")
-                    // pattern is "*.example.com.", so pattern[1..] is ".example.com."
-                    if lookup_name.ends_with(&pattern[1..]) {
-                        found_ips.push(*ip);
-                    }
-                }
-            }
-
-            if !found_ips.is_empty() {
-                // Remove duplicates and sort, though `Vec` might be fine for this limited case
-                found_ips.sort_unstable();
-                found_ips.dedup();
-
-                for ip in found_ips {
-                    let mut record = Record::with(name.clone(), RecordType::A, ttl);
-                    record.set_data(Some(RData::A(A(ip))));
-                    response.add_answer(record);
-                }
-                response.set_response_code(ResponseCode::NoError);
-            } else if let Some(ip) = fallback_ip {
-                // 3. Fallback if enabled
-                let mut record = Record::with(name.clone(), RecordType::A, ttl);
-                record.set_data(Some(RData::A(A(ip))));
-                response.add_answer(record);
-                response.set_response_code(ResponseCode::NoError);
-            } else {
-                response.set_response_code(ResponseCode::NXDomain);
-            }
-        } else {
-            // For other record types, if the exact name exists, return NoError but no data.
-            // If the name doesn't exist at all (even by wildcard), return NXDomain.
-            if records_guard.exact_matches.contains_key(&lookup_name) {
-                response.set_response_code(ResponseCode::NoError);
-            } else {
-                // Also check for wildcard match if not exact, for the purpose of NXDomain vs NoError
-                let mut name_found_by_wildcard = false;
-                for (pattern, _) in &records_guard.wildcards {
-                    if pattern.starts_with("*.") {
-                        if lookup_name.ends_with(&pattern[1..]) {
-                            name_found_by_wildcard = true;
-                            break;
-                        }
-                    }
-                }
-                if name_found_by_wildcard {
-                    response.set_response_code(ResponseCode::NoError);
-                } else {
-                    response.set_response_code(ResponseCode::NXDomain);
-                }
+    let reply = localdns::control::request(&socket, command)
+        .with_context(|| format!("Failed to reach the control socket {:?}", socket))?;
+    match reply {
+        Ok(lines) => {
+            for line in lines {
+                println!("{}", line);
             }
+            Ok(())
         }
-    } else {
-        response.set_response_code(ResponseCode::FormErr);
+        Err(reason) => anyhow::bail!(reason),
     }
+}
 
-    // Serialize and send
-    let response_bytes = response.to_vec()?;
-    socket.send_to(&response_bytes, src).await?;
+/// Sends `log` records, and `tracing` events through them, to stderr. `RUST_LOG`
+/// wins over `level` when set.
+fn init_logging(level: &str) {
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or(level)).init();
+}
 
-    Ok(())
-}
\ No newline at end of file
+/// The multi-threaded runtime, or a single-threaded one for the embedded profile.
+fn runtime(embedded: bool) -> std::io::Result<tokio::runtime::Runtime> {
+    let mut builder = if embedded {
+        tokio::runtime::Builder::new_current_thread()
+    } else {
+        tokio::runtime::Builder::new_multi_thread()
+    };
+    builder.enable_all().build()
+}
@@ -0,0 +1,147 @@
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, SocketAddrV4};
+use std::sync::Arc;
+use socket2::{Domain, Protocol, Socket, Type};
+use tokio::net::UdpSocket;
+use tokio::sync::RwLock;
+use hickory_proto::op::{Message, MessageType, OpCode};
+use hickory_proto::rr::rdata::{A, AAAA};
+use hickory_proto::rr::{DNSClass, LowerName, RData, Record, RecordType};
+
+use crate::loader::{name_key, DnsCache};
+use crate::server::{is_response, ServeError};
+
+/// The IPv4 mDNS group, and the port every responder listens on (RFC 6762 3).
+pub const MDNS_GROUP: SocketAddrV4 = SocketAddrV4::new(Ipv4Addr::new(224, 0, 0, 251), 5353);
+
+/// TTL of address records, the one RFC 6762 10 recommends for host names.
+pub const HOST_TTL: u32 = 120;
+
+/// Most that legacy unicast answers may be cached for (RFC 6762 6.7).
+const LEGACY_TTL: u32 = 10;
+
+/// The top bit of a question's class asks for a unicast reply, and of a
+/// record's class that it replaces what caches held for the name.
+const UNICAST_RESPONSE: u16 = 0x8000;
+
+/// Whether the key `name` is under `.local`, the domain mDNS owns.
+pub fn is_local(name: &str) -> bool {
+    name == "local." || name.ends_with(".local.")
+}
+
+/// Binds `addr` (normally `0.0.0.0:5353`) alongside other responders on the
+/// host, and joins the mDNS group. Multicast loop stays on, so responders
+/// on this host hear our answers; ours come back as responses and are dropped.
+pub fn bind(addr: SocketAddr) -> Result<UdpSocket, ServeError> {
+    let listen = || -> std::io::Result<UdpSocket> {
+        let socket = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP))?;
+        socket.set_reuse_address(true)?;
+        #[cfg(unix)]
+        socket.set_reuse_port(true)?;
+        socket.set_nonblocking(true)?;
+        socket.bind(&addr.into())?;
+        socket.join_multicast_v4(MDNS_GROUP.ip(), &Ipv4Addr::UNSPECIFIED)?;
+        socket.set_multicast_loop_v4(true)?;
+        // Receivers drop mDNS packets that may have crossed a router (RFC 6762 11)
+        socket.set_multicast_ttl_v4(255)?;
+        UdpSocket::from_std(socket.into())
+    };
+    listen().map_err(|source| ServeError::Bind {
+        addr: addr.to_string(),
+        source,
+    })
+}
+
+/// Answers mDNS questions about the `.local` names in `records`. Queries
+/// from port 5353 are answered to the group unless they ask for a unicast
+/// reply; queries from any other port are legacy unicast ones, answered
+/// to the sender as a unicast DNS server would.
+pub async fn serve(socket: UdpSocket, records: Arc<RwLock<DnsCache>>) -> Result<(), ServeError> {
+    let mut buf = [0u8; 9000];
+    loop {
+        let (len, peer) = match socket.recv_from(&mut buf).await {
+            Ok(x) => x,
+            Err(e) => {
+                tracing::error!("Failed to receive mDNS packet: {}", e);
+                continue;
+            }
+        };
+        // Including our own answers, looped back to us
+        if is_response(&buf[..len]) {
+            continue;
+        }
+        let Ok(request) = Message::from_vec(&buf[..len]) else {
+            tracing::debug!("Ignoring a malformed mDNS packet from {}", peer);
+            continue;
+        };
+        let legacy = peer.port() != MDNS_GROUP.port();
+        let Some(response) = answer(&request, legacy, &*records.read().await) else {
+            continue;
+        };
+        let unicast = legacy || request.queries().iter().any(|q| u16::from(q.query_class()) & UNICAST_RESPONSE != 0);
+        let destination = if unicast { peer } else { SocketAddr::V4(MDNS_GROUP) };
+        let sent = match response.to_vec() {
+            Ok(bytes) => socket.send_to(&bytes, destination).await.map(|_| ()),
+            Err(e) => Err(std::io::Error::new(std::io::ErrorKind::InvalidData, e)),
+        };
+        if let Err(e) = sent {
+            tracing::warn!("Failed to send mDNS answer to {}: {}", destination, e);
+        }
+    }
+}
+
+/// The answer to an mDNS query, or `None` when it asks about nothing we own,
+/// since other responders may own those names and silence is how mDNS says
+/// "not me". Only A, AAAA and ANY questions for exact `.local` names are
+/// answered, leaving out what the querier listed as already known.
+/// `legacy` answers are shaped for a unicast resolver: the query's ID and
+/// questions echoed, short TTLs, and no cache-flush bit.
+pub fn answer(request: &Message, legacy: bool, records: &DnsCache) -> Option<Message> {
+    if request.message_type() != MessageType::Query || request.op_code() != OpCode::Query {
+        return None;
+    }
+    let mut response = Message::new();
+    response.set_message_type(MessageType::Response);
+    response.set_authoritative(true);
+    if legacy {
+        response.set_id(request.id());
+    }
+    for query in request.queries() {
+        let class = u16::from(query.query_class()) & !UNICAST_RESPONSE;
+        if class != u16::from(DNSClass::IN) && class != u16::from(DNSClass::ANY) {
+            continue;
+        }
+        let key = name_key(query.name());
+        if !is_local(&key) {
+            continue;
+        }
+        let Some(entries) = records.exact_matches.get(&LowerName::new(query.name())) else {
+            continue;
+        };
+        let mut answered = false;
+        for entry in entries.iter().filter(|entry| !entry.ip.is_unspecified()) {
+            let rdata = match (entry.ip, query.query_type()) {
+                (IpAddr::V4(ip), RecordType::A | RecordType::ANY) => RData::A(A(ip)),
+                (IpAddr::V6(ip), RecordType::AAAA | RecordType::ANY) => RData::AAAA(AAAA(ip)),
+                _ => continue,
+            };
+            answered = true;
+            // Known-answer suppression: skip what the querier holds for at least half its lifetime (RFC 6762 7.1)
+            let known = request.answers().iter().any(|record| {
+                record.name() == query.name() && record.data() == Some(&rdata) && record.ttl() >= HOST_TTL / 2
+            });
+            if known {
+                continue;
+            }
+            let mut record = Record::from_rdata(query.name().clone(), if legacy { LEGACY_TTL } else { HOST_TTL }, rdata);
+            if !legacy {
+                // We are the only owner of our names, so caches may drop what they had (RFC 6762 10.2)
+                record.set_dns_class(DNSClass::Unknown(u16::from(DNSClass::IN) | UNICAST_RESPONSE));
+            }
+            response.add_answer(record);
+        }
+        if legacy && answered {
+            response.add_query(query.clone());
+        }
+    }
+    (!response.answers().is_empty()).then_some(response)
+}
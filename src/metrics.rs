@@ -0,0 +1,112 @@
+use std::fmt::Write;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+
+use crate::blocklist::Blocks;
+use crate::http::{Request, Response};
+use crate::querylog::rcode_name;
+use crate::server::AnswerSource;
+use crate::stats::{Counters, Stats, QTYPES, RCODES};
+
+/// The Prometheus text exposition format.
+const CONTENT_TYPE: &str = "text/plain; version=0.0.4; charset=utf-8";
+
+/// Answers `GET /metrics` for the listeners in `listeners`, the default one
+/// first, and the blocks in `blocks`.
+pub fn handle(request: &Request, listeners: &[(String, Arc<Stats>)], blocks: &Blocks) -> Response {
+    match (request.method.as_str(), request.path.as_str()) {
+        ("GET", "/metrics") => Response::new(200, CONTENT_TYPE, render(listeners, blocks)),
+        (_, "/metrics") => Response::text(405, "only GET is supported\n").with_header("Allow", "GET"),
+        _ => Response::text(404, "not found\n"),
+    }
+}
+
+/// Every counter, labelled by listener. Reloads and record counts are kept
+/// on the first listener's stats and reported without a label, and blocks
+/// are labelled by list.
+pub fn render(listeners: &[(String, Arc<Stats>)], blocks: &Blocks) -> String {
+    let counters: Vec<(&str, Counters)> = listeners.iter().map(|(name, stats)| (name.as_str(), stats.counters())).collect();
+    let mut out = String::new();
+
+    header(&mut out, "localdns_queries_total", "counter", "Queries answered.");
+    for (listener, counters) in &counters {
+        sample(&mut out, "localdns_queries_total", &[("listener", listener)], counters.queries);
+    }
+    header(&mut out, "localdns_rate_limited_total", "counter", "UDP queries dropped or truncated for going over rate_limit_qps.");
+    for (listener, stats) in listeners {
+        sample(&mut out, "localdns_rate_limited_total", &[("listener", listener)], stats.rate_limited.load(Ordering::Relaxed));
+    }
+    header(&mut out, "localdns_query_errors_total", "counter", "UDP and unix socket queries left unanswered because they didn't parse or the answer couldn't be sent.");
+    for (listener, stats) in listeners {
+        sample(&mut out, "localdns_query_errors_total", &[("listener", listener)], stats.errors.load(Ordering::Relaxed));
+    }
+    header(&mut out, "localdns_overloaded_total", "counter", "UDP queries dropped for arriving with max_inflight_queries in progress.");
+    for (listener, stats) in listeners {
+        sample(&mut out, "localdns_overloaded_total", &[("listener", listener)], stats.overloaded.load(Ordering::Relaxed));
+    }
+    header(&mut out, "localdns_upstream_cache_total", "counter", "Forwarded queries by whether the upstream cache answered them.");
+    for (listener, stats) in listeners {
+        let hits = stats.upstream_cache_hits.load(Ordering::Relaxed);
+        let misses = stats.upstream_cache_misses.load(Ordering::Relaxed);
+        sample(&mut out, "localdns_upstream_cache_total", &[("listener", listener), ("result", "hit")], hits);
+        sample(&mut out, "localdns_upstream_cache_total", &[("listener", listener), ("result", "miss")], misses);
+    }
+    header(&mut out, "localdns_responses_total", "counter", "Responses by response code.");
+    for (listener, counters) in &counters {
+        for (i, count) in counters.rcodes.iter().enumerate() {
+            let rcode = RCODES.get(i).map(|r| rcode_name(*r)).unwrap_or_else(|| "other".to_string());
+            sample(&mut out, "localdns_responses_total", &[("listener", listener), ("rcode", &rcode)], *count);
+        }
+    }
+    header(&mut out, "localdns_queries_by_type_total", "counter", "Queries by question type.");
+    for (listener, counters) in &counters {
+        for (i, count) in counters.qtypes.iter().enumerate() {
+            let qtype = QTYPES.get(i).map(|t| t.to_string()).unwrap_or_else(|| "other".to_string());
+            sample(&mut out, "localdns_queries_by_type_total", &[("listener", listener), ("type", &qtype)], *count);
+        }
+    }
+    header(&mut out, "localdns_answers_by_source_total", "counter", "Queries by what answered them, such as exact or wildcard records.");
+    for (listener, counters) in &counters {
+        for (source, count) in AnswerSource::ALL.iter().zip(&counters.sources) {
+            sample(&mut out, "localdns_answers_by_source_total", &[("listener", listener), ("source", source.as_str())], *count);
+        }
+    }
+
+    if let Some((_, stats)) = listeners.first() {
+        header(&mut out, "localdns_reloads_total", "counter", "Reloads that swapped in new records.");
+        sample(&mut out, "localdns_reloads_total", &[], stats.reloads.load(Ordering::Relaxed));
+        header(&mut out, "localdns_reload_failures_total", "counter", "Reloads that kept the previous records.");
+        sample(&mut out, "localdns_reload_failures_total", &[], stats.reload_failures.load(Ordering::Relaxed));
+        header(&mut out, "localdns_records", "gauge", "Records being served, as of the last load.");
+        let exact = stats.exact_records.load(Ordering::Relaxed) as u64;
+        let wildcards = stats.wildcard_records.load(Ordering::Relaxed) as u64;
+        sample(&mut out, "localdns_records", &[("kind", "exact")], exact);
+        sample(&mut out, "localdns_records", &[("kind", "wildcard")], wildcards);
+        header(&mut out, "localdns_conflicting_names", "gauge", "Names whose sources list different addresses, as of the last load.");
+        sample(&mut out, "localdns_conflicting_names", &[], stats.conflicting_names.load(Ordering::Relaxed) as u64);
+    }
+    header(&mut out, "localdns_blocks_total", "counter", "Queries answered as blocks, by the list that blocked them.");
+    for (list, hits) in blocks.hits() {
+        sample(&mut out, "localdns_blocks_total", &[("list", &list)], hits);
+    }
+    out
+}
+
+fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+}
+
+fn sample(out: &mut String, name: &str, labels: &[(&str, &str)], value: u64) {
+    out.push_str(name);
+    if !labels.is_empty() {
+        let labels: Vec<String> = labels.iter().map(|(key, value)| format!("{}=\"{}\"", key, escape(value))).collect();
+        let _ = write!(out, "{{{}}}", labels.join(","));
+    }
+    let _ = writeln!(out, " {}", value);
+}
+
+/// Label values escape backslashes, quotes and newlines.
+fn escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
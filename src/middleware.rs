@@ -0,0 +1,201 @@
+use std::fmt;
+use std::future::Future;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::Arc;
+use tokio::sync::RwLock;
+use hickory_proto::op::{Edns as EdnsRecord, Message, ResponseCode};
+
+use crate::loader::DnsCache;
+use crate::loader::name_key;
+use crate::mdns;
+use crate::reverse::parse_in_addr_arpa;
+use crate::server::{add_nsid, lookup, response_for, AnswerSource, ResolveOptions, ServeError};
+
+/// A response and what produced it.
+pub type Answer = (Message, AnswerSource);
+
+pub type BoxFuture<'a> = Pin<Box<dyn Future<Output = Result<Answer, ServeError>> + Send + 'a>>;
+
+/// Everything a middleware may look at while answering one query.
+pub struct QueryContext<'a> {
+    pub request: &'a Message,
+    /// The querying client; `None` for the JSON API, whose requests carry no address
+    pub client: Option<SocketAddr>,
+    pub records: &'a RwLock<DnsCache>,
+    pub options: &'a ResolveOptions,
+}
+
+/// One step of query handling. A middleware answers by returning a response,
+/// or hands the query on with `next.run(ctx)` and may then rewrite what
+/// comes back; not calling `next` short-circuits the rest of the chain.
+pub trait Middleware: Send + Sync {
+    fn handle<'a>(&'a self, ctx: &'a QueryContext<'a>, next: Next<'a>) -> BoxFuture<'a>;
+}
+
+/// The middlewares after the current one.
+pub struct Next<'a> {
+    rest: &'a [Arc<dyn Middleware>],
+}
+
+impl<'a> Next<'a> {
+    pub fn run(self, ctx: &'a QueryContext<'a>) -> BoxFuture<'a> {
+        match self.rest.split_first() {
+            Some((first, rest)) => first.handle(ctx, Next { rest }),
+            // Nothing in the chain took the query
+            None => Box::pin(async move {
+                let mut response = response_for(ctx.request);
+                response.set_response_code(ResponseCode::Refused);
+                Ok((response, AnswerSource::None))
+            }),
+        }
+    }
+}
+
+/// The middlewares every query runs through, outermost first.
+#[derive(Clone)]
+pub struct Chain(Arc<[Arc<dyn Middleware>]>);
+
+impl Chain {
+    pub fn new(middlewares: Vec<Arc<dyn Middleware>>) -> Self {
+        Self(middlewares.into())
+    }
+
+    /// The built-in steps, in their default order, for embedders to splice
+    /// their own into.
+    pub fn builtin() -> Vec<Arc<dyn Middleware>> {
+        vec![Arc::new(Edns), Arc::new(Nsid), Arc::new(Blocklist), Arc::new(Forward), Arc::new(Lookup)]
+    }
+
+    pub fn run<'a>(&'a self, ctx: &'a QueryContext<'a>) -> BoxFuture<'a> {
+        Next { rest: &self.0 }.run(ctx)
+    }
+}
+
+impl Default for Chain {
+    fn default() -> Self {
+        Self::new(Self::builtin())
+    }
+}
+
+impl fmt::Debug for Chain {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Chain({} middlewares)", self.0.len())
+    }
+}
+
+/// Answers from the loaded records and the configured synthetic names.
+/// It never calls `next`, so middlewares placed after it don't run.
+pub struct Lookup;
+
+impl Middleware for Lookup {
+    fn handle<'a>(&'a self, ctx: &'a QueryContext<'a>, _next: Next<'a>) -> BoxFuture<'a> {
+        Box::pin(lookup(ctx.request, ctx.records, ctx.options))
+    }
+}
+
+/// Adds the RFC 5001 server identifier to whatever the rest of the chain answered.
+pub struct Nsid;
+
+impl Middleware for Nsid {
+    fn handle<'a>(&'a self, ctx: &'a QueryContext<'a>, next: Next<'a>) -> BoxFuture<'a> {
+        Box::pin(async move {
+            let (mut response, source) = next.run(ctx).await?;
+            if let Some(nsid) = &ctx.options.nsid {
+                add_nsid(ctx.request, &mut response, nsid);
+            }
+            Ok((response, source))
+        })
+    }
+}
+
+/// RFC 6891: answers a request carrying an OPT record with one of our own,
+/// advertising `edns_udp_payload`, and refuses EDNS versions above 0 with
+/// BADVERS without looking the name up.
+pub struct Edns;
+
+impl Middleware for Edns {
+    fn handle<'a>(&'a self, ctx: &'a QueryContext<'a>, next: Next<'a>) -> BoxFuture<'a> {
+        Box::pin(async move {
+            let Some(requested) = ctx.request.extensions() else {
+                return next.run(ctx).await;
+            };
+            let mut edns = EdnsRecord::new();
+            if requested.version() > 0 {
+                let mut response = response_for(ctx.request);
+                response.set_response_code(ResponseCode::BADVERS);
+                edns.set_max_payload(ctx.options.edns_payload);
+                response.set_edns(edns);
+                return Ok((response, AnswerSource::None));
+            }
+            let (mut response, source) = next.run(ctx).await?;
+            if let Some(existing) = response.extensions() {
+                edns = existing.clone();
+            }
+            edns.set_version(0);
+            edns.set_max_payload(ctx.options.edns_payload);
+            response.set_edns(edns);
+            Ok((response, source))
+        })
+    }
+}
+
+/// Answers names set aside as blocks according to `blocklist_mode`, and hands
+/// everything else on.
+pub struct Blocklist;
+
+impl Middleware for Blocklist {
+    fn handle<'a>(&'a self, ctx: &'a QueryContext<'a>, next: Next<'a>) -> BoxFuture<'a> {
+        Box::pin(async move {
+            let blocklist = &ctx.options.blocklist;
+            if blocklist.enabled() {
+                let records = ctx.records.read().await;
+                if let Some(response) = blocklist.answer(ctx.request, ctx.client, &records, ctx.options) {
+                    return Ok((response, AnswerSource::Blocked));
+                }
+            }
+            next.run(ctx).await
+        })
+    }
+}
+
+/// Sends a query the rest of the chain answered with NXDOMAIN, and nothing
+/// else to say, to the upstreams of the most specific `forward_zones` entry
+/// containing the name, or else to `upstream` when the name is outside our
+/// zone. Reverse lookups of private, loopback and link-local addresses only
+/// leave through a forward zone, as RFC 6303 asks.
+pub struct Forward;
+
+impl Middleware for Forward {
+    fn handle<'a>(&'a self, ctx: &'a QueryContext<'a>, next: Next<'a>) -> BoxFuture<'a> {
+        Box::pin(async move {
+            let (response, source) = next.run(ctx).await?;
+            let Some(query) = ctx.request.queries().first() else {
+                return Ok((response, source));
+            };
+            if source != AnswerSource::None || response.response_code() != ResponseCode::NXDomain {
+                return Ok((response, source));
+            }
+            // A SIGHUP may have changed these since the listener started
+            let (soa, upstreams) = match &ctx.records.read().await.settings {
+                Some(settings) => (settings.soa.clone(), settings.upstreams.clone()),
+                None => (ctx.options.soa.clone(), ctx.options.upstreams.clone()),
+            };
+            let name = name_key(query.name());
+            let local_only = soa.contains(&name)
+                || (ctx.options.mdns && mdns::is_local(&name))
+                || parse_in_addr_arpa(&name).is_some_and(|a| a.is_private() || a.is_loopback() || a.is_link_local());
+            let route = match upstreams.zone_for(&name) {
+                // `addresses` keeps these to the local records
+                Some(route) if route.upstreams.is_empty() => return Ok((response, source)),
+                Some(route) => route,
+                None => match upstreams.fallback() {
+                    Some(route) if !local_only => route,
+                    _ => return Ok((response, source)),
+                },
+            };
+            let response = ctx.options.forwarder.resolve(ctx.request, route, &ctx.options.stats).await?;
+            Ok((response, AnswerSource::Upstream))
+        })
+    }
+}
@@ -0,0 +1,230 @@
+use std::collections::HashSet;
+use std::fs;
+use std::io::Write;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+use serde::{Deserialize, Serialize};
+
+use crate::loader::{name_key, parse_presentation};
+
+/// An address record added over the control socket or the HTTP API.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RuntimeRecord {
+    /// Key form (see `name_key`), with `*.` in front for a wildcard
+    pub name: String,
+    pub ip: IpAddr,
+    pub ttl: Option<u32>,
+}
+
+/// The key form of `name` as runtime records and removals use it, keeping a
+/// leading `*.` for a wildcard.
+pub fn runtime_key(name: &str) -> Result<String, String> {
+    let (wildcard, bare) = match name.strip_prefix("*.") {
+        Some(rest) => (true, rest),
+        None => (false, name),
+    };
+    let key = name_key(&parse_presentation(bare).map_err(|e| format!("invalid name {:?}: {}", name, e))?);
+    Ok(if wildcard { format!("*.{}", key) } else { key })
+}
+
+/// Address records added and names removed at runtime, over the control
+/// socket or the HTTP API. Every load applies them on top of the sources, so
+/// they survive reloads, and with a journal restarts too.
+#[derive(Debug, Default)]
+pub struct Overlay {
+    state: Mutex<State>,
+    journal: Option<Journal>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    /// Each with when it was last added, in seconds since the epoch
+    added: Vec<(RuntimeRecord, u64)>,
+    /// Names whose addresses from the other sources are left out
+    removed: HashSet<String>,
+}
+
+/// Where `runtime_journal` keeps the changes, and how long an added record
+/// lives without being added again; 0 for ever.
+#[derive(Debug)]
+struct Journal {
+    path: PathBuf,
+    lifetime: u64,
+}
+
+/// One line of the journal.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
+#[serde(tag = "op", rename_all = "lowercase", deny_unknown_fields)]
+enum Change {
+    Add { name: String, address: IpAddr, ttl: Option<u32>, at: u64 },
+    Remove { name: String },
+}
+
+impl Overlay {
+    /// The overlay the journal at `path` leaves, with records older than
+    /// `lifetime` seconds dropped, and each later change appended to it. Lines
+    /// that don't parse are skipped with a warning. The journal is then
+    /// rewritten with only what is left, so it doesn't grow without bound.
+    pub fn open(path: &Path, lifetime: u64) -> Self {
+        let overlay = Self { state: Mutex::default(), journal: Some(Journal { path: path.to_path_buf(), lifetime }) };
+        let content = match fs::read_to_string(path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+            Err(e) => {
+                tracing::error!("Failed to read the runtime journal {:?}, starting without it: {}", path, e);
+                return overlay;
+            }
+        };
+        {
+            let mut state = overlay.state.lock().unwrap();
+            let mut expired = 0;
+            for (number, line) in content.lines().enumerate().filter(|(_, line)| !line.trim().is_empty()) {
+                match serde_json::from_str::<Change>(line) {
+                    Ok(Change::Add { at, .. }) if lifetime > 0 && at.saturating_add(lifetime) <= now() => expired += 1,
+                    Ok(Change::Add { name, address, ttl, at }) => {
+                        state.add(RuntimeRecord { name, ip: address, ttl }, at);
+                    }
+                    Ok(Change::Remove { name }) => state.remove(&name),
+                    Err(e) => tracing::warn!("Skipping line {} of the runtime journal {:?}: {}", number + 1, path, e),
+                }
+            }
+            tracing::info!(
+                "Replayed {} runtime records and {} removals from {:?}, dropping {} expired",
+                state.added.len(),
+                state.removed.len(),
+                path,
+                expired
+            );
+        }
+        overlay.compact();
+        overlay
+    }
+
+    /// Adds `record`, returning false when the same name and address were
+    /// already added; either way it counts as refreshed.
+    pub fn add(&self, record: RuntimeRecord) -> bool {
+        let at = now();
+        self.append(&[Change::Add { name: record.name.clone(), address: record.ip, ttl: record.ttl, at }]);
+        self.state.lock().unwrap().add(record, at)
+    }
+
+    /// Drops the records added for `name` and hides its addresses from every
+    /// other source; what is added for it afterwards is served again.
+    pub fn remove(&self, name: &str) {
+        self.append(&[Change::Remove { name: name.to_string() }]);
+        self.state.lock().unwrap().remove(name);
+    }
+
+    /// Makes `records`, all for `name`, the only ones it has from any source.
+    pub fn replace(&self, name: &str, records: Vec<RuntimeRecord>) {
+        let at = now();
+        let mut changes = vec![Change::Remove { name: name.to_string() }];
+        changes.extend(records.iter().map(|r| Change::Add { name: r.name.clone(), address: r.ip, ttl: r.ttl, at }));
+        self.append(&changes);
+        let mut state = self.state.lock().unwrap();
+        state.remove(name);
+        for record in records {
+            state.add(record, at);
+        }
+    }
+
+    /// Drops the records not added again within the journal's lifetime,
+    /// returning them.
+    pub fn expire(&self) -> Vec<RuntimeRecord> {
+        let Some(lifetime) = self.lifetime() else {
+            return Vec::new();
+        };
+        let now = now();
+        let mut state = self.state.lock().unwrap();
+        let (kept, expired): (Vec<_>, Vec<_>) = std::mem::take(&mut state.added).into_iter().partition(|(_, at)| at.saturating_add(lifetime) > now);
+        state.added = kept;
+        expired.into_iter().map(|(record, _)| record).collect()
+    }
+
+    /// The journal's lifetime in seconds, when records expire at all.
+    pub fn lifetime(&self) -> Option<u64> {
+        self.journal.as_ref().map(|journal| journal.lifetime).filter(|lifetime| *lifetime > 0)
+    }
+
+    pub fn records(&self) -> Vec<RuntimeRecord> {
+        self.state.lock().unwrap().added.iter().map(|(record, _)| record.clone()).collect()
+    }
+
+    pub fn removed(&self) -> HashSet<String> {
+        self.state.lock().unwrap().removed.clone()
+    }
+
+    /// Whether changes outlive a restart.
+    pub fn persistent(&self) -> bool {
+        self.journal.is_some()
+    }
+
+    /// Appends `changes` to the journal. A failed write is logged and the
+    /// change still applied, as it is only the restart that loses it.
+    fn append(&self, changes: &[Change]) {
+        let Some(journal) = &self.journal else {
+            return;
+        };
+        let mut lines = String::new();
+        for change in changes {
+            lines.push_str(&serde_json::to_string(change).expect("changes serialize to JSON"));
+            lines.push('\n');
+        }
+        let written = fs::OpenOptions::new().create(true).append(true).open(&journal.path).and_then(|mut file| file.write_all(lines.as_bytes()));
+        if let Err(e) = written {
+            tracing::error!("Failed to append to the runtime journal {:?}; the change won't survive a restart: {}", journal.path, e);
+        }
+    }
+
+    /// Rewrites the journal as the removals followed by the records, which
+    /// replays to the same state. It is written beside and renamed over the
+    /// journal, so a crash never leaves a torn one.
+    fn compact(&self) {
+        let Some(journal) = &self.journal else {
+            return;
+        };
+        let mut lines = String::new();
+        {
+            let state = self.state.lock().unwrap();
+            let mut removed: Vec<&String> = state.removed.iter().collect();
+            removed.sort();
+            let removals = removed.into_iter().map(|name| Change::Remove { name: name.clone() });
+            let adds = state.added.iter().map(|(r, at)| Change::Add { name: r.name.clone(), address: r.ip, ttl: r.ttl, at: *at });
+            for change in removals.chain(adds) {
+                lines.push_str(&serde_json::to_string(&change).expect("changes serialize to JSON"));
+                lines.push('\n');
+            }
+        }
+        let mut tmp = journal.path.as_os_str().to_owned();
+        tmp.push(".tmp");
+        let tmp = PathBuf::from(tmp);
+        let written = fs::File::create(&tmp)
+            .and_then(|mut file| file.write_all(lines.as_bytes()).and_then(|_| file.sync_all()))
+            .and_then(|_| fs::rename(&tmp, &journal.path));
+        if let Err(e) = written {
+            tracing::error!("Failed to rewrite the runtime journal {:?}: {}", journal.path, e);
+        }
+    }
+}
+
+impl State {
+    fn add(&mut self, record: RuntimeRecord, at: u64) -> bool {
+        if let Some((_, added_at)) = self.added.iter_mut().find(|(added, _)| added.name == record.name && added.ip == record.ip) {
+            *added_at = at;
+            return false;
+        }
+        self.added.push((record, at));
+        true
+    }
+
+    fn remove(&mut self, name: &str) {
+        self.added.retain(|(added, _)| added.name != name);
+        self.removed.insert(name.to_string());
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs()
+}
@@ -0,0 +1,166 @@
+use ipnet::IpNet;
+use serde::Deserialize;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use hickory_proto::op::{Message, ResponseCode};
+
+use crate::anonymize::{AnonymizeMode, Anonymizer};
+use crate::loader::name_key;
+use crate::server::AnswerSource;
+
+/// The log target query lines are written under, so `RUST_LOG` can turn them
+/// off or up on their own.
+pub const TARGET: &str = "localdns::query";
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct QueryLogConfig {
+    pub enabled: bool,
+    /// Fraction of queries to log, between 0.0 and 1.0
+    pub sample_rate: f64,
+    /// Log every SERVFAIL, FORMERR, REFUSED and NOTIMP answer regardless of sampling
+    pub always_log_errors: bool,
+    /// Query names under these suffixes are always logged
+    pub always_log_suffixes: Vec<String>,
+    /// Clients in these networks are always logged
+    pub always_log_clients: Vec<IpNet>,
+}
+
+impl Default for QueryLogConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            sample_rate: 1.0,
+            always_log_errors: true,
+            always_log_suffixes: Vec::new(),
+            always_log_clients: Vec::new(),
+        }
+    }
+}
+
+/// Emits one line per answered query. Each line carries the rate it was sampled
+/// at (1 when a rule forced it), so counts can be re-weighted downstream.
+#[derive(Debug)]
+pub struct QueryLog {
+    enabled: bool,
+    sample_rate_bits: AtomicU64, // f64 bits, so the rate can be changed while serving
+    always_log_errors: bool,
+    always_log_suffixes: Vec<String>, // Lowercase, with leading and trailing dots
+    always_log_clients: Vec<IpNet>,
+    anonymizer: Arc<Anonymizer>,
+}
+
+impl QueryLog {
+    pub fn new(config: &QueryLogConfig, anonymizer: Arc<Anonymizer>) -> Self {
+        let always_log_suffixes = config
+            .always_log_suffixes
+            .iter()
+            .map(|s| format!(".{}.", s.trim_matches('.').to_lowercase()))
+            .collect();
+        Self {
+            enabled: config.enabled,
+            sample_rate_bits: AtomicU64::new(config.sample_rate.clamp(0.0, 1.0).to_bits()),
+            always_log_errors: config.always_log_errors,
+            always_log_suffixes,
+            always_log_clients: config.always_log_clients.clone(),
+            anonymizer,
+        }
+    }
+
+    pub fn sample_rate(&self) -> f64 {
+        f64::from_bits(self.sample_rate_bits.load(Ordering::Relaxed))
+    }
+
+    pub fn set_sample_rate(&self, rate: f64) {
+        self.sample_rate_bits.store(rate.clamp(0.0, 1.0).to_bits(), Ordering::Relaxed);
+    }
+
+    /// The rate this query is logged at, or `None` if it should be skipped.
+    fn effective_rate(&self, client: Option<SocketAddr>, lookup_name: &str, rcode: ResponseCode) -> Option<f64> {
+        let forced = (self.always_log_errors && is_error(rcode))
+            || client.is_some_and(|client| self.always_log_clients.iter().any(|net| net.contains(&client.ip())))
+            || self.always_log_suffixes.iter().any(|suffix| {
+                lookup_name.ends_with(suffix.as_str()) || lookup_name == &suffix[1..]
+            });
+        if forced {
+            return Some(1.0);
+        }
+
+        let rate = self.sample_rate();
+        if rate >= 1.0 || (rate > 0.0 && rand::random::<f64>() < rate) {
+            Some(rate)
+        } else {
+            None
+        }
+    }
+
+    pub fn record(
+        &self,
+        listener: &str,
+        client: Option<SocketAddr>,
+        request: &Message,
+        response: &Message,
+        source: AnswerSource,
+        elapsed: Duration,
+    ) {
+        if !self.enabled {
+            return;
+        }
+        let (qname, key, qtype) = match request.queries().first() {
+            Some(q) => (q.name().to_string(), name_key(q.name()), q.query_type().to_string()),
+            None => (".".to_string(), ".".to_string(), "-".to_string()),
+        };
+        let rcode = response.response_code();
+        let Some(rate) = self.effective_rate(client, &key, rcode) else {
+            return;
+        };
+        let client = self.client(client);
+        tracing::info!(
+            target: TARGET,
+            listener = %listener,
+            client = %client,
+            id = request.id(),
+            name = %qname,
+            r#type = %qtype,
+            rcode = %rcode_name(rcode),
+            source = %source.as_str(),
+            answers = response.answers().len(),
+            elapsed_us = elapsed.as_micros() as u64,
+            sample_rate = rate,
+            "query"
+        );
+    }
+
+    /// `client` as the log shows it, anonymized as `anonymize_clients` asks.
+    pub fn client(&self, client: Option<SocketAddr>) -> String {
+        // The port is only worth keeping when the address itself is
+        match (client, self.anonymizer.mode()) {
+            (None, _) => "-".to_string(), // A unix socket peer
+            (Some(client), AnonymizeMode::Off) => client.to_string(),
+            (Some(client), _) => self.anonymizer.client(client.ip()).unwrap_or_else(|| "-".to_string()),
+        }
+    }
+}
+
+fn is_error(rcode: ResponseCode) -> bool {
+    matches!(
+        rcode,
+        ResponseCode::ServFail | ResponseCode::FormErr | ResponseCode::Refused | ResponseCode::NotImp
+    )
+}
+
+/// The conventional mnemonic, e.g. `NXDOMAIN`, which unlike `Display` has no spaces.
+pub fn rcode_name(rcode: ResponseCode) -> String {
+    match rcode {
+        ResponseCode::NoError => "NOERROR".to_string(),
+        ResponseCode::FormErr => "FORMERR".to_string(),
+        ResponseCode::ServFail => "SERVFAIL".to_string(),
+        ResponseCode::NXDomain => "NXDOMAIN".to_string(),
+        ResponseCode::NotImp => "NOTIMP".to_string(),
+        ResponseCode::Refused => "REFUSED".to_string(),
+        ResponseCode::BADVERS => "BADVERS".to_string(),
+        other => u16::from(other).to_string(),
+    }
+}
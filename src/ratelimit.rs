@@ -0,0 +1,103 @@
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::Mutex;
+use tokio::time::{Duration, Instant};
+
+/// How often idle clients are swept out of the table.
+const SWEEP_INTERVAL: Duration = Duration::from_secs(60);
+
+/// What happens to a query over its client's rate.
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum RateLimitAction {
+    /// Send nothing
+    #[default]
+    Drop,
+    /// Send an empty response with TC set, so a real client retries over TCP,
+    /// as BIND's RRL does with `slip`
+    Slip,
+}
+
+/// The outcome of checking one query against the limiter.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verdict {
+    Allow,
+    Drop,
+    Slip,
+}
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+#[derive(Debug)]
+struct Table {
+    buckets: HashMap<IpAddr, Bucket>,
+    swept: Instant,
+}
+
+/// A token bucket per client address: each holds up to `burst` queries and
+/// refills at `qps` per second.
+#[derive(Debug)]
+pub struct RateLimiter {
+    qps: f64,
+    burst: f64,
+    action: RateLimitAction,
+    table: Mutex<Table>,
+}
+
+impl RateLimiter {
+    /// None when `qps` is 0, which turns limiting off. A `burst` of 0 allows one second's worth.
+    pub fn new(qps: u32, burst: u32, action: RateLimitAction) -> Option<Self> {
+        if qps == 0 {
+            return None;
+        }
+        let burst = if burst == 0 { qps } else { burst };
+        Some(Self {
+            qps: f64::from(qps),
+            burst: f64::from(burst),
+            action,
+            table: Mutex::new(Table { buckets: HashMap::new(), swept: Instant::now() }),
+        })
+    }
+
+    pub fn check(&self, client: IpAddr) -> Verdict {
+        self.check_at(client, Instant::now())
+    }
+
+    /// Takes a token from `client`'s bucket as of `now`. Clients whose buckets
+    /// have refilled are dropped from the table every `SWEEP_INTERVAL`, since a
+    /// new bucket starts full anyway.
+    pub fn check_at(&self, client: IpAddr, now: Instant) -> Verdict {
+        let mut table = self.table.lock().unwrap();
+        if now.saturating_duration_since(table.swept) >= SWEEP_INTERVAL {
+            let (qps, burst) = (self.qps, self.burst);
+            table.buckets.retain(|_, bucket| refilled(bucket, qps, burst, now) < burst);
+            table.swept = now;
+        }
+        let bucket = table.buckets.entry(client).or_insert(Bucket { tokens: self.burst, updated: now });
+        bucket.tokens = refilled(bucket, self.qps, self.burst, now);
+        bucket.updated = bucket.updated.max(now);
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            return Verdict::Allow;
+        }
+        match self.action {
+            RateLimitAction::Drop => Verdict::Drop,
+            RateLimitAction::Slip => Verdict::Slip,
+        }
+    }
+
+    /// Clients with a bucket in the table.
+    pub fn tracked(&self) -> usize {
+        self.table.lock().unwrap().buckets.len()
+    }
+}
+
+fn refilled(bucket: &Bucket, qps: f64, burst: f64, now: Instant) -> f64 {
+    let elapsed = now.saturating_duration_since(bucket.updated).as_secs_f64();
+    (bucket.tokens + elapsed * qps).min(burst)
+}
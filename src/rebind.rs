@@ -0,0 +1,101 @@
+//! DNS rebinding protection: upstream answers that point a name from outside
+//! at an address on the local network, which would let a web page reach
+//! devices behind the router through a name its own server controls.
+
+use hickory_proto::op::{Message, Query, ResponseCode};
+use hickory_proto::rr::{RData, Record};
+use serde::Deserialize;
+use std::collections::HashSet;
+use std::net::{IpAddr, Ipv4Addr};
+
+use crate::loader::{name_key, parents};
+
+/// What becomes of an upstream response with a private address among its answers.
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum RebindAction {
+    /// Drop the offending A and AAAA records and pass on the rest
+    #[default]
+    Strip,
+    Nxdomain,
+    Servfail,
+}
+
+/// Checks upstream answers for `block_private_upstream`. Records from our own
+/// sources never pass through it.
+#[derive(Debug, Clone)]
+pub struct RebindGuard {
+    action: RebindAction,
+    /// Zone keys (see `name_key`) whose names may resolve to private addresses
+    allow: HashSet<String>,
+}
+
+impl RebindGuard {
+    pub fn new(action: RebindAction, allow: HashSet<String>) -> Self {
+        Self { action, allow }
+    }
+
+    /// Whether the key `name` is, or is under, one of the `rebind_allow` zones.
+    fn allows(&self, name: &str) -> bool {
+        std::iter::once(name).chain(parents(name)).any(|zone| self.allow.contains(zone))
+    }
+
+    /// Applies the action to `response`, an upstream's answer to `query`, when
+    /// one of its A or AAAA answers is a private address and the name isn't
+    /// allowed one. Each such address is logged. Returns whether it did.
+    pub fn check(&self, query: &Query, response: &mut Message) -> bool {
+        if self.allows(&name_key(query.name())) {
+            return false;
+        }
+        let private: Vec<IpAddr> = response.answers().iter().filter_map(address).filter(|&ip| is_private(ip)).collect();
+        if private.is_empty() {
+            return false;
+        }
+        for ip in private {
+            tracing::warn!("Blocked an upstream answer for {}: {} is a private address", query.name(), ip);
+        }
+        match self.action {
+            RebindAction::Strip => {
+                let answers = response.take_answers();
+                response.add_answers(answers.into_iter().filter(|record| !is_rebinding(record)));
+            }
+            RebindAction::Nxdomain | RebindAction::Servfail => {
+                response.take_answers();
+                response.take_name_servers();
+                response.take_additionals();
+                let code = if self.action == RebindAction::Nxdomain { ResponseCode::NXDomain } else { ResponseCode::ServFail };
+                response.set_response_code(code);
+            }
+        }
+        true
+    }
+}
+
+/// Whether `ip` belongs on a local network rather than in a public answer:
+/// RFC 1918 and unique local (fc00::/7), loopback, link-local, and the
+/// unspecified address, including IPv4 ones mapped into IPv6.
+pub fn is_private(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => is_private_v4(ip),
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(mapped) => is_private_v4(mapped),
+            None => ip.is_loopback() || ip.is_unspecified() || ip.is_unique_local() || ip.is_unicast_link_local(),
+        },
+    }
+}
+
+fn is_private_v4(ip: Ipv4Addr) -> bool {
+    ip.is_private() || ip.is_loopback() || ip.is_link_local() || ip.is_unspecified()
+}
+
+fn address(record: &Record) -> Option<IpAddr> {
+    match record.data()? {
+        RData::A(a) => Some(IpAddr::V4(a.0)),
+        RData::AAAA(aaaa) => Some(IpAddr::V6(aaaa.0)),
+        _ => None,
+    }
+}
+
+fn is_rebinding(record: &Record) -> bool {
+    address(record).is_some_and(is_private)
+}
@@ -0,0 +1,110 @@
+use serde_json::Value;
+use std::net::Ipv4Addr;
+
+/// A static DHCP reservation. `location` is the 1-based line of a dnsmasq
+/// `dhcp-host` entry, or the 1-based position of a Kea reservation.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Reservation {
+    pub location: usize,
+    pub hostname: String,
+    pub ip: Ipv4Addr,
+}
+
+/// Parses Kea reservations JSON when the file looks like JSON, and dnsmasq
+/// `dhcp-host` lines otherwise. Entries without both a hostname and an IPv4
+/// address are skipped, since they can't become a record.
+pub fn parse(content: &str) -> Result<Vec<Reservation>, serde_json::Error> {
+    if is_kea(content) {
+        parse_kea(content)
+    } else {
+        Ok(parse_dnsmasq(content))
+    }
+}
+
+/// Whether `content` looks like Kea JSON rather than dnsmasq lines.
+pub fn is_kea(content: &str) -> bool {
+    matches!(content.trim_start().chars().next(), Some('{' | '['))
+}
+
+/// Accepts a bare `reservations` array, an object holding one, or a full
+/// Kea config with reservations under `Dhcp4` and its `subnet4` entries.
+fn parse_kea(content: &str) -> Result<Vec<Reservation>, serde_json::Error> {
+    let root: Value = serde_json::from_str(content)?;
+    let mut lists: Vec<&Value> = Vec::new();
+    if root.is_array() {
+        lists.push(&root);
+    } else {
+        let dhcp4 = root.get("Dhcp4").unwrap_or(&root);
+        lists.push(dhcp4);
+        if let Some(subnets) = dhcp4.get("subnet4").and_then(Value::as_array) {
+            lists.extend(subnets);
+        }
+    }
+
+    let mut reservations = Vec::new();
+    let mut location = 1;
+    for list in lists {
+        let entries = match list {
+            Value::Array(entries) => entries,
+            other => match other.get("reservations").and_then(Value::as_array) {
+                Some(entries) => entries,
+                None => continue,
+            },
+        };
+        for entry in entries {
+            let hostname = entry.get("hostname").and_then(Value::as_str).filter(|h| !h.is_empty());
+            let ip = entry.get("ip-address").and_then(Value::as_str).and_then(|ip| ip.parse().ok());
+            if let (Some(hostname), Some(ip)) = (hostname, ip) {
+                reservations.push(Reservation { location, hostname: hostname.to_string(), ip });
+            }
+            location += 1;
+        }
+    }
+    Ok(reservations)
+}
+
+/// `dhcp-host=` lines from a dnsmasq config file, or the bare entries of a
+/// `dhcp-hostsfile`. Fields are told apart by their form, as dnsmasq does.
+fn parse_dnsmasq(content: &str) -> Vec<Reservation> {
+    let mut reservations = Vec::new();
+    for (index, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let entry = match line.split_once('=') {
+            Some(("dhcp-host", entry)) => entry,
+            Some(_) => continue, // Another dnsmasq option
+            None => line,
+        };
+
+        let (mut hostname, mut ip) = (None, None);
+        for field in entry.split(',').map(str::trim) {
+            if let Ok(address) = field.parse::<Ipv4Addr>() {
+                ip = Some(address);
+            } else if is_hostname_field(field) {
+                hostname = Some(field);
+            }
+        }
+        if let (Some(hostname), Some(ip)) = (hostname, ip) {
+            reservations.push(Reservation { location: index + 1, hostname: hostname.to_string(), ip });
+        }
+    }
+    reservations
+}
+
+/// Whether a `dhcp-host` field is the hostname rather than a MAC, client ID,
+/// tag, IPv6 address, lease time or keyword.
+fn is_hostname_field(field: &str) -> bool {
+    let is_mac = field.split(':').count() >= 6 && field.split(':').all(|p| p.len() <= 2 || p == "*");
+    let is_lease_time = field == "infinite"
+        || field.trim_end_matches(['s', 'm', 'h', 'd', 'w']).chars().all(|c| c.is_ascii_digit());
+    let has_prefix = ["id:", "set:", "tag:", "net:"].iter().any(|prefix| field.starts_with(prefix));
+    !(field.is_empty()
+        || is_mac
+        || is_lease_time
+        || has_prefix
+        || field.starts_with('[')
+        || field == "ignore"
+        || field == "*")
+}
@@ -0,0 +1,98 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Mutex;
+use hickory_proto::rr::{Record, RecordType};
+use tokio::time::{Duration, Instant};
+
+#[derive(Debug)]
+struct Slot {
+    answers: Vec<Record>,
+    stored: Instant,
+    expires: Instant,
+    /// Its place in `Table::order`
+    used: u64,
+}
+
+#[derive(Debug, Default)]
+struct Table {
+    slots: HashMap<(String, RecordType), Slot>,
+    /// Keys by when they were last used, least recently first
+    order: BTreeMap<u64, (String, RecordType)>,
+    tick: u64,
+}
+
+/// Positive upstream answers by lowercased name and type, each kept until the
+/// smallest TTL among its records runs out. Holds at most `max_entries`,
+/// dropping the least recently used first. It lives apart from `DnsCache`,
+/// so reloading the local records doesn't empty it.
+#[derive(Debug)]
+pub struct ResponseCache {
+    max_entries: usize,
+    table: Mutex<Table>,
+}
+
+impl ResponseCache {
+    /// A `max_entries` of 0 caches nothing.
+    pub fn new(max_entries: usize) -> Self {
+        Self { max_entries, table: Mutex::new(Table::default()) }
+    }
+
+    /// The answer records stored for `name` and `qtype`, unless they have
+    /// expired, with their TTLs lowered by the time spent in the cache.
+    pub fn get(&self, name: &str, qtype: RecordType) -> Option<Vec<Record>> {
+        let mut table = self.table.lock().unwrap();
+        let key = (name.to_string(), qtype);
+        let now = Instant::now();
+        let slot = table.slots.get(&key)?;
+        if slot.expires <= now {
+            let used = slot.used;
+            table.order.remove(&used);
+            table.slots.remove(&key);
+            return None;
+        }
+        let age = u32::try_from(now.duration_since(slot.stored).as_secs()).unwrap_or(u32::MAX);
+        let mut answers = slot.answers.clone();
+        for record in &mut answers {
+            record.set_ttl(record.ttl().saturating_sub(age));
+        }
+        let previous = slot.used;
+        table.tick += 1;
+        let used = table.tick;
+        table.order.remove(&previous);
+        table.order.insert(used, key.clone());
+        table.slots.get_mut(&key).expect("looked up above").used = used;
+        Some(answers)
+    }
+
+    /// Stores `answers`, unless there are none or one has a TTL of 0.
+    pub fn insert(&self, name: &str, qtype: RecordType, answers: Vec<Record>) {
+        let Some(ttl) = answers.iter().map(Record::ttl).min().filter(|ttl| *ttl > 0) else {
+            return;
+        };
+        if self.max_entries == 0 {
+            return;
+        }
+        let mut table = self.table.lock().unwrap();
+        let key = (name.to_string(), qtype);
+        if let Some(previous) = table.slots.remove(&key) {
+            table.order.remove(&previous.used);
+        }
+        while table.slots.len() >= self.max_entries {
+            let Some((_, oldest)) = table.order.pop_first() else { break };
+            table.slots.remove(&oldest);
+        }
+        table.tick += 1;
+        let used = table.tick;
+        let stored = Instant::now();
+        let expires = stored + Duration::from_secs(u64::from(ttl));
+        table.order.insert(used, key.clone());
+        table.slots.insert(key, Slot { answers, stored, expires, used });
+    }
+
+    pub fn len(&self) -> usize {
+        self.table.lock().unwrap().slots.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
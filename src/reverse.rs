@@ -0,0 +1,140 @@
+use serde::Deserialize;
+use std::net::Ipv4Addr;
+
+/// Which of the names an address reverses to are answered.
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum PtrNames {
+    /// Only the first: an explicit PTR, else the first lease, else the first hosts line
+    #[default]
+    Primary,
+    All,
+}
+
+#[derive(Deserialize)]
+struct RawClasslessZone {
+    network: String,
+    zone: String,
+}
+
+/// An RFC 2317 classless reverse zone, e.g. `192.168.1.16/28` served as
+/// `16-28.1.168.192.in-addr.arpa.`. Names in the parent /24 zone for addresses
+/// inside the block are answered with a CNAME into the classless zone.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(try_from = "RawClasslessZone")]
+pub struct ClasslessZone {
+    network: Ipv4Addr,
+    prefix_len: u8,
+    zone: String,   // Lowercase, with trailing dot
+    parent: String, // The enclosing /24 reverse zone, e.g. "1.168.192.in-addr.arpa."
+}
+
+/// How a query name relates to a classless zone.
+#[derive(Debug, PartialEq, Eq)]
+pub enum ClasslessMatch {
+    /// A parent-zone name whose address falls inside the block
+    Alias { target: String, address: Ipv4Addr },
+    /// A name inside the classless zone for an address in the block
+    Address(Ipv4Addr),
+    /// The classless zone itself
+    Apex,
+    /// A name inside the classless zone that doesn't map to an address in the block
+    Unknown,
+}
+
+impl TryFrom<RawClasslessZone> for ClasslessZone {
+    type Error = String;
+
+    fn try_from(raw: RawClasslessZone) -> Result<Self, Self::Error> {
+        let (addr, len) = raw
+            .network
+            .split_once('/')
+            .ok_or_else(|| format!("{:?} is not in CIDR notation", raw.network))?;
+        let network: Ipv4Addr = addr
+            .parse()
+            .map_err(|_| format!("{:?} is not an IPv4 address", addr))?;
+        let prefix_len: u8 = len
+            .parse()
+            .map_err(|_| format!("{:?} is not a prefix length", len))?;
+        if !(25..=32).contains(&prefix_len) {
+            return Err(format!(
+                "classless delegation needs a prefix longer than /24, got /{}",
+                prefix_len
+            ));
+        }
+        if u32::from(network) & !mask(prefix_len) != 0 {
+            return Err(format!("{} has host bits set", raw.network));
+        }
+
+        let mut zone = raw.zone.trim().to_lowercase();
+        if !zone.ends_with('.') {
+            zone.push('.');
+        }
+        let [a, b, c, _] = network.octets();
+        Ok(Self {
+            network,
+            prefix_len,
+            zone,
+            parent: format!("{}.{}.{}.in-addr.arpa.", c, b, a),
+        })
+    }
+}
+
+fn mask(prefix_len: u8) -> u32 {
+    u32::MAX << (32 - prefix_len as u32)
+}
+
+impl ClasslessZone {
+    fn contains(&self, address: Ipv4Addr) -> bool {
+        u32::from(address) & mask(self.prefix_len) == u32::from(self.network)
+    }
+
+    /// The address whose last octet is `label`, if it lies inside the block.
+    fn address_for(&self, label: &str) -> Option<Ipv4Addr> {
+        let octet: u8 = label.parse().ok()?;
+        let [a, b, c, _] = self.network.octets();
+        let address = Ipv4Addr::new(a, b, c, octet);
+        self.contains(address).then_some(address)
+    }
+
+    /// Classifies a lowercase, dot-terminated query name against this zone.
+    pub fn classify(&self, lookup_name: &str) -> Option<ClasslessMatch> {
+        if lookup_name == self.zone {
+            return Some(ClasslessMatch::Apex);
+        }
+        if let Some(label) = strip_single_label(lookup_name, &self.zone) {
+            return Some(match self.address_for(label) {
+                Some(address) => ClasslessMatch::Address(address),
+                None => ClasslessMatch::Unknown,
+            });
+        }
+        let label = strip_single_label(lookup_name, &self.parent)?;
+        let address = self.address_for(label)?;
+        Some(ClasslessMatch::Alias {
+            target: format!("{}.{}", label, self.zone),
+            address,
+        })
+    }
+}
+
+/// The address a lowercase `d.c.b.a.in-addr.arpa.` name stands for.
+pub fn parse_in_addr_arpa(lookup_name: &str) -> Option<Ipv4Addr> {
+    let labels = lookup_name.strip_suffix(".in-addr.arpa.")?;
+    let mut octets = [0u8; 4];
+    let mut count = 0;
+    for label in labels.split('.') {
+        // Leading zeros aren't part of the canonical form
+        if count == 4 || (label.len() > 1 && label.starts_with('0')) {
+            return None;
+        }
+        octets[3 - count] = label.parse().ok()?;
+        count += 1;
+    }
+    (count == 4).then(|| Ipv4Addr::from(octets))
+}
+
+/// Returns `label` when `name` is exactly `label.` followed by `zone`.
+fn strip_single_label<'a>(name: &'a str, zone: &str) -> Option<&'a str> {
+    let label = name.strip_suffix(zone)?.strip_suffix('.')?;
+    (!label.is_empty() && !label.contains('.')).then_some(label)
+}
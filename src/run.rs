@@ -0,0 +1,862 @@
+//! Running the server as the `localdns` binary does, for embedding it or
+//! starting it from a test.
+
+use anyhow::Context;
+use crate::{config, diff, events, http, json_api, listeners, loader, mdns, metrics, overlay, server, snapshot, soa, stats, tcp, view, watch, watchdog};
+use std::io::Write;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use tokio::sync::RwLock;
+use tokio::time::{sleep, Duration};
+
+/// Answer settings listeners copy from `ResolveOptions` when they start, so a
+/// SIGHUP leaves them as they were.
+const RESTART_ONLY_ANSWER_SETTINGS: &str = "fallback_ip, classless_reverse, ptr_names, [query_log], anonymize_clients, \
+     [connectivity_checks], nsid, edns_udp_payload, multiple_questions, negative_response, wildcard_merge, full_any_answers, \
+     blocklist_mode, blocklist_sinkhole, expose_version, rotate_answers, max_answers, [limits] max_alias_chain, rate_limit_qps, rate_limit_burst, \
+     rate_limit_action, max_inflight_queries, mdns, cache_max_entries, upstream_timeout_ms and rebind_guard";
+
+/// A server started by `run_server`.
+pub struct RunningServer {
+    /// Where the first listen address was bound, with the port the system
+    /// picked when `listen_port` is 0
+    pub local_addr: SocketAddr,
+    task: tokio::task::JoinHandle<anyhow::Result<()>>,
+}
+
+impl RunningServer {
+    /// Serves until the UDP listener fails or the watchdog gives up on it.
+    pub async fn wait(self) -> anyhow::Result<()> {
+        self.task.await.context("the UDP listener panicked")?
+    }
+}
+
+/// Loads the records and binds every listener `config` sets up, returning
+/// once they are bound. Reloads, the watchdog and the listeners run on
+/// spawned tasks from then on.
+pub async fn run_server(config_path: &Path, config: config::Config) -> anyhow::Result<RunningServer> {
+    let started_at = listeners::now();
+    // 2. Load DNS Records (Initial)
+    if config.startup_wait_for_sources > 0 {
+        wait_for_sources(&config).await;
+    }
+
+    tracing::info!("Loading DNS records...");
+    let mut load_options = config.load_options(config_path);
+    if let Some(journal) = &config.runtime_journal {
+        load_options.overlay = Arc::new(overlay::Overlay::open(journal, config.runtime_lifetime));
+    }
+    // Shared by the control socket and the HTTP API
+    let overlay = load_options.overlay.clone();
+    // Reads may sleep between retries, so keep them off the async workers
+    let initial_options = load_options.clone();
+    let loaded = tokio::task::spawn_blocking(move || loader::load_records(&initial_options))
+        .await
+        .context("initial load task panicked")?;
+    let mut initial_records = match (loaded, config.startup_policy) {
+        (Ok(cache), _) => cache,
+        (Err(e), config::StartupPolicy::Strict) => {
+            return Err(anyhow::Error::new(e).context("limits_policy is \"fail\" and the records exceed a limit"));
+        }
+        (Err(e), config::StartupPolicy::Degraded) => {
+            tracing::warn!("Starting degraded with no records: {}", e);
+            // Better to start from the last good records than from none
+            restore_snapshot(&config, loader::DnsCache::default())
+        }
+    };
+
+    if !initial_records.failures.is_empty() {
+        match config.startup_policy {
+            config::StartupPolicy::Strict => {
+                let error = initial_records.failures.remove(0);
+                return Err(anyhow::Error::new(error)
+                    .context("startup_policy is \"strict\" and a source failed to load"));
+            }
+            config::StartupPolicy::Degraded => {
+                tracing::warn!(
+                    "Starting degraded: {} source(s) failed to load and will be retried by the watcher.",
+                    initial_records.failures.len()
+                );
+                initial_records = restore_snapshot(&config, initial_records);
+            }
+        }
+    } else if initial_records.snapshot_written.is_none() {
+        save_snapshot(config.snapshot_path.as_deref(), &initial_records);
+    }
+    
+    let mut serials = soa::SerialTracker::new(config.soa.serial_scheme, config.soa.serial_file.clone());
+    initial_records.serial = serials.update(&initial_records);
+
+    tracing::info!("Loaded {} exact domains and {} wildcard patterns ({} conflicting names).", initial_records.exact_matches.len(), initial_records.wildcards.len(), initial_records.conflicts.len());
+
+    let records = Arc::new(RwLock::new(initial_records));
+    let last_reload: Arc<Mutex<Option<diff::RecordDiff>>> = Arc::default();
+    let events = Arc::new(if config.embedded.enabled {
+        events::Events::with_buffer(events::EMBEDDED_BUFFER)
+    } else {
+        events::Events::default()
+    });
+    let options = server::ResolveOptions::from_config(&config);
+    options.stats.set_records(&*records.read().await);
+    let active_listeners = Arc::new(listeners::Listeners::new(records.clone(), options.clone(), &config));
+
+    if let Some(lifetime) = overlay.lifetime() {
+        let (overlay, records, events) = (overlay.clone(), records.clone(), events.clone());
+        tokio::spawn(async move {
+            let mut every = tokio::time::interval(Duration::from_secs(lifetime.min(60)));
+            loop {
+                every.tick().await;
+                let expired = overlay.expire();
+                if !expired.is_empty() {
+                    tracing::info!("Runtime records not refreshed within runtime_lifetime expired");
+                    records.write().await.apply_overlay(&overlay);
+                    events.publish_expired(&expired);
+                }
+            }
+        });
+    }
+
+    // Start file watcher task
+    let records_clone = records.clone();
+    let (dhcp_path, hosts_path) = (config.dhcp_lease_file.clone(), config.hosts_file.clone());
+    let (hosts_paths, hosts_dir) = (config.hosts_files.clone(), config.hosts_dir.clone());
+    let zone_paths = config.zone_files.clone();
+    // Writes in WAL mode only touch the -wal file until a checkpoint
+    let reservations_path = config.dhcp_reservations_file.clone();
+    let db_paths: Vec<PathBuf> = config.records_db.iter().flat_map(|db| [db.clone(), wal_path(db)]).collect();
+    let db_poll = (config.records_db.is_some() && config.records_db_poll > 0).then(|| Duration::from_secs(config.records_db_poll));
+    let snapshot_path = config.snapshot_path.clone();
+    let last_reload_clone = last_reload.clone();
+    let events_clone = events.clone();
+    let reload_stats = options.stats.clone();
+    let watch_interval = Duration::from_secs(config.watch_interval);
+    let watch_mode = config.watch_mode;
+    let reload_config_path = config_path.to_path_buf();
+    let (hangup_tx, mut hangups) = tokio::sync::mpsc::unbounded_channel();
+    #[cfg(unix)]
+    tokio::spawn(forward_hangups(hangup_tx));
+    #[cfg(unix)]
+    tokio::spawn(dump_on_signal(records.clone(), config.dump_path.clone()));
+    #[cfg(not(unix))]
+    drop(hangup_tx);
+    let (control_tx, mut control_reloads) = tokio::sync::mpsc::unbounded_channel::<tokio::sync::oneshot::Sender<()>>();
+
+    tokio::spawn(async move {
+        let mut load_options = load_options;
+        let mut settings: Option<Arc<server::ReloadedSettings>> = None;
+        // Everything watched, with how it's named in the log
+        let file = |path: PathBuf, what| (watch::Target::File(path), what);
+        let mut watched: Vec<(watch::Target, &str)> = vec![file(dhcp_path, "DHCP file"), file(hosts_path, "Hosts file")];
+        watched.extend(hosts_paths.into_iter().map(|path| file(path, "Hosts file")));
+        watched.extend(hosts_dir.map(|path| (watch::Target::Dir { path, extension: ".hosts".to_string() }, "Hosts directory")));
+        watched.extend(zone_paths.into_iter().map(|path| file(path, "Zone file")));
+        watched.extend(reservations_path.map(|path| file(path, "DHCP reservations")));
+        watched.extend(db_paths.into_iter().map(|path| file(path, "Records database")));
+        // Files the hosts files include come last, as they change with the hosts files
+        let configured = watched.len();
+        let mut included = records_clone.read().await.included.clone();
+        watched.extend(included.iter().cloned().map(|path| file(path, "Included hosts file")));
+        let mut changes = watch::watch_targets(watched.iter().map(|(target, _)| target.clone()).collect(), watch_mode, watch_interval);
+        let mut retry = tokio::time::interval(watch_interval);
+        retry.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        retry.tick().await;
+        let mut last_db_poll = tokio::time::Instant::now();
+        let failure_messages = |cache: &loader::DnsCache| -> Vec<String> {
+            cache.failures.iter().map(|e| e.to_string()).collect()
+        };
+        let mut last_failures = failure_messages(&*records_clone.read().await);
+        let mut reloaded_expiry = None;
+
+        loop {
+            // A control command waiting for this reload; dropped unless the records are replaced
+            let mut control_ack = None;
+            // Reloading once the first lease runs out drops it, even if the file hasn't changed
+            // and only once per expiry, should the reload not replace the records
+            let next_expiry = records_clone.read().await.next_expiry.filter(|&expires| Some(expires) != reloaded_expiry);
+            let lease_expiry = async {
+                match next_expiry {
+                    Some(expires) => sleep(Duration::from_secs(expires.saturating_sub(listeners::now()))).await,
+                    None => std::future::pending().await,
+                }
+            };
+            let reload_needed = tokio::select! {
+                Some(changed) = changes.recv() => {
+                    let mut described: Vec<&str> = Vec::new();
+                    for path in &changed {
+                        if let Some((_, what)) = watched.iter().find(|(target, _)| target.path() == path) {
+                            if !described.contains(what) {
+                                tracing::info!("{} changed. Reloading...", what);
+                                described.push(what);
+                            }
+                        }
+                    }
+                    true
+                }
+                Some(()) = hangups.recv() => {
+                    tracing::info!("SIGHUP received. Re-reading the config and reloading...");
+                    match config::Config::load(&reload_config_path) {
+                        Ok(config) => {
+                            // Only what can change without rebinding or rewatching anything
+                            let reloaded = config.load_options(&reload_config_path);
+                            load_options.suffix = reloaded.suffix;
+                            load_options.ttl = reloaded.ttl;
+                            load_options.records = reloaded.records;
+                            load_options.cnames = reloaded.cnames;
+                            load_options.addresses = reloaded.addresses;
+                            settings = Some(Arc::new(server::ReloadedSettings::from_config(&config)));
+                            tracing::info!(
+                                "Applied ttl, domain_suffix, [[records]], [cnames], addresses, upstream and [forward_zones]. These keep their startup values until a restart: {}",
+                                RESTART_ONLY_ANSWER_SETTINGS
+                            );
+                        }
+                        Err(e) => tracing::warn!("Config file failed to load ({}), reloading records with the current settings.", e),
+                    }
+                    true
+                }
+                _ = lease_expiry => {
+                    tracing::info!("A DHCP lease expired. Reloading...");
+                    reloaded_expiry = next_expiry;
+                    true
+                }
+                Some(ack) = control_reloads.recv() => {
+                    tracing::info!("Reload requested over the control socket. Reloading...");
+                    control_ack = Some(ack);
+                    true
+                }
+                _ = retry.tick() => {
+                    // Sources that couldn't be read are retried on every tick until they load;
+                    // missing or unparsable files are picked up by the watcher once fixed
+                    let mut reload_needed = records_clone.read().await.failures.iter().any(|f| f.is_read_error());
+                    if db_poll.is_some_and(|poll| last_db_poll.elapsed() >= poll) {
+                        last_db_poll = tokio::time::Instant::now();
+                        reload_needed = true;
+                    }
+                    reload_needed
+                }
+            };
+
+            if reload_needed {
+                let (load_options, snapshot_path) = (load_options.clone(), snapshot_path.clone());
+                // Reads may sleep between retries, so keep them off the async workers
+                let loaded = tokio::task::spawn_blocking(move || {
+                    let loaded = loader::load_records(&load_options);
+                    if let Ok(cache) = &loaded {
+                        if cache.failures.is_empty() {
+                            save_snapshot(snapshot_path.as_deref(), cache);
+                        }
+                    }
+                    loaded
+                })
+                .await
+                .expect("reload task panicked");
+                let mut new_cache = match loaded {
+                    Ok(cache) => cache,
+                    Err(e) => {
+                        tracing::error!("Failed to reload records ({}), keeping the previous set.", e);
+                        reload_stats.reload_failures.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+                        continue;
+                    }
+                };
+                new_cache.settings = settings.clone();
+                // A hosts file that failed to load reports none of its includes, so those stay watched
+                let mut found = new_cache.included.clone();
+                if new_cache.failures.iter().any(|f| f.kind() == loader::SourceKind::Hosts) {
+                    found.extend(included.iter().filter(|path| !new_cache.included.contains(path)).cloned());
+                }
+                if found != included {
+                    included = found;
+                    watched.truncate(configured);
+                    watched.extend(included.iter().cloned().map(|path| file(path, "Included hosts file")));
+                    changes.shutdown();
+                    changes = watch::watch_targets(watched.iter().map(|(target, _)| target.clone()).collect(), watch_mode, watch_interval);
+                }
+                let failures = failure_messages(&new_cache);
+                if failures != last_failures {
+                    events_clone.publish(events::Event::Sources { failures: failures.clone() });
+                    last_failures = failures;
+                }
+
+                let exact_count = new_cache.exact_matches.len();
+                let wildcard_count = new_cache.wildcards.len();
+                let conflict_count = new_cache.conflicts.len();
+                let mut writer = records_clone.write().await;
+                // Never trade a working source for a failed one; keep serving the old records instead
+                if new_cache.can_replace(&writer) {
+                    new_cache.serial = serials.update(&new_cache);
+                    let old_cache = std::mem::replace(&mut *writer, new_cache);
+                    // Queries are answered again from here on; the diff only needs to read
+                    let current = writer.downgrade();
+                    reload_stats.reloads.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+                    reload_stats.set_records(&current);
+                    tracing::info!("Reloaded records. Now serving {} exact domains and {} wildcard patterns ({} conflicting names).", exact_count, wildcard_count, conflict_count);
+                    let reload_diff = diff::RecordDiff::between(&old_cache, &current);
+                    events_clone.publish_reload(&current, &reload_diff);
+                    drop(current);
+                    reload_diff.log(diff::LOG_LIMIT);
+                    *last_reload_clone.lock().unwrap() = Some(reload_diff);
+                    if let Some(ack) = control_ack.take() {
+                        let _ = ack.send(());
+                    }
+                } else if writer.snapshot_written.is_some() {
+                    drop(writer);
+                    tracing::warn!("Sources still failing, keeping the records from the snapshot.");
+                    reload_stats.reload_failures.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+                } else {
+                    drop(writer);
+                    tracing::error!("Failed to reload records, keeping the previous set.");
+                    reload_stats.reload_failures.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+                }
+            }
+        }
+    });
+
+    // 3. Bind a UDP socket per listen address; the first is the one the watchdog probes
+    let mut sockets = Vec::new();
+    // As bound, so the TCP listeners and rebinds reuse a port the system picked
+    let mut addrs: Vec<String> = Vec::new();
+    let workers = config.workers;
+    for addr in config.listen_addrs().iter().map(|addr| addr.to_string()) {
+        let bound = bind_with_retry(&addr, &config.bind_retry, || server::bind_workers(&addr, config.dscp, config.listen_interface.as_deref(), workers)).await?;
+        let addr = bound[0].local_addr()?.to_string();
+        match bound.len() {
+            _ if workers == 1 => tracing::info!("DNS Server listening on {}", addr),
+            1 => tracing::info!("DNS Server listening on {} ({} workers sharing one socket, no SO_REUSEPORT)", addr, workers),
+            n => tracing::info!("DNS Server listening on {} ({} workers, SO_REUSEPORT)", addr, n),
+        }
+        sockets.push(bound);
+        addrs.push(addr);
+    }
+    let mut sockets = sockets.into_iter();
+    let socket = sockets.next().expect("validated to have at least one listen address");
+    let addr = addrs[0].clone();
+    let extra_udp: Vec<(String, Vec<tokio::net::UdpSocket>)> = addrs[1..].iter().cloned().zip(sockets).collect();
+    let default_listener = |transports| listeners::ListenerStatus {
+        name: view::DEFAULT_LISTENER.to_string(),
+        address: addrs.join(", "),
+        transports,
+        view: None,
+        bound_at: listeners::now(),
+    };
+
+    let stats = options.stats.clone();
+    let health = Arc::new(watchdog::Health::new(&config.watchdog));
+
+    let mut tcp_listeners = Vec::new();
+    if config.tcp.enabled {
+        for addr in &addrs {
+            let listener = bind_with_retry(addr, &config.bind_retry, || tcp::bind(addr, config.dscp, config.listen_interface.as_deref())).await?;
+            tracing::info!("DNS Server listening on {} (TCP)", addr);
+            tcp_listeners.push((addr.clone(), listener));
+        }
+    }
+    let (moves, mut move_requests) = tokio::sync::mpsc::channel(1);
+    let mut default = DefaultListener {
+        configured: config.listen_addrs(),
+        bound: addrs.clone(),
+        running: Vec::new(),
+        moves,
+        records: records.clone(),
+        options: options.clone(),
+        workers,
+        dscp: config.dscp,
+        interface: config.listen_interface.clone(),
+        tcp: config.tcp.clone(),
+        embedded: config.embedded.clone(),
+    };
+    default.serve(extra_udp, tcp_listeners);
+
+    if let Some(http_addr) = &config.http.listen {
+        let listener = bind_with_retry(http_addr, &config.bind_retry, || http::bind(http_addr)).await?;
+        tracing::info!("JSON API listening on http://{}/resolve", http_addr);
+        let api = json_api::JsonApi {
+            records: records.clone(),
+            options: Arc::new(options.clone()),
+            cors_allow_origin: config.http.cors_allow_origin.clone(),
+            limits: config.limits.clone(),
+            limits_policy: config.limits_policy,
+            health: health.clone(),
+            last_reload: last_reload.clone(),
+            events: events.clone(),
+            listeners: active_listeners.clone(),
+            overlay: overlay.clone(),
+            api_token: config.http.api_token.clone(),
+            started_at,
+        };
+        tokio::spawn(async move {
+            let handler = move |request| {
+                let api = api.clone();
+                async move { api.handle(request).await }
+            };
+            if let Err(e) = http::serve(listener, handler).await {
+                tracing::error!("HTTP listener stopped: {}", e);
+            }
+        });
+    }
+
+    active_listeners.register(
+        default_listener(if config.tcp.enabled { vec!["udp", "tcp"] } else { vec!["udp"] }),
+        stats.clone(),
+    );
+
+    // Extra listeners, each answering through its own view
+    for listener in &config.listeners {
+        let view = listener.view.as_ref().map(|v| config.views[v].clone()).unwrap_or_default();
+        let address = &listener.address;
+        let socket = bind_with_retry(address, &config.bind_retry, || server::bind(address, config.dscp, config.listen_interface.as_deref())).await?;
+        let tcp = if config.tcp.enabled {
+            Some(bind_with_retry(address, &config.bind_retry, || tcp::bind(address, config.dscp, config.listen_interface.as_deref())).await?)
+        } else {
+            None
+        };
+        active_listeners.launch(listener, &view, socket, tcp);
+    }
+    tokio::spawn(watch_listeners(config_path.to_path_buf(), active_listeners.clone(), default, watch_mode, watch_interval));
+
+    #[cfg(unix)]
+    if let Some(path) = &config.listen_unix {
+        let view = config.unix.view.as_ref().map(|v| config.views[v].clone()).unwrap_or_default();
+        let options = view.apply(view::UNIX_LISTENER, &options);
+        let socket = crate::unix::bind(path, &config.unix)?;
+        tracing::info!("DNS Server listening on {:?} (unix)", path);
+        let status = listeners::ListenerStatus {
+            name: view::UNIX_LISTENER.to_string(),
+            address: path.display().to_string(),
+            transports: vec!["unix"],
+            view: config.unix.view.clone(),
+            bound_at: listeners::now(),
+        };
+        active_listeners.register(status, options.stats.clone());
+        let records = records.clone();
+        tokio::spawn(async move {
+            if let Err(e) = crate::unix::serve(socket, records, options).await {
+                tracing::error!("Unix listener stopped: {}", e);
+            }
+        });
+    }
+
+    #[cfg(unix)]
+    if let Some(path) = &config.control_socket {
+        let listener = crate::control::bind(path)?;
+        tracing::info!("Control socket listening on {:?}", path);
+        let control = Arc::new(crate::control::Control {
+            overlay: overlay.clone(),
+            records: records.clone(),
+            reloads: control_tx.clone(),
+            hosts_path: config.hosts_file.clone(),
+            blocks: options.blocklist.blocks.clone(),
+            events: events.clone(),
+        });
+        tokio::spawn(async move {
+            if let Err(e) = crate::control::serve(listener, control).await {
+                tracing::error!("Control socket stopped: {}", e);
+            }
+        });
+    }
+    #[cfg(not(unix))]
+    let _ = (overlay, &control_tx);
+
+    if config.mdns {
+        let addr = std::net::SocketAddr::from((std::net::Ipv4Addr::UNSPECIFIED, mdns::MDNS_GROUP.port()));
+        let socket = mdns::bind(addr)?;
+        tracing::info!("Answering mDNS queries for .local names on {}", mdns::MDNS_GROUP);
+        let records = records.clone();
+        tokio::spawn(async move {
+            if let Err(e) = mdns::serve(socket, records).await {
+                tracing::error!("mDNS responder stopped: {}", e);
+            }
+        });
+    }
+
+    if let Some(metrics_addr) = &config.metrics_listen {
+        let listener = bind_with_retry(metrics_addr, &config.bind_retry, || http::bind(metrics_addr)).await?;
+        tracing::info!("Metrics listening on http://{}/metrics", metrics_addr);
+        let active_listeners = active_listeners.clone();
+        let blocks = options.blocklist.blocks.clone();
+        tokio::spawn(async move {
+            let handler = move |request| {
+                let stats = active_listeners.stats();
+                let blocks = blocks.clone();
+                async move { metrics::handle(&request, &stats, &blocks) }
+            };
+            if let Err(e) = http::serve(listener, handler).await {
+                tracing::error!("Metrics listener stopped: {}", e);
+            }
+        });
+    }
+
+    if config.summary_interval > 0 {
+        let every = Duration::from_secs(config.summary_interval);
+        let active_listeners = active_listeners.clone();
+        tokio::spawn(stats::summarize(move || active_listeners.stats(), records.clone(), every));
+    }
+
+    // 4. Server Loop, rebinding the socket whenever the watchdog finds it deaf
+    let (recover_tx, mut recover_rx) = tokio::sync::mpsc::channel(1);
+    let local_addr = socket[0].local_addr()?;
+    // Follows the main socket when the default listener moves
+    let (target_tx, target) = tokio::sync::watch::channel(watchdog::probe_target(local_addr));
+    if config.watchdog.enabled && config.listen_interface.is_some() && local_addr.ip().is_unspecified() {
+        // Its probes would go to loopback, which a socket bound to another interface never hears
+        tracing::warn!("Watchdog off: listen_interface is set and {} is a wildcard address", local_addr);
+    } else if config.watchdog.enabled {
+        tokio::spawn(watchdog::run(target, config.watchdog.clone(), health.clone(), recover_tx));
+    }
+    let task = tokio::spawn(async move {
+        let (mut socket, mut addr) = (socket, addr);
+        loop {
+            let (stop, stopped) = tokio::sync::oneshot::channel::<()>();
+            let next = {
+                let stopped = async {
+                    let _ = stopped.await;
+                };
+                let serving = listeners::serve_udp_workers(socket, workers, records.clone(), options.clone(), &config.embedded, stopped);
+                tokio::pin!(serving);
+                tokio::select! {
+                    result = &mut serving => return result.map_err(anyhow::Error::from),
+                    Some(recovery) = recover_rx.recv() => Err(recovery),
+                    Some(request) = move_requests.recv() => {
+                        // Answer what was already received before letting go of the address
+                        let _ = stop.send(());
+                        if let Err(e) = serving.await {
+                            tracing::warn!("UDP listener on {} stopped with an error while moving: {}", addr, e);
+                        }
+                        Ok(request)
+                    }
+                }
+            };
+            let recovery = match next {
+                Ok(MoveRequest { addr: to, done }) => {
+                    match server::bind_workers(&to, config.dscp, config.listen_interface.as_deref(), workers).await {
+                        Ok(bound) => {
+                            let local_addr = bound[0].local_addr()?;
+                            (socket, addr) = (bound, local_addr.to_string());
+                            tracing::info!("DNS Server listening on {} (moved)", addr);
+                            target_tx.send_replace(watchdog::probe_target(local_addr));
+                            let _ = done.send(Ok(addr.clone()));
+                        }
+                        Err(e) => {
+                            socket = bind_with_retry(&addr, &config.bind_retry, || server::bind_workers(&addr, config.dscp, config.listen_interface.as_deref(), workers))
+                                .await
+                                .context("failed to bind the UDP socket again after a failed move")?;
+                            let _ = done.send(Err(e.to_string()));
+                        }
+                    }
+                    continue;
+                }
+                Err(recovery) => recovery,
+            };
+            if recovery == watchdog::Recovery::Exit {
+                anyhow::bail!("Watchdog: {} stopped answering queries", addr);
+            }
+            health.rebinds.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+            socket = bind_with_retry(&addr, &config.bind_retry, || server::bind_workers(&addr, config.dscp, config.listen_interface.as_deref(), workers))
+                .await
+                .context("Watchdog: failed to rebind the UDP socket")?;
+            tracing::info!("DNS Server listening on {} (rebound)", addr);
+            active_listeners.rebound(view::DEFAULT_LISTENER);
+        }
+    });
+    Ok(RunningServer { local_addr, task })
+}
+
+/// Applies changes to `listen_address`, `listen_port`, `[[listeners]]`,
+/// `[views]` and `[ddr]` in the config file while running. Other settings
+/// still need a restart.
+async fn watch_listeners(config_path: PathBuf, active: Arc<listeners::Listeners>, mut default: DefaultListener, mode: watch::WatchMode, every: Duration) {
+    let mut changes = watch::watch(vec![config_path.clone()], mode, every);
+    while changes.recv().await.is_some() {
+        let config = match config::Config::load(&config_path) {
+            Ok(config) => config,
+            Err(e) => {
+                tracing::warn!("Config file changed but failed to load ({}), keeping the current listeners.", e);
+                continue;
+            }
+        };
+        tracing::info!("Config file changed. Applying listen address, listener and DDR changes; send SIGHUP for TTLs and the suffix, other settings take effect after a restart.");
+        active.set_ddr(config.ddr());
+        default.apply(config.listen_addrs(), &active).await;
+        let failed = active.apply(&config.listeners, &config.views).await;
+        if !failed.is_empty() {
+            tracing::warn!("{} listener(s) failed to start and will be retried when the config changes again.", failed.len());
+        }
+    }
+}
+
+/// Asks the main receive loop to move to `addr`. It answers with the address
+/// it bound, or with why it stayed where it was.
+struct MoveRequest {
+    addr: String,
+    done: tokio::sync::oneshot::Sender<Result<String, String>>,
+}
+
+/// The default listener's sockets besides the main one, which the watchdog
+/// loop owns: UDP on the other listen addresses and TCP on all of them. When
+/// `listen_address` or `listen_port` change, they are stopped, the main loop
+/// moved, and then they are bound again.
+struct DefaultListener {
+    /// As configured, so a port the system picked isn't taken for a change
+    configured: Vec<SocketAddr>,
+    /// As bound; the first is the main loop's
+    bound: Vec<String>,
+    running: Vec<(tokio::sync::oneshot::Sender<()>, tokio::task::JoinHandle<()>)>,
+    moves: tokio::sync::mpsc::Sender<MoveRequest>,
+    records: Arc<RwLock<loader::DnsCache>>,
+    options: server::ResolveOptions,
+    workers: usize,
+    dscp: Option<crate::dscp::Dscp>,
+    interface: Option<String>,
+    tcp: config::TcpConfig,
+    embedded: config::EmbeddedConfig,
+}
+
+impl DefaultListener {
+    /// Serves each of `udp` and `tcp` until `stop`.
+    fn serve(&mut self, udp: Vec<(String, Vec<tokio::net::UdpSocket>)>, tcp: Vec<(String, tokio::net::TcpListener)>) {
+        for (addr, socket) in udp {
+            let (stop, stopped) = tokio::sync::oneshot::channel::<()>();
+            let (records, options, embedded, workers) = (self.records.clone(), self.options.clone(), self.embedded.clone(), self.workers);
+            let task = tokio::spawn(async move {
+                let stopped = async {
+                    let _ = stopped.await;
+                };
+                if let Err(e) = listeners::serve_udp_workers(socket, workers, records, options, &embedded, stopped).await {
+                    tracing::error!("UDP listener on {} stopped: {}", addr, e);
+                }
+            });
+            self.running.push((stop, task));
+        }
+        for (addr, listener) in tcp {
+            let (stop, stopped) = tokio::sync::oneshot::channel::<()>();
+            let (records, options, tcp_config) = (self.records.clone(), Arc::new(self.options.clone()), self.tcp.clone());
+            let task = tokio::spawn(async move {
+                let stats = options.stats.clone();
+                // Open connections keep being served until they go idle
+                tokio::select! {
+                    result = tcp::serve(listener, records, options, tcp_config, stats) => {
+                        if let Err(e) = result {
+                            tracing::error!("TCP listener on {} stopped: {}", addr, e);
+                        }
+                    }
+                    _ = stopped => {}
+                }
+            });
+            self.running.push((stop, task));
+        }
+    }
+
+    /// Stops what `serve` started, once the queries already received are answered.
+    async fn stop(&mut self) {
+        let (stops, tasks): (Vec<_>, Vec<_>) = self.running.drain(..).unzip();
+        for stop in stops {
+            let _ = stop.send(());
+        }
+        for task in tasks {
+            let _ = task.await;
+        }
+    }
+
+    /// Binds `rest` over UDP, then TCP on `first` and `rest` as bound, so a
+    /// port the system picked is shared. Addresses that fail are logged and
+    /// left out.
+    async fn bind(&self, first: String, rest: &[String]) -> (Vec<String>, Vec<(String, Vec<tokio::net::UdpSocket>)>, Vec<(String, tokio::net::TcpListener)>) {
+        let (mut bound, mut udp, mut tcp) = (vec![first], Vec::new(), Vec::new());
+        for addr in rest {
+            let socket = server::bind_workers(addr, self.dscp, self.interface.as_deref(), self.workers).await;
+            match socket.and_then(|socket| Ok((socket[0].local_addr()?.to_string(), socket))) {
+                Ok((addr, socket)) => {
+                    bound.push(addr.clone());
+                    udp.push((addr, socket));
+                }
+                Err(e) => tracing::error!("Failed to bind {}: {}", addr, e),
+            }
+        }
+        if self.tcp.enabled {
+            for addr in &bound {
+                match tcp::bind(addr, self.dscp, self.interface.as_deref()).await {
+                    Ok(listener) => tcp.push((addr.clone(), listener)),
+                    Err(e) => tracing::error!("Failed to bind {} (TCP): {}", addr, e),
+                }
+            }
+        }
+        (bound, udp, tcp)
+    }
+
+    /// Moves to `desired` when it differs from the addresses configured so
+    /// far. Should the main loop fail to bind the first, everything goes back
+    /// to the old addresses and the move is tried again on the next change.
+    async fn apply(&mut self, desired: Vec<SocketAddr>, active: &listeners::Listeners) {
+        if desired == self.configured {
+            return;
+        }
+        let addrs: Vec<String> = desired.iter().map(|addr| addr.to_string()).collect();
+        tracing::info!("Moving the default listener from {} to {}", self.bound.join(", "), addrs.join(", "));
+        self.stop().await;
+        let (done, moved) = tokio::sync::oneshot::channel();
+        if self.moves.send(MoveRequest { addr: addrs[0].clone(), done }).await.is_err() {
+            // The main loop has stopped, and the server with it
+            return;
+        }
+        let (first, rest) = match moved.await {
+            Ok(Ok(first)) => {
+                self.configured = desired;
+                (first, addrs[1..].to_vec())
+            }
+            Ok(Err(e)) => {
+                tracing::error!("Failed to move the default listener to {}, staying on {}: {}", addrs[0], self.bound.join(", "), e);
+                (self.bound[0].clone(), self.bound[1..].to_vec())
+            }
+            Err(_) => return,
+        };
+        let (bound, udp, tcp) = self.bind(first, &rest).await;
+        self.serve(udp, tcp);
+        active.moved(view::DEFAULT_LISTENER, bound.join(", "));
+        self.bound = bound;
+    }
+}
+
+/// Sends on `reload` for every SIGHUP, which the reload task answers like a
+/// file change, re-reading the config first.
+#[cfg(unix)]
+async fn forward_hangups(reload: tokio::sync::mpsc::UnboundedSender<()>) {
+    let mut hangup = match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::hangup()) {
+        Ok(signal) => signal,
+        Err(e) => {
+            tracing::warn!("Can't handle SIGHUP ({}), reloads happen on file changes only", e);
+            return;
+        }
+    };
+    while hangup.recv().await.is_some() {
+        if reload.send(()).is_err() {
+            return;
+        }
+    }
+}
+
+/// Writes the served records to `path`, or stdout, on every SIGUSR1. They are
+/// rendered under the read lock, from memory, so they show what is answered
+/// even when the files say otherwise.
+#[cfg(unix)]
+async fn dump_on_signal(records: Arc<RwLock<loader::DnsCache>>, path: Option<PathBuf>) {
+    let mut usr1 = match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::user_defined1()) {
+        Ok(signal) => signal,
+        Err(e) => {
+            tracing::warn!("Can't handle SIGUSR1 ({}), dumps are only available over the control socket", e);
+            return;
+        }
+    };
+    while usr1.recv().await.is_some() {
+        let dump = crate::dump::render(&*records.read().await, crate::dump::DumpFormat::Text);
+        let written = match &path {
+            // Through a temporary file, so a reader never sees half a dump
+            Some(path) => {
+                let temporary = path.with_extension("tmp");
+                std::fs::write(&temporary, &dump).and_then(|()| std::fs::rename(&temporary, path))
+            }
+            None => std::io::stdout().lock().write_all(dump.as_bytes()),
+        };
+        match (written, &path) {
+            (Ok(()), Some(path)) => tracing::info!("SIGUSR1 received. Dumped the records to {:?}", path),
+            (Ok(()), None) => tracing::info!("SIGUSR1 received. Dumped the records to stdout"),
+            (Err(e), _) => tracing::warn!("Failed to dump the records: {}", e),
+        }
+    }
+}
+
+/// Waits up to `startup_wait_for_sources` seconds for every configured source
+/// to become readable: the lease and hosts files, the hosts directory, zone
+/// files, reservations and the records database.
+async fn wait_for_sources(config: &config::Config) {
+    let deadline = tokio::time::Instant::now() + Duration::from_secs(config.startup_wait_for_sources);
+    let mut sources = vec![&config.dhcp_lease_file, &config.hosts_file];
+    sources.extend(&config.hosts_files);
+    sources.extend(&config.hosts_dir);
+    sources.extend(&config.zone_files);
+    sources.extend(&config.dhcp_reservations_file);
+    sources.extend(&config.records_db);
+    loop {
+        let unreadable: Vec<&PathBuf> = sources
+            .iter()
+            .copied()
+            .filter(|path| std::fs::File::open(path).is_err())
+            .collect();
+        if unreadable.is_empty() {
+            return;
+        }
+        if tokio::time::Instant::now() >= deadline {
+            tracing::warn!("Sources still unreadable after {}s: {:?}", config.startup_wait_for_sources, unreadable);
+            return;
+        }
+        sleep(Duration::from_millis(500)).await;
+    }
+}
+
+/// Retries `bind` while the address is in use or not yet assigned, as happens
+/// when an old instance or another resolver is still releasing it during boot.
+async fn bind_with_retry<T, F, Fut>(addr: &str, retry: &config::BindRetryConfig, bind: F) -> anyhow::Result<T>
+where
+    F: Fn() -> Fut,
+    Fut: std::future::Future<Output = Result<T, server::ServeError>>,
+{
+    let mut attempt = 1;
+    loop {
+        let error = match bind().await {
+            Ok(bound) => return Ok(bound),
+            Err(e) => e,
+        };
+        let kind = match &error {
+            server::ServeError::Bind { source, .. } => source.kind(),
+            _ => return Err(error.into()),
+        };
+        let transient = matches!(kind, std::io::ErrorKind::AddrInUse | std::io::ErrorKind::AddrNotAvailable);
+        if !transient || attempt >= retry.attempts {
+            let hint = if transient {
+                format!("Could not bind {} after {} attempt(s); check whether another resolver (systemd-resolved, dnsmasq, a previous localdns) is using it", addr, attempt)
+            } else {
+                format!("Could not bind {}", addr)
+            };
+            return Err(anyhow::Error::new(error).context(hint));
+        }
+        tracing::warn!(
+            "{} (attempt {}/{}), retrying in {}s",
+            error, attempt, retry.attempts, retry.interval
+        );
+        attempt += 1;
+        sleep(Duration::from_secs(retry.interval)).await;
+    }
+}
+
+/// Swaps the partial `loaded` records for the snapshot, if one is configured and
+/// recent enough. The failures are kept so the watcher goes on retrying the sources.
+fn restore_snapshot(config: &config::Config, loaded: loader::DnsCache) -> loader::DnsCache {
+    let Some(path) = &config.snapshot_path else {
+        return loaded;
+    };
+    match snapshot::read(path, Duration::from_secs(config.max_snapshot_age)) {
+        Ok(mut cache) => {
+            let age = cache.snapshot_written.and_then(|t| t.elapsed().ok()).unwrap_or_default();
+            tracing::info!("Serving records from the snapshot written {}s ago until every source loads.", age.as_secs());
+            cache.failures = loaded.failures;
+            cache
+        }
+        Err(e) => {
+            tracing::warn!("{}", e);
+            loaded
+        }
+    }
+}
+
+/// The write-ahead log SQLite keeps next to `db` in WAL mode.
+fn wal_path(db: &Path) -> PathBuf {
+    let mut path = db.as_os_str().to_owned();
+    path.push("-wal");
+    PathBuf::from(path)
+}
+
+fn save_snapshot(path: Option<&Path>, cache: &loader::DnsCache) {
+    if let Some(path) = path {
+        if let Err(e) = snapshot::write(path, cache) {
+            tracing::warn!("{}", e);
+        }
+    }
+}
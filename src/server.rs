@@ -0,0 +1,1169 @@
+use std::future::{poll_fn, Future};
+use std::net::{IpAddr, Ipv4Addr};
+use std::net::SocketAddr;
+use std::cell::OnceCell;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::task::Poll;
+use tokio::net::UdpSocket;
+use tokio::sync::{mpsc, RwLock, Semaphore};
+use tokio::time::{Duration, Instant};
+use hickory_proto::error::ProtoError;
+use hickory_proto::op::{Message, MessageType, OpCode, Query, ResponseCode};
+use hickory_proto::rr::{DNSClass, LowerName, Name, RData, Record, RecordType};
+use hickory_proto::rr::rdata::opt::{EdnsCode, EdnsOption};
+use hickory_proto::rr::rdata::{A, AAAA, CNAME, HINFO, MX, PTR, SRV, TXT};
+use hickory_proto::serialize::binary::{BinEncodable, BinEncoder};
+use serde::Deserialize;
+use socket2::{Domain, Protocol, SockRef, Socket, Type};
+
+use crate::anonymize::Anonymizer;
+use crate::blocklist::Blocklist;
+use crate::buffers::{Buffer, BufferPool};
+use crate::connectivity::{CheckPolicy, ConnectivityChecks};
+use crate::ddr::{Ddr, DDR_NAME};
+use crate::dscp::Dscp;
+use crate::forward::{Forwarder, Upstreams};
+use crate::loader::{name_key, parse_key, parse_presentation, DnsCache, Entry, MxRecord, SrvRecord, TxtRecord};
+use crate::middleware::{Chain, QueryContext};
+use crate::querylog::QueryLog;
+use crate::ratelimit::{RateLimiter, Verdict};
+use crate::reverse::{parse_in_addr_arpa, ClasslessMatch, ClasslessZone, PtrNames};
+use crate::soa::Soa;
+use crate::stats::Stats;
+use crate::view::View;
+
+#[derive(Debug, thiserror::Error)]
+pub enum ServeError {
+    #[error("Failed to bind {addr}: {source}")]
+    Bind {
+        addr: String,
+        #[source]
+        source: std::io::Error,
+    },
+    #[error("I/O error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Failed to parse message: {0}")]
+    Proto(#[from] ProtoError),
+}
+
+/// Binds the UDP socket for `addr`. With `interface`, it only sees traffic
+/// through that network interface; see `bind_to_device`.
+pub async fn bind(addr: &str, dscp: Option<Dscp>, interface: Option<&str>) -> Result<UdpSocket, ServeError> {
+    let socket = match interface {
+        None => UdpSocket::bind(addr).await,
+        Some(interface) => bind_udp_to_device(addr, interface),
+    };
+    let socket = socket.map_err(|source| ServeError::Bind {
+        addr: addr.to_string(),
+        source,
+    })?;
+    if let Some(dscp) = dscp {
+        dscp.apply(&socket, socket.local_addr()?);
+    }
+    Ok(socket)
+}
+
+/// Builds the socket by hand, since the device has to be set before binding.
+fn bind_udp_to_device(addr: &str, interface: &str) -> std::io::Result<UdpSocket> {
+    bind_udp_socket(addr, Some(interface), false)
+}
+
+fn bind_udp_socket(addr: &str, interface: Option<&str>, reuse_port: bool) -> std::io::Result<UdpSocket> {
+    let addr: SocketAddr = addr
+        .parse()
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?;
+    let socket = Socket::new(Domain::for_address(addr), Type::DGRAM, Some(Protocol::UDP))?;
+    if let Some(interface) = interface {
+        bind_to_device(&socket, interface)?;
+    }
+    #[cfg(target_os = "linux")]
+    if reuse_port {
+        socket.set_reuse_port(true)?;
+    }
+    #[cfg(not(target_os = "linux"))]
+    let _ = reuse_port;
+    socket.set_nonblocking(true)?;
+    socket.bind(&addr.into())?;
+    UdpSocket::from_std(socket.into())
+}
+
+/// Whether `bind_workers` gives each worker a socket of its own. Linux
+/// spreads the datagrams for one address across SO_REUSEPORT sockets; other
+/// systems either lack the option or hand every datagram to one socket.
+pub const REUSE_PORT: bool = cfg!(target_os = "linux");
+
+/// Binds the UDP sockets `workers` receive loops read `addr` from: one per
+/// worker with SO_REUSEPORT where `REUSE_PORT` holds, otherwise a single one
+/// they share. The first socket decides the port, so a `0` port is picked
+/// once for all of them.
+pub async fn bind_workers(addr: &str, dscp: Option<Dscp>, interface: Option<&str>, workers: usize) -> Result<Vec<UdpSocket>, ServeError> {
+    if workers <= 1 || !REUSE_PORT {
+        return Ok(vec![bind(addr, dscp, interface).await?]);
+    }
+    let mut sockets: Vec<UdpSocket> = Vec::with_capacity(workers);
+    let mut bound = addr.to_string();
+    while sockets.len() < workers {
+        let socket = bind_udp_socket(&bound, interface, true).map_err(|source| ServeError::Bind {
+            addr: addr.to_string(),
+            source,
+        })?;
+        let local = socket.local_addr()?;
+        if let Some(dscp) = dscp {
+            dscp.apply(&socket, local);
+        }
+        bound = local.to_string();
+        sockets.push(socket);
+    }
+    Ok(sockets)
+}
+
+/// Restricts `socket` to traffic through `interface` with SO_BINDTODEVICE,
+/// which only Linux has. Call it before binding.
+pub fn bind_to_device<'a>(socket: impl Into<SockRef<'a>>, interface: &str) -> std::io::Result<()> {
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    {
+        let socket = socket.into();
+        socket
+            .bind_device(Some(interface.as_bytes()))
+            .map_err(|e| std::io::Error::new(e.kind(), format!("SO_BINDTODEVICE {:?}: {}", interface, e)))
+    }
+    #[cfg(not(any(target_os = "linux", target_os = "android")))]
+    {
+        let _ = socket.into();
+        Err(std::io::Error::new(
+            std::io::ErrorKind::Unsupported,
+            format!("can't bind to interface {:?}: SO_BINDTODEVICE is Linux-only", interface),
+        ))
+    }
+}
+
+/// What every DNS client can receive over UDP (RFC 1035 4.2.1).
+const MIN_UDP_PAYLOAD: u16 = 512;
+
+/// Settings that shape how queries are answered.
+#[derive(Debug, Clone)]
+pub struct ResolveOptions {
+    pub ttl: u32,
+    pub fallback_ip: Option<Ipv4Addr>,
+    pub classless_reverse: Vec<ClasslessZone>,
+    pub ptr_names: PtrNames,
+    pub query_log: Arc<QueryLog>,
+    pub connectivity_checks: ConnectivityChecks,
+    pub nsid: Option<Vec<u8>>, // Encoded once, copied into every response that asks
+    /// UDP payload size advertised to EDNS clients, and the most a UDP response may use
+    pub edns_payload: u16,
+    /// The answer to a request asking more than one question
+    pub multiple_questions: MultipleQuestions,
+    /// The answer for names in our zone that have no records
+    pub negative_response: NegativeResponse,
+    /// Add every covering wildcard's addresses to a name's own, instead of the most specific wildcard alone
+    pub wildcard_merge: bool,
+    /// Answer ANY with every record of the name instead of RFC 8482's single HINFO
+    pub full_any_answers: bool,
+    pub blocklist: Blocklist,
+    /// What CHAOS-class queries may learn; `None` when `expose_version` is off
+    pub chaos: Option<ChaosNames>,
+    /// Where the next multi-address answer starts, when `rotate_answers` is on
+    pub rotation: Option<Arc<AtomicUsize>>,
+    /// Most addresses in one answer; 0 for no limit
+    pub max_answers: usize,
+    /// CNAMEs followed within one answer, `[limits] max_alias_chain`
+    pub max_alias_chain: usize,
+    /// Per-client limit on UDP queries; `None` when `rate_limit_qps` is 0
+    pub rate_limit: Option<Arc<RateLimiter>>,
+    /// UDP queries `serve_until` answers at once, per socket
+    pub max_inflight_queries: usize,
+    /// SVCB records for `_dns.resolver.arpa.`; `None` in views that don't advertise them
+    pub ddr: Option<Arc<Ddr>>,
+    /// Sends on and caches what the lookup has no answer for, whether or not there are upstreams
+    pub forwarder: Arc<Forwarder>,
+    /// `upstream` and `[forward_zones]` at startup
+    pub upstreams: Arc<Upstreams>,
+    pub soa: Arc<Soa>,
+    /// `.local` belongs to multicast DNS, so its names aren't forwarded
+    pub mdns: bool,
+    /// What every query runs through; replace it to add steps around the built-in lookup
+    pub middleware: Chain,
+    pub stats: Arc<Stats>,
+    /// The receiving listener and what its clients may see
+    pub view: Arc<View>,
+}
+
+/// How a request with more than one question is answered. RFC 1035 allows
+/// several, but no server has ever given them a meaning.
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum MultipleQuestions {
+    /// Answer the first question, echoing only that one
+    #[default]
+    First,
+    Formerr,
+    Refused,
+}
+
+/// What a name under `domain_suffix` with no records at all is answered with.
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum NegativeResponse {
+    #[default]
+    Nxdomain,
+    /// NOERROR without answers, which some stub resolvers take as denying
+    /// only the type asked for rather than every type of the name
+    Nodata,
+}
+
+/// The TXT answers to CHAOS-class `version.bind` and `hostname.bind`.
+#[derive(Debug, Clone)]
+pub struct ChaosNames {
+    pub version: String,
+    pub hostname: Option<String>,
+}
+
+/// The answer settings re-read from the config file on SIGHUP. Loaded records
+/// carry them, so they take over from `ResolveOptions` in every listener.
+#[derive(Debug)]
+pub struct ReloadedSettings {
+    pub ttl: u32,
+    pub soa: Arc<Soa>,
+    pub upstreams: Arc<Upstreams>,
+}
+
+impl ReloadedSettings {
+    pub fn from_config(config: &crate::config::Config) -> Self {
+        Self {
+            ttl: config.ttl,
+            soa: Arc::new(config.soa().expect("validated with the config")),
+            upstreams: Arc::new(config.upstreams()),
+        }
+    }
+}
+
+/// What produced an answer, as reported in the query log.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AnswerSource {
+    Exact,
+    Wildcard,
+    Alias,
+    Fallback,
+    ConnectivityCheck,
+    Classless,
+    Ptr,
+    Ddr,
+    Soa,
+    /// CHAOS-class `version.bind` or `hostname.bind`
+    Chaos,
+    /// A name listed at 0.0.0.0 or ::, under `blocklist_mode`
+    Blocked,
+    /// Answered by an embedder's middleware
+    Middleware,
+    /// Forwarded to an `upstream` resolver, or answered from its cache
+    Upstream,
+    None,
+}
+
+impl AnswerSource {
+    pub const ALL: [AnswerSource; 14] = [
+        AnswerSource::Exact,
+        AnswerSource::Wildcard,
+        AnswerSource::Alias,
+        AnswerSource::Fallback,
+        AnswerSource::ConnectivityCheck,
+        AnswerSource::Classless,
+        AnswerSource::Ptr,
+        AnswerSource::Ddr,
+        AnswerSource::Soa,
+        AnswerSource::Chaos,
+        AnswerSource::Blocked,
+        AnswerSource::Middleware,
+        AnswerSource::Upstream,
+        AnswerSource::None,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AnswerSource::Exact => "exact",
+            AnswerSource::Wildcard => "wildcard",
+            AnswerSource::Alias => "cname",
+            AnswerSource::Fallback => "fallback",
+            AnswerSource::ConnectivityCheck => "connectivity_check",
+            AnswerSource::Classless => "classless",
+            AnswerSource::Ptr => "ptr",
+            AnswerSource::Ddr => "ddr",
+            AnswerSource::Soa => "soa",
+            AnswerSource::Chaos => "chaos",
+            AnswerSource::Blocked => "blocked",
+            AnswerSource::Middleware => "middleware",
+            AnswerSource::Upstream => "upstream",
+            AnswerSource::None => "none",
+        }
+    }
+}
+
+impl ResolveOptions {
+    pub fn from_config(config: &crate::config::Config) -> Self {
+        Self {
+            ttl: config.ttl,
+            fallback_ip: config.fallback_ip,
+            classless_reverse: config.classless_reverse.clone(),
+            ptr_names: config.ptr_names,
+            query_log: Arc::new(QueryLog::new(
+                &config.query_log,
+                Arc::new(Anonymizer::new(
+                    config.anonymize_clients,
+                    Duration::from_secs(config.anonymize_key_rotation),
+                )),
+            )),
+            connectivity_checks: ConnectivityChecks::new(&config.connectivity_checks, config.fallback_ip),
+            nsid: config.nsid(),
+            edns_payload: config.edns_udp_payload,
+            multiple_questions: config.multiple_questions,
+            negative_response: config.negative_response,
+            wildcard_merge: config.wildcard_merge,
+            full_any_answers: config.full_any_answers,
+            blocklist: config.blocklist(),
+            chaos: config.expose_version.then(|| ChaosNames {
+                version: format!("localdns {}", env!("CARGO_PKG_VERSION")),
+                hostname: crate::config::hostname(),
+            }),
+            rotation: config.rotate_answers.then(|| Arc::new(AtomicUsize::new(0))),
+            max_answers: config.max_answers,
+            max_alias_chain: config.limits.max_alias_chain,
+            rate_limit: RateLimiter::new(config.rate_limit_qps, config.rate_limit_burst, config.rate_limit_action).map(Arc::new),
+            max_inflight_queries: config.max_inflight_queries,
+            ddr: Some(Arc::new(Ddr::new(config.ddr()))),
+            forwarder: Arc::new(config.forwarder()),
+            upstreams: Arc::new(config.upstreams()),
+            soa: Arc::new(config.soa().expect("validated with the config")),
+            mdns: config.mdns,
+            middleware: Chain::default(),
+            stats: Arc::new(Stats::default()),
+            view: Arc::new(View::default()),
+        }
+    }
+}
+
+pub async fn serve(
+    socket: UdpSocket,
+    records: Arc<RwLock<DnsCache>>,
+    options: ResolveOptions,
+) -> Result<(), ServeError> {
+    serve_until(socket, records, options, std::future::pending()).await
+}
+
+/// Like `serve`, but stops reading once `stop` completes, then waits for the
+/// queries already received to be answered before closing the socket.
+/// Each query is answered on a task of its own, up to `max_inflight_queries`
+/// at a time; queries arriving while that many are in progress are dropped,
+/// and counted in `Stats::overloaded`, as the client will retry.
+/// The socket may be shared with other receive loops, as `workers` does where
+/// there is no SO_REUSEPORT.
+pub async fn serve_until(
+    socket: impl Into<Arc<UdpSocket>>,
+    records: Arc<RwLock<DnsCache>>,
+    options: ResolveOptions,
+    stop: impl Future<Output = ()>,
+) -> Result<(), ServeError> {
+    let socket = socket.into();
+    let inflight = Arc::new(Semaphore::new(options.max_inflight_queries.max(1)));
+    let options = Arc::new(options);
+    // Every query task holds a sender; `recv` returns `None` once all are done
+    let (drain, mut drained) = mpsc::channel::<()>(1);
+    tokio::pin!(stop);
+
+    let pool = BufferPool::new(RECEIVE_BUFFER, POOLED_BUFFERS);
+    tracing::info!("Entering server loop...");
+    loop {
+        let mut buf = pool.take();
+        let received = tokio::select! {
+            _ = &mut stop => break,
+            received = socket.recv_from(&mut buf) => received,
+        };
+        let (len, src) = match received {
+            Ok(x) => x,
+            Err(e) => {
+                tracing::error!("Failed to receive UDP packet: {}", e);
+                continue;
+            }
+        };
+
+        // Never waits: either a permit is free or the query is dropped
+        let Ok(permit) = inflight.clone().try_acquire_owned() else {
+            options.stats.overloaded.fetch_add(1, Ordering::Relaxed);
+            tracing::debug!("Dropping a query from {}: {} queries in progress (max_inflight_queries)", src, options.max_inflight_queries);
+            continue;
+        };
+        buf.truncate(len);
+        let records = records.clone();
+        let socket = socket.clone();
+        let options = options.clone();
+        let drain = drain.clone();
+
+        tokio::spawn(async move {
+            if let Err(e) = handle_query(socket, buf, src, records, &options).await {
+                options.stats.errors.fetch_add(1, Ordering::Relaxed);
+                tracing::error!("Error handling query from {}: {}", src, e);
+            }
+            drop((permit, drain));
+        });
+    }
+    drop(drain);
+    drained.recv().await;
+    Ok(())
+}
+
+/// Receive buffer for `serve_until`, which also holds the response.
+pub const RECEIVE_BUFFER: usize = 4096;
+
+/// Idle buffers `serve_until` keeps for the next queries, 1 MiB of them.
+/// Bursts with more queries in flight allocate the rest and free them after.
+const POOLED_BUFFERS: usize = 256;
+
+/// Receive buffer for `serve_inline`: the largest query worth expecting, since
+/// queries carry one question and at most an OPT record.
+pub const INLINE_BUFFER: usize = 1232;
+
+/// Like `serve`, but answers queries inside the receive loop instead of
+/// spawning a task for each, with at most `max_inflight` in progress. Reading
+/// pauses while the budget is used up. For small single-threaded deployments.
+/// Stops like `serve_until`.
+pub async fn serve_inline(
+    socket: impl Into<Arc<UdpSocket>>,
+    records: Arc<RwLock<DnsCache>>,
+    options: ResolveOptions,
+    max_inflight: usize,
+    stop: impl Future<Output = ()>,
+) -> Result<(), ServeError> {
+    let socket = socket.into();
+    tokio::pin!(stop);
+    let max_inflight = max_inflight.max(1);
+    let mut inflight: Vec<Pin<Box<dyn Future<Output = ()> + Send + '_>>> = Vec::with_capacity(max_inflight);
+
+    // One per query in progress, so the loop never allocates one
+    let pool = BufferPool::new(INLINE_BUFFER, max_inflight);
+    tracing::info!("Entering server loop (inline, up to {} queries at a time)...", max_inflight);
+    loop {
+        tokio::select! {
+            _ = &mut stop => break,
+            received = recv_pooled(&socket, &pool), if inflight.len() < max_inflight => {
+                let (data, src) = match received {
+                    Ok(x) => x,
+                    Err(e) => {
+                        tracing::error!("Failed to receive UDP packet: {}", e);
+                        continue;
+                    }
+                };
+                let (socket, records, options) = (socket.clone(), records.clone(), &options);
+                inflight.push(Box::pin(async move {
+                    if let Err(e) = handle_query(socket, data, src, records, options).await {
+                        options.stats.errors.fetch_add(1, Ordering::Relaxed);
+                        tracing::error!("Error handling query from {}: {}", src, e);
+                    }
+                }));
+            }
+            _ = finish_some(&mut inflight), if !inflight.is_empty() => {}
+        }
+    }
+    // Stopped: answer what was already received
+    while !inflight.is_empty() {
+        finish_some(&mut inflight).await;
+    }
+    Ok(())
+}
+
+/// The next packet on `socket`, in a buffer from `pool` cut to its length.
+async fn recv_pooled(socket: &UdpSocket, pool: &Arc<BufferPool>) -> std::io::Result<(Buffer, SocketAddr)> {
+    let mut buf = pool.take();
+    let (len, src) = socket.recv_from(&mut buf).await?;
+    buf.truncate(len);
+    Ok((buf, src))
+}
+
+/// Drops every finished query; pending until at least one finishes.
+async fn finish_some(inflight: &mut Vec<Pin<Box<dyn Future<Output = ()> + Send + '_>>>) {
+    poll_fn(|cx| {
+        let before = inflight.len();
+        inflight.retain_mut(|query| query.as_mut().poll(cx).is_pending());
+        if inflight.len() < before { Poll::Ready(()) } else { Poll::Pending }
+    })
+    .await
+}
+
+/// Answers the UDP query in `data`, encoding the response over it once the
+/// request is parsed, so a pooled buffer serves both.
+pub async fn handle_query(
+    socket: Arc<UdpSocket>,
+    data: impl Into<Buffer>,
+    src: SocketAddr,
+    records: Arc<RwLock<DnsCache>>,
+    options: &ResolveOptions,
+) -> Result<(), ServeError> {
+    let started = Instant::now();
+    let mut data = data.into();
+    if is_response(&data) {
+        tracing::debug!("Ignoring a response sent to our port by {}", src);
+        return Ok(());
+    }
+    let verdict = options.rate_limit.as_ref().map_or(Verdict::Allow, |limiter| limiter.check(src.ip()));
+    if verdict == Verdict::Drop {
+        options.stats.rate_limited.fetch_add(1, Ordering::Relaxed);
+        return Ok(());
+    }
+
+    // Parse the query
+    let request = match Message::from_vec(&data) {
+        Ok(request) => request,
+        Err(e) => match notimp_for_opcode(&data) {
+            Some(response) => {
+                socket.send_to(&response, src).await?;
+                return Ok(());
+            }
+            None => return Err(e.into()),
+        },
+    };
+    if verdict == Verdict::Slip {
+        options.stats.rate_limited.fetch_add(1, Ordering::Relaxed);
+        let mut response = response_for(&request);
+        response.set_truncated(true);
+        encode_into(&response, &mut data)?;
+        socket.send_to(&data, src).await?;
+        return Ok(());
+    }
+    let (response, source) = resolve(&request, Some(src), &records, options).await?;
+    let elapsed = started.elapsed();
+    options.query_log.record(&options.view.listener, Some(src), &request, &response, source, elapsed);
+    options.stats.record(request.queries().first().map(|q| q.query_type()), response.response_code(), source, elapsed);
+
+    // Serialize and send
+    encode_udp_into(&response, udp_limit(&request, options.edns_payload), &mut data)?;
+    socket.send_to(&data, src).await?;
+
+    Ok(())
+}
+
+/// Builds the answer to a parsed request by running it through
+/// `options.middleware`; shared by every transport.
+pub async fn resolve(
+    request: &Message,
+    client: Option<SocketAddr>,
+    records: &RwLock<DnsCache>,
+    options: &ResolveOptions,
+) -> Result<(Message, AnswerSource), ServeError> {
+    // UPDATE, NOTIFY and STATUS aren't lookups, so no step of the chain should treat them as one
+    if request.op_code() != OpCode::Query {
+        let mut response = response_for(request);
+        response.set_response_code(ResponseCode::NotImp);
+        return Ok((response, AnswerSource::None));
+    }
+    let questions = request.queries().len();
+    let code = match (questions, options.multiple_questions) {
+        (0, _) => Some(ResponseCode::FormErr),
+        (1, _) => None,
+        (_, MultipleQuestions::First) => {
+            tracing::debug!("Answering only the first of {} questions from {:?}", questions, client);
+            None
+        }
+        (_, MultipleQuestions::Formerr) => Some(ResponseCode::FormErr),
+        (_, MultipleQuestions::Refused) => Some(ResponseCode::Refused),
+    };
+    if let Some(code) = code {
+        let mut response = response_for(request);
+        response.set_response_code(code);
+        return Ok((response, AnswerSource::None));
+    }
+    let ctx = QueryContext { request, client, records, options };
+    options.middleware.run(&ctx).await
+}
+
+/// Whether `data` has the QR bit set. Listeners drop these unanswered, so a
+/// spoofed response can't start two servers answering each other in a loop.
+pub fn is_response(data: &[u8]) -> bool {
+    data.get(2).is_some_and(|flags| flags & 0x80 != 0)
+}
+
+/// A NOTIMP response to a request whose opcode hickory can't parse, such as
+/// the obsolete IQUERY (RFC 3425). The request is parsed as a QUERY to echo
+/// its ID and question, and the response carries the original opcode. `None`
+/// when `data` is a response, too short, or unparseable for another reason.
+pub fn notimp_for_opcode(data: &[u8]) -> Option<Vec<u8>> {
+    const OPCODE: u8 = 0b0111_1000;
+    let flags = *data.get(2)?;
+    let opcode = (flags & OPCODE) >> 3;
+    // Responses are never answered, and a known opcode failed to parse for some other reason
+    if flags & 0x80 != 0 || matches!(opcode, 0 | 2 | 4 | 5) {
+        return None;
+    }
+    let mut as_query = data.to_vec();
+    as_query[2] &= !OPCODE;
+    let request = Message::from_vec(&as_query).ok()?;
+    let mut response = response_for(&request);
+    response.set_response_code(ResponseCode::NotImp);
+    let mut bytes = response.to_vec().ok()?;
+    bytes[2] = (bytes[2] & !OPCODE) | (opcode << 3);
+    Some(bytes)
+}
+
+/// Largest UDP response `request` can take: 512 bytes without EDNS, else
+/// the size it advertised, but no more than `ours`.
+pub fn udp_limit(request: &Message, ours: u16) -> usize {
+    match request.extensions() {
+        Some(edns) => usize::from(edns.max_payload().clamp(MIN_UDP_PAYLOAD, ours.max(MIN_UDP_PAYLOAD))),
+        None => usize::from(MIN_UDP_PAYLOAD),
+    }
+}
+
+/// `response` on the wire, cut down to fit in `limit`. The additional
+/// section goes first, without TC since it is only a hint (RFC 2181 9); then
+/// the authority section and as many trailing answers as it takes, with TC
+/// set, so the client gets what fits and retries over TCP for the rest. The
+/// header, question and OPT record always stay.
+pub fn encode_udp(response: &Message, limit: usize) -> Result<Vec<u8>, ProtoError> {
+    let mut bytes = Vec::new();
+    encode_udp_into(response, limit, &mut bytes)?;
+    Ok(bytes)
+}
+
+/// Like `encode_udp`, but replaces the contents of `out`, which keeps its
+/// allocation from one response to the next.
+pub fn encode_udp_into(response: &Message, limit: usize, out: &mut Vec<u8>) -> Result<(), ProtoError> {
+    encode_into(response, out)?;
+    if out.len() <= limit {
+        return Ok(());
+    }
+    let mut truncated = response.clone();
+    truncated.take_additionals();
+    encode_into(&truncated, out)?;
+    if out.len() <= limit {
+        return Ok(());
+    }
+    truncated.take_name_servers();
+    truncated.set_truncated(true);
+    let answers = truncated.take_answers();
+    // The most leading answers that fit, found by bisecting the count
+    let (mut fits, mut too_many) = (0, answers.len() + 1);
+    while too_many - fits > 1 {
+        let count = (fits + too_many) / 2;
+        let mut candidate = truncated.clone();
+        candidate.insert_answers(answers[..count].to_vec());
+        encode_into(&candidate, out)?;
+        if out.len() <= limit {
+            fits = count;
+        } else {
+            too_many = count;
+        }
+    }
+    truncated.insert_answers(answers[..fits].to_vec());
+    encode_into(&truncated, out)
+}
+
+/// `message` on the wire in place of what `out` held.
+fn encode_into(message: &Message, out: &mut Vec<u8>) -> Result<(), ProtoError> {
+    out.clear();
+    message.emit(&mut BinEncoder::new(out))
+}
+
+/// An empty response to `request`, echoing its ID, opcode, flags and question.
+pub fn response_for(request: &Message) -> Message {
+    let mut response = Message::new();
+    response.set_id(request.id());
+    response.set_message_type(MessageType::Response);
+    response.set_op_code(request.op_code());
+    response.set_recursion_desired(request.recursion_desired());
+    response.set_recursion_available(true);
+    if let Some(query) = request.queries().first() {
+        response.add_query(query.clone());
+    }
+    response
+}
+
+/// Answers from the loaded records; the body of the built-in `Lookup` middleware.
+pub(crate) async fn lookup(
+    request: &Message,
+    records: &RwLock<DnsCache>,
+    options: &ResolveOptions,
+) -> Result<(Message, AnswerSource), ServeError> {
+    answer(request, &*records.read().await, options)
+}
+
+/// What the local records say to `request`: the exact, wildcard and fallback
+/// addresses, the other record types, PTRs, aliases, synthesized names and the
+/// response code, with no I/O. The rest of the chain (EDNS, NSID, blocking,
+/// forwarding) isn't involved, so a hand-built `DnsCache` is all a test needs.
+pub fn answer(request: &Message, records: &DnsCache, options: &ResolveOptions) -> Result<(Message, AnswerSource), ServeError> {
+    let view = &options.view;
+    let mut source = AnswerSource::None;
+
+    let mut response = response_for(request);
+
+    if let Some(query) = request.queries().first() {
+        if query.query_class() != DNSClass::IN {
+            return Ok(other_class(request, query, options));
+        }
+        let name = query.name();
+        // Matching uses the lowercased name; answers are always owned by `name` itself,
+        // so clients randomizing the case (dns0x20) get back exactly what they sent.
+        // The tables take the `LowerName`; the key form, for the zone and reverse
+        // checks, is only built once one of them needs it
+        let key = LowerName::new(name);
+        let text_key = OnceCell::new();
+        let lookup_name = || text_key.get_or_init(|| name_key(name)).as_str();
+
+        // A SIGHUP may have re-read these since the listener started
+        let (ttl, soa, upstreams) = match &records.settings {
+            Some(settings) => (settings.ttl, &*settings.soa, &*settings.upstreams),
+            None => (options.ttl, &*options.soa, &*options.upstreams),
+        };
+        // Set by the branches below when the name has no records of any kind
+        let mut missing = false;
+
+        let mut found: Vec<&Entry> = Vec::new();
+        let txts: Vec<&TxtRecord> = match records.txts.get(&key) {
+            Some(txts) => txts.iter().filter(|txt| view.shows_txt(txt)).collect(),
+            None => Vec::new(),
+        };
+        let mut mxs: Vec<&MxRecord> = match records.mxs.get(&key) {
+            Some(mxs) => mxs.iter().filter(|mx| view.shows_mx(mx)).collect(),
+            None => Vec::new(),
+        };
+        let mut srvs: Vec<&SrvRecord> = match records.srvs.get(&key) {
+            Some(srvs) => srvs.iter().filter(|srv| view.shows_srv(srv)).collect(),
+            None => Vec::new(),
+        };
+        // Names with only TXT, MX or SRV records exist, so other types get NODATA
+        let has_other_records = !txts.is_empty() || !mxs.is_empty() || !srvs.is_empty();
+
+        let classless = options
+            .classless_reverse
+            .iter()
+            .find_map(|zone| zone.classify(lookup_name()));
+
+        let ddr = match &options.ddr {
+            Some(ddr) if lookup_name() == DDR_NAME => ddr.records(),
+            _ => Vec::new(),
+        };
+        if !ddr.is_empty() {
+            // RFC 9462: clients discover our encrypted endpoints through SVCB
+            source = AnswerSource::Ddr;
+            if query.query_type() == RecordType::SVCB {
+                for rdata in ddr {
+                    response.add_answer(Record::from_rdata(name.clone(), ttl, rdata));
+                }
+            }
+            response.set_response_code(ResponseCode::NoError);
+        } else if let Some(classless) = classless {
+            // RFC 2317: parent-zone names alias into the classless zone, which holds the PTRs
+            source = AnswerSource::Classless;
+            let is_apex = classless == ClasslessMatch::Apex;
+            let (owner, address) = match classless {
+                ClasslessMatch::Alias { target, address } => {
+                    let target = Name::from_ascii(&target)?;
+                    let mut record = Record::with(name.clone(), RecordType::CNAME, ttl);
+                    record.set_data(Some(RData::CNAME(CNAME(target.clone()))));
+                    response.add_answer(record);
+                    (target, Some(address))
+                }
+                ClasslessMatch::Address(address) => (name.clone(), Some(address)),
+                ClasslessMatch::Apex | ClasslessMatch::Unknown => (name.clone(), None),
+            };
+            let names = match address {
+                Some(a) if view.reverse => reverse_names(records, options, a),
+                _ => Vec::new(),
+            };
+
+            if !names.is_empty() {
+                if query.query_type() == RecordType::PTR {
+                    for ptr_name in names {
+                        let mut record = Record::with(owner.clone(), RecordType::PTR, ttl);
+                        record.set_data(Some(RData::PTR(PTR(parse_presentation(ptr_name)?))));
+                        response.add_answer(record);
+                    }
+                }
+                response.set_response_code(ResponseCode::NoError);
+            } else if is_apex || !response.answers().is_empty() {
+                response.set_response_code(ResponseCode::NoError);
+            } else {
+                response.set_response_code(ResponseCode::NXDomain);
+            }
+        } else if query.query_type() == RecordType::SOA && lookup_name() == soa.zone_key {
+            source = AnswerSource::Soa;
+            let rdata = RData::SOA(soa.rdata(records.serial));
+            response.add_answer(Record::from_rdata(name.clone(), ttl, rdata));
+            response.set_response_code(ResponseCode::NoError);
+        } else if let Some(address) =
+            (query.query_type() == RecordType::PTR).then(|| parse_in_addr_arpa(lookup_name())).flatten()
+        {
+            // Explicit PTRs, where configured, replace the names generated from forward records
+            let names = if view.reverse {
+                reverse_names(records, options, address)
+            } else {
+                Vec::new()
+            };
+            if !names.is_empty() {
+                source = AnswerSource::Ptr;
+                for ptr_name in names {
+                    let mut record = Record::with(name.clone(), RecordType::PTR, ttl);
+                    record.set_data(Some(RData::PTR(PTR(parse_presentation(ptr_name)?))));
+                    response.add_answer(record);
+                }
+                response.set_response_code(ResponseCode::NoError);
+            } else {
+                response.set_response_code(ResponseCode::NXDomain);
+            }
+        } else if let Some(alias) = records.cnames.get(&key).filter(|a| view.shows_alias(a)) {
+            // An alias answers every type with its CNAME, followed along the chain for the rest
+            source = AnswerSource::Alias;
+            let mut record = Record::with(name.clone(), RecordType::CNAME, ttl);
+            record.set_data(Some(RData::CNAME(CNAME(parse_presentation(&alias.target)?))));
+            response.add_answer(record);
+            if query.query_type() != RecordType::CNAME {
+                let mut target = &alias.target;
+                let mut hops = 1;
+                while let Some(next) = records.cnames.get(&parse_key(target)?).filter(|a| view.shows_alias(a)) {
+                    if hops == options.max_alias_chain {
+                        tracing::debug!("{}: CNAME chain longer than {}, not followed further", lookup_name(), options.max_alias_chain);
+                        break;
+                    }
+                    let mut record = Record::with(parse_presentation(target)?, RecordType::CNAME, ttl);
+                    record.set_data(Some(RData::CNAME(CNAME(parse_presentation(&next.target)?))));
+                    response.add_answer(record);
+                    target = &next.target;
+                    hops += 1;
+                }
+                let ipv6 = query.query_type() == RecordType::AAAA;
+                let owner = parse_presentation(target)?;
+                let addresses: Vec<&Entry> = match records.exact_matches.get(&LowerName::new(&owner)) {
+                    Some(entries) if matches!(query.query_type(), RecordType::A | RecordType::AAAA) => {
+                        entries.iter().filter(|e| view.shows(e) && e.ip.is_ipv6() == ipv6).collect()
+                    }
+                    _ => Vec::new(),
+                };
+                let mut addresses = addresses;
+                rotate(&mut addresses, options);
+                cap_answers(&mut addresses, options, target);
+                let address_ttl = addresses.iter().map(|e| e.ttl.unwrap_or(ttl)).min().unwrap_or(ttl);
+                for entry in addresses {
+                    let rdata = match entry.ip {
+                        IpAddr::V4(ip) => RData::A(A(ip)),
+                        IpAddr::V6(ip) => RData::AAAA(AAAA(ip)),
+                    };
+                    response.add_answer(Record::from_rdata(owner.clone(), address_ttl, rdata));
+                }
+            }
+            response.set_response_code(ResponseCode::NoError);
+        } else if query.query_type() == RecordType::TXT && !txts.is_empty() {
+            source = AnswerSource::Exact;
+            let ttl = txts.iter().map(|txt| txt.ttl.unwrap_or(ttl)).min().unwrap_or(ttl);
+            for txt in txts {
+                let rdata = RData::TXT(TXT::from_bytes(txt.strings.iter().map(Vec::as_slice).collect()));
+                response.add_answer(Record::from_rdata(name.clone(), ttl, rdata));
+            }
+            response.set_response_code(ResponseCode::NoError);
+        } else if query.query_type() == RecordType::MX && !mxs.is_empty() {
+            source = AnswerSource::Exact;
+            // Stable, so equal preferences keep the order their sources list them in
+            mxs.sort_by_key(|mx| mx.preference);
+            let ttl = mxs.iter().map(|mx| mx.ttl.unwrap_or(ttl)).min().unwrap_or(ttl);
+            for mx in &mxs {
+                let rdata = RData::MX(MX::new(mx.preference, parse_presentation(&mx.exchange)?));
+                response.add_answer(Record::from_rdata(name.clone(), ttl, rdata));
+            }
+            // Local exchanges come with their addresses, saving the client a lookup
+            add_target_addresses(&mut response, records, view, mxs.iter().map(|mx| mx.exchange.as_str()), ttl)?;
+            response.set_response_code(ResponseCode::NoError);
+        } else if query.query_type() == RecordType::SRV && !srvs.is_empty() {
+            source = AnswerSource::Exact;
+            // Clients pick among equal priorities by weight (RFC 2782), so only priority orders them
+            srvs.sort_by_key(|srv| srv.priority);
+            let ttl = srvs.iter().map(|srv| srv.ttl.unwrap_or(ttl)).min().unwrap_or(ttl);
+            for srv in &srvs {
+                let rdata = RData::SRV(SRV::new(srv.priority, srv.weight, srv.port, parse_presentation(&srv.target)?));
+                response.add_answer(Record::from_rdata(name.clone(), ttl, rdata));
+            }
+            add_target_addresses(&mut response, records, view, srvs.iter().map(|srv| srv.target.as_str()), ttl)?;
+            response.set_response_code(ResponseCode::NoError);
+        } else if query.query_type() == RecordType::ANY {
+            found.extend(records.exact_matches.get(&key).into_iter().flatten().filter(|e| view.shows(e)));
+            source = AnswerSource::Exact;
+            if found.is_empty() && !has_other_records {
+                let mut covering = records.covering_wildcards(&key).filter(|(_, e)| view.shows(e)).peekable();
+                let most_specific = covering.peek().map(|(pattern, _)| pattern.len());
+                found.extend(covering.take_while(|(pattern, _)| Some(pattern.len()) == most_specific).map(|(_, e)| e));
+                source = AnswerSource::Wildcard;
+            }
+            if found.is_empty() && !has_other_records {
+                source = AnswerSource::None;
+                missing = true;
+            } else if options.full_any_answers {
+                found.sort_by_key(|entry| entry.ip);
+                found.dedup_by_key(|entry| entry.ip);
+                for family in [false, true] {
+                    let addresses: Vec<&&Entry> = found.iter().filter(|entry| entry.ip.is_ipv6() == family).collect();
+                    let ttl = addresses.iter().map(|entry| entry.ttl.unwrap_or(ttl)).min().unwrap_or(ttl);
+                    for entry in addresses {
+                        let rdata = match entry.ip {
+                            IpAddr::V4(ip) => RData::A(A(ip)),
+                            IpAddr::V6(ip) => RData::AAAA(AAAA(ip)),
+                        };
+                        response.add_answer(Record::from_rdata(name.clone(), ttl, rdata));
+                    }
+                }
+                let txt_ttl = txts.iter().map(|txt| txt.ttl.unwrap_or(ttl)).min().unwrap_or(ttl);
+                for txt in txts {
+                    let rdata = RData::TXT(TXT::from_bytes(txt.strings.iter().map(Vec::as_slice).collect()));
+                    response.add_answer(Record::from_rdata(name.clone(), txt_ttl, rdata));
+                }
+                mxs.sort_by_key(|mx| mx.preference);
+                let mx_ttl = mxs.iter().map(|mx| mx.ttl.unwrap_or(ttl)).min().unwrap_or(ttl);
+                for mx in &mxs {
+                    let rdata = RData::MX(MX::new(mx.preference, parse_presentation(&mx.exchange)?));
+                    response.add_answer(Record::from_rdata(name.clone(), mx_ttl, rdata));
+                }
+                srvs.sort_by_key(|srv| srv.priority);
+                let srv_ttl = srvs.iter().map(|srv| srv.ttl.unwrap_or(ttl)).min().unwrap_or(ttl);
+                for srv in &srvs {
+                    let rdata = RData::SRV(SRV::new(srv.priority, srv.weight, srv.port, parse_presentation(&srv.target)?));
+                    response.add_answer(Record::from_rdata(name.clone(), srv_ttl, rdata));
+                }
+                response.set_response_code(ResponseCode::NoError);
+            } else {
+                // RFC 8482: one synthesized HINFO stands in for the whole set, so ANY
+                // answers stay small and can't be used to amplify
+                let rdata = RData::HINFO(HINFO::new("RFC8482".to_string(), String::new()));
+                response.add_answer(Record::from_rdata(name.clone(), ttl, rdata));
+                response.set_response_code(ResponseCode::NoError);
+            }
+        } else if matches!(query.query_type(), RecordType::A | RecordType::AAAA) {
+            // 1. Try exact match
+            if let Some(entries) = records.exact_matches.get(&key) {
+                found.extend(entries.iter().filter(|e| view.shows(e)));
+                if !found.is_empty() {
+                    source = AnswerSource::Exact;
+                }
+            }
+
+            // 2. Wildcards, for names without records of their own (RFC 4592), where the most
+            // specific pattern wins; under `wildcard_merge`, every one adds to the exact addresses
+            if options.wildcard_merge || (found.is_empty() && !has_other_records) {
+                // "*.example.com." covers "sub.example.com." and "a.b.example.com.", not "notexample.com."
+                let mut covering = records.covering_wildcards(&key).filter(|(_, e)| view.shows(e)).peekable();
+                let most_specific = covering.peek().map(|(pattern, _)| pattern.len());
+                for (pattern, entry) in covering {
+                    if !options.wildcard_merge && Some(pattern.len()) != most_specific {
+                        break;
+                    }
+                    found.push(entry);
+                    if source == AnswerSource::None {
+                        source = AnswerSource::Wildcard;
+                    }
+                }
+            }
+
+            if !found.is_empty() {
+                // The name exists; with only the other family's addresses the answer is empty (NODATA)
+                let ipv6 = query.query_type() == RecordType::AAAA;
+                found.retain(|entry| entry.ip.is_ipv6() == ipv6);
+                if found.is_empty() {
+                    source = AnswerSource::None;
+                }
+                // Remove duplicates and sort, keeping the first source seen for each IP
+                found.sort_by_key(|entry| entry.ip);
+                found.dedup_by_key(|entry| entry.ip);
+                rotate(&mut found, options);
+                cap_answers(&mut found, options, lookup_name());
+
+                // Every record in the answer gets the lowest TTL among them (RFC 2181 5.2)
+                let ttl = found.iter().map(|entry| entry.ttl.unwrap_or(ttl)).min().unwrap_or(ttl);
+                for entry in found {
+                    tracing::debug!("{} -> {} (from {})", lookup_name(), entry.ip, entry.source);
+                    let rdata = match entry.ip {
+                        IpAddr::V4(ip) => RData::A(A(ip)),
+                        IpAddr::V6(ip) => RData::AAAA(AAAA(ip)),
+                    };
+                    response.add_answer(Record::from_rdata(name.clone(), ttl, rdata));
+                }
+                response.set_response_code(ResponseCode::NoError);
+            } else if has_other_records {
+                response.set_response_code(ResponseCode::NoError);
+            } else if query.query_type() == RecordType::AAAA {
+                // Connectivity checks and the fallback only answer with IPv4 addresses
+                missing = true;
+            } else if let Some(policy) = options.connectivity_checks.lookup(lookup_name()) {
+                // 3. Connectivity checks never get the catch-all, unless we are the portal
+                source = AnswerSource::ConnectivityCheck;
+                match (policy, options.connectivity_checks.portal_ip) {
+                    (CheckPolicy::Portal, Some(ip)) => {
+                        let mut record = Record::with(name.clone(), RecordType::A, ttl);
+                        record.set_data(Some(RData::A(A(ip))));
+                        response.add_answer(record);
+                        response.set_response_code(ResponseCode::NoError);
+                    }
+                    _ => {
+                        response.set_response_code(ResponseCode::NXDomain);
+                    }
+                }
+            } else if let Some(ip) = options.fallback_ip.filter(|_| soa.contains(lookup_name())) {
+                // 4. Fallback if enabled, for names in our zone only
+                source = AnswerSource::Fallback;
+                let mut record = Record::with(name.clone(), RecordType::A, ttl);
+                record.set_data(Some(RData::A(A(ip))));
+                response.add_answer(record);
+                response.set_response_code(ResponseCode::NoError);
+            } else {
+                missing = true;
+            }
+        } else {
+            // Other types get NODATA when the name has addresses of its own or from a
+            // wildcard, or records of another type
+            let exact = records.exact_matches.get(&key);
+            missing = !has_other_records
+                && !exact.is_some_and(|entries| entries.iter().any(|e| view.shows(e)))
+                && !records.covering_wildcards(&key).any(|(_, e)| view.shows(e));
+            if !missing {
+                response.set_response_code(ResponseCode::NoError);
+            }
+        }
+
+        if missing {
+            // NXDOMAIN, unless `negative_response` asks for NODATA in our zone; names a
+            // forward zone sends on stay NXDOMAIN so the `Forward` step still sees them
+            let forwarded = upstreams.zone_for(lookup_name()).is_some_and(|route| !route.upstreams.is_empty());
+            let nodata = options.negative_response == NegativeResponse::Nodata && soa.contains(lookup_name()) && !forwarded;
+            response.set_response_code(if nodata { ResponseCode::NoError } else { ResponseCode::NXDomain });
+        }
+
+        // RFC 2308: negative answers in our zone carry its SOA, so resolvers
+        // downstream cache them for the negative TTL instead of asking again
+        let negative = response.answers().is_empty()
+            && matches!(response.response_code(), ResponseCode::NXDomain | ResponseCode::NoError);
+        if negative && soa.contains(lookup_name()) {
+            let rdata = RData::SOA(soa.rdata(records.serial));
+            response.add_name_server(Record::from_rdata(soa.zone.clone(), soa.negative_ttl(), rdata));
+        }
+    } else {
+        response.set_response_code(ResponseCode::FormErr);
+    }
+
+    Ok((response, source))
+}
+
+/// Adds the A and AAAA records of each local name among `targets` to the
+/// additional section, once per name, for MX exchanges and SRV targets.
+fn add_target_addresses<'a>(
+    response: &mut Message,
+    cache: &DnsCache,
+    view: &View,
+    targets: impl Iterator<Item = &'a str>,
+    ttl: u32,
+) -> Result<(), ProtoError> {
+    let mut seen: Vec<&str> = Vec::new();
+    for target in targets {
+        if seen.contains(&target) {
+            continue;
+        }
+        seen.push(target);
+        let owner = parse_presentation(target)?;
+        let Some(entries) = cache.exact_matches.get(&LowerName::new(&owner)) else { continue };
+        let mut addresses: Vec<&Entry> = entries.iter().filter(|e| view.shows(e)).collect();
+        addresses.sort_by_key(|entry| entry.ip);
+        addresses.dedup_by_key(|entry| entry.ip);
+        for entry in addresses {
+            let rdata = match entry.ip {
+                IpAddr::V4(ip) => RData::A(A(ip)),
+                IpAddr::V6(ip) => RData::AAAA(AAAA(ip)),
+            };
+            response.add_additional(Record::from_rdata(owner.clone(), entry.ttl.unwrap_or(ttl), rdata));
+        }
+    }
+    Ok(())
+}
+
+/// CHAOS-class `version.bind` and `hostname.bind` (and their RFC 4892
+/// `version.server` and `id.server` spellings) get TXT answers unless
+/// `expose_version` is off; other CHAOS names are refused. The loaded records
+/// are all IN, so any other class is not implemented.
+fn other_class(request: &Message, query: &Query, options: &ResolveOptions) -> (Message, AnswerSource) {
+    let mut response = response_for(request);
+    if query.query_class() != DNSClass::CH {
+        response.set_response_code(ResponseCode::NotImp);
+        return (response, AnswerSource::None);
+    }
+    let text = options.chaos.as_ref().and_then(|chaos| match name_key(query.name()).as_str() {
+        "version.bind." | "version.server." => Some(chaos.version.clone()),
+        "hostname.bind." | "id.server." => chaos.hostname.clone(),
+        _ => None,
+    });
+    let Some(text) = text else {
+        response.set_response_code(ResponseCode::Refused);
+        return (response, AnswerSource::None);
+    };
+    if matches!(query.query_type(), RecordType::TXT | RecordType::ANY) {
+        let mut record = Record::from_rdata(query.name().clone(), 0, RData::TXT(TXT::new(vec![text])));
+        record.set_dns_class(DNSClass::CH);
+        response.add_answer(record);
+    }
+    response.set_response_code(ResponseCode::NoError);
+    (response, AnswerSource::Chaos)
+}
+
+/// The PTR names for `address` that the listener's view lets its clients
+/// see, or just the first of them under `PtrNames::Primary`.
+fn reverse_names<'a>(records: &'a DnsCache, options: &ResolveOptions, address: Ipv4Addr) -> Vec<&'a String> {
+    let Some(names) = records.reverse.get(&address) else {
+        return Vec::new();
+    };
+    let visible = names.iter().filter(|name| options.view.shows_ptr(records, address, name));
+    match options.ptr_names {
+        PtrNames::Primary => visible.take(1).collect(),
+        PtrNames::All => visible.collect(),
+    }
+}
+
+/// Rotates a sorted answer by one more place than the previous one, so
+/// clients that take the first address spread across all of them.
+fn rotate<T>(answers: &mut [T], options: &ResolveOptions) {
+    if let (Some(rotation), 2..) = (&options.rotation, answers.len()) {
+        let start = rotation.fetch_add(1, Ordering::Relaxed) % answers.len();
+        answers.rotate_left(start);
+    }
+}
+
+/// Cuts a sorted, rotated answer down to `max_answers`. With rotation on,
+/// those are the ones the rotation put first, so successive answers walk
+/// through the whole set; otherwise they are a random few, kept in order.
+/// Either way, every address gets its share of clients over time.
+fn cap_answers<T>(answers: &mut Vec<T>, options: &ResolveOptions, name: &str) {
+    let max = options.max_answers;
+    if max == 0 || answers.len() <= max {
+        return;
+    }
+    tracing::debug!("{}: answering with {} of {} addresses (max_answers)", name, max, answers.len());
+    if options.rotation.is_some() {
+        answers.truncate(max);
+        return;
+    }
+    let mut keep = rand::seq::index::sample(&mut rand::thread_rng(), answers.len(), max).into_vec();
+    keep.sort_unstable();
+    let mut keep = keep.into_iter().peekable();
+    let mut index = 0;
+    answers.retain(|_| {
+        let kept = keep.next_if_eq(&index).is_some();
+        index += 1;
+        kept
+    });
+}
+
+/// RFC 5001: a client asks for the server identifier with an empty NSID option.
+pub(crate) fn add_nsid(request: &Message, response: &mut Message, nsid: &[u8]) {
+    let requested = matches!(
+        request.extensions().as_ref().and_then(|edns| edns.option(EdnsCode::NSID)),
+        Some(option) if option.is_empty()
+    );
+    if !requested {
+        return;
+    }
+    let mut edns = response.extensions().clone().unwrap_or_default();
+    edns.options_mut()
+        .insert(EdnsOption::Unknown(u16::from(EdnsCode::NSID), nsid.to_vec()));
+    response.set_edns(edns);
+}
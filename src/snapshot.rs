@@ -0,0 +1,405 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::io::Write;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::loader::{name_key, parse_key, Alias, DnsCache, Entry, MxRecord, PtrRecord, Source, SourceKind, SrvRecord, TxtRecord};
+
+/// Bumped whenever the on-disk layout changes; other versions are ignored.
+const VERSION: u32 = 2;
+
+#[derive(Debug, thiserror::Error)]
+pub enum SnapshotError {
+    #[error("Failed to access snapshot {path:?}: {source}")]
+    Io {
+        path: PathBuf,
+        #[source]
+        source: std::io::Error,
+    },
+    #[error("Failed to parse snapshot {path:?}: {source}")]
+    Parse {
+        path: PathBuf,
+        #[source]
+        source: serde_json::Error,
+    },
+    #[error("Snapshot {path:?} has version {found}, expected {VERSION}")]
+    Version { path: PathBuf, found: u32 },
+    #[error("Snapshot {path:?} is {}s old, older than max_snapshot_age", age.as_secs())]
+    TooOld { path: PathBuf, age: Duration },
+}
+
+#[derive(Serialize, Deserialize)]
+struct Snapshot {
+    version: u32,
+    written_at: u64, // Seconds since the Unix epoch
+    exact: Vec<(String, Vec<SnapshotEntry>)>,
+    wildcards: Vec<(String, SnapshotEntry)>,
+    #[serde(default)]
+    ptrs: Vec<(String, SnapshotEntry)>,
+    #[serde(default)]
+    cnames: Vec<(String, SnapshotAlias)>,
+    #[serde(default)]
+    txts: Vec<(String, SnapshotTxt)>,
+    #[serde(default)]
+    mxs: Vec<(String, SnapshotMx)>,
+    #[serde(default)]
+    srvs: Vec<(String, SnapshotSrv)>,
+    #[serde(default)]
+    blocked: Vec<(String, Vec<SnapshotEntry>)>,
+    #[serde(default)]
+    blocked_wildcards: Vec<(String, SnapshotEntry)>,
+    /// Exact names generated for leases without a hostname
+    #[serde(default)]
+    synthesized: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct SnapshotEntry {
+    ip: IpAddr, // Snapshots from before AAAA support hold IPv4 addresses only, in the same form
+    kind: String,
+    path: PathBuf,
+    location: usize,
+    by_line: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    ttl: Option<u32>,
+}
+
+impl SnapshotEntry {
+    fn new(entry: &Entry) -> Self {
+        Self {
+            ip: entry.ip,
+            kind: entry.source.kind.as_str().to_string(),
+            path: entry.source.path.to_path_buf(),
+            location: entry.source.location,
+            by_line: entry.source.by_line,
+            ttl: entry.ttl,
+        }
+    }
+
+    fn into_entry(self, paths: &mut HashMap<PathBuf, Arc<Path>>) -> Entry {
+        Entry {
+            ip: self.ip,
+            source: source(&self.kind, self.path, self.location, self.by_line, paths),
+            ttl: self.ttl,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct SnapshotAlias {
+    target: String,
+    kind: String,
+    path: PathBuf,
+    location: usize,
+    by_line: bool,
+}
+
+impl SnapshotAlias {
+    fn new(alias: &Alias) -> Self {
+        Self {
+            target: alias.target.clone(),
+            kind: alias.source.kind.as_str().to_string(),
+            path: alias.source.path.to_path_buf(),
+            location: alias.source.location,
+            by_line: alias.source.by_line,
+        }
+    }
+
+    fn into_alias(self, paths: &mut HashMap<PathBuf, Arc<Path>>) -> Alias {
+        Alias {
+            target: self.target,
+            source: source(&self.kind, self.path, self.location, self.by_line, paths),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct SnapshotTxt {
+    strings: Vec<Vec<u8>>,
+    kind: String,
+    path: PathBuf,
+    location: usize,
+    by_line: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    ttl: Option<u32>,
+}
+
+impl SnapshotTxt {
+    fn new(txt: &TxtRecord) -> Self {
+        Self {
+            strings: txt.strings.clone(),
+            kind: txt.source.kind.as_str().to_string(),
+            path: txt.source.path.to_path_buf(),
+            location: txt.source.location,
+            by_line: txt.source.by_line,
+            ttl: txt.ttl,
+        }
+    }
+
+    fn into_txt(self, paths: &mut HashMap<PathBuf, Arc<Path>>) -> TxtRecord {
+        TxtRecord {
+            strings: self.strings,
+            source: source(&self.kind, self.path, self.location, self.by_line, paths),
+            ttl: self.ttl,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct SnapshotMx {
+    preference: u16,
+    exchange: String,
+    kind: String,
+    path: PathBuf,
+    location: usize,
+    by_line: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    ttl: Option<u32>,
+}
+
+impl SnapshotMx {
+    fn new(mx: &MxRecord) -> Self {
+        Self {
+            preference: mx.preference,
+            exchange: mx.exchange.clone(),
+            kind: mx.source.kind.as_str().to_string(),
+            path: mx.source.path.to_path_buf(),
+            location: mx.source.location,
+            by_line: mx.source.by_line,
+            ttl: mx.ttl,
+        }
+    }
+
+    fn into_mx(self, paths: &mut HashMap<PathBuf, Arc<Path>>) -> MxRecord {
+        MxRecord {
+            preference: self.preference,
+            exchange: self.exchange,
+            source: source(&self.kind, self.path, self.location, self.by_line, paths),
+            ttl: self.ttl,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct SnapshotSrv {
+    priority: u16,
+    weight: u16,
+    port: u16,
+    target: String,
+    kind: String,
+    path: PathBuf,
+    location: usize,
+    by_line: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    ttl: Option<u32>,
+}
+
+impl SnapshotSrv {
+    fn new(srv: &SrvRecord) -> Self {
+        Self {
+            priority: srv.priority,
+            weight: srv.weight,
+            port: srv.port,
+            target: srv.target.clone(),
+            kind: srv.source.kind.as_str().to_string(),
+            path: srv.source.path.to_path_buf(),
+            location: srv.source.location,
+            by_line: srv.source.by_line,
+            ttl: srv.ttl,
+        }
+    }
+
+    fn into_srv(self, paths: &mut HashMap<PathBuf, Arc<Path>>) -> SrvRecord {
+        SrvRecord {
+            priority: self.priority,
+            weight: self.weight,
+            port: self.port,
+            target: self.target,
+            source: source(&self.kind, self.path, self.location, self.by_line, paths),
+            ttl: self.ttl,
+        }
+    }
+}
+
+/// `paths` shares one allocation per file, as the loader does.
+fn source(kind: &str, path: PathBuf, location: usize, by_line: bool, paths: &mut HashMap<PathBuf, Arc<Path>>) -> Source {
+    let kind = match kind {
+        "dhcp" => SourceKind::Dhcp,
+        "config" => SourceKind::Config,
+        "database" => SourceKind::Database,
+        "reservation" => SourceKind::Reservation,
+        "zone" => SourceKind::Zone,
+        "runtime" => SourceKind::Runtime,
+        _ => SourceKind::Hosts,
+    };
+    let path = paths
+        .entry(path)
+        .or_insert_with_key(|path| Arc::from(path.as_path()))
+        .clone();
+    Source { kind, path, location, by_line }
+}
+
+/// Writes the merged records of `cache` to `path`, replacing it atomically.
+pub fn write(path: &Path, cache: &DnsCache) -> Result<(), SnapshotError> {
+    let io_error = |source| SnapshotError::Io { path: path.to_path_buf(), source };
+    let mut exact: Vec<(String, Vec<SnapshotEntry>)> = cache
+        .exact_matches
+        .iter()
+        .map(|(name, entries)| (name_key(name), entries.iter().map(SnapshotEntry::new).collect()))
+        .collect();
+    exact.sort_by(|a, b| a.0.cmp(&b.0));
+    let mut cnames: Vec<(String, SnapshotAlias)> =
+        cache.cnames.iter().map(|(name, alias)| (name_key(name), SnapshotAlias::new(alias))).collect();
+    cnames.sort_by(|a, b| a.0.cmp(&b.0));
+    let mut txts: Vec<(String, SnapshotTxt)> = cache
+        .txts
+        .iter()
+        .flat_map(|(name, txts)| txts.iter().map(|txt| (name_key(name), SnapshotTxt::new(txt))))
+        .collect();
+    txts.sort_by(|a, b| a.0.cmp(&b.0));
+    let mut mxs: Vec<(String, SnapshotMx)> = cache
+        .mxs
+        .iter()
+        .flat_map(|(name, mxs)| mxs.iter().map(|mx| (name_key(name), SnapshotMx::new(mx))))
+        .collect();
+    mxs.sort_by(|a, b| a.0.cmp(&b.0));
+    let mut srvs: Vec<(String, SnapshotSrv)> = cache
+        .srvs
+        .iter()
+        .flat_map(|(name, srvs)| srvs.iter().map(|srv| (name_key(name), SnapshotSrv::new(srv))))
+        .collect();
+    srvs.sort_by(|a, b| a.0.cmp(&b.0));
+    let mut blocked: Vec<(String, Vec<SnapshotEntry>)> = cache
+        .blocked
+        .iter()
+        .map(|(name, entries)| (name_key(name), entries.iter().map(SnapshotEntry::new).collect()))
+        .collect();
+    blocked.sort_by(|a, b| a.0.cmp(&b.0));
+    let mut synthesized: Vec<String> = cache.synthesized.iter().map(name_key).collect();
+    synthesized.sort();
+    let snapshot = Snapshot {
+        version: VERSION,
+        written_at: SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs(),
+        exact,
+        wildcards: cache
+            .wildcards
+            .iter()
+            .map(|(pattern, entry)| (pattern.clone(), SnapshotEntry::new(entry)))
+            .collect(),
+        ptrs: cache
+            .ptrs
+            .iter()
+            .map(|ptr| {
+                let entry = Entry { ip: IpAddr::V4(ptr.ip), source: ptr.source.clone(), ttl: None };
+                (ptr.name.clone(), SnapshotEntry::new(&entry))
+            })
+            .collect(),
+        cnames,
+        txts,
+        mxs,
+        srvs,
+        blocked,
+        blocked_wildcards: cache
+            .blocked_wildcards
+            .iter()
+            .map(|(pattern, entry)| (pattern.clone(), SnapshotEntry::new(entry)))
+            .collect(),
+        synthesized,
+    };
+    let json = serde_json::to_vec(&snapshot).expect("records serialize to JSON");
+
+    // Write beside the target and rename over it, so a crash never leaves a torn snapshot
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    let tmp = PathBuf::from(tmp);
+    let mut file = fs::File::create(&tmp).map_err(io_error)?;
+    file.write_all(&json).and_then(|_| file.sync_all()).map_err(io_error)?;
+    fs::rename(&tmp, path).map_err(io_error)
+}
+
+/// Reads a snapshot no older than `max_age` back into a cache marked as stale.
+pub fn read(path: &Path, max_age: Duration) -> Result<DnsCache, SnapshotError> {
+    let content = fs::read(path).map_err(|source| SnapshotError::Io {
+        path: path.to_path_buf(),
+        source,
+    })?;
+    let snapshot: Snapshot = serde_json::from_slice(&content).map_err(|source| SnapshotError::Parse {
+        path: path.to_path_buf(),
+        source,
+    })?;
+    if snapshot.version != VERSION {
+        return Err(SnapshotError::Version { path: path.to_path_buf(), found: snapshot.version });
+    }
+    let written = UNIX_EPOCH + Duration::from_secs(snapshot.written_at);
+    let age = SystemTime::now().duration_since(written).unwrap_or_default();
+    if age > max_age {
+        return Err(SnapshotError::TooOld { path: path.to_path_buf(), age });
+    }
+
+    let mut paths = HashMap::new();
+    // Names are written in key form; one that doesn't parse back is left out
+    let key = |name: &str| parse_key(name).ok();
+    let mut cache = DnsCache {
+        snapshot_written: Some(written),
+        ..DnsCache::default()
+    };
+    for (name, entries) in snapshot.exact {
+        let Some(name) = key(&name) else { continue };
+        let entries = entries.into_iter().map(|e| e.into_entry(&mut paths)).collect();
+        cache.exact_matches.insert(name, entries);
+    }
+    cache.wildcards = snapshot
+        .wildcards
+        .into_iter()
+        .map(|(pattern, e)| (pattern, e.into_entry(&mut paths)))
+        .collect();
+    cache.ptrs = snapshot
+        .ptrs
+        .into_iter()
+        .filter_map(|(name, e)| match e.into_entry(&mut paths) {
+            Entry { ip: IpAddr::V4(ip), source, .. } => Some(PtrRecord { ip, name, source }),
+            Entry { ip: IpAddr::V6(_), .. } => None, // Only IPv4 addresses have PTRs
+        })
+        .collect();
+    cache.cnames = snapshot
+        .cnames
+        .into_iter()
+        .filter_map(|(name, alias)| Some((key(&name)?, alias.into_alias(&mut paths))))
+        .collect();
+    for (name, txt) in snapshot.txts {
+        let Some(name) = key(&name) else { continue };
+        cache.txts.entry(name).or_default().push(txt.into_txt(&mut paths));
+    }
+    for (name, mx) in snapshot.mxs {
+        let Some(name) = key(&name) else { continue };
+        cache.mxs.entry(name).or_default().push(mx.into_mx(&mut paths));
+    }
+    for (name, srv) in snapshot.srvs {
+        let Some(name) = key(&name) else { continue };
+        cache.srvs.entry(name).or_default().push(srv.into_srv(&mut paths));
+    }
+    for (name, entries) in snapshot.blocked {
+        let Some(name) = key(&name) else { continue };
+        let entries = entries.into_iter().map(|e| e.into_entry(&mut paths)).collect();
+        cache.blocked.insert(name, entries);
+    }
+    cache.blocked_wildcards = snapshot
+        .blocked_wildcards
+        .into_iter()
+        .map(|(pattern, e)| (pattern, e.into_entry(&mut paths)))
+        .collect();
+    // Before the PTRs are built, as real names are preferred to generated ones
+    cache.synthesized = snapshot
+        .synthesized
+        .iter()
+        .filter_map(|name| key(name))
+        .filter(|name| cache.exact_matches.contains_key(name))
+        .collect();
+    cache.build_reverse();
+    cache.index_wildcards();
+    Ok(cache)
+}
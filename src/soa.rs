@@ -0,0 +1,238 @@
+use hickory_proto::rr::rdata::SOA;
+use hickory_proto::rr::Name;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::loader::{is_subdomain, name_key, DnsCache};
+
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum SerialScheme {
+    /// `YYYYMMDDnn`, with up to 100 changes a day before it runs ahead of the date
+    #[default]
+    Date,
+    /// Seconds since the Unix epoch
+    Epoch,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct SoaConfig {
+    /// Primary name server; defaults to `ns.<domain_suffix>`
+    pub mname: Option<String>,
+    /// Responsible mailbox in name form; defaults to `hostmaster.<domain_suffix>`
+    pub rname: Option<String>,
+    pub refresh: u32,
+    pub retry: u32,
+    pub expire: u32,
+    pub minimum: u32,
+    pub serial_scheme: SerialScheme,
+    /// Where the last serial is kept, so a restart never hands out a lower one
+    pub serial_file: Option<PathBuf>,
+}
+
+impl Default for SoaConfig {
+    fn default() -> Self {
+        Self {
+            mname: None,
+            rname: None,
+            refresh: 3600,
+            retry: 600,
+            expire: 86400,
+            minimum: 60,
+            serial_scheme: SerialScheme::Date,
+            serial_file: None,
+        }
+    }
+}
+
+/// The SOA of the `domain_suffix` zone, minus the serial, which follows the records.
+#[derive(Debug, Clone)]
+pub struct Soa {
+    pub zone: Name,
+    pub zone_key: String, // See `name_key`
+    mname: Name,
+    rname: Name,
+    refresh: i32,
+    retry: i32,
+    expire: i32,
+    minimum: u32,
+}
+
+impl Soa {
+    pub fn new(config: &SoaConfig, suffix: &str) -> Result<Self, String> {
+        let suffix = suffix.trim_matches('.');
+        let parse = |name: &str| {
+            let mut name = Name::from_ascii(name).map_err(|e| format!("invalid name {:?}: {}", name, e))?;
+            name.set_fqdn(true);
+            Ok::<Name, String>(name)
+        };
+        let mname = config.mname.clone().unwrap_or_else(|| format!("ns.{}", suffix));
+        let rname = config.rname.clone().unwrap_or_else(|| format!("hostmaster.{}", suffix));
+        let timer = |value: u32, field: &str| {
+            i32::try_from(value).map_err(|_| format!("`{}` is larger than {}", field, i32::MAX))
+        };
+        let zone = parse(if suffix.is_empty() { "." } else { suffix })?;
+        Ok(Self {
+            zone_key: name_key(&zone),
+            zone,
+            mname: parse(&mname)?,
+            rname: parse(&rname)?,
+            refresh: timer(config.refresh, "refresh")?,
+            retry: timer(config.retry, "retry")?,
+            expire: timer(config.expire, "expire")?,
+            minimum: config.minimum,
+        })
+    }
+
+    /// Whether the name with this key (see `name_key`) is the apex or below it.
+    pub fn contains(&self, key: &str) -> bool {
+        key == self.zone_key || is_subdomain(key, &self.zone_key)
+    }
+
+    /// How long a negative answer may be cached: the SOA's MINIMUM, which is
+    /// also the TTL of the SOA sent with it (RFC 2308 3).
+    pub fn negative_ttl(&self) -> u32 {
+        self.minimum
+    }
+
+    pub fn rdata(&self, serial: u32) -> SOA {
+        SOA::new(
+            self.mname.clone(),
+            self.rname.clone(),
+            serial,
+            self.refresh,
+            self.retry,
+            self.expire,
+            self.minimum,
+        )
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+struct SerialState {
+    serial: u32,
+    fingerprint: String, // SHA-256 of the record set the serial was issued for
+}
+
+/// Hands out zone serials: a new, higher one whenever the record set changes,
+/// the same one otherwise, including across restarts when `serial_file` is set.
+#[derive(Debug)]
+pub struct SerialTracker {
+    scheme: SerialScheme,
+    path: Option<PathBuf>,
+    state: Option<SerialState>,
+}
+
+impl SerialTracker {
+    pub fn new(scheme: SerialScheme, path: Option<PathBuf>) -> Self {
+        let state = path.as_deref().and_then(|path| match read_state(path) {
+            Ok(state) => state,
+            Err(e) => {
+                tracing::warn!("Ignoring serial file {:?}: {}", path, e);
+                None
+            }
+        });
+        Self { scheme, path, state }
+    }
+
+    /// The serial for `cache`, bumped and persisted if its records changed.
+    pub fn update(&mut self, cache: &DnsCache) -> u32 {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+        self.update_at(cache, now)
+    }
+
+    /// `update` with the current time given in seconds since the epoch.
+    pub fn update_at(&mut self, cache: &DnsCache, now: u64) -> u32 {
+        let fingerprint = fingerprint(cache);
+        if let Some(state) = &self.state {
+            if state.fingerprint == fingerprint {
+                return state.serial;
+            }
+        }
+
+        let base = match self.scheme {
+            SerialScheme::Date => date_serial(now),
+            SerialScheme::Epoch => u32::try_from(now).unwrap_or(u32::MAX),
+        };
+        let serial = match &self.state {
+            Some(state) if base <= state.serial => state.serial.wrapping_add(1),
+            _ => base,
+        };
+        let state = SerialState { serial, fingerprint };
+        if let Some(path) = &self.path {
+            if let Err(e) = write_state(path, &state) {
+                tracing::warn!("Failed to write serial file {:?}: {}", path, e);
+            }
+        }
+        self.state = Some(state);
+        serial
+    }
+}
+
+/// Hashes every record in a stable order, so equal record sets hash equal.
+fn fingerprint(cache: &DnsCache) -> String {
+    let mut lines: Vec<String> = Vec::new();
+    for (name, entries) in &cache.exact_matches {
+        for entry in entries {
+            lines.push(format!("exact {} {}", name_key(name), entry.ip));
+        }
+    }
+    for (pattern, entry) in &cache.wildcards {
+        lines.push(format!("wildcard {} {}", pattern, entry.ip));
+    }
+    for ptr in &cache.ptrs {
+        lines.push(format!("ptr {} {}", ptr.ip, ptr.name));
+    }
+    lines.sort();
+    let mut hasher = Sha256::new();
+    for line in &lines {
+        hasher.update(line.as_bytes());
+        hasher.update(b"\n");
+    }
+    hasher.finalize().iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// `YYYYMMDD00` for the UTC date of `now`.
+fn date_serial(now: u64) -> u32 {
+    let (year, month, day) = civil_from_days((now / 86400) as i64);
+    (year as u32) * 1_000_000 + month * 10_000 + day * 100
+}
+
+/// Converts days since 1970-01-01 to a (year, month, day) date, after
+/// Howard Hinnant's `civil_from_days`.
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+fn read_state(path: &Path) -> Result<Option<SerialState>, String> {
+    match fs::read_to_string(path) {
+        Ok(content) => serde_json::from_str(&content).map(Some).map_err(|e| e.to_string()),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+/// Written via a temporary file and a rename, like the snapshot.
+fn write_state(path: &Path, state: &SerialState) -> std::io::Result<()> {
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    let tmp = PathBuf::from(tmp);
+    let mut file = fs::File::create(&tmp)?;
+    file.write_all(serde_json::to_string(state).expect("serial state serializes").as_bytes())?;
+    file.sync_all()?;
+    fs::rename(&tmp, path)
+}
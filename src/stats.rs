@@ -0,0 +1,240 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+use tokio::sync::RwLock;
+use tokio::time::{interval, Duration};
+use hickory_proto::op::ResponseCode;
+use hickory_proto::rr::RecordType;
+
+use crate::loader::DnsCache;
+use crate::querylog::rcode_name;
+use crate::server::AnswerSource;
+
+/// Response codes counted separately; everything else is counted as `other`.
+pub(crate) const RCODES: [ResponseCode; 6] = [
+    ResponseCode::NoError,
+    ResponseCode::FormErr,
+    ResponseCode::ServFail,
+    ResponseCode::NXDomain,
+    ResponseCode::NotImp,
+    ResponseCode::Refused,
+];
+
+/// Query types counted separately; everything else is counted as `other`.
+pub(crate) const QTYPES: [RecordType; 12] = [
+    RecordType::A,
+    RecordType::AAAA,
+    RecordType::PTR,
+    RecordType::SOA,
+    RecordType::CNAME,
+    RecordType::TXT,
+    RecordType::MX,
+    RecordType::SRV,
+    RecordType::NS,
+    RecordType::SVCB,
+    RecordType::HTTPS,
+    RecordType::ANY,
+];
+
+/// Latency buckets by power of two: bucket `b` holds answers that took under
+/// 2^b microseconds, the last one everything slower.
+const LATENCY_BUCKETS: usize = 24;
+
+/// Counters shared by the listeners. Updated with relaxed atomics; readers only
+/// need eventually consistent numbers.
+#[derive(Debug, Default)]
+pub struct Stats {
+    pub tcp_connections_open: AtomicUsize,
+    pub tcp_connections_accepted: AtomicU64,
+    pub tcp_connections_shed: AtomicU64,
+    pub queries: AtomicU64,
+    /// UDP queries over their client's rate, dropped or slipped rather than answered
+    pub rate_limited: AtomicU64,
+    /// UDP queries dropped for arriving with `max_inflight_queries` in progress
+    pub overloaded: AtomicU64,
+    /// Queries left unanswered because they didn't parse or the answer
+    /// couldn't be sent; a TCP query that doesn't parse also ends its connection
+    pub errors: AtomicU64,
+    /// Forwarded queries answered from the upstream cache, and those sent upstream
+    pub upstream_cache_hits: AtomicU64,
+    pub upstream_cache_misses: AtomicU64,
+    rcodes: [AtomicU64; RCODES.len() + 1],
+    sources: [AtomicU64; AnswerSource::ALL.len()],
+    latency: [AtomicU64; LATENCY_BUCKETS],
+    qtypes: [AtomicU64; QTYPES.len() + 1],
+    pub reloads: AtomicU64,
+    pub reload_failures: AtomicU64,
+    /// Record counts as of the last load, set by whoever swaps the records in
+    pub exact_records: AtomicUsize,
+    pub wildcard_records: AtomicUsize,
+    /// Names whose sources disagree on their address
+    pub conflicting_names: AtomicUsize,
+}
+
+impl Stats {
+    /// Counts one answered query.
+    pub fn record(&self, qtype: Option<RecordType>, rcode: ResponseCode, source: AnswerSource, elapsed: Duration) {
+        self.queries.fetch_add(1, Ordering::Relaxed);
+        let qtype = QTYPES.iter().position(|t| Some(*t) == qtype).unwrap_or(QTYPES.len());
+        self.qtypes[qtype].fetch_add(1, Ordering::Relaxed);
+        let rcode = RCODES.iter().position(|r| *r == rcode).unwrap_or(RCODES.len());
+        self.rcodes[rcode].fetch_add(1, Ordering::Relaxed);
+        self.sources[source as usize].fetch_add(1, Ordering::Relaxed);
+        let micros = u64::try_from(elapsed.as_micros()).unwrap_or(u64::MAX);
+        let bucket = (64 - micros.leading_zeros() as usize).min(LATENCY_BUCKETS - 1);
+        self.latency[bucket].fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// The record counts of `records`, for the gauges.
+    pub fn set_records(&self, records: &DnsCache) {
+        self.exact_records.store(records.exact_matches.len(), Ordering::Relaxed);
+        self.wildcard_records.store(records.wildcards.len(), Ordering::Relaxed);
+        self.conflicting_names.store(records.conflicts.len(), Ordering::Relaxed);
+    }
+
+    pub(crate) fn counters(&self) -> Counters {
+        let load = |counters: &[AtomicU64]| counters.iter().map(|c| c.load(Ordering::Relaxed)).collect();
+        Counters {
+            queries: self.queries.load(Ordering::Relaxed),
+            errors: self.errors.load(Ordering::Relaxed),
+            rcodes: load(&self.rcodes),
+            sources: load(&self.sources),
+            latency: load(&self.latency),
+            qtypes: load(&self.qtypes),
+            reloads: self.reloads.load(Ordering::Relaxed),
+            cache_hits: self.upstream_cache_hits.load(Ordering::Relaxed),
+            cache_misses: self.upstream_cache_misses.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// A point-in-time copy of the query counters.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Counters {
+    pub(crate) queries: u64,
+    errors: u64,
+    pub(crate) rcodes: Vec<u64>,
+    pub(crate) sources: Vec<u64>,
+    latency: Vec<u64>,
+    pub(crate) qtypes: Vec<u64>,
+    reloads: u64,
+    cache_hits: u64,
+    cache_misses: u64,
+}
+
+impl Counters {
+    /// What was counted since `earlier`.
+    fn since(&self, earlier: &Counters) -> Counters {
+        let minus = |now: &[u64], then: &[u64]| {
+            now.iter().enumerate().map(|(i, n)| n - then.get(i).copied().unwrap_or(0)).collect()
+        };
+        Counters {
+            queries: self.queries - earlier.queries,
+            errors: self.errors - earlier.errors,
+            rcodes: minus(&self.rcodes, &earlier.rcodes),
+            sources: minus(&self.sources, &earlier.sources),
+            latency: minus(&self.latency, &earlier.latency),
+            qtypes: minus(&self.qtypes, &earlier.qtypes),
+            reloads: self.reloads - earlier.reloads,
+            cache_hits: self.cache_hits - earlier.cache_hits,
+            cache_misses: self.cache_misses - earlier.cache_misses,
+        }
+    }
+
+    /// The upper bound of the latency bucket holding the 95th percentile.
+    fn p95_micros(&self) -> Option<u64> {
+        let target = self.queries - self.queries / 20;
+        let mut seen = 0;
+        for (bucket, count) in self.latency.iter().enumerate() {
+            seen += count;
+            if seen >= target && seen > 0 {
+                return Some(1 << bucket);
+            }
+        }
+        None
+    }
+}
+
+/// Prints one `summary` line per listener every `every`, covering the queries
+/// it answered since the previous one, in the same key=value form as the query log.
+/// Reloads are counted on the first listener's stats and reported on every line.
+/// `listeners` is asked every time, since listeners can come and go.
+pub async fn summarize(
+    listeners: impl Fn() -> Vec<(String, Arc<Stats>)>,
+    records: Arc<RwLock<DnsCache>>,
+    every: Duration,
+) {
+    let mut ticker = interval(every);
+    ticker.tick().await;
+    // Keyed by the counters themselves, as a restarted listener keeps its name.
+    // Holding on to them keeps the address from being reused for another.
+    let key = |stats: &Arc<Stats>| Arc::as_ptr(stats) as usize;
+    let mut previous: HashMap<usize, (Arc<Stats>, Counters)> =
+        listeners().into_iter().map(|(_, stats)| (key(&stats), (stats.clone(), stats.counters()))).collect();
+    loop {
+        ticker.tick().await;
+        let listeners = listeners();
+        // A listener started since the last line counts from zero
+        let windows: Vec<Counters> = listeners
+            .iter()
+            .map(|(_, stats)| {
+                let current = stats.counters();
+                let window = match previous.get(&key(stats)) {
+                    Some((_, earlier)) => current.since(earlier),
+                    None => current.since(&Counters::default()),
+                };
+                previous.insert(key(stats), (stats.clone(), current));
+                window
+            })
+            .collect();
+        previous.retain(|k, _| listeners.iter().any(|(_, stats)| key(stats) == *k));
+        let reloads = windows.first().map(|w| w.reloads).unwrap_or(0);
+        let records = records.read().await;
+        for ((name, stats), window) in listeners.iter().zip(&windows) {
+            tracing::info!("{}", summary_line(name, stats, window, reloads, every, &records));
+        }
+    }
+}
+
+fn summary_line(listener: &str, stats: &Stats, window: &Counters, reloads: u64, every: Duration, records: &DnsCache) -> String {
+    let mut line = format!(
+        "summary listener={} interval_s={} queries={} errors={}",
+        listener,
+        every.as_secs(),
+        window.queries,
+        window.errors
+    );
+    for (i, count) in window.rcodes.iter().enumerate().filter(|(_, c)| **c > 0) {
+        let name = RCODES.get(i).map(|r| rcode_name(*r)).unwrap_or_else(|| "other".to_string());
+        line.push_str(&format!(" rcode.{}={}", name, count));
+    }
+    for (source, count) in AnswerSource::ALL.iter().zip(&window.sources).filter(|(_, c)| **c > 0) {
+        line.push_str(&format!(" source.{}={}", source.as_str(), count));
+    }
+    // A hit is an answer from the loaded records rather than a fallback or synthesized one
+    let hits: u64 = [AnswerSource::Exact, AnswerSource::Wildcard, AnswerSource::Alias, AnswerSource::Ptr]
+        .iter()
+        .map(|s| window.sources[*s as usize])
+        .sum();
+    if window.queries > 0 {
+        line.push_str(&format!(" hit_ratio={:.3}", hits as f64 / window.queries as f64));
+    }
+    // Only forwarded queries reach the cache
+    if window.cache_hits + window.cache_misses > 0 {
+        line.push_str(&format!(" cache_hits={} cache_misses={}", window.cache_hits, window.cache_misses));
+    }
+    if let Some(p95) = window.p95_micros() {
+        line.push_str(&format!(" p95_us={}", p95));
+    }
+    line.push_str(&format!(
+        " exact={} wildcards={} conflicts={} reloads={} failing_sources={} stale={} tcp_open={}",
+        records.exact_matches.len(),
+        records.wildcards.len(),
+        records.conflicts.len(),
+        reloads,
+        records.failures.len(),
+        records.snapshot_written.is_some(),
+        stats.tcp_connections_open.load(Ordering::Relaxed),
+    ));
+    line
+}
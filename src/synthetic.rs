@@ -0,0 +1,45 @@
+//! Records made up in memory, at whatever size a benchmark or test needs,
+//! without writing a hosts file or parsing one.
+
+use std::net::{IpAddr, Ipv4Addr};
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::loader::{table_key, DnsCache, Entry, Source, SourceKind};
+
+/// The domain every synthetic name is under.
+pub const SUFFIX: &str = "lan.";
+
+/// `exact` names `host<i>.lan.` and `wildcards` patterns `*.zone<i>.lan.`,
+/// indexed as loading would leave them. Each has one IPv4 address, 10.0.0.0
+/// plus its position, counting the wildcards on from the exact names.
+pub fn cache(exact: usize, wildcards: usize) -> DnsCache {
+    let path: Arc<Path> = Arc::from(Path::new("synthetic"));
+    let entry = |i: usize| Entry {
+        ip: IpAddr::V4(Ipv4Addr::from(0x0a00_0000 + i as u32)),
+        source: Source { kind: SourceKind::Hosts, path: path.clone(), location: i + 1, by_line: true },
+        ttl: None,
+    };
+    let mut cache = DnsCache {
+        exact_matches: (0..exact).map(|i| (table_key(&exact_name(i)), vec![entry(i)])).collect(),
+        wildcards: (0..wildcards).map(|i| (format!("*.zone{}.{}", i, SUFFIX), entry(exact + i))).collect(),
+        ..DnsCache::default()
+    };
+    cache.index_wildcards();
+    cache
+}
+
+/// The `i`th exact name of `cache`.
+pub fn exact_name(i: usize) -> String {
+    format!("host{}.{}", i, SUFFIX)
+}
+
+/// A name the `i`th wildcard of `cache` answers.
+pub fn wildcard_name(i: usize) -> String {
+    format!("www.zone{}.{}", i, SUFFIX)
+}
+
+/// A name under the suffix nothing in `cache` answers.
+pub fn missing_name() -> String {
+    format!("missing.{}", SUFFIX)
+}
@@ -0,0 +1,228 @@
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::atomic::Ordering;
+use std::sync::{Arc, Mutex};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpSocket, TcpStream};
+use tokio::sync::{Notify, RwLock};
+use tokio::time::{sleep_until, timeout, Duration, Instant};
+use hickory_proto::op::Message;
+use hickory_proto::rr::rdata::opt::{EdnsCode, EdnsOption};
+
+use crate::config::TcpConfig;
+use crate::dscp::Dscp;
+use crate::loader::DnsCache;
+use crate::server::{bind_to_device, is_response, notimp_for_opcode, resolve, ResolveOptions, ServeError};
+use crate::stats::Stats;
+
+/// Binds with SO_REUSEADDR, so connections from a previous instance lingering
+/// in TIME_WAIT don't hold the port after a restart.
+pub async fn bind(addr: &str, dscp: Option<Dscp>, interface: Option<&str>) -> Result<TcpListener, ServeError> {
+    let listen = || -> std::io::Result<TcpListener> {
+        let addr: SocketAddr = addr
+            .parse()
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?;
+        let socket = if addr.is_ipv4() { TcpSocket::new_v4()? } else { TcpSocket::new_v6()? };
+        socket.set_reuseaddr(true)?;
+        if let Some(interface) = interface {
+            bind_to_device(&socket, interface)?;
+        }
+        // Accepted connections inherit the marking from the listener
+        if let Some(dscp) = dscp {
+            dscp.apply(&socket, addr);
+        }
+        socket.bind(addr)?;
+        socket.listen(1024)
+    };
+    listen().map_err(|source| ServeError::Bind {
+        addr: addr.to_string(),
+        source,
+    })
+}
+
+struct Tracked {
+    last_active: Instant,
+    close: Arc<Notify>,
+}
+
+/// Open connections, so the idlest one can be shed when the cap is reached.
+#[derive(Default)]
+struct Connections {
+    next_id: u64,
+    open: HashMap<u64, Tracked>,
+}
+
+struct Shared {
+    records: Arc<RwLock<DnsCache>>,
+    options: Arc<ResolveOptions>,
+    config: TcpConfig,
+    stats: Arc<Stats>,
+    connections: Mutex<Connections>,
+}
+
+pub async fn serve(
+    listener: TcpListener,
+    records: Arc<RwLock<DnsCache>>,
+    options: Arc<ResolveOptions>,
+    config: TcpConfig,
+    stats: Arc<Stats>,
+) -> Result<(), ServeError> {
+    let shared = Arc::new(Shared {
+        records,
+        options,
+        config,
+        stats,
+        connections: Mutex::new(Connections::default()),
+    });
+
+    loop {
+        let (stream, peer) = match listener.accept().await {
+            Ok(x) => x,
+            Err(e) => {
+                tracing::error!("Failed to accept TCP connection: {}", e);
+                continue;
+            }
+        };
+
+        let close = Arc::new(Notify::new());
+        let id = {
+            let mut connections = shared.connections.lock().unwrap();
+            let max = shared.config.max_connections;
+            if max > 0 && connections.open.len() >= max {
+                let idlest = connections
+                    .open
+                    .iter()
+                    .min_by_key(|(_, tracked)| tracked.last_active)
+                    .map(|(id, _)| *id);
+                if let Some(tracked) = idlest.and_then(|id| connections.open.remove(&id)) {
+                    // notify_one stores a permit, so the connection sees it even if it isn't waiting yet
+                    tracked.close.notify_one();
+                    shared.stats.tcp_connections_shed.fetch_add(1, Ordering::Relaxed);
+                    tracing::warn!("TCP connection limit ({}) reached, closing the idlest connection", max);
+                }
+            }
+            let id = connections.next_id;
+            connections.next_id += 1;
+            connections.open.insert(id, Tracked { last_active: Instant::now(), close: close.clone() });
+            id
+        };
+        shared.stats.tcp_connections_accepted.fetch_add(1, Ordering::Relaxed);
+        shared.stats.tcp_connections_open.fetch_add(1, Ordering::Relaxed);
+
+        let shared = shared.clone();
+        tokio::spawn(async move {
+            if let Err(e) = handle_connection(stream, peer, id, &shared, close).await {
+                tracing::error!("Error handling TCP connection from {}: {}", peer, e);
+            }
+            shared.connections.lock().unwrap().open.remove(&id);
+            shared.stats.tcp_connections_open.fetch_sub(1, Ordering::Relaxed);
+        });
+    }
+}
+
+async fn handle_connection(
+    mut stream: TcpStream,
+    peer: SocketAddr,
+    id: u64,
+    shared: &Shared,
+    close: Arc<Notify>,
+) -> Result<(), ServeError> {
+    let config = &shared.config;
+    let idle_timeout = Duration::from_secs(config.idle_timeout);
+    let lifetime_deadline = (config.max_lifetime > 0)
+        .then(|| Instant::now() + Duration::from_secs(config.max_lifetime));
+    let mut served: u32 = 0;
+
+    loop {
+        let frame = tokio::select! {
+            _ = close.notified() => break,
+            _ = sleep_until(lifetime_deadline.unwrap_or_else(Instant::now)), if lifetime_deadline.is_some() => break,
+            frame = timeout(idle_timeout, read_frame(&mut stream)) => match frame {
+                Ok(Ok(Some(frame))) => frame,
+                Ok(Ok(None)) | Err(_) => break, // Closed by the client, or idle for too long
+                Ok(Err(e)) => return Err(e.into()),
+            },
+        };
+
+        let started = Instant::now();
+        if is_response(&frame) {
+            tracing::debug!("Ignoring a response sent over TCP by {}", peer);
+            continue;
+        }
+        let request = match Message::from_vec(&frame) {
+            Ok(request) => request,
+            Err(e) => match notimp_for_opcode(&frame) {
+                Some(response) => {
+                    write_frame(&mut stream, &response).await?;
+                    continue;
+                }
+                None => {
+                    // Nothing to answer and no telling what the client meant, so hang up
+                    shared.stats.errors.fetch_add(1, Ordering::Relaxed);
+                    tracing::debug!("Closing the TCP connection from {} after a malformed query: {}", peer, e);
+                    break;
+                }
+            },
+        };
+        let (mut response, source) = resolve(&request, Some(peer), &shared.records, &shared.options).await?;
+        let elapsed = started.elapsed();
+        shared.options.query_log.record(&shared.options.view.listener, Some(peer), &request, &response, source, elapsed);
+        shared.stats.record(request.queries().first().map(|q| q.query_type()), response.response_code(), source, elapsed);
+        if wants_keepalive(&request) {
+            add_keepalive(&mut response, config.idle_timeout);
+        }
+
+        write_frame(&mut stream, &response.to_vec()?).await?;
+
+        if let Some(tracked) = shared.connections.lock().unwrap().open.get_mut(&id) {
+            tracked.last_active = Instant::now();
+        }
+
+        served += 1;
+        if config.max_queries > 0 && served >= config.max_queries {
+            break;
+        }
+    }
+    Ok(())
+}
+
+/// Reads one length-prefixed DNS message, or `None` if the peer closed the connection.
+async fn read_frame(stream: &mut TcpStream) -> std::io::Result<Option<Vec<u8>>> {
+    let mut len = [0u8; 2];
+    match stream.read_exact(&mut len).await {
+        Ok(_) => {}
+        Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e),
+    }
+    let mut frame = vec![0u8; u16::from_be_bytes(len) as usize];
+    stream.read_exact(&mut frame).await?;
+    Ok(Some(frame))
+}
+
+/// Writes `message` with its two-byte length prefix.
+async fn write_frame(stream: &mut TcpStream, message: &[u8]) -> std::io::Result<()> {
+    let len = u16::try_from(message.len()).map_err(|_| {
+        std::io::Error::new(std::io::ErrorKind::InvalidData, "response exceeds 65535 bytes")
+    })?;
+    stream.write_all(&len.to_be_bytes()).await?;
+    stream.write_all(message).await
+}
+
+/// RFC 7828: clients signal support by sending an empty edns-tcp-keepalive option.
+fn wants_keepalive(request: &Message) -> bool {
+    matches!(
+        request.extensions().as_ref().and_then(|edns| edns.option(EdnsCode::Keepalive)),
+        Some(option) if option.is_empty()
+    )
+}
+
+/// Tells the client how long it may keep the connection idle, in units of 100ms.
+fn add_keepalive(response: &mut Message, idle_timeout_secs: u64) {
+    let timeout = u16::try_from(idle_timeout_secs.saturating_mul(10)).unwrap_or(u16::MAX);
+    let mut edns = response.extensions().clone().unwrap_or_default();
+    edns.options_mut().insert(EdnsOption::Unknown(
+        u16::from(EdnsCode::Keepalive),
+        timeout.to_be_bytes().to_vec(),
+    ));
+    response.set_edns(edns);
+}
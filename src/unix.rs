@@ -0,0 +1,101 @@
+use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+use std::path::Path;
+use std::sync::Arc;
+use std::sync::atomic::Ordering;
+use tokio::net::UnixDatagram;
+use tokio::sync::RwLock;
+use tokio::time::Instant;
+use hickory_proto::op::Message;
+
+use crate::config::UnixConfig;
+use crate::loader::DnsCache;
+use crate::server::{is_response, notimp_for_opcode, resolve, ResolveOptions, ServeError};
+
+/// Binds the datagram socket at `path`, replacing a socket file left behind by
+/// a previous instance, then applies the configured mode and ownership.
+pub fn bind(path: &Path, config: &UnixConfig) -> Result<UnixDatagram, ServeError> {
+    let listen = || -> std::io::Result<UnixDatagram> {
+        match std::fs::symlink_metadata(path) {
+            Ok(metadata) if metadata.file_type().is_socket() => std::fs::remove_file(path)?,
+            Ok(_) => {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::AlreadyExists,
+                    "the path exists and is not a socket",
+                ))
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+        let socket = UnixDatagram::bind(path)?;
+        if let Some(mode) = config.mode {
+            std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode))?;
+        }
+        if config.uid.is_some() || config.gid.is_some() {
+            std::os::unix::fs::chown(path, config.uid, config.gid)?;
+        }
+        Ok(socket)
+    };
+    listen().map_err(|source| ServeError::Bind {
+        addr: path.display().to_string(),
+        source,
+    })
+}
+
+/// Answers DNS wire messages on `socket` like the UDP listener does, replying
+/// to each sender's socket path. Senders that didn't bind a path can't be
+/// answered and are skipped.
+pub async fn serve(
+    socket: UnixDatagram,
+    records: Arc<RwLock<DnsCache>>,
+    options: ResolveOptions,
+) -> Result<(), ServeError> {
+    let socket = Arc::new(socket);
+    let options = Arc::new(options);
+
+    let mut buf = [0u8; 4096];
+    loop {
+        let (len, peer) = match socket.recv_from(&mut buf).await {
+            Ok(x) => x,
+            Err(e) => {
+                tracing::error!("Failed to receive unix datagram: {}", e);
+                continue;
+            }
+        };
+        let Some(peer) = peer.as_pathname().map(Path::to_path_buf) else {
+            tracing::warn!("Ignoring unix datagram from an unbound socket, which can't be answered");
+            continue;
+        };
+
+        let data = buf[..len].to_vec();
+        let (socket, records, options) = (socket.clone(), records.clone(), options.clone());
+        tokio::spawn(async move {
+            let started = Instant::now();
+            let answered = async {
+                if is_response(&data) {
+                    tracing::debug!("Ignoring a response sent by {:?}", peer);
+                    return Ok(());
+                }
+                let request = match Message::from_vec(&data) {
+                    Ok(request) => request,
+                    Err(e) => match notimp_for_opcode(&data) {
+                        Some(response) => {
+                            socket.send_to(&response, &peer).await?;
+                            return Ok(());
+                        }
+                        None => return Err(e.into()),
+                    },
+                };
+                let (response, source) = resolve(&request, None, &records, &options).await?;
+                let elapsed = started.elapsed();
+                options.query_log.record(&options.view.listener, None, &request, &response, source, elapsed);
+                options.stats.record(request.queries().first().map(|q| q.query_type()), response.response_code(), source, elapsed);
+                socket.send_to(&response.to_vec()?, &peer).await?;
+                Ok::<_, ServeError>(())
+            };
+            if let Err(e) = answered.await {
+                options.stats.errors.fetch_add(1, Ordering::Relaxed);
+                tracing::error!("Error handling query from {:?}: {}", peer, e);
+            }
+        });
+    }
+}
@@ -0,0 +1,125 @@
+use serde::Deserialize;
+use std::net::Ipv4Addr;
+use std::sync::Arc;
+
+use crate::loader::{table_key, Alias, DnsCache, Entry, MxRecord, SourceKind, SrvRecord, TxtRecord};
+use crate::server::ResolveOptions;
+use crate::stats::Stats;
+
+/// The name of the listener on `listen_address`/`listen_port`, which always
+/// answers with the full records.
+pub const DEFAULT_LISTENER: &str = "default";
+
+/// The name of the listener on `listen_unix`.
+pub const UNIX_LISTENER: &str = "unix";
+
+/// An extra socket with its own answering policy, e.g. for a guest VLAN.
+#[derive(Deserialize, Debug, Clone)]
+pub struct ListenerConfig {
+    pub name: String,
+    /// Socket address, e.g. `"192.168.50.1:53"` or `"[fd00::1]:53"`
+    pub address: String,
+    /// Key into `[views]`; the full, default view when unset
+    pub view: Option<String>,
+}
+
+/// What a listener's clients can see.
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct ViewConfig {
+    /// Record sources answered from; the others are invisible to this view
+    pub sources: Vec<SourceKind>,
+    /// Answer unknown names with `fallback_ip`
+    pub fallback: bool,
+    /// Answer PTR queries
+    pub reverse: bool,
+    /// Advertise DDR endpoints
+    pub ddr: bool,
+}
+
+impl Default for ViewConfig {
+    fn default() -> Self {
+        Self {
+            sources: vec![SourceKind::Dhcp, SourceKind::Hosts, SourceKind::Config, SourceKind::Database, SourceKind::Reservation, SourceKind::Zone, SourceKind::Runtime],
+            fallback: true,
+            reverse: true,
+            ddr: true,
+        }
+    }
+}
+
+impl ViewConfig {
+    /// `options` as seen through this view on the listener `name`, with
+    /// counters of its own.
+    pub fn apply(&self, name: &str, options: &ResolveOptions) -> ResolveOptions {
+        let mut options = options.clone();
+        if !self.fallback {
+            options.fallback_ip = None;
+        }
+        if !self.ddr {
+            options.ddr = None;
+        }
+        options.view = Arc::new(View {
+            listener: name.to_string(),
+            sources: self.sources.clone(),
+            reverse: self.reverse,
+        });
+        options.stats = Arc::new(Stats::default());
+        options
+    }
+}
+
+/// The part of a view that filters records while answering.
+#[derive(Debug, Clone)]
+pub struct View {
+    pub listener: String,
+    sources: Vec<SourceKind>,
+    pub reverse: bool,
+}
+
+impl Default for View {
+    fn default() -> Self {
+        let config = ViewConfig::default();
+        Self {
+            listener: DEFAULT_LISTENER.to_string(),
+            sources: config.sources,
+            reverse: config.reverse,
+        }
+    }
+}
+
+impl View {
+    pub fn shows(&self, entry: &Entry) -> bool {
+        self.sources.contains(&entry.source.kind)
+    }
+
+    pub fn shows_alias(&self, alias: &Alias) -> bool {
+        self.sources.contains(&alias.source.kind)
+    }
+
+    pub fn shows_txt(&self, txt: &TxtRecord) -> bool {
+        self.sources.contains(&txt.source.kind)
+    }
+
+    pub fn shows_mx(&self, mx: &MxRecord) -> bool {
+        self.sources.contains(&mx.source.kind)
+    }
+
+    pub fn shows_srv(&self, srv: &SrvRecord) -> bool {
+        self.sources.contains(&srv.source.kind)
+    }
+
+    /// Whether `name` may be given as a PTR for `ip`: it is an explicit PTR, or
+    /// a forward record for the address, from a source this view shows.
+    pub fn shows_ptr(&self, cache: &DnsCache, ip: Ipv4Addr, name: &str) -> bool {
+        let explicit = cache
+            .ptrs
+            .iter()
+            .any(|ptr| ptr.ip == ip && ptr.name == name && self.sources.contains(&ptr.source.kind));
+        explicit
+            || cache
+                .exact_matches
+                .get(&table_key(name))
+                .is_some_and(|entries| entries.iter().any(|e| e.ip == ip && self.shows(e)))
+    }
+}
@@ -0,0 +1,411 @@
+use serde::Deserialize;
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tokio::time::{sleep, timeout_at, Duration, Instant};
+
+/// Quiet time after the last event before a batch of changes is sent, so a
+/// burst of writes, or a write followed by a rename, reloads once.
+pub const DEBOUNCE: Duration = Duration::from_millis(200);
+
+/// Longest a batch is held back while events keep arriving.
+const MAX_DELAY: Duration = Duration::from_secs(2);
+
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum WatchMode {
+    /// Change events from the kernel (inotify on Linux), polling where they aren't available
+    #[default]
+    Events,
+    /// Compare each file's metadata every `watch_interval`, for filesystems that
+    /// don't deliver events, such as NFS
+    Poll,
+}
+
+/// Something to notice changes to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Target {
+    /// A file, which may be created, replaced or removed
+    File(PathBuf),
+    /// The files in a directory whose names end in `extension`, including
+    /// ones added or removed later. Changes are reported as the directory.
+    Dir { path: PathBuf, extension: String },
+}
+
+impl Target {
+    pub fn path(&self) -> &Path {
+        match self {
+            Target::File(path) | Target::Dir { path, .. } => path,
+        }
+    }
+
+    /// What polling compares: for each file, its modification time, size and
+    /// inode, or None while it is missing.
+    fn stamp(&self) -> Vec<(OsString, Stamp)> {
+        match self {
+            Target::File(path) => vec![(OsString::new(), stamp(path))],
+            Target::Dir { path, extension } => {
+                let mut stamps: Vec<(OsString, Stamp)> = std::fs::read_dir(path)
+                    .into_iter()
+                    .flatten()
+                    .flatten()
+                    .filter(|entry| entry.file_name().to_string_lossy().ends_with(extension.as_str()))
+                    .map(|entry| (entry.file_name(), stamp(&entry.path())))
+                    .collect();
+                stamps.sort();
+                stamps
+            }
+        }
+    }
+}
+
+type Stamp = Option<(Option<SystemTime>, u64, u64)>;
+
+fn stamp(path: &Path) -> Stamp {
+    let metadata = std::fs::metadata(path).ok()?;
+    #[cfg(unix)]
+    let inode = std::os::unix::fs::MetadataExt::ino(&metadata);
+    #[cfg(not(unix))]
+    let inode = 0;
+    Some((metadata.modified().ok(), metadata.len(), inode))
+}
+
+/// The batches of changed paths from `watch` or `watch_targets`. Watching
+/// stops at `shutdown` or when this is dropped: the event thread is woken to
+/// close its descriptor and exit, rather than waiting for another change.
+pub struct Watcher {
+    batches: mpsc::Receiver<Vec<PathBuf>>,
+    tasks: Vec<JoinHandle<()>>,
+    events: Option<events::Stop>,
+}
+
+impl Watcher {
+    /// The next batch, or None once the watcher has been shut down.
+    pub async fn recv(&mut self) -> Option<Vec<PathBuf>> {
+        self.batches.recv().await
+    }
+
+    pub fn shutdown(&mut self) {
+        if let Some(events) = self.events.take() {
+            events.stop();
+        }
+        for task in self.tasks.drain(..) {
+            task.abort();
+        }
+        self.batches.close();
+    }
+}
+
+impl Drop for Watcher {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
+
+/// Watches the files at `paths`, which need not exist yet, and sends the ones
+/// that changed, one batch per burst of changes. Must be called from within the runtime.
+pub fn watch(paths: Vec<PathBuf>, mode: WatchMode, interval: Duration) -> Watcher {
+    watch_targets(paths.into_iter().map(Target::File).collect(), mode, interval)
+}
+
+/// Like `watch`, for directories as well as files.
+pub fn watch_targets(targets: Vec<Target>, mode: WatchMode, interval: Duration) -> Watcher {
+    let (changed_tx, changed_rx) = mpsc::unbounded_channel();
+    let (polled, events) = match mode {
+        WatchMode::Events => events::spawn(&targets, changed_tx.clone(), interval),
+        WatchMode::Poll => (targets, None),
+    };
+    let mut tasks = Vec::new();
+    if !polled.is_empty() {
+        tasks.push(tokio::spawn(poll(polled, interval, changed_tx)));
+    }
+    let (batch_tx, batches) = mpsc::channel(16);
+    tasks.push(tokio::spawn(debounce(changed_rx, batch_tx)));
+    Watcher { batches, tasks, events }
+}
+
+/// Sends the path of each target whose stamp differs from the previous check,
+/// until the watcher is shut down.
+async fn poll(targets: Vec<Target>, interval: Duration, changed: mpsc::UnboundedSender<PathBuf>) {
+    let mut last: Vec<_> = targets.iter().map(Target::stamp).collect();
+    loop {
+        sleep(interval).await;
+        if changed.is_closed() {
+            return;
+        }
+        for (target, last) in targets.iter().zip(last.iter_mut()) {
+            let current = target.stamp();
+            if current != *last {
+                *last = current;
+                if changed.send(target.path().to_path_buf()).is_err() {
+                    return;
+                }
+            }
+        }
+    }
+}
+
+/// Collects changed paths until `DEBOUNCE` passes without another one.
+async fn debounce(mut changed: mpsc::UnboundedReceiver<PathBuf>, batches: mpsc::Sender<Vec<PathBuf>>) {
+    while let Some(first) = changed.recv().await {
+        let deadline = Instant::now() + MAX_DELAY;
+        let mut batch = vec![first];
+        loop {
+            let quiet = (Instant::now() + DEBOUNCE).min(deadline);
+            match timeout_at(quiet, changed.recv()).await {
+                Ok(Some(path)) => {
+                    if !batch.contains(&path) {
+                        batch.push(path);
+                    }
+                }
+                Ok(None) | Err(_) => break,
+            }
+        }
+        if batches.send(batch).await.is_err() {
+            return;
+        }
+    }
+}
+
+/// The directory holding `path`, which is what gets watched so that the file
+/// can be created, replaced or removed.
+fn parent(path: &Path) -> PathBuf {
+    match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir.to_path_buf(),
+        _ => PathBuf::from("."),
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod events {
+    use super::{parent, poll, Target};
+    use std::collections::HashMap;
+    use std::ffi::{CString, OsStr, OsString};
+    use std::fs::File;
+    use std::io::{Read, Write};
+    use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+    use std::os::unix::ffi::OsStrExt;
+    use std::path::Path;
+    use std::time::Duration;
+    use tokio::sync::mpsc;
+
+    const MASK: u32 = libc::IN_CREATE
+        | libc::IN_MODIFY
+        | libc::IN_ATTRIB
+        | libc::IN_CLOSE_WRITE
+        | libc::IN_MOVED_FROM
+        | libc::IN_MOVED_TO
+        | libc::IN_DELETE;
+
+    /// Size of `struct inotify_event` before the name.
+    const HEADER: usize = 16;
+
+    /// Which entries of a watched directory an event has to name.
+    enum Matcher {
+        Name(OsString),
+        Extension(String),
+    }
+
+    impl Matcher {
+        fn matches(&self, name: &OsStr) -> bool {
+            match self {
+                Matcher::Name(wanted) => wanted == name,
+                Matcher::Extension(extension) => !name.is_empty() && name.to_string_lossy().ends_with(extension.as_str()),
+            }
+        }
+    }
+
+    type Watches = HashMap<i32, Vec<(Matcher, Target)>>;
+
+    /// Wakes the event thread to make it exit; an eventfd it polls alongside inotify.
+    pub struct Stop(File);
+
+    impl Stop {
+        pub fn stop(mut self) {
+            if let Err(e) = self.0.write_all(&1u64.to_ne_bytes()) {
+                tracing::warn!("Can't stop watching for file changes ({})", e);
+            }
+        }
+    }
+
+    fn open(fd: i32) -> std::io::Result<File> {
+        if fd < 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        // SAFETY: fd is a freshly opened descriptor owned by nothing else
+        Ok(File::from(unsafe { OwnedFd::from_raw_fd(fd) }))
+    }
+
+    /// Watches the directories holding each target from a blocking thread, and
+    /// a directory target itself as well. Returns the targets that couldn't be
+    /// watched, for the caller to poll instead, and what stops the thread.
+    pub fn spawn(targets: &[Target], changed: mpsc::UnboundedSender<std::path::PathBuf>, interval: Duration) -> (Vec<Target>, Option<Stop>) {
+        // SAFETY: neither call takes pointers; the results are checked before use
+        let opened = open(unsafe { libc::inotify_init1(libc::IN_CLOEXEC) })
+            .and_then(|inotify| Ok((inotify, open(unsafe { libc::eventfd(0, libc::EFD_CLOEXEC) })?)));
+        let (inotify, wakeup) = match opened {
+            Ok(opened) => opened,
+            Err(e) => {
+                tracing::warn!("File change events unavailable ({}), polling instead", e);
+                return (targets.to_vec(), None);
+            }
+        };
+
+        let mut watches: Watches = HashMap::new();
+        let mut polled = Vec::new();
+        for target in targets {
+            let path = target.path();
+            let Some(name) = path.file_name() else {
+                polled.push(target.clone());
+                continue;
+            };
+            let mut wanted = vec![(parent(path), Matcher::Name(name.to_os_string()))];
+            if let Target::Dir { extension, .. } = target {
+                wanted.push((path.to_path_buf(), Matcher::Extension(extension.clone())));
+            }
+            let added: Option<Vec<(i32, Matcher)>> = wanted
+                .into_iter()
+                .map(|(dir, matcher)| match add_watch(&inotify, &dir) {
+                    Ok(wd) => Some((wd, matcher)),
+                    Err(e) => {
+                        tracing::warn!("Can't watch {:?} for changes ({}), polling {:?} instead", dir, e, path);
+                        None
+                    }
+                })
+                .collect();
+            let Some(added) = added else {
+                polled.push(target.clone());
+                continue;
+            };
+            for (wd, matcher) in added {
+                watches.entry(wd).or_default().push((matcher, target.clone()));
+            }
+        }
+
+        if watches.is_empty() {
+            return (polled, None);
+        }
+        let stop = match wakeup.try_clone() {
+            Ok(stop) => Stop(stop),
+            Err(e) => {
+                tracing::warn!("File change events unavailable ({}), polling instead", e);
+                return (targets.to_vec(), None);
+            }
+        };
+        let runtime = tokio::runtime::Handle::current();
+        std::thread::spawn(move || read_events(inotify, wakeup, watches, changed, runtime, interval));
+        (polled, Some(stop))
+    }
+
+    /// Blocks until `inotify` has events, or returns false once `wakeup` has been written to.
+    fn wait(inotify: &File, wakeup: &File) -> std::io::Result<bool> {
+        let mut fds = [inotify, wakeup].map(|file| libc::pollfd { fd: file.as_raw_fd(), events: libc::POLLIN, revents: 0 });
+        loop {
+            // SAFETY: fds is a valid array of two pollfd for the duration of the call
+            if unsafe { libc::poll(fds.as_mut_ptr(), 2, -1) } >= 0 {
+                return Ok(fds[1].revents == 0);
+            }
+            let e = std::io::Error::last_os_error();
+            if e.kind() != std::io::ErrorKind::Interrupted {
+                return Err(e);
+            }
+        }
+    }
+
+    fn add_watch(inotify: &File, dir: &Path) -> std::io::Result<i32> {
+        let c_dir = CString::new(dir.as_os_str().as_bytes()).map_err(std::io::Error::other)?;
+        // SAFETY: c_dir is a valid NUL-terminated string for the duration of the call
+        let wd = unsafe { libc::inotify_add_watch(inotify.as_raw_fd(), c_dir.as_ptr(), MASK) };
+        if wd < 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        Ok(wd)
+    }
+
+    /// Sends the paths of the targets named by each event until the watcher is
+    /// stopped or the receiver goes away. Both descriptors close on return.
+    fn read_events(
+        mut inotify: File,
+        wakeup: File,
+        mut watches: Watches,
+        changed: mpsc::UnboundedSender<std::path::PathBuf>,
+        runtime: tokio::runtime::Handle,
+        interval: Duration,
+    ) {
+        let mut buffer = [0u8; 4096];
+        loop {
+            let ready = wait(&inotify, &wakeup);
+            if matches!(ready, Ok(false)) {
+                return;
+            }
+            let read = match ready.and_then(|_| inotify.read(&mut buffer)) {
+                Ok(read) => read,
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+                Err(e) => {
+                    tracing::warn!("Reading file change events failed ({}), polling instead", e);
+                    let mut targets: Vec<Target> = Vec::new();
+                    for (_, target) in watches.into_values().flatten() {
+                        if !targets.contains(&target) {
+                            targets.push(target);
+                        }
+                    }
+                    runtime.spawn(poll(targets, interval, changed));
+                    return;
+                }
+            };
+            let mut offset = 0;
+            while offset + HEADER <= read {
+                let field = |at: usize| <[u8; 4]>::try_from(&buffer[offset + at..offset + at + 4]).unwrap();
+                let wd = i32::from_ne_bytes(field(0));
+                let mask = u32::from_ne_bytes(field(4));
+                let len = u32::from_ne_bytes(field(12)) as usize;
+                let name = &buffer[offset + HEADER..(offset + HEADER + len).min(read)];
+                let name = OsStr::from_bytes(name.split(|&b| b == 0).next().unwrap_or_default());
+                offset += HEADER + len;
+
+                if mask & libc::IN_IGNORED != 0 {
+                    // A watched directory went away; look for its targets by polling from now on
+                    if let Some(lost) = watches.remove(&wd) {
+                        let targets: Vec<Target> = lost.into_iter().map(|(_, target)| target).collect();
+                        for target in &targets {
+                            tracing::warn!("Stopped receiving change events for {:?}, polling instead", target.path());
+                            let _ = changed.send(target.path().to_path_buf());
+                        }
+                        runtime.spawn(poll(targets, interval, changed.clone()));
+                    }
+                    continue;
+                }
+                let Some(watched) = watches.get(&wd) else { continue };
+                for (matcher, target) in watched {
+                    if matcher.matches(name) && changed.send(target.path().to_path_buf()).is_err() {
+                        return;
+                    }
+                }
+            }
+            if watches.is_empty() || changed.is_closed() {
+                return;
+            }
+        }
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+mod events {
+    use super::Target;
+    use std::path::PathBuf;
+    use std::time::Duration;
+    use tokio::sync::mpsc;
+
+    pub struct Stop;
+
+    impl Stop {
+        pub fn stop(self) {}
+    }
+
+    /// No event source on this platform, so every target is polled.
+    pub fn spawn(targets: &[Target], _changed: mpsc::UnboundedSender<PathBuf>, _interval: Duration) -> (Vec<Target>, Option<Stop>) {
+        (targets.to_vec(), None)
+    }
+}
@@ -0,0 +1,167 @@
+use serde::Deserialize;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::net::UdpSocket;
+use tokio::sync::{mpsc, watch};
+use tokio::time::{sleep, timeout, Duration, Instant};
+use hickory_proto::op::{Message, Query};
+use hickory_proto::rr::{Name, RecordType};
+
+/// Name asked by the probe. Under `.invalid`, so it can never be a real record.
+const PROBE_NAME: &str = "localdns-watchdog.invalid.";
+
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum WatchdogAction {
+    /// Rebind the UDP socket, exiting if that fails or doesn't help
+    #[default]
+    Rebind,
+    /// Exit non-zero right away and leave recovery to the supervisor
+    Exit,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct WatchdogConfig {
+    pub enabled: bool,
+    /// Seconds between probes
+    pub interval: u64,
+    /// Seconds to wait for the probe's answer
+    pub timeout: u64,
+    /// Consecutive failed probes before acting
+    pub failure_threshold: u32,
+    pub action: WatchdogAction,
+}
+
+impl Default for WatchdogConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            interval: 30,
+            timeout: 2,
+            failure_threshold: 3,
+            action: WatchdogAction::Rebind,
+        }
+    }
+}
+
+/// Probe results, exposed on `/healthz`.
+#[derive(Debug)]
+pub struct Health {
+    pub enabled: bool,
+    pub failure_threshold: u32,
+    pub consecutive_failures: AtomicU32,
+    pub probes: AtomicU64,
+    pub failures: AtomicU64,
+    pub rebinds: AtomicU64,
+    pub last_success: Mutex<Option<Instant>>,
+}
+
+impl Health {
+    pub fn new(config: &WatchdogConfig) -> Self {
+        Self {
+            enabled: config.enabled,
+            failure_threshold: config.failure_threshold,
+            consecutive_failures: AtomicU32::new(0),
+            probes: AtomicU64::new(0),
+            failures: AtomicU64::new(0),
+            rebinds: AtomicU64::new(0),
+            last_success: Mutex::new(None),
+        }
+    }
+
+    /// Healthy until the failure threshold is reached.
+    pub fn is_healthy(&self) -> bool {
+        !self.enabled || self.consecutive_failures.load(Ordering::Relaxed) < self.failure_threshold
+    }
+}
+
+/// What the serving loop should do about a deaf socket.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Recovery {
+    Rebind,
+    Exit,
+}
+
+/// The address to probe; wildcard listen addresses are reached over loopback.
+pub fn probe_target(listen: SocketAddr) -> SocketAddr {
+    let ip = match listen.ip() {
+        IpAddr::V4(ip) if ip.is_unspecified() => IpAddr::V4(Ipv4Addr::LOCALHOST),
+        IpAddr::V6(ip) if ip.is_unspecified() => IpAddr::V6(Ipv6Addr::LOCALHOST),
+        ip => ip,
+    };
+    SocketAddr::new(ip, listen.port())
+}
+
+/// Probes `target` forever, asking for `Recovery` once the threshold is reached.
+/// A rebind that isn't followed by a successful probe escalates to `Exit`.
+/// `target` is read before every probe, so it can follow a moved listener.
+pub async fn run(target: watch::Receiver<SocketAddr>, config: WatchdogConfig, health: Arc<Health>, recover: mpsc::Sender<Recovery>) {
+    let mut rebound = false;
+    loop {
+        sleep(Duration::from_secs(config.interval)).await;
+        let target = *target.borrow();
+        health.probes.fetch_add(1, Ordering::Relaxed);
+        match probe(target, Duration::from_secs(config.timeout)).await {
+            Ok(()) => {
+                health.consecutive_failures.store(0, Ordering::Relaxed);
+                *health.last_success.lock().unwrap() = Some(Instant::now());
+                rebound = false;
+                continue;
+            }
+            Err(e) => {
+                health.failures.fetch_add(1, Ordering::Relaxed);
+                let failures = health.consecutive_failures.fetch_add(1, Ordering::Relaxed) + 1;
+                tracing::warn!("Watchdog: probe of {} failed ({}/{}): {}", target, failures, config.failure_threshold, e);
+                if failures < config.failure_threshold {
+                    continue;
+                }
+            }
+        }
+
+        let action = if config.action == WatchdogAction::Exit || rebound {
+            Recovery::Exit
+        } else {
+            Recovery::Rebind
+        };
+        tracing::error!("Watchdog: {} is not answering its own queries, {}", target, match action {
+            Recovery::Rebind => "rebinding the socket",
+            Recovery::Exit => "exiting so the supervisor can restart the server",
+        });
+        if recover.send(action).await.is_err() {
+            return;
+        }
+        rebound = true;
+        health.consecutive_failures.store(0, Ordering::Relaxed);
+    }
+}
+
+async fn probe(target: SocketAddr, wait: Duration) -> std::io::Result<()> {
+    let local: SocketAddr = if target.is_ipv4() {
+        (Ipv4Addr::UNSPECIFIED, 0).into()
+    } else {
+        (Ipv6Addr::UNSPECIFIED, 0).into()
+    };
+    let socket = UdpSocket::bind(local).await?;
+    socket.connect(target).await?;
+
+    let id: u16 = rand::random();
+    let mut query = Message::new();
+    query.set_id(id);
+    query.add_query(Query::query(Name::from_ascii(PROBE_NAME).expect("valid probe name"), RecordType::A));
+    let bytes = query.to_vec().map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+    socket.send(&bytes).await?;
+
+    let mut buf = [0u8; 512];
+    let deadline = Instant::now() + wait;
+    loop {
+        let len = timeout(deadline.saturating_duration_since(Instant::now()), socket.recv(&mut buf))
+            .await
+            .map_err(|_| std::io::Error::new(std::io::ErrorKind::TimedOut, "no answer"))??;
+        // Anything with our ID will do; the rcode says nothing about liveness
+        if len >= 2 && u16::from_be_bytes([buf[0], buf[1]]) == id {
+            return Ok(());
+        }
+    }
+}
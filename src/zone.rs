@@ -0,0 +1,284 @@
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+use crate::loader::{name_key, parse_presentation};
+
+/// Longest character-string a TXT record can hold (RFC 1035 3.3).
+const MAX_CHARACTER_STRING: usize = 255;
+
+/// A record read from a zone file. `name` is in key form (see `name_key`)
+/// and starts with `*.` for a wildcard.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ZoneRecord {
+    /// 1-based line the record starts on
+    pub line: usize,
+    pub name: String,
+    /// From the record itself, or `$TTL`; the `ttl` setting applies when neither is given
+    pub ttl: Option<u32>,
+    pub data: ZoneData,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ZoneData {
+    Address(IpAddr),
+    /// Target in key form
+    Cname(String),
+    /// Target in key form
+    Ptr(String),
+    /// One entry per character-string
+    Txt(Vec<Vec<u8>>),
+}
+
+/// One record or directive, possibly continued over several lines in parentheses.
+struct Entry {
+    line: usize,
+    /// Started with whitespace, so the owner is the previous record's
+    inherits_owner: bool,
+    /// Words and quoted strings, with escapes left in place
+    tokens: Vec<String>,
+}
+
+/// Parses an RFC 1035 master file. Relative names are under `origin` (a key
+/// such as `lan.`) until a `$ORIGIN` changes it. Unsupported record types and
+/// malformed records are skipped with a warning naming `source` and the line.
+pub fn parse(content: &str, origin: &str, source: &str) -> Vec<ZoneRecord> {
+    let mut origin = origin.to_string();
+    let mut default_ttl: Option<u32> = None;
+    let mut owner: Option<String> = None;
+    let mut records = Vec::new();
+    for entry in entries(content, source) {
+        let warn = |message: String| tracing::warn!("Skipping {}:{}: {}", source, entry.line, message);
+        let Some(first) = entry.tokens.first() else { continue };
+        if !entry.inherits_owner && first.starts_with('$') {
+            let argument = entry.tokens.get(1).map(String::as_str);
+            match (first.to_ascii_uppercase().as_str(), argument) {
+                ("$ORIGIN", Some(name)) => match absolute(name, &origin) {
+                    Ok(name) => origin = name,
+                    Err(e) => warn(format!("invalid $ORIGIN {:?}: {}", name, e)),
+                },
+                ("$TTL", Some(ttl)) => match parse_ttl(ttl) {
+                    Some(ttl) => default_ttl = Some(ttl),
+                    None => warn(format!("invalid $TTL {:?}", ttl)),
+                },
+                (directive, _) => warn(format!("unsupported directive {}", directive)),
+            }
+            continue;
+        }
+
+        let mut rest = entry.tokens.as_slice();
+        if !entry.inherits_owner {
+            match absolute(first, &origin) {
+                Ok(name) => owner = Some(name),
+                Err(e) => {
+                    // Continuation lines must not land on the previous owner instead
+                    owner = None;
+                    warn(format!("invalid name {:?}: {}", first, e));
+                    continue;
+                }
+            }
+            rest = &rest[1..];
+        }
+        let Some(name) = owner.clone() else {
+            warn("no owner name to continue from".to_string());
+            continue;
+        };
+
+        // TTL and class come in either order before the type
+        let mut ttl = None;
+        let mut class = "IN".to_string();
+        while let [token, tail @ ..] = rest {
+            if ttl.is_none() && token.starts_with(|c: char| c.is_ascii_digit()) {
+                let Some(value) = parse_ttl(token) else { break };
+                ttl = Some(value);
+            } else if matches!(token.to_ascii_uppercase().as_str(), "IN" | "CH" | "CS" | "HS") {
+                class = token.to_ascii_uppercase();
+            } else {
+                break;
+            }
+            rest = tail;
+        }
+        let Some((kind, rdata)) = rest.split_first() else {
+            warn("no record type".to_string());
+            continue;
+        };
+        let kind = kind.to_ascii_uppercase();
+        if class != "IN" {
+            warn(format!("unsupported class {}", class));
+            continue;
+        }
+        let data = match record_data(&kind, rdata, &origin) {
+            Ok(data) => data,
+            Err(e) => {
+                warn(format!("{} record for {}: {}", kind, name, e));
+                continue;
+            }
+        };
+        if name.starts_with("*.") && !matches!(data, ZoneData::Address(_)) {
+            warn(format!("wildcard {} records are not supported", kind));
+            continue;
+        }
+        records.push(ZoneRecord { line: entry.line, name, ttl: ttl.or(default_ttl), data });
+    }
+    records
+}
+
+fn record_data(kind: &str, rdata: &[String], origin: &str) -> Result<ZoneData, String> {
+    let single = || match rdata {
+        [token] => Ok(token.as_str()),
+        _ => Err(format!("expected one value, found {}", rdata.len())),
+    };
+    match kind {
+        "A" => single()?.parse::<Ipv4Addr>().map(|ip| ZoneData::Address(ip.into())).map_err(|e| e.to_string()),
+        "AAAA" => single()?.parse::<Ipv6Addr>().map(|ip| ZoneData::Address(ip.into())).map_err(|e| e.to_string()),
+        "CNAME" => Ok(ZoneData::Cname(absolute(single()?, origin)?)),
+        "PTR" => Ok(ZoneData::Ptr(absolute(single()?, origin)?)),
+        "TXT" if rdata.is_empty() => Err("no text".to_string()),
+        "TXT" => {
+            let strings = rdata
+                .iter()
+                .map(|token| unescape(token))
+                .collect::<Result<Vec<_>, _>>()?;
+            if strings.iter().any(|s| s.len() > MAX_CHARACTER_STRING) {
+                return Err(format!("strings are limited to {} bytes", MAX_CHARACTER_STRING));
+            }
+            Ok(ZoneData::Txt(strings))
+        }
+        _ => Err("unsupported record type".to_string()),
+    }
+}
+
+/// `name` in key form: `@` is the origin, and a name without a trailing dot is relative to it.
+fn absolute(name: &str, origin: &str) -> Result<String, String> {
+    let fqdn = if name == "@" {
+        origin.to_string()
+    } else if name.ends_with('.') && !name.ends_with("\\.") {
+        name.to_string()
+    } else if origin == "." {
+        format!("{}.", name)
+    } else {
+        format!("{}.{}", name, origin)
+    };
+    parse_presentation(&fqdn).map(|name| name_key(&name)).map_err(|e| e.to_string())
+}
+
+/// A TTL in seconds, or with BIND's unit letters, as in `1h30m` or `1w`.
+fn parse_ttl(ttl: &str) -> Option<u32> {
+    if let Ok(seconds) = ttl.parse() {
+        return Some(seconds);
+    }
+    let mut total: u32 = 0;
+    let mut number: Option<u32> = None;
+    for c in ttl.chars() {
+        if let Some(digit) = c.to_digit(10) {
+            number = Some(number.unwrap_or(0).checked_mul(10)?.checked_add(digit)?);
+            continue;
+        }
+        let unit = match c.to_ascii_lowercase() {
+            's' => 1,
+            'm' => 60,
+            'h' => 3600,
+            'd' => 86400,
+            'w' => 604800,
+            _ => return None,
+        };
+        total = total.checked_add(number.take()?.checked_mul(unit)?)?;
+    }
+    match number {
+        None => Some(total),
+        Some(_) => None, // A number without a unit after one with
+    }
+}
+
+/// Decodes `\X` and `\DDD` escapes into bytes.
+fn unescape(text: &str) -> Result<Vec<u8>, String> {
+    let mut bytes = Vec::with_capacity(text.len());
+    let mut chars = text.bytes();
+    while let Some(byte) = chars.next() {
+        if byte != b'\\' {
+            bytes.push(byte);
+            continue;
+        }
+        let escaped = chars.next().ok_or("text ends with a lone backslash")?;
+        if escaped.is_ascii_digit() {
+            let digits = [escaped, chars.next().unwrap_or(0), chars.next().unwrap_or(0)];
+            if !digits.iter().all(u8::is_ascii_digit) {
+                return Err("\\DDD escapes need three digits".to_string());
+            }
+            let value = digits.iter().fold(0u32, |n, d| n * 10 + u32::from(d - b'0'));
+            bytes.push(u8::try_from(value).map_err(|_| format!("\\{} is not a byte", value))?);
+        } else {
+            bytes.push(escaped);
+        }
+    }
+    Ok(bytes)
+}
+
+/// Splits `content` into entries, dropping `;` comments and joining lines
+/// inside parentheses.
+fn entries(content: &str, source: &str) -> Vec<Entry> {
+    let mut entries = Vec::new();
+    let mut current: Option<Entry> = None;
+    let mut depth = 0usize;
+    for (index, line) in content.lines().enumerate() {
+        let mut chars = line.chars().peekable();
+        if current.is_none() {
+            current = Some(Entry {
+                line: index + 1,
+                inherits_owner: line.starts_with([' ', '\t']),
+                tokens: Vec::new(),
+            });
+        }
+        let entry = current.as_mut().expect("an entry is open");
+        while let Some(&c) = chars.peek() {
+            match c {
+                ';' => break,
+                '(' => {
+                    depth += 1;
+                    chars.next();
+                }
+                ')' => {
+                    depth = depth.saturating_sub(1);
+                    chars.next();
+                }
+                '"' => {
+                    chars.next();
+                    let mut text = String::new();
+                    while let Some(c) = chars.next() {
+                        match c {
+                            '"' => break,
+                            '\\' => {
+                                text.push(c);
+                                text.extend(chars.next());
+                            }
+                            c => text.push(c),
+                        }
+                    }
+                    entry.tokens.push(text);
+                }
+                c if c.is_whitespace() => {
+                    chars.next();
+                }
+                _ => {
+                    let mut text = String::new();
+                    while let Some(&c) = chars.peek() {
+                        if c.is_whitespace() || matches!(c, ';' | '(' | ')' | '"') {
+                            break;
+                        }
+                        text.push(c);
+                        chars.next();
+                        if c == '\\' {
+                            text.extend(chars.next());
+                        }
+                    }
+                    entry.tokens.push(text);
+                }
+            }
+        }
+        if depth == 0 {
+            entries.extend(current.take());
+        }
+    }
+    if let Some(entry) = current {
+        tracing::warn!("Skipping {}:{}: unbalanced parentheses", source, entry.line);
+    }
+    entries
+}
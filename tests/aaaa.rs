@@ -0,0 +1,101 @@
+use hickory_proto::op::{Message, Query, ResponseCode};
+use hickory_proto::rr::{Name, RData, RecordType};
+use localdns::config::Config;
+use localdns::loader::{load_records, parse_key, DnsCache, LoadOptions};
+use localdns::server::{resolve, ResolveOptions};
+use std::fs;
+use std::net::IpAddr;
+use tokio::sync::RwLock;
+
+const CONFIG: &str = r#"
+listen_address = "127.0.0.1"
+listen_port = 10053
+dhcp_lease_file = "/nonexistent/leases"
+hosts_file = "/nonexistent/hosts"
+domain_suffix = "lan"
+nsid = ""
+"#;
+
+const HOSTS: &str = "\
+192.168.1.5 nas.lan
+fd00::5 nas.lan
+fd00::9 printer.lan
+fe80::1%eth0 router.lan
+";
+
+/// Loads `hosts` and `leases` from a scratch directory unique to `test`.
+fn load(test: &str, hosts: &str, leases: &str) -> DnsCache {
+    let dir = std::env::temp_dir().join(format!("localdns-aaaa-{}-{}", test, std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("hosts"), hosts).unwrap();
+    fs::write(dir.join("leases.json"), leases).unwrap();
+    let options = LoadOptions::new(dir.join("leases.json"), dir.join("hosts"), "lan", dir.join("config.toml"));
+    let cache = load_records(&options).unwrap();
+    fs::remove_dir_all(&dir).unwrap();
+    cache
+}
+
+fn options() -> ResolveOptions {
+    let path = std::env::temp_dir().join(format!("localdns-aaaa-{}.toml", std::process::id()));
+    fs::write(&path, CONFIG).unwrap();
+    let config = Config::load(&path).unwrap();
+    let _ = fs::remove_file(&path);
+    ResolveOptions::from_config(&config)
+}
+
+fn addresses(cache: &DnsCache, name: &str) -> Vec<IpAddr> {
+    let key = parse_key(name).unwrap();
+    cache.exact_matches.get(&key).map(|entries| entries.iter().map(|e| e.ip).collect()).unwrap_or_default()
+}
+
+async fn answer(cache: DnsCache, name: &str, qtype: RecordType) -> (ResponseCode, Vec<RData>) {
+    let mut request = Message::new();
+    request.add_query(Query::query(Name::from_ascii(name).unwrap(), qtype));
+    let (response, _) = resolve(&request, None, &RwLock::new(cache), &options()).await.unwrap();
+    let data = response.answers().iter().filter_map(|r| r.data().cloned()).collect();
+    (response.response_code(), data)
+}
+
+#[test]
+fn ipv6_hosts_lines_are_loaded() {
+    let cache = load("load", HOSTS, r#"{"Leases":[]}"#);
+
+    assert_eq!(addresses(&cache, "nas.lan."), vec!["192.168.1.5".parse::<IpAddr>().unwrap(), "fd00::5".parse().unwrap()]);
+    assert_eq!(addresses(&cache, "printer.lan."), vec!["fd00::9".parse::<IpAddr>().unwrap()]);
+    // Scoped addresses mean nothing off-link, so they are skipped
+    assert!(addresses(&cache, "router.lan.").is_empty());
+}
+
+#[test]
+fn v4_and_v6_addresses_for_one_name_are_not_a_conflict() {
+    let leases = r#"{"Leases":[{"Address":[10,0,0,2],"Hostname":"laptop"}]}"#;
+    let cache = load("family", "fd00::2 laptop.lan\n", leases);
+
+    assert_eq!(addresses(&cache, "laptop.lan.").len(), 2);
+    assert!(cache.conflicts.is_empty());
+}
+
+#[tokio::test]
+async fn aaaa_queries_answer_ipv6_entries() {
+    let (code, data) = answer(load("answer", HOSTS, r#"{"Leases":[]}"#), "NAS.lan.", RecordType::AAAA).await;
+
+    assert_eq!(code, ResponseCode::NoError);
+    assert_eq!(data, vec![RData::AAAA("fd00::5".parse().unwrap())]);
+}
+
+#[tokio::test]
+async fn the_other_family_gets_an_empty_answer() {
+    let v4 = answer(load("v4only", "192.168.1.7 tv.lan\n", r#"{"Leases":[]}"#), "tv.lan.", RecordType::AAAA).await;
+    let v6 = answer(load("v6only", HOSTS, r#"{"Leases":[]}"#), "printer.lan.", RecordType::A).await;
+
+    assert_eq!(v4, (ResponseCode::NoError, Vec::new()));
+    assert_eq!(v6, (ResponseCode::NoError, Vec::new()));
+}
+
+#[tokio::test]
+async fn unknown_names_are_still_nxdomain_for_aaaa() {
+    let (code, data) = answer(load("unknown", HOSTS, r#"{"Leases":[]}"#), "missing.lan.", RecordType::AAAA).await;
+
+    assert_eq!(code, ResponseCode::NXDomain);
+    assert!(data.is_empty());
+}
@@ -0,0 +1,114 @@
+use hickory_proto::op::{Message, MessageType, Query, ResponseCode};
+use hickory_proto::rr::rdata::A;
+use hickory_proto::rr::{Name, RData, Record, RecordType};
+use localdns::config::Config;
+use localdns::loader::{load_records, parse_address_directive, DnsCache};
+use localdns::server::{resolve, AnswerSource, ResolveOptions};
+use std::fs;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use tokio::net::UdpSocket;
+use tokio::sync::RwLock;
+
+const CONFIG: &str = r#"
+listen_address = "127.0.0.1"
+listen_port = 10053
+dhcp_lease_file = "/nonexistent/leases"
+hosts_file = "hosts"
+domain_suffix = "lan"
+nsid = ""
+cache_max_entries = 0
+"#;
+
+/// An upstream answering every question with 10.9.9.9.
+async fn fake_upstream() -> SocketAddr {
+    let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let addr = socket.local_addr().unwrap();
+    tokio::spawn(async move {
+        let mut buffer = [0u8; 512];
+        loop {
+            let (len, peer) = socket.recv_from(&mut buffer).await.unwrap();
+            let request = Message::from_vec(&buffer[..len]).unwrap();
+            let query = request.queries()[0].clone();
+            let mut response = Message::new();
+            response.set_id(request.id());
+            response.set_message_type(MessageType::Response);
+            response.add_answer(Record::from_rdata(query.name().clone(), 60, RData::A(A(Ipv4Addr::new(10, 9, 9, 9)))));
+            response.add_query(query);
+            socket.send_to(&response.to_vec().unwrap(), peer).await.unwrap();
+        }
+    });
+    addr
+}
+
+fn load(test: &str, settings: &str) -> (RwLock<DnsCache>, ResolveOptions) {
+    let dir = std::env::temp_dir().join(format!("localdns-addresses-{}-{}", test, std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("hosts"), "10.0.0.20 wiki.blocked.corp\n").unwrap();
+    let path = dir.join("config.toml");
+    fs::write(&path, format!("{}{}", CONFIG, settings)).unwrap();
+    let mut config = Config::load(&path).unwrap();
+    config.hosts_file = dir.join("hosts");
+    let records = RwLock::new(load_records(&config.load_options(&path)).unwrap());
+    fs::remove_dir_all(&dir).unwrap();
+    (records, ResolveOptions::from_config(&config))
+}
+
+async fn ask(records: &RwLock<DnsCache>, options: &ResolveOptions, name: &str) -> (ResponseCode, AnswerSource, Vec<RData>) {
+    let mut request = Message::new();
+    request.add_query(Query::query(Name::from_ascii(name).unwrap(), RecordType::A));
+    let (response, source) = resolve(&request, None, records, options).await.unwrap();
+    let answers = response.answers().iter().filter_map(|record| record.data().cloned()).collect();
+    (response.response_code(), source, answers)
+}
+
+#[test]
+fn directives_list_one_or_more_domains() {
+    let ip: Option<IpAddr> = Some("1.2.3.4".parse().unwrap());
+    assert_eq!(parse_address_directive("/Internal.Corp/1.2.3.4"), Ok((vec!["internal.corp.".to_string()], ip)));
+    assert_eq!(
+        parse_address_directive("address=/a.test/b.test/c.test/1.2.3.4"),
+        Ok((vec!["a.test.".to_string(), "b.test.".to_string(), "c.test.".to_string()], ip))
+    );
+    assert_eq!(
+        parse_address_directive("/ads.test/tracker.test/"),
+        Ok((vec!["ads.test.".to_string(), "tracker.test.".to_string()], None))
+    );
+    assert_eq!(parse_address_directive("/v6.test/fd00::1"), Ok((vec!["v6.test.".to_string()], Some("fd00::1".parse().unwrap()))));
+
+    for bad in ["internal.corp/1.2.3.4", "/internal.corp", "//1.2.3.4", "/a.test//1.2.3.4", "/#/1.2.3.4", "/a.test/not-an-ip", "/bad_name!/1.2.3.4"] {
+        assert!(parse_address_directive(bad).is_err(), "{:?}", bad);
+    }
+}
+
+#[tokio::test]
+async fn a_directive_answers_for_the_domain_and_everything_under_it() {
+    let (records, options) = load("answer", "addresses = [\"/internal.corp/vpn.test/10.0.0.5\"]\n");
+    let expected = vec![RData::A(A(Ipv4Addr::new(10, 0, 0, 5)))];
+
+    assert_eq!(ask(&records, &options, "internal.corp.").await, (ResponseCode::NoError, AnswerSource::Exact, expected.clone()));
+    assert_eq!(ask(&records, &options, "a.b.internal.corp.").await, (ResponseCode::NoError, AnswerSource::Wildcard, expected.clone()));
+    assert_eq!(ask(&records, &options, "vpn.test.").await.2, expected);
+    assert_eq!(ask(&records, &options, "notinternal.corp.").await.0, ResponseCode::NXDomain);
+}
+
+#[tokio::test]
+async fn a_directive_without_an_address_keeps_the_subtree_from_upstreams() {
+    let upstream = fake_upstream().await;
+    let (records, options) = load("local", &format!("upstream = [\"{}\"]\naddresses = [\"/blocked.corp/\"]\n", upstream));
+
+    assert_eq!(ask(&records, &options, "blocked.corp.").await, (ResponseCode::NXDomain, AnswerSource::None, Vec::new()));
+    assert_eq!(ask(&records, &options, "x.y.blocked.corp.").await.0, ResponseCode::NXDomain);
+    // Local records in the subtree still answer, as with dnsmasq
+    assert_eq!(ask(&records, &options, "wiki.blocked.corp.").await.1, AnswerSource::Exact);
+    assert_eq!(ask(&records, &options, "other.corp.").await.1, AnswerSource::Upstream);
+}
+
+#[test]
+fn invalid_directives_are_rejected_with_the_config() {
+    let path = std::env::temp_dir().join(format!("localdns-addresses-invalid-{}.toml", std::process::id()));
+    fs::write(&path, format!("{}addresses = [\"/internal.corp/10.0.0\"]\n", CONFIG)).unwrap();
+    let error = Config::load(&path).unwrap_err().to_string();
+    let _ = fs::remove_file(&path);
+
+    assert!(error.contains("addresses") && error.contains("internal.corp"), "{}", error);
+}
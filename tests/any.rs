@@ -0,0 +1,91 @@
+use hickory_proto::op::{Message, Query, ResponseCode};
+use hickory_proto::rr::{Name, RData, RecordType};
+use localdns::config::Config;
+use localdns::loader::{load_records, DnsCache};
+use localdns::server::{resolve, ResolveOptions};
+use std::fs;
+use tokio::sync::RwLock;
+
+const CONFIG: &str = r#"
+listen_address = "127.0.0.1"
+listen_port = 10053
+dhcp_lease_file = "/nonexistent/leases"
+hosts_file = "hosts"
+domain_suffix = "lan"
+nsid = ""
+ttl = 120
+"#;
+
+const HOSTS: &str = "\
+192.168.1.5 nas.lan
+fd00::5 nas.lan
+10.0.0.1 *.dev.lan
+txt nas.lan \"owner=ops\"
+mx mail.lan 10 nas.lan
+";
+
+fn load(test: &str, settings: &str) -> (RwLock<DnsCache>, ResolveOptions) {
+    let dir = std::env::temp_dir().join(format!("localdns-any-{}-{}", test, std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("hosts"), HOSTS).unwrap();
+    let path = dir.join("config.toml");
+    fs::write(&path, format!("{}{}", settings, CONFIG)).unwrap();
+    let mut config = Config::load(&path).unwrap();
+    config.hosts_file = dir.join("hosts");
+    let records = RwLock::new(load_records(&config.load_options(&path)).unwrap());
+    fs::remove_dir_all(&dir).unwrap();
+    (records, ResolveOptions::from_config(&config))
+}
+
+async fn any(records: &RwLock<DnsCache>, options: &ResolveOptions, name: &str) -> Message {
+    let mut request = Message::new();
+    request.set_recursion_desired(true);
+    request.add_query(Query::query(Name::from_ascii(name).unwrap(), RecordType::ANY));
+    let (response, _) = resolve(&request, None, records, options).await.unwrap();
+    // Through the wire format, as a client would see it
+    Message::from_vec(&response.to_vec().unwrap()).unwrap()
+}
+
+#[tokio::test]
+async fn any_gets_a_single_hinfo_for_local_names() {
+    let (records, options) = load("hinfo", "");
+
+    for name in ["nas.lan.", "mail.lan.", "box.dev.lan."] {
+        let response = any(&records, &options, name).await;
+        assert_eq!(response.response_code(), ResponseCode::NoError, "{}", name);
+        assert_eq!(response.answers().len(), 1, "{}", name);
+        let answer = &response.answers()[0];
+        assert_eq!((answer.name().to_string().as_str(), answer.record_type(), answer.ttl()), (name, RecordType::HINFO, 120));
+        match answer.data() {
+            Some(RData::HINFO(hinfo)) => assert_eq!((hinfo.cpu(), hinfo.os()), (&b"RFC8482"[..], &b""[..])),
+            other => panic!("{:?}", other),
+        }
+    }
+}
+
+#[tokio::test]
+async fn any_for_an_unknown_name_is_nxdomain() {
+    let (records, options) = load("unknown", "");
+
+    let response = any(&records, &options, "missing.lan.").await;
+
+    assert_eq!(response.response_code(), ResponseCode::NXDomain);
+    assert!(response.answers().is_empty());
+    assert_eq!(response.name_servers()[0].record_type(), RecordType::SOA);
+}
+
+#[tokio::test]
+async fn full_any_answers_return_every_record() {
+    let (records, options) = load("full", "full_any_answers = true\n");
+
+    let response = any(&records, &options, "nas.lan.").await;
+    assert_eq!(response.response_code(), ResponseCode::NoError);
+    let types: Vec<RecordType> = response.answers().iter().map(|record| record.record_type()).collect();
+    assert_eq!(types, [RecordType::A, RecordType::AAAA, RecordType::TXT]);
+
+    let response = any(&records, &options, "mail.lan.").await;
+    assert_eq!(response.answers().iter().map(|record| record.record_type()).collect::<Vec<_>>(), [RecordType::MX]);
+
+    let response = any(&records, &options, "box.dev.lan.").await;
+    assert!(matches!(response.answers()[0].data(), Some(RData::A(a)) if a.0 == std::net::Ipv4Addr::new(10, 0, 0, 1)));
+}
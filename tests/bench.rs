@@ -0,0 +1,81 @@
+use localdns::config::Config;
+use localdns::run::{run_server, RunningServer};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+const CONFIG: &str = r#"
+listen_address = "127.0.0.1"
+listen_port = 0
+dhcp_lease_file = "leases.json"
+hosts_file = "hosts"
+domain_suffix = "lan"
+nsid = ""
+
+[watchdog]
+enabled = false
+"#;
+
+fn scratch(test: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("localdns-bench-{}-{}", test, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// The whole server over `hosts` in `dir`, on a port the system picks.
+async fn start(dir: &Path, hosts: &str) -> RunningServer {
+    fs::write(dir.join("hosts"), hosts).unwrap();
+    fs::write(dir.join("leases.json"), r#"{"Leases":[]}"#).unwrap();
+    let path = dir.join("config.toml");
+    fs::write(&path, CONFIG).unwrap();
+    let mut config = Config::load(&path).unwrap();
+    config.hosts_file = dir.join("hosts");
+    config.dhcp_lease_file = dir.join("leases.json");
+    run_server(&path, config).await.unwrap()
+}
+
+/// Runs `localdns bench --json` with `args` and parses its report.
+async fn bench(args: &[&str]) -> serde_json::Value {
+    let args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+    let output = tokio::task::spawn_blocking(move || Command::new(env!("CARGO_BIN_EXE_localdns")).arg("bench").args(&args).arg("--json").output().unwrap())
+        .await
+        .unwrap();
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    serde_json::from_slice(&output.stdout).unwrap()
+}
+
+#[tokio::test]
+async fn reports_answers_and_rcodes_from_a_running_server() {
+    let dir = scratch("names");
+    let server = start(&dir, "192.168.1.5 nas.lan\n").await;
+    let names = dir.join("names.txt");
+    fs::write(&names, "# one of each\nnas.lan\nmissing.lan\n").unwrap();
+    let server = server.local_addr.to_string();
+
+    let report = bench(&["--server", &server, "--duration", "300ms", "--concurrency", "2", "--names-file", names.to_str().unwrap()]).await;
+    fs::remove_dir_all(&dir).unwrap();
+
+    assert_eq!(report["server"], server);
+    assert_eq!(report["concurrency"], 2);
+    let answered = report["answered"].as_u64().unwrap();
+    assert!(answered > 0, "{}", report);
+    assert_eq!(report["sent"].as_u64().unwrap(), answered + report["timeouts"].as_u64().unwrap() + report["errors"].as_u64().unwrap());
+    assert_eq!(report["mismatched_ids"], 0);
+    let rcodes = report["rcodes"].as_object().unwrap();
+    assert!(rcodes.keys().all(|rcode| rcode == "No Error" || rcode == "Non-Existent Domain"), "{}", report);
+    assert!(report["latency_us"]["p50"].as_u64().unwrap() <= report["latency_us"]["max"].as_u64().unwrap());
+}
+
+#[tokio::test]
+async fn random_subdomains_exercise_the_nxdomain_path() {
+    let dir = scratch("random");
+    let server = start(&dir, "").await;
+    let server = server.local_addr.to_string();
+
+    let report = bench(&["--server", &server, "--duration", "200ms", "--concurrency", "1", "--random-subdomains", "lan", "--qtypes", "A=1,AAAA=1"]).await;
+    fs::remove_dir_all(&dir).unwrap();
+
+    let rcodes = report["rcodes"].as_object().unwrap();
+    assert_eq!(rcodes.keys().collect::<Vec<_>>(), ["Non-Existent Domain"], "{}", report);
+}
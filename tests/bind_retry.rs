@@ -0,0 +1,90 @@
+use hickory_proto::op::{Message, Query};
+use hickory_proto::rr::{Name, RecordType};
+use localdns::config::Config;
+use localdns::run::{run_server, RunningServer};
+use std::fs;
+use std::net::{SocketAddr, UdpSocket};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+const CONFIG: &str = r#"
+listen_address = "127.0.0.1"
+listen_port = 10053
+dhcp_lease_file = "leases.json"
+hosts_file = "hosts"
+domain_suffix = "lan"
+nsid = ""
+
+[watchdog]
+enabled = false
+
+[tcp]
+enabled = false
+"#;
+
+fn scratch(test: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("localdns-bind-retry-{}-{}", test, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// Starts the server on `port` of 127.0.0.1, retrying as `retry` says.
+async fn start_on(dir: &Path, port: u16, retry: &str) -> anyhow::Result<RunningServer> {
+    fs::write(dir.join("hosts"), "").unwrap();
+    fs::write(dir.join("leases.json"), r#"{"Leases":[]}"#).unwrap();
+    let path = dir.join("config.toml");
+    fs::write(&path, format!("{}\n[bind_retry]\n{}\n", CONFIG, retry)).unwrap();
+    let mut config = Config::load(&path).unwrap();
+    config.hosts_file = dir.join("hosts");
+    config.dhcp_lease_file = dir.join("leases.json");
+    config.listen_port = port;
+    run_server(&path, config).await
+}
+
+/// Whether `server` answers a query over UDP within two seconds.
+async fn answers(server: SocketAddr) -> bool {
+    let client = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let mut request = Message::new();
+    request.set_id(7).set_recursion_desired(true);
+    request.add_query(Query::query(Name::from_ascii("missing.lan.").unwrap(), RecordType::A));
+    client.send_to(&request.to_vec().unwrap(), server).await.unwrap();
+    let mut buf = [0u8; 512];
+    tokio::time::timeout(Duration::from_secs(2), client.recv(&mut buf)).await.is_ok()
+}
+
+#[tokio::test]
+async fn gives_up_naming_the_address_and_a_likely_culprit() {
+    let dir = scratch("fail");
+    let holder = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let port = holder.local_addr().unwrap().port();
+
+    let started = Instant::now();
+    let error = start_on(&dir, port, "attempts = 2\ninterval = 1").await.err().expect("the port is taken");
+    let message = format!("{:#}", error);
+    fs::remove_dir_all(&dir).unwrap();
+
+    assert!(message.contains(&format!("127.0.0.1:{}", port)), "{}", message);
+    assert!(message.contains("after 2 attempt(s)") && message.contains("another resolver"), "{}", message);
+    // One wait between the two attempts
+    assert!(started.elapsed() >= Duration::from_secs(1));
+}
+
+#[tokio::test]
+async fn binds_once_the_address_is_released() {
+    let dir = scratch("release");
+    let holder = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let address = holder.local_addr().unwrap();
+    // Released while the server waits between attempts
+    let release = tokio::spawn(async move {
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        drop(holder);
+    });
+
+    let server = start_on(&dir, address.port(), "attempts = 5\ninterval = 1").await.unwrap();
+    release.await.unwrap();
+    fs::remove_dir_all(&dir).unwrap();
+
+    assert_eq!(server.local_addr, address);
+    assert!(answers(address).await);
+}
@@ -0,0 +1,126 @@
+use hickory_proto::op::{Message, Query, ResponseCode};
+use hickory_proto::rr::{Name, RData, RecordType};
+use localdns::config::Config;
+use localdns::loader::load_records;
+use localdns::server::{resolve, AnswerSource, ResolveOptions};
+use std::fs;
+use std::net::IpAddr;
+use tokio::sync::RwLock;
+
+const CONFIG: &str = r#"
+listen_address = "127.0.0.1"
+listen_port = 10053
+dhcp_lease_file = "/nonexistent/leases"
+hosts_file = "hosts"
+domain_suffix = "lan"
+nsid = ""
+"#;
+
+const HOSTS: &str = "\
+192.168.1.5 nas.lan
+0.0.0.0 ads.example.com
+:: ads.example.com
+0.0.0.0 tracker.example.net
+0.0.0.0 *.telemetry.example.org
+192.168.1.7 ok.telemetry.example.org
+0.0.0.0 nas.lan
+";
+
+/// Answers to each `(name, type)` after loading `HOSTS` with `settings`.
+async fn ask(test: &str, settings: &str, queries: &[(&str, RecordType)]) -> Vec<(ResponseCode, AnswerSource, Vec<IpAddr>)> {
+    let dir = std::env::temp_dir().join(format!("localdns-blocklist-{}-{}", test, std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("hosts"), HOSTS).unwrap();
+    let path = dir.join("config.toml");
+    fs::write(&path, format!("{}{}", CONFIG, settings)).unwrap();
+    let mut config = Config::load(&path).unwrap();
+    config.hosts_file = dir.join("hosts");
+    let records = RwLock::new(load_records(&config.load_options(&path)).unwrap());
+    fs::remove_dir_all(&dir).unwrap();
+    let options = ResolveOptions::from_config(&config);
+
+    let mut answers = Vec::new();
+    for (name, qtype) in queries {
+        let mut request = Message::new();
+        request.add_query(Query::query(Name::from_ascii(name).unwrap(), *qtype));
+        let (response, source) = resolve(&request, None, &records, &options).await.unwrap();
+        let ips = response
+            .answers()
+            .iter()
+            .filter_map(|record| match record.data() {
+                Some(RData::A(a)) => Some(IpAddr::V4(a.0)),
+                Some(RData::AAAA(aaaa)) => Some(IpAddr::V6(aaaa.0)),
+                _ => None,
+            })
+            .collect();
+        answers.push((response.response_code(), source, ips));
+    }
+    answers
+}
+
+fn ip(text: &str) -> IpAddr {
+    text.parse().unwrap()
+}
+
+#[tokio::test]
+async fn blocks_answer_nxdomain_for_a_and_aaaa() {
+    let answers = ask("nxdomain", "blocklist_mode = \"nxdomain\"\n", &[
+        ("ads.example.com.", RecordType::A),
+        ("ads.example.com.", RecordType::AAAA),
+        ("tracker.example.net.", RecordType::AAAA),
+        ("nas.lan.", RecordType::A),
+    ])
+    .await;
+
+    assert_eq!(answers[0], (ResponseCode::NXDomain, AnswerSource::Blocked, vec![]));
+    assert_eq!(answers[1], (ResponseCode::NXDomain, AnswerSource::Blocked, vec![]));
+    assert_eq!(answers[2], (ResponseCode::NXDomain, AnswerSource::Blocked, vec![]));
+    // A block listed beside a real address is ignored
+    assert_eq!(answers[3], (ResponseCode::NoError, AnswerSource::Exact, vec![ip("192.168.1.5")]));
+}
+
+#[tokio::test]
+async fn nodata_mode_answers_empty() {
+    let answers = ask("nodata", "blocklist_mode = \"nodata\"\n", &[
+        ("ads.example.com.", RecordType::A),
+        ("tracker.example.net.", RecordType::MX),
+    ])
+    .await;
+
+    assert_eq!(answers[0], (ResponseCode::NoError, AnswerSource::Blocked, vec![]));
+    assert_eq!(answers[1], (ResponseCode::NoError, AnswerSource::Blocked, vec![]));
+}
+
+#[tokio::test]
+async fn sinkhole_mode_answers_with_the_sinkhole_of_each_family() {
+    let settings = "blocklist_mode = \"sinkhole\"\nblocklist_sinkhole = [\"192.168.1.2\", \"fd00::2\"]\n";
+    let answers = ask("sinkhole", settings, &[
+        ("ads.example.com.", RecordType::A),
+        ("ads.example.com.", RecordType::AAAA),
+        ("deep.telemetry.example.org.", RecordType::A),
+    ])
+    .await;
+
+    assert_eq!(answers[0], (ResponseCode::NoError, AnswerSource::Blocked, vec![ip("192.168.1.2")]));
+    assert_eq!(answers[1], (ResponseCode::NoError, AnswerSource::Blocked, vec![ip("fd00::2")]));
+    assert_eq!(answers[2], (ResponseCode::NoError, AnswerSource::Blocked, vec![ip("192.168.1.2")]));
+}
+
+#[tokio::test]
+async fn names_with_records_are_not_blocked_by_a_wildcard() {
+    let answers = ask("wildcard", "blocklist_mode = \"nxdomain\"\n", &[
+        ("ok.telemetry.example.org.", RecordType::A),
+        ("other.telemetry.example.org.", RecordType::A),
+    ])
+    .await;
+
+    assert_eq!(answers[0], (ResponseCode::NoError, AnswerSource::Exact, vec![ip("192.168.1.7")]));
+    assert_eq!(answers[1], (ResponseCode::NXDomain, AnswerSource::Blocked, vec![]));
+}
+
+#[tokio::test]
+async fn without_a_mode_the_unspecified_address_is_served() {
+    let answers = ask("off", "", &[("ads.example.com.", RecordType::A)]).await;
+
+    assert_eq!(answers[0], (ResponseCode::NoError, AnswerSource::Exact, vec![ip("0.0.0.0")]));
+}
@@ -0,0 +1,176 @@
+use hickory_proto::op::{Message, Query, ResponseCode};
+use hickory_proto::rr::{Name, RecordType};
+use localdns::blocklist::{BlockEvent, Blocks, RECENT_BLOCKS};
+use localdns::config::Config;
+use localdns::events::Events;
+use localdns::http::{Request, Response};
+use localdns::json_api::JsonApi;
+use localdns::listeners::{self, Listeners};
+use localdns::loader::load_records;
+use localdns::metrics;
+use localdns::server::{resolve, AnswerSource, ResolveOptions};
+use localdns::watchdog::Health;
+use std::fs;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::RwLock;
+
+const CONFIG: &str = r#"
+listen_address = "127.0.0.1"
+listen_port = 10053
+dhcp_lease_file = "/nonexistent/leases"
+hosts_file = "hosts"
+domain_suffix = "lan"
+nsid = ""
+blocklist_mode = "nxdomain"
+"#;
+
+fn scratch(test: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("localdns-blocks-{}-{}", test, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// Two lists beside the hosts file in `dir`: one blocking a name, the other a wildcard.
+fn api(dir: &Path) -> JsonApi {
+    fs::write(dir.join("hosts"), "192.168.1.5 nas.lan\n").unwrap();
+    fs::write(dir.join("ads.hosts"), "# ads\n0.0.0.0 ads.example.com\n").unwrap();
+    fs::write(dir.join("trackers.hosts"), "0.0.0.0 *.tracker.example.net\n").unwrap();
+    let settings = format!("hosts_files = [{:?}, {:?}]\n\n[http]\napi_token = \"s3cret\"\n", dir.join("ads.hosts"), dir.join("trackers.hosts"));
+    let path = dir.join("config.toml");
+    fs::write(&path, format!("{}{}", CONFIG, settings)).unwrap();
+    let mut config = Config::load(&path).unwrap();
+    config.hosts_file = dir.join("hosts");
+    let load_options = config.load_options(&path);
+    let records = Arc::new(RwLock::new(load_records(&load_options).unwrap()));
+    let options = ResolveOptions::from_config(&config);
+    JsonApi {
+        records: records.clone(),
+        options: Arc::new(options.clone()),
+        cors_allow_origin: None,
+        limits: config.limits.clone(),
+        limits_policy: config.limits_policy,
+        health: Arc::new(Health::new(&config.watchdog)),
+        last_reload: Arc::new(Mutex::new(None)),
+        events: Arc::new(Events::default()),
+        listeners: Arc::new(Listeners::new(records, options, &config)),
+        overlay: load_options.overlay.clone(),
+        api_token: config.http.api_token.clone(),
+        started_at: listeners::now(),
+    }
+}
+
+async fn ask(api: &JsonApi, name: &str) -> (ResponseCode, AnswerSource) {
+    let client: SocketAddr = "192.168.1.20:5353".parse().unwrap();
+    let mut request = Message::new();
+    request.set_id(7).set_recursion_desired(true);
+    request.add_query(Query::query(Name::from_ascii(name).unwrap(), RecordType::A));
+    let (response, source) = resolve(&request, Some(client), &api.records, &api.options).await.unwrap();
+    (response.response_code(), source)
+}
+
+async fn call(api: &JsonApi, method: &str, path: &str, token: Option<&str>, body: &str) -> (u16, serde_json::Value) {
+    let mut headers = Vec::new();
+    if let Some(token) = token {
+        headers.push(("authorization".to_string(), format!("Bearer {}", token)));
+    }
+    let request = Request { method: method.to_string(), path: path.to_string(), query: Vec::new(), headers, body: body.as_bytes().to_vec() };
+    let Response { status, body, .. } = api.handle(request).await;
+    (status, serde_json::from_slice(&body).unwrap_or(serde_json::Value::Null))
+}
+
+async fn post(api: &JsonApi, path: &str, token: Option<&str>, body: &str) -> (u16, serde_json::Value) {
+    call(api, "POST", path, token, body).await
+}
+
+#[tokio::test]
+async fn each_block_is_counted_against_its_list_with_the_rule_it_matched() {
+    let dir = scratch("accounting");
+    let api = api(&dir);
+    fs::remove_dir_all(&dir).unwrap();
+    let (ads, trackers) = (dir.join("ads.hosts").display().to_string(), dir.join("trackers.hosts").display().to_string());
+
+    assert_eq!(ask(&api, "ads.example.com.").await, (ResponseCode::NXDomain, AnswerSource::Blocked));
+    assert_eq!(ask(&api, "a.tracker.example.net.").await.1, AnswerSource::Blocked);
+    assert_eq!(ask(&api, "b.tracker.example.net.").await.1, AnswerSource::Blocked);
+    assert_eq!(ask(&api, "nas.lan.").await.1, AnswerSource::Exact);
+
+    let (status, body) = call(&api, "GET", "/blocks", None, "").await;
+    assert_eq!(status, 200);
+    assert_eq!(body["hits"][&ads], 1);
+    assert_eq!(body["hits"][&trackers], 2);
+    // Newest first
+    let recent = body["recent"].as_array().unwrap();
+    assert_eq!(recent.len(), 3);
+    assert_eq!(recent[0]["name"], "b.tracker.example.net.");
+    assert_eq!(recent[0]["rule"], "*.tracker.example.net.");
+    assert_eq!((recent[2]["list"].as_str(), recent[2]["line"].as_u64()), (Some(ads.as_str()), Some(2)));
+    assert_eq!(recent[2]["rule"], "ads.example.com.");
+    assert_eq!(recent[2]["client"], "192.168.1.20:5353");
+
+    let text = metrics::render(&[], &api.options.blocklist.blocks);
+    assert!(text.lines().any(|line| line == format!("localdns_blocks_total{{list={:?}}} 2", trackers)), "{}", text);
+}
+
+#[tokio::test]
+async fn a_disabled_list_stops_blocking_until_its_ttl_runs_out() {
+    let dir = scratch("disable");
+    let api = api(&dir);
+    fs::remove_dir_all(&dir).unwrap();
+    let trackers = dir.join("trackers.hosts").display().to_string();
+    let body = format!("{{\"list\": {:?}, \"ttl\": 1}}", trackers);
+
+    assert_eq!(post(&api, "/blocks/disable", None, &body).await.0, 401);
+    let (status, body) = post(&api, "/blocks/disable", Some("s3cret"), &body).await;
+    assert_eq!(status, 200, "{}", body);
+    assert_eq!(body["disabled"][&trackers], 1);
+    assert_eq!(ask(&api, "a.tracker.example.net.").await.1, AnswerSource::None);
+    // The other list still blocks
+    assert_eq!(ask(&api, "ads.example.com.").await.1, AnswerSource::Blocked);
+
+    tokio::time::sleep(Duration::from_millis(1100)).await;
+    assert_eq!(ask(&api, "a.tracker.example.net.").await.1, AnswerSource::Blocked);
+    let (_, body) = call(&api, "GET", "/blocks", None, "").await;
+    assert!(body["disabled"].as_object().unwrap().is_empty(), "{}", body);
+
+    let (status, _) = post(&api, "/blocks/disable", Some("s3cret"), r#"{"list": "/nowhere.hosts", "ttl": 60}"#).await;
+    assert_eq!(status, 404);
+}
+
+#[tokio::test]
+async fn an_allowed_name_is_answered_until_the_entry_is_removed() {
+    let dir = scratch("allow");
+    let api = api(&dir);
+    fs::remove_dir_all(&dir).unwrap();
+
+    let (status, body) = post(&api, "/blocks/allow", Some("s3cret"), r#"{"name": "A.Tracker.Example.net", "ttl": 300}"#).await;
+    assert_eq!(status, 200, "{}", body);
+    assert_eq!(body["allowed"]["a.tracker.example.net."], 300);
+    assert_eq!(ask(&api, "a.tracker.example.net.").await.1, AnswerSource::None);
+    // Only the name itself, not the rest of the wildcard
+    assert_eq!(ask(&api, "b.tracker.example.net.").await.1, AnswerSource::Blocked);
+
+    post(&api, "/blocks/allow", Some("s3cret"), r#"{"name": "a.tracker.example.net", "ttl": 0}"#).await;
+    assert_eq!(ask(&api, "a.tracker.example.net.").await.1, AnswerSource::Blocked);
+
+    let (status, _) = post(&api, "/blocks/allow", Some("s3cret"), r#"{"name": "*.tracker.example.net", "ttl": 60}"#).await;
+    assert_eq!(status, 400);
+}
+
+#[test]
+fn only_the_latest_blocks_are_kept() {
+    let blocks = Blocks::default();
+    for i in 0..RECENT_BLOCKS + 10 {
+        let name = format!("host{}.example.", i);
+        blocks.record(BlockEvent { at: 0, client: "-".to_string(), name: name.clone(), list: "ads".to_string(), rule: name, line: i });
+    }
+
+    let recent = blocks.recent();
+    assert_eq!(recent.len(), RECENT_BLOCKS);
+    assert_eq!(recent[0].line, RECENT_BLOCKS + 9);
+    assert_eq!(recent[RECENT_BLOCKS - 1].line, 10);
+    assert_eq!(blocks.hits()["ads"], (RECENT_BLOCKS + 10) as u64);
+}
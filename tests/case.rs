@@ -0,0 +1,113 @@
+use hickory_proto::op::{Message, Query};
+use hickory_proto::rr::{Name, RecordType};
+use localdns::config::Config;
+use localdns::loader::{parse_key, DnsCache, Entry, Source, SourceKind};
+use localdns::server::{resolve, ResolveOptions};
+use std::net::Ipv4Addr;
+use std::path::Path;
+use std::sync::Arc;
+use tokio::sync::RwLock;
+
+const CONFIG: &str = r#"
+listen_address = "127.0.0.1"
+listen_port = 10053
+dhcp_lease_file = "/nonexistent/leases"
+hosts_file = "/nonexistent/hosts"
+domain_suffix = "lan"
+fallback_ip = "10.9.9.9"
+nsid = ""
+"#;
+
+fn options() -> ResolveOptions {
+    let path = std::env::temp_dir().join(format!("localdns-case-{}.toml", std::process::id()));
+    std::fs::write(&path, CONFIG).unwrap();
+    let config = Config::load(&path).unwrap();
+    let _ = std::fs::remove_file(&path);
+    ResolveOptions::from_config(&config)
+}
+
+fn records() -> RwLock<DnsCache> {
+    let mut cache = DnsCache::default();
+    let path: Arc<Path> = Arc::from(Path::new("/etc/hosts"));
+    let entry = |location| Entry {
+        ip: Ipv4Addr::new(192, 168, 1, 5).into(),
+        source: Source { kind: SourceKind::Hosts, path: path.clone(), location, by_line: true },
+        ttl: None,
+    };
+    cache.exact_matches.insert(parse_key("nas.lan.").unwrap(), vec![entry(1)]);
+    cache.wildcards.push(("*.example.com.".to_string(), entry(2)));
+    cache.build_reverse();
+    cache.index_wildcards();
+    RwLock::new(cache)
+}
+
+/// Reads the name at `offset`, following compression pointers, as raw labels.
+fn wire_name(message: &[u8], mut offset: usize) -> (Vec<u8>, usize) {
+    let mut name = Vec::new();
+    let mut end = None;
+    loop {
+        let len = message[offset] as usize;
+        if len & 0xC0 == 0xC0 {
+            end.get_or_insert(offset + 2);
+            offset = ((len & 0x3F) << 8) | message[offset + 1] as usize;
+            continue;
+        }
+        if len == 0 {
+            return (name, end.unwrap_or(offset + 1));
+        }
+        name.extend_from_slice(&message[offset + 1..offset + 1 + len]);
+        name.push(b'.');
+        offset += 1 + len;
+    }
+}
+
+/// The question name and every answer owner name, exactly as encoded.
+async fn names_in_response(qname: &str, qtype: RecordType) -> (Vec<u8>, Vec<Vec<u8>>) {
+    let mut request = Message::new();
+    request.add_query(Query::query(Name::from_ascii(qname).unwrap(), qtype));
+    let (response, _) = resolve(&request, None, &records(), &options()).await.unwrap();
+    let wire = response.to_vec().unwrap();
+
+    let (question, mut offset) = wire_name(&wire, 12);
+    offset += 4; // QTYPE and QCLASS
+    let ancount = u16::from_be_bytes([wire[6], wire[7]]);
+    let mut owners = Vec::new();
+    for _ in 0..ancount {
+        let (owner, end) = wire_name(&wire, offset);
+        owners.push(owner);
+        let rdlength = u16::from_be_bytes([wire[end + 8], wire[end + 9]]) as usize;
+        offset = end + 10 + rdlength;
+    }
+    (question, owners)
+}
+
+async fn assert_case_preserved(qname: &str, qtype: RecordType, answers: usize) {
+    let (question, owners) = names_in_response(qname, qtype).await;
+    assert_eq!(question, qname.as_bytes(), "question for {}", qname);
+    assert_eq!(owners.len(), answers, "answers for {}", qname);
+    for owner in owners {
+        assert_eq!(owner, qname.as_bytes(), "owner for {}", qname);
+    }
+}
+
+#[tokio::test]
+async fn exact_answers_keep_the_query_case() {
+    assert_case_preserved("nAs.LaN.", RecordType::A, 1).await;
+}
+
+#[tokio::test]
+async fn wildcard_and_fallback_answers_keep_the_query_case() {
+    assert_case_preserved("WwW.eXaMpLe.CoM.", RecordType::A, 1).await;
+    assert_case_preserved("uNkNoWn.LaN.", RecordType::A, 1).await;
+}
+
+#[tokio::test]
+async fn ptr_and_soa_answers_keep_the_query_case() {
+    assert_case_preserved("5.1.168.192.In-Addr.ARPA.", RecordType::PTR, 1).await;
+    assert_case_preserved("LaN.", RecordType::SOA, 1).await;
+}
+
+#[tokio::test]
+async fn empty_answers_echo_the_question_case() {
+    assert_case_preserved("nAs.LaN.", RecordType::TXT, 0).await;
+}
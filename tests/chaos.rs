@@ -0,0 +1,80 @@
+use hickory_proto::op::{Message, Query, ResponseCode};
+use hickory_proto::rr::{DNSClass, Name, RData, RecordType};
+use localdns::config::Config;
+use localdns::loader::DnsCache;
+use localdns::server::{resolve, AnswerSource, ResolveOptions};
+use tokio::sync::RwLock;
+
+const CONFIG: &str = r#"
+listen_address = "127.0.0.1"
+listen_port = 10053
+dhcp_lease_file = "/nonexistent/leases"
+hosts_file = "/nonexistent/hosts"
+domain_suffix = "lan"
+fallback_ip = "10.9.9.9"
+nsid = ""
+"#;
+
+fn options(test: &str, settings: &str) -> ResolveOptions {
+    let path = std::env::temp_dir().join(format!("localdns-chaos-{}-{}.toml", test, std::process::id()));
+    std::fs::write(&path, format!("{}{}", CONFIG, settings)).unwrap();
+    let config = Config::load(&path).unwrap();
+    let _ = std::fs::remove_file(&path);
+    ResolveOptions::from_config(&config)
+}
+
+/// The response code, source and TXT strings for `name` queried in `class`.
+async fn ask(options: &ResolveOptions, name: &str, qtype: RecordType, class: DNSClass) -> (ResponseCode, AnswerSource, Vec<String>) {
+    let mut query = Query::query(Name::from_ascii(name).unwrap(), qtype);
+    query.set_query_class(class);
+    let mut request = Message::new();
+    request.add_query(query);
+    let records = RwLock::new(DnsCache::default());
+    let (response, source) = resolve(&request, None, &records, options).await.unwrap();
+    let response = Message::from_vec(&response.to_vec().unwrap()).unwrap();
+    let texts = response
+        .answers()
+        .iter()
+        .filter(|record| record.dns_class() == DNSClass::CH)
+        .filter_map(|record| match record.data() {
+            Some(RData::TXT(txt)) => Some(txt.to_string()),
+            _ => None,
+        })
+        .collect();
+    (response.response_code(), source, texts)
+}
+
+#[tokio::test]
+async fn version_bind_is_answered_in_chaos() {
+    let options = options("version", "");
+
+    let version = ask(&options, "version.bind.", RecordType::TXT, DNSClass::CH).await;
+    let hostname = ask(&options, "HOSTNAME.bind.", RecordType::TXT, DNSClass::CH).await;
+
+    assert_eq!(version, (ResponseCode::NoError, AnswerSource::Chaos, vec![format!("localdns {}", env!("CARGO_PKG_VERSION"))]));
+    assert_eq!(hostname.0, ResponseCode::NoError);
+    assert_eq!(hostname.1, AnswerSource::Chaos);
+}
+
+#[tokio::test]
+async fn other_chaos_names_and_classes_are_not_answered_from_in_data() {
+    let options = options("classes", "");
+
+    // The fallback would answer these in IN
+    let chaos = ask(&options, "nas.lan.", RecordType::A, DNSClass::CH).await;
+    let hesiod = ask(&options, "nas.lan.", RecordType::A, DNSClass::HS).await;
+    let internet = ask(&options, "nas.lan.", RecordType::A, DNSClass::IN).await;
+
+    assert_eq!(chaos, (ResponseCode::Refused, AnswerSource::None, vec![]));
+    assert_eq!(hesiod, (ResponseCode::NotImp, AnswerSource::None, vec![]));
+    assert_eq!(internet.1, AnswerSource::Fallback);
+}
+
+#[tokio::test]
+async fn expose_version_off_refuses_version_bind() {
+    let options = options("hidden", "expose_version = false\n");
+
+    let version = ask(&options, "version.bind.", RecordType::TXT, DNSClass::CH).await;
+
+    assert_eq!(version, (ResponseCode::Refused, AnswerSource::None, vec![]));
+}
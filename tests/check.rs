@@ -0,0 +1,152 @@
+use localdns::check::{self, Severity};
+use localdns::config::Config;
+use localdns::loader::load_records;
+use std::fs;
+use std::path::PathBuf;
+
+const CONFIG: &str = r#"
+listen_address = "127.0.0.1"
+listen_port = 10053
+dhcp_lease_file = "leases.json"
+hosts_file = "hosts"
+domain_suffix = "lan"
+nsid = ""
+"#;
+
+struct Dir(PathBuf);
+
+impl Drop for Dir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+/// A config in its own directory, with the lease file and hosts file next to it.
+fn setup(test: &str, extra: &str, leases: &str, hosts: &str) -> (Dir, PathBuf) {
+    let dir = std::env::temp_dir().join(format!("localdns-check-{}-{}", test, std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("leases.json"), leases).unwrap();
+    fs::write(dir.join("hosts"), hosts).unwrap();
+    let config = CONFIG.replace("\"leases.json\"", &format!("{:?}", dir.join("leases.json"))).replace("\"hosts\"", &format!("{:?}", dir.join("hosts")));
+    let path = dir.join("config.toml");
+    fs::write(&path, format!("{}{}", config, extra)).unwrap();
+    (Dir(dir), path)
+}
+
+#[test]
+fn unknown_keys_are_found_with_their_line_and_a_suggestion() {
+    let content = "domain_sufix = \"lan\"\n\n[http]\nlisten = \"127.0.0.1:8053\"\nlisten_adress = \"x\"\n\n[[records]]\nname = \"nas.lan\"\nvlaue = \"10.0.0.1\"\n\n[views.iot]\nsorces = [\"dhcp\"]\n\n[cnames]\n\"anything.lan\" = \"nas.lan\"\n\n[ddr]\nenabled = true\ntaget = \"dns.lan\"\n";
+    let unknown = check::unknown_keys(content);
+
+    assert_eq!(
+        unknown,
+        vec![
+            ("domain_sufix".to_string(), 1, Some("domain_suffix")),
+            ("http.listen_adress".to_string(), 5, None),
+            ("records.vlaue".to_string(), 9, Some("value")),
+            ("views.iot.sorces".to_string(), 12, Some("sources")),
+            ("ddr.taget".to_string(), 19, Some("target")),
+        ]
+    );
+}
+
+#[test]
+fn the_example_config_has_no_unknown_keys() {
+    let example = include_str!("../config.toml");
+    assert!(example.parse::<toml::Table>().is_ok());
+    assert_eq!(check::unknown_keys(example), Vec::new());
+}
+
+#[test]
+fn stats_interval_is_another_name_for_summary_interval() {
+    assert_eq!(check::unknown_keys("stats_interval = 60\n"), Vec::new());
+    let (_dir, path) = setup("stats-interval", "stats_interval = 60\n", "{\"Leases\": []}", "");
+    assert_eq!(Config::load(&path).unwrap().summary_interval, 60);
+}
+
+#[test]
+fn skipped_hosts_lines_are_reported_with_the_reason() {
+    let hosts = "192.168.1.5 nas.lan\n192.168.1.10O printer.lan\n192.168.1.6\nfe80::1%eth0 router.lan\n192.168.1.7 bad_name!\n";
+    let (_dir, path) = setup("hosts", "", "{\"Leases\": []}", hosts);
+    let report = check::check(&path);
+
+    let warnings: Vec<&str> = report.problems.iter().filter(|p| p.severity == Severity::Warning).map(|p| p.message.as_str()).collect();
+    assert_eq!(report.errors(), 0, "{:?}", report.problems);
+    assert_eq!(warnings.len(), 4, "{:?}", warnings);
+    assert!(warnings[0].contains("hosts:2") && warnings[0].contains("\"192.168.1.10O\" is not an IP address"), "{}", warnings[0]);
+    assert!(warnings[1].contains("hosts:3") && warnings[1].contains("expected an address followed by names"), "{}", warnings[1]);
+    assert!(warnings[2].contains("hosts:4") && warnings[2].contains("scoped address"), "{}", warnings[2]);
+    assert!(warnings[3].contains("hosts:5") && warnings[3].contains("bad_name!"), "{}", warnings[3]);
+    assert_eq!(report.loaded, Some((1, 0)));
+}
+
+#[test]
+fn broken_sources_and_settings_are_errors() {
+    let (_dir, path) = setup("broken", "domain_sufix = \"lan\"\n", "{\"Leases\": [", "192.168.1.5 nas.lan\n");
+    let report = check::check(&path);
+
+    assert_eq!(report.errors(), 2, "{:?}", report.problems);
+    assert!(report.problems[0].message.contains("unknown key `domain_sufix`"), "{}", report.problems[0]);
+    assert!(report.problems[0].message.contains("config.toml:8"), "{}", report.problems[0]);
+    assert!(report.problems[1].message.contains("leases.json"), "{}", report.problems[1]);
+    assert!(check::summary(&path, &report).ends_with("2 error(s), 0 warning(s)"));
+}
+
+#[test]
+fn a_config_that_fails_to_load_is_the_only_problem() {
+    let (_dir, path) = setup("config", "ttl = \"soon\"\n", "{\"Leases\": []}", "");
+    let report = check::check(&path);
+
+    assert_eq!(report.problems.len(), 1);
+    assert_eq!(report.problems[0].severity, Severity::Error);
+    assert!(report.problems[0].to_string().starts_with("error: "));
+    assert_eq!(report.loaded, None);
+}
+
+#[test]
+fn clean_setups_pass() {
+    let (_dir, path) = setup("clean", "", "{\"Leases\": []}", "192.168.1.5 nas.lan\n10.0.0.1 *.dev.lan\n");
+    let report = check::check(&path);
+
+    assert!(report.problems.is_empty(), "{:?}", report.problems);
+    assert!(check::summary(&path, &report).ends_with("OK, 1 names and 1 wildcards"));
+}
+
+#[test]
+fn loading_keeps_what_was_skipped() {
+    let (_dir, path) = setup("skipped", "", "{\"Leases\": []}", "192.168.1.5 nas.lan\nnot-an-address nas.lan\n");
+    let config = Config::load(&path).unwrap();
+    let records = load_records(&config.load_options(&path)).unwrap();
+
+    assert_eq!(records.skipped.len(), 1);
+    assert_eq!(records.skipped[0].source.location, 2);
+    assert_eq!(records.skipped[0].what, "line \"not-an-address nas.lan\"");
+}
+
+#[test]
+fn invalid_names_are_reported_wherever_loading_skips_them() {
+    let leases = r#"{"Leases": [{"Address": [10,0,0,2], "Hostname": "!!!"}]}"#;
+    let hosts = "192.168.1.5 nas..lan\ncname files.lan -bad.lan\nmx lan 10 relay_.lan.-\n";
+    let (_dir, path) = setup("names", "", leases, hosts);
+    let report = check::check(&path);
+
+    let warnings: Vec<&str> = report.problems.iter().filter(|p| p.severity == Severity::Warning).map(|p| p.message.as_str()).collect();
+    assert_eq!(warnings.len(), 4, "{:?}", warnings);
+    assert!(warnings.iter().any(|w| w.contains("hosts:1") && w.contains("empty label")), "{:?}", warnings);
+    assert!(warnings.iter().any(|w| w.contains("hosts:2") && w.contains("starts or ends with a hyphen")), "{:?}", warnings);
+    assert!(warnings.iter().any(|w| w.contains("hosts:3") && w.contains("invalid name")), "{:?}", warnings);
+    assert!(warnings.iter().any(|w| w.contains("DHCP hostname \"!!!\" of 10.0.0.2")), "{:?}", warnings);
+}
+
+#[test]
+fn plain_check_reports_conflicting_addresses() {
+    let leases = r#"{"Leases": [{"Address": [10,0,0,2], "Hostname": "nas"}]}"#;
+    let (_dir, path) = setup("conflicts", "", leases, "192.168.1.5 nas.lan\n");
+    let report = check::check(&path);
+
+    assert_eq!(report.errors(), 0, "{:?}", report.problems);
+    assert_eq!(report.warnings(), 1, "{:?}", report.problems);
+    let line = report.problems[0].to_string();
+    assert!(line.starts_with("warning: conflicting addresses for nas.lan.:"), "{}", line);
+    assert!(line.contains("[10.0.0.2]") && line.contains("[192.168.1.5]"), "{}", line);
+}
@@ -0,0 +1,136 @@
+use hickory_proto::op::{Message, Query, ResponseCode};
+use hickory_proto::rr::{Name, RData, RecordType};
+use localdns::config::Config;
+use localdns::loader::{load_records, DnsCache};
+use localdns::server::{resolve, AnswerSource, ResolveOptions};
+use std::fs;
+use std::path::PathBuf;
+use tokio::sync::RwLock;
+
+const CONFIG: &str = r#"
+listen_address = "127.0.0.1"
+listen_port = 10053
+dhcp_lease_file = "/nonexistent/leases"
+hosts_file = "hosts"
+domain_suffix = "lan"
+nsid = ""
+"#;
+
+const SETTINGS: &str = r#"
+[[classless_reverse]]
+network = "192.168.1.16/28"
+zone = "16-28.1.168.192.in-addr.arpa"
+"#;
+
+/// One name on each side of both ends of the /28: .16 and .31 are in it, .15 and .32 aren't.
+const HOSTS: &str = "\
+192.168.1.15 below.lan
+192.168.1.16 first.lan
+192.168.1.31 last.lan
+192.168.1.32 above.lan
+";
+
+fn scratch(test: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("localdns-classless-{}-{}", test, std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// The records and options for `SETTINGS` over `HOSTS`, loaded as the server would.
+fn load(test: &str) -> (RwLock<DnsCache>, ResolveOptions) {
+    let dir = scratch(test);
+    fs::write(dir.join("hosts"), HOSTS).unwrap();
+    let path = dir.join("config.toml");
+    fs::write(&path, format!("{}{}", CONFIG, SETTINGS)).unwrap();
+    let mut config = Config::load(&path).unwrap();
+    config.hosts_file = dir.join("hosts");
+    let records = load_records(&config.load_options(&path)).unwrap();
+    fs::remove_dir_all(&dir).unwrap();
+    (RwLock::new(records), ResolveOptions::from_config(&config))
+}
+
+async fn ptr((records, options): &(RwLock<DnsCache>, ResolveOptions), name: &str) -> (Message, AnswerSource) {
+    let mut request = Message::new();
+    request.set_id(7).set_recursion_desired(true);
+    request.add_query(Query::query(Name::from_ascii(name).unwrap(), RecordType::PTR));
+    resolve(&request, None, records, options).await.unwrap()
+}
+
+/// Each answer as `owner type data`.
+fn answers(response: &Message) -> Vec<String> {
+    response
+        .answers()
+        .iter()
+        .map(|record| match record.data() {
+            Some(RData::CNAME(target)) => format!("{} CNAME {}", record.name(), target.0),
+            Some(RData::PTR(target)) => format!("{} PTR {}", record.name(), target.0),
+            other => format!("{} {:?}", record.name(), other),
+        })
+        .collect()
+}
+
+#[tokio::test]
+async fn both_ends_of_the_block_alias_into_the_classless_zone() {
+    let loaded = load("alias");
+
+    let (response, source) = ptr(&loaded, "16.1.168.192.in-addr.arpa.").await;
+    assert_eq!(source, AnswerSource::Classless);
+    assert_eq!(
+        answers(&response),
+        [
+            "16.1.168.192.in-addr.arpa. CNAME 16.16-28.1.168.192.in-addr.arpa.",
+            "16.16-28.1.168.192.in-addr.arpa. PTR first.lan.",
+        ]
+    );
+
+    let (response, source) = ptr(&loaded, "31.1.168.192.in-addr.arpa.").await;
+    assert_eq!(source, AnswerSource::Classless);
+    assert_eq!(
+        answers(&response),
+        [
+            "31.1.168.192.in-addr.arpa. CNAME 31.16-28.1.168.192.in-addr.arpa.",
+            "31.16-28.1.168.192.in-addr.arpa. PTR last.lan.",
+        ]
+    );
+}
+
+#[tokio::test]
+async fn addresses_just_outside_the_block_get_a_plain_ptr() {
+    let loaded = load("outside");
+
+    for (name, target) in [("15.1.168.192.in-addr.arpa.", "below.lan."), ("32.1.168.192.in-addr.arpa.", "above.lan.")] {
+        let (response, source) = ptr(&loaded, name).await;
+        assert_eq!(source, AnswerSource::Ptr, "{}", name);
+        assert_eq!(answers(&response), [format!("{} PTR {}", name, target)]);
+    }
+}
+
+#[tokio::test]
+async fn the_classless_zone_answers_only_for_its_own_addresses() {
+    let loaded = load("zone");
+
+    let (response, _) = ptr(&loaded, "16.16-28.1.168.192.in-addr.arpa.").await;
+    assert_eq!(answers(&response), ["16.16-28.1.168.192.in-addr.arpa. PTR first.lan."]);
+    let (response, _) = ptr(&loaded, "31.16-28.1.168.192.in-addr.arpa.").await;
+    assert_eq!(answers(&response), ["31.16-28.1.168.192.in-addr.arpa. PTR last.lan."]);
+
+    // Labels for the neighbours don't exist in the zone, even though they have names
+    for outside in ["15.16-28.1.168.192.in-addr.arpa.", "32.16-28.1.168.192.in-addr.arpa."] {
+        let (response, source) = ptr(&loaded, outside).await;
+        assert_eq!((response.response_code(), source), (ResponseCode::NXDomain, AnswerSource::Classless), "{}", outside);
+        assert!(response.answers().is_empty());
+    }
+    let (response, _) = ptr(&loaded, "16-28.1.168.192.in-addr.arpa.").await;
+    assert_eq!(response.response_code(), ResponseCode::NoError);
+}
+
+#[test]
+fn blocks_must_be_narrower_than_a_24_with_no_host_bits() {
+    let dir = scratch("invalid");
+    let path = dir.join("config.toml");
+    for network in ["192.168.1.0/24", "192.168.1.17/28", "192.168.1.16"] {
+        fs::write(&path, format!("{}{}", CONFIG, SETTINGS.replace("192.168.1.16/28", network))).unwrap();
+        assert!(Config::load(&path).is_err(), "{}", network);
+    }
+    fs::remove_dir_all(&dir).unwrap();
+}
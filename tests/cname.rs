@@ -0,0 +1,162 @@
+use hickory_proto::op::{Message, Query, ResponseCode};
+use hickory_proto::rr::{Name, RData, RecordType};
+use localdns::config::{Config, ConfigError};
+use localdns::loader::{load_records, parse_key, DnsCache, SourceKind};
+use localdns::server::{resolve, AnswerSource, ResolveOptions};
+use localdns::view::ViewConfig;
+use std::fs;
+use tokio::sync::RwLock;
+
+const CONFIG: &str = r#"
+listen_address = "127.0.0.1"
+listen_port = 10053
+dhcp_lease_file = "leases.json"
+hosts_file = "hosts"
+domain_suffix = "lan"
+nsid = ""
+
+[cnames]
+"files.lan" = "nas.lan"
+"www.lan" = "files.lan"
+"#;
+
+const HOSTS: &str = "\
+192.168.1.5 nas.lan
+192.168.1.9 brother-hl.lan
+cname printer.lan brother-hl.lan
+cname nas.lan other.lan
+cname loop-a.lan loop-b.lan
+cname loop-b.lan loop-a.lan
+cname broken..lan nas.lan
+";
+
+/// Loads `HOSTS` and the `[cnames]` in `config` from a scratch directory.
+fn load(test: &str, config: &str) -> (DnsCache, ResolveOptions) {
+    let dir = std::env::temp_dir().join(format!("localdns-cname-{}-{}", test, std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("hosts"), HOSTS).unwrap();
+    fs::write(dir.join("leases.json"), r#"{"Leases":[]}"#).unwrap();
+    let path = dir.join("config.toml");
+    fs::write(&path, config).unwrap();
+    let mut config = Config::load(&path).unwrap();
+    config.hosts_file = dir.join("hosts");
+    config.dhcp_lease_file = dir.join("leases.json");
+    let cache = load_records(&config.load_options(&path)).unwrap();
+    fs::remove_dir_all(&dir).unwrap();
+    (cache, ResolveOptions::from_config(&config))
+}
+
+/// The response code, answer source, and each answer as `owner type data`.
+async fn answer(cache: DnsCache, options: &ResolveOptions, name: &str, qtype: RecordType) -> (ResponseCode, AnswerSource, Vec<String>) {
+    let mut request = Message::new();
+    request.add_query(Query::query(Name::from_ascii(name).unwrap(), qtype));
+    let (response, source) = resolve(&request, None, &RwLock::new(cache), options).await.unwrap();
+    let answers = response
+        .answers()
+        .iter()
+        .map(|r| {
+            let data = match r.data() {
+                Some(RData::CNAME(cname)) => cname.0.to_string(),
+                Some(data) => data.to_string(),
+                None => String::new(),
+            };
+            format!("{} {} {}", r.name(), r.record_type(), data)
+        })
+        .collect();
+    (response.response_code(), source, answers)
+}
+
+#[test]
+fn aliases_load_from_hosts_and_config() {
+    let (cache, _) = load("load", CONFIG);
+
+    assert_eq!(cache.cnames[&parse_key("printer.lan.").unwrap()].target, "brother-hl.lan.");
+    assert_eq!(cache.cnames[&parse_key("printer.lan.").unwrap()].source.kind, SourceKind::Hosts);
+    assert_eq!(cache.cnames[&parse_key("files.lan.").unwrap()].source.kind, SourceKind::Config);
+    // A name with addresses can't also be an alias
+    assert!(!cache.cnames.contains_key(&parse_key("nas.lan.").unwrap()));
+    assert_eq!(cache.cnames.len(), 5);
+}
+
+#[tokio::test]
+async fn cname_queries_get_the_alias() {
+    let (cache, options) = load("cname", CONFIG);
+
+    assert_eq!(
+        answer(cache, &options, "printer.lan.", RecordType::CNAME).await,
+        (ResponseCode::NoError, AnswerSource::Alias, vec!["printer.lan. CNAME brother-hl.lan.".to_string()])
+    );
+}
+
+#[tokio::test]
+async fn address_queries_follow_the_chain() {
+    let (cache, options) = load("chain", CONFIG);
+    let (code, _, answers) = answer(cache, &options, "WWW.lan.", RecordType::A).await;
+
+    assert_eq!(code, ResponseCode::NoError);
+    assert_eq!(answers, ["WWW.lan. CNAME files.lan.", "files.lan. CNAME nas.lan.", "nas.lan. A 192.168.1.5"]);
+}
+
+#[tokio::test]
+async fn other_types_and_families_get_just_the_aliases() {
+    let (cache, options) = load("nodata", CONFIG);
+    let (code, _, answers) = answer(cache, &options, "printer.lan.", RecordType::AAAA).await;
+
+    assert_eq!(code, ResponseCode::NoError);
+    assert_eq!(answers, ["printer.lan. CNAME brother-hl.lan."]);
+}
+
+#[tokio::test]
+async fn loops_stop_at_the_chain_limit() {
+    let (cache, options) = load("loop", CONFIG);
+    let (code, _, answers) = answer(cache, &options, "loop-a.lan.", RecordType::A).await;
+
+    assert_eq!(code, ResponseCode::NoError);
+    assert_eq!(answers.len(), 8);
+}
+
+#[tokio::test]
+async fn chains_past_max_alias_chain_are_cut_short() {
+    let config = format!("{}\n[limits]\nmax_alias_chain = 1\n", CONFIG);
+    let (cache, options) = load("chain-limit", &config);
+    let (code, _, answers) = answer(cache, &options, "www.lan.", RecordType::A).await;
+
+    // The first link is answered, but files.lan is not followed to nas.lan's address
+    assert_eq!(code, ResponseCode::NoError);
+    assert_eq!(answers, ["www.lan. CNAME files.lan."]);
+}
+
+#[test]
+fn max_alias_chain_must_be_at_least_one() {
+    let dir = std::env::temp_dir().join(format!("localdns-cname-zero-chain-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("config.toml");
+    fs::write(&path, format!("{}\n[limits]\nmax_alias_chain = 0\n", CONFIG)).unwrap();
+    let result = Config::load(&path);
+    fs::remove_dir_all(&dir).unwrap();
+
+    assert!(matches!(result, Err(ConfigError::Validation { field: "limits.max_alias_chain", .. })), "{:?}", result.err());
+}
+
+#[tokio::test]
+async fn views_hide_aliases_from_hidden_sources() {
+    let (cache, options) = load("view", CONFIG);
+    let view = ViewConfig { sources: vec![SourceKind::Hosts], ..ViewConfig::default() };
+    let options = view.apply("hosts-only", &options);
+
+    let (code, _, answers) = answer(cache, &options, "files.lan.", RecordType::A).await;
+    assert_eq!(code, ResponseCode::NXDomain);
+    assert!(answers.is_empty());
+}
+
+#[test]
+fn invalid_config_aliases_are_rejected() {
+    let dir = std::env::temp_dir().join(format!("localdns-cname-invalid-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("config.toml");
+    fs::write(&path, format!("{}\"bad..lan\" = \"nas.lan\"\n", CONFIG)).unwrap();
+    let error = Config::load(&path).unwrap_err().to_string();
+    fs::remove_dir_all(&dir).unwrap();
+
+    assert!(error.contains("cnames"), "{}", error);
+}
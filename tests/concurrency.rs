@@ -0,0 +1,150 @@
+use hickory_proto::op::{Message, Query};
+use hickory_proto::rr::{Name, RData, RecordType};
+use localdns::buffers::BufferPool;
+use localdns::config::Config;
+use localdns::server::{serve, ResolveOptions};
+use localdns::synthetic;
+use std::collections::HashSet;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::UdpSocket;
+use tokio::sync::RwLock;
+
+const CONFIG: &str = r#"
+listen_address = "127.0.0.1"
+listen_port = 10053
+dhcp_lease_file = "/nonexistent/leases"
+hosts_file = "/nonexistent/hosts"
+domain_suffix = "lan"
+nsid = ""
+"#;
+
+const CLIENTS: usize = 10;
+const QUERIES_PER_CLIENT: usize = 500;
+const NAMES: usize = 1000;
+
+fn options(test: &str, settings: &str) -> ResolveOptions {
+    let path = std::env::temp_dir().join(format!("localdns-concurrency-{}-{}.toml", test, std::process::id()));
+    std::fs::write(&path, format!("{}{}", settings, CONFIG)).unwrap();
+    let config = Config::load(&path).unwrap();
+    let _ = std::fs::remove_file(&path);
+    ResolveOptions::from_config(&config)
+}
+
+fn request(id: u16, host: usize) -> Vec<u8> {
+    query(id, &synthetic::exact_name(host))
+}
+
+fn query(id: u16, name: &str) -> Vec<u8> {
+    let mut request = Message::new();
+    request.set_id(id);
+    request.add_query(Query::query(Name::from_ascii(name).unwrap(), RecordType::A));
+    request.to_vec().unwrap()
+}
+
+/// Sends every query of `hosts` at once, query ID `i` asking for
+/// `synthetic::exact_name(hosts[i])`, and checks each answer belongs to the
+/// query with its ID.
+async fn ask_all(server: SocketAddr, hosts: Vec<usize>) {
+    let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let mut unanswered: HashSet<u16> = (0..hosts.len() as u16).collect();
+    let mut buf = [0u8; 4096];
+    // Loopback drops datagrams when the server's receive queue overflows, so
+    // what isn't answered is asked again
+    for _ in 0..10 {
+        for &id in &unanswered {
+            client.send_to(&request(id, hosts[id as usize]), server).await.unwrap();
+        }
+        while let Ok(received) = tokio::time::timeout(Duration::from_millis(500), client.recv(&mut buf)).await {
+            let response = Message::from_vec(&buf[..received.unwrap()]).unwrap();
+            let host = hosts[response.id() as usize];
+            assert_eq!(response.queries()[0].name().to_string(), synthetic::exact_name(host));
+            let addresses: Vec<IpAddr> = response
+                .answers()
+                .iter()
+                .filter_map(|record| match record.data() {
+                    Some(RData::A(a)) => Some(IpAddr::V4(a.0)),
+                    _ => None,
+                })
+                .collect();
+            assert_eq!(addresses, [IpAddr::V4(Ipv4Addr::from(0x0a00_0000 + host as u32))]);
+            unanswered.remove(&response.id());
+            if unanswered.is_empty() {
+                return;
+            }
+        }
+    }
+    panic!("{} queries were never answered", unanswered.len());
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn thousands_of_concurrent_queries_each_get_their_own_answer() {
+    let records = Arc::new(RwLock::new(synthetic::cache(NAMES, 0)));
+    let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let server = socket.local_addr().unwrap();
+    tokio::spawn(serve(socket, records, options("stress", "")));
+
+    let clients: Vec<_> = (0..CLIENTS)
+        .map(|client| {
+            let hosts = (0..QUERIES_PER_CLIENT).map(|query| (client * QUERIES_PER_CLIENT + query * 7) % NAMES).collect();
+            tokio::spawn(ask_all(server, hosts))
+        })
+        .collect();
+    for client in clients {
+        client.await.unwrap();
+    }
+}
+
+#[tokio::test]
+async fn queries_past_max_inflight_are_dropped_until_one_finishes() {
+    // Holds every forwarded query until upstream_timeout_ms runs out
+    let silent = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let settings = format!("upstream = [\"{}\"]\nupstream_timeout_ms = 1000\nmax_inflight_queries = 2\n", silent.local_addr().unwrap());
+    let options = options("saturated", &settings);
+    let stats = options.stats.clone();
+    let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let server = socket.local_addr().unwrap();
+    tokio::spawn(serve(socket, Arc::new(RwLock::new(synthetic::cache(1, 0))), options));
+    let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let mut buf = [0u8; 4096];
+
+    client.send_to(&query(1, "slow.example."), server).await.unwrap();
+    client.send_to(&query(2, "slow.example."), server).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    client.send_to(&request(3, 0), server).await.unwrap();
+
+    // Only the two forwarded queries are answered, once the upstream times out
+    let mut answered = Vec::new();
+    for _ in 0..2 {
+        let len = tokio::time::timeout(Duration::from_secs(5), client.recv(&mut buf)).await.unwrap().unwrap();
+        answered.push(Message::from_vec(&buf[..len]).unwrap().id());
+    }
+    answered.sort();
+    assert_eq!(answered, [1, 2]);
+    assert_eq!(stats.overloaded.load(Ordering::Relaxed), 1);
+
+    // With the permits back, local names are answered again
+    client.send_to(&request(4, 0), server).await.unwrap();
+    let len = tokio::time::timeout(Duration::from_secs(5), client.recv(&mut buf)).await.unwrap().unwrap();
+    let response = Message::from_vec(&buf[..len]).unwrap();
+    assert_eq!((response.id(), response.answers().len()), (4, 1));
+}
+
+#[test]
+fn buffers_go_back_to_the_pool_until_it_is_full() {
+    let pool = BufferPool::new(512, 2);
+
+    let mut first = pool.take();
+    assert_eq!(first.len(), 512);
+    first.truncate(40);
+    let buffers: Vec<_> = (0..3).map(|_| pool.take()).collect();
+    drop(first);
+    assert_eq!(pool.idle(), 1);
+    // Taken again at full length, whatever was last written to it
+    assert_eq!(pool.take().len(), 512);
+
+    drop(buffers);
+    assert_eq!(pool.idle(), 2);
+}
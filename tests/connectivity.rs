@@ -0,0 +1,92 @@
+use hickory_proto::op::{Message, Query, ResponseCode};
+use hickory_proto::rr::{Name, RData, RecordType};
+use localdns::config::Config;
+use localdns::connectivity::{CheckPolicy, ConnectivityCheckConfig, ConnectivityChecks};
+use localdns::loader::DnsCache;
+use localdns::server::{resolve, AnswerSource, ResolveOptions};
+use std::net::{IpAddr, Ipv4Addr};
+use tokio::sync::RwLock;
+
+const CONFIG: &str = r#"
+listen_address = "127.0.0.1"
+listen_port = 10053
+dhcp_lease_file = "/nonexistent/leases"
+hosts_file = "/nonexistent/hosts"
+domain_suffix = "lan"
+nsid = ""
+fallback_ip = "192.168.1.2"
+
+[connectivity_checks]
+add = ["portal-check.lan"]
+remove = ["nmcheck.gnome.org"]
+
+[connectivity_checks.overrides]
+"captive.apple.com" = "portal"
+"#;
+
+fn options(test: &str) -> ResolveOptions {
+    let path = std::env::temp_dir().join(format!("localdns-connectivity-{}-{}.toml", test, std::process::id()));
+    std::fs::write(&path, CONFIG).unwrap();
+    let config = Config::load(&path).unwrap();
+    let _ = std::fs::remove_file(&path);
+    ResolveOptions::from_config(&config)
+}
+
+async fn answer(options: &ResolveOptions, name: &str) -> (ResponseCode, AnswerSource, Vec<IpAddr>) {
+    let mut request = Message::new();
+    request.set_id(7).set_recursion_desired(true);
+    request.add_query(Query::query(Name::from_ascii(name).unwrap(), RecordType::A));
+    let (response, source) = resolve(&request, None, &RwLock::new(DnsCache::default()), options).await.unwrap();
+    let addresses = response
+        .answers()
+        .iter()
+        .filter_map(|record| match record.data() {
+            Some(RData::A(a)) => Some(IpAddr::V4(a.0)),
+            _ => None,
+        })
+        .collect();
+    (response.response_code(), source, addresses)
+}
+
+#[tokio::test]
+async fn listed_domains_are_exempt_from_the_fallback() {
+    let options = options("exempt");
+
+    let (code, source, addresses) = answer(&options, "connectivitycheck.gstatic.com.").await;
+    assert_eq!((code, source), (ResponseCode::NXDomain, AnswerSource::ConnectivityCheck));
+    assert!(addresses.is_empty());
+    // Names below a listed domain count too
+    assert_eq!(answer(&options, "a.www.msftconnecttest.com.").await.1, AnswerSource::ConnectivityCheck);
+
+    // An added domain under our zone is exempt, where a neighbour gets the catch-all
+    assert_eq!(answer(&options, "portal-check.lan.").await.0, ResponseCode::NXDomain);
+    let fallback = [IpAddr::V4(Ipv4Addr::new(192, 168, 1, 2))];
+    assert_eq!(answer(&options, "other.lan.").await, (ResponseCode::NoError, AnswerSource::Fallback, fallback.to_vec()));
+}
+
+#[tokio::test]
+async fn portal_overrides_answer_with_the_portal_ip() {
+    let options = options("portal");
+
+    let (code, source, addresses) = answer(&options, "captive.apple.com.").await;
+    assert_eq!((code, source), (ResponseCode::NoError, AnswerSource::ConnectivityCheck));
+    assert_eq!(addresses, [IpAddr::V4(Ipv4Addr::new(192, 168, 1, 2))]);
+}
+
+#[test]
+fn the_list_can_be_trimmed_and_extended() {
+    let config = ConnectivityCheckConfig {
+        policy: CheckPolicy::Portal,
+        portal_ip: Some(Ipv4Addr::new(10, 0, 0, 1)),
+        add: vec!["Check.Example.com.".to_string()],
+        remove: vec!["nmcheck.gnome.org".to_string()],
+        overrides: [("captive.apple.com".to_string(), CheckPolicy::Exempt)].into(),
+    };
+    let checks = ConnectivityChecks::new(&config, Some(Ipv4Addr::new(192, 168, 1, 2)));
+
+    assert_eq!(checks.portal_ip, Some(Ipv4Addr::new(10, 0, 0, 1)));
+    assert_eq!(checks.lookup("check.example.com."), Some(CheckPolicy::Portal));
+    assert_eq!(checks.lookup("captive.apple.com."), Some(CheckPolicy::Exempt));
+    assert_eq!(checks.lookup("nmcheck.gnome.org."), None);
+    assert_eq!(checks.lookup("example.com."), None);
+}
@@ -0,0 +1,154 @@
+#![cfg(unix)]
+
+use localdns::control::{self, Control, ReloadRequest, Request};
+use localdns::dump::DumpFormat;
+use localdns::loader::{load_records, parse_key, DnsCache, LoadOptions, SourceKind};
+use localdns::overlay::Overlay;
+use std::fs;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::sync::{mpsc, RwLock};
+
+struct Server {
+    dir: PathBuf,
+    socket: PathBuf,
+    records: Arc<RwLock<DnsCache>>,
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
+
+/// A control socket over the records of a hosts file, reloaded as the server's reload loop would.
+async fn start(test: &str, hosts: &str) -> Server {
+    let dir = std::env::temp_dir().join(format!("localdns-control-{}-{}", test, std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("hosts"), hosts).unwrap();
+    let socket = dir.join("control.sock");
+    let overlay = Arc::new(Overlay::default());
+    let options = LoadOptions {
+        overlay: overlay.clone(),
+        ..LoadOptions::new(dir.join("leases.json"), dir.join("hosts"), "lan", dir.join("config.toml"))
+    };
+    let records = Arc::new(RwLock::new(load_records(&options).unwrap()));
+
+    let (reloads, mut requests) = mpsc::unbounded_channel::<ReloadRequest>();
+    let reloaded = records.clone();
+    tokio::spawn(async move {
+        while let Some(done) = requests.recv().await {
+            *reloaded.write().await = load_records(&options).unwrap();
+            let _ = done.send(());
+        }
+    });
+    let control = Arc::new(Control { overlay, records: records.clone(), reloads, hosts_path: dir.join("hosts"), blocks: Default::default(), events: Arc::default() });
+    tokio::spawn(control::serve(control::bind(&socket).unwrap(), control));
+    Server { dir, socket, records }
+}
+
+async fn ctl(socket: &Path, command: &str) -> Result<Vec<String>, String> {
+    let (socket, command) = (socket.to_path_buf(), command.to_string());
+    tokio::task::spawn_blocking(move || control::request(&socket, &command).unwrap()).await.unwrap()
+}
+
+async fn addresses(records: &RwLock<DnsCache>, name: &str) -> Vec<IpAddr> {
+    records.read().await.exact_matches.get(&parse_key(name).unwrap()).map(|entries| entries.iter().map(|e| e.ip).collect()).unwrap_or_default()
+}
+
+#[test]
+fn commands_parse() {
+    assert_eq!(
+        Request::parse("add-record vm1.lan a 10.0.0.9 30"),
+        Ok(Request::AddRecord { name: "vm1.lan".to_string(), kind: "A".to_string(), value: "10.0.0.9".to_string(), ttl: Some(30), persist: false })
+    );
+    assert_eq!(
+        Request::parse("add-record --persist-to-hosts vm1.lan AAAA fd00::9"),
+        Ok(Request::AddRecord { name: "vm1.lan".to_string(), kind: "AAAA".to_string(), value: "fd00::9".to_string(), ttl: None, persist: true })
+    );
+    assert_eq!(Request::parse("remove-record vm1.lan"), Ok(Request::RemoveRecord { name: "vm1.lan".to_string() }));
+    assert_eq!(Request::parse("  list "), Ok(Request::List));
+    assert_eq!(Request::parse("reload"), Ok(Request::Reload));
+    assert_eq!(Request::parse("dump"), Ok(Request::Dump { format: DumpFormat::Text }));
+    assert_eq!(Request::parse("dump json"), Ok(Request::Dump { format: DumpFormat::Json }));
+    assert_eq!(Request::parse("blocks"), Ok(Request::Blocks));
+
+    for bad in ["", "add-record vm1.lan A", "add-record vm1.lan MX 10.0.0.9", "add-record vm1.lan A 10.0.0.9 soon", "add-record --persist-to-hosts vm1.lan A 10.0.0.9 30", "remove-record", "list all", "dump yaml", "dump json text", "blocks all", "frobnicate"] {
+        assert!(Request::parse(bad).is_err(), "{:?}", bad);
+    }
+}
+
+#[tokio::test]
+async fn added_records_are_served_and_listed() {
+    let server = start("add", "192.168.1.5 nas.lan\n").await;
+
+    assert_eq!(ctl(&server.socket, "add-record vm1.lan A 10.0.0.9").await, Ok(Vec::new()));
+    assert_eq!(ctl(&server.socket, "add-record *.vms.lan AAAA fd00::9 30").await, Ok(Vec::new()));
+
+    assert_eq!(addresses(&server.records, "vm1.lan.").await, ["10.0.0.9".parse::<IpAddr>().unwrap()]);
+    let records = server.records.read().await;
+    assert_eq!(records.exact_matches[&parse_key("vm1.lan.").unwrap()][0].source.kind, SourceKind::Runtime);
+    assert_eq!(records.wildcards.iter().find(|(p, _)| p == "*.vms.lan.").unwrap().1.ttl, Some(30));
+    drop(records);
+
+    let list = ctl(&server.socket, "list").await.unwrap();
+    assert!(list.iter().any(|line| line.starts_with("vm1.lan. A 10.0.0.9 runtime ")), "{:?}", list);
+    assert!(list.iter().any(|line| line.starts_with("nas.lan. A 192.168.1.5 hosts ")), "{:?}", list);
+    assert!(list.iter().any(|line| line.starts_with("*.vms.lan. AAAA fd00::9 runtime ")), "{:?}", list);
+
+    assert!(ctl(&server.socket, "add-record vm1.lan A 10.0.0.9").await.unwrap_err().contains("already"));
+    assert!(ctl(&server.socket, "add-record vm1.lan A 10.0.0").await.unwrap_err().contains("10.0.0"));
+}
+
+#[tokio::test]
+async fn runtime_changes_outlive_file_reloads() {
+    let server = start("reload", "192.168.1.5 nas.lan\n192.168.1.6 printer.lan\n").await;
+    ctl(&server.socket, "add-record vm1.lan A 10.0.0.9").await.unwrap();
+    ctl(&server.socket, "remove-record printer.lan").await.unwrap();
+    assert!(addresses(&server.records, "printer.lan.").await.is_empty());
+
+    // The hosts file changes and is reloaded, as the watcher would
+    fs::write(server.dir.join("hosts"), "192.168.1.5 nas.lan\n192.168.1.7 printer.lan\n").unwrap();
+    ctl(&server.socket, "reload").await.unwrap();
+
+    assert_eq!(addresses(&server.records, "vm1.lan.").await, ["10.0.0.9".parse::<IpAddr>().unwrap()]);
+    assert!(addresses(&server.records, "printer.lan.").await.is_empty());
+    assert_eq!(addresses(&server.records, "nas.lan.").await.len(), 1);
+
+    // Adding a removed name again serves only what was added
+    ctl(&server.socket, "add-record printer.lan A 10.0.0.7").await.unwrap();
+    assert_eq!(addresses(&server.records, "printer.lan.").await, ["10.0.0.7".parse::<IpAddr>().unwrap()]);
+    ctl(&server.socket, "remove-record vm1.lan").await.unwrap();
+    assert!(addresses(&server.records, "vm1.lan.").await.is_empty());
+    assert!(ctl(&server.socket, "remove-record vm1.lan").await.unwrap_err().contains("no address records"));
+}
+
+#[tokio::test]
+async fn persisted_records_are_appended_to_the_hosts_file() {
+    // No trailing newline, as a hand-edited file may end
+    let server = start("persist", "192.168.1.5 nas.lan").await;
+
+    ctl(&server.socket, "add-record --persist-to-hosts vm1.lan A 10.0.0.9").await.unwrap();
+
+    assert_eq!(fs::read_to_string(server.dir.join("hosts")).unwrap(), "192.168.1.5 nas.lan\n10.0.0.9 vm1.lan\n");
+    let records = server.records.read().await;
+    assert_eq!(records.exact_matches[&parse_key("vm1.lan.").unwrap()][0].source.kind, SourceKind::Hosts);
+}
+
+#[tokio::test]
+async fn dumps_show_the_records_in_memory_rather_than_the_files() {
+    let server = start("dump", "192.168.1.5 nas.lan\n").await;
+    ctl(&server.socket, "add-record vm1.lan A 10.0.0.9").await.unwrap();
+    // Changed on disk, but not reloaded yet
+    fs::write(server.dir.join("hosts"), "192.168.1.99 nas.lan\n").unwrap();
+
+    let dump = ctl(&server.socket, "dump").await.unwrap();
+    assert!(dump.iter().any(|line| line.starts_with("exact nas.lan. A 192.168.1.5 ")), "{:?}", dump);
+    assert!(dump.iter().any(|line| line.starts_with("exact vm1.lan. A 10.0.0.9 ttl=- runtime ")), "{:?}", dump);
+
+    let json = ctl(&server.socket, "dump json").await.unwrap();
+    assert_eq!(json.len(), 1);
+    let json: serde_json::Value = serde_json::from_str(&json[0]).unwrap();
+    assert_eq!(json["exact"]["vm1.lan."][0]["source"], "runtime");
+}
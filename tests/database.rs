@@ -0,0 +1,92 @@
+#![cfg(feature = "sqlite")]
+
+use localdns::database::DEFAULT_QUERY;
+use localdns::loader::{load_records, parse_key, LoadOptions};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+fn options(dir: &Path, db: PathBuf, query: &str) -> LoadOptions {
+    LoadOptions {
+        records_db: Some(db),
+        records_db_query: query.to_string(),
+        ..LoadOptions::new(dir.join("leases.json"), dir.join("hosts"), "lan", dir.join("config.toml"))
+    }
+}
+
+/// A scratch directory with empty DHCP and hosts files and a records database.
+fn setup(test: &str, sql: &str) -> (PathBuf, PathBuf) {
+    let dir = std::env::temp_dir().join(format!("localdns-db-{}-{}", test, std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("leases.json"), r#"{"Leases":[]}"#).unwrap();
+    fs::write(dir.join("hosts"), "").unwrap();
+    let db = dir.join("records.sqlite");
+    let _ = fs::remove_file(&db);
+    let connection = rusqlite::Connection::open(&db).unwrap();
+    connection.execute_batch(sql).unwrap();
+    (dir, db)
+}
+
+#[test]
+fn rows_load_like_any_other_source() {
+    let (dir, db) = setup(
+        "rows",
+        "CREATE TABLE records (name TEXT NOT NULL, type TEXT NOT NULL DEFAULT 'A', value TEXT NOT NULL, ttl INTEGER);
+         INSERT INTO records VALUES ('nas.lan', 'A', '192.168.1.5', 300);
+         INSERT INTO records VALUES ('*.dev.lan', 'a', '192.168.1.6', NULL);
+         INSERT INTO records VALUES ('192.168.1.7', 'PTR', 'printer.lan', NULL);
+         INSERT INTO records VALUES ('bad..lan', 'A', '192.168.1.8', NULL);
+         INSERT INTO records VALUES ('mail.lan', 'MX', 'mx.lan', NULL);",
+    );
+    let cache = load_records(&options(&dir, db, DEFAULT_QUERY)).unwrap();
+    fs::remove_dir_all(&dir).unwrap();
+
+    assert!(cache.failures.is_empty());
+    let nas = &cache.exact_matches[&parse_key("nas.lan.").unwrap()];
+    assert_eq!(nas[0].ip.to_string(), "192.168.1.5");
+    assert_eq!(nas[0].ttl, Some(300));
+    assert_eq!(nas[0].source.to_string(), format!("database {}#1", dir.join("records.sqlite").display()));
+    assert_eq!(cache.wildcards[0].0, "*.dev.lan.");
+    assert_eq!(cache.reverse[&"192.168.1.7".parse().unwrap()], ["printer.lan."]);
+    assert_eq!(cache.exact_matches.len(), 1);
+}
+
+#[test]
+fn aaaa_rows_need_an_ipv6_address() {
+    let (dir, db) = setup(
+        "aaaa",
+        "CREATE TABLE records (name TEXT NOT NULL, type TEXT NOT NULL DEFAULT 'A', value TEXT NOT NULL, ttl INTEGER);
+         INSERT INTO records VALUES ('nas.lan', 'AAAA', 'fd00::5', NULL);
+         INSERT INTO records VALUES ('tv.lan', 'A', 'fd00::6', NULL);
+         INSERT INTO records VALUES ('tv.lan', 'AAAA', '192.168.1.6', NULL);",
+    );
+    let cache = load_records(&options(&dir, db, DEFAULT_QUERY)).unwrap();
+    fs::remove_dir_all(&dir).unwrap();
+
+    assert_eq!(cache.exact_matches[&parse_key("nas.lan.").unwrap()][0].ip.to_string(), "fd00::5");
+    assert!(!cache.exact_matches.contains_key(&parse_key("tv.lan.").unwrap()));
+}
+
+#[test]
+fn a_custom_query_can_map_another_schema() {
+    let (dir, db) = setup(
+        "query",
+        "CREATE TABLE hosts (hostname TEXT, address TEXT);
+         INSERT INTO hosts VALUES ('nas.lan', '192.168.1.5');",
+    );
+    let query = "SELECT hostname, 'A', address, NULL FROM hosts";
+    let cache = load_records(&options(&dir, db, query)).unwrap();
+    fs::remove_dir_all(&dir).unwrap();
+
+    assert!(cache.exact_matches.contains_key(&parse_key("nas.lan.").unwrap()));
+}
+
+#[test]
+fn database_errors_are_source_failures() {
+    let (dir, db) = setup("broken", "CREATE TABLE other (x TEXT);");
+    let cache = load_records(&options(&dir, db, DEFAULT_QUERY)).unwrap();
+    fs::remove_dir_all(&dir).unwrap();
+
+    assert_eq!(cache.failures.len(), 1);
+    assert!(cache.failures[0].is_read_error());
+    assert!(cache.failures[0].to_string().contains("no such table"), "{}", cache.failures[0]);
+}
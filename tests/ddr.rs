@@ -0,0 +1,112 @@
+use hickory_proto::op::Message;
+use hickory_proto::rr::rdata::svcb::{SvcParamKey, SvcParamValue, SVCB};
+use hickory_proto::rr::{Name, RData, Record, RecordType};
+use localdns::config::{Config, ConfigError};
+use localdns::ddr::{Ddr, DdrConfig, DDR_NAME};
+use std::net::{IpAddr, Ipv4Addr};
+
+const CONFIG: &str = r#"
+listen_address = "192.168.1.1"
+dhcp_lease_file = "leases.json"
+hosts_file = "hosts"
+domain_suffix = "lan"
+
+[http]
+listen = "127.0.0.1:8443"
+
+[ddr]
+enabled = true
+target = "dns.home.arpa"
+"#;
+
+/// Sends the synthesized records through the wire format and parses them back.
+fn decode(records: Vec<RData>) -> Vec<SVCB> {
+    let mut message = Message::new();
+    for rdata in records {
+        message.add_answer(Record::from_rdata(Name::from_ascii(DDR_NAME).unwrap(), 300, rdata));
+    }
+    let parsed = Message::from_vec(&message.to_vec().unwrap()).unwrap();
+    parsed
+        .answers()
+        .iter()
+        .map(|record| {
+            assert_eq!(record.record_type(), RecordType::SVCB);
+            match record.data() {
+                Some(RData::SVCB(svcb)) => svcb.clone(),
+                other => panic!("expected SVCB, got {:?}", other),
+            }
+        })
+        .collect()
+}
+
+fn param(svcb: &SVCB, key: SvcParamKey) -> Option<&SvcParamValue> {
+    svcb.svc_params().iter().find(|(k, _)| *k == key).map(|(_, v)| v)
+}
+
+#[test]
+fn advertises_dns_query_on_the_http_port() {
+    let config: Config = toml::from_str(CONFIG).unwrap();
+    let records = decode(config.ddr());
+    assert_eq!(records.len(), 1);
+
+    let doh = &records[0];
+    assert_eq!(doh.svc_priority(), 1);
+    assert_eq!(doh.target_name(), &Name::from_ascii("dns.home.arpa.").unwrap());
+    // `/dns-query` is plain HTTP/1.1 behind a TLS proxy: no `dot`, no `h2`
+    match param(doh, SvcParamKey::Alpn) {
+        Some(SvcParamValue::Alpn(alpn)) => assert_eq!(alpn.0, vec!["http/1.1".to_string()]),
+        other => panic!("unexpected alpn {:?}", other),
+    }
+    assert_eq!(param(doh, SvcParamKey::Port), Some(&SvcParamValue::Port(8443)));
+    match param(doh, SvcParamKey::Ipv4Hint) {
+        Some(SvcParamValue::Ipv4Hint(hint)) => assert_eq!(hint.0[0].0, Ipv4Addr::new(192, 168, 1, 1)),
+        other => panic!("unexpected ipv4hint {:?}", other),
+    }
+    // dohpath (key 7) is unknown to hickory and comes back as raw bytes
+    match param(doh, SvcParamKey::Unknown(7)) {
+        Some(SvcParamValue::Unknown(path)) => assert_eq!(path.0, b"/dns-query{?dns}"),
+        other => panic!("unexpected dohpath {:?}", other),
+    }
+}
+
+#[test]
+fn replacing_the_records_reaches_every_holder() {
+    let config: Config = toml::from_str(CONFIG).unwrap();
+    let ddr = Ddr::new(config.ddr());
+    assert_eq!(ddr.records().len(), 1);
+
+    let moved: Config = toml::from_str(&CONFIG.replace("8443", "9443")).unwrap();
+    ddr.replace(moved.ddr());
+    assert_eq!(param(&decode(ddr.records())[0], SvcParamKey::Port), Some(&SvcParamValue::Port(9443)));
+
+    let disabled: Config = toml::from_str(&CONFIG.replace("enabled = true", "enabled = false")).unwrap();
+    ddr.replace(disabled.ddr());
+    assert!(ddr.records().is_empty());
+}
+
+#[test]
+fn rejects_incomplete_config() {
+    let listen = Some("127.0.0.1:8443");
+    let disabled: DdrConfig = toml::from_str("enabled = false").unwrap();
+    assert!(Ddr::encode(&disabled, None, None).unwrap().is_empty());
+
+    let no_target: DdrConfig = toml::from_str("enabled = true").unwrap();
+    assert!(Ddr::encode(&no_target, listen, None).is_err());
+
+    let enabled: DdrConfig = toml::from_str("enabled = true\ntarget = \"dns.lan\"").unwrap();
+    assert!(Ddr::encode(&enabled, Some("127.0.0.1:0"), None).is_err());
+    assert!(Ddr::encode(&enabled, Some("127.0.0.1"), None).is_err());
+    assert_eq!(Ddr::encode(&enabled, listen, Some(IpAddr::V4(Ipv4Addr::LOCALHOST))).unwrap().len(), 1);
+}
+
+#[test]
+fn enabling_ddr_without_an_http_listener_is_refused() {
+    let dir = std::env::temp_dir().join(format!("localdns-ddr-validation-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("config.toml");
+    std::fs::write(&path, CONFIG.replace("[http]\nlisten = \"127.0.0.1:8443\"\n", "")).unwrap();
+
+    let error = Config::load(&path).unwrap_err();
+    std::fs::remove_dir_all(&dir).unwrap();
+    assert!(matches!(&error, ConfigError::Validation { field: "ddr", message } if message.contains("http.listen")), "{:?}", error);
+}
@@ -0,0 +1,113 @@
+use localdns::diff::RecordDiff;
+use localdns::loader::{parse_key, DnsCache, Entry, Source, SourceKind};
+use std::net::IpAddr;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+/// Every log line of the test binary, `tracing` events included, as
+/// `<target> <message>`. Tests run in parallel, so each looks for lines about
+/// names of its own.
+struct Capture(Mutex<Vec<String>>);
+
+impl log::Log for Capture {
+    fn enabled(&self, _: &log::Metadata) -> bool {
+        true
+    }
+
+    fn log(&self, record: &log::Record) {
+        self.0.lock().unwrap().push(format!("{} {}", record.target(), record.args()));
+    }
+
+    fn flush(&self) {}
+}
+
+static CAPTURE: Capture = Capture(Mutex::new(Vec::new()));
+
+fn capture_logs() {
+    // Only the first call in the binary installs it
+    let _ = log::set_logger(&CAPTURE);
+    log::set_max_level(log::LevelFilter::Trace);
+}
+
+/// The captured lines containing `needle`.
+fn logged(needle: &str) -> Vec<String> {
+    CAPTURE.0.lock().unwrap().iter().filter(|line| line.contains(needle)).cloned().collect()
+}
+
+fn entry(kind: SourceKind, path: &str, location: usize, ip: &str) -> Entry {
+    Entry { ip: ip.parse().unwrap(), source: Source { kind, path: Arc::from(Path::new(path)), location, by_line: kind == SourceKind::Hosts }, ttl: None }
+}
+
+/// Records as if read from `/etc/hosts`, one line per `(name, address)`.
+fn hosts_records(lines: &[(&str, &str)]) -> DnsCache {
+    let mut cache = DnsCache::default();
+    for (index, (name, ip)) in lines.iter().enumerate() {
+        let entry = entry(SourceKind::Hosts, "/etc/hosts", index + 1, ip);
+        cache.exact_matches.entry(parse_key(name).unwrap()).or_default().push(entry);
+    }
+    cache
+}
+
+/// Leases for `(name, address)` pairs, each with the wildcard a lease brings along.
+fn leases(hosts: &[(&str, &str)]) -> DnsCache {
+    let mut cache = DnsCache::default();
+    for (index, (name, ip)) in hosts.iter().enumerate() {
+        let entry = entry(SourceKind::Dhcp, "/var/lib/leases.json", index + 1, ip);
+        cache.exact_matches.entry(parse_key(name).unwrap()).or_default().push(entry.clone());
+        cache.wildcards.push((format!("*.{}", name), entry));
+    }
+    cache
+}
+
+fn ips(list: &[&str]) -> Vec<IpAddr> {
+    list.iter().map(|ip| ip.parse().unwrap()).collect()
+}
+
+#[test]
+fn names_are_sorted_into_added_removed_and_changed() {
+    let old = leases(&[("laptop.lan.", "192.168.1.10"), ("phone.lan.", "192.168.1.11"), ("tv.lan.", "192.168.1.12")]);
+    let mut new = leases(&[("laptop.lan.", "192.168.1.10"), ("phone.lan.", "192.168.1.21"), ("printer.lan.", "192.168.1.13")]);
+    new.serial = 9;
+    let diff = RecordDiff::between(&old, &new);
+
+    assert_eq!(diff.serial, 9);
+    let names = |changes: &[localdns::diff::NameChange]| changes.iter().map(|c| c.name.clone()).collect::<Vec<_>>();
+    // Wildcards count as names of their own
+    assert_eq!(names(&diff.added), ["*.printer.lan.", "printer.lan."]);
+    assert_eq!(names(&diff.removed), ["*.tv.lan.", "tv.lan."]);
+    assert_eq!(names(&diff.changed), ["*.phone.lan.", "phone.lan."]);
+
+    let phone = &diff.changed[1];
+    assert_eq!((phone.before.clone(), phone.after.clone()), (ips(&["192.168.1.11"]), ips(&["192.168.1.21"])));
+    assert_eq!((phone.kind, phone.source.as_str()), ("dhcp", "dhcp /var/lib/leases.json#2"));
+    // A removed name keeps where its old addresses came from
+    let tv = &diff.removed[1];
+    assert_eq!((tv.before.clone(), tv.after.clone()), (ips(&["192.168.1.12"]), Vec::new()));
+    assert_eq!(tv.source, "dhcp /var/lib/leases.json#3");
+    assert!(RecordDiff::between(&new, &new).is_empty());
+}
+
+#[test]
+fn added_addresses_on_a_name_count_as_a_change() {
+    let old = hosts_records(&[("nas.lan.", "192.168.1.5")]);
+    let new = hosts_records(&[("nas.lan.", "192.168.1.5"), ("nas.lan.", "fd00::5")]);
+    let diff = RecordDiff::between(&old, &new);
+
+    assert!(diff.added.is_empty() && diff.removed.is_empty());
+    assert_eq!(diff.changed[0].after, ips(&["192.168.1.5", "fd00::5"]));
+    assert_eq!(diff.changed[0].kind, "hosts");
+}
+
+#[test]
+fn the_log_stops_at_the_limit_and_counts_the_rest() {
+    capture_logs();
+    let hosts: Vec<(String, String)> = (0..5).map(|i| (format!("diff-limit-{}.lan.", i), format!("10.9.0.{}", i + 1))).collect();
+    let hosts: Vec<(&str, &str)> = hosts.iter().map(|(n, ip)| (n.as_str(), ip.as_str())).collect();
+    let diff = RecordDiff::between(&DnsCache::default(), &hosts_records(&hosts));
+    diff.log(3);
+
+    assert!(!logged("Reload diff: 5 added, 0 removed, 0 changed.").is_empty());
+    assert_eq!(logged("+ diff-limit-").len(), 3);
+    assert!(!logged("+ diff-limit-0.lan. 10.9.0.1 (hosts /etc/hosts:1)").is_empty());
+    assert!(!logged("... +2 more").is_empty());
+}
@@ -0,0 +1,132 @@
+use data_encoding::BASE64URL_NOPAD;
+use hickory_proto::op::{Message, Query, ResponseCode};
+use hickory_proto::rr::{Name, RData, RecordType};
+use localdns::config::Config;
+use localdns::doh;
+use localdns::http::{Request, Response};
+use localdns::loader::{load_records, DnsCache};
+use localdns::server::ResolveOptions;
+use std::fs;
+use std::net::Ipv4Addr;
+use tokio::sync::RwLock;
+
+const CONFIG: &str = r#"
+listen_address = "127.0.0.1"
+listen_port = 10053
+dhcp_lease_file = "/nonexistent/leases"
+hosts_file = "hosts"
+domain_suffix = "lan"
+nsid = ""
+negative_ttl = 30
+"#;
+
+fn load(test: &str) -> (RwLock<DnsCache>, ResolveOptions) {
+    let dir = std::env::temp_dir().join(format!("localdns-doh-{}-{}", test, std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("hosts"), "192.168.1.5 nas.lan\n").unwrap();
+    let path = dir.join("config.toml");
+    fs::write(&path, CONFIG).unwrap();
+    let mut config = Config::load(&path).unwrap();
+    config.hosts_file = dir.join("hosts");
+    let records = RwLock::new(load_records(&config.load_options(&path)).unwrap());
+    fs::remove_dir_all(&dir).unwrap();
+    (records, ResolveOptions::from_config(&config))
+}
+
+fn query(name: &str) -> Vec<u8> {
+    let mut request = Message::new();
+    // RFC 8484 asks for ID 0, so answers are cacheable by HTTP caches
+    request.set_id(0);
+    request.set_recursion_desired(true);
+    request.add_query(Query::query(Name::from_ascii(name).unwrap(), RecordType::A));
+    request.to_vec().unwrap()
+}
+
+fn get(dns: &str) -> Request {
+    Request {
+        method: "GET".to_string(),
+        path: "/dns-query".to_string(),
+        query: vec![("dns".to_string(), dns.to_string())],
+        headers: Vec::new(),
+        body: Vec::new(),
+    }
+}
+
+fn post(content_type: &str, body: Vec<u8>) -> Request {
+    Request {
+        method: "POST".to_string(),
+        path: "/dns-query".to_string(),
+        query: Vec::new(),
+        headers: vec![("content-type".to_string(), content_type.to_string())],
+        body,
+    }
+}
+
+fn header<'a>(response: &'a Response, name: &str) -> Option<&'a str> {
+    response.headers.iter().find(|(k, _)| k.eq_ignore_ascii_case(name)).map(|(_, v)| v.as_str())
+}
+
+fn address(response: &Response) -> Option<Ipv4Addr> {
+    let answer = Message::from_vec(&response.body).unwrap();
+    answer.answers().iter().find_map(|record| match record.data() {
+        Some(RData::A(a)) => Some(a.0),
+        _ => None,
+    })
+}
+
+#[tokio::test]
+async fn get_and_post_answer_in_wire_format() {
+    let (records, options) = load("answer");
+
+    let by_get = doh::handle(&get(&BASE64URL_NOPAD.encode(&query("nas.lan."))), &records, &options).await;
+    let by_post = doh::handle(&post("application/dns-message", query("nas.lan.")), &records, &options).await;
+
+    for response in [&by_get, &by_post] {
+        assert_eq!(response.status, 200);
+        assert_eq!(header(response, "Content-Type"), Some(doh::CONTENT_TYPE));
+        assert_eq!(header(response, "Cache-Control"), Some("max-age=60"));
+        assert_eq!(address(response), Some(Ipv4Addr::new(192, 168, 1, 5)));
+    }
+}
+
+#[tokio::test]
+async fn negative_answers_are_cacheable_for_the_negative_ttl() {
+    let (records, options) = load("negative");
+
+    let response = doh::handle(&get(&BASE64URL_NOPAD.encode(&query("missing.lan."))), &records, &options).await;
+
+    assert_eq!(response.status, 200);
+    assert_eq!(Message::from_vec(&response.body).unwrap().response_code(), ResponseCode::NXDomain);
+    assert_eq!(header(&response, "Cache-Control"), Some("max-age=30"));
+}
+
+#[tokio::test]
+async fn answers_follow_the_live_records() {
+    let (records, options) = load("live");
+    // As a reload swapping in records without nas.lan would
+    *records.write().await = DnsCache::default();
+
+    let response = doh::handle(&post("application/dns-message", query("nas.lan.")), &records, &options).await;
+
+    assert_eq!(Message::from_vec(&response.body).unwrap().response_code(), ResponseCode::NXDomain);
+}
+
+#[tokio::test]
+async fn bad_requests_are_rejected() {
+    let (records, options) = load("bad");
+    let status = |request: Request| {
+        let (records, options) = (&records, &options);
+        async move { doh::handle(&request, records, options).await.status }
+    };
+
+    assert_eq!(status(get("not base64!")).await, 400);
+    assert_eq!(status(Request { query: Vec::new(), ..get("") }).await, 400);
+    assert_eq!(status(post("application/dns-message", vec![1, 2, 3])).await, 400);
+    assert_eq!(status(post("application/json", query("nas.lan."))).await, 415);
+    assert_eq!(status(get(&BASE64URL_NOPAD.encode(&vec![0u8; 70_000]))).await, 413);
+    assert_eq!(status(Request { method: "PUT".to_string(), ..get("") }).await, 405);
+    // A response is not a query
+    let mut response = Message::from_vec(&query("nas.lan.")).unwrap();
+    response.set_message_type(hickory_proto::op::MessageType::Response);
+    assert_eq!(status(post("application/dns-message", response.to_vec().unwrap())).await, 400);
+}
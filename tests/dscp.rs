@@ -0,0 +1,53 @@
+use localdns::dscp::Dscp;
+use localdns::{forward, server, tcp};
+use serde::Deserialize;
+use socket2::SockRef;
+
+#[derive(Deserialize)]
+struct Wrapper {
+    dscp: Dscp,
+}
+
+fn dscp(toml_value: &str) -> Result<Dscp, toml::de::Error> {
+    toml::from_str::<Wrapper>(&format!("dscp = {}", toml_value)).map(|w| w.dscp)
+}
+
+#[test]
+fn parses_numbers_and_class_names() {
+    assert_eq!(dscp("46").unwrap().value(), 46);
+    assert_eq!(dscp("\"ef\"").unwrap().value(), 46);
+    assert_eq!(dscp("\"CS6\"").unwrap().value(), 48);
+    assert_eq!(dscp("\"af31\"").unwrap().value(), 26);
+    assert!(dscp("64").is_err());
+    assert!(dscp("\"af44\"").is_err());
+    assert!(dscp("\"cs8\"").is_err());
+}
+
+#[cfg(target_os = "linux")]
+#[tokio::test]
+async fn marks_udp_and_tcp_listeners() {
+    let class = dscp("\"cs6\"").unwrap();
+
+    let udp = server::bind("127.0.0.1:0", Some(class), None).await.unwrap();
+    assert_eq!(SockRef::from(&udp).tos_v4().unwrap(), 48 << 2);
+
+    let listener = tcp::bind("127.0.0.1:0", Some(class), None).await.unwrap();
+    assert_eq!(SockRef::from(&listener).tos_v4().unwrap(), 48 << 2);
+}
+
+#[cfg(target_os = "linux")]
+#[tokio::test]
+async fn marks_upstream_sockets() {
+    let class = dscp("\"af21\"").unwrap();
+    let upstream_udp = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let upstream_tcp = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+
+    let udp = forward::udp_connect(upstream_udp.local_addr().unwrap(), Some(class)).await.unwrap();
+    assert_eq!(SockRef::from(&udp).tos_v4().unwrap(), 18 << 2);
+    let stream = forward::tcp_connect(upstream_tcp.local_addr().unwrap(), Some(class)).await.unwrap();
+    assert_eq!(SockRef::from(&stream).tos_v4().unwrap(), 18 << 2);
+
+    // Unmarked without `upstream_dscp`, whatever the listeners use
+    let udp = forward::udp_connect(upstream_udp.local_addr().unwrap(), None).await.unwrap();
+    assert_eq!(SockRef::from(&udp).tos_v4().unwrap(), 0);
+}
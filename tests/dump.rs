@@ -0,0 +1,119 @@
+use localdns::config::Config;
+use localdns::dump::{self, DumpFormat};
+use localdns::loader::{load_records, DnsCache};
+use std::fs;
+
+const CONFIG: &str = r#"
+listen_address = "127.0.0.1"
+listen_port = 10053
+dhcp_lease_file = "/nonexistent/leases"
+hosts_file = "hosts"
+domain_suffix = "lan"
+nsid = ""
+
+[[records]]
+name = "nas.lan"
+type = "A"
+value = "192.168.1.50"
+ttl = 300
+
+[cnames]
+"files.lan" = "nas.lan"
+"#;
+
+fn load(test: &str, hosts: &str) -> DnsCache {
+    load_with(test, hosts, "")
+}
+
+/// `load` with `settings` appended to `CONFIG`.
+fn load_with(test: &str, hosts: &str, settings: &str) -> DnsCache {
+    let dir = std::env::temp_dir().join(format!("localdns-dump-{}-{}", test, std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("hosts"), hosts).unwrap();
+    let path = dir.join("config.toml");
+    fs::write(&path, format!("{}{}", CONFIG, settings)).unwrap();
+    let mut config = Config::load(&path).unwrap();
+    config.hosts_file = dir.join("hosts");
+    let records = load_records(&config.load_options(&path)).unwrap();
+    fs::remove_dir_all(&dir).unwrap();
+    records
+}
+
+#[test]
+fn text_dumps_are_sorted_lines_with_their_source() {
+    let records = load("text", "192.168.1.6 printer.lan\n192.168.1.5 nas.lan\n10.0.0.1 *.dev.lan\n");
+    let dump = dump::render(&records, DumpFormat::Text);
+    let lines: Vec<&str> = dump.lines().collect();
+
+    assert!(lines[0].starts_with("# serial "), "{}", dump);
+    assert!(lines[0].ends_with("2 exact names, 1 wildcards"), "{}", dump);
+    let records: Vec<&str> = lines.iter().copied().filter(|line| !line.starts_with('#')).collect();
+    assert_eq!(records.len(), 5, "{}", dump);
+    assert!(records[0].starts_with("exact nas.lan. A 192.168.1.5 ttl=- hosts "), "{}", dump);
+    assert!(records[0].ends_with("hosts:2"), "{}", dump);
+    assert!(records[1].starts_with("exact nas.lan. A 192.168.1.50 ttl=300 config "), "{}", dump);
+    assert!(records[2].starts_with("exact printer.lan. A 192.168.1.6 ttl=- hosts "), "{}", dump);
+    assert!(records[3].starts_with("wildcard *.dev.lan. A 10.0.0.1 ttl=- hosts "), "{}", dump);
+    assert!(records[4].starts_with("cname files.lan. CNAME nas.lan. ttl=- config "), "{}", dump);
+}
+
+#[test]
+fn dumps_are_stable_across_loads() {
+    let hosts = "192.168.1.7 c.lan\n192.168.1.6 b.lan\n192.168.1.5 a.lan\nfd00::5 a.lan\n";
+    let (first, second) = (load("stable-a", hosts), load("stable-b", hosts));
+
+    for format in [DumpFormat::Text, DumpFormat::Json] {
+        // The paths differ, so compare without them
+        let strip = |dump: String| dump.replace("stable-a", "stable").replace("stable-b", "stable");
+        assert_eq!(strip(dump::render(&first, format)), strip(dump::render(&second, format)));
+    }
+}
+
+#[test]
+fn json_dumps_key_entries_by_name() {
+    let records = load("json", "192.168.1.5 nas.lan\nfd00::5 nas.lan\n10.0.0.1 *.dev.lan\n");
+    let dump: serde_json::Value = serde_json::from_str(&dump::render(&records, DumpFormat::Json)).unwrap();
+
+    let nas = dump["exact"]["nas.lan."].as_array().unwrap();
+    assert_eq!(nas.len(), 3);
+    assert_eq!((nas[0]["address"].as_str(), nas[0]["type"].as_str(), nas[0]["source"].as_str()), (Some("192.168.1.5"), Some("A"), Some("hosts")));
+    assert!(nas.iter().any(|entry| entry["address"] == "fd00::5" && entry["type"] == "AAAA"), "{:?}", nas);
+    assert_eq!(dump["wildcards"]["*.dev.lan."][0]["address"], "10.0.0.1");
+    assert_eq!(dump["cnames"]["files.lan."]["target"], "nas.lan.");
+    assert_eq!(dump["serial"], records.serial);
+    // The lease file is missing, which a dump shows as well
+    assert!(dump["failures"][0].as_str().unwrap().contains("/nonexistent/leases"), "{}", dump["failures"]);
+}
+
+#[test]
+fn json_dumps_txt_mx_and_srv_records() {
+    let settings = r#"
+[[records]]
+name = "_ldap._tcp.lan"
+type = "srv"
+priority = 10
+weight = 50
+port = 389
+target = "dc1.lan"
+ttl = 120
+"#;
+    let hosts = "txt nas.lan \"owner=ops\" \"rack=2\"\nmx lan 20 relay.lan\nmx lan 10 backup.lan\n";
+    let records = load_with("json-extra", hosts, settings);
+    let dump = dump::json(&records);
+
+    assert_eq!(dump["txts"]["nas.lan."][0]["strings"], serde_json::json!(["owner=ops", "rack=2"]));
+    assert_eq!(dump["txts"]["nas.lan."][0]["source"], "hosts");
+    // In load order, as the text dump's sorted lines are not
+    let mx: Vec<_> = dump["mxs"]["lan."].as_array().unwrap().iter().map(|m| (m["preference"].as_u64(), m["exchange"].as_str())).collect();
+    assert_eq!(mx, [(Some(20), Some("relay.lan.")), (Some(10), Some("backup.lan."))]);
+    let srv = &dump["srvs"]["_ldap._tcp.lan."][0];
+    assert_eq!((srv["priority"].as_u64(), srv["weight"].as_u64(), srv["port"].as_u64()), (Some(10), Some(50), Some(389)));
+    assert_eq!((srv["target"].as_str(), srv["ttl"].as_u64(), srv["source"].as_str()), (Some("dc1.lan."), Some(120), Some("config")));
+}
+
+#[test]
+fn formats_parse() {
+    assert_eq!("text".parse(), Ok(DumpFormat::Text));
+    assert_eq!("json".parse(), Ok(DumpFormat::Json));
+    assert!("yaml".parse::<DumpFormat>().is_err());
+}
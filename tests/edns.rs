@@ -0,0 +1,157 @@
+use hickory_proto::op::{Edns, Message, Query, ResponseCode};
+use hickory_proto::rr::rdata::A;
+use hickory_proto::rr::{Name, RData, Record, RecordType};
+use localdns::config::Config;
+use localdns::loader::{load_records, DnsCache};
+use localdns::server::{encode_udp, handle_query, resolve, response_for, udp_limit, ResolveOptions};
+use std::net::Ipv4Addr;
+use std::sync::Arc;
+use tokio::net::UdpSocket;
+use tokio::sync::RwLock;
+
+const CONFIG: &str = r#"
+listen_address = "127.0.0.1"
+listen_port = 10053
+dhcp_lease_file = "/nonexistent/leases"
+hosts_file = "/nonexistent/hosts"
+domain_suffix = "lan"
+fallback_ip = "10.9.9.9"
+nsid = ""
+edns_udp_payload = 1232
+"#;
+
+fn options(test: &str) -> ResolveOptions {
+    let path = std::env::temp_dir().join(format!("localdns-edns-{}-{}.toml", test, std::process::id()));
+    std::fs::write(&path, CONFIG).unwrap();
+    let config = Config::load(&path).unwrap();
+    let _ = std::fs::remove_file(&path);
+    ResolveOptions::from_config(&config)
+}
+
+/// A query for `name`, with an OPT record of `version` advertising `payload` when given.
+fn query(name: &str, edns: Option<(u8, u16)>) -> Message {
+    let mut message = Message::new();
+    message.set_id(4321);
+    message.add_query(Query::query(Name::from_ascii(name).unwrap(), RecordType::A));
+    if let Some((version, payload)) = edns {
+        let mut opt = Edns::new();
+        opt.set_version(version);
+        opt.set_max_payload(payload);
+        message.set_edns(opt);
+    }
+    // Through the wire format, as a listener would see it
+    Message::from_vec(&message.to_vec().unwrap()).unwrap()
+}
+
+#[tokio::test]
+async fn an_edns_query_gets_our_opt_record_back() {
+    let options = options("with");
+    let records = RwLock::new(DnsCache::default());
+    let request = query("device.lan.", Some((0, 4096)));
+
+    let (response, _) = resolve(&request, None, &records, &options).await.unwrap();
+    let response = Message::from_vec(&response.to_vec().unwrap()).unwrap();
+
+    assert_eq!(response.response_code(), ResponseCode::NoError);
+    assert_eq!(response.answers().len(), 1);
+    let edns = response.extensions().as_ref().expect("OPT record in the response");
+    assert_eq!(edns.max_payload(), 1232);
+    assert_eq!(edns.version(), 0);
+    // The client offered more than we do, so ours is the limit
+    assert_eq!(udp_limit(&request, options.edns_payload), 1232);
+}
+
+#[tokio::test]
+async fn a_query_without_edns_gets_no_opt_record() {
+    let options = options("without");
+    let records = RwLock::new(DnsCache::default());
+    let request = query("device.lan.", None);
+
+    let (response, _) = resolve(&request, None, &records, &options).await.unwrap();
+
+    assert!(response.extensions().is_none());
+    assert_eq!(response.answers().len(), 1);
+    assert_eq!(udp_limit(&request, options.edns_payload), 512);
+}
+
+#[tokio::test]
+async fn unknown_edns_versions_get_badvers() {
+    let options = options("badvers");
+    let records = RwLock::new(DnsCache::default());
+    let request = query("device.lan.", Some((1, 1232)));
+
+    let (response, _) = resolve(&request, None, &records, &options).await.unwrap();
+    let response = Message::from_vec(&response.to_vec().unwrap()).unwrap();
+
+    assert_eq!(response.id(), 4321);
+    // BADVERS shares code 16 with BADSIG, which is what decoding yields
+    assert_eq!(u16::from(response.response_code()), u16::from(ResponseCode::BADVERS));
+    assert!(response.answers().is_empty());
+    assert_eq!(response.extensions().as_ref().map(|edns| edns.version()), Some(0));
+}
+
+#[test]
+fn responses_over_the_limit_are_truncated() {
+    let request = query("many.lan.", Some((0, 600)));
+    let mut response = response_for(&request);
+    response.set_edns(Edns::new());
+    for i in 0..60 {
+        let ip = Ipv4Addr::new(192, 168, 1, i);
+        response.add_answer(Record::from_rdata(Name::from_ascii("many.lan.").unwrap(), 60, RData::A(A(ip))));
+    }
+    let limit = udp_limit(&request, 1232);
+
+    let bytes = encode_udp(&response, limit).unwrap();
+    let sent = Message::from_vec(&bytes).unwrap();
+
+    assert_eq!(limit, 600);
+    assert!(bytes.len() <= limit);
+    assert!(sent.truncated());
+    // As many answers as fit are kept, in order: 16 bytes each, after the header, question and OPT
+    assert_eq!(sent.answers().len(), 35);
+    assert_eq!(sent.answers()[0].data(), response.answers()[0].data());
+    assert_eq!(sent.queries(), request.queries());
+    assert!(sent.extensions().is_some());
+    // A response that fits goes out whole
+    let small = encode_udp(&response, 4096).unwrap();
+    assert_eq!(Message::from_vec(&small).unwrap().answers().len(), 60);
+}
+
+#[tokio::test]
+async fn a_round_robin_name_is_sent_truncated_over_udp() {
+    // A container fleet behind one name: 60 addresses don't fit in 512 bytes
+    let dir = std::env::temp_dir().join(format!("localdns-edns-fleet-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let hosts: String = (1..=60).map(|i| format!("10.0.0.{} fleet.lan\n", i)).collect();
+    std::fs::write(dir.join("hosts"), hosts).unwrap();
+    let path = dir.join("config.toml");
+    std::fs::write(&path, CONFIG).unwrap();
+    let mut config = Config::load(&path).unwrap();
+    config.hosts_file = dir.join("hosts");
+    let records = Arc::new(RwLock::new(load_records(&config.load_options(&path)).unwrap()));
+    std::fs::remove_dir_all(&dir).unwrap();
+    let options = ResolveOptions::from_config(&config);
+
+    let server = Arc::new(UdpSocket::bind("127.0.0.1:0").await.unwrap());
+    let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    for (edns, limit) in [(None, 512), (Some((0, 800)), 800)] {
+        let request = query("fleet.lan.", edns).to_vec().unwrap();
+        handle_query(server.clone(), request, client.local_addr().unwrap(), records.clone(), &options).await.unwrap();
+        let mut buf = [0; 4096];
+        let len = client.recv(&mut buf).await.unwrap();
+        let sent = Message::from_vec(&buf[..len]).unwrap();
+
+        assert!(len <= limit, "{} > {}", len, limit);
+        assert!(sent.truncated());
+        assert!(!sent.answers().is_empty() && sent.answers().len() < 60, "{}", sent.answers().len());
+    }
+
+    // The whole set fits in what the client and we both allow
+    let request = query("fleet.lan.", Some((0, 4096))).to_vec().unwrap();
+    handle_query(server.clone(), request, client.local_addr().unwrap(), records.clone(), &options).await.unwrap();
+    let mut buf = [0; 4096];
+    let len = client.recv(&mut buf).await.unwrap();
+    let sent = Message::from_vec(&buf[..len]).unwrap();
+    assert!(!sent.truncated());
+    assert_eq!(sent.answers().len(), 60);
+}
@@ -0,0 +1,277 @@
+use hickory_proto::op::{Message, MessageType, OpCode, Query, ResponseCode};
+use hickory_proto::rr::{Name, RData, RecordType};
+use localdns::config::Config;
+use localdns::run::{run_server, RunningServer};
+use localdns::server::{bind_workers, REUSE_PORT};
+use std::fs;
+use std::net::{IpAddr, SocketAddr};
+use std::path::PathBuf;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpStream, UdpSocket};
+
+const CONFIG: &str = r#"
+listen_address = "127.0.0.1"
+listen_port = 0
+dhcp_lease_file = "leases"
+hosts_file = "hosts"
+domain_suffix = "lan"
+nsid = ""
+ttl = 60
+watch_interval = 1
+
+[watchdog]
+enabled = false
+"#;
+
+const HOSTS: &str = "\
+192.168.1.5 nas.lan
+fd00::5 nas.lan
+10.0.0.1 *.dev.lan
+";
+
+const LEASES: &str = "4070908800 aa:bb:cc:dd:ee:ff 192.168.1.23 laptop 01:aa:bb:cc:dd:ee:ff\n";
+
+struct Harness {
+    dir: PathBuf,
+    server: RunningServer,
+}
+
+impl Drop for Harness {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
+
+/// The server as the binary runs it, over a temporary config, hosts and lease file.
+async fn start(test: &str) -> Harness {
+    start_with(test, "").await
+}
+
+/// Like `start`, with top-level `settings` added to the config.
+async fn start_with(test: &str, settings: &str) -> Harness {
+    let dir = std::env::temp_dir().join(format!("localdns-e2e-{}-{}", test, std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("hosts"), HOSTS).unwrap();
+    fs::write(dir.join("leases"), LEASES).unwrap();
+    let path = dir.join("config.toml");
+    fs::write(&path, format!("{}{}", settings, CONFIG)).unwrap();
+    let mut config = Config::load(&path).unwrap();
+    config.hosts_file = dir.join("hosts");
+    config.dhcp_lease_file = dir.join("leases");
+    let server = run_server(&path, config).await.unwrap();
+    Harness { dir, server }
+}
+
+/// Sends one query over UDP and decodes the response, checking it answers this query.
+async fn query(server: SocketAddr, id: u16, name: &str, qtype: RecordType) -> Message {
+    let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let mut request = Message::new();
+    request.set_id(id).set_op_code(OpCode::Query).set_recursion_desired(true);
+    request.add_query(Query::query(Name::from_ascii(name).unwrap(), qtype));
+    client.send_to(&request.to_vec().unwrap(), server).await.unwrap();
+
+    let mut buf = [0u8; 4096];
+    let (len, from) = tokio::time::timeout(Duration::from_secs(5), client.recv_from(&mut buf)).await.expect("no response").unwrap();
+    assert_eq!(from, server);
+    let response = Message::from_vec(&buf[..len]).unwrap();
+    assert_eq!((response.id(), response.message_type(), response.op_code()), (id, MessageType::Response, OpCode::Query));
+    assert!(response.recursion_desired(), "RD is copied from the request");
+    assert_eq!(response.queries(), request.queries());
+    response
+}
+
+fn addresses(response: &Message) -> Vec<IpAddr> {
+    response
+        .answers()
+        .iter()
+        .filter_map(|record| match record.data() {
+            Some(RData::A(a)) => Some(IpAddr::V4(a.0)),
+            Some(RData::AAAA(aaaa)) => Some(IpAddr::V6(aaaa.0)),
+            _ => None,
+        })
+        .collect()
+}
+
+#[tokio::test]
+async fn the_bound_port_is_reported() {
+    let harness = start("port").await;
+
+    let addr = harness.server.local_addr;
+    assert_eq!(addr.ip(), IpAddr::from([127, 0, 0, 1]));
+    assert_ne!(addr.port(), 0);
+}
+
+#[tokio::test]
+async fn exact_names_and_leases_are_answered() {
+    let harness = start("exact").await;
+    let server = harness.server.local_addr;
+
+    let response = query(server, 0x1234, "nas.lan.", RecordType::A).await;
+    assert_eq!(response.response_code(), ResponseCode::NoError);
+    assert_eq!(addresses(&response), ["192.168.1.5".parse::<IpAddr>().unwrap()]);
+    assert_eq!(response.answers()[0].ttl(), 60);
+
+    let response = query(server, 0xbeef, "nas.lan.", RecordType::AAAA).await;
+    assert_eq!(addresses(&response), ["fd00::5".parse::<IpAddr>().unwrap()]);
+
+    let response = query(server, 2, "laptop.lan.", RecordType::A).await;
+    assert_eq!(addresses(&response), ["192.168.1.23".parse::<IpAddr>().unwrap()]);
+}
+
+#[tokio::test]
+async fn wildcards_are_answered() {
+    let harness = start("wildcard").await;
+
+    let response = query(harness.server.local_addr, 3, "ci.dev.lan.", RecordType::A).await;
+
+    assert_eq!(response.response_code(), ResponseCode::NoError);
+    assert_eq!(addresses(&response), ["10.0.0.1".parse::<IpAddr>().unwrap()]);
+    assert_eq!(response.answers()[0].name().to_string(), "ci.dev.lan.");
+}
+
+#[tokio::test]
+async fn unknown_names_are_nxdomain() {
+    let harness = start("nxdomain").await;
+
+    let response = query(harness.server.local_addr, 4, "missing.lan.", RecordType::A).await;
+
+    assert_eq!(response.response_code(), ResponseCode::NXDomain);
+    assert!(response.answers().is_empty());
+    assert_eq!(response.name_servers()[0].record_type(), RecordType::SOA);
+}
+
+#[tokio::test]
+async fn a_rewritten_hosts_file_is_served_after_the_reload() {
+    let harness = start("reload").await;
+    let server = harness.server.local_addr;
+    assert_eq!(query(server, 5, "printer.lan.", RecordType::A).await.response_code(), ResponseCode::NXDomain);
+
+    fs::write(harness.dir.join("hosts"), "192.168.1.99 nas.lan\n192.168.1.7 printer.lan\n").unwrap();
+
+    let deadline = tokio::time::Instant::now() + Duration::from_secs(15);
+    loop {
+        let response = query(server, 6, "printer.lan.", RecordType::A).await;
+        if response.response_code() == ResponseCode::NoError {
+            assert_eq!(addresses(&response), ["192.168.1.7".parse::<IpAddr>().unwrap()]);
+            break;
+        }
+        assert!(tokio::time::Instant::now() < deadline, "the rewritten hosts file was never reloaded");
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    let response = query(server, 7, "nas.lan.", RecordType::A).await;
+    assert_eq!(addresses(&response), ["192.168.1.99".parse::<IpAddr>().unwrap()]);
+    // Gone from the file, gone from the answers
+    assert!(addresses(&query(server, 8, "nas.lan.", RecordType::AAAA).await).is_empty());
+}
+
+#[tokio::test]
+async fn workers_share_one_port() {
+    let sockets = bind_workers("127.0.0.1:0", None, None, 4).await.unwrap();
+
+    assert_eq!(sockets.len(), if REUSE_PORT { 4 } else { 1 });
+    let port = sockets[0].local_addr().unwrap().port();
+    assert!(sockets.iter().all(|socket| socket.local_addr().unwrap().port() == port));
+}
+
+#[tokio::test]
+async fn four_workers_answer_and_reload_like_one() {
+    let harness = start_with("workers", "workers = 4\n").await;
+    let server = harness.server.local_addr;
+
+    // Queries from many source ports, which the kernel spreads over the sockets
+    let queries: Vec<_> = (0..64u16)
+        .map(|id| {
+            tokio::spawn(async move {
+                let (name, expected) = if id % 2 == 0 { ("nas.lan.", "192.168.1.5") } else { ("ci.dev.lan.", "10.0.0.1") };
+                assert_eq!(addresses(&query(server, id, name, RecordType::A).await), [expected.parse::<IpAddr>().unwrap()]);
+            })
+        })
+        .collect();
+    for query in queries {
+        query.await.unwrap();
+    }
+    assert_eq!(query(server, 100, "missing.lan.", RecordType::A).await.response_code(), ResponseCode::NXDomain);
+
+    fs::write(harness.dir.join("hosts"), "192.168.1.7 printer.lan\n").unwrap();
+    let deadline = tokio::time::Instant::now() + Duration::from_secs(15);
+    while query(server, 101, "printer.lan.", RecordType::A).await.response_code() != ResponseCode::NoError {
+        assert!(tokio::time::Instant::now() < deadline, "the rewritten hosts file was never reloaded");
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+}
+
+/// Asks for `name` until it answers with `expected`, which a reload should bring.
+async fn wait_for(server: SocketAddr, name: &str, expected: &str) {
+    let deadline = tokio::time::Instant::now() + Duration::from_secs(15);
+    while addresses(&query(server, 9, name, RecordType::A).await) != [expected.parse::<IpAddr>().unwrap()] {
+        assert!(tokio::time::Instant::now() < deadline, "{} never answered {}", name, expected);
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+}
+
+#[tokio::test]
+async fn included_hosts_files_are_watched_once_included() {
+    let harness = start("include").await;
+    let server = harness.server.local_addr;
+    assert_eq!(query(server, 5, "printer.lan.", RecordType::A).await.response_code(), ResponseCode::NXDomain);
+
+    // Included before it exists, so creating it is what has to be noticed
+    fs::write(harness.dir.join("hosts"), format!("{}#include static.hosts\n", HOSTS)).unwrap();
+    tokio::time::sleep(Duration::from_secs(2)).await;
+    fs::write(harness.dir.join("static.hosts"), "192.168.1.7 printer.lan\n").unwrap();
+    wait_for(server, "printer.lan.", "192.168.1.7").await;
+
+    fs::write(harness.dir.join("static.hosts"), "192.168.1.8 printer.lan\n").unwrap();
+    wait_for(server, "printer.lan.", "192.168.1.8").await;
+    assert_eq!(addresses(&query(server, 10, "nas.lan.", RecordType::A).await), ["192.168.1.5".parse::<IpAddr>().unwrap()]);
+}
+
+/// Whether `server` answers a query for `nas.lan.` within half a second.
+async fn answers(server: SocketAddr) -> bool {
+    let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let mut request = Message::new();
+    request.set_id(11).add_query(Query::query(Name::from_ascii("nas.lan.").unwrap(), RecordType::A));
+    client.send_to(&request.to_vec().unwrap(), server).await.unwrap();
+    let mut buf = [0u8; 4096];
+    tokio::time::timeout(Duration::from_millis(500), client.recv(&mut buf)).await.is_ok_and(|received| received.is_ok())
+}
+
+/// `GET /status` from the HTTP listener at `addr`.
+async fn status(addr: &str) -> serde_json::Value {
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    stream.write_all(b"GET /status HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n").await.unwrap();
+    let mut response = Vec::new();
+    tokio::time::timeout(Duration::from_secs(2), stream.read_to_end(&mut response)).await.unwrap().unwrap();
+    let response = String::from_utf8(response).unwrap();
+    serde_json::from_str(response.split_once("\r\n\r\n").unwrap().1).unwrap()
+}
+
+#[tokio::test]
+async fn a_changed_listen_address_moves_the_default_listener() {
+    let dir = std::env::temp_dir().join(format!("localdns-e2e-move-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("hosts"), HOSTS).unwrap();
+    fs::write(dir.join("leases"), LEASES).unwrap();
+    let http = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().to_string();
+    let path = dir.join("config.toml");
+    let config = format!("{}\n[http]\nlisten = {:?}\n", CONFIG, http);
+    fs::write(&path, &config).unwrap();
+    let mut loaded = Config::load(&path).unwrap();
+    loaded.hosts_file = dir.join("hosts");
+    loaded.dhcp_lease_file = dir.join("leases");
+    let harness = Harness { server: run_server(&path, loaded).await.unwrap(), dir };
+    let old = harness.server.local_addr;
+    assert!(answers(old).await);
+
+    let new: SocketAddr = std::net::UdpSocket::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+    fs::write(&path, config.replace("listen_address = \"127.0.0.1\"", &format!("listen_address = \"{}\"", new))).unwrap();
+    let deadline = tokio::time::Instant::now() + Duration::from_secs(15);
+    while !answers(new).await {
+        assert!(tokio::time::Instant::now() < deadline, "never moved to {}", new);
+    }
+
+    assert!(!answers(old).await, "{} was let go of", old);
+    let listeners = status(&http).await["listeners"].clone();
+    assert_eq!((listeners[0]["name"].as_str(), listeners[0]["address"].as_str()), (Some("default"), Some(new.to_string().as_str())));
+}
@@ -0,0 +1,146 @@
+use localdns::config::{Config, ConfigError};
+use localdns::loader::{load_records, LoadError, LoadOptions, SourceKind};
+use localdns::server::{self, ServeError};
+use localdns::tcp;
+use std::fs;
+use std::net::{TcpListener, UdpSocket};
+use std::path::PathBuf;
+
+const CONFIG: &str = r#"
+listen_address = "127.0.0.1"
+listen_port = 10053
+dhcp_lease_file = "leases.json"
+hosts_file = "hosts"
+domain_suffix = "lan"
+nsid = ""
+"#;
+
+/// A directory of its own for each test, removed when dropped.
+struct Scratch(PathBuf);
+
+impl Scratch {
+    fn new(test: &str) -> Self {
+        let dir = std::env::temp_dir().join(format!("localdns-errors-{}-{}", test, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        Self(dir)
+    }
+
+    fn write(&self, name: &str, content: &str) -> PathBuf {
+        let path = self.0.join(name);
+        fs::write(&path, content).unwrap();
+        path
+    }
+}
+
+impl Drop for Scratch {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+#[test]
+fn a_missing_config_file_is_an_io_error() {
+    let dir = Scratch::new("config-missing");
+    let path = dir.0.join("config.toml");
+
+    let error = Config::load(&path).unwrap_err();
+    match &error {
+        ConfigError::Io { path: reported, source } => {
+            assert_eq!(reported, &path);
+            assert_eq!(source.kind(), std::io::ErrorKind::NotFound);
+        }
+        other => panic!("expected Io, got {:?}", other),
+    }
+    assert!(error.to_string().contains("config.toml"), "{}", error);
+}
+
+#[test]
+fn bad_toml_is_a_parse_error_with_its_position() {
+    let dir = Scratch::new("config-parse");
+    let path = dir.write("config.toml", &format!("{}listen_port = \"fifty-three\n", CONFIG));
+
+    match Config::load(&path).unwrap_err() {
+        ConfigError::Parse { path: reported, line, column, .. } => {
+            assert_eq!(reported, path);
+            // CONFIG starts with an empty line, so the bad value is past its last one
+            assert_eq!(line, CONFIG.lines().count() + 1);
+            assert!(column > 1);
+        }
+        other => panic!("expected Parse, got {:?}", other),
+    }
+}
+
+#[test]
+fn a_value_out_of_range_names_its_field() {
+    let dir = Scratch::new("config-validation");
+    let path = dir.write("config.toml", &format!("{}\n[limits]\nmax_alias_chain = 0\n", CONFIG));
+
+    let error = Config::load(&path).unwrap_err();
+    assert!(matches!(error, ConfigError::Validation { field: "limits.max_alias_chain", .. }), "{:?}", error);
+}
+
+#[test]
+fn a_tls_listener_is_refused_rather_than_ignored() {
+    let dir = Scratch::new("config-tls");
+    let path = dir.write("config.toml", &format!("{}\n[tls]\ncert = \"cert.pem\"\nkey = \"key.pem\"\n", CONFIG));
+
+    let error = Config::load(&path).unwrap_err();
+    assert!(matches!(&error, ConfigError::Validation { field: "tls", message } if message.contains("proxy")), "{:?}", error);
+}
+
+#[test]
+fn each_failing_source_is_reported_with_its_path() {
+    let dir = Scratch::new("load");
+    let dhcp_path = dir.write("leases.json", "{\"Leases\": [");
+    // There, but not a file that can be read
+    let hosts_path = dir.0.join("hosts.d");
+    std::fs::create_dir(&hosts_path).unwrap();
+    let options = LoadOptions::new(dhcp_path.clone(), hosts_path.clone(), "lan", dir.0.join("config.toml"));
+    let failures = load_records(&options).unwrap().failures;
+
+    assert_eq!(failures.len(), 2);
+    assert!(failures.iter().any(|e| matches!(e, LoadError::DhcpParse { path, .. } if *path == dhcp_path)), "{:?}", failures);
+    assert!(failures.iter().any(|e| matches!(e, LoadError::Hosts { path, .. } if *path == hosts_path)), "{:?}", failures);
+
+    let missing = dir.0.join("missing.json");
+    let failures = load_records(&LoadOptions { dhcp_path: missing.clone(), ..options }).unwrap().failures;
+    let found = failures.iter().any(|e| matches!(e, LoadError::Missing { kind: SourceKind::Dhcp, path } if *path == missing));
+    assert!(found, "{:?}", failures);
+}
+
+#[tokio::test]
+async fn an_address_in_use_is_a_bind_error() {
+    let udp = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let address = udp.local_addr().unwrap().to_string();
+    match server::bind(&address, None, None).await {
+        Err(ServeError::Bind { addr, source }) => {
+            assert_eq!(addr, address);
+            assert_eq!(source.kind(), std::io::ErrorKind::AddrInUse);
+        }
+        other => panic!("expected Bind, got {:?}", other.map(|_| ())),
+    }
+
+    let tcp = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = tcp.local_addr().unwrap().to_string();
+    let error = tcp::bind(&address, None, None).await.unwrap_err();
+    assert!(matches!(&error, ServeError::Bind { addr, .. } if *addr == address), "{:?}", error);
+}
+
+#[tokio::test]
+async fn the_bind_error_survives_run_server() {
+    let dir = Scratch::new("run-bind");
+    dir.write("hosts", "");
+    dir.write("leases.json", r#"{"Leases":[]}"#);
+    let holder = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let path = dir.write("config.toml", &format!("{}\n[bind_retry]\nattempts = 1\n", CONFIG));
+    let mut config = Config::load(&path).unwrap();
+    config.hosts_file = dir.0.join("hosts");
+    config.dhcp_lease_file = dir.0.join("leases.json");
+    config.listen_port = holder.local_addr().unwrap().port();
+    config.watchdog.enabled = false;
+
+    let error = localdns::run::run_server(&path, config).await.err().expect("the port is taken");
+    let serve = error.chain().find_map(|e| e.downcast_ref::<ServeError>());
+    assert!(matches!(serve, Some(ServeError::Bind { .. })), "{:#}", error);
+}
@@ -0,0 +1,168 @@
+use localdns::config::Config;
+use localdns::diff::RecordDiff;
+use localdns::events::{Event, Events};
+use localdns::http::Request;
+use localdns::json_api::JsonApi;
+use localdns::listeners::{self, Listeners};
+use localdns::loader::{parse_key, DnsCache, Entry, Source, SourceKind};
+use localdns::server::ResolveOptions;
+use localdns::watchdog::Health;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use tokio::sync::{mpsc, RwLock};
+use tokio::time::{timeout, Duration};
+
+const CONFIG: &str = r#"
+listen_address = "127.0.0.1"
+listen_port = 10053
+dhcp_lease_file = "/nonexistent/leases"
+hosts_file = "/nonexistent/hosts"
+domain_suffix = "lan"
+nsid = ""
+"#;
+
+fn api(test: &str, events: Events, records: DnsCache) -> JsonApi {
+    let path = std::env::temp_dir().join(format!("localdns-events-{}-{}.toml", test, std::process::id()));
+    std::fs::write(&path, CONFIG).unwrap();
+    let config = Config::load(&path).unwrap();
+    let _ = std::fs::remove_file(&path);
+    let records = Arc::new(RwLock::new(records));
+    let options = ResolveOptions::from_config(&config);
+    JsonApi {
+        records: records.clone(),
+        options: Arc::new(options.clone()),
+        cors_allow_origin: None,
+        limits: config.limits.clone(),
+        limits_policy: config.limits_policy,
+        health: Arc::new(Health::new(&config.watchdog)),
+        last_reload: Arc::new(Mutex::new(None)),
+        events: Arc::new(events),
+        listeners: Arc::new(Listeners::new(records, options, &config)),
+        overlay: Arc::default(),
+        api_token: None,
+        started_at: listeners::now(),
+    }
+}
+
+fn entry(kind: SourceKind, path: &str, location: usize, ip: &str) -> Entry {
+    Entry { ip: ip.parse().unwrap(), source: Source { kind, path: Arc::from(Path::new(path)), location, by_line: kind == SourceKind::Hosts }, ttl: None }
+}
+
+/// Records as if read from `/etc/hosts`, one line per `(name, address)`.
+fn hosts_records(lines: &[(&str, &str)]) -> DnsCache {
+    let mut cache = DnsCache::default();
+    for (index, (name, ip)) in lines.iter().enumerate() {
+        let entry = entry(SourceKind::Hosts, "/etc/hosts", index + 1, ip);
+        cache.exact_matches.entry(parse_key(name).unwrap()).or_default().push(entry);
+    }
+    cache
+}
+
+async fn subscribe(api: &JsonApi) -> mpsc::Receiver<Vec<u8>> {
+    let request = Request { method: "GET".to_string(), path: "/events".to_string(), query: Vec::new(), headers: Vec::new(), body: Vec::new() };
+    let response = api.handle(request).await;
+    assert_eq!(response.status, 200);
+    response.stream.expect("a streamed body")
+}
+
+/// The next event's name and data, skipping keepalives.
+async fn next(stream: &mut mpsc::Receiver<Vec<u8>>) -> (String, serde_json::Value) {
+    loop {
+        let chunk = timeout(Duration::from_secs(2), stream.recv()).await.unwrap().unwrap();
+        let chunk = String::from_utf8(chunk).unwrap();
+        if chunk.starts_with(':') {
+            continue;
+        }
+        let (event, data) = chunk.trim_end().split_once('\n').unwrap();
+        let data = serde_json::from_str(data.strip_prefix("data: ").unwrap()).unwrap();
+        return (event.strip_prefix("event: ").unwrap().to_string(), data);
+    }
+}
+
+fn lease(cache: &mut DnsCache, name: &str, ip: &str) {
+    let entry = entry(SourceKind::Dhcp, "/var/lib/leases.json", 1, ip);
+    cache.exact_matches.entry(parse_key(name).unwrap()).or_default().push(entry.clone());
+    cache.wildcards.push((format!("*.{}", name), entry));
+}
+
+#[tokio::test]
+async fn subscribers_start_with_a_snapshot() {
+    let mut records = hosts_records(&[("nas.lan.", "192.168.1.5"), ("tv.lan.", "192.168.1.6")]);
+    records.serial = 4;
+    let api = api("snapshot", Events::default(), records);
+    let mut stream = subscribe(&api).await;
+
+    let (event, data) = next(&mut stream).await;
+    assert_eq!(event, "snapshot");
+    assert_eq!(data, serde_json::json!({"type": "snapshot", "serial": 4, "exact": 2, "wildcards": 0, "conflicts": 0, "failures": []}));
+}
+
+#[tokio::test]
+async fn a_reload_is_followed_by_its_dhcp_hosts() {
+    let api = api("reload", Events::default(), DnsCache::default());
+    let mut stream = subscribe(&api).await;
+    next(&mut stream).await;
+
+    let old = hosts_records(&[("nas.lan.", "192.168.1.5")]);
+    let mut new = hosts_records(&[("nas.lan.", "192.168.1.50")]);
+    lease(&mut new, "laptop.lan.", "192.168.1.10");
+    new.serial = 2;
+    api.events.publish_reload(&new, &RecordDiff::between(&old, &new));
+
+    let (event, reload) = next(&mut stream).await;
+    assert_eq!(event, "reload");
+    assert_eq!((reload["serial"].as_u64(), reload["added"].as_u64(), reload["changed"].as_u64()), (Some(2), Some(2), Some(1)));
+    // The hosts file change and the lease's wildcard aren't hosts of their own
+    let (event, host) = next(&mut stream).await;
+    assert_eq!(event, "host");
+    assert_eq!(host["change"], "appeared");
+    assert_eq!(host["name"], "laptop.lan.");
+    assert_eq!(host["after"], serde_json::json!(["192.168.1.10"]));
+    assert_eq!(host["source"], "dhcp /var/lib/leases.json#1");
+
+    api.events.publish(Event::Sources { failures: Vec::new() });
+    assert_eq!(next(&mut stream).await.0, "sources");
+}
+
+#[tokio::test]
+async fn a_subscriber_that_falls_behind_is_told_what_it_lost() {
+    let api = api("lossy", Events::with_buffer(2), DnsCache::default());
+    let mut stream = subscribe(&api).await;
+    next(&mut stream).await;
+
+    // The stream task doesn't get to run in between, so these overrun its buffer
+    for serial in 0..10 {
+        api.events.publish(Event::Reload { serial, exact: 0, wildcards: 0, added: 0, removed: 0, changed: 0 });
+    }
+
+    let (event, lossy) = next(&mut stream).await;
+    assert_eq!(event, "lossy");
+    assert_eq!(lossy["dropped"], 8);
+    // Then it carries on from the oldest event still buffered
+    let (event, reload) = next(&mut stream).await;
+    assert_eq!((event.as_str(), reload["serial"].as_u64()), ("reload", Some(8)));
+}
+
+#[tokio::test]
+async fn runtime_record_changes_are_published() {
+    let mut api = api("record", Events::default(), hosts_records(&[("nas.lan.", "192.168.1.5")]));
+    api.api_token = Some("secret".to_string());
+    let mut stream = subscribe(&api).await;
+    next(&mut stream).await;
+
+    let request = |method: &str, body: &str| Request {
+        method: method.to_string(),
+        path: "/records/vm1.lan".to_string(),
+        query: Vec::new(),
+        headers: vec![("authorization".to_string(), "Bearer secret".to_string())],
+        body: body.as_bytes().to_vec(),
+    };
+    assert_eq!(api.handle(request("PUT", r#"{"addresses": ["10.0.0.9", "fd00::9"]}"#)).await.status, 200);
+    let (event, record) = next(&mut stream).await;
+    assert_eq!(event, "record");
+    assert_eq!(record, serde_json::json!({"type": "record", "name": "vm1.lan.", "change": "replaced", "addresses": ["10.0.0.9", "fd00::9"]}));
+
+    assert_eq!(api.handle(request("DELETE", "")).await.status, 204);
+    let (_, record) = next(&mut stream).await;
+    assert_eq!((record["change"].as_str(), record["addresses"].as_array().map(Vec::len)), (Some("removed"), Some(0)));
+}
@@ -0,0 +1,88 @@
+use hickory_proto::op::{Message, Query, ResponseCode};
+use hickory_proto::rr::{Name, RData, RecordType};
+use localdns::config::Config;
+use localdns::loader::{parse_key, DnsCache, Entry, Source, SourceKind};
+use localdns::server::{resolve, AnswerSource, ResolveOptions};
+use std::net::Ipv4Addr;
+use std::path::Path;
+use std::sync::Arc;
+use tokio::sync::RwLock;
+
+const CONFIG: &str = r#"
+listen_address = "127.0.0.1"
+listen_port = 10053
+dhcp_lease_file = "/nonexistent/leases"
+hosts_file = "/nonexistent/hosts"
+domain_suffix = "lan"
+fallback_ip = "10.9.9.9"
+nsid = ""
+"#;
+
+fn options(config: &str) -> ResolveOptions {
+    let path = std::env::temp_dir().join(format!("localdns-fallback-{}.toml", std::process::id()));
+    std::fs::write(&path, config).unwrap();
+    let config = Config::load(&path).unwrap();
+    let _ = std::fs::remove_file(&path);
+    ResolveOptions::from_config(&config)
+}
+
+fn records() -> RwLock<DnsCache> {
+    let mut cache = DnsCache::default();
+    let path: Arc<Path> = Arc::from(Path::new("/etc/hosts"));
+    let entry = |location, ip: [u8; 4]| Entry {
+        ip: Ipv4Addr::from(ip).into(),
+        source: Source { kind: SourceKind::Hosts, path: path.clone(), location, by_line: true },
+        ttl: None,
+    };
+    cache.exact_matches.insert(parse_key("nas.lan.").unwrap(), vec![entry(1, [192, 168, 1, 5])]);
+    cache.wildcards.push(("*.dev.lan.".to_string(), entry(2, [192, 168, 1, 20])));
+    cache.wildcards.push(("*.example.com.".to_string(), entry(3, [192, 168, 1, 30])));
+    cache.index_wildcards();
+    RwLock::new(cache)
+}
+
+async fn answer(options: &ResolveOptions, name: &str) -> (ResponseCode, AnswerSource, Vec<RData>) {
+    let mut request = Message::new();
+    request.add_query(Query::query(Name::from_ascii(name).unwrap(), RecordType::A));
+    let (response, source) = resolve(&request, None, &records(), options).await.unwrap();
+    let data = response.answers().iter().filter_map(|r| r.data().cloned()).collect();
+    (response.response_code(), source, data)
+}
+
+fn a(ip: [u8; 4]) -> Vec<RData> {
+    vec![RData::A(Ipv4Addr::from(ip).into())]
+}
+
+#[tokio::test]
+async fn unknown_names_in_the_suffix_get_the_fallback() {
+    let options = options(CONFIG);
+
+    assert_eq!(answer(&options, "unknown.lan.").await, (ResponseCode::NoError, AnswerSource::Fallback, a([10, 9, 9, 9])));
+    assert_eq!(answer(&options, "a.b.LAN.").await, (ResponseCode::NoError, AnswerSource::Fallback, a([10, 9, 9, 9])));
+}
+
+#[tokio::test]
+async fn names_outside_the_suffix_are_nxdomain() {
+    let options = options(CONFIG);
+
+    assert_eq!(answer(&options, "example.org.").await, (ResponseCode::NXDomain, AnswerSource::None, Vec::new()));
+    // Only whole labels count, so a name merely ending in the same letters is outside
+    assert_eq!(answer(&options, "wlan.").await, (ResponseCode::NXDomain, AnswerSource::None, Vec::new()));
+}
+
+#[tokio::test]
+async fn real_records_win_over_the_fallback() {
+    let options = options(CONFIG);
+
+    assert_eq!(answer(&options, "nas.lan.").await, (ResponseCode::NoError, AnswerSource::Exact, a([192, 168, 1, 5])));
+    assert_eq!(answer(&options, "api.dev.lan.").await, (ResponseCode::NoError, AnswerSource::Wildcard, a([192, 168, 1, 20])));
+    // Wildcards outside the suffix match as before
+    assert_eq!(answer(&options, "www.example.com.").await, (ResponseCode::NoError, AnswerSource::Wildcard, a([192, 168, 1, 30])));
+}
+
+#[tokio::test]
+async fn an_empty_suffix_makes_every_name_eligible() {
+    let options = options(&CONFIG.replace(r#"domain_suffix = "lan""#, r#"domain_suffix = """#));
+
+    assert_eq!(answer(&options, "example.org.").await, (ResponseCode::NoError, AnswerSource::Fallback, a([10, 9, 9, 9])));
+}
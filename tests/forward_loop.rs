@@ -0,0 +1,255 @@
+use hickory_proto::op::{Edns, Message, Query, ResponseCode};
+use hickory_proto::rr::rdata::opt::{EdnsCode, EdnsOption};
+use hickory_proto::rr::{Name, RData, RecordType};
+use localdns::config::{Config, ConfigError};
+use localdns::forward::{self, LOOP_OPTION};
+use localdns::run::{run_server, RunningServer};
+use std::fs;
+use std::net::{IpAddr, SocketAddr, UdpSocket};
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+const CONFIG: &str = r#"
+listen_address = "127.0.0.1"
+listen_port = 10053
+dhcp_lease_file = "leases.json"
+hosts_file = "hosts"
+domain_suffix = "lan"
+nsid = ""
+"#;
+
+/// A directory of its own for each test, removed when dropped.
+struct Scratch(PathBuf);
+
+impl Scratch {
+    fn new(test: &str) -> Self {
+        let dir = std::env::temp_dir().join(format!("localdns-{}-{}", test, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        Self(dir)
+    }
+
+    fn path(&self, name: &str) -> PathBuf {
+        self.0.join(name)
+    }
+
+    fn write(&self, name: &str, content: &str) -> PathBuf {
+        let path = self.path(name);
+        fs::write(&path, content).unwrap();
+        path
+    }
+}
+
+impl Drop for Scratch {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+/// A query for `name` with ID 7 and RD set.
+fn request(name: &str, qtype: RecordType) -> Message {
+    let mut request = Message::new();
+    request.set_id(7).set_recursion_desired(true);
+    request.add_query(Query::query(Name::from_ascii(name).unwrap(), qtype));
+    request
+}
+
+/// Sends `request` to `server` over UDP; `None` when nothing comes back within two seconds.
+async fn udp_query(server: SocketAddr, request: &Message) -> Option<Message> {
+    let client = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    client.send_to(&request.to_vec().unwrap(), server).await.unwrap();
+    let mut buf = [0u8; 4096];
+    let len = tokio::time::timeout(Duration::from_secs(2), client.recv(&mut buf)).await.ok()?.ok()?;
+    Some(Message::from_vec(&buf[..len]).unwrap())
+}
+
+/// The A and AAAA addresses among the answers.
+fn addresses(response: &Message) -> Vec<IpAddr> {
+    response
+        .answers()
+        .iter()
+        .filter_map(|record| match record.data() {
+            Some(RData::A(a)) => Some(IpAddr::V4(a.0)),
+            Some(RData::AAAA(aaaa)) => Some(IpAddr::V6(aaaa.0)),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Every log line of the test binary, `tracing` events included, as
+/// `<target> <message>`. Tests run in parallel, so each looks for lines about
+/// names of its own.
+struct Capture(Mutex<Vec<String>>);
+
+impl log::Log for Capture {
+    fn enabled(&self, _: &log::Metadata) -> bool {
+        true
+    }
+
+    fn log(&self, record: &log::Record) {
+        self.0.lock().unwrap().push(format!("{} {}", record.target(), record.args()));
+    }
+
+    fn flush(&self) {}
+}
+
+static CAPTURE: Capture = Capture(Mutex::new(Vec::new()));
+
+fn capture_logs() {
+    // Only the first call in the binary installs it
+    let _ = log::set_logger(&CAPTURE);
+    log::set_max_level(log::LevelFilter::Trace);
+}
+
+/// The captured lines containing `needle`.
+fn logged(needle: &str) -> Vec<String> {
+    CAPTURE.0.lock().unwrap().iter().filter(|line| line.contains(needle)).cloned().collect()
+}
+
+
+/// A loopback port nothing is bound to right now.
+fn free_port() -> u16 {
+    UdpSocket::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port()
+}
+
+fn load(dir: &Scratch, settings: &str) -> Result<Config, ConfigError> {
+    Config::load(&dir.write("config.toml", &format!("{}{}", CONFIG, settings)))
+}
+
+#[test]
+fn an_upstream_on_our_own_listen_address_is_rejected() {
+    let dir = Scratch::new("loop-config");
+    // CONFIG listens on 127.0.0.1:10053
+    let error = load(&dir, "upstream = [\"192.0.2.1\", \"127.0.0.1:10053\"]\n").unwrap_err();
+    assert!(matches!(&error, ConfigError::Validation { field: "upstream", message } if message.contains("127.0.0.1:10053")), "{:?}", error);
+
+    let error = load(&dir, "\n[forward_zones]\n\"corp.example\" = [\"tcp://127.0.0.1:10053\"]\n").unwrap_err();
+    assert!(matches!(error, ConfigError::Validation { field: "forward_zones", .. }), "{:?}", error);
+
+    // Another port, or another host, is fine
+    assert!(load(&dir, "upstream = [\"127.0.0.1:10054\", \"192.0.2.1:10053\"]\n").is_ok());
+}
+
+#[test]
+fn a_wildcard_listener_covers_every_local_address() {
+    let ours: [SocketAddr; 1] = ["0.0.0.0:53".parse().unwrap()];
+    let loops = |upstream: &str| forward::loops_back(upstream.parse().unwrap(), &ours);
+
+    assert_eq!(loops("127.0.0.1:53"), Some(ours[0]));
+    assert_eq!(loops("127.0.0.53:53"), Some(ours[0]));
+    assert_eq!(loops("127.0.0.1:5353"), None);
+    // Not an address of this host
+    assert_eq!(loops("192.0.2.1:53"), None);
+    // An IPv4 listener doesn't take IPv6, where a dual-stack `::` one takes both
+    assert_eq!(loops("[::1]:53"), None);
+    let dual: [SocketAddr; 1] = ["[::]:53".parse().unwrap()];
+    assert!(forward::loops_back("127.0.0.1:53".parse().unwrap(), &dual).is_some());
+    assert!(forward::loops_back("[::1]:53".parse().unwrap(), &dual).is_some());
+}
+
+/// An instance on `port` whose `upstream` is `upstream`, on loopback.
+async fn instance(dir: &Scratch, port: u16, upstream: u16) -> RunningServer {
+    dir.write("hosts", "");
+    dir.write("leases.json", r#"{"Leases":[]}"#);
+    let settings = format!("upstream = [\"127.0.0.1:{}\"]\n\n[watchdog]\nenabled = false\n\n[tcp]\nenabled = false\n", upstream);
+    let mut config = Config::load(&dir.write("config.toml", &format!("{}{}", CONFIG, settings))).unwrap();
+    config.hosts_file = dir.path("hosts");
+    config.dhcp_lease_file = dir.path("leases.json");
+    config.listen_port = port;
+    run_server(&dir.path("config.toml"), config).await.unwrap()
+}
+
+/// An instance on `port` with `hosts`, and `nsid` left to default to the hostname.
+async fn default_instance(dir: &Scratch, port: u16, hosts: &str, settings: &str) -> RunningServer {
+    dir.write("hosts", hosts);
+    dir.write("leases.json", r#"{"Leases":[]}"#);
+    let content = format!("{}{}\n[watchdog]\nenabled = false\n\n[tcp]\nenabled = false\n", CONFIG.replace("nsid = \"\"\n", ""), settings);
+    let mut config = Config::load(&dir.write("config.toml", &content)).unwrap();
+    assert!(config.nsid.is_none());
+    config.hosts_file = dir.path("hosts");
+    config.dhcp_lease_file = dir.path("leases.json");
+    config.listen_port = port;
+    run_server(&dir.path("config.toml"), config).await.unwrap()
+}
+
+#[tokio::test]
+async fn instances_sharing_a_hostname_are_not_mistaken_for_a_loop() {
+    let (stub, system) = (Scratch::new("loop-default-stub"), Scratch::new("loop-default-system"));
+    let (stub_port, system_port) = (free_port(), free_port());
+    // A per-user stub in front of the system instance, both on this host, so both NSIDs are its hostname
+    let _system = default_instance(&system, system_port, "192.0.2.9 shared.example\n", "").await;
+    let stub = default_instance(&stub, stub_port, "", &format!("upstream = [\"127.0.0.1:{}\"]\n", system_port)).await;
+
+    let response = udp_query(stub.local_addr, &request("shared.example.", RecordType::A)).await.unwrap();
+    assert_eq!(response.response_code(), ResponseCode::NoError);
+    assert_eq!(addresses(&response), ["192.0.2.9".parse::<IpAddr>().unwrap()]);
+}
+
+#[tokio::test]
+async fn two_instances_forwarding_to_each_other_answer_servfail_at_once() {
+    capture_logs();
+    let (first, second) = (Scratch::new("loop-first"), Scratch::new("loop-second"));
+    let (first_port, second_port) = (free_port(), free_port());
+    let first = instance(&first, first_port, second_port).await;
+    let _second = instance(&second, second_port, first_port).await;
+
+    let started = Instant::now();
+    let response = udp_query(first.local_addr, &request("forward-loop.example.", RecordType::A)).await.unwrap();
+    assert_eq!(response.response_code(), ResponseCode::ServFail);
+    // Well before the 2s upstream timeout, so nobody waited on the loop
+    assert!(started.elapsed() < Duration::from_secs(1), "{:?}", started.elapsed());
+    assert_eq!(logged("Forwarding loop: forward-loop.example. A came back").len(), 1);
+
+    // The loop doesn't flood the log
+    for _ in 0..3 {
+        let response = udp_query(first.local_addr, &request("forward-loop.example.", RecordType::AAAA)).await.unwrap();
+        assert_eq!(response.response_code(), ResponseCode::ServFail);
+    }
+    assert!(logged("Forwarding loop: forward-loop.example. AAAA").is_empty());
+}
+
+#[tokio::test]
+async fn a_query_another_instance_forwarded_is_forwarded_on() {
+    let (dir, upstream) = (Scratch::new("loop-chain"), Scratch::new("loop-chain-upstream"));
+    let (port, upstream_port) = (free_port(), free_port());
+    // The upstream has no upstream of its own, and the name in its hosts file
+    upstream.write("hosts", "192.0.2.7 chain.example\n");
+    upstream.write("leases.json", r#"{"Leases":[]}"#);
+    let settings = "\n[watchdog]\nenabled = false\n\n[tcp]\nenabled = false\n";
+    let mut config = Config::load(&upstream.write("config.toml", &format!("{}{}", CONFIG, settings))).unwrap();
+    config.hosts_file = upstream.path("hosts");
+    config.dhcp_lease_file = upstream.path("leases.json");
+    config.listen_port = upstream_port;
+    let _upstream = run_server(&upstream.path("config.toml"), config).await.unwrap();
+    let server = instance(&dir, port, upstream_port).await;
+
+    // Tagged by some other instance on its way to us
+    let mut request = request("chain.example.", RecordType::A);
+    let mut edns = Edns::new();
+    edns.options_mut().insert(EdnsOption::Unknown(LOOP_OPTION, b"\x05other".to_vec()));
+    request.set_edns(edns);
+    let response = udp_query(server.local_addr, &request).await.unwrap();
+    assert_eq!(addresses(&response), ["192.0.2.7".parse::<IpAddr>().unwrap()]);
+    assert!(response.extensions().as_ref().and_then(|e| e.option(EdnsCode::from(LOOP_OPTION))).is_none());
+}
+
+#[tokio::test]
+async fn forwarded_queries_do_not_carry_the_nsid() {
+    let dir = Scratch::new("loop-no-nsid");
+    let upstream = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let settings = format!("upstream = [\"{}\"]\nupstream_timeout_ms = 200\n", upstream.local_addr().unwrap());
+    let server = default_instance(&dir, free_port(), "", &settings).await;
+    let nsid = Config::load(&dir.path("config.toml")).unwrap().nsid().expect("the hostname");
+
+    let query = request("leak.example.", RecordType::A);
+    let client = tokio::spawn(async move { udp_query(server.local_addr, &query).await });
+    let mut buffer = [0u8; 512];
+    let (len, _) = tokio::time::timeout(Duration::from_secs(2), upstream.recv_from(&mut buffer)).await.unwrap().unwrap();
+    let forwarded = Message::from_vec(&buffer[..len]).unwrap();
+    let option = forwarded.extensions().as_ref().and_then(|e| e.option(EdnsCode::from(LOOP_OPTION)).cloned());
+    let Some(EdnsOption::Unknown(_, path)) = option else { panic!("no loop option in {:?}", forwarded) };
+    assert_eq!(path.len(), 9, "one 8-byte identifier");
+    assert!(!path.windows(nsid.len()).any(|window| window == nsid.as_slice()), "{:?}", path);
+    client.abort();
+}
@@ -0,0 +1,143 @@
+use hickory_proto::op::{Message, MessageType, Query, ResponseCode};
+use hickory_proto::rr::rdata::A;
+use hickory_proto::rr::{Name, RData, Record, RecordType};
+use localdns::config::Config;
+use localdns::loader::{load_records, DnsCache};
+use localdns::server::{resolve, AnswerSource, ReloadedSettings, ResolveOptions};
+use std::fs;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::sync::Arc;
+use tokio::net::UdpSocket;
+use tokio::sync::RwLock;
+
+const CONFIG: &str = r#"
+listen_address = "127.0.0.1"
+listen_port = 10053
+dhcp_lease_file = "/nonexistent/leases"
+hosts_file = "hosts"
+domain_suffix = "lan"
+nsid = ""
+cache_max_entries = 0
+"#;
+
+/// An upstream answering every question with an A record for `ip`, so the
+/// answer tells which upstream was asked.
+async fn fake_upstream(ip: Ipv4Addr) -> SocketAddr {
+    let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let addr = socket.local_addr().unwrap();
+    tokio::spawn(async move {
+        let mut buffer = [0u8; 512];
+        loop {
+            let (len, peer) = socket.recv_from(&mut buffer).await.unwrap();
+            let request = Message::from_vec(&buffer[..len]).unwrap();
+            let query = request.queries()[0].clone();
+            let mut response = Message::new();
+            response.set_id(request.id());
+            response.set_message_type(MessageType::Response);
+            response.add_answer(Record::from_rdata(query.name().clone(), 60, RData::A(A(ip))));
+            response.add_query(query);
+            socket.send_to(&response.to_vec().unwrap(), peer).await.unwrap();
+        }
+    });
+    addr
+}
+
+fn config(test: &str, settings: &str) -> Config {
+    let dir = std::env::temp_dir().join(format!("localdns-forward-zones-{}-{}", test, std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("config.toml");
+    fs::write(&path, format!("{}{}", CONFIG, settings)).unwrap();
+    let mut config = Config::load(&path).unwrap();
+    config.hosts_file = dir.join("hosts");
+    fs::write(&config.hosts_file, "192.168.1.5 nas.lan\n").unwrap();
+    config
+}
+
+fn load(config: &Config) -> RwLock<DnsCache> {
+    let path = config.hosts_file.with_file_name("config.toml");
+    let records = RwLock::new(load_records(&config.load_options(&path)).unwrap());
+    fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    records
+}
+
+/// The response code, source, and the address of the upstream that answered, as its last octet.
+async fn ask(records: &RwLock<DnsCache>, options: &ResolveOptions, name: &str, qtype: RecordType) -> (ResponseCode, AnswerSource, Option<u8>) {
+    let mut request = Message::new();
+    request.add_query(Query::query(Name::from_ascii(name).unwrap(), qtype));
+    let (response, source) = resolve(&request, None, records, options).await.unwrap();
+    let upstream = response.answers().iter().find_map(|record| match record.data() {
+        Some(RData::A(a)) => Some(a.0.octets()[3]),
+        _ => None,
+    });
+    (response.response_code(), source, upstream)
+}
+
+#[tokio::test]
+async fn the_longest_matching_zone_picks_the_upstream() {
+    let (one, two, three) = (
+        fake_upstream(Ipv4Addr::new(10, 0, 0, 1)).await,
+        fake_upstream(Ipv4Addr::new(10, 0, 0, 2)).await,
+        fake_upstream(Ipv4Addr::new(10, 0, 0, 3)).await,
+    );
+    let config = config("longest", &format!(
+        "upstream = [\"{}\"]\n[forward_zones]\n\"Example.com\" = [\"{}\"]\n\"corp.example.com.\" = [\"{}\"]\n",
+        three, one, two
+    ));
+    let records = load(&config);
+    let options = ResolveOptions::from_config(&config);
+
+    let upstream = |name: &'static str| {
+        let (records, options) = (&records, &options);
+        async move { ask(records, options, name, RecordType::A).await.2 }
+    };
+    assert_eq!(upstream("host.corp.example.com.").await, Some(2));
+    // The zone's own name is in it, and suffixes only match whole labels
+    assert_eq!(upstream("CORP.example.com.").await, Some(2));
+    assert_eq!(upstream("notcorp.example.com.").await, Some(1));
+    assert_eq!(upstream("www.example.com.").await, Some(1));
+    assert_eq!(upstream("cdn.example.net.").await, Some(3));
+    // Local records still come first
+    assert_eq!(ask(&records, &options, "nas.lan.", RecordType::A).await.1, AnswerSource::Exact);
+}
+
+#[tokio::test]
+async fn forward_zones_reach_inside_our_zone_and_private_reverse_lookups() {
+    let lab = fake_upstream(Ipv4Addr::new(10, 0, 0, 7)).await;
+    let config = config("local", &format!(
+        "[forward_zones]\n\"lab.lan\" = [\"{}\"]\n\"168.192.in-addr.arpa\" = [\"{}\"]\n",
+        lab, lab
+    ));
+    let records = load(&config);
+    let options = ResolveOptions::from_config(&config);
+
+    assert_eq!(ask(&records, &options, "printer.lab.lan.", RecordType::A).await, (ResponseCode::NoError, AnswerSource::Upstream, Some(7)));
+    assert_eq!(ask(&records, &options, "9.1.168.192.in-addr.arpa.", RecordType::PTR).await.1, AnswerSource::Upstream);
+    // Without a global upstream, nothing else is forwarded
+    assert_eq!(ask(&records, &options, "printer.lan.", RecordType::A).await, (ResponseCode::NXDomain, AnswerSource::None, None));
+    assert_eq!(ask(&records, &options, "cdn.example.net.", RecordType::A).await, (ResponseCode::NXDomain, AnswerSource::None, None));
+}
+
+#[tokio::test]
+async fn reloaded_settings_replace_the_zone_table() {
+    let (old, new) = (fake_upstream(Ipv4Addr::new(10, 0, 0, 1)).await, fake_upstream(Ipv4Addr::new(10, 0, 0, 2)).await);
+    let startup = config("reload", &format!("[forward_zones]\n\"corp.example.com\" = [\"{}\"]\n", old));
+    let records = load(&startup);
+    let options = ResolveOptions::from_config(&startup);
+    assert_eq!(ask(&records, &options, "a.corp.example.com.", RecordType::A).await.2, Some(1));
+
+    let reloaded = config("reload", &format!("[forward_zones]\n\"corp.example.com\" = [\"{}\"]\n", new));
+    fs::remove_dir_all(reloaded.hosts_file.parent().unwrap()).unwrap();
+    records.write().await.settings = Some(Arc::new(ReloadedSettings::from_config(&reloaded)));
+
+    assert_eq!(ask(&records, &options, "a.corp.example.com.", RecordType::A).await.2, Some(2));
+}
+
+#[test]
+fn forward_zones_need_upstreams() {
+    let path = std::env::temp_dir().join(format!("localdns-forward-zones-invalid-{}.toml", std::process::id()));
+    fs::write(&path, format!("{}[forward_zones]\n\"corp.example.com\" = []\n", CONFIG)).unwrap();
+    let error = Config::load(&path).unwrap_err().to_string();
+    let _ = fs::remove_file(&path);
+
+    assert!(error.contains("forward_zones") && error.contains("corp.example.com"), "{}", error);
+}
@@ -0,0 +1,126 @@
+use localdns::config::Config;
+use localdns::loader::{load_records, parse_key, DnsCache};
+use std::fs;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+
+const CONFIG: &str = r#"
+listen_address = "127.0.0.1"
+listen_port = 10053
+dhcp_lease_file = "leases.json"
+hosts_file = "hosts"
+hosts_files = ["project.txt"]
+hosts_dir = "hosts.d"
+domain_suffix = "lan"
+nsid = ""
+"#;
+
+fn scratch(test: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("localdns-hosts-files-{}-{}", test, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(dir.join("hosts.d")).unwrap();
+    fs::write(dir.join("leases.json"), r#"{"Leases":[]}"#).unwrap();
+    fs::write(dir.join("hosts"), "192.168.1.5 nas.lan\n").unwrap();
+    fs::write(dir.join("project.txt"), "192.168.1.6 nas.lan\n192.168.1.40 api.lan\n").unwrap();
+    fs::write(dir.join("hosts.d/20-media.hosts"), "192.168.1.50 tv.lan\n").unwrap();
+    fs::write(dir.join("hosts.d/10-lab.hosts"), "192.168.1.60 lab.lan\n192.168.1.61 tv.lan\n").unwrap();
+    fs::write(dir.join("hosts.d/notes.txt"), "192.168.1.70 ignored.lan\n").unwrap();
+    dir
+}
+
+/// Loads the config above with every path under `dir`.
+fn load(dir: &Path) -> DnsCache {
+    let path = dir.join("config.toml");
+    fs::write(&path, CONFIG).unwrap();
+    let mut config = Config::load(&path).unwrap();
+    config.dhcp_lease_file = dir.join("leases.json");
+    config.hosts_file = dir.join("hosts");
+    config.hosts_files = vec![dir.join("project.txt")];
+    config.hosts_dir = Some(dir.join("hosts.d"));
+    load_records(&config.load_options(&path)).unwrap()
+}
+
+fn addresses(cache: &DnsCache, name: &str) -> Vec<IpAddr> {
+    cache.exact_matches.get(&parse_key(name).unwrap()).map(|entries| entries.iter().map(|e| e.ip).collect()).unwrap_or_default()
+}
+
+fn ip(address: &str) -> IpAddr {
+    address.parse().unwrap()
+}
+
+#[test]
+fn every_file_is_loaded_and_merged() {
+    let dir = scratch("merge");
+    let cache = load(&dir);
+    fs::remove_dir_all(&dir).unwrap();
+
+    // Later files add their addresses to names already listed
+    assert_eq!(addresses(&cache, "nas.lan."), [ip("192.168.1.5"), ip("192.168.1.6")]);
+    assert_eq!(addresses(&cache, "api.lan."), [ip("192.168.1.40")]);
+    assert_eq!(addresses(&cache, "tv.lan."), [ip("192.168.1.50"), ip("192.168.1.61")]);
+    assert!(addresses(&cache, "ignored.lan.").is_empty());
+    assert!(cache.failures.is_empty());
+}
+
+#[test]
+fn directory_files_load_in_name_order() {
+    let dir = scratch("order");
+    let cache = load(&dir);
+    fs::remove_dir_all(&dir).unwrap();
+
+    let sources: Vec<String> = cache.exact_matches[&parse_key("tv.lan.").unwrap()].iter().map(|e| e.source.to_string()).collect();
+    let lab = cache.exact_matches[&parse_key("lab.lan.").unwrap()][0].source.to_string();
+    assert!(lab.ends_with("10-lab.hosts:1"), "{}", lab);
+    // Each address keeps the file that listed it
+    assert!(sources[0].ends_with("20-media.hosts:1") && sources[1].ends_with("10-lab.hosts:2"), "{:?}", sources);
+}
+
+#[test]
+fn a_missing_file_or_directory_fails_only_itself() {
+    let dir = scratch("missing");
+    fs::remove_file(dir.join("project.txt")).unwrap();
+    fs::remove_dir_all(dir.join("hosts.d")).unwrap();
+    let cache = load(&dir);
+    fs::remove_dir_all(&dir).unwrap();
+
+    let failed: Vec<&Path> = cache.failures.iter().map(|f| f.path()).collect();
+    assert_eq!(failed, [dir.join("project.txt"), dir.join("hosts.d")]);
+    assert_eq!(addresses(&cache, "nas.lan."), [ip("192.168.1.5")]);
+}
+
+#[test]
+fn includes_nest_relative_to_the_including_file() {
+    let dir = scratch("include");
+    fs::create_dir_all(dir.join("static")).unwrap();
+    fs::write(dir.join("hosts"), "192.168.1.5 nas.lan\n#include static/main.hosts\n#include missing.hosts\n# include is just a comment\n").unwrap();
+    fs::write(dir.join("static/main.hosts"), "192.168.1.80 printer.lan\n$INCLUDE \"nested.hosts\"\n").unwrap();
+    fs::write(dir.join("static/nested.hosts"), "192.168.1.81 scanner.lan\n").unwrap();
+    let cache = load(&dir);
+    fs::remove_dir_all(&dir).unwrap();
+
+    assert_eq!(addresses(&cache, "printer.lan."), [ip("192.168.1.80")]);
+    assert_eq!(addresses(&cache, "scanner.lan."), [ip("192.168.1.81")]);
+    let scanner = cache.exact_matches[&parse_key("scanner.lan.").unwrap()][0].source.to_string();
+    assert!(scanner.ends_with("nested.hosts:1"), "{}", scanner);
+    // A missing include is a warning, and is still watched for
+    assert!(cache.failures.is_empty(), "{:?}", cache.failures);
+    assert_eq!(cache.skipped.len(), 1);
+    assert!(cache.skipped[0].reason.contains("doesn't exist"), "{}", cache.skipped[0].reason);
+    assert_eq!(cache.included, [dir.join("static/main.hosts"), dir.join("static/nested.hosts"), dir.join("missing.hosts")]);
+}
+
+#[test]
+fn include_cycles_are_skipped_with_a_warning() {
+    let dir = scratch("cycle");
+    fs::write(dir.join("hosts"), "192.168.1.5 nas.lan\n#include loop.hosts\n").unwrap();
+    fs::write(dir.join("loop.hosts"), "192.168.1.90 loop.lan\n#include hosts\n#include loop.hosts\n").unwrap();
+    let cache = load(&dir);
+    fs::remove_dir_all(&dir).unwrap();
+
+    // Each file is read once; project.txt adds its own nas.lan address
+    assert_eq!(addresses(&cache, "nas.lan."), [ip("192.168.1.5"), ip("192.168.1.6")]);
+    assert_eq!(addresses(&cache, "loop.lan."), [ip("192.168.1.90")]);
+    assert_eq!(cache.skipped.len(), 2);
+    assert!(cache.skipped.iter().all(|skipped| skipped.reason.contains("already being read")), "{:?}", cache.skipped);
+    assert!(cache.failures.is_empty());
+}
@@ -0,0 +1,94 @@
+use hickory_proto::op::{Message, Query};
+use hickory_proto::rr::{Name, RecordType};
+use localdns::config::Config;
+use localdns::loader::{parse_key, DnsCache, Entry, Source, SourceKind};
+use localdns::server::{self, ResolveOptions};
+use std::net::{Ipv4Addr, SocketAddr};
+use std::path::Path;
+use std::sync::Arc;
+use tokio::net::UdpSocket;
+use tokio::sync::RwLock;
+use tokio::time::{timeout, Duration};
+
+const CONFIG: &str = r#"
+listen_address = "127.0.0.1"
+listen_port = 10053
+dhcp_lease_file = "/nonexistent/leases"
+hosts_file = "/nonexistent/hosts"
+domain_suffix = "lan"
+nsid = ""
+
+[embedded]
+enabled = true
+max_inflight = 2
+"#;
+
+fn config() -> Config {
+    let path = std::env::temp_dir().join(format!("localdns-inline-{}.toml", std::process::id()));
+    std::fs::write(&path, CONFIG).unwrap();
+    let config = Config::load(&path).unwrap();
+    let _ = std::fs::remove_file(&path);
+    config
+}
+
+fn records() -> Arc<RwLock<DnsCache>> {
+    let mut cache = DnsCache::default();
+    let source = Source { kind: SourceKind::Hosts, path: Arc::from(Path::new("/etc/hosts")), location: 1, by_line: true };
+    cache
+        .exact_matches
+        .insert(parse_key("nas.lan.").unwrap(), vec![Entry { ip: Ipv4Addr::new(192, 168, 1, 5).into(), source, ttl: None }]);
+    cache.build_reverse();
+    Arc::new(RwLock::new(cache))
+}
+
+/// Starts a listener on an ephemeral port, answering inline or with a task per query.
+async fn start(inline: bool) -> SocketAddr {
+    let config = config();
+    let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let address = socket.local_addr().unwrap();
+    let options = ResolveOptions::from_config(&config);
+    let records = records();
+    tokio::spawn(async move {
+        if inline {
+            server::serve_inline(socket, records, options, config.embedded.max_inflight, std::future::pending()).await
+        } else {
+            server::serve(socket, records, options).await
+        }
+    });
+    address
+}
+
+fn query(id: u16, name: &str) -> Vec<u8> {
+    let mut message = Message::new();
+    message.set_id(id).set_recursion_desired(true);
+    message.add_query(Query::query(Name::from_ascii(name).unwrap(), RecordType::A));
+    message.to_vec().unwrap()
+}
+
+/// Sends every query before reading any answer, so more are pending than the budget allows.
+async fn answers(server: SocketAddr, names: &[&str]) -> Vec<Vec<u8>> {
+    let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    for (id, name) in names.iter().enumerate() {
+        client.send_to(&query(id as u16, name), server).await.unwrap();
+    }
+    let mut answers = vec![Vec::new(); names.len()];
+    let mut buf = [0u8; 4096];
+    for _ in names {
+        let len = timeout(Duration::from_secs(2), client.recv(&mut buf)).await.unwrap().unwrap();
+        let id = Message::from_vec(&buf[..len]).unwrap().id() as usize;
+        answers[id] = buf[..len].to_vec();
+    }
+    answers
+}
+
+#[tokio::test(flavor = "current_thread")]
+async fn inline_answers_match_spawned_ones() {
+    let names = ["nas.lan.", "NAS.lan.", "missing.lan.", "nas.lan.", "5.1.168.192.in-addr.arpa.", "nas.lan."];
+    let (inline, spawned) = (start(true).await, start(false).await);
+
+    let inline = answers(inline, &names).await;
+    let spawned = answers(spawned, &names).await;
+
+    assert!(inline.iter().all(|answer| !answer.is_empty()));
+    assert_eq!(inline, spawned);
+}
@@ -0,0 +1,68 @@
+use localdns::config::Config;
+use localdns::{server, tcp};
+use std::fs;
+
+const CONFIG: &str = r#"
+listen_address = "127.0.0.1"
+dhcp_lease_file = "/nonexistent/leases"
+hosts_file = "/nonexistent/hosts"
+domain_suffix = "lan"
+"#;
+
+#[cfg(target_os = "linux")]
+#[tokio::test]
+async fn listeners_are_bound_to_the_interface() {
+    use socket2::SockRef;
+
+    let udp = server::bind("127.0.0.1:0", None, Some("lo")).await.unwrap();
+    assert_eq!(SockRef::from(&udp).device().unwrap().as_deref(), Some(&b"lo"[..]));
+    // Still answers what arrives through it
+    let client = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    client.send_to(b"ping", udp.local_addr().unwrap()).await.unwrap();
+    let mut buffer = [0u8; 4];
+    udp.recv_from(&mut buffer).await.unwrap();
+
+    let listener = tcp::bind("127.0.0.1:0", None, Some("lo")).await.unwrap();
+    assert_eq!(SockRef::from(&listener).device().unwrap().as_deref(), Some(&b"lo"[..]));
+}
+
+#[tokio::test]
+async fn without_an_interface_nothing_changes() {
+    let udp = server::bind("127.0.0.1:0", None, None).await.unwrap();
+    #[cfg(target_os = "linux")]
+    assert_eq!(socket2::SockRef::from(&udp).device().unwrap(), None);
+    // Host names still resolve, as they always have
+    assert!(server::bind("localhost:0", None, None).await.is_ok());
+    drop(udp);
+}
+
+#[tokio::test]
+async fn a_missing_interface_fails_the_bind_naming_it() {
+    let error = server::bind("127.0.0.1:0", None, Some("nosuchif0")).await.unwrap_err().to_string();
+    assert!(error.contains("127.0.0.1:0") && error.contains("nosuchif0"), "{}", error);
+
+    let error = tcp::bind("127.0.0.1:0", None, Some("nosuchif0")).await.unwrap_err().to_string();
+    assert!(error.contains("nosuchif0"), "{}", error);
+}
+
+#[test]
+fn interface_names_are_validated() {
+    let path = std::env::temp_dir().join(format!("localdns-interface-{}.toml", std::process::id()));
+    let load = |interface: &str| {
+        fs::write(&path, format!("{}listen_interface = {:?}\n", CONFIG, interface)).unwrap();
+        Config::load(&path).map(|config| config.listen_interface).map_err(|e| e.to_string())
+    };
+
+    let too_long = load("an-interface-name-too-long");
+    let empty = load("");
+    let fine = load("br-lan");
+    let _ = fs::remove_file(&path);
+
+    assert!(too_long.unwrap_err().contains("listen_interface"));
+    assert!(empty.is_err());
+    if cfg!(target_os = "linux") {
+        assert_eq!(fine.unwrap().as_deref(), Some("br-lan"));
+    } else {
+        assert!(fine.unwrap_err().contains("Linux"));
+    }
+}
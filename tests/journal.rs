@@ -0,0 +1,147 @@
+use localdns::config::{Config, ConfigError};
+use localdns::dump::{self, DumpFormat};
+use localdns::loader::{load_records, LoadOptions};
+use localdns::overlay::{Overlay, RuntimeRecord};
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const CONFIG: &str = r#"
+listen_address = "127.0.0.1"
+listen_port = 10053
+dhcp_lease_file = "leases.json"
+hosts_file = "hosts"
+domain_suffix = "lan"
+nsid = ""
+"#;
+
+/// A directory of its own for each test, removed when dropped.
+struct Scratch(PathBuf);
+
+impl Scratch {
+    fn new(test: &str) -> Self {
+        let dir = std::env::temp_dir().join(format!("localdns-{}-{}", test, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        Self(dir)
+    }
+
+    fn path(&self, name: &str) -> PathBuf {
+        self.0.join(name)
+    }
+
+    fn write(&self, name: &str, content: &str) -> PathBuf {
+        let path = self.path(name);
+        fs::write(&path, content).unwrap();
+        path
+    }
+}
+
+impl Drop for Scratch {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+fn record(name: &str, ip: &str) -> RuntimeRecord {
+    RuntimeRecord { name: name.to_string(), ip: ip.parse().unwrap(), ttl: None }
+}
+
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs()
+}
+
+#[test]
+fn changes_are_replayed_after_a_restart() {
+    let dir = Scratch::new("journal-replay");
+    let path = dir.path("runtime.jsonl");
+    {
+        let overlay = Overlay::open(&path, 0);
+        assert!(overlay.add(record("vm1.lan.", "10.0.0.9")));
+        overlay.add(RuntimeRecord { ttl: Some(30), ..record("*.vms.lan.", "fd00::9") });
+        overlay.remove("printer.lan.");
+        overlay.replace("nas.lan.", vec![record("nas.lan.", "10.0.0.5")]);
+    }
+
+    let overlay = Overlay::open(&path, 0);
+    assert_eq!(
+        overlay.records(),
+        [record("vm1.lan.", "10.0.0.9"), RuntimeRecord { ttl: Some(30), ..record("*.vms.lan.", "fd00::9") }, record("nas.lan.", "10.0.0.5")]
+    );
+    assert_eq!(overlay.removed(), ["nas.lan.".to_string(), "printer.lan.".to_string()].into());
+    // One JSON object per line, readable without the server
+    for line in std::fs::read_to_string(&path).unwrap().lines() {
+        serde_json::from_str::<serde_json::Value>(line).unwrap();
+    }
+}
+
+#[test]
+fn corrupt_lines_and_expired_records_are_left_out() {
+    let dir = Scratch::new("journal-corrupt");
+    let stale = now() - 7200;
+    let journal = format!(
+        "{}\n{{\"op\":\"add\",\"name\":\"half\n{}\n{}\n",
+        r#"{"op":"add","name":"fresh.lan.","address":"10.0.0.1","ttl":null,"at":NOW}"#.replace("NOW", &now().to_string()),
+        r#"{"op":"add","name":"stale.lan.","address":"10.0.0.2","ttl":null,"at":STALE}"#.replace("STALE", &stale.to_string()),
+        r#"{"op":"remove","name":"gone.lan."}"#,
+    );
+    let path = dir.write("runtime.jsonl", &journal);
+
+    let overlay = Overlay::open(&path, 3600);
+    assert_eq!(overlay.records(), [record("fresh.lan.", "10.0.0.1")]);
+    assert!(overlay.removed().contains("gone.lan."));
+    // Rewritten with only what was kept
+    let content = std::fs::read_to_string(&path).unwrap();
+    assert_eq!(content.lines().count(), 2, "{}", content);
+    assert!(!content.contains("stale.lan.") && !content.contains("half"));
+}
+
+#[test]
+fn records_expire_a_lifetime_after_they_were_last_added() {
+    let dir = Scratch::new("journal-expire");
+    let path = dir.write("runtime.jsonl", &format!("{}\n", r#"{"op":"add","name":"old.lan.","address":"10.0.0.3","ttl":null,"at":AT}"#.replace("AT", &(now() - 50).to_string())));
+
+    let overlay = Overlay::open(&path, 60);
+    assert_eq!(overlay.lifetime(), Some(60));
+    assert!(overlay.expire().is_empty());
+    // Adding it again refreshes it
+    assert!(!overlay.add(record("old.lan.", "10.0.0.3")));
+    drop(overlay);
+    let overlay = Overlay::open(&path, 45);
+    assert_eq!(overlay.records(), [record("old.lan.", "10.0.0.3")]);
+
+    // Without a journal nothing expires
+    assert_eq!(Overlay::default().lifetime(), None);
+}
+
+#[test]
+fn dumps_mark_runtime_records_as_dynamic() {
+    let dir = Scratch::new("journal-dump");
+    let hosts_path = dir.write("hosts", "192.168.1.5 nas.lan\n");
+    let overlay = Arc::new(Overlay::open(&dir.path("runtime.jsonl"), 0));
+    overlay.add(record("vm1.lan.", "10.0.0.9"));
+    let options = LoadOptions { overlay, ..LoadOptions::new(dir.path("leases.json"), hosts_path, "lan", dir.path("config.toml")) };
+    let records = load_records(&options).unwrap();
+
+    let text = dump::render(&records, DumpFormat::Text);
+    let vm1 = text.lines().find(|line| line.starts_with("exact vm1.lan. ")).unwrap();
+    assert!(vm1.ends_with(" dynamic"), "{}", vm1);
+    let nas = text.lines().find(|line| line.starts_with("exact nas.lan. ")).unwrap();
+    assert!(!nas.ends_with(" dynamic"), "{}", nas);
+
+    let json = dump::json(&records);
+    assert_eq!(json["exact"]["vm1.lan."][0]["dynamic"], true);
+    assert_eq!(json["exact"]["nas.lan."][0]["dynamic"], false);
+}
+
+#[test]
+fn a_lifetime_needs_a_journal() {
+    let dir = Scratch::new("journal-config");
+    let path = dir.write("config.toml", &format!("{}runtime_lifetime = 3600\n", CONFIG));
+    let error = Config::load(&path).unwrap_err();
+    assert!(matches!(error, ConfigError::Validation { field: "runtime_lifetime", .. }), "{:?}", error);
+
+    let path = dir.write("config.toml", &format!("{}runtime_journal = \"runtime.jsonl\"\nruntime_lifetime = 3600\n", CONFIG));
+    assert!(Config::load(&path).is_ok());
+}
@@ -0,0 +1,141 @@
+use localdns::config::Config;
+use localdns::events::Events;
+use localdns::http::{self, Request, Response};
+use localdns::json_api::JsonApi;
+use localdns::listeners::{self, Listeners};
+use localdns::loader::{parse_key, DnsCache, Entry, Source, SourceKind};
+use localdns::server::ResolveOptions;
+use localdns::watchdog::Health;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::sync::RwLock;
+
+const CONFIG: &str = r#"
+listen_address = "127.0.0.1"
+listen_port = 10053
+dhcp_lease_file = "/nonexistent/leases"
+hosts_file = "/nonexistent/hosts"
+domain_suffix = "lan"
+nsid = ""
+"#;
+
+/// The API over `nas.lan` at 192.168.1.5 and fd00::5, with `extra` added to `CONFIG`.
+fn api(test: &str, extra: &str) -> JsonApi {
+    let path = std::env::temp_dir().join(format!("localdns-json-api-{}-{}.toml", test, std::process::id()));
+    std::fs::write(&path, format!("{}{}", CONFIG, extra)).unwrap();
+    let config = Config::load(&path).unwrap();
+    let _ = std::fs::remove_file(&path);
+
+    let mut cache = DnsCache::default();
+    for (line, ip) in [(1, "192.168.1.5"), (2, "fd00::5")] {
+        let source = Source { kind: SourceKind::Hosts, path: Arc::from(Path::new("/etc/hosts")), location: line, by_line: true };
+        cache.exact_matches.entry(parse_key("nas.lan.").unwrap()).or_default().push(Entry { ip: ip.parse().unwrap(), source, ttl: None });
+    }
+    let records = Arc::new(RwLock::new(cache));
+    let options = ResolveOptions::from_config(&config);
+    JsonApi {
+        records: records.clone(),
+        options: Arc::new(options.clone()),
+        cors_allow_origin: config.http.cors_allow_origin.clone(),
+        limits: config.limits.clone(),
+        limits_policy: config.limits_policy,
+        health: Arc::new(Health::new(&config.watchdog)),
+        last_reload: Arc::new(Mutex::new(None)),
+        events: Arc::new(Events::default()),
+        listeners: Arc::new(Listeners::new(records, options, &config)),
+        overlay: Arc::default(),
+        api_token: None,
+        started_at: listeners::now(),
+    }
+}
+
+fn request(method: &str, path: &str, query: &[(&str, &str)]) -> Request {
+    Request {
+        method: method.to_string(),
+        path: path.to_string(),
+        query: query.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect(),
+        headers: Vec::new(),
+        body: Vec::new(),
+    }
+}
+
+/// The status and JSON body `api` answers `request` with; `Null` for a body that isn't JSON.
+async fn call(api: &JsonApi, request: Request) -> (u16, serde_json::Value) {
+    let Response { status, body, .. } = api.handle(request).await;
+    (status, serde_json::from_slice(&body).unwrap_or(serde_json::Value::Null))
+}
+
+#[tokio::test]
+async fn resolve_answers_in_the_dns_json_shape() {
+    let api = api("shape", "");
+    let (status, body) = call(&api, request("GET", "/resolve", &[("name", "nas.lan"), ("type", "AAAA")])).await;
+
+    assert_eq!(status, 200);
+    assert_eq!(body["Status"], 0);
+    assert_eq!((body["TC"].as_bool(), body["RD"].as_bool()), (Some(false), Some(true)));
+    assert_eq!(body["Question"][0]["name"], "nas.lan.");
+    assert_eq!(body["Question"][0]["type"], 28);
+    let answer = &body["Answer"][0];
+    assert_eq!((answer["name"].as_str(), answer["type"].as_u64(), answer["data"].as_str()), (Some("nas.lan."), Some(28), Some("fd00::5")));
+    assert!(answer["TTL"].is_u64(), "{}", body);
+}
+
+#[tokio::test]
+async fn resolve_defaults_to_a_and_reports_nxdomain_with_the_soa() {
+    let api = api("nxdomain", "");
+
+    let (_, body) = call(&api, request("GET", "/resolve", &[("name", "nas.lan")])).await;
+    assert_eq!(body["Answer"][0]["data"], "192.168.1.5");
+
+    let (status, body) = call(&api, request("GET", "/resolve", &[("name", "missing.lan"), ("type", "1"), ("rd", "0")])).await;
+    assert_eq!(status, 200);
+    assert_eq!(body["Status"], 3);
+    assert_eq!(body["RD"], false);
+    assert!(body.get("Answer").is_none());
+    assert_eq!(body["Authority"][0]["type"], 6);
+}
+
+#[tokio::test]
+async fn bad_parameters_are_json_errors_with_400() {
+    let api = api("errors", "");
+
+    for query in [&[][..], &[("name", "nas.lan"), ("type", "NOPE")][..]] {
+        let (status, body) = call(&api, request("GET", "/resolve", query)).await;
+        assert_eq!(status, 400);
+        assert!(body["error"].is_string(), "{}", body);
+    }
+    assert_eq!(call(&api, request("POST", "/resolve", &[("name", "nas.lan")])).await.0, 405);
+}
+
+#[tokio::test]
+async fn cors_headers_are_sent_when_configured() {
+    let origin = |response: &Response| {
+        response.headers.iter().find(|(name, _)| name == "Access-Control-Allow-Origin").map(|(_, value)| value.clone())
+    };
+
+    let closed = api("no-cors", "").handle(request("GET", "/resolve", &[("name", "nas.lan")])).await;
+    assert_eq!(origin(&closed), None);
+
+    let api = api("cors", "[http]\ncors_allow_origin = \"https://dash.lan\"\n");
+    let preflight = api.handle(request("OPTIONS", "/resolve", &[])).await;
+    assert_eq!(preflight.status, 204);
+    assert_eq!(origin(&preflight).as_deref(), Some("https://dash.lan"));
+    assert!(preflight.headers.iter().any(|(name, value)| name == "Access-Control-Allow-Methods" && value.contains("GET")));
+}
+
+#[tokio::test]
+async fn chunked_bodies_are_refused_rather_than_misread() {
+    let listener = http::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(http::serve(listener, |_: Request| async { Response::text(200, "ok") }));
+
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    let request = "PUT /records/nas.lan HTTP/1.1\r\nHost: localhost\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nhello\r\n0\r\n\r\n";
+    stream.write_all(request.as_bytes()).await.unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+
+    assert!(response.starts_with("HTTP/1.1 501 Not Implemented\r\n"), "{}", response);
+}
@@ -0,0 +1,303 @@
+use localdns::config::Config;
+use localdns::leases::{parse, Lease, LeaseFormat};
+use localdns::loader::{load_records, parse_key, DnsCache};
+use std::fs;
+use std::net::IpAddr;
+
+const CONFIG: &str = r#"
+listen_address = "127.0.0.1"
+listen_port = 10053
+dhcp_lease_file = "leases"
+hosts_file = "hosts"
+domain_suffix = "lan"
+nsid = ""
+"#;
+
+const JSON: &str = r#"{"Leases":[
+    {"Address":[192,168,1,23],"Hostname":"laptop","HardwareAddress":[170,187,204,221,238,255]},
+    {"Address":[192,168,1,24],"Hostname":""}
+]}"#;
+
+const DNSMASQ: &str = "\
+4070908800 aa:bb:cc:dd:ee:ff 192.168.1.23 laptop 01:aa:bb:cc:dd:ee:ff
+0 11:22:33:44:55:66 192.168.1.24 * *
+duid 00:01:00:01:2c:5f:4e:1a:aa:bb:cc:dd:ee:ff
+4070909300 1234 fd00::23 laptop 00:01:00:01:2c:5f:4e:1a:aa:bb:cc:dd:ee:ff
+not a lease
+";
+
+const ISC: &str = r#"# The format of this file is documented in the dhcpd.leases(5) manual page.
+# This lease file was written by isc-dhcp-4.4.3
+
+authoring-byte-order little-endian;
+
+server-duid "\000\001\000\001,_N\032";
+
+lease 192.168.1.50 {
+  starts 3 2024/01/10 10:00:00;
+  ends 3 2024/01/10 12:00:00;
+  binding state active;
+  client-hostname "old-phone";
+}
+lease 192.168.1.60 {
+  starts 4 2099/06/15 10:30:05;
+  ends 4 2099/06/15 12:30:05;
+  binding state active;
+  next binding state free;
+  hardware ethernet aa:bb:cc:dd:ee:ff;
+  uid "\001\252\273\314\335\356\377";
+  client-hostname "laptop";
+}
+lease 192.168.1.50 {
+  starts 1 2099/01/01 00:00:00;
+  ends never;
+  binding state active;
+  client-hostname "phone";
+}
+lease 192.168.1.70 {
+  ends epoch 4070908800; # Thu Jan 01 00:00:00 2099
+  binding state free;
+  client-hostname "gone";
+}
+lease 192.168.1.80 {
+  ends 4 2099/01/01 00:00:00;
+  abandoned;
+  client-hostname
+    "abandoned";
+}
+lease 192.168.1.90 {
+  ends 3 2024/01/10 12:00:00;
+  client-hostname "expired";
+}
+failover peer "dhcp-failover" state {
+  my state normal at 1 2024/01/08 09:00:00;
+}
+"#;
+
+const KEA: &str = "\
+address,hwaddr,client_id,valid_lifetime,expire,subnet_id,fqdn_fwd,fqdn_rev,hostname,state,user_context,pool_id
+192.168.1.23,aa:bb:cc:dd:ee:ff,01:aa:bb:cc:dd:ee:ff,3600,4070908800,1,1,1,laptop.lan.,0,,0
+192.168.1.24,11:22:33:44:55:66,,3600,4070908800,1,0,0,tablet,0,,0
+192.168.1.25,11:22:33:44:55:77,,3600,4070908800,1,0,0,printer,0,,0
+192.168.1.24,11:22:33:44:55:66,,0,4070908800,1,0,0,tablet,0,,0
+192.168.1.26,11:22:33:44:55:88,,3600,1704888000,1,0,0,expired,0,,0
+192.168.1.27,11:22:33:44:55:99,,3600,4070908800,1,0,0,declined,1,,0
+\"192.168.1.28\",11:22:33:44:55:aa,,4294967295,0,1,0,0,\"tv&#x2cden\",0,,0
+";
+
+/// Loads `leases` through the config, with `settings` appended to it.
+fn load(test: &str, leases: &str, settings: &str) -> DnsCache {
+    let dir = std::env::temp_dir().join(format!("localdns-leases-{}-{}", test, std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("leases"), leases).unwrap();
+    fs::write(dir.join("hosts"), "").unwrap();
+    let path = dir.join("config.toml");
+    fs::write(&path, format!("{}{}", CONFIG, settings)).unwrap();
+    let mut config = Config::load(&path).unwrap();
+    config.dhcp_lease_file = dir.join("leases");
+    config.hosts_file = dir.join("hosts");
+    let cache = load_records(&config.load_options(&path)).unwrap();
+    fs::remove_dir_all(&dir).unwrap();
+    cache
+}
+
+fn addresses(cache: &DnsCache, name: &str) -> Vec<IpAddr> {
+    cache.exact_matches.get(&parse_key(name).unwrap()).map(|entries| entries.iter().map(|e| e.ip).collect()).unwrap_or_default()
+}
+
+#[test]
+fn dnsmasq_lines_parse_with_expiry_and_mac() {
+    let leases = parse(DNSMASQ, LeaseFormat::Dnsmasq, "dhcp test").unwrap();
+
+    assert_eq!(
+        leases,
+        [
+            Lease {
+                location: 1,
+                ip: "192.168.1.23".parse().unwrap(),
+                hostname: "laptop".to_string(),
+                mac: Some([0xaa, 0xbb, 0xcc, 0xdd, 0xee, 0xff]),
+                expires: Some(4070908800),
+            },
+            Lease {
+                location: 2,
+                ip: "192.168.1.24".parse().unwrap(),
+                hostname: String::new(),
+                mac: Some([0x11, 0x22, 0x33, 0x44, 0x55, 0x66]),
+                expires: None,
+            },
+            Lease {
+                location: 4,
+                ip: "fd00::23".parse().unwrap(),
+                hostname: "laptop".to_string(),
+                mac: None,
+                expires: Some(4070909300),
+            },
+        ]
+    );
+}
+
+#[test]
+fn both_formats_build_the_same_records() {
+    for (format, content) in [("json", JSON), ("dnsmasq", DNSMASQ)] {
+        for setting in ["auto", format] {
+            let test = format!("{}-{}", format, setting);
+            let cache = load(&test, content, &format!("dhcp_lease_format = {:?}\n", setting));
+
+            assert_eq!(addresses(&cache, "laptop.lan.")[0], "192.168.1.23".parse::<IpAddr>().unwrap(), "{}", test);
+            assert!(cache.wildcards.iter().any(|(pattern, _)| pattern == "*.laptop.lan."), "{}", test);
+            // JSON leases are counted by entry, dnsmasq ones by line
+            let source = cache.exact_matches[&parse_key("laptop.lan.").unwrap()][0].source.to_string();
+            assert!(source.contains(if format == "json" { "/leases#" } else { "/leases:" }), "{}: {}", test, source);
+            // The unnamed lease gets no name of its own
+            assert_eq!(cache.exact_matches.len(), 1, "{}", test);
+        }
+    }
+}
+
+#[test]
+fn dnsmasq_ipv6_leases_answer_aaaa() {
+    let cache = load("ipv6", DNSMASQ, "");
+
+    assert_eq!(addresses(&cache, "laptop.lan."), ["192.168.1.23".parse::<IpAddr>().unwrap(), "fd00::23".parse().unwrap()]);
+    assert_eq!(cache.exact_matches[&parse_key("laptop.lan.").unwrap()][1].source.to_string().rsplit(':').next(), Some("4"));
+}
+
+#[test]
+fn unnamed_dnsmasq_leases_can_be_synthesized() {
+    let cache = load("unnamed", DNSMASQ, "synthesize_unnamed = \"mac\"\n");
+
+    assert_eq!(addresses(&cache, "mac-112233445566.lan."), ["192.168.1.24".parse::<IpAddr>().unwrap()]);
+}
+
+#[test]
+fn a_forced_format_does_not_sniff() {
+    let cache = load("forced", DNSMASQ, "dhcp_lease_format = \"json\"\n");
+
+    assert!(cache.failures[0].to_string().contains("Failed to parse DHCP JSON"), "{}", cache.failures[0]);
+}
+
+#[test]
+fn isc_stanzas_keep_the_latest_active_lease_per_address() {
+    let leases = parse(ISC, LeaseFormat::Isc, "dhcp test").unwrap();
+
+    assert_eq!(
+        leases,
+        [
+            Lease {
+                location: 14,
+                ip: "192.168.1.60".parse().unwrap(),
+                hostname: "laptop".to_string(),
+                mac: Some([0xaa, 0xbb, 0xcc, 0xdd, 0xee, 0xff]),
+                expires: Some(4085209805),
+            },
+            Lease {
+                location: 23,
+                ip: "192.168.1.50".parse().unwrap(),
+                hostname: "phone".to_string(),
+                mac: None,
+                expires: None,
+            },
+        ]
+    );
+}
+
+#[test]
+fn isc_leases_load_through_the_same_path() {
+    for setting in ["auto", "isc"] {
+        let cache = load(&format!("isc-{}", setting), ISC, &format!("dhcp_lease_format = {:?}\n", setting));
+
+        assert_eq!(addresses(&cache, "phone.lan."), ["192.168.1.50".parse::<IpAddr>().unwrap()], "{}", setting);
+        assert!(cache.wildcards.iter().any(|(pattern, _)| pattern == "*.laptop.lan."), "{}", setting);
+        assert_eq!(cache.exact_matches.len(), 2, "{}", setting);
+        assert!(cache.failures.is_empty());
+    }
+}
+
+#[test]
+fn kea_rows_keep_the_last_current_one_per_address() {
+    let leases = parse(KEA, LeaseFormat::Kea, "dhcp test").unwrap();
+    let summary: Vec<(usize, String, Option<u64>)> = leases.iter().map(|l| (l.location, l.hostname.clone(), l.expires)).collect();
+
+    assert_eq!(
+        summary,
+        [
+            (2, "laptop.lan.".to_string(), Some(4070908800)),
+            (4, "printer".to_string(), Some(4070908800)),
+            (8, "tv,den".to_string(), None),
+        ]
+    );
+    assert_eq!(leases[0].mac, Some([0xaa, 0xbb, 0xcc, 0xdd, 0xee, 0xff]));
+}
+
+#[test]
+fn kea_hostnames_already_under_the_suffix_keep_it_once() {
+    for setting in ["auto", "kea"] {
+        let cache = load(&format!("kea-{}", setting), KEA, &format!("dhcp_lease_format = {:?}\n", setting));
+
+        assert_eq!(addresses(&cache, "laptop.lan."), ["192.168.1.23".parse::<IpAddr>().unwrap()], "{}", setting);
+        assert!(!cache.exact_matches.contains_key(&parse_key("laptop.lan.lan.").unwrap()));
+        assert!(cache.wildcards.iter().any(|(pattern, _)| pattern == "*.laptop.lan."), "{}", setting);
+        assert_eq!(addresses(&cache, "printer.lan."), ["192.168.1.25".parse::<IpAddr>().unwrap()], "{}", setting);
+        assert!(addresses(&cache, "tablet.lan.").is_empty(), "{}", setting);
+    }
+}
+
+/// A networkd lease expiring `from_now` seconds from now.
+fn networkd_lease(address: u8, hostname: &str, from_now: i64) -> String {
+    let now = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_secs() as i64;
+    format!(r#"{{"Address":[192,168,1,{}],"Hostname":"{}","ExpirationRealtimeUSec":{}}}"#, address, hostname, (now + from_now) * 1_000_000)
+}
+
+#[test]
+fn json_leases_carry_their_expiry() {
+    let content = r#"{"Leases":[
+        {"Address":[192,168,1,23],"Hostname":"laptop","ExpirationRealtimeUSec":4070908800123456},
+        {"Address":[192,168,1,24],"Hostname":"tv","ExpirationRealtimeUSec":18446744073709551615}
+    ]}"#;
+    let leases = parse(content, LeaseFormat::Json, "dhcp test").unwrap();
+
+    assert_eq!(leases.iter().map(|l| l.expires).collect::<Vec<_>>(), [Some(4070908800), None]);
+}
+
+#[test]
+fn expired_leases_are_left_out_until_expiry_is_ignored() {
+    let leases = format!(
+        r#"{{"Leases":[{},{},{}]}}"#,
+        networkd_lease(10, "gone", -60),
+        networkd_lease(11, "laptop", 600),
+        networkd_lease(12, "phone", 3600)
+    );
+    let cache = load("expired", &leases, "");
+
+    assert!(addresses(&cache, "gone.lan.").is_empty());
+    assert_eq!(addresses(&cache, "laptop.lan."), ["192.168.1.11".parse::<IpAddr>().unwrap()]);
+    // The reload loop reloads once the laptop's lease runs out
+    let now = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_secs();
+    assert!(cache.next_expiry.is_some_and(|at| at > now && at <= now + 600), "{:?}", cache.next_expiry);
+
+    let cache = load("expired-ignored", &leases, "dhcp_ignore_expiry = true\n");
+    assert_eq!(addresses(&cache, "gone.lan."), ["192.168.1.10".parse::<IpAddr>().unwrap()]);
+    assert_eq!(cache.next_expiry, None);
+}
+
+#[test]
+fn the_lease_expiring_last_wins_a_hostname() {
+    let leases = format!(
+        r#"{{"Leases":[{},{}]}}"#,
+        networkd_lease(20, "laptop", 3600),
+        networkd_lease(21, "laptop", 600)
+    );
+    let cache = load("moved", &leases, "");
+
+    assert_eq!(addresses(&cache, "laptop.lan."), ["192.168.1.20".parse::<IpAddr>().unwrap()]);
+    assert_eq!(cache.wildcards.iter().filter(|(pattern, _)| pattern == "*.laptop.lan.").count(), 1);
+    assert!(cache.skipped.iter().any(|s| s.reason.contains("192.168.1.20")), "{:?}", cache.skipped.iter().map(|s| s.to_string()).collect::<Vec<_>>());
+
+    // A lease of the other family doesn't compete, as with dnsmasq's IPv6 lines
+    let cache = load("moved-ipv6", DNSMASQ, "");
+    assert_eq!(addresses(&cache, "laptop.lan.").len(), 2);
+
+    let cache = load("moved-ignored", &leases, "dhcp_ignore_expiry = true\n");
+    assert_eq!(addresses(&cache, "laptop.lan.").len(), 2);
+}
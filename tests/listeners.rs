@@ -0,0 +1,160 @@
+use hickory_proto::op::{Message, Query, ResponseCode};
+use hickory_proto::rr::{Name, RecordType};
+use localdns::config::Config;
+use localdns::listeners::Listeners;
+use localdns::loader::{parse_key, DnsCache, Entry, Source, SourceKind};
+use localdns::server::ResolveOptions;
+use localdns::view::{ListenerConfig, ViewConfig};
+use std::collections::HashMap;
+use std::net::{Ipv4Addr, UdpSocket as StdUdpSocket};
+use std::path::Path;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use tokio::net::UdpSocket;
+use tokio::sync::RwLock;
+use tokio::time::{timeout, Duration};
+
+const CONFIG: &str = r#"
+listen_address = "127.0.0.1"
+listen_port = 10053
+dhcp_lease_file = "/nonexistent/leases"
+hosts_file = "/nonexistent/hosts"
+domain_suffix = "lan"
+nsid = ""
+
+[tcp]
+enabled = false
+"#;
+
+fn listeners(test: &str) -> Listeners {
+    let path = std::env::temp_dir().join(format!("localdns-listeners-{}-{}.toml", test, std::process::id()));
+    std::fs::write(&path, CONFIG).unwrap();
+    let config = Config::load(&path).unwrap();
+    let _ = std::fs::remove_file(&path);
+
+    let mut cache = DnsCache::default();
+    let source = Source { kind: SourceKind::Hosts, path: Arc::from(Path::new("/etc/hosts")), location: 1, by_line: true };
+    cache
+        .exact_matches
+        .insert(parse_key("nas.lan.").unwrap(), vec![Entry { ip: Ipv4Addr::new(192, 168, 1, 5).into(), source, ttl: None }]);
+    let records = Arc::new(RwLock::new(cache));
+    Listeners::new(records, ResolveOptions::from_config(&config), &config)
+}
+
+/// A loopback address nothing is bound to right now.
+fn free_address() -> String {
+    StdUdpSocket::bind("127.0.0.1:0").unwrap().local_addr().unwrap().to_string()
+}
+
+fn listener(name: &str, address: &str) -> ListenerConfig {
+    ListenerConfig { name: name.to_string(), address: address.to_string(), view: None }
+}
+
+async fn query(address: &str) -> Option<Message> {
+    let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let mut message = Message::new();
+    message.set_id(9).add_query(Query::query(Name::from_ascii("nas.lan.").unwrap(), RecordType::A));
+    client.send_to(&message.to_vec().unwrap(), address).await.unwrap();
+    let mut buf = [0u8; 4096];
+    let len = timeout(Duration::from_millis(500), client.recv(&mut buf)).await.ok()?.ok()?;
+    Some(Message::from_vec(&buf[..len]).unwrap())
+}
+
+fn names(listeners: &Listeners) -> Vec<String> {
+    listeners.status().into_iter().map(|status| status.name).collect()
+}
+
+#[tokio::test]
+async fn added_listeners_answer_and_removed_ones_release_their_socket() {
+    let listeners = listeners("add");
+    let address = free_address();
+
+    let failed = listeners.apply(&[listener("wg", &address)], &HashMap::new()).await;
+    assert!(failed.is_empty());
+    assert_eq!(names(&listeners), ["wg"]);
+    assert!(listeners.status()[0].bound_at > 0);
+    assert_eq!(query(&address).await.unwrap().response_code(), ResponseCode::NoError);
+
+    let failed = listeners.apply(&[], &HashMap::new()).await;
+    assert!(failed.is_empty());
+    assert!(names(&listeners).is_empty());
+    // Stopping waits for the socket to be closed
+    StdUdpSocket::bind(&address).unwrap();
+}
+
+#[tokio::test]
+async fn a_listener_that_cannot_bind_leaves_the_others_running() {
+    let listeners = listeners("fail");
+    let (working, taken) = (free_address(), free_address());
+    let _holder = StdUdpSocket::bind(&taken).unwrap();
+    listeners.apply(&[listener("lan", &working)], &HashMap::new()).await;
+
+    let failed = listeners.apply(&[listener("lan", &working), listener("guest", &taken)], &HashMap::new()).await;
+    assert_eq!(failed.len(), 1);
+    assert_eq!(failed[0].0, "guest");
+    assert_eq!(names(&listeners), ["lan"]);
+    assert!(query(&working).await.is_some());
+}
+
+#[tokio::test]
+async fn a_moved_listener_is_rebound_on_the_new_address() {
+    let listeners = listeners("move");
+    let (before, after) = (free_address(), free_address());
+    listeners.apply(&[listener("lan", &before)], &HashMap::new()).await;
+
+    listeners.apply(&[listener("lan", &after)], &HashMap::new()).await;
+    assert_eq!(listeners.status()[0].address, after);
+    assert!(query(&after).await.is_some());
+    assert!(query(&before).await.is_none());
+}
+
+#[tokio::test]
+async fn a_view_hides_other_sources_and_counts_its_own_queries() {
+    let listeners = listeners("view");
+    let (lan, guest) = (free_address(), free_address());
+    let dhcp_only = ViewConfig { sources: vec![SourceKind::Dhcp], fallback: false, ..ViewConfig::default() };
+    let views = HashMap::from([("guest".to_string(), dhcp_only)]);
+    let guest_listener = ListenerConfig { view: Some("guest".to_string()), ..listener("guest", &guest) };
+    assert!(listeners.apply(&[listener("lan", &lan), guest_listener], &views).await.is_empty());
+
+    // nas.lan comes from the hosts file, which the guest view doesn't see
+    assert_eq!(query(&lan).await.unwrap().answers().len(), 1);
+    assert_eq!(query(&guest).await.unwrap().response_code(), ResponseCode::NXDomain);
+    assert_eq!(query(&guest).await.unwrap().response_code(), ResponseCode::NXDomain);
+
+    let queries: Vec<(String, u64)> =
+        listeners.stats().into_iter().map(|(name, stats)| (name, stats.queries.load(Ordering::Relaxed))).collect();
+    assert_eq!(queries, [("lan".to_string(), 1), ("guest".to_string(), 2)]);
+}
+
+/// Loads `CONFIG` with its `listen_address` line replaced by `line`.
+fn load_with(test: &str, line: &str) -> Result<Config, String> {
+    let path = std::env::temp_dir().join(format!("localdns-listeners-{}-{}.toml", test, std::process::id()));
+    std::fs::write(&path, CONFIG.replace("listen_address = \"127.0.0.1\"", line)).unwrap();
+    let config = Config::load(&path).map_err(|e| e.to_string());
+    let _ = std::fs::remove_file(&path);
+    config
+}
+
+#[test]
+fn listen_address_takes_one_address_or_a_list() {
+    let single = load_with("listen-single", "listen_address = \"127.0.0.1\"").unwrap();
+    let list = load_with("listen-list", "listen_address = [\"192.168.1.1:53\", \"[fd00::1]:53\", \"::1\"]").unwrap();
+
+    assert_eq!(single.listen_addrs(), ["127.0.0.1:10053".parse().unwrap()]);
+    assert_eq!(
+        list.listen_addrs(),
+        ["192.168.1.1:53".parse().unwrap(), "[fd00::1]:53".parse().unwrap(), "[::1]:10053".parse().unwrap()]
+    );
+}
+
+#[test]
+fn bad_listen_addresses_are_rejected() {
+    let invalid = load_with("listen-invalid", "listen_address = [\"127.0.0.1\", \"lan-router\"]").unwrap_err();
+    let repeated = load_with("listen-repeated", "listen_address = [\"127.0.0.1\", \"127.0.0.1:10053\"]").unwrap_err();
+    let empty = load_with("listen-empty", "listen_address = []").unwrap_err();
+
+    assert!(invalid.contains("\"lan-router\""), "{}", invalid);
+    assert!(repeated.contains("127.0.0.1:10053 is listed more than once"), "{}", repeated);
+    assert!(empty.contains("at least one address"), "{}", empty);
+}
@@ -0,0 +1,146 @@
+use hickory_proto::op::{Message, MessageType, Query, ResponseCode};
+use hickory_proto::rr::rdata::A;
+use hickory_proto::rr::{DNSClass, Name, RData, Record, RecordType};
+use localdns::config::Config;
+use localdns::loader::{load_records, DnsCache};
+use localdns::mdns;
+use localdns::server::{resolve, AnswerSource, ResolveOptions};
+use std::fs;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::sync::Arc;
+use tokio::net::UdpSocket;
+use tokio::sync::RwLock;
+use tokio::time::{timeout, Duration};
+
+const CONFIG: &str = r#"
+listen_address = "127.0.0.1"
+listen_port = 10053
+dhcp_lease_file = "/nonexistent/leases"
+hosts_file = "hosts"
+domain_suffix = "lan"
+nsid = ""
+mdns = true
+"#;
+
+fn load(test: &str, settings: &str) -> (DnsCache, ResolveOptions) {
+    let dir = std::env::temp_dir().join(format!("localdns-mdns-{}-{}", test, std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("hosts"), "192.168.1.5 nas.local\nfd00::5 nas.local\n192.168.1.6 printer.lan\n").unwrap();
+    let path = dir.join("config.toml");
+    fs::write(&path, format!("{}{}", CONFIG, settings)).unwrap();
+    let mut config = Config::load(&path).unwrap();
+    config.hosts_file = dir.join("hosts");
+    let records = load_records(&config.load_options(&path)).unwrap();
+    fs::remove_dir_all(&dir).unwrap();
+    (records, ResolveOptions::from_config(&config))
+}
+
+fn query(name: &str, qtype: RecordType) -> Message {
+    let mut request = Message::new();
+    request.set_id(0x1234);
+    request.add_query(Query::query(Name::from_ascii(name).unwrap(), qtype));
+    request
+}
+
+#[test]
+fn multicast_answers_carry_the_cache_flush_bit_and_no_question() {
+    let (records, _) = load("multicast", "");
+
+    let response = mdns::answer(&query("NAS.local.", RecordType::ANY), false, &records).unwrap();
+
+    assert_eq!(response.id(), 0);
+    assert_eq!(response.message_type(), MessageType::Response);
+    assert!(response.authoritative());
+    assert!(response.queries().is_empty());
+    assert_eq!(response.answers().len(), 2);
+    for record in response.answers() {
+        assert_eq!(record.name().to_ascii(), "NAS.local.");
+        assert_eq!(record.ttl(), mdns::HOST_TTL);
+        assert_eq!(u16::from(record.dns_class()), 0x8001);
+    }
+}
+
+#[test]
+fn names_we_dont_own_get_no_answer() {
+    let (records, _) = load("silent", "");
+
+    for (name, qtype) in [
+        ("phone.local.", RecordType::A),
+        // Ours, but not under .local
+        ("printer.lan.", RecordType::A),
+        // Ours, but not a type we answer
+        ("nas.local.", RecordType::TXT),
+    ] {
+        assert!(mdns::answer(&query(name, qtype), false, &records).is_none(), "{}", name);
+    }
+}
+
+#[test]
+fn legacy_unicast_answers_look_like_unicast_dns() {
+    let (records, _) = load("legacy", "");
+
+    let response = mdns::answer(&query("nas.local.", RecordType::A), true, &records).unwrap();
+
+    assert_eq!(response.id(), 0x1234);
+    assert_eq!(response.queries().len(), 1);
+    let [record] = response.answers() else { panic!("{:?}", response.answers()) };
+    assert_eq!(record.data(), Some(&RData::A(A(Ipv4Addr::new(192, 168, 1, 5)))));
+    assert_eq!(record.dns_class(), DNSClass::IN);
+    assert!(record.ttl() <= 10);
+}
+
+#[test]
+fn known_answers_are_not_repeated() {
+    let (records, _) = load("known", "");
+    let mut request = query("nas.local.", RecordType::ANY);
+    let name = Name::from_ascii("nas.local.").unwrap();
+    request.add_answer(Record::from_rdata(name.clone(), mdns::HOST_TTL, RData::A(A(Ipv4Addr::new(192, 168, 1, 5)))));
+
+    let response = mdns::answer(&request, false, &records).unwrap();
+    assert_eq!(response.answers().len(), 1);
+    assert_eq!(response.answers()[0].record_type(), RecordType::AAAA);
+
+    // A known answer about to expire is refreshed
+    let mut request = query("nas.local.", RecordType::A);
+    request.add_answer(Record::from_rdata(name, mdns::HOST_TTL / 4, RData::A(A(Ipv4Addr::new(192, 168, 1, 5)))));
+    assert_eq!(mdns::answer(&request, false, &records).unwrap().answers().len(), 1);
+}
+
+#[tokio::test]
+async fn the_responder_answers_legacy_queries_to_the_sender() {
+    let (records, _) = load("socket", "");
+    let Ok(socket) = mdns::bind(SocketAddr::from((Ipv4Addr::LOCALHOST, 0))) else {
+        eprintln!("skipping: no multicast here");
+        return;
+    };
+    let addr = socket.local_addr().unwrap();
+    tokio::spawn(mdns::serve(socket, Arc::new(RwLock::new(records))));
+
+    let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let mut buffer = [0u8; 512];
+    client.send_to(&query("missing.local.", RecordType::A).to_vec().unwrap(), addr).await.unwrap();
+    client.send_to(&query("nas.local.", RecordType::A).to_vec().unwrap(), addr).await.unwrap();
+    // Only the name we own is answered
+    let len = timeout(Duration::from_secs(2), client.recv(&mut buffer)).await.unwrap().unwrap();
+    let response = Message::from_vec(&buffer[..len]).unwrap();
+    assert_eq!(response.queries()[0].name().to_ascii(), "nas.local.");
+    assert_eq!(response.answers().len(), 1);
+}
+
+#[tokio::test]
+async fn local_names_are_answered_over_unicast_but_never_forwarded() {
+    // Nothing listens here; forwarding would answer SERVFAIL after the timeout
+    let (records, options) = load("unicast", "upstream = [\"127.0.0.1:9\"]\nupstream_timeout_ms = 100\n");
+    let records = RwLock::new(records);
+    let ask = |name: &'static str| {
+        let (records, options) = (&records, &options);
+        async move {
+            let (response, source) = resolve(&query(name, RecordType::A), None, records, options).await.unwrap();
+            (response.response_code(), source)
+        }
+    };
+
+    assert_eq!(ask("nas.local.").await, (ResponseCode::NoError, AnswerSource::Exact));
+    assert_eq!(ask("phone.local.").await, (ResponseCode::NXDomain, AnswerSource::None));
+    assert_eq!(ask("cdn.example.net.").await.1, AnswerSource::Upstream);
+}
@@ -0,0 +1,87 @@
+use hickory_proto::op::ResponseCode;
+use hickory_proto::rr::RecordType;
+use localdns::blocklist::Blocks;
+use localdns::loader::{parse_key, Conflict, DnsCache, SourceKind};
+use localdns::metrics::render;
+use localdns::server::AnswerSource;
+use localdns::stats::Stats;
+use std::sync::atomic::Ordering;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+fn listeners() -> Vec<(String, Arc<Stats>)> {
+    let default = Arc::new(Stats::default());
+    let guest = Arc::new(Stats::default());
+    let answered = |stats: &Stats, qtype, rcode, source| stats.record(Some(qtype), rcode, source, Duration::from_micros(50));
+    answered(&default, RecordType::A, ResponseCode::NoError, AnswerSource::Exact);
+    answered(&default, RecordType::A, ResponseCode::NoError, AnswerSource::Wildcard);
+    answered(&default, RecordType::AAAA, ResponseCode::NXDomain, AnswerSource::None);
+    answered(&guest, RecordType::NAPTR, ResponseCode::NoError, AnswerSource::None);
+    default.reloads.fetch_add(2, Ordering::Relaxed);
+    default.reload_failures.fetch_add(1, Ordering::Relaxed);
+    default.exact_records.store(12, Ordering::Relaxed);
+    default.wildcard_records.store(3, Ordering::Relaxed);
+    vec![("default".to_string(), default), ("guest".to_string(), guest)]
+}
+
+#[test]
+fn counters_are_labelled_by_listener() {
+    let text = render(&listeners(), &Blocks::default());
+    let lines: Vec<&str> = text.lines().collect();
+
+    for expected in [
+        r#"localdns_queries_total{listener="default"} 3"#,
+        r#"localdns_queries_total{listener="guest"} 1"#,
+        r#"localdns_responses_total{listener="default",rcode="NOERROR"} 2"#,
+        r#"localdns_responses_total{listener="default",rcode="NXDOMAIN"} 1"#,
+        r#"localdns_queries_by_type_total{listener="default",type="A"} 2"#,
+        r#"localdns_queries_by_type_total{listener="guest",type="other"} 1"#,
+        r#"localdns_answers_by_source_total{listener="default",source="exact"} 1"#,
+        r#"localdns_answers_by_source_total{listener="default",source="wildcard"} 1"#,
+    ] {
+        assert!(lines.contains(&expected), "missing {}\n{}", expected, text);
+    }
+}
+
+#[test]
+fn reloads_and_record_counts_are_reported_once() {
+    let text = render(&listeners(), &Blocks::default());
+    let lines: Vec<&str> = text.lines().collect();
+
+    for expected in [
+        "# TYPE localdns_records gauge",
+        "localdns_reloads_total 2",
+        "localdns_reload_failures_total 1",
+        r#"localdns_records{kind="exact"} 12"#,
+        r#"localdns_records{kind="wildcard"} 3"#,
+    ] {
+        assert!(lines.contains(&expected), "missing {}\n{}", expected, text);
+    }
+    assert_eq!(lines.iter().filter(|line| line.starts_with("localdns_reloads_total")).count(), 1);
+}
+
+#[test]
+fn conflicting_names_are_a_gauge_set_with_the_records() {
+    let mut records = DnsCache::default();
+    records.exact_matches.insert(parse_key("nas.lan.").unwrap(), Vec::new());
+    for name in ["nas.lan.", "printer.lan."] {
+        let sources = vec![
+            (SourceKind::Hosts, Arc::from(Path::new("/etc/hosts")), vec!["192.168.1.5".parse().unwrap()]),
+            (SourceKind::Dhcp, Arc::from(Path::new("/var/lib/leases")), vec!["192.168.1.6".parse().unwrap()]),
+        ];
+        records.conflicts.push(Conflict { name: name.to_string(), sources });
+    }
+    let stats = Arc::new(Stats::default());
+    stats.set_records(&records);
+
+    let text = render(&[("default".to_string(), stats.clone())], &Blocks::default());
+    let lines: Vec<&str> = text.lines().collect();
+    assert!(lines.contains(&"# TYPE localdns_conflicting_names gauge"), "{}", text);
+    assert!(lines.contains(&"localdns_conflicting_names 2"), "{}", text);
+    assert!(lines.contains(&r#"localdns_records{kind="exact"} 1"#), "{}", text);
+
+    // A later load without them brings it back down
+    stats.set_records(&DnsCache::default());
+    assert!(render(&[("default".to_string(), stats)], &Blocks::default()).lines().any(|line| line == "localdns_conflicting_names 0"));
+}
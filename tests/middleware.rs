@@ -0,0 +1,105 @@
+use hickory_proto::op::{Message, Query, ResponseCode};
+use hickory_proto::rr::{Name, RecordType};
+use localdns::config::Config;
+use localdns::loader::DnsCache;
+use localdns::middleware::{BoxFuture, Chain, Lookup, Middleware, Next, QueryContext};
+use localdns::server::{resolve, response_for, AnswerSource, ResolveOptions};
+use std::sync::{Arc, Mutex};
+use tokio::sync::RwLock;
+
+const CONFIG: &str = r#"
+listen_address = "127.0.0.1"
+listen_port = 10053
+dhcp_lease_file = "/nonexistent/leases"
+hosts_file = "/nonexistent/hosts"
+domain_suffix = "lan"
+fallback_ip = "10.9.9.9"
+nsid = ""
+"#;
+
+fn options(test: &str, middlewares: Vec<Arc<dyn Middleware>>) -> ResolveOptions {
+    let path = std::env::temp_dir()
+        .join(format!("localdns-middleware-{}-{}.toml", test, std::process::id()));
+    std::fs::write(&path, CONFIG).unwrap();
+    let config = Config::load(&path).unwrap();
+    let _ = std::fs::remove_file(&path);
+    let mut options = ResolveOptions::from_config(&config);
+    options.middleware = Chain::new(middlewares);
+    options
+}
+
+fn query(name: &str) -> Message {
+    let mut message = Message::new();
+    message.add_query(Query::query(Name::from_ascii(name).unwrap(), RecordType::A));
+    message
+}
+
+/// Notes when a query enters and leaves it.
+struct Trace {
+    name: &'static str,
+    log: Arc<Mutex<Vec<String>>>,
+}
+
+impl Middleware for Trace {
+    fn handle<'a>(&'a self, ctx: &'a QueryContext<'a>, next: Next<'a>) -> BoxFuture<'a> {
+        Box::pin(async move {
+            self.log.lock().unwrap().push(format!("{} in", self.name));
+            let answer = next.run(ctx).await;
+            self.log.lock().unwrap().push(format!("{} out", self.name));
+            answer
+        })
+    }
+}
+
+/// Refuses everything without consulting the rest of the chain.
+struct Refuse;
+
+impl Middleware for Refuse {
+    fn handle<'a>(&'a self, ctx: &'a QueryContext<'a>, _next: Next<'a>) -> BoxFuture<'a> {
+        Box::pin(async move {
+            let mut response = response_for(ctx.request);
+            response.set_response_code(ResponseCode::Refused);
+            Ok((response, AnswerSource::Middleware))
+        })
+    }
+}
+
+#[tokio::test]
+async fn middlewares_run_in_order_around_the_lookup() {
+    let log = Arc::new(Mutex::new(Vec::new()));
+    let trace = |name| Arc::new(Trace { name, log: log.clone() }) as Arc<dyn Middleware>;
+    let options = options("order", vec![trace("outer"), trace("inner"), Arc::new(Lookup)]);
+    let records = RwLock::new(DnsCache::default());
+
+    let (response, source) = resolve(&query("anything.lan."), None, &records, &options).await.unwrap();
+
+    assert_eq!(source, AnswerSource::Fallback);
+    assert_eq!(response.answers().len(), 1);
+    assert_eq!(*log.lock().unwrap(), ["outer in", "inner in", "inner out", "outer out"]);
+}
+
+#[tokio::test]
+async fn a_middleware_that_answers_skips_the_rest() {
+    let log = Arc::new(Mutex::new(Vec::new()));
+    let after = Arc::new(Trace { name: "after", log: log.clone() });
+    let options = options("short-circuit", vec![Arc::new(Refuse), after, Arc::new(Lookup)]);
+    let records = RwLock::new(DnsCache::default());
+
+    let (response, source) = resolve(&query("anything.lan."), None, &records, &options).await.unwrap();
+
+    assert_eq!(source, AnswerSource::Middleware);
+    assert_eq!(response.response_code(), ResponseCode::Refused);
+    assert!(response.answers().is_empty());
+    assert!(log.lock().unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn a_chain_that_never_answers_refuses() {
+    let options = options("empty", Vec::new());
+    let records = RwLock::new(DnsCache::default());
+
+    let (response, source) = resolve(&query("anything.lan."), None, &records, &options).await.unwrap();
+
+    assert_eq!(source, AnswerSource::None);
+    assert_eq!(response.response_code(), ResponseCode::Refused);
+}
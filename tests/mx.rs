@@ -0,0 +1,125 @@
+use hickory_proto::op::{Message, Query, ResponseCode};
+use hickory_proto::rr::{Name, RData, RecordType};
+use localdns::config::Config;
+use localdns::loader::{load_records, DnsCache};
+use localdns::server::{resolve, ResolveOptions};
+use std::fs;
+use std::net::IpAddr;
+use tokio::sync::RwLock;
+
+const CONFIG: &str = r#"
+listen_address = "127.0.0.1"
+listen_port = 10053
+dhcp_lease_file = "/nonexistent/leases"
+hosts_file = "hosts"
+domain_suffix = "lan"
+nsid = ""
+
+[[records]]
+name = "lan"
+type = "MX"
+preference = 5
+exchange = "primary.lan"
+"#;
+
+const HOSTS: &str = "\
+192.168.1.25 relay.lan
+fd00::25 relay.lan
+192.168.1.26 primary.lan
+mx lan 20 relay.lan
+mx lan 10 backup.example.com
+MX lan 10 relay.lan
+mx office.lan 70000 relay.lan
+";
+
+fn load(test: &str, config: &str) -> (RwLock<DnsCache>, ResolveOptions) {
+    let dir = std::env::temp_dir().join(format!("localdns-mx-{}-{}", test, std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("hosts"), HOSTS).unwrap();
+    let path = dir.join("config.toml");
+    fs::write(&path, config).unwrap();
+    let mut config = Config::load(&path).unwrap();
+    config.hosts_file = dir.join("hosts");
+    let records = RwLock::new(load_records(&config.load_options(&path)).unwrap());
+    fs::remove_dir_all(&dir).unwrap();
+    (records, ResolveOptions::from_config(&config))
+}
+
+async fn ask(records: &RwLock<DnsCache>, options: &ResolveOptions, name: &str, qtype: RecordType) -> Message {
+    let mut request = Message::new();
+    request.add_query(Query::query(Name::from_ascii(name).unwrap(), qtype));
+    resolve(&request, None, records, options).await.unwrap().0
+}
+
+#[tokio::test]
+async fn mx_answers_are_ordered_by_preference() {
+    let (records, options) = load("order", CONFIG);
+
+    let response = ask(&records, &options, "lan.", RecordType::MX).await;
+
+    assert_eq!(response.response_code(), ResponseCode::NoError);
+    let mxs: Vec<(u16, String)> = response
+        .answers()
+        .iter()
+        .filter_map(|record| match record.data() {
+            Some(RData::MX(mx)) => Some((mx.preference(), mx.exchange().to_string())),
+            _ => None,
+        })
+        .collect();
+    // Equal preferences keep the order they were listed in; the config comes after the hosts file
+    assert_eq!(mxs, [
+        (5, "primary.lan.".to_string()),
+        (10, "backup.example.com.".to_string()),
+        (10, "relay.lan.".to_string()),
+        (20, "relay.lan.".to_string()),
+    ]);
+}
+
+#[tokio::test]
+async fn local_exchanges_get_their_addresses_in_the_additional_section() {
+    let (records, options) = load("additional", CONFIG);
+
+    let response = ask(&records, &options, "lan.", RecordType::MX).await;
+
+    let additional: Vec<(String, IpAddr)> = response
+        .additionals()
+        .iter()
+        .filter_map(|record| match record.data() {
+            Some(RData::A(a)) => Some((record.name().to_string(), IpAddr::V4(a.0))),
+            Some(RData::AAAA(aaaa)) => Some((record.name().to_string(), IpAddr::V6(aaaa.0))),
+            _ => None,
+        })
+        .collect();
+    // Each exchange once, and nothing for the one that isn't local
+    assert_eq!(additional, [
+        ("primary.lan.".to_string(), "192.168.1.26".parse().unwrap()),
+        ("relay.lan.".to_string(), "192.168.1.25".parse().unwrap()),
+        ("relay.lan.".to_string(), "fd00::25".parse().unwrap()),
+    ]);
+}
+
+#[tokio::test]
+async fn names_with_only_mx_records_get_nodata() {
+    let (records, options) = load("nodata", CONFIG);
+
+    let response = ask(&records, &options, "lan.", RecordType::A).await;
+    assert_eq!(response.response_code(), ResponseCode::NoError);
+    assert!(response.answers().is_empty());
+    // An address-only name has no MX records
+    let response = ask(&records, &options, "relay.lan.", RecordType::MX).await;
+    assert_eq!(response.response_code(), ResponseCode::NoError);
+    assert!(response.answers().is_empty());
+    // The out-of-range preference was skipped
+    let response = ask(&records, &options, "office.lan.", RecordType::MX).await;
+    assert_eq!(response.response_code(), ResponseCode::NXDomain);
+}
+
+#[test]
+fn config_mx_records_need_preference_and_exchange() {
+    let path = std::env::temp_dir().join(format!("localdns-mx-invalid-{}.toml", std::process::id()));
+    fs::write(&path, CONFIG.replace("preference = 5\n", "")).unwrap();
+    let error = Config::load(&path).unwrap_err().to_string();
+    let _ = fs::remove_file(&path);
+
+    assert!(error.contains("preference"), "{}", error);
+}
@@ -0,0 +1,271 @@
+use hickory_proto::rr::{LowerName, Name};
+use localdns::loader::{load_records, name_key, parse_key, DnsCache, HostnamePolicy, LoadError, LoadOptions, SynthesizeUnnamed};
+use std::fs;
+use std::path::PathBuf;
+
+/// Loads `hosts` and `leases` from a scratch directory unique to `test`.
+fn load(test: &str, hosts: &str, leases: &str) -> DnsCache {
+    load_with(test, hosts, leases, |_| {})
+}
+
+fn load_with(test: &str, hosts: &str, leases: &str, configure: impl FnOnce(&mut LoadOptions)) -> DnsCache {
+    let dir: PathBuf = std::env::temp_dir().join(format!("localdns-{}-{}", test, std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let (hosts_path, lease_path) = (dir.join("hosts"), dir.join("leases.json"));
+    fs::write(&hosts_path, hosts).unwrap();
+    fs::write(&lease_path, leases).unwrap();
+    let mut options = LoadOptions::new(lease_path, hosts_path, "lan", dir.join("config.toml"));
+    configure(&mut options);
+    let cache = load_records(&options).unwrap();
+    fs::remove_dir_all(&dir).unwrap();
+    cache
+}
+
+/// The key a query for these raw labels is looked up under.
+fn query_key(labels: &[&[u8]]) -> LowerName {
+    let mut name = Name::from_labels(labels.iter().copied()).unwrap();
+    name.set_fqdn(true);
+    LowerName::from(name)
+}
+
+fn lease(hostname: &str, last_octet: u8) -> String {
+    format!(r#"{{"Address":[10,0,0,{}],"Hostname":{}}}"#, last_octet, serde_json::Value::from(hostname))
+}
+
+#[test]
+fn dhcp_hostname_with_space_is_sanitized() {
+    let leases = format!(r#"{{"Leases":[{}]}}"#, lease("Tom s-iPad", 2));
+    let cache = load("space", "", &leases);
+
+    assert!(cache.exact_matches.contains_key(&query_key(&[b"tom-s-ipad", b"lan"])));
+    assert!(cache.exact_matches.contains_key(&query_key(&[b"TOM-S-IPAD", b"LAN"])));
+
+    let cache = load_with("space-skip", "", &leases, |o| o.hostnames.policy = HostnamePolicy::Skip);
+    assert!(cache.exact_matches.is_empty());
+    let skipped = cache.skipped[0].to_string();
+    assert!(skipped.contains("\"Tom s-iPad\" of 10.0.0.2") && skipped.contains("' '"), "{}", skipped);
+}
+
+#[test]
+fn escaped_dot_stays_inside_its_label() {
+    let cache = load("dot", "10.0.0.3 a\\.b.lan\n10.0.0.4 trailing\\..lan.\n", r#"{"Leases":[]}"#);
+
+    assert!(cache.exact_matches.contains_key(&query_key(&[b"a.b", b"lan"])));
+    assert!(cache.exact_matches.contains_key(&query_key(&[b"trailing.", b"lan"])));
+    assert!(!cache.exact_matches.contains_key(&query_key(&[b"a", b"b", b"lan"])));
+}
+
+#[test]
+fn escaped_high_bit_bytes_stay_raw() {
+    // UTF-8 bytes written as decimal escapes are taken as the bytes they are
+    let cache = load("highbit", "10.0.0.5 na\\195\\175ve.lan\n", r#"{"Leases":[]}"#);
+
+    assert!(cache.exact_matches.contains_key(&query_key(&["na\u{ef}ve".as_bytes(), b"lan"])));
+}
+
+#[test]
+fn unicode_hostnames_are_served_under_their_idna_form() {
+    let leases = format!(r#"{{"Leases":[{},{}]}}"#, lease("caf\u{e9}", 4), lease("Tom s caf\u{e9}", 5));
+    let cache = load("idna", "10.0.0.5 b\u{fc}ro.lan\n10.0.0.6 *.B\u{dc}RO.lan\n", &leases);
+
+    // Resolvers send the punycode labels, whatever the case
+    assert!(cache.exact_matches.contains_key(&query_key(&[b"xn--bro-hoa", b"lan"])));
+    assert!(cache.exact_matches.contains_key(&query_key(&[b"XN--CAF-DMA", b"LAN"])));
+    assert!(cache.wildcards.iter().any(|(pattern, _)| pattern == "*.xn--bro-hoa.lan."));
+    // The space is replaced before the label is encoded
+    let names: Vec<String> = cache.exact_matches.keys().map(name_key).collect();
+    assert!(names.iter().any(|name| name.starts_with("xn--tom-s-caf-")), "{:?}", names);
+    assert!(!cache.exact_matches.contains_key(&query_key(&["caf\u{e9}".as_bytes(), b"lan"])));
+}
+
+#[test]
+fn mixed_case_and_trailing_dots_share_a_key() {
+    let cache = load("case", "10.0.0.1 NAS.Lan\n10.0.0.2 nas.lan.\n10.0.0.3 Printer.LAN.\n", r#"{"Leases":[]}"#);
+
+    assert_eq!(cache.exact_matches[&parse_key("nas.lan.").unwrap()].len(), 2);
+    assert!(cache.exact_matches.contains_key(&query_key(&[b"pRINTER", b"lan"])));
+    assert_eq!(cache.exact_matches.len(), 2);
+}
+
+#[test]
+fn invalid_unicode_names_are_skipped_with_their_origin() {
+    // Too long once encoded
+    let hosts = format!("10.0.0.7 good.lan {}.lan\n", "\u{e9}".repeat(60));
+    let cache = load("bad-idna", &hosts, r#"{"Leases":[]}"#);
+
+    assert_eq!(cache.exact_matches.keys().map(name_key).collect::<Vec<_>>(), ["good.lan."]);
+    let skipped = cache.skipped[0].to_string();
+    assert!(skipped.contains("hosts") && skipped.contains(":1"), "{}", skipped);
+}
+
+#[test]
+fn wildcards_are_keyed_like_exact_names() {
+    let leases = format!(r#"{{"Leases":[{}]}}"#, lease("Tom s-iPad", 2));
+    let cache = load("wildcard", "10.0.0.6 *.Example.com\n", &leases);
+
+    let patterns: Vec<&str> = cache.wildcards.iter().map(|(p, _)| p.as_str()).collect();
+    assert!(patterns.contains(&format!("*.{}", name_key(&query_key(&[b"tom-s-ipad", b"lan"]))).as_str()));
+    assert!(patterns.contains(&"*.example.com."));
+}
+
+#[test]
+fn invalid_hostnames_are_skipped() {
+    let hosts = "10.0.0.7 nas..lan good.lan bad_-.lan -dash.lan sp@ce.lan\n10.0.0.8 a123456789012345678901234567890123456789012345678901234567890123.lan\n";
+    let cache = load("invalid", hosts, r#"{"Leases":[]}"#);
+
+    let names: Vec<String> = cache.exact_matches.keys().map(name_key).collect();
+    assert_eq!(names, ["good.lan."]);
+    assert!(cache.failures.is_empty());
+}
+
+#[test]
+fn service_labels_keep_their_underscores() {
+    let cache = load("service", "10.0.0.9 _http._tcp.lan\n", r#"{"Leases":[]}"#);
+
+    assert!(cache.exact_matches.contains_key(&parse_key("_http._tcp.lan.").unwrap()));
+}
+
+#[test]
+fn strict_hosts_fails_the_source_on_an_invalid_name() {
+    let cache = load_with("strict", "10.0.0.7 good.lan\n10.0.0.8 nas..lan\n", r#"{"Leases":[]}"#, |o| o.strict_hosts = true);
+
+    assert!(cache.exact_matches.is_empty());
+    assert_eq!(cache.failures.len(), 1);
+    let message = cache.failures[0].to_string();
+    assert!(message.contains("\"nas..lan\"") && message.contains("line 2"), "{}", message);
+}
+
+#[test]
+fn strict_hosts_reports_a_malformed_entry_as_such() {
+    let cache = load_with("strict-entry", "10.0.0.7 good.lan\nmx mail.lan ten mx.lan\n", r#"{"Leases":[]}"#, |o| o.strict_hosts = true);
+
+    assert_eq!(cache.failures.len(), 1);
+    match &cache.failures[0] {
+        LoadError::InvalidEntry { line, kind, .. } => assert_eq!((*line, *kind), (2, "mx")),
+        other => panic!("expected an invalid entry, got {:?}", other),
+    }
+}
+
+#[test]
+fn entry_keywords_match_in_any_case() {
+    let hosts = "PTR 10.0.0.9 printer.lan\nCName files.lan nas.lan\n10.0.0.5 nas.lan\n";
+    let cache = load("keywords", hosts, r#"{"Leases":[]}"#);
+
+    assert!(cache.skipped.is_empty(), "{:?}", cache.skipped);
+    assert_eq!(cache.ptrs.len(), 1);
+    assert_eq!(cache.ptrs[0].name, "printer.lan.");
+    assert_eq!(cache.cnames[&parse_key("files.lan.").unwrap()].target, "nas.lan.");
+}
+
+const UNNAMED: &str = r#"{"Leases":[
+    {"Address":[10,0,0,20],"Hostname":"","HardwareAddress":[161,178,195,212,229,246]},
+    {"Address":[10,0,0,21],"ClientId":[1,2,0,0,0,0,1]},
+    {"Address":[10,0,0,22],"Hostname":""}
+]}"#;
+
+#[test]
+fn unnamed_leases_get_mac_names() {
+    let cache = load_with("mac", "", UNNAMED, |o| o.synthesize_unnamed = SynthesizeUnnamed::Mac);
+
+    let mut names: Vec<String> = cache.synthesized.iter().map(name_key).collect();
+    names.sort();
+    assert_eq!(names, ["mac-020000000001.lan.", "mac-a1b2c3d4e5f6.lan."]);
+    assert!(cache.exact_matches.contains_key(&parse_key("mac-a1b2c3d4e5f6.lan.").unwrap()));
+    assert!(cache.wildcards.is_empty());
+    assert_eq!(cache.reverse[&"10.0.0.20".parse().unwrap()], ["mac-a1b2c3d4e5f6.lan."]);
+}
+
+#[test]
+fn unnamed_leases_get_ip_names_unless_a_real_name_is_taken() {
+    let hosts = "10.9.9.9 ip-10-0-0-21.lan\n";
+    let cache = load_with("ip", hosts, UNNAMED, |o| o.synthesize_unnamed = SynthesizeUnnamed::Ip);
+
+    assert!(cache.synthesized.contains(&parse_key("ip-10-0-0-20.lan.").unwrap()));
+    assert!(cache.synthesized.contains(&parse_key("ip-10-0-0-22.lan.").unwrap()));
+    assert!(!cache.synthesized.contains(&parse_key("ip-10-0-0-21.lan.").unwrap()));
+    let entries = &cache.exact_matches[&parse_key("ip-10-0-0-21.lan.").unwrap()];
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0].ip.to_string(), "10.9.9.9");
+}
+
+#[test]
+fn unnamed_leases_stay_invisible_by_default() {
+    let cache = load("unnamed", "", UNNAMED);
+
+    assert!(cache.exact_matches.is_empty());
+    assert!(cache.synthesized.is_empty());
+}
+
+/// Names, addresses and PTRs, one per line, without the source locations.
+fn records(cache: &DnsCache) -> Vec<String> {
+    let mut lines: Vec<String> = cache
+        .exact_matches
+        .iter()
+        .flat_map(|(name, entries)| entries.iter().map(move |e| format!("{} {}", name, e.ip)))
+        .collect();
+    lines.extend(cache.wildcards.iter().map(|(pattern, e)| format!("{} {}", pattern, e.ip)));
+    lines.extend(cache.ptrs.iter().map(|p| format!("ptr {} {}", p.ip, p.name)));
+    lines.sort();
+    lines
+}
+
+#[test]
+fn crlf_and_bom_hosts_file_loads_like_lf() {
+    let empty = r#"{"Leases":[]}"#;
+    let lf = load("lf", include_str!("fixtures/hosts-lf"), empty);
+    let crlf = load("crlf", include_str!("fixtures/hosts-crlf-bom"), empty);
+
+    assert!(lf.exact_matches.contains_key(&parse_key("nas.lan.").unwrap()));
+    assert_eq!(records(&crlf), records(&lf));
+    assert!(crlf.failures.is_empty());
+}
+
+#[test]
+fn bom_before_lease_json_is_ignored() {
+    let leases = format!("\u{feff}{{\"Leases\":[{}]}}\r\n", lease("laptop", 7));
+    let cache = load("bom-leases", "", &leases);
+
+    assert!(cache.failures.is_empty());
+    assert!(cache.exact_matches.contains_key(&parse_key("laptop.lan.").unwrap()));
+}
+
+/// What devices have been seen to announce, and what each should become.
+const HOSTILE: &[(&str, Option<&str>)] = &[
+    ("  living-room-tv  ", Some("living-room-tv.lan.")),
+    ("Tom's iPad", Some("tom-s-ipad.lan.")),
+    ("_printer_", Some("printer.lan.")),
+    ("my__nas", Some("my-nas.lan.")),
+    ("-laptop-.", Some("laptop.lan.")),
+    ("kitchen..speaker", Some("kitchen.speaker.lan.")),
+    ("*", None),
+    ("!!!", None),
+    // Emoji have an IDNA form, so they stay
+    ("\u{1f4f1}\u{1f4a5}", Some("xn--hs8hxe.lan.")),
+    ("phone\u{1f4f1}", Some("xn--phone-nr14d.lan.")),
+    // A line separator has none, so it is replaced like a space
+    ("tv\u{2028}den", Some("tv-den.lan.")),
+    ("\u{0}null\u{7f}", Some("null.lan.")),
+];
+
+#[test]
+fn hostile_dhcp_hostnames_only_produce_valid_names() {
+    let mut leases: Vec<String> = HOSTILE.iter().enumerate().map(|(i, (hostname, _))| lease(hostname, 30 + i as u8)).collect();
+    let long = "a".repeat(64);
+    leases.push(lease(&long, 60));
+    leases.push(lease(&["abcdefghij"; 26].join("."), 61));
+    let cache = load("hostile", "", &format!(r#"{{"Leases":[{}]}}"#, leases.join(",")));
+
+    let mut names: Vec<String> = cache.exact_matches.keys().map(name_key).collect();
+    names.sort();
+    let mut expected: Vec<&str> = HOSTILE.iter().filter_map(|(_, name)| *name).collect();
+    expected.sort();
+    assert_eq!(names, expected);
+    // Every key is a plain hostname, or the IDNA form of one
+    for name in &names {
+        assert!(localdns::loader::check_hostname(name).is_ok(), "{}", name);
+        assert!(name.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'.'), "{}", name);
+    }
+    // The leases with nothing left, a 64-byte label or a name over 255 bytes are skipped
+    assert_eq!(cache.skipped.len(), 4, "{:?}", cache.skipped.iter().map(|s| s.to_string()).collect::<Vec<_>>());
+    assert!(cache.skipped.iter().any(|s| s.reason.contains("the limit is 63")));
+}
@@ -0,0 +1,87 @@
+use hickory_proto::op::{Message, OpCode, Query, ResponseCode};
+use hickory_proto::rr::{Name, RecordType};
+use localdns::config::Config;
+use localdns::loader::DnsCache;
+use localdns::server::{notimp_for_opcode, resolve, AnswerSource, ResolveOptions};
+use tokio::sync::RwLock;
+
+const CONFIG: &str = r#"
+listen_address = "127.0.0.1"
+listen_port = 10053
+dhcp_lease_file = "/nonexistent/leases"
+hosts_file = "/nonexistent/hosts"
+domain_suffix = "lan"
+fallback_ip = "10.9.9.9"
+nsid = ""
+"#;
+
+fn options() -> ResolveOptions {
+    let path = std::env::temp_dir().join(format!("localdns-opcode-{}.toml", std::process::id()));
+    std::fs::write(&path, CONFIG).unwrap();
+    let config = Config::load(&path).unwrap();
+    let _ = std::fs::remove_file(&path);
+    ResolveOptions::from_config(&config)
+}
+
+fn request(op_code: OpCode) -> Message {
+    let mut message = Message::new();
+    message.set_id(777).set_op_code(op_code);
+    // The fallback would give this an answer if it were looked up
+    message.add_query(Query::query(Name::from_ascii("laptop.lan.").unwrap(), RecordType::A));
+    message
+}
+
+#[tokio::test]
+async fn update_notify_and_status_get_notimp() {
+    let options = options();
+    let records = RwLock::new(DnsCache::default());
+
+    for op_code in [OpCode::Update, OpCode::Notify, OpCode::Status] {
+        let request = request(op_code);
+        let (response, source) = resolve(&request, None, &records, &options).await.unwrap();
+
+        assert_eq!(response.response_code(), ResponseCode::NotImp, "{:?}", op_code);
+        assert_eq!(source, AnswerSource::None);
+        assert_eq!(response.id(), 777);
+        assert_eq!(response.op_code(), op_code);
+        assert_eq!(response.queries(), request.queries());
+        assert!(response.answers().is_empty() && response.name_servers().is_empty(), "{:?}", op_code);
+    }
+}
+
+#[tokio::test]
+async fn queries_are_still_answered() {
+    let options = options();
+    let records = RwLock::new(DnsCache::default());
+
+    let (response, _) = resolve(&request(OpCode::Query), None, &records, &options).await.unwrap();
+
+    assert_eq!(response.response_code(), ResponseCode::NoError);
+    assert_eq!(response.answers().len(), 1);
+}
+
+#[test]
+fn iquery_gets_notimp_with_its_id_and_question() {
+    let mut iquery = request(OpCode::Query).to_vec().unwrap();
+    iquery[2] |= 1 << 3; // Opcode 1, which hickory can't represent
+    assert!(Message::from_vec(&iquery).is_err());
+
+    let response = notimp_for_opcode(&iquery).expect("a response to IQUERY");
+
+    assert_eq!(response[..2], 777u16.to_be_bytes());
+    assert_eq!(response[2] & 0x80, 0x80, "QR set");
+    assert_eq!((response[2] >> 3) & 0x0f, 1, "opcode echoed");
+    assert_eq!(response[3] & 0x0f, ResponseCode::NotImp.low());
+    assert_eq!(response[4..12], [0, 1, 0, 0, 0, 0, 0, 0], "one question, no records");
+    assert_eq!(response[12..], iquery[12..]);
+}
+
+#[test]
+fn responses_and_known_opcodes_are_left_alone() {
+    let mut response = request(OpCode::Query).to_vec().unwrap();
+    response[2] |= 0x80 | (1 << 3);
+
+    assert!(notimp_for_opcode(&response).is_none());
+    assert!(notimp_for_opcode(&request(OpCode::Update).to_vec().unwrap()).is_none());
+    assert!(notimp_for_opcode(&[0, 1]).is_none());
+}
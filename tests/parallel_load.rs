@@ -0,0 +1,136 @@
+use localdns::loader::{load_records, parse_key, LoadOptions, SourceKind};
+use std::fmt::Write;
+use std::fs;
+use std::net::IpAddr;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+/// A directory of its own for each test, removed when dropped.
+struct Scratch(PathBuf);
+
+impl Scratch {
+    fn new(test: &str) -> Self {
+        let dir = std::env::temp_dir().join(format!("localdns-{}-{}", test, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        Self(dir)
+    }
+
+    fn path(&self, name: &str) -> PathBuf {
+        self.0.join(name)
+    }
+
+    fn write(&self, name: &str, content: &str) -> PathBuf {
+        let path = self.path(name);
+        fs::write(&path, content).unwrap();
+        path
+    }
+}
+
+impl Drop for Scratch {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+/// Every log line of the test binary, `tracing` events included, as
+/// `<target> <message>`. Tests run in parallel, so each looks for lines about
+/// names of its own.
+struct Capture(Mutex<Vec<String>>);
+
+impl log::Log for Capture {
+    fn enabled(&self, _: &log::Metadata) -> bool {
+        true
+    }
+
+    fn log(&self, record: &log::Record) {
+        self.0.lock().unwrap().push(format!("{} {}", record.target(), record.args()));
+    }
+
+    fn flush(&self) {}
+}
+
+static CAPTURE: Capture = Capture(Mutex::new(Vec::new()));
+
+fn capture_logs() {
+    // Only the first call in the binary installs it
+    let _ = log::set_logger(&CAPTURE);
+    log::set_max_level(log::LevelFilter::Trace);
+}
+
+/// The captured lines containing `needle`.
+fn logged(needle: &str) -> Vec<String> {
+    CAPTURE.0.lock().unwrap().iter().filter(|line| line.contains(needle)).cloned().collect()
+}
+
+/// Hosts files that all list `shared.lan`, the first one large enough to
+/// finish parsing after the others.
+fn hosts_files(dir: &Scratch) -> Vec<PathBuf> {
+    let mut large = String::from("10.0.0.1 shared.lan\n");
+    for i in 0..20_000 {
+        writeln!(large, "10.1.{}.{} host-{}.lan", i / 250, i % 250 + 1, i).unwrap();
+    }
+    let mut paths = vec![dir.write("0-large", &large)];
+    for i in 1..6 {
+        paths.push(dir.write(&format!("{}-small", i), &format!("10.0.0.{} shared.lan\n", i + 1)));
+    }
+    paths
+}
+
+fn options(dir: &Scratch, hosts: &[PathBuf]) -> LoadOptions {
+    let dhcp_path = dir.write("leases.json", r#"{"Leases":[]}"#);
+    LoadOptions {
+        hosts_paths: hosts[1..].to_vec(),
+        ..LoadOptions::new(dhcp_path, hosts[0].clone(), "lan", dir.path("config.toml"))
+    }
+}
+
+fn shared(options: &LoadOptions) -> Vec<(IpAddr, usize)> {
+    let cache = load_records(options).unwrap();
+    // Source paths are compared by position, as each file has its own
+    let order: Vec<PathBuf> = std::iter::once(&options.hosts_path).chain(&options.hosts_paths).cloned().collect();
+    cache.exact_matches[&parse_key("shared.lan.").unwrap()]
+        .iter()
+        .map(|e| (e.ip, order.iter().position(|p| *p.as_path() == *e.source.path).unwrap()))
+        .collect()
+}
+
+#[test]
+fn merging_follows_the_configured_order_whichever_file_finishes_first() {
+    let dir = Scratch::new("parallel-order");
+    let options = options(&dir, &hosts_files(&dir));
+
+    let first = shared(&options);
+    let expected: Vec<(IpAddr, usize)> = (0..6).map(|i| (format!("10.0.0.{}", i + 1).parse().unwrap(), i)).collect();
+    assert_eq!(first, expected);
+    for _ in 0..3 {
+        assert_eq!(shared(&options), first);
+    }
+}
+
+#[test]
+fn a_failing_file_leaves_the_others_loaded() {
+    let dir = Scratch::new("parallel-isolation");
+    let mut hosts = hosts_files(&dir);
+    hosts[2] = dir.path("missing");
+    let cache = load_records(&options(&dir, &hosts)).unwrap();
+
+    assert_eq!(cache.failures.len(), 1);
+    assert_eq!((cache.failures[0].kind(), cache.failures[0].path()), (SourceKind::Hosts, hosts[2].as_path()));
+    assert_eq!(cache.exact_matches[&parse_key("shared.lan.").unwrap()].len(), 5);
+    assert!(cache.exact_matches.contains_key(&parse_key("host-19999.lan.").unwrap()));
+}
+
+#[test]
+fn each_source_and_the_whole_load_are_timed() {
+    capture_logs();
+    let dir = Scratch::new("parallel-timing");
+    let hosts = hosts_files(&dir);
+    load_records(&options(&dir, &hosts)).unwrap();
+
+    for path in &hosts {
+        let lines = logged(&format!("Parsed hosts {:?} in ", path));
+        assert_eq!(lines.len(), 1, "{:?}", path);
+    }
+    assert!(!logged("Loaded 7 sources in ").is_empty());
+}
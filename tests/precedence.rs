@@ -0,0 +1,104 @@
+use hickory_proto::op::{Message, Query, ResponseCode};
+use hickory_proto::rr::{Name, RData, RecordType};
+use localdns::config::Config;
+use localdns::loader::load_records;
+use localdns::server::{resolve, AnswerSource, ResolveOptions};
+use std::fs;
+use std::net::IpAddr;
+use tokio::sync::RwLock;
+
+const CONFIG: &str = r#"
+listen_address = "127.0.0.1"
+listen_port = 10053
+dhcp_lease_file = "/nonexistent/leases"
+hosts_file = "hosts"
+domain_suffix = "lan"
+nsid = ""
+"#;
+
+const HOSTS: &str = "\
+192.168.1.1 *.lan
+192.168.1.5 nas.lan
+192.168.1.6 nas.lan
+fd00::7 v6only.lan
+192.168.1.20 *.svc.lan
+192.168.1.21 *.svc.lan
+";
+
+/// The answer to each `(name, type)` after loading `HOSTS` with `settings`.
+async fn ask(test: &str, settings: &str, queries: &[(&str, RecordType)]) -> Vec<(ResponseCode, AnswerSource, Vec<IpAddr>)> {
+    let dir = std::env::temp_dir().join(format!("localdns-precedence-{}-{}", test, std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("hosts"), HOSTS).unwrap();
+    let path = dir.join("config.toml");
+    fs::write(&path, format!("{}{}", CONFIG, settings)).unwrap();
+    let mut config = Config::load(&path).unwrap();
+    config.hosts_file = dir.join("hosts");
+    let records = RwLock::new(load_records(&config.load_options(&path)).unwrap());
+    fs::remove_dir_all(&dir).unwrap();
+    let options = ResolveOptions::from_config(&config);
+
+    let mut answers = Vec::new();
+    for (name, qtype) in queries {
+        let mut request = Message::new();
+        request.add_query(Query::query(Name::from_ascii(name).unwrap(), *qtype));
+        let (response, source) = resolve(&request, None, &records, &options).await.unwrap();
+        let ips = response
+            .answers()
+            .iter()
+            .filter_map(|record| match record.data() {
+                Some(RData::A(a)) => Some(IpAddr::V4(a.0)),
+                Some(RData::AAAA(aaaa)) => Some(IpAddr::V6(aaaa.0)),
+                _ => None,
+            })
+            .collect();
+        answers.push((response.response_code(), source, ips));
+    }
+    answers
+}
+
+fn ips(texts: &[&str]) -> Vec<IpAddr> {
+    texts.iter().map(|text| text.parse().unwrap()).collect()
+}
+
+#[tokio::test]
+async fn exact_records_shadow_wildcards() {
+    let answers = ask("exact", "", &[("nas.lan.", RecordType::A), ("v6only.lan.", RecordType::A)]).await;
+
+    // Every exact address, and none of the catch-all's
+    assert_eq!(answers[0], (ResponseCode::NoError, AnswerSource::Exact, ips(&["192.168.1.5", "192.168.1.6"])));
+    // The name exists with only an IPv6 address, so A is NODATA
+    assert_eq!(answers[1], (ResponseCode::NoError, AnswerSource::None, vec![]));
+}
+
+#[tokio::test]
+async fn the_most_specific_wildcard_wins() {
+    let answers = ask("specific", "", &[
+        ("web.svc.lan.", RecordType::A),
+        ("a.b.svc.lan.", RecordType::A),
+        ("printer.lan.", RecordType::A),
+        ("svc.lan.", RecordType::A),
+    ])
+    .await;
+
+    assert_eq!(answers[0], (ResponseCode::NoError, AnswerSource::Wildcard, ips(&["192.168.1.20", "192.168.1.21"])));
+    assert_eq!(answers[1], (ResponseCode::NoError, AnswerSource::Wildcard, ips(&["192.168.1.20", "192.168.1.21"])));
+    assert_eq!(answers[2], (ResponseCode::NoError, AnswerSource::Wildcard, ips(&["192.168.1.1"])));
+    // *.svc.lan doesn't cover svc.lan itself, but *.lan does
+    assert_eq!(answers[3], (ResponseCode::NoError, AnswerSource::Wildcard, ips(&["192.168.1.1"])));
+}
+
+#[tokio::test]
+async fn wildcard_merge_answers_with_the_union() {
+    let answers = ask("merge", "wildcard_merge = true\n", &[
+        ("nas.lan.", RecordType::A),
+        ("web.svc.lan.", RecordType::A),
+    ])
+    .await;
+
+    assert_eq!(answers[0], (ResponseCode::NoError, AnswerSource::Exact, ips(&["192.168.1.1", "192.168.1.5", "192.168.1.6"])));
+    assert_eq!(
+        answers[1],
+        (ResponseCode::NoError, AnswerSource::Wildcard, ips(&["192.168.1.1", "192.168.1.20", "192.168.1.21"]))
+    );
+}
@@ -0,0 +1,115 @@
+use hickory_proto::op::{Message, Query, ResponseCode};
+use hickory_proto::rr::{Name, RData, RecordType};
+use localdns::config::Config;
+use localdns::loader::{load_records, DnsCache, SourceKind};
+use localdns::server::{resolve, ResolveOptions};
+use localdns::view::ViewConfig;
+use std::fs;
+use std::path::PathBuf;
+use tokio::sync::RwLock;
+
+const CONFIG: &str = r#"
+listen_address = "127.0.0.1"
+listen_port = 10053
+dhcp_lease_file = "leases.json"
+hosts_file = "hosts"
+domain_suffix = "lan"
+nsid = ""
+"#;
+
+const HOSTS: &str = "\
+192.168.1.5 zeta.lan alpha.lan
+192.168.1.5 middle.lan
+192.168.1.9 printer.lan
+ptr 192.168.1.9 printer-explicit.lan
+";
+
+const LEASES: &str = r#"{"Leases":[{"Address":[192,168,1,5],"Hostname":"laptop"}]}"#;
+
+fn scratch(test: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("localdns-ptr-{}-{}", test, std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// Loads `HOSTS` and `leases` with `settings` appended to the config.
+fn load(test: &str, leases: &str, settings: &str) -> (DnsCache, ResolveOptions) {
+    let dir = scratch(test);
+    fs::write(dir.join("hosts"), HOSTS).unwrap();
+    fs::write(dir.join("leases.json"), leases).unwrap();
+    let path = dir.join("config.toml");
+    fs::write(&path, format!("{}{}", CONFIG, settings)).unwrap();
+    let mut config = Config::load(&path).unwrap();
+    config.hosts_file = dir.join("hosts");
+    config.dhcp_lease_file = dir.join("leases.json");
+    let cache = load_records(&config.load_options(&path)).unwrap();
+    fs::remove_dir_all(&dir).unwrap();
+    (cache, ResolveOptions::from_config(&config))
+}
+
+async fn ptr(cache: DnsCache, options: &ResolveOptions, name: &str) -> (ResponseCode, Vec<String>) {
+    let mut request = Message::new();
+    request.add_query(Query::query(Name::from_ascii(name).unwrap(), RecordType::PTR));
+    let (response, _) = resolve(&request, None, &RwLock::new(cache), options).await.unwrap();
+    let names = response
+        .answers()
+        .iter()
+        .filter_map(|r| match r.data() {
+            Some(RData::PTR(ptr)) => Some(ptr.0.to_string()),
+            _ => None,
+        })
+        .collect();
+    (response.response_code(), names)
+}
+
+#[test]
+fn reverse_index_lists_leases_first_then_hosts_lines_in_order() {
+    let (cache, _) = load("index", LEASES, "");
+
+    let names = &cache.reverse[&"192.168.1.5".parse().unwrap()];
+    assert_eq!(names, &["laptop.lan.", "alpha.lan.", "zeta.lan.", "middle.lan."]);
+}
+
+#[tokio::test]
+async fn primary_name_by_default() {
+    let (cache, options) = load("primary", LEASES, "");
+    assert_eq!(ptr(cache, &options, "5.1.168.192.in-addr.arpa.").await, (ResponseCode::NoError, vec!["laptop.lan.".to_string()]));
+
+    // Without the lease, the first hosts line; names sharing it go alphabetically
+    let (cache, options) = load("hosts-only", r#"{"Leases":[]}"#, "");
+    assert_eq!(ptr(cache, &options, "5.1.168.192.in-addr.arpa.").await, (ResponseCode::NoError, vec!["alpha.lan.".to_string()]));
+}
+
+#[tokio::test]
+async fn all_names_behind_the_setting() {
+    let (cache, options) = load("all", LEASES, "ptr_names = \"all\"\n");
+    let (code, names) = ptr(cache, &options, "5.1.168.192.in-addr.arpa.").await;
+
+    assert_eq!(code, ResponseCode::NoError);
+    assert_eq!(names, ["laptop.lan.", "alpha.lan.", "zeta.lan.", "middle.lan."]);
+}
+
+#[tokio::test]
+async fn explicit_ptr_stays_primary() {
+    let (cache, options) = load("explicit", LEASES, "");
+
+    let (_, names) = ptr(cache, &options, "9.1.168.192.in-addr.arpa.").await;
+    assert_eq!(names, ["printer-explicit.lan."]);
+}
+
+#[tokio::test]
+async fn primary_is_picked_among_what_the_view_shows() {
+    let (cache, options) = load("view", LEASES, "");
+    let view = ViewConfig { sources: vec![SourceKind::Hosts], ..ViewConfig::default() };
+    let options = view.apply("hosts-only", &options);
+
+    let (_, names) = ptr(cache, &options, "5.1.168.192.in-addr.arpa.").await;
+    assert_eq!(names, ["alpha.lan."]);
+}
+
+#[tokio::test]
+async fn unknown_addresses_are_nxdomain() {
+    let (cache, options) = load("unknown", LEASES, "");
+
+    assert_eq!(ptr(cache, &options, "77.1.168.192.in-addr.arpa.").await, (ResponseCode::NXDomain, Vec::new()));
+}
@@ -0,0 +1,135 @@
+use hickory_proto::op::{Message, Query, ResponseCode};
+use hickory_proto::rr::{Name, RecordType};
+use localdns::anonymize::{AnonymizeMode, Anonymizer};
+use localdns::querylog::{QueryLog, QueryLogConfig};
+use localdns::server::AnswerSource;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Every log line of the test binary, `tracing` events included, as
+/// `<target> <message>`. Tests run in parallel, so each looks for lines about
+/// names of its own.
+struct Capture(Mutex<Vec<String>>);
+
+impl log::Log for Capture {
+    fn enabled(&self, _: &log::Metadata) -> bool {
+        true
+    }
+
+    fn log(&self, record: &log::Record) {
+        self.0.lock().unwrap().push(format!("{} {}", record.target(), record.args()));
+    }
+
+    fn flush(&self) {}
+}
+
+static CAPTURE: Capture = Capture(Mutex::new(Vec::new()));
+
+fn capture_logs() {
+    // Only the first call in the binary installs it
+    let _ = log::set_logger(&CAPTURE);
+    log::set_max_level(log::LevelFilter::Trace);
+}
+
+/// The captured lines containing `needle`.
+fn logged(needle: &str) -> Vec<String> {
+    CAPTURE.0.lock().unwrap().iter().filter(|line| line.contains(needle)).cloned().collect()
+}
+
+fn log(config: QueryLogConfig, mode: AnonymizeMode) -> QueryLog {
+    capture_logs();
+    QueryLog::new(&QueryLogConfig { enabled: true, ..config }, Arc::new(Anonymizer::new(mode, Duration::from_secs(3600))))
+}
+
+/// Logs an answer to a query for `name` from `client`.
+fn record(log: &QueryLog, client: &str, name: &str, rcode: ResponseCode) {
+    let mut request = Message::new();
+    request.add_query(Query::query(Name::from_ascii(name).unwrap(), RecordType::A));
+    let mut response = Message::new();
+    response.set_response_code(rcode);
+    let client: SocketAddr = client.parse().unwrap();
+    log.record("default", Some(client), &request, &response, AnswerSource::Exact, Duration::from_micros(40));
+}
+
+#[test]
+fn sampled_out_queries_are_skipped_unless_a_rule_forces_them() {
+    let config = QueryLogConfig {
+        sample_rate: 0.0,
+        always_log_suffixes: vec!["iot.lan".to_string()],
+        always_log_clients: vec!["10.9.0.0/16".parse().unwrap()],
+        ..QueryLogConfig::default()
+    };
+    let log = log(config, AnonymizeMode::Off);
+
+    record(&log, "192.168.1.20:5353", "sampled-out.lan.", ResponseCode::NoError);
+    record(&log, "192.168.1.20:5353", "cam.iot.lan.", ResponseCode::NoError);
+    record(&log, "10.9.0.4:5353", "forced-client.lan.", ResponseCode::NoError);
+    record(&log, "192.168.1.20:5353", "forced-error.lan.", ResponseCode::ServFail);
+
+    assert!(logged("name=sampled-out.lan.").is_empty());
+    for name in ["cam.iot.lan.", "forced-client.lan.", "forced-error.lan."] {
+        let lines = logged(&format!("name={} ", name));
+        assert_eq!(lines.len(), 1, "{}", name);
+        // Forced lines count once, whatever the rate
+        assert!(lines[0].starts_with("localdns::query ") && lines[0].contains("sample_rate=1"), "{}", lines[0]);
+    }
+    assert!(logged("name=forced-error.lan.")[0].contains("rcode=SERVFAIL"));
+}
+
+#[test]
+fn lines_carry_the_rate_which_can_change_while_serving() {
+    let log = log(QueryLogConfig { sample_rate: 0.0, always_log_errors: false, ..QueryLogConfig::default() }, AnonymizeMode::Off);
+    record(&log, "192.168.1.20:5353", "before.rate.lan.", ResponseCode::NoError);
+
+    log.set_sample_rate(1.0);
+    assert_eq!(log.sample_rate(), 1.0);
+    record(&log, "192.168.1.20:5353", "after.rate.lan.", ResponseCode::NoError);
+
+    assert!(logged("name=before.rate.lan.").is_empty());
+    let line = &logged("name=after.rate.lan.")[0];
+    assert!(line.contains("client=192.168.1.20:5353") && line.contains("sample_rate=1"), "{}", line);
+    // Out-of-range rates are clamped
+    log.set_sample_rate(7.0);
+    assert_eq!(log.sample_rate(), 1.0);
+}
+
+#[test]
+fn anonymized_clients_are_logged_without_address_or_port() {
+    let truncate = log(QueryLogConfig::default(), AnonymizeMode::Truncate);
+    record(&truncate, "192.168.1.77:5353", "truncated.anon.lan.", ResponseCode::NoError);
+    let line = &logged("name=truncated.anon.lan.")[0];
+    assert!(line.contains("client=192.168.1.0 "), "{}", line);
+
+    let dropped = log(QueryLogConfig::default(), AnonymizeMode::Drop);
+    record(&dropped, "192.168.1.77:5353", "dropped.anon.lan.", ResponseCode::NoError);
+    let line = &logged("name=dropped.anon.lan.")[0];
+    assert!(line.contains("client=- ") && !line.contains("192.168.1"), "{}", line);
+}
+
+#[test]
+fn truncation_keeps_the_network_part() {
+    let anonymizer = Anonymizer::new(AnonymizeMode::Truncate, Duration::from_secs(3600));
+    let ip = |s: &str| s.parse::<IpAddr>().unwrap();
+
+    assert_eq!(anonymizer.client(ip("10.1.2.3")).as_deref(), Some("10.1.2.0"));
+    assert_eq!(anonymizer.client(ip("2001:db8:1:2:3:4:5:6")).as_deref(), Some("2001:db8:1::"));
+    assert_eq!(Anonymizer::new(AnonymizeMode::Drop, Duration::from_secs(1)).client(ip("10.1.2.3")), None);
+    assert_eq!(Anonymizer::new(AnonymizeMode::Off, Duration::from_secs(1)).client(ip("10.1.2.3")).as_deref(), Some("10.1.2.3"));
+}
+
+#[test]
+fn hashes_tell_clients_apart_until_the_key_rotates() {
+    let ip = |s: &str| s.parse::<IpAddr>().unwrap();
+    let anonymizer = Anonymizer::new(AnonymizeMode::Hash, Duration::from_secs(3600));
+    let first = anonymizer.client(ip("192.168.1.77")).unwrap();
+
+    assert_eq!(first.len(), 16);
+    assert!(first.chars().all(|c| c.is_ascii_hexdigit()));
+    assert_eq!(anonymizer.client(ip("192.168.1.77")).unwrap(), first);
+    assert_ne!(anonymizer.client(ip("192.168.1.78")).unwrap(), first);
+
+    // A zero rotation picks a new key for every address, so nothing correlates
+    let rotating = Anonymizer::new(AnonymizeMode::Hash, Duration::ZERO);
+    assert_ne!(rotating.client(ip("192.168.1.77")), rotating.client(ip("192.168.1.77")));
+}
@@ -0,0 +1,134 @@
+use hickory_proto::op::{Message, MessageType, Query, ResponseCode};
+use hickory_proto::rr::{Name, RecordType};
+use localdns::config::Config;
+use localdns::loader::DnsCache;
+use localdns::server::{self, is_response, resolve, ResolveOptions};
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use tokio::net::UdpSocket;
+use tokio::sync::RwLock;
+use tokio::time::{timeout, Duration};
+
+const CONFIG: &str = r#"
+listen_address = "127.0.0.1"
+listen_port = 10053
+dhcp_lease_file = "/nonexistent/leases"
+hosts_file = "/nonexistent/hosts"
+domain_suffix = "lan"
+fallback_ip = "10.9.9.9"
+nsid = ""
+"#;
+
+fn options(test: &str, settings: &str) -> ResolveOptions {
+    let path = std::env::temp_dir().join(format!("localdns-questions-{}-{}.toml", test, std::process::id()));
+    std::fs::write(&path, format!("{}{}", CONFIG, settings)).unwrap();
+    let config = Config::load(&path).unwrap();
+    let _ = std::fs::remove_file(&path);
+    ResolveOptions::from_config(&config)
+}
+
+/// A query with one A question per name in `names`.
+fn request(names: &[&str]) -> Message {
+    let mut message = Message::new();
+    message.set_id(4242).set_recursion_desired(true);
+    for name in names {
+        message.add_query(Query::query(Name::from_ascii(name).unwrap(), RecordType::A));
+    }
+    message
+}
+
+fn request_one() -> Message {
+    request(&["laptop.lan."])
+}
+
+#[tokio::test]
+async fn no_question_gets_formerr() {
+    let options = options("none", "");
+    let records = RwLock::new(DnsCache::default());
+
+    let (response, _) = resolve(&request(&[]), None, &records, &options).await.unwrap();
+
+    assert_eq!(response.response_code(), ResponseCode::FormErr);
+    assert_eq!(response.id(), 4242);
+    assert_eq!(response.message_type(), MessageType::Response);
+    assert!(response.recursion_desired());
+    assert!(response.queries().is_empty() && response.answers().is_empty());
+}
+
+#[tokio::test]
+async fn several_questions_get_the_first_answered() {
+    let options = options("first", "");
+    let records = RwLock::new(DnsCache::default());
+    let request = request(&["laptop.lan.", "phone.lan."]);
+
+    let (response, _) = resolve(&request, None, &records, &options).await.unwrap();
+
+    assert_eq!(response.response_code(), ResponseCode::NoError);
+    assert_eq!(response.queries(), &request.queries()[..1]);
+    assert_eq!(response.answers().len(), 1);
+    assert_eq!(response.answers()[0].name(), &Name::from_ascii("laptop.lan.").unwrap());
+}
+
+#[tokio::test]
+async fn several_questions_can_be_refused_or_rejected() {
+    let records = RwLock::new(DnsCache::default());
+    let request = request(&["laptop.lan.", "phone.lan."]);
+
+    for (setting, code) in [("refused", ResponseCode::Refused), ("formerr", ResponseCode::FormErr)] {
+        let options = options(setting, &format!("multiple_questions = \"{}\"\n", setting));
+        let (response, _) = resolve(&request, None, &records, &options).await.unwrap();
+
+        assert_eq!(response.response_code(), code, "{}", setting);
+        assert!(response.answers().is_empty(), "{}", setting);
+    }
+    // A single question is answered as usual
+    let options = options("single", "multiple_questions = \"refused\"\n");
+    let (response, _) = resolve(&request_one(), None, &records, &options).await.unwrap();
+    assert_eq!(response.answers().len(), 1);
+}
+
+#[tokio::test]
+async fn responses_sent_to_the_listener_are_dropped() {
+    let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let address = socket.local_addr().unwrap();
+    tokio::spawn(server::serve(socket, Arc::new(RwLock::new(DnsCache::default())), options("qr", "")));
+    let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+
+    let mut spoofed = request_one();
+    spoofed.set_id(1).set_message_type(MessageType::Response);
+    let spoofed = spoofed.to_vec().unwrap();
+    assert!(is_response(&spoofed));
+    client.send_to(&spoofed, address).await.unwrap();
+    let mut query = request_one();
+    query.set_id(2);
+    client.send_to(&query.to_vec().unwrap(), address).await.unwrap();
+
+    // Only the real query is answered
+    let mut buf = [0u8; 512];
+    let len = timeout(Duration::from_secs(2), client.recv(&mut buf)).await.unwrap().unwrap();
+    assert_eq!(Message::from_vec(&buf[..len]).unwrap().id(), 2);
+    assert!(timeout(Duration::from_millis(200), client.recv(&mut buf)).await.is_err());
+}
+
+#[tokio::test]
+async fn unparsable_queries_are_counted_as_errors() {
+    let options = options("garbled", "");
+    let stats = options.stats.clone();
+    let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let address = socket.local_addr().unwrap();
+    tokio::spawn(server::serve(socket, Arc::new(RwLock::new(DnsCache::default())), options));
+    let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+
+    // A standard query's header promising a question that never comes
+    client.send_to(&[0, 1, 1, 0, 0, 1, 0, 0, 0, 0, 0, 0, 3], address).await.unwrap();
+    let mut query = request_one();
+    query.set_id(2);
+    client.send_to(&query.to_vec().unwrap(), address).await.unwrap();
+
+    let mut buf = [0u8; 512];
+    let len = timeout(Duration::from_secs(2), client.recv(&mut buf)).await.unwrap().unwrap();
+    assert_eq!(Message::from_vec(&buf[..len]).unwrap().id(), 2);
+    assert!(timeout(Duration::from_millis(200), client.recv(&mut buf)).await.is_err());
+    assert_eq!(stats.errors.load(Ordering::Relaxed), 1);
+    assert_eq!(stats.queries.load(Ordering::Relaxed), 1);
+}
@@ -0,0 +1,92 @@
+use hickory_proto::op::{Message, Query};
+use hickory_proto::rr::{Name, RecordType};
+use localdns::config::Config;
+use localdns::loader::DnsCache;
+use localdns::ratelimit::{RateLimitAction, RateLimiter, Verdict};
+use localdns::server::{self, ResolveOptions};
+use std::net::IpAddr;
+use std::sync::Arc;
+use tokio::net::UdpSocket;
+use tokio::sync::RwLock;
+use tokio::time::{timeout, Duration, Instant};
+
+const CONFIG: &str = r#"
+listen_address = "127.0.0.1"
+listen_port = 10053
+dhcp_lease_file = "/nonexistent/leases"
+hosts_file = "/nonexistent/hosts"
+domain_suffix = "lan"
+fallback_ip = "10.9.9.9"
+nsid = ""
+rate_limit_qps = 5
+rate_limit_burst = 3
+rate_limit_action = "slip"
+"#;
+
+fn ip(text: &str) -> IpAddr {
+    text.parse().unwrap()
+}
+
+#[test]
+fn a_hammering_client_is_limited_and_others_are_not() {
+    let limiter = RateLimiter::new(10, 20, RateLimitAction::Drop).unwrap();
+    let now = Instant::now();
+    let noisy = ip("192.168.1.50");
+
+    let verdicts: Vec<Verdict> = (0..500).map(|_| limiter.check_at(noisy, now)).collect();
+
+    assert_eq!(verdicts.iter().filter(|v| **v == Verdict::Allow).count(), 20);
+    assert_eq!(verdicts[20..], [Verdict::Drop; 480]);
+    // Another client still has its whole burst
+    let quiet = ip("192.168.1.51");
+    assert!((0..20).all(|_| limiter.check_at(quiet, now) == Verdict::Allow));
+    // Tokens come back at rate_limit_qps
+    let later = now + Duration::from_millis(500);
+    let refilled = (0..10).filter(|_| limiter.check_at(noisy, later) == Verdict::Allow).count();
+    assert_eq!(refilled, 5);
+}
+
+#[test]
+fn zero_qps_disables_limiting() {
+    assert!(RateLimiter::new(0, 100, RateLimitAction::Slip).is_none());
+}
+
+#[test]
+fn idle_clients_are_evicted() {
+    let limiter = RateLimiter::new(10, 10, RateLimitAction::Slip).unwrap();
+    let now = Instant::now();
+    for last in 1..=100u8 {
+        limiter.check_at(ip(&format!("10.0.0.{}", last)), now);
+    }
+    assert_eq!(limiter.tracked(), 100);
+
+    // Well after each bucket has refilled, the next check sweeps them out
+    assert_eq!(limiter.check_at(ip("10.0.1.1"), now + Duration::from_secs(120)), Verdict::Allow);
+    assert_eq!(limiter.tracked(), 1);
+}
+
+#[tokio::test]
+async fn slipped_queries_are_answered_truncated() {
+    let path = std::env::temp_dir().join(format!("localdns-ratelimit-{}.toml", std::process::id()));
+    std::fs::write(&path, CONFIG).unwrap();
+    let config = Config::load(&path).unwrap();
+    let _ = std::fs::remove_file(&path);
+    let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let address = socket.local_addr().unwrap();
+    tokio::spawn(server::serve(socket, Arc::new(RwLock::new(DnsCache::default())), ResolveOptions::from_config(&config)));
+
+    let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let mut query = Message::new();
+    query.add_query(Query::query(Name::from_ascii("device.lan.").unwrap(), RecordType::A));
+    let mut truncated = Vec::new();
+    for id in 0..6 {
+        query.set_id(id);
+        client.send_to(&query.to_vec().unwrap(), address).await.unwrap();
+        let mut buf = [0u8; 512];
+        let len = timeout(Duration::from_secs(2), client.recv(&mut buf)).await.unwrap().unwrap();
+        let response = Message::from_vec(&buf[..len]).unwrap();
+        truncated.push(response.truncated() && response.answers().is_empty());
+    }
+
+    assert_eq!(truncated, [false, false, false, true, true, true]);
+}
@@ -0,0 +1,100 @@
+use localdns::config::Config;
+use localdns::loader::{load_records, parse_key, SourceKind};
+use std::fs;
+use std::net::IpAddr;
+use std::path::PathBuf;
+
+const CONFIG: &str = r#"
+listen_address = "127.0.0.1"
+listen_port = 10053
+dhcp_lease_file = "leases.json"
+hosts_file = "hosts"
+domain_suffix = "lan"
+nsid = ""
+
+[[records]]
+name = "nas.lan"
+value = "192.168.1.5"
+
+[[records]]
+name = "nas.lan"
+type = "aaaa"
+value = "fd00::5"
+ttl = 30
+
+[[records]]
+name = "*.test.lan"
+type = "A"
+value = "192.168.1.50"
+"#;
+
+fn scratch(test: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("localdns-records-{}-{}", test, std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("leases.json"), r#"{"Leases":[]}"#).unwrap();
+    dir
+}
+
+fn config(dir: &std::path::Path, content: &str) -> Result<Config, String> {
+    let path = dir.join("config.toml");
+    fs::write(&path, content).unwrap();
+    let mut config = Config::load(&path).map_err(|e| e.to_string())?;
+    config.hosts_file = dir.join("hosts");
+    config.dhcp_lease_file = dir.join("leases.json");
+    Ok(config)
+}
+
+#[test]
+fn config_records_load_with_wildcards_and_ttls() {
+    let dir = scratch("load");
+    fs::write(dir.join("hosts"), "").unwrap();
+    let config = config(&dir, CONFIG).unwrap();
+    let cache = load_records(&config.load_options(&dir.join("config.toml"))).unwrap();
+    fs::remove_dir_all(&dir).unwrap();
+
+    let nas = &cache.exact_matches[&parse_key("nas.lan.").unwrap()];
+    let ips: Vec<IpAddr> = nas.iter().map(|e| e.ip).collect();
+    assert_eq!(ips, ["192.168.1.5".parse::<IpAddr>().unwrap(), "fd00::5".parse().unwrap()]);
+    assert_eq!(nas.iter().map(|e| e.ttl).collect::<Vec<_>>(), [None, Some(30)]);
+    assert!(nas.iter().all(|e| e.source.kind == SourceKind::Config));
+    assert_eq!(nas[1].source.to_string(), format!("config {}#2", dir.join("config.toml").display()));
+    assert_eq!(cache.wildcards[0].0, "*.test.lan.");
+}
+
+#[test]
+fn config_records_are_merged_again_on_every_load() {
+    let dir = scratch("reload");
+    let config = config(&dir, CONFIG).unwrap();
+    let options = config.load_options(&dir.join("config.toml"));
+
+    fs::write(dir.join("hosts"), "192.168.1.9 printer.lan\n").unwrap();
+    let first = load_records(&options).unwrap();
+    fs::write(dir.join("hosts"), "192.168.1.9 printer.lan\n192.168.1.5 nas.lan\n").unwrap();
+    let second = load_records(&options).unwrap();
+    fs::remove_dir_all(&dir).unwrap();
+
+    assert!(first.exact_matches.contains_key(&parse_key("nas.lan.").unwrap()));
+    // The hosts file now lists the same address, which merges without a conflict
+    assert_eq!(second.exact_matches[&parse_key("nas.lan.").unwrap()].len(), 2);
+    assert!(second.conflicts.is_empty());
+}
+
+#[test]
+fn invalid_records_fail_config_loading() {
+    let dir = scratch("invalid");
+    let entry = |name: &str, kind: &str, value: &str| {
+        format!("{}\n[[records]]\nname = {:?}\ntype = {:?}\nvalue = {:?}\n", CONFIG, name, kind, value)
+    };
+
+    let bad_ip = config(&dir, &entry("tv.lan", "A", "192.168.1.300")).unwrap_err();
+    let bad_name = config(&dir, &entry("tv..lan", "A", "192.168.1.30")).unwrap_err();
+    let wrong_family = config(&dir, &entry("tv.lan", "A", "fd00::30")).unwrap_err();
+    let bad_type = config(&dir, &entry("tv.lan", "NS", "ns.lan")).unwrap_err();
+    fs::remove_dir_all(&dir).unwrap();
+
+    assert!(bad_ip.contains("records") && bad_ip.contains("entry 4"), "{}", bad_ip);
+    assert!(bad_ip.contains("192.168.1.300"), "{}", bad_ip);
+    assert!(bad_name.contains("tv..lan"), "{}", bad_name);
+    assert!(wrong_family.contains("not an IPv4 address"), "{}", wrong_family);
+    assert!(bad_type.contains("unsupported record type"), "{}", bad_type);
+}
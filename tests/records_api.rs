@@ -0,0 +1,157 @@
+use hickory_proto::op::{Message, Query};
+use hickory_proto::rr::{Name, RData, RecordType};
+use localdns::config::Config;
+use localdns::events::Events;
+use localdns::http::{Request, Response};
+use localdns::json_api::JsonApi;
+use localdns::listeners::{self, Listeners};
+use localdns::loader::load_records;
+use localdns::server::{resolve, ResolveOptions};
+use localdns::watchdog::Health;
+use std::fs;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use tokio::sync::RwLock;
+
+const CONFIG: &str = r#"
+listen_address = "127.0.0.1"
+listen_port = 10053
+dhcp_lease_file = "/nonexistent/leases"
+hosts_file = "hosts"
+domain_suffix = "lan"
+nsid = ""
+cache_max_entries = 0
+
+[http]
+api_token = "s3cret"
+"#;
+
+fn api(test: &str, hosts: &str) -> JsonApi {
+    let dir = std::env::temp_dir().join(format!("localdns-records-api-{}-{}", test, std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("hosts"), hosts).unwrap();
+    let path = dir.join("config.toml");
+    fs::write(&path, CONFIG).unwrap();
+    let mut config = Config::load(&path).unwrap();
+    config.hosts_file = dir.join("hosts");
+    let load_options = config.load_options(&path);
+    let records = Arc::new(RwLock::new(load_records(&load_options).unwrap()));
+    fs::remove_dir_all(&dir).unwrap();
+    let options = ResolveOptions::from_config(&config);
+    JsonApi {
+        records: records.clone(),
+        options: Arc::new(options.clone()),
+        cors_allow_origin: None,
+        limits: config.limits.clone(),
+        limits_policy: config.limits_policy,
+        health: Arc::new(Health::new(&config.watchdog)),
+        last_reload: Arc::new(Mutex::new(None)),
+        events: Arc::new(Events::default()),
+        listeners: Arc::new(Listeners::new(records, options, &config)),
+        overlay: load_options.overlay.clone(),
+        api_token: config.http.api_token.clone(),
+        started_at: listeners::now() - 5,
+    }
+}
+
+async fn call(api: &JsonApi, method: &str, path: &str, token: Option<&str>, body: &str) -> (u16, serde_json::Value) {
+    let mut headers = Vec::new();
+    if let Some(token) = token {
+        headers.push(("authorization".to_string(), format!("Bearer {}", token)));
+    }
+    let request = Request {
+        method: method.to_string(),
+        path: path.to_string(),
+        query: Vec::new(),
+        headers,
+        body: body.as_bytes().to_vec(),
+    };
+    let Response { status, body, .. } = api.handle(request).await;
+    (status, serde_json::from_slice(&body).unwrap_or(serde_json::Value::Null))
+}
+
+/// The addresses a DNS query for `name` is answered with.
+async fn ask(api: &JsonApi, name: &str) -> Vec<IpAddr> {
+    let mut request = Message::new();
+    request.add_query(Query::query(Name::from_ascii(name).unwrap(), RecordType::A));
+    let (response, _) = resolve(&request, None, &api.records, &api.options).await.unwrap();
+    response
+        .answers()
+        .iter()
+        .filter_map(|record| match record.data() {
+            Some(RData::A(a)) => Some(IpAddr::V4(a.0)),
+            _ => None,
+        })
+        .collect()
+}
+
+#[tokio::test]
+async fn records_are_listed_with_their_source() {
+    let api = api("list", "192.168.1.5 nas.lan\n10.0.0.1 *.dev.lan\n");
+    let (status, body) = call(&api, "GET", "/records", None, "").await;
+
+    assert_eq!(status, 200);
+    let nas = &body["exact"]["nas.lan."][0];
+    assert_eq!((nas["address"].as_str(), nas["type"].as_str(), nas["source"].as_str()), (Some("192.168.1.5"), Some("A"), Some("hosts")));
+    assert!(nas["location"].as_str().unwrap().ends_with("hosts:1"), "{}", nas);
+    assert_eq!(body["wildcards"]["*.dev.lan."][0]["address"], "10.0.0.1");
+
+    assert_eq!(call(&api, "GET", "/records/nas.lan", None, "").await.1["entries"][0]["address"], "192.168.1.5");
+    assert_eq!(call(&api, "GET", "/records/nothing.lan", None, "").await.0, 404);
+    assert_eq!(call(&api, "POST", "/records", None, "").await.0, 405);
+}
+
+#[tokio::test]
+async fn put_and_delete_are_answered_by_the_next_query() {
+    let api = api("mutate", "192.168.1.5 nas.lan\n");
+
+    let (status, body) = call(&api, "PUT", "/records/vm1.lan", Some("s3cret"), r#"{"addresses": ["10.0.0.9", "fd00::9"], "ttl": 30}"#).await;
+    assert_eq!(status, 200, "{}", body);
+    assert_eq!(body["entries"].as_array().unwrap().len(), 2);
+    assert_eq!(body["entries"][0]["source"], "runtime");
+    assert_eq!(body["entries"][0]["ttl"], 30);
+    assert_eq!(ask(&api, "vm1.lan.").await, ["10.0.0.9".parse::<IpAddr>().unwrap()]);
+
+    // A PUT replaces what the name had, from any source
+    call(&api, "PUT", "/records/nas.lan", Some("s3cret"), r#"{"addresses": ["10.0.0.5"]}"#).await;
+    assert_eq!(ask(&api, "nas.lan.").await, ["10.0.0.5".parse::<IpAddr>().unwrap()]);
+
+    assert_eq!(call(&api, "DELETE", "/records/nas.lan", Some("s3cret"), "").await.0, 204);
+    assert!(ask(&api, "nas.lan.").await.is_empty());
+    assert_eq!(call(&api, "DELETE", "/records/nas.lan", Some("s3cret"), "").await.0, 404);
+
+    // Runtime changes are what the control socket would make
+    assert!(api.overlay.removed().contains("nas.lan."));
+}
+
+#[tokio::test]
+async fn mutations_need_the_bearer_token() {
+    let mut api = api("auth", "192.168.1.5 nas.lan\n");
+    let body = r#"{"addresses": ["10.0.0.9"]}"#;
+
+    assert_eq!(call(&api, "PUT", "/records/vm1.lan", None, body).await.0, 401);
+    assert_eq!(call(&api, "PUT", "/records/vm1.lan", Some("guess"), body).await.0, 401);
+    assert_eq!(call(&api, "DELETE", "/records/nas.lan", Some("s3cre"), "").await.0, 401);
+    assert!(ask(&api, "vm1.lan.").await.is_empty());
+    assert_eq!(ask(&api, "nas.lan.").await.len(), 1);
+
+    assert_eq!(call(&api, "PUT", "/records/vm1.lan", Some("s3cret"), r#"{"addresses": []}"#).await.0, 400);
+    assert_eq!(call(&api, "PUT", "/records/vm1.lan", Some("s3cret"), r#"{"addresses": ["10.0.0"]}"#).await.0, 400);
+    assert_eq!(call(&api, "PUT", "/records/bad_name!", Some("s3cret"), body).await.0, 400);
+
+    // Without a configured token nothing can be changed
+    api.api_token = None;
+    assert_eq!(call(&api, "PUT", "/records/vm1.lan", Some("s3cret"), body).await.0, 403);
+}
+
+#[tokio::test]
+async fn status_reports_uptime_and_the_last_reload() {
+    let api = api("status", "192.168.1.5 nas.lan\n");
+    let (status, body) = call(&api, "GET", "/status", None, "").await;
+
+    assert_eq!(status, 200);
+    assert!(body["uptime_secs"].as_u64().unwrap() >= 5, "{}", body);
+    assert_eq!(body["started_at"], api.started_at);
+    assert_eq!(body["last_reload_at"], serde_json::Value::Null);
+    assert_eq!(body["records"]["exact"], 1);
+}
@@ -0,0 +1,75 @@
+use hickory_proto::op::{Message, Query, ResponseCode};
+use hickory_proto::rr::{Name, RecordType};
+use localdns::config::Config;
+use localdns::loader::{parse_key, DnsCache, Entry, Source, SourceKind};
+use localdns::server::{resolve, ReloadedSettings, ResolveOptions};
+use std::net::Ipv4Addr;
+use std::path::Path;
+use std::sync::Arc;
+use tokio::sync::RwLock;
+
+const CONFIG: &str = r#"
+listen_address = "127.0.0.1"
+listen_port = 10053
+dhcp_lease_file = "/nonexistent/leases"
+hosts_file = "/nonexistent/hosts"
+domain_suffix = "lan"
+fallback_ip = "10.9.9.9"
+nsid = ""
+ttl = 60
+"#;
+
+fn config(test: &str, content: &str) -> Config {
+    let path = std::env::temp_dir().join(format!("localdns-reload-{}-{}.toml", test, std::process::id()));
+    std::fs::write(&path, content).unwrap();
+    let config = Config::load(&path).unwrap();
+    let _ = std::fs::remove_file(&path);
+    config
+}
+
+/// `nas.lan` from the hosts file, with the settings from `reloaded` if given.
+fn records(reloaded: Option<&Config>) -> RwLock<DnsCache> {
+    let mut cache = DnsCache::default();
+    let entry = Entry {
+        ip: Ipv4Addr::new(192, 168, 1, 5).into(),
+        source: Source { kind: SourceKind::Hosts, path: Arc::from(Path::new("/etc/hosts")), location: 1, by_line: true },
+        ttl: None,
+    };
+    cache.exact_matches.insert(parse_key("nas.lan.").unwrap(), vec![entry]);
+    cache.settings = reloaded.map(|config| Arc::new(ReloadedSettings::from_config(config)));
+    RwLock::new(cache)
+}
+
+async fn answer(records: &RwLock<DnsCache>, options: &ResolveOptions, name: &str, qtype: RecordType) -> (ResponseCode, Vec<u32>) {
+    let mut request = Message::new();
+    request.add_query(Query::query(Name::from_ascii(name).unwrap(), qtype));
+    let (response, _) = resolve(&request, None, records, options).await.unwrap();
+    (response.response_code(), response.answers().iter().map(|r| r.ttl()).collect())
+}
+
+#[tokio::test]
+async fn startup_settings_apply_until_a_reload() {
+    let options = ResolveOptions::from_config(&config("startup", CONFIG));
+
+    assert_eq!(answer(&records(None), &options, "nas.lan.", RecordType::A).await, (ResponseCode::NoError, vec![60]));
+}
+
+#[tokio::test]
+async fn reloaded_ttl_replaces_the_startup_one() {
+    let options = ResolveOptions::from_config(&config("ttl", CONFIG));
+    let reloaded = config("ttl-new", &CONFIG.replace("ttl = 60", "ttl = 5"));
+
+    assert_eq!(answer(&records(Some(&reloaded)), &options, "nas.lan.", RecordType::A).await, (ResponseCode::NoError, vec![5]));
+}
+
+#[tokio::test]
+async fn reloaded_suffix_moves_the_zone() {
+    let options = ResolveOptions::from_config(&config("suffix", CONFIG));
+    let reloaded = config("suffix-new", &CONFIG.replace(r#"domain_suffix = "lan""#, r#"domain_suffix = "home""#));
+    let records = records(Some(&reloaded));
+
+    assert_eq!(answer(&records, &options, "home.", RecordType::SOA).await.0, ResponseCode::NoError);
+    // The fallback now covers the new suffix only
+    assert_eq!(answer(&records, &options, "unknown.home.", RecordType::A).await.0, ResponseCode::NoError);
+    assert_eq!(answer(&records, &options, "unknown.lan.", RecordType::A).await.0, ResponseCode::NXDomain);
+}
@@ -0,0 +1,126 @@
+use hickory_proto::rr::Name;
+use localdns::loader::{load_records, name_key, parse_key, DnsCache, LoadOptions, ReservationPrecedence, SourceKind};
+use localdns::reservations::{parse, Reservation};
+use std::fs;
+use std::net::{IpAddr, Ipv4Addr};
+
+/// Loads `reservations` alongside `leases` from a scratch directory unique to `test`.
+fn load(test: &str, reservations: &str, leases: &str, precedence: ReservationPrecedence) -> DnsCache {
+    let dir = std::env::temp_dir().join(format!("localdns-res-{}-{}", test, std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("leases.json"), leases).unwrap();
+    fs::write(dir.join("hosts"), "").unwrap();
+    fs::write(dir.join("reservations"), reservations).unwrap();
+    let options = LoadOptions {
+        reservations_path: Some(dir.join("reservations")),
+        reservation_precedence: precedence,
+        ..LoadOptions::new(dir.join("leases.json"), dir.join("hosts"), "lan", dir.join("config.toml"))
+    };
+    let cache = load_records(&options).unwrap();
+    fs::remove_dir_all(&dir).unwrap();
+    cache
+}
+
+fn addresses(cache: &DnsCache, name: &str) -> Vec<(IpAddr, SourceKind)> {
+    let key = parse_key(name).unwrap();
+    cache.exact_matches[&key].iter().map(|e| (e.ip, e.source.kind)).collect()
+}
+
+fn wildcard_kinds(cache: &DnsCache, pattern: &str) -> Vec<SourceKind> {
+    let key = name_key(&Name::from_ascii(pattern).unwrap());
+    cache.wildcards.iter().filter(|(p, _)| *p == key).map(|(_, e)| e.source.kind).collect()
+}
+
+fn reservation(location: usize, hostname: &str, ip: [u8; 4]) -> Reservation {
+    Reservation { location, hostname: hostname.to_string(), ip: Ipv4Addr::from(ip) }
+}
+
+#[test]
+fn kea_config_reservations_from_global_and_subnets() {
+    let json = r#"{"Dhcp4": {
+        "reservations": [{"hw-address": "aa:bb:cc:dd:ee:01", "ip-address": "10.0.0.10", "hostname": "nas"}],
+        "subnet4": [{"subnet": "10.0.1.0/24", "reservations": [
+            {"hw-address": "aa:bb:cc:dd:ee:02", "hostname": "no-address"},
+            {"hw-address": "aa:bb:cc:dd:ee:03", "ip-address": "10.0.1.20", "hostname": "printer"}
+        ]}]
+    }}"#;
+
+    assert_eq!(
+        parse(json).unwrap(),
+        vec![reservation(1, "nas", [10, 0, 0, 10]), reservation(3, "printer", [10, 0, 1, 20])]
+    );
+}
+
+#[test]
+fn kea_bare_reservations_array() {
+    let json = r#"[{"ip-address": "10.0.0.11", "hostname": "tv"}]"#;
+
+    assert_eq!(parse(json).unwrap(), vec![reservation(1, "tv", [10, 0, 0, 11])]);
+}
+
+#[test]
+fn dnsmasq_dhcp_host_fields_in_any_order() {
+    let conf = "\
+# Static leases
+domain=lan
+dhcp-host=aa:bb:cc:dd:ee:01,10.0.0.10,nas,infinite
+dhcp-host=id:01:aa:bb:cc:dd:ee:02,set:trusted,printer,10.0.0.20,12h
+dhcp-host=aa:bb:cc:dd:ee:03,ignore
+aa:bb:cc:dd:ee:04,10.0.0.40,[fd00::40],kiosk
+";
+
+    assert_eq!(
+        parse(conf).unwrap(),
+        vec![
+            reservation(3, "nas", [10, 0, 0, 10]),
+            reservation(4, "printer", [10, 0, 0, 20]),
+            reservation(6, "kiosk", [10, 0, 0, 40]),
+        ]
+    );
+}
+
+#[test]
+fn reservations_register_exact_and_wildcard_names() {
+    let cache = load("names", "dhcp-host=aa:bb:cc:dd:ee:01,10.0.0.10,nas\n", r#"{"Leases":[]}"#, ReservationPrecedence::Lease);
+
+    assert_eq!(addresses(&cache, "nas.lan."), vec![(Ipv4Addr::new(10, 0, 0, 10).into(), SourceKind::Reservation)]);
+    assert_eq!(wildcard_kinds(&cache, "*.nas.lan."), vec![SourceKind::Reservation]);
+    assert!(cache.conflicts.is_empty());
+}
+
+#[test]
+fn dnsmasq_entries_are_named_by_line_and_kea_ones_by_position() {
+    let dnsmasq = load("dnsmasq-source", "# Static hosts\ndhcp-host=aa:bb:cc:dd:ee:01,10.0.0.10,nas\n", r#"{"Leases":[]}"#, ReservationPrecedence::Lease);
+    let kea = load("kea-source", r#"[{"ip-address": "10.0.0.10", "hostname": "nas"}]"#, r#"{"Leases":[]}"#, ReservationPrecedence::Lease);
+
+    let source = |cache: &DnsCache| cache.exact_matches[&parse_key("nas.lan.").unwrap()][0].source.to_string();
+    assert!(source(&dnsmasq).ends_with("/reservations:2"), "{}", source(&dnsmasq));
+    assert!(source(&kea).ends_with("/reservations#1"), "{}", source(&kea));
+}
+
+#[test]
+fn live_lease_wins_by_default() {
+    let leases = r#"{"Leases":[{"Address":[10,0,0,99],"Hostname":"nas"}]}"#;
+    let cache = load("lease", "dhcp-host=10.0.0.10,nas\n", leases, ReservationPrecedence::Lease);
+
+    assert_eq!(addresses(&cache, "nas.lan."), vec![(Ipv4Addr::new(10, 0, 0, 99).into(), SourceKind::Dhcp)]);
+    assert_eq!(wildcard_kinds(&cache, "*.nas.lan."), vec![SourceKind::Dhcp]);
+    assert!(cache.conflicts.is_empty());
+}
+
+#[test]
+fn reservation_precedence_prefers_the_reservation() {
+    let leases = r#"{"Leases":[{"Address":[10,0,0,99],"Hostname":"nas"}]}"#;
+    let cache = load("reserved", "dhcp-host=10.0.0.10,nas\n", leases, ReservationPrecedence::Reservation);
+
+    assert_eq!(addresses(&cache, "nas.lan."), vec![(Ipv4Addr::new(10, 0, 0, 10).into(), SourceKind::Reservation)]);
+    assert_eq!(wildcard_kinds(&cache, "*.nas.lan."), vec![SourceKind::Reservation]);
+}
+
+#[test]
+fn invalid_kea_json_is_a_source_failure() {
+    let cache = load("invalid", "{\"Dhcp4\": ", r#"{"Leases":[]}"#, ReservationPrecedence::Lease);
+
+    assert_eq!(cache.failures.len(), 1);
+    assert_eq!(cache.failures[0].kind(), SourceKind::Reservation);
+}
@@ -0,0 +1,188 @@
+use hickory_proto::op::{Message, MessageType, Query, ResponseCode};
+use hickory_proto::rr::{Name, RData, RecordType};
+use localdns::config::Config;
+use localdns::loader::{parse_key, DnsCache, Entry, MxRecord, Source, SourceKind};
+use localdns::server::{answer, AnswerSource, ResolveOptions};
+use localdns::synthetic;
+use std::net::IpAddr;
+use std::path::Path;
+use std::sync::Arc;
+
+const CONFIG: &str = r#"
+listen_address = "127.0.0.1"
+listen_port = 10053
+dhcp_lease_file = "/nonexistent/leases"
+hosts_file = "/nonexistent/hosts"
+domain_suffix = "lan"
+nsid = ""
+ttl = 60
+"#;
+
+fn options() -> ResolveOptions {
+    options_with("default", "")
+}
+
+fn options_with(test: &str, settings: &str) -> ResolveOptions {
+    let path = std::env::temp_dir().join(format!("localdns-resolver-{}-{}.toml", test, std::process::id()));
+    std::fs::write(&path, format!("{}{}", CONFIG, settings)).unwrap();
+    let config = Config::load(&path).unwrap();
+    let _ = std::fs::remove_file(&path);
+    ResolveOptions::from_config(&config)
+}
+
+fn entry(ip: &str) -> Entry {
+    let source = Source { kind: SourceKind::Hosts, path: Arc::from(Path::new("hosts")), location: 1, by_line: true };
+    Entry { ip: ip.parse().unwrap(), source, ttl: None }
+}
+
+/// Records built by hand, as no source file would list them: out of order and with a duplicate.
+fn cache() -> DnsCache {
+    let mut cache = DnsCache::default();
+    cache.exact_matches.insert(parse_key("nas.lan.").unwrap(), vec![entry("192.168.1.5"), entry("fd00::5")]);
+    let web = ["192.168.1.12", "192.168.1.10", "192.168.1.11", "192.168.1.10"];
+    cache.exact_matches.insert(parse_key("web.lan.").unwrap(), web.into_iter().map(entry).collect());
+    cache.wildcards.push(("*.dev.lan.".to_string(), entry("10.0.0.1")));
+    cache.wildcards.push(("*.b.dev.lan.".to_string(), entry("10.0.0.2")));
+    cache.index_wildcards();
+    let mx = MxRecord { preference: 10, exchange: "nas.lan.".to_string(), source: entry("192.168.1.5").source, ttl: None };
+    cache.mxs.insert(parse_key("mail.lan.").unwrap(), vec![mx]);
+    cache
+}
+
+fn query(name: &str, qtype: RecordType) -> Message {
+    let mut request = Message::new();
+    request.set_id(7);
+    request.add_query(Query::query(Name::from_ascii(name).unwrap(), qtype));
+    request
+}
+
+fn addresses(response: &Message) -> Vec<IpAddr> {
+    response
+        .answers()
+        .iter()
+        .filter_map(|record| match record.data() {
+            Some(RData::A(a)) => Some(IpAddr::V4(a.0)),
+            Some(RData::AAAA(aaaa)) => Some(IpAddr::V6(aaaa.0)),
+            _ => None,
+        })
+        .collect()
+}
+
+#[test]
+fn exact_names_answer_with_their_own_family() {
+    let (response, source) = answer(&query("NAS.lan.", RecordType::A), &cache(), &options()).unwrap();
+
+    assert_eq!((response.id(), response.message_type()), (7, MessageType::Response));
+    assert_eq!(response.response_code(), ResponseCode::NoError);
+    assert_eq!(source, AnswerSource::Exact);
+    assert_eq!(addresses(&response), ["192.168.1.5".parse::<IpAddr>().unwrap()]);
+    // The owner keeps the case the client asked with
+    assert_eq!(response.answers()[0].name().to_string(), "NAS.lan.");
+    assert_eq!(response.answers()[0].ttl(), 60);
+
+    let (response, _) = answer(&query("nas.lan.", RecordType::AAAA), &cache(), &options()).unwrap();
+    assert_eq!(addresses(&response), ["fd00::5".parse::<IpAddr>().unwrap()]);
+}
+
+#[test]
+fn the_most_specific_wildcard_answers() {
+    let (response, source) = answer(&query("a.b.dev.lan.", RecordType::A), &cache(), &options()).unwrap();
+    assert_eq!(source, AnswerSource::Wildcard);
+    assert_eq!(addresses(&response), ["10.0.0.2".parse::<IpAddr>().unwrap()]);
+
+    let (response, _) = answer(&query("x.dev.lan.", RecordType::A), &cache(), &options()).unwrap();
+    assert_eq!(addresses(&response), ["10.0.0.1".parse::<IpAddr>().unwrap()]);
+
+    // The parent of a wildcard isn't covered by it
+    let (response, _) = answer(&query("dev.lan.", RecordType::A), &cache(), &options()).unwrap();
+    assert_eq!(response.response_code(), ResponseCode::NXDomain);
+}
+
+#[test]
+fn other_types_get_nodata_for_known_names_and_nxdomain_otherwise() {
+    for name in ["nas.lan.", "x.dev.lan.", "mail.lan."] {
+        let (response, _) = answer(&query(name, RecordType::CAA), &cache(), &options()).unwrap();
+        assert_eq!(response.response_code(), ResponseCode::NoError, "{}", name);
+        assert!(response.answers().is_empty(), "{}", name);
+        // NODATA in our zone carries the SOA for negative caching
+        assert_eq!(response.name_servers()[0].record_type(), RecordType::SOA, "{}", name);
+    }
+    let (response, source) = answer(&query("missing.lan.", RecordType::CAA), &cache(), &options()).unwrap();
+    assert_eq!(response.response_code(), ResponseCode::NXDomain);
+    assert_eq!(source, AnswerSource::None);
+
+    let (response, _) = answer(&query("mail.lan.", RecordType::MX), &cache(), &options()).unwrap();
+    assert!(matches!(response.answers()[0].data(), Some(RData::MX(mx)) if mx.preference() == 10));
+    // With the exchange's addresses alongside
+    assert_eq!(response.additionals().len(), 2);
+}
+
+#[test]
+fn a_request_without_a_question_is_formerr() {
+    let mut request = Message::new();
+    request.set_id(8);
+
+    let (response, source) = answer(&request, &cache(), &options()).unwrap();
+
+    assert_eq!(response.response_code(), ResponseCode::FormErr);
+    assert_eq!(response.id(), 8);
+    assert_eq!(source, AnswerSource::None);
+}
+
+#[test]
+fn several_addresses_are_deduplicated_and_sorted() {
+    let (response, _) = answer(&query("web.lan.", RecordType::A), &cache(), &options()).unwrap();
+
+    let expected: Vec<IpAddr> = ["192.168.1.10", "192.168.1.11", "192.168.1.12"].iter().map(|ip| ip.parse().unwrap()).collect();
+    assert_eq!(addresses(&response), expected);
+    // The same every time, without rotate_answers
+    let (again, _) = answer(&query("web.lan.", RecordType::A), &cache(), &options()).unwrap();
+    assert_eq!(addresses(&again), expected);
+}
+
+#[test]
+fn synthetic_records_answer_the_names_benchmarks_ask_for() {
+    let records = synthetic::cache(100, 10);
+    assert_eq!((records.exact_matches.len(), records.wildcards.len()), (100, 10));
+
+    let (response, source) = answer(&query(&synthetic::exact_name(50), RecordType::A), &records, &options()).unwrap();
+    assert_eq!((source, addresses(&response).len()), (AnswerSource::Exact, 1));
+    let (response, source) = answer(&query(&synthetic::wildcard_name(5), RecordType::A), &records, &options()).unwrap();
+    assert_eq!((source, addresses(&response).len()), (AnswerSource::Wildcard, 1));
+    let (response, _) = answer(&query(&synthetic::missing_name(), RecordType::A), &records, &options()).unwrap();
+    assert_eq!(response.response_code(), ResponseCode::NXDomain);
+}
+
+#[test]
+fn negative_response_picks_nxdomain_or_nodata_for_missing_names_in_our_zone() {
+    let nodata = options_with("nodata", "negative_response = \"nodata\"\n");
+    for qtype in [RecordType::A, RecordType::AAAA, RecordType::TXT] {
+        let (response, source) = answer(&query("missing.lan.", qtype), &cache(), &options()).unwrap();
+        assert_eq!((response.response_code(), source), (ResponseCode::NXDomain, AnswerSource::None), "{}", qtype);
+
+        let (response, source) = answer(&query("missing.lan.", qtype), &cache(), &nodata).unwrap();
+        assert_eq!((response.response_code(), source), (ResponseCode::NoError, AnswerSource::None), "{}", qtype);
+        assert!(response.answers().is_empty(), "{}", qtype);
+        assert_eq!(response.name_servers()[0].record_type(), RecordType::SOA, "{}", qtype);
+
+        // Outside the suffix, missing names are still NXDOMAIN, to be forwarded
+        let (response, _) = answer(&query("missing.example.", qtype), &cache(), &nodata).unwrap();
+        assert_eq!(response.response_code(), ResponseCode::NXDomain, "{}", qtype);
+    }
+    // Names covered by a wildcard or with records are unchanged either way
+    let (response, _) = answer(&query("x.dev.lan.", RecordType::TXT), &cache(), &nodata).unwrap();
+    assert_eq!(response.response_code(), ResponseCode::NoError);
+    let (response, _) = answer(&query("nas.lan.", RecordType::A), &cache(), &nodata).unwrap();
+    assert_eq!(response.answers().len(), 1);
+}
+
+#[test]
+fn names_in_a_forward_zone_under_the_suffix_stay_nxdomain_with_nodata() {
+    let settings = "negative_response = \"nodata\"\n\n[forward_zones]\n\"lab.lan\" = [\"192.168.1.1\"]\n";
+    let options = options_with("nodata-forward", settings);
+
+    let (response, _) = answer(&query("box.lab.lan.", RecordType::A), &cache(), &options).unwrap();
+    assert_eq!(response.response_code(), ResponseCode::NXDomain);
+    let (response, _) = answer(&query("box.lan.", RecordType::A), &cache(), &options).unwrap();
+    assert_eq!(response.response_code(), ResponseCode::NoError);
+}
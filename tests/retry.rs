@@ -0,0 +1,138 @@
+use localdns::loader::{load_records, read_with_retry, LoadError, LoadOptions, RetryPolicy};
+use std::fs;
+use std::io::{Error, ErrorKind};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// A directory of its own for each test, removed when dropped.
+struct Scratch(PathBuf);
+
+impl Scratch {
+    fn new(test: &str) -> Self {
+        let dir = std::env::temp_dir().join(format!("localdns-{}-{}", test, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        Self(dir)
+    }
+
+    fn path(&self, name: &str) -> PathBuf {
+        self.0.join(name)
+    }
+
+    fn write(&self, name: &str, content: &str) -> PathBuf {
+        let path = self.path(name);
+        fs::write(&path, content).unwrap();
+        path
+    }
+}
+
+impl Drop for Scratch {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+/// Every log line of the test binary, `tracing` events included, as
+/// `<target> <message>`. Tests run in parallel, so each looks for lines about
+/// names of its own.
+struct Capture(Mutex<Vec<String>>);
+
+impl log::Log for Capture {
+    fn enabled(&self, _: &log::Metadata) -> bool {
+        true
+    }
+
+    fn log(&self, record: &log::Record) {
+        self.0.lock().unwrap().push(format!("{} {}", record.target(), record.args()));
+    }
+
+    fn flush(&self) {}
+}
+
+static CAPTURE: Capture = Capture(Mutex::new(Vec::new()));
+
+fn capture_logs() {
+    // Only the first call in the binary installs it
+    let _ = log::set_logger(&CAPTURE);
+    log::set_max_level(log::LevelFilter::Trace);
+}
+
+/// The captured lines containing `needle`.
+fn logged(needle: &str) -> Vec<String> {
+    CAPTURE.0.lock().unwrap().iter().filter(|line| line.contains(needle)).cloned().collect()
+}
+
+/// Reads like an NFS mount after a failover, answering ESTALE until the file is back.
+fn stale_until_present(path: &Path) -> std::io::Result<String> {
+    std::fs::read_to_string(path).map_err(|e| match e.kind() {
+        ErrorKind::NotFound => Error::from(ErrorKind::StaleNetworkFileHandle),
+        _ => e,
+    })
+}
+
+static TIMEOUTS: AtomicU32 = AtomicU32::new(0);
+
+/// A mount that never answers in time.
+fn always_timing_out(_: &Path) -> std::io::Result<String> {
+    TIMEOUTS.fetch_add(1, Ordering::Relaxed);
+    Err(Error::from(ErrorKind::TimedOut))
+}
+
+#[test]
+fn a_file_that_comes_back_mid_retry_is_read() {
+    capture_logs();
+    let dir = Scratch::new("retry-appears");
+    let path = dir.path("leases.json");
+    let retry = RetryPolicy { attempts: 5, backoff: Duration::from_millis(50), read: stale_until_present };
+
+    let writer = {
+        let path = path.clone();
+        std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(120));
+            // Written in place of the missing one, with a BOM as Notepad would
+            std::fs::write(path, "\u{feff}{\"Leases\":[]}").unwrap();
+        })
+    };
+    let started = Instant::now();
+    let content = read_with_retry(&path, retry).unwrap();
+    writer.join().unwrap();
+
+    assert_eq!(content, "{\"Leases\":[]}");
+    // At least the first two waits of 50ms and 100ms
+    assert!(started.elapsed() >= Duration::from_millis(150));
+    let warnings = logged(&format!("Transient error reading {:?} (attempt 1/5)", path));
+    assert_eq!(warnings.len(), 1, "transient failures are logged as such");
+}
+
+#[test]
+fn errors_that_are_not_transient_are_not_retried() {
+    let dir = Scratch::new("retry-permanent");
+    let retry = RetryPolicy { attempts: 5, backoff: Duration::from_secs(10), ..RetryPolicy::default() };
+
+    let started = Instant::now();
+    let error = read_with_retry(&dir.path("missing"), retry).unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::NotFound);
+    assert!(started.elapsed() < Duration::from_secs(1));
+}
+
+#[test]
+fn a_source_still_failing_after_the_last_attempt_is_a_load_error() {
+    let dir = Scratch::new("retry-exhausted");
+    let hosts_path = dir.write("hosts", "192.168.1.5 nas.lan\n");
+    let options = LoadOptions {
+        retry: RetryPolicy { attempts: 3, backoff: Duration::from_millis(10), read: always_timing_out },
+        ..LoadOptions::new(dir.path("leases.json"), hosts_path.clone(), "lan", dir.path("config.toml"))
+    };
+    let cache = load_records(&options).unwrap();
+
+    // Only the hosts file is there to be read, so only it was tried
+    assert_eq!(TIMEOUTS.load(Ordering::Relaxed), 3);
+    let hosts = cache.failures.iter().find_map(|e| match e {
+        LoadError::Hosts { path, source } if *path == hosts_path => Some(source.kind()),
+        _ => None,
+    });
+    assert_eq!(hosts, Some(ErrorKind::TimedOut), "{:?}", cache.failures);
+    assert!(cache.exact_matches.is_empty());
+}
@@ -0,0 +1,106 @@
+use hickory_proto::op::{Message, Query};
+use hickory_proto::rr::{Name, RData, RecordType};
+use localdns::config::Config;
+use localdns::loader::load_records;
+use localdns::server::{resolve, ResolveOptions};
+use std::fs;
+use std::net::Ipv4Addr;
+use tokio::sync::RwLock;
+
+const CONFIG: &str = r#"
+listen_address = "127.0.0.1"
+listen_port = 10053
+dhcp_lease_file = "/nonexistent/leases"
+hosts_file = "hosts"
+domain_suffix = "lan"
+nsid = ""
+"#;
+
+const HOSTS: &str = "\
+192.168.1.12 web.lan
+192.168.1.10 web.lan
+192.168.1.11 web.lan
+192.168.1.5 nas.lan
+";
+
+/// The A records of each of `count` answers for `name`, with `settings` added to the config.
+async fn answers(test: &str, settings: &str, name: &str, count: usize) -> Vec<Vec<Ipv4Addr>> {
+    let dir = std::env::temp_dir().join(format!("localdns-rotation-{}-{}", test, std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("hosts"), HOSTS).unwrap();
+    let path = dir.join("config.toml");
+    fs::write(&path, format!("{}{}", CONFIG, settings)).unwrap();
+    let mut config = Config::load(&path).unwrap();
+    config.hosts_file = dir.join("hosts");
+    let records = RwLock::new(load_records(&config.load_options(&path)).unwrap());
+    fs::remove_dir_all(&dir).unwrap();
+    let options = ResolveOptions::from_config(&config);
+
+    let mut request = Message::new();
+    request.add_query(Query::query(Name::from_ascii(name).unwrap(), RecordType::A));
+    let mut answers = Vec::new();
+    for _ in 0..count {
+        let (response, _) = resolve(&request, None, &records, &options).await.unwrap();
+        let addresses = response.answers().iter().map(|record| match record.data() {
+            Some(RData::A(a)) => a.0,
+            other => panic!("expected an A record, got {:?}", other),
+        });
+        answers.push(addresses.collect());
+    }
+    answers
+}
+
+/// The first A record of each of `count` answers, with how many records the answer had.
+async fn first_answers(test: &str, settings: &str, name: &str, count: usize) -> Vec<(Ipv4Addr, usize)> {
+    answers(test, settings, name, count).await.into_iter().map(|answer| (answer[0], answer.len())).collect()
+}
+
+fn ip(last: u8) -> Ipv4Addr {
+    Ipv4Addr::new(192, 168, 1, last)
+}
+
+#[tokio::test]
+async fn rotated_answers_cycle_through_every_address() {
+    let firsts = first_answers("on", "rotate_answers = true\n", "web.lan.", 6).await;
+
+    assert_eq!(firsts, [(ip(10), 3), (ip(11), 3), (ip(12), 3), (ip(10), 3), (ip(11), 3), (ip(12), 3)]);
+}
+
+#[tokio::test]
+async fn answers_stay_sorted_by_default() {
+    let firsts = first_answers("off", "", "web.lan.", 3).await;
+
+    assert_eq!(firsts, [(ip(10), 3); 3]);
+}
+
+#[tokio::test]
+async fn single_addresses_are_unaffected() {
+    let firsts = first_answers("single", "rotate_answers = true\n", "nas.lan.", 2).await;
+
+    assert_eq!(firsts, [(ip(5), 1); 2]);
+}
+
+#[tokio::test]
+async fn capped_rotated_answers_walk_through_every_address() {
+    let answers = answers("cap-rotate", "rotate_answers = true\nmax_answers = 2\n", "web.lan.", 3).await;
+
+    assert_eq!(answers, [vec![ip(10), ip(11)], vec![ip(11), ip(12)], vec![ip(12), ip(10)]]);
+}
+
+#[tokio::test]
+async fn capped_answers_pick_different_addresses_over_time() {
+    let answers = answers("cap-random", "max_answers = 2\n", "web.lan.", 60).await;
+
+    let mut seen: Vec<Ipv4Addr> = Vec::new();
+    for answer in &answers {
+        assert_eq!(answer.len(), 2);
+        // Still in address order
+        assert!(answer[0] < answer[1], "{:?}", answer);
+        seen.extend(answer);
+    }
+    seen.sort();
+    seen.dedup();
+    assert_eq!(seen, [ip(10), ip(11), ip(12)]);
+    // Names within the cap are answered whole
+    assert_eq!(first_answers("cap-single", "max_answers = 2\n", "nas.lan.", 1).await, [(ip(5), 1)]);
+}
@@ -0,0 +1,184 @@
+use hickory_proto::op::{Message, Query};
+use hickory_proto::rr::{Name, RData, RecordType};
+use localdns::config::Config;
+use localdns::dump::{self, DumpFormat};
+use localdns::loader::{load_records, parse_key, DnsCache, Entry, Source, SourceKind};
+use localdns::run::run_server;
+use localdns::snapshot::{self, SnapshotError};
+use std::fs;
+use std::net::{IpAddr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+const CONFIG: &str = r#"
+listen_address = "127.0.0.1"
+listen_port = 0
+dhcp_lease_file = "leases.json"
+hosts_file = "hosts"
+domain_suffix = "lan"
+nsid = ""
+"#;
+
+const DAY: Duration = Duration::from_secs(86400);
+
+/// A directory of its own for each test, removed when dropped.
+struct Scratch(PathBuf);
+
+impl Scratch {
+    fn new(test: &str) -> Self {
+        let dir = std::env::temp_dir().join(format!("localdns-{}-{}", test, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        Self(dir)
+    }
+
+    fn path(&self, name: &str) -> PathBuf {
+        self.0.join(name)
+    }
+
+    fn write(&self, name: &str, content: &str) -> PathBuf {
+        let path = self.path(name);
+        fs::write(&path, content).unwrap();
+        path
+    }
+}
+
+impl Drop for Scratch {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+/// `CONFIG` followed by `settings`, with the sources pointing into `dir`.
+fn config(dir: &Scratch, settings: &str) -> Config {
+    let mut config = Config::load(&dir.write("config.toml", &format!("{}{}", CONFIG, settings))).unwrap();
+    config.hosts_file = dir.path("hosts");
+    config.dhcp_lease_file = dir.path("leases.json");
+    config
+}
+
+/// A query for `name` with ID 7 and RD set.
+fn request(name: &str, qtype: RecordType) -> Message {
+    let mut request = Message::new();
+    request.set_id(7).set_recursion_desired(true);
+    request.add_query(Query::query(Name::from_ascii(name).unwrap(), qtype));
+    request
+}
+
+/// Sends `request` to `server` over UDP; `None` when nothing comes back within two seconds.
+async fn udp_query(server: SocketAddr, request: &Message) -> Option<Message> {
+    let client = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    client.send_to(&request.to_vec().unwrap(), server).await.unwrap();
+    let mut buf = [0u8; 4096];
+    let len = tokio::time::timeout(Duration::from_secs(2), client.recv(&mut buf)).await.ok()?.ok()?;
+    Some(Message::from_vec(&buf[..len]).unwrap())
+}
+
+/// The A and AAAA addresses among the answers.
+fn addresses(response: &Message) -> Vec<IpAddr> {
+    response
+        .answers()
+        .iter()
+        .filter_map(|record| match record.data() {
+            Some(RData::A(a)) => Some(IpAddr::V4(a.0)),
+            Some(RData::AAAA(aaaa)) => Some(IpAddr::V6(aaaa.0)),
+            _ => None,
+        })
+        .collect()
+}
+
+fn records() -> DnsCache {
+    let mut cache = DnsCache::default();
+    for (line, ip) in [(1, "192.168.1.5"), (2, "fd00::5"), (3, "10.0.0.1")] {
+        let source = Source { kind: SourceKind::Hosts, path: Arc::from(Path::new("/etc/hosts")), location: line, by_line: true };
+        let entry = Entry { ip: ip.parse().unwrap(), source, ttl: None };
+        if line == 3 {
+            cache.wildcards.push(("*.dev.lan.".to_string(), entry));
+        } else {
+            cache.exact_matches.entry(parse_key("nas.lan.").unwrap()).or_default().push(entry);
+        }
+    }
+    cache
+}
+
+#[test]
+fn records_survive_the_round_trip_marked_as_stale() {
+    let dir = Scratch::new("snapshot-round-trip");
+    let path = dir.path("records.snapshot");
+    snapshot::write(&path, &records()).unwrap();
+    let restored = snapshot::read(&path, DAY).unwrap();
+
+    assert!(restored.snapshot_written.is_some());
+    let nas: Vec<(IpAddr, SourceKind, usize)> = restored.exact_matches[&parse_key("nas.lan.").unwrap()].iter().map(|e| (e.ip, e.source.kind, e.source.location)).collect();
+    assert_eq!(nas, [("192.168.1.5".parse().unwrap(), SourceKind::Hosts, 1), ("fd00::5".parse().unwrap(), SourceKind::Hosts, 2)]);
+    assert_eq!(restored.wildcards[0].0, "*.dev.lan.");
+    assert_eq!(&*restored.wildcards[0].1.source.path, Path::new("/etc/hosts"));
+    // Written atomically, so nothing is left beside it
+    assert!(!dir.path("records.snapshot.tmp").exists());
+}
+
+#[test]
+fn unusable_snapshots_are_refused() {
+    let dir = Scratch::new("snapshot-refused");
+    let path = dir.path("records.snapshot");
+    snapshot::write(&path, &records()).unwrap();
+    let json: serde_json::Value = serde_json::from_slice(&fs::read(&path).unwrap()).unwrap();
+
+    let mut old = json.clone();
+    old["written_at"] = 1.into();
+    fs::write(&path, old.to_string()).unwrap();
+    assert!(matches!(snapshot::read(&path, DAY), Err(SnapshotError::TooOld { .. })));
+
+    let mut future = json;
+    future["version"] = 99.into();
+    fs::write(&path, future.to_string()).unwrap();
+    assert!(matches!(snapshot::read(&path, DAY), Err(SnapshotError::Version { found: 99, .. })));
+
+    fs::write(&path, "{\"version\": ").unwrap();
+    assert!(matches!(snapshot::read(&path, DAY), Err(SnapshotError::Parse { .. })));
+    assert!(matches!(snapshot::read(&dir.path("missing"), DAY), Err(SnapshotError::Io { .. })));
+}
+
+#[tokio::test]
+async fn a_startup_with_a_missing_source_serves_the_snapshot() {
+    let (first, second) = (Scratch::new("snapshot-first"), Scratch::new("snapshot-second"));
+    let settings = format!("snapshot_path = {:?}\n\n[watchdog]\nenabled = false\n", first.path("records.snapshot"));
+    first.write("hosts", "192.168.1.5 nas.lan\n");
+    first.write("leases.json", r#"{"Leases":[]}"#);
+    let server = run_server(&first.path("config.toml"), config(&first, &settings)).await.unwrap();
+    assert!(udp_query(server.local_addr, &request("nas.lan.", RecordType::A)).await.is_some());
+    assert!(first.path("records.snapshot").exists());
+
+    // The hosts file isn't there this time
+    second.write("leases.json", r#"{"Leases":[]}"#);
+    let server = run_server(&second.path("config.toml"), config(&second, &settings)).await.unwrap();
+
+    let response = udp_query(server.local_addr, &request("nas.lan.", RecordType::A)).await.unwrap();
+    assert_eq!(addresses(&response), ["192.168.1.5".parse::<IpAddr>().unwrap()]);
+}
+
+#[test]
+fn generated_names_stay_marked_in_dumps_across_the_round_trip() {
+    let dir = Scratch::new("snapshot-generated");
+    dir.write("hosts", "192.168.1.5 nas.lan\n");
+    dir.write("leases.json", r#"{"Leases":[{"Address":[10,0,0,20],"Hostname":"","HardwareAddress":[161,178,195,212,229,246]}]}"#);
+    let config = config(&dir, "synthesize_unnamed = \"mac\"\n");
+    let records = load_records(&config.load_options(&dir.path("config.toml"))).unwrap();
+    let path = dir.path("records.snapshot");
+    snapshot::write(&path, &records).unwrap();
+    let restored = snapshot::read(&path, DAY).unwrap();
+    assert_eq!(restored.synthesized, records.synthesized);
+
+    for records in [&records, &restored] {
+        let text = dump::render(records, DumpFormat::Text);
+        let generated = text.lines().find(|line| line.starts_with("exact mac-a1b2c3d4e5f6.lan. ")).unwrap();
+        assert!(generated.ends_with(" generated"), "{}", generated);
+        let nas = text.lines().find(|line| line.starts_with("exact nas.lan. ")).unwrap();
+        assert!(!nas.ends_with(" generated"), "{}", nas);
+
+        let json = dump::json(records);
+        assert_eq!(json["exact"]["mac-a1b2c3d4e5f6.lan."][0]["generated"], true);
+        assert_eq!(json["exact"]["nas.lan."][0]["generated"], false);
+    }
+}
@@ -0,0 +1,117 @@
+use hickory_proto::op::{Message, Query, ResponseCode};
+use hickory_proto::rr::{Name, RData, RecordType};
+use localdns::config::Config;
+use localdns::loader::{parse_key, DnsCache, Entry, Source, SourceKind};
+use localdns::server::{resolve, ResolveOptions};
+use localdns::soa::{SerialScheme, SerialTracker};
+use std::net::Ipv4Addr;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::sync::RwLock;
+
+// 2024-05-17 12:00:00 UTC
+const NOON: u64 = 1_715_947_200;
+
+fn cache(names: &[(&str, [u8; 4])]) -> DnsCache {
+    let mut cache = DnsCache::default();
+    let path: Arc<Path> = Arc::from(Path::new("/etc/hosts"));
+    for (index, (name, ip)) in names.iter().enumerate() {
+        let source = Source { kind: SourceKind::Hosts, path: path.clone(), location: index + 1, by_line: true };
+        cache
+            .exact_matches
+            .insert(parse_key(name).unwrap(), vec![Entry { ip: Ipv4Addr::from(*ip).into(), source, ttl: None }]);
+    }
+    cache
+}
+
+fn serial_file(test: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("localdns-serial-{}-{}", test, std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    path
+}
+
+#[test]
+fn date_serial_follows_the_day_and_counts_changes() {
+    let mut serials = SerialTracker::new(SerialScheme::Date, None);
+    let a = cache(&[("nas.lan.", [192, 168, 1, 5])]);
+    let b = cache(&[("nas.lan.", [192, 168, 1, 6])]);
+
+    assert_eq!(serials.update_at(&a, NOON), 2024051700);
+    assert_eq!(serials.update_at(&a, NOON + 60), 2024051700);
+    assert_eq!(serials.update_at(&b, NOON + 120), 2024051701);
+    assert_eq!(serials.update_at(&a, NOON + 86400), 2024051800);
+}
+
+#[test]
+fn restart_with_unchanged_data_keeps_the_serial() {
+    let path = serial_file("restart");
+    let a = cache(&[("nas.lan.", [192, 168, 1, 5])]);
+    let b = cache(&[("nas.lan.", [192, 168, 1, 5]), ("printer.lan.", [192, 168, 1, 9])]);
+
+    let first = SerialTracker::new(SerialScheme::Epoch, Some(path.clone())).update_at(&a, NOON);
+    assert_eq!(first, NOON as u32);
+
+    // Restarted later with the same records: same serial, even though the clock moved
+    let mut restarted = SerialTracker::new(SerialScheme::Epoch, Some(path.clone()));
+    assert_eq!(restarted.update_at(&a, NOON + 3600), first);
+
+    // Restarted with a clock that went backwards: still never lower
+    let second = SerialTracker::new(SerialScheme::Epoch, Some(path.clone())).update_at(&b, NOON - 3600);
+    assert!(second > first);
+    assert_eq!(SerialTracker::new(SerialScheme::Epoch, Some(path.clone())).update_at(&b, NOON), second);
+
+    std::fs::remove_file(&path).unwrap();
+}
+
+const CONFIG: &str = r#"
+listen_address = "127.0.0.1"
+listen_port = 10053
+dhcp_lease_file = "/nonexistent/leases"
+hosts_file = "/nonexistent/hosts"
+domain_suffix = "lan"
+nsid = ""
+negative_ttl = 30
+"#;
+
+/// The response code and authority SOA as `(owner, ttl, serial, minimum)` for each query.
+async fn negatives(queries: &[(&str, RecordType)]) -> Vec<(ResponseCode, Option<(String, u32, u32, u32)>)> {
+    let path = std::env::temp_dir().join(format!("localdns-soa-negative-{}.toml", std::process::id()));
+    std::fs::write(&path, CONFIG).unwrap();
+    let config = Config::load(&path).unwrap();
+    let _ = std::fs::remove_file(&path);
+    let options = ResolveOptions::from_config(&config);
+    let mut records = cache(&[("nas.lan.", [192, 168, 1, 5])]);
+    records.serial = 2024051703;
+    let records = RwLock::new(records);
+
+    let mut results = Vec::new();
+    for (name, qtype) in queries {
+        let mut request = Message::new();
+        request.add_query(Query::query(Name::from_ascii(name).unwrap(), *qtype));
+        let (response, _) = resolve(&request, None, &records, &options).await.unwrap();
+        let soa = response.name_servers().iter().find_map(|record| match record.data() {
+            Some(RData::SOA(soa)) => Some((record.name().to_string(), record.ttl(), soa.serial(), soa.minimum())),
+            _ => None,
+        });
+        results.push((response.response_code(), soa));
+    }
+    results
+}
+
+#[tokio::test]
+async fn negative_answers_in_the_zone_carry_its_soa() {
+    let results = negatives(&[
+        ("missing.lan.", RecordType::A),
+        ("nas.lan.", RecordType::MX),
+        ("nas.lan.", RecordType::A),
+        ("example.com.", RecordType::A),
+    ])
+    .await;
+
+    let soa = Some(("lan.".to_string(), 30, 2024051703, 30));
+    assert_eq!(results[0], (ResponseCode::NXDomain, soa.clone()));
+    assert_eq!(results[1], (ResponseCode::NoError, soa));
+    // Positive answers and names outside the zone stay bare
+    assert_eq!(results[2], (ResponseCode::NoError, None));
+    assert_eq!(results[3], (ResponseCode::NXDomain, None));
+}
@@ -0,0 +1,125 @@
+use hickory_proto::op::{Message, Query, ResponseCode};
+use hickory_proto::rr::{Name, RData, RecordType};
+use localdns::config::Config;
+use localdns::loader::{load_records, DnsCache};
+use localdns::server::{resolve, ResolveOptions};
+use std::fs;
+use std::net::Ipv4Addr;
+use tokio::sync::RwLock;
+
+const CONFIG: &str = r#"
+listen_address = "127.0.0.1"
+listen_port = 10053
+dhcp_lease_file = "/nonexistent/leases"
+hosts_file = "hosts"
+domain_suffix = "lan"
+nsid = ""
+
+[[records]]
+name = "_LDAP._tcp.lan"
+type = "SRV"
+priority = 20
+weight = 0
+port = 389
+target = "backup-dc.example.com"
+ttl = 120
+
+[[records]]
+name = "_ldap._tcp.lan"
+type = "srv"
+priority = 10
+weight = 50
+port = 389
+target = "dc1.lan"
+ttl = 120
+
+[[records]]
+name = "_minecraft._tcp.lan"
+type = "SRV"
+priority = 0
+weight = 5
+port = 25565
+target = "games.lan"
+"#;
+
+fn load() -> (RwLock<DnsCache>, ResolveOptions) {
+    let dir = std::env::temp_dir().join(format!("localdns-srv-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("hosts"), "192.168.1.10 dc1.lan\n").unwrap();
+    let path = dir.join("config.toml");
+    fs::write(&path, CONFIG).unwrap();
+    let mut config = Config::load(&path).unwrap();
+    config.hosts_file = dir.join("hosts");
+    let records = RwLock::new(load_records(&config.load_options(&path)).unwrap());
+    fs::remove_dir_all(&dir).unwrap();
+    (records, ResolveOptions::from_config(&config))
+}
+
+async fn ask(records: &RwLock<DnsCache>, options: &ResolveOptions, name: &str, qtype: RecordType) -> Message {
+    let mut request = Message::new();
+    request.set_id(99);
+    request.add_query(Query::query(Name::from_ascii(name).unwrap(), qtype));
+    let (response, _) = resolve(&request, None, records, options).await.unwrap();
+    // Through the wire format, as a client would see it
+    Message::from_vec(&response.to_vec().unwrap()).unwrap()
+}
+
+#[tokio::test]
+async fn srv_answers_round_trip_with_every_field() {
+    let (records, options) = load();
+
+    let response = ask(&records, &options, "_ldap._tcp.lan.", RecordType::SRV).await;
+
+    assert_eq!(response.id(), 99);
+    assert_eq!(response.response_code(), ResponseCode::NoError);
+    let srvs: Vec<(String, u32, u16, u16, u16, String)> = response
+        .answers()
+        .iter()
+        .filter_map(|record| match record.data() {
+            Some(RData::SRV(srv)) => Some((
+                record.name().to_string(),
+                record.ttl(),
+                srv.priority(),
+                srv.weight(),
+                srv.port(),
+                srv.target().to_string(),
+            )),
+            _ => None,
+        })
+        .collect();
+    // Lowest priority first; the owner keeps its underscore labels
+    assert_eq!(srvs, [
+        ("_ldap._tcp.lan.".to_string(), 120, 10, 50, 389, "dc1.lan.".to_string()),
+        ("_ldap._tcp.lan.".to_string(), 120, 20, 0, 389, "backup-dc.example.com.".to_string()),
+    ]);
+    // Only the local target's address is added
+    let additional: Vec<(String, Option<Ipv4Addr>)> = response
+        .additionals()
+        .iter()
+        .map(|record| (record.name().to_string(), record.data().and_then(|data| data.as_a()).map(|a| a.0)))
+        .collect();
+    assert_eq!(additional, [("dc1.lan.".to_string(), Some(Ipv4Addr::new(192, 168, 1, 10)))]);
+}
+
+#[tokio::test]
+async fn srv_names_without_local_targets_still_answer() {
+    let (records, options) = load();
+
+    let response = ask(&records, &options, "_minecraft._tcp.lan.", RecordType::SRV).await;
+    assert_eq!(response.answers().len(), 1);
+    assert!(response.additionals().is_empty());
+    // Other types at an SRV name are NODATA
+    let response = ask(&records, &options, "_minecraft._tcp.lan.", RecordType::A).await;
+    assert_eq!(response.response_code(), ResponseCode::NoError);
+    assert!(response.answers().is_empty());
+}
+
+#[test]
+fn srv_records_need_every_field() {
+    let path = std::env::temp_dir().join(format!("localdns-srv-invalid-{}.toml", std::process::id()));
+    fs::write(&path, CONFIG.replace("port = 25565\n", "")).unwrap();
+    let error = Config::load(&path).unwrap_err().to_string();
+    let _ = fs::remove_file(&path);
+
+    assert!(error.contains("entry 3") && error.contains("port"), "{}", error);
+}
@@ -0,0 +1,196 @@
+use hickory_proto::op::{Message, Query};
+use hickory_proto::rr::{Name, RData, RecordType};
+use localdns::config::Config;
+use localdns::loader::{LoadError, SourceKind};
+use localdns::run::run_server;
+use std::fs;
+use std::net::{IpAddr, SocketAddr, TcpListener};
+use std::path::PathBuf;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::time::{sleep, timeout, Duration};
+
+const CONFIG: &str = r#"
+listen_address = "127.0.0.1"
+listen_port = 0
+dhcp_lease_file = "leases.json"
+hosts_file = "hosts"
+domain_suffix = "lan"
+nsid = ""
+"#;
+
+/// A directory of its own for each test, removed when dropped.
+struct Scratch(PathBuf);
+
+impl Scratch {
+    fn new(test: &str) -> Self {
+        let dir = std::env::temp_dir().join(format!("localdns-{}-{}", test, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        Self(dir)
+    }
+
+    fn path(&self, name: &str) -> PathBuf {
+        self.0.join(name)
+    }
+
+    fn write(&self, name: &str, content: &str) -> PathBuf {
+        let path = self.path(name);
+        fs::write(&path, content).unwrap();
+        path
+    }
+}
+
+impl Drop for Scratch {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+/// A query for `name` with ID 7 and RD set.
+fn request(name: &str, qtype: RecordType) -> Message {
+    let mut request = Message::new();
+    request.set_id(7).set_recursion_desired(true);
+    request.add_query(Query::query(Name::from_ascii(name).unwrap(), qtype));
+    request
+}
+
+/// Sends `request` to `server` over UDP; `None` when nothing comes back within two seconds.
+async fn udp_query(server: SocketAddr, request: &Message) -> Option<Message> {
+    let client = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    client.send_to(&request.to_vec().unwrap(), server).await.unwrap();
+    let mut buf = [0u8; 4096];
+    let len = tokio::time::timeout(Duration::from_secs(2), client.recv(&mut buf)).await.ok()?.ok()?;
+    Some(Message::from_vec(&buf[..len]).unwrap())
+}
+
+/// The A and AAAA addresses among the answers.
+fn addresses(response: &Message) -> Vec<IpAddr> {
+    response
+        .answers()
+        .iter()
+        .filter_map(|record| match record.data() {
+            Some(RData::A(a)) => Some(IpAddr::V4(a.0)),
+            Some(RData::AAAA(aaaa)) => Some(IpAddr::V6(aaaa.0)),
+            _ => None,
+        })
+        .collect()
+}
+
+const LEASES: &str = r#"{"Leases":[{"Address":[192,168,1,10],"Hostname":"laptop"}]}"#;
+
+/// Starts the server from `dir`, whose sources the test has written, or left out.
+async fn start(dir: &Scratch, extra: &str) -> anyhow::Result<localdns::run::RunningServer> {
+    let mut config = Config::load(&dir.write("config.toml", &format!("{}{}", CONFIG, extra))).unwrap();
+    config.hosts_file = dir.path("hosts");
+    config.dhcp_lease_file = dir.path("leases.json");
+    config.watchdog.enabled = false;
+    run_server(&dir.path("config.toml"), config).await
+}
+
+/// The first source failure behind a refused start.
+fn failure(error: &anyhow::Error) -> &LoadError {
+    assert!(format!("{:#}", error).contains("startup_policy is \"strict\""), "{:#}", error);
+    error.chain().find_map(|e| e.downcast_ref::<LoadError>()).expect("a LoadError")
+}
+
+#[tokio::test]
+async fn strict_refuses_a_missing_source() {
+    let dir = Scratch::new("startup-strict-missing");
+    dir.write("leases.json", LEASES);
+
+    let error = start(&dir, "startup_policy = \"strict\"\n").await.err().expect("refused");
+    assert!(matches!(failure(&error), LoadError::Missing { kind: SourceKind::Hosts, path } if *path == dir.path("hosts")));
+}
+
+#[tokio::test]
+async fn strict_refuses_an_unreadable_source() {
+    let dir = Scratch::new("startup-strict-unreadable");
+    dir.write("hosts", "192.168.1.5 nas.lan\n");
+    // A directory where the lease file should be can't be read as one
+    std::fs::create_dir(dir.path("leases.json")).unwrap();
+
+    let error = start(&dir, "startup_policy = \"strict\"\n").await.err().expect("refused");
+    assert!(matches!(failure(&error), LoadError::Dhcp { path, .. } if *path == dir.path("leases.json")));
+}
+
+#[tokio::test]
+async fn strict_refuses_a_corrupt_source() {
+    let dir = Scratch::new("startup-strict-corrupt");
+    dir.write("hosts", "192.168.1.5 nas.lan\n");
+    dir.write("leases.json", "{\"Leases\": [");
+
+    let error = start(&dir, "startup_policy = \"strict\"\n").await.err().expect("refused");
+    assert!(matches!(failure(&error), LoadError::DhcpParse { .. }));
+}
+
+#[tokio::test]
+async fn strict_starts_when_every_source_loads() {
+    let dir = Scratch::new("startup-strict-ok");
+    dir.write("hosts", "192.168.1.5 nas.lan\n");
+    dir.write("leases.json", LEASES);
+
+    start(&dir, "startup_policy = \"strict\"\n").await.unwrap();
+}
+
+#[tokio::test]
+async fn startup_waits_for_a_zone_file_as_for_the_hosts_file() {
+    let dir = Scratch::new("startup-wait-zone");
+    dir.write("hosts", "192.168.1.5 nas.lan\n");
+    dir.write("leases.json", LEASES);
+    let zone = dir.path("lab.zone");
+    let extra = format!("startup_policy = \"strict\"\nstartup_wait_for_sources = 5\nzone_files = [{:?}]\n", zone);
+    let write = tokio::spawn(async move {
+        sleep(Duration::from_millis(700)).await;
+        std::fs::write(zone, "$ORIGIN lab.lan.\nnas A 192.168.1.30\n").unwrap();
+    });
+
+    // Strict would refuse to start had the zone file still been missing
+    let server = start(&dir, &extra).await.unwrap();
+    write.await.unwrap();
+    let nas = udp_query(server.local_addr, &request("nas.lab.lan.", RecordType::A)).await.unwrap();
+    assert_eq!(addresses(&nas), ["192.168.1.30".parse::<IpAddr>().unwrap()]);
+}
+
+/// `GET path` against the HTTP listener at `addr`, as JSON.
+async fn get(addr: &str, path: &str) -> serde_json::Value {
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    let request = format!("GET {} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n", path);
+    stream.write_all(request.as_bytes()).await.unwrap();
+    let mut response = Vec::new();
+    timeout(Duration::from_secs(2), stream.read_to_end(&mut response)).await.unwrap().unwrap();
+    let response = String::from_utf8(response).unwrap();
+    let (_, body) = response.split_once("\r\n\r\n").unwrap();
+    serde_json::from_str(body).unwrap()
+}
+
+#[tokio::test]
+async fn degraded_serves_what_loaded_and_reports_the_rest_until_it_recovers() {
+    let dir = Scratch::new("startup-degraded");
+    dir.write("hosts", "192.168.1.5 nas.lan\n");
+    dir.write("leases.json", "{\"Leases\": [");
+    let http = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().to_string();
+    let extra = format!("startup_policy = \"degraded\"\nwatch_interval = 1\n\n[http]\nlisten = {:?}\n", http);
+    let server = start(&dir, &extra).await.unwrap();
+
+    let nas = udp_query(server.local_addr, &request("nas.lan.", RecordType::A)).await.unwrap();
+    assert_eq!(addresses(&nas), ["192.168.1.5".parse::<IpAddr>().unwrap()]);
+    let status = get(&http, "/status").await;
+    let failures = status["records"]["failures"].as_array().unwrap();
+    assert_eq!(failures.len(), 1);
+    assert!(failures[0].as_str().unwrap().contains("leases.json"), "{:?}", failures);
+
+    // A fixed lease file is picked up and the failure cleared
+    dir.write("leases.json", LEASES);
+    let mut recovered = false;
+    for _ in 0..40 {
+        sleep(Duration::from_millis(100)).await;
+        if get(&http, "/status").await["records"]["failures"].as_array().unwrap().is_empty() {
+            recovered = true;
+            break;
+        }
+    }
+    assert!(recovered, "the lease file was reloaded");
+    let laptop = udp_query(server.local_addr, &request("laptop.lan.", RecordType::A)).await.unwrap();
+    assert_eq!(addresses(&laptop), ["192.168.1.10".parse::<IpAddr>().unwrap()]);
+}
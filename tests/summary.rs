@@ -0,0 +1,100 @@
+use hickory_proto::op::ResponseCode;
+use hickory_proto::rr::RecordType;
+use localdns::loader::{parse_key, DnsCache, Entry, Source, SourceKind};
+use localdns::server::AnswerSource;
+use localdns::stats::{self, Stats};
+use std::path::Path;
+use std::sync::atomic::Ordering;
+use std::sync::{Arc, Mutex};
+use tokio::sync::RwLock;
+use tokio::time::{sleep, Duration};
+
+/// Every log line of the test binary, `tracing` events included, as
+/// `<target> <message>`. Tests run in parallel, so each looks for lines about
+/// names of its own.
+struct Capture(Mutex<Vec<String>>);
+
+impl log::Log for Capture {
+    fn enabled(&self, _: &log::Metadata) -> bool {
+        true
+    }
+
+    fn log(&self, record: &log::Record) {
+        self.0.lock().unwrap().push(format!("{} {}", record.target(), record.args()));
+    }
+
+    fn flush(&self) {}
+}
+
+static CAPTURE: Capture = Capture(Mutex::new(Vec::new()));
+
+fn capture_logs() {
+    // Only the first call in the binary installs it
+    let _ = log::set_logger(&CAPTURE);
+    log::set_max_level(log::LevelFilter::Trace);
+}
+
+/// The captured lines containing `needle`.
+fn logged(needle: &str) -> Vec<String> {
+    CAPTURE.0.lock().unwrap().iter().filter(|line| line.contains(needle)).cloned().collect()
+}
+
+const EVERY: Duration = Duration::from_millis(300);
+
+/// Waits for the `n`th summary line of `listener`.
+async fn line(listener: &str, n: usize) -> String {
+    let needle = format!("summary listener={} ", listener);
+    for _ in 0..50 {
+        if let Some(line) = logged(&needle).get(n - 1) {
+            return line.clone();
+        }
+        sleep(Duration::from_millis(50)).await;
+    }
+    panic!("no summary line {} for {}", n, listener);
+}
+
+#[tokio::test]
+async fn each_line_covers_the_window_since_the_last() {
+    capture_logs();
+    let stats = Arc::new(Stats::default());
+    let mut cache = DnsCache::default();
+    let source = Source { kind: SourceKind::Hosts, path: Arc::from(Path::new("/etc/hosts")), location: 1, by_line: true };
+    cache.exact_matches.insert(parse_key("nas.lan.").unwrap(), vec![Entry { ip: "192.168.1.5".parse().unwrap(), source, ttl: None }]);
+    let records = Arc::new(RwLock::new(cache));
+    let listener = stats.clone();
+    tokio::spawn(stats::summarize(move || vec![("summary-window".to_string(), listener.clone())], records, EVERY));
+
+    sleep(Duration::from_millis(50)).await;
+    let served = Duration::from_micros(100);
+    stats.record(Some(RecordType::A), ResponseCode::NoError, AnswerSource::Exact, served);
+    stats.record(Some(RecordType::A), ResponseCode::NXDomain, AnswerSource::None, served);
+    stats.errors.fetch_add(1, Ordering::Relaxed);
+
+    let first = line("summary-window", 1).await;
+    for part in ["queries=2 ", "errors=1 ", "rcode.NOERROR=1 ", "rcode.NXDOMAIN=1 ", "hit_ratio=0.500 ", "p95_us=128 ", "exact=1 wildcards=0 "] {
+        assert!(first.contains(part), "{} in {}", part, first);
+    }
+    // Nothing happened since, so the next line counts nothing
+    let second = line("summary-window", 2).await;
+    assert!(second.contains("queries=0 errors=0 ") && !second.contains("hit_ratio"), "{}", second);
+}
+
+#[tokio::test]
+async fn one_line_per_listener_as_they_come_and_go() {
+    capture_logs();
+    let (first, second) = (Arc::new(Stats::default()), Arc::new(Stats::default()));
+    let current = Arc::new(Mutex::new(vec![("summary-a".to_string(), first.clone())]));
+    let listeners = current.clone();
+    let records = Arc::new(RwLock::new(Default::default()));
+    tokio::spawn(stats::summarize(move || listeners.lock().unwrap().clone(), records, EVERY));
+
+    sleep(Duration::from_millis(50)).await;
+    first.record(Some(RecordType::A), ResponseCode::NoError, AnswerSource::Exact, Duration::ZERO);
+    // Queries from before a listener joins still count towards its first line
+    second.record(Some(RecordType::AAAA), ResponseCode::NoError, AnswerSource::Exact, Duration::ZERO);
+    second.record(Some(RecordType::AAAA), ResponseCode::NoError, AnswerSource::Exact, Duration::ZERO);
+    current.lock().unwrap().push(("summary-b".to_string(), second));
+
+    assert!(line("summary-a", 1).await.contains("queries=1 "));
+    assert!(line("summary-b", 1).await.contains("queries=2 "));
+}
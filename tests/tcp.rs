@@ -0,0 +1,108 @@
+use hickory_proto::op::{Message, Query, ResponseCode};
+use hickory_proto::rr::{Name, RData, RecordType};
+use localdns::config::{Config, TcpConfig};
+use localdns::loader::{parse_key, DnsCache, Entry, Source, SourceKind};
+use localdns::server::ResolveOptions;
+use localdns::stats::Stats;
+use localdns::tcp;
+use std::net::Ipv4Addr;
+use std::path::Path;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::sync::RwLock;
+use tokio::time::{timeout, Duration};
+
+const CONFIG: &str = r#"
+listen_address = "127.0.0.1"
+listen_port = 10053
+dhcp_lease_file = "/nonexistent/leases"
+hosts_file = "/nonexistent/hosts"
+domain_suffix = "lan"
+nsid = ""
+"#;
+
+fn config(test: &str, extra: &str) -> Config {
+    let path = std::env::temp_dir().join(format!("localdns-tcp-{}-{}.toml", test, std::process::id()));
+    std::fs::write(&path, format!("{}{}", CONFIG, extra)).unwrap();
+    let config = Config::load(&path).unwrap();
+    let _ = std::fs::remove_file(&path);
+    config
+}
+
+fn records() -> Arc<RwLock<DnsCache>> {
+    let mut cache = DnsCache::default();
+    let source = Source { kind: SourceKind::Hosts, path: Arc::from(Path::new("/etc/hosts")), location: 1, by_line: true };
+    cache
+        .exact_matches
+        .insert(parse_key("nas.lan.").unwrap(), vec![Entry { ip: Ipv4Addr::new(192, 168, 1, 5).into(), source, ttl: None }]);
+    Arc::new(RwLock::new(cache))
+}
+
+/// Sends one length-prefixed A query for `name` and reads the framed response.
+async fn query(stream: &mut TcpStream, name: &str) -> Message {
+    let mut message = Message::new();
+    message.set_id(7).set_recursion_desired(true);
+    message.add_query(Query::query(Name::from_ascii(name).unwrap(), RecordType::A));
+    let bytes = message.to_vec().unwrap();
+    stream.write_all(&(bytes.len() as u16).to_be_bytes()).await.unwrap();
+    stream.write_all(&bytes).await.unwrap();
+    let mut len = [0u8; 2];
+    timeout(Duration::from_secs(2), stream.read_exact(&mut len)).await.unwrap().unwrap();
+    let mut frame = vec![0u8; u16::from_be_bytes(len) as usize];
+    stream.read_exact(&mut frame).await.unwrap();
+    Message::from_vec(&frame).unwrap()
+}
+
+#[test]
+fn tcp_is_only_served_when_turned_on() {
+    // Configs written before `[tcp]` existed keep serving UDP alone
+    let defaults = config("default", "").tcp;
+    assert!(!defaults.enabled);
+    assert_eq!((defaults.idle_timeout, defaults.max_lifetime, defaults.max_queries, defaults.max_connections), (10, 300, 0, 256));
+
+    assert!(config("on", "\n[tcp]\nenabled = true\n").tcp.enabled);
+}
+
+#[tokio::test]
+async fn answers_framed_queries_until_max_queries() {
+    let listener = tcp::bind("127.0.0.1:0", None, None).await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let options = Arc::new(ResolveOptions::from_config(&config("serve", "")));
+    let stats = Arc::new(Stats::default());
+    let settings = TcpConfig { max_queries: 2, ..TcpConfig::default() };
+    tokio::spawn(tcp::serve(listener, records(), options, settings, stats.clone()));
+
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    let response = query(&mut stream, "nas.lan.").await;
+    assert_eq!(response.id(), 7);
+    assert_eq!(response.response_code(), ResponseCode::NoError);
+    assert!(matches!(response.answers()[0].data(), Some(RData::A(a)) if a.0 == Ipv4Addr::new(192, 168, 1, 5)));
+    assert_eq!(query(&mut stream, "missing.lan.").await.response_code(), ResponseCode::NXDomain);
+
+    // The second query was the last this connection may send
+    let mut rest = Vec::new();
+    let read = timeout(Duration::from_secs(2), stream.read_to_end(&mut rest)).await.unwrap().unwrap();
+    assert_eq!(read, 0);
+    assert_eq!(stats.tcp_connections_accepted.load(Ordering::Relaxed), 1);
+}
+
+#[tokio::test]
+async fn a_malformed_query_is_counted_and_ends_the_connection() {
+    let listener = tcp::bind("127.0.0.1:0", None, None).await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let options = Arc::new(ResolveOptions::from_config(&config("malformed", "")));
+    let stats = Arc::new(Stats::default());
+    tokio::spawn(tcp::serve(listener, records(), options, TcpConfig::default(), stats.clone()));
+
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    assert_eq!(query(&mut stream, "nas.lan.").await.response_code(), ResponseCode::NoError);
+    // A header cut short after the ID and flags
+    stream.write_all(&[0, 4, 0, 7, 1, 0]).await.unwrap();
+
+    let mut rest = Vec::new();
+    let read = timeout(Duration::from_secs(2), stream.read_to_end(&mut rest)).await.unwrap().unwrap();
+    assert_eq!(read, 0);
+    assert_eq!(stats.errors.load(Ordering::Relaxed), 1);
+}
@@ -0,0 +1,173 @@
+use hickory_proto::op::{Message, Query};
+use hickory_proto::rr::{Name, RecordType};
+use localdns::config::Config;
+use localdns::loader::{load_records, parse_key, DnsCache, LoadOptions};
+use localdns::server::{resolve, ResolveOptions};
+use std::fs;
+use std::path::PathBuf;
+use tokio::sync::RwLock;
+
+const CONFIG: &str = r#"
+listen_address = "127.0.0.1"
+listen_port = 10053
+dhcp_lease_file = "/nonexistent/leases"
+hosts_file = "/nonexistent/hosts"
+domain_suffix = "lan"
+nsid = ""
+ttl = 120
+ttl_dhcp = 30
+ttl_hosts = 3600
+
+[ttl_overrides]
+"printer.lan" = 5
+"dev.lan" = 600
+"#;
+
+const HOSTS: &str = "\
+192.168.1.5 nas.lan
+192.168.1.9 printer.lan
+192.168.1.20 *.dev.lan
+192.168.1.21 api.dev.lan
+";
+
+const LEASES: &str = r#"{"Leases":[
+    {"Address":[10,0,0,2],"Hostname":"laptop"},
+    {"Address":[10,0,0,3],"Hostname":"nas"}
+]}"#;
+
+fn config(test: &str) -> (PathBuf, Config) {
+    let dir = std::env::temp_dir().join(format!("localdns-ttl-{}-{}", test, std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("config.toml");
+    fs::write(&path, CONFIG).unwrap();
+    let config = Config::load(&path).unwrap();
+    (dir, config)
+}
+
+/// Loads the hosts and leases above with the TTL settings from `CONFIG`.
+fn load(test: &str) -> (DnsCache, ResolveOptions) {
+    load_hosts(test, HOSTS)
+}
+
+fn load_hosts(test: &str, hosts: &str) -> (DnsCache, ResolveOptions) {
+    let (dir, config) = config(test);
+    fs::write(dir.join("hosts"), hosts).unwrap();
+    fs::write(dir.join("leases.json"), LEASES).unwrap();
+    let options = LoadOptions {
+        dhcp_path: dir.join("leases.json"),
+        hosts_path: dir.join("hosts"),
+        ..config.load_options(&dir.join("config.toml"))
+    };
+    let cache = load_records(&options).unwrap();
+    fs::remove_dir_all(&dir).unwrap();
+    (cache, ResolveOptions::from_config(&config))
+}
+
+async fn answer_ttls(cache: DnsCache, options: &ResolveOptions, name: &str) -> Vec<u32> {
+    let mut request = Message::new();
+    request.add_query(Query::query(Name::from_ascii(name).unwrap(), RecordType::A));
+    let (response, _) = resolve(&request, None, &RwLock::new(cache), options).await.unwrap();
+    response.answers().iter().map(|r| r.ttl()).collect()
+}
+
+#[test]
+fn sources_and_names_get_their_ttls() {
+    let (cache, _) = load("load");
+    let ttls = |name: &str| cache.exact_matches[&parse_key(name).unwrap()].iter().map(|e| e.ttl).collect::<Vec<_>>();
+
+    assert_eq!(ttls("laptop.lan."), [Some(30)]);
+    assert_eq!(ttls("printer.lan."), [Some(5)]);
+    assert_eq!(ttls("api.dev.lan."), [Some(600)]);
+    let wildcard = cache.wildcards.iter().find(|(p, _)| p == "*.dev.lan.").unwrap();
+    assert_eq!(wildcard.1.ttl, Some(600));
+}
+
+#[test]
+fn without_settings_entries_use_the_default() {
+    let dir = std::env::temp_dir().join(format!("localdns-ttl-default-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("hosts"), HOSTS).unwrap();
+    fs::write(dir.join("leases.json"), LEASES).unwrap();
+    let options = LoadOptions::new(dir.join("leases.json"), dir.join("hosts"), "lan", dir.join("config.toml"));
+    let cache = load_records(&options).unwrap();
+    fs::remove_dir_all(&dir).unwrap();
+
+    assert!(cache.exact_matches.values().flatten().all(|e| e.ttl.is_none()));
+}
+
+#[tokio::test]
+async fn answers_carry_the_record_ttl() {
+    let (cache, options) = load("answer");
+
+    assert_eq!(answer_ttls(cache, &options, "printer.lan.").await, [5]);
+}
+
+#[tokio::test]
+async fn mixed_sources_answer_with_the_lowest_ttl() {
+    // nas.lan comes from both the hosts file (3600) and a lease (30)
+    let (cache, options) = load("mixed");
+
+    assert_eq!(answer_ttls(cache, &options, "nas.lan.").await, [30, 30]);
+}
+
+#[tokio::test]
+async fn hosts_lines_can_set_their_own_ttl() {
+    let hosts = "\
+192.168.1.30 flaky.lan # ttl=5
+192.168.1.31 semi.lan;ttl=7
+192.168.1.32 *.failover.lan standby.lan ; ttl=8
+192.168.1.33 plain.lan # the office printer, ttl=5 is for flaky
+192.168.1.34 noted.lan # ttl=9 fails over to .35
+192.168.1.9 printer.lan # ttl=50
+";
+    let (cache, options) = load_hosts("inline", hosts);
+    let ttl = |name: &str| cache.exact_matches[&parse_key(name).unwrap()][0].ttl;
+
+    assert_eq!(ttl("flaky.lan."), Some(5));
+    assert_eq!(ttl("semi.lan."), Some(7));
+    assert_eq!(ttl("standby.lan."), Some(8));
+    assert_eq!(cache.wildcards.iter().find(|(p, _)| p == "*.failover.lan.").unwrap().1.ttl, Some(8));
+    // An ordinary comment keeps the hosts TTL, and a line's own TTL wins over `ttl_overrides`
+    assert_eq!(ttl("plain.lan."), Some(3600));
+    assert_eq!(ttl("noted.lan."), Some(9));
+    assert_eq!(ttl("printer.lan."), Some(50));
+    assert!(cache.skipped.is_empty(), "{:?}", cache.skipped);
+
+    assert_eq!(answer_ttls(cache, &options, "flaky.lan.").await, [5]);
+}
+
+#[test]
+fn malformed_annotations_are_skipped_with_their_line() {
+    let hosts = "\
+192.168.1.30 flaky.lan # ttl=soon
+192.168.1.31 semi.lan ;ttl
+192.168.1.32 other.lan ;color=red
+192.168.1.33 huge.lan # ttl=4294967295
+";
+    let (cache, _) = load_hosts("malformed", hosts);
+
+    let skipped: Vec<(usize, &str)> = cache.skipped.iter().map(|s| (s.source.location, s.reason.as_str())).collect();
+    assert_eq!(
+        skipped,
+        [
+            (1, "\"soon\" is not a TTL in seconds"),
+            (2, "expected `key=value`, not \"ttl\""),
+            (3, "unknown key \"color\"; only ttl is supported"),
+            (4, "\"4294967295\" is not a TTL in seconds"),
+        ]
+    );
+    // The names still load, with the hosts TTL
+    assert!(["flaky.lan.", "semi.lan.", "other.lan.", "huge.lan."].iter().all(|name| cache.exact_matches[&parse_key(name).unwrap()][0].ttl == Some(3600)));
+}
+
+#[test]
+fn invalid_override_names_are_rejected() {
+    let dir = std::env::temp_dir().join(format!("localdns-ttl-invalid-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("config.toml");
+    fs::write(&path, format!("{}\"bad..lan\" = 5\n", CONFIG)).unwrap();
+    let error = Config::load(&path).unwrap_err().to_string();
+    fs::remove_dir_all(&dir).unwrap();
+
+    assert!(error.contains("ttl_overrides"), "{}", error);
+}
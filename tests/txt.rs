@@ -0,0 +1,146 @@
+use hickory_proto::op::{Message, Query, ResponseCode};
+use hickory_proto::rr::{Name, RData, RecordType};
+use localdns::config::Config;
+use localdns::loader::{load_records, parse_key, SourceKind};
+use localdns::server::{resolve, ResolveOptions};
+use std::fs;
+use std::path::PathBuf;
+use tokio::sync::RwLock;
+
+const CONFIG: &str = r#"
+listen_address = "127.0.0.1"
+listen_port = 10053
+dhcp_lease_file = "/nonexistent/leases"
+hosts_file = "hosts"
+domain_suffix = "lan"
+nsid = ""
+
+[[records]]
+name = "relay.lan"
+type = "txt"
+value = "v=spf1 ip4:192.168.1.25 -all"
+ttl = 60
+"#;
+
+const HOSTS: &str = r#"
+192.168.1.5 nas.lan
+TXT _acme-challenge.nas.lan "token with spaces # and a hash" # a comment
+txt nas.lan "owner=ops" "rack=\"2\""
+txt bad_name!.lan "ignored"
+txt empty.lan
+"#;
+
+fn scratch(test: &str, hosts: &str) -> (PathBuf, Config) {
+    let dir = std::env::temp_dir().join(format!("localdns-txt-{}-{}", test, std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("hosts"), hosts).unwrap();
+    let path = dir.join("config.toml");
+    fs::write(&path, CONFIG).unwrap();
+    let mut config = Config::load(&path).unwrap();
+    config.hosts_file = dir.join("hosts");
+    (dir, config)
+}
+
+/// The TXT answers to `name`, each as its character-strings.
+async fn txt(records: &RwLock<localdns::loader::DnsCache>, options: &ResolveOptions, name: &str) -> (ResponseCode, Vec<Vec<Vec<u8>>>) {
+    let mut request = Message::new();
+    request.add_query(Query::query(Name::from_ascii(name).unwrap(), RecordType::TXT));
+    let (response, _) = resolve(&request, None, records, options).await.unwrap();
+    let texts = response
+        .answers()
+        .iter()
+        .filter_map(|record| match record.data() {
+            Some(RData::TXT(txt)) => Some(txt.iter().map(|s| s.to_vec()).collect()),
+            _ => None,
+        })
+        .collect();
+    (response.response_code(), texts)
+}
+
+fn strings(texts: &[&str]) -> Vec<Vec<u8>> {
+    texts.iter().map(|text| text.as_bytes().to_vec()).collect()
+}
+
+#[tokio::test]
+async fn hosts_and_config_txt_records_are_answered() {
+    let (dir, config) = scratch("answer", HOSTS);
+    let cache = load_records(&config.load_options(&dir.join("config.toml"))).unwrap();
+    fs::remove_dir_all(&dir).unwrap();
+    assert!(!cache.txts.contains_key(&parse_key("bad_name!.lan.").unwrap()) && !cache.txts.contains_key(&parse_key("empty.lan.").unwrap()));
+    assert_eq!(cache.txts[&parse_key("relay.lan.").unwrap()][0].source.kind, SourceKind::Config);
+    let records = RwLock::new(cache);
+    let options = ResolveOptions::from_config(&config);
+
+    let acme = txt(&records, &options, "_acme-challenge.nas.lan.").await;
+    assert_eq!(acme, (ResponseCode::NoError, vec![strings(&["token with spaces # and a hash"])]));
+    let nas = txt(&records, &options, "nas.lan.").await;
+    assert_eq!(nas, (ResponseCode::NoError, vec![strings(&["owner=ops", "rack=\"2\""])]));
+    let relay = txt(&records, &options, "relay.lan.").await;
+    assert_eq!(relay, (ResponseCode::NoError, vec![strings(&["v=spf1 ip4:192.168.1.25 -all"])]));
+}
+
+#[tokio::test]
+async fn long_text_is_split_into_character_strings() {
+    let long = "k".repeat(600);
+    let (dir, config) = scratch("long", &format!("txt dkim.lan \"{}\"\n", long));
+    let records = RwLock::new(load_records(&config.load_options(&dir.join("config.toml"))).unwrap());
+    fs::remove_dir_all(&dir).unwrap();
+    let options = ResolveOptions::from_config(&config);
+
+    let (code, texts) = txt(&records, &options, "dkim.lan.").await;
+
+    assert_eq!(code, ResponseCode::NoError);
+    let lengths: Vec<usize> = texts[0].iter().map(Vec::len).collect();
+    assert_eq!(lengths, [255, 255, 90]);
+    assert_eq!(texts[0].concat(), long.as_bytes());
+}
+
+#[tokio::test]
+async fn names_with_only_addresses_get_nodata() {
+    let (dir, config) = scratch("nodata", HOSTS);
+    let records = RwLock::new(load_records(&config.load_options(&dir.join("config.toml"))).unwrap());
+    fs::remove_dir_all(&dir).unwrap();
+    let options = ResolveOptions::from_config(&config);
+
+    let mut request = Message::new();
+    request.add_query(Query::query(Name::from_ascii("nas.lan.").unwrap(), RecordType::A));
+    let (response, _) = resolve(&request, None, &records, &options).await.unwrap();
+    assert_eq!(response.answers().len(), 1);
+    // The acme name has a TXT record and no address
+    let mut request = Message::new();
+    request.add_query(Query::query(Name::from_ascii("_acme-challenge.nas.lan.").unwrap(), RecordType::A));
+    let (response, _) = resolve(&request, None, &records, &options).await.unwrap();
+    assert_eq!(response.response_code(), ResponseCode::NoError);
+    assert!(response.answers().is_empty());
+
+    let (dir, config) = scratch("nodata-only", "192.168.1.9 printer.lan\n");
+    let records = RwLock::new(load_records(&config.load_options(&dir.join("config.toml"))).unwrap());
+    fs::remove_dir_all(&dir).unwrap();
+    assert_eq!(txt(&records, &options, "printer.lan.").await, (ResponseCode::NoError, vec![]));
+}
+
+#[test]
+fn reloads_pick_up_txt_changes() {
+    let (dir, config) = scratch("reload", "txt nas.lan \"v1\"\n");
+    let options = config.load_options(&dir.join("config.toml"));
+    let before = load_records(&options).unwrap();
+    fs::write(dir.join("hosts"), "txt nas.lan \"v2\"\ntxt new.lan \"added\"\n").unwrap();
+    let after = load_records(&options).unwrap();
+    fs::remove_dir_all(&dir).unwrap();
+
+    assert_eq!(before.txts[&parse_key("nas.lan.").unwrap()][0].strings, strings(&["v1"]));
+    assert_eq!(after.txts[&parse_key("nas.lan.").unwrap()][0].strings, strings(&["v2"]));
+    assert!(after.txts.contains_key(&parse_key("new.lan.").unwrap()));
+}
+
+#[test]
+fn invalid_txt_names_in_the_config_are_rejected() {
+    let dir = std::env::temp_dir().join(format!("localdns-txt-invalid-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("config.toml");
+    fs::write(&path, CONFIG.replace("relay.lan", "bad name.lan")).unwrap();
+    let error = Config::load(&path).unwrap_err().to_string();
+    fs::remove_dir_all(&dir).unwrap();
+
+    assert!(error.contains("records"), "{}", error);
+}
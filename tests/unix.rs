@@ -0,0 +1,103 @@
+#![cfg(unix)]
+
+use hickory_proto::op::{Message, Query, ResponseCode};
+use hickory_proto::rr::{Name, RecordType};
+use localdns::config::{Config, UnixConfig};
+use localdns::loader::{parse_key, DnsCache, Entry, Source, SourceKind};
+use localdns::server::ResolveOptions;
+use localdns::unix;
+use localdns::view::{ViewConfig, UNIX_LISTENER};
+use std::net::Ipv4Addr;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::net::UnixDatagram;
+use tokio::sync::RwLock;
+use tokio::time::{timeout, Duration};
+
+const CONFIG: &str = r#"
+listen_address = "127.0.0.1"
+listen_port = 10053
+dhcp_lease_file = "/nonexistent/leases"
+hosts_file = "/nonexistent/hosts"
+domain_suffix = "lan"
+nsid = ""
+"#;
+
+fn scratch(test: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("localdns-unix-{}-{}", test, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+fn options(dir: &Path) -> ResolveOptions {
+    let path = dir.join("config.toml");
+    std::fs::write(&path, CONFIG).unwrap();
+    ResolveOptions::from_config(&Config::load(&path).unwrap())
+}
+
+fn records() -> Arc<RwLock<DnsCache>> {
+    let mut cache = DnsCache::default();
+    let source = Source { kind: SourceKind::Hosts, path: Arc::from(Path::new("/etc/hosts")), location: 1, by_line: true };
+    cache
+        .exact_matches
+        .insert(parse_key("nas.lan.").unwrap(), vec![Entry { ip: Ipv4Addr::new(192, 168, 1, 5).into(), source, ttl: None }]);
+    Arc::new(RwLock::new(cache))
+}
+
+/// Sends one A query from a client socket bound next to the server's.
+async fn query(dir: &Path, server: &Path, name: &str) -> Message {
+    let client = UnixDatagram::bind(dir.join("client.sock")).unwrap();
+    let mut message = Message::new();
+    message.set_id(7).set_recursion_desired(true);
+    message.add_query(Query::query(Name::from_ascii(name).unwrap(), RecordType::A));
+    client.send_to(&message.to_vec().unwrap(), server).await.unwrap();
+    let mut buf = [0u8; 4096];
+    let len = timeout(Duration::from_secs(2), client.recv(&mut buf)).await.unwrap().unwrap();
+    Message::from_vec(&buf[..len]).unwrap()
+}
+
+#[tokio::test]
+async fn answers_over_the_socket_and_replaces_a_stale_one() {
+    let dir = scratch("answer");
+    let path = dir.join("dns.sock");
+    // Left behind by an instance that didn't shut down cleanly
+    drop(std::os::unix::net::UnixDatagram::bind(&path).unwrap());
+
+    let config = UnixConfig { mode: Some(0o600), ..UnixConfig::default() };
+    let socket = unix::bind(&path, &config).unwrap();
+    assert_eq!(std::fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o600);
+    tokio::spawn(unix::serve(socket, records(), options(&dir)));
+
+    let response = query(&dir, &path, "nas.lan.").await;
+    assert_eq!(response.id(), 7);
+    assert_eq!(response.response_code(), ResponseCode::NoError);
+    assert_eq!(response.answers().len(), 1);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn view_applies_to_the_socket() {
+    let dir = scratch("view");
+    let path = dir.join("dns.sock");
+    let view = ViewConfig { sources: vec![SourceKind::Dhcp], ..ViewConfig::default() };
+    let options = view.apply(UNIX_LISTENER, &options(&dir));
+    let socket = unix::bind(&path, &UnixConfig::default()).unwrap();
+    tokio::spawn(unix::serve(socket, records(), options));
+
+    let response = query(&dir, &path, "nas.lan.").await;
+    assert_eq!(response.response_code(), ResponseCode::NXDomain);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn refuses_to_replace_a_regular_file() {
+    let dir = scratch("file");
+    let path = dir.join("dns.sock");
+    std::fs::write(&path, "not a socket").unwrap();
+
+    assert!(unix::bind(&path, &UnixConfig::default()).is_err());
+    assert_eq!(std::fs::read_to_string(&path).unwrap(), "not a socket");
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,398 @@
+use hickory_proto::op::{Message, MessageType, Query, ResponseCode};
+use hickory_proto::rr::rdata::A;
+use hickory_proto::rr::{Name, RData, Record, RecordType};
+use localdns::config::Config;
+use localdns::forward::{Transport, Upstream};
+use localdns::loader::{load_records, DnsCache};
+use localdns::rebind::is_private;
+use localdns::response_cache::ResponseCache;
+use localdns::server::{resolve, AnswerSource, ResolveOptions};
+use std::fs;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, UdpSocket};
+use tokio::sync::RwLock;
+
+const CONFIG: &str = r#"
+listen_address = "127.0.0.1"
+listen_port = 10053
+dhcp_lease_file = "/nonexistent/leases"
+hosts_file = "hosts"
+domain_suffix = "lan"
+nsid = ""
+"#;
+
+/// The answer a fake upstream gives: `big.example.` only fits over TCP, `short.example.`
+/// lives for a second, `gone.example.` doesn't exist, `mixed.example.` gets a public
+/// address alongside a private one, everything else gets 10.0.0.1.
+fn upstream_answer(request: &Message, tcp: bool) -> Message {
+    let query = request.queries()[0].clone();
+    let mut response = Message::new();
+    response.set_id(request.id());
+    response.set_message_type(MessageType::Response);
+    response.add_query(query.clone());
+    let name = query.name().to_ascii().to_lowercase();
+    match name.as_str() {
+        "gone.example." => {
+            response.set_response_code(ResponseCode::NXDomain);
+        }
+        "big.example." if !tcp => {
+            response.set_truncated(true);
+        }
+        "mixed.example." => {
+            for ip in [Ipv4Addr::new(203, 0, 113, 7), Ipv4Addr::new(192, 168, 1, 1)] {
+                response.add_answer(Record::from_rdata(query.name().clone(), 300, RData::A(A(ip))));
+            }
+        }
+        _ => {
+            let ttl = if name == "short.example." { 1 } else { 300 };
+            response.add_answer(Record::from_rdata(query.name().clone(), ttl, RData::A(A(Ipv4Addr::new(10, 0, 0, 1)))));
+        }
+    }
+    response
+}
+
+/// Answers on UDP and TCP at one loopback port, counting the UDP queries.
+async fn fake_upstream() -> (SocketAddr, Arc<AtomicUsize>) {
+    let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let addr = socket.local_addr().unwrap();
+    let listener = TcpListener::bind(addr).await.unwrap();
+    let asked = Arc::new(AtomicUsize::new(0));
+    let counter = asked.clone();
+    tokio::spawn(async move {
+        let mut buffer = [0u8; 512];
+        loop {
+            let (len, peer) = socket.recv_from(&mut buffer).await.unwrap();
+            counter.fetch_add(1, Ordering::SeqCst);
+            let request = Message::from_vec(&buffer[..len]).unwrap();
+            socket.send_to(&upstream_answer(&request, false).to_vec().unwrap(), peer).await.unwrap();
+        }
+    });
+    tokio::spawn(async move {
+        loop {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut len = [0u8; 2];
+            stream.read_exact(&mut len).await.unwrap();
+            let mut frame = vec![0u8; u16::from_be_bytes(len) as usize];
+            stream.read_exact(&mut frame).await.unwrap();
+            let response = upstream_answer(&Message::from_vec(&frame).unwrap(), true).to_vec().unwrap();
+            stream.write_all(&(response.len() as u16).to_be_bytes()).await.unwrap();
+            stream.write_all(&response).await.unwrap();
+        }
+    });
+    (addr, asked)
+}
+
+fn scratch(test: &str, settings: &str) -> (PathBuf, Config) {
+    let dir = std::env::temp_dir().join(format!("localdns-upstream-{}-{}", test, std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("hosts"), "192.168.1.5 nas.lan\n192.168.1.6 www.example.com\n").unwrap();
+    let path = dir.join("config.toml");
+    fs::write(&path, format!("{}{}", CONFIG, settings)).unwrap();
+    let mut config = Config::load(&path).unwrap();
+    config.hosts_file = dir.join("hosts");
+    (dir, config)
+}
+
+async fn ask(records: &RwLock<DnsCache>, options: &ResolveOptions, name: &str, qtype: RecordType) -> (Message, AnswerSource) {
+    let mut request = Message::new();
+    request.set_id(7);
+    request.set_recursion_desired(true);
+    request.add_query(Query::query(Name::from_ascii(name).unwrap(), qtype));
+    resolve(&request, None, records, options).await.unwrap()
+}
+
+fn ttls(response: &Message) -> Vec<u32> {
+    response.answers().iter().map(Record::ttl).collect()
+}
+
+#[tokio::test]
+async fn only_names_outside_the_zone_without_records_are_forwarded() {
+    let (upstream, asked) = fake_upstream().await;
+    let (dir, config) = scratch("forward", &format!("upstream = [\"{}\"]\n", upstream));
+    let records = RwLock::new(load_records(&config.load_options(&dir.join("config.toml"))).unwrap());
+    fs::remove_dir_all(&dir).unwrap();
+    let options = ResolveOptions::from_config(&config);
+
+    let (response, source) = ask(&records, &options, "cdn.example.net.", RecordType::A).await;
+    assert_eq!((response.response_code(), source), (ResponseCode::NoError, AnswerSource::Upstream));
+    assert_eq!(response.id(), 7);
+    assert!(!response.authoritative());
+    assert_eq!(response.answers()[0].data().and_then(|d| d.as_a()), Some(&A(Ipv4Addr::new(10, 0, 0, 1))));
+    let (response, source) = ask(&records, &options, "gone.example.", RecordType::A).await;
+    assert_eq!((response.response_code(), source), (ResponseCode::NXDomain, AnswerSource::Upstream));
+    // Found over TCP after the TC answer over UDP
+    let (response, _) = ask(&records, &options, "big.example.", RecordType::A).await;
+    assert_eq!(response.answers().len(), 1);
+    assert_eq!(asked.load(Ordering::SeqCst), 3);
+
+    // Local records win, unknown names in our zone and private reverse lookups stay NXDOMAIN
+    let (_, source) = ask(&records, &options, "www.example.com.", RecordType::A).await;
+    assert_eq!(source, AnswerSource::Exact);
+    let (response, source) = ask(&records, &options, "missing.lan.", RecordType::A).await;
+    assert_eq!((response.response_code(), source), (ResponseCode::NXDomain, AnswerSource::None));
+    let (response, _) = ask(&records, &options, "9.1.168.192.in-addr.arpa.", RecordType::PTR).await;
+    assert_eq!(response.response_code(), ResponseCode::NXDomain);
+    // NODATA for a local name isn't forwarded either
+    let (response, _) = ask(&records, &options, "www.example.com.", RecordType::AAAA).await;
+    assert_eq!(response.response_code(), ResponseCode::NoError);
+    assert_eq!(asked.load(Ordering::SeqCst), 3);
+}
+
+#[tokio::test]
+async fn cached_answers_count_down_and_survive_reloads() {
+    let (upstream, asked) = fake_upstream().await;
+    let (dir, config) = scratch("cache", &format!("upstream = [\"{}\"]\n", upstream));
+    let load_options = config.load_options(&dir.join("config.toml"));
+    let records = RwLock::new(load_records(&load_options).unwrap());
+    let options = ResolveOptions::from_config(&config);
+
+    let (first, _) = ask(&records, &options, "cdn.example.net.", RecordType::A).await;
+    assert_eq!(ttls(&first), [300]);
+    tokio::time::sleep(Duration::from_millis(1100)).await;
+    *records.write().await = load_records(&load_options).unwrap();
+    fs::remove_dir_all(&dir).unwrap();
+    // The client's casing comes back, though the cache is keyed by the lowercased name
+    let (second, source) = ask(&records, &options, "CDN.Example.net.", RecordType::A).await;
+
+    assert_eq!(source, AnswerSource::Upstream);
+    assert_eq!(ttls(&second), [299]);
+    assert_eq!(second.answers()[0].name().to_string(), "CDN.Example.net.");
+    assert_eq!(asked.load(Ordering::SeqCst), 1);
+    assert_eq!(options.stats.upstream_cache_hits.load(Ordering::Relaxed), 1);
+    assert_eq!(options.stats.upstream_cache_misses.load(Ordering::Relaxed), 1);
+    // Negative answers aren't cached
+    ask(&records, &options, "gone.example.", RecordType::A).await;
+    ask(&records, &options, "gone.example.", RecordType::A).await;
+    assert_eq!(asked.load(Ordering::SeqCst), 3);
+}
+
+#[tokio::test]
+async fn entries_expire_with_their_smallest_ttl() {
+    let (upstream, asked) = fake_upstream().await;
+    let (dir, config) = scratch("expiry", &format!("upstream = [\"{}\"]\n", upstream));
+    let records = RwLock::new(load_records(&config.load_options(&dir.join("config.toml"))).unwrap());
+    fs::remove_dir_all(&dir).unwrap();
+    let options = ResolveOptions::from_config(&config);
+
+    ask(&records, &options, "short.example.", RecordType::A).await;
+    ask(&records, &options, "short.example.", RecordType::A).await;
+    assert_eq!(asked.load(Ordering::SeqCst), 1);
+    tokio::time::sleep(Duration::from_millis(1100)).await;
+    ask(&records, &options, "short.example.", RecordType::A).await;
+    assert_eq!(asked.load(Ordering::SeqCst), 2);
+}
+
+#[test]
+fn the_least_recently_used_entry_is_dropped_first() {
+    let cache = ResponseCache::new(2);
+    let record = |name: &str| vec![Record::from_rdata(Name::from_ascii(name).unwrap(), 60, RData::A(A(Ipv4Addr::LOCALHOST)))];
+    cache.insert("a.", RecordType::A, record("a."));
+    cache.insert("b.", RecordType::A, record("b."));
+    assert!(cache.get("a.", RecordType::A).is_some());
+    cache.insert("c.", RecordType::A, record("c."));
+
+    assert_eq!(cache.len(), 2);
+    assert!(cache.get("b.", RecordType::A).is_none());
+    assert!(cache.get("a.", RecordType::A).is_some() && cache.get("c.", RecordType::A).is_some());
+    // Keyed by type as well, and a cache of 0 entries holds nothing
+    assert!(cache.get("a.", RecordType::AAAA).is_none());
+    let off = ResponseCache::new(0);
+    off.insert("a.", RecordType::A, record("a."));
+    assert!(off.is_empty());
+}
+
+#[tokio::test]
+async fn silent_upstreams_get_servfail() {
+    // Bound but never read, so every query times out
+    let silent = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let settings = format!("upstream = [\"{}\"]\nupstream_timeout_ms = 100\n", silent.local_addr().unwrap());
+    let (dir, config) = scratch("silent", &settings);
+    let records = RwLock::new(load_records(&config.load_options(&dir.join("config.toml"))).unwrap());
+    fs::remove_dir_all(&dir).unwrap();
+    let options = ResolveOptions::from_config(&config);
+
+    let (response, source) = ask(&records, &options, "cdn.example.net.", RecordType::A).await;
+
+    assert_eq!((response.response_code(), source), (ResponseCode::ServFail, AnswerSource::Upstream));
+}
+
+#[tokio::test]
+async fn tcp_upstreams_are_only_asked_over_tcp() {
+    let (upstream, asked) = fake_upstream().await;
+    // Listed after one that never answers, so it is the fallback
+    let silent = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let settings = format!(
+        "upstream = [\"udp://{}\", \"tcp://{}\"]\nupstream_timeout_ms = 100\n",
+        silent.local_addr().unwrap(),
+        upstream
+    );
+    let (dir, config) = scratch("tcp", &settings);
+    let records = RwLock::new(load_records(&config.load_options(&dir.join("config.toml"))).unwrap());
+    fs::remove_dir_all(&dir).unwrap();
+    let options = ResolveOptions::from_config(&config);
+
+    let (response, _) = ask(&records, &options, "cdn.example.net.", RecordType::A).await;
+
+    assert_eq!(response.answers().len(), 1);
+    assert_eq!(asked.load(Ordering::SeqCst), 0);
+}
+
+/// A TCP-only upstream that answers every query on a connection until the
+/// client closes it, counting the connections.
+async fn persistent_tcp_upstream() -> (SocketAddr, Arc<AtomicUsize>) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let accepted = Arc::new(AtomicUsize::new(0));
+    let counter = accepted.clone();
+    tokio::spawn(async move {
+        loop {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            counter.fetch_add(1, Ordering::SeqCst);
+            tokio::spawn(async move {
+                let mut len = [0u8; 2];
+                while stream.read_exact(&mut len).await.is_ok() {
+                    let mut frame = vec![0u8; u16::from_be_bytes(len) as usize];
+                    stream.read_exact(&mut frame).await.unwrap();
+                    let response = upstream_answer(&Message::from_vec(&frame).unwrap(), true).to_vec().unwrap();
+                    stream.write_all(&(response.len() as u16).to_be_bytes()).await.unwrap();
+                    stream.write_all(&response).await.unwrap();
+                }
+            });
+        }
+    });
+    (addr, accepted)
+}
+
+#[tokio::test]
+async fn tcp_connections_are_kept_for_the_next_query() {
+    let (upstream, accepted) = persistent_tcp_upstream().await;
+    let (dir, config) = scratch("tcp-reuse", &format!("upstream = [\"tcp://{}\"]\n", upstream));
+    let records = RwLock::new(load_records(&config.load_options(&dir.join("config.toml"))).unwrap());
+    fs::remove_dir_all(&dir).unwrap();
+    let options = ResolveOptions::from_config(&config);
+
+    for name in ["one.example.net.", "two.example.net.", "three.example.net."] {
+        let (response, _) = ask(&records, &options, name, RecordType::A).await;
+        assert_eq!(response.answers().len(), 1, "{}", name);
+    }
+    assert_eq!(accepted.load(Ordering::SeqCst), 1);
+
+    // The one-query-per-connection upstream closes each, so a new one is dialled
+    let (closing, _) = fake_upstream().await;
+    let (dir, config) = scratch("tcp-closed", &format!("upstream = [\"tcp://{}\"]\n", closing));
+    let records = RwLock::new(load_records(&config.load_options(&dir.join("config.toml"))).unwrap());
+    fs::remove_dir_all(&dir).unwrap();
+    let options = ResolveOptions::from_config(&config);
+    for name in ["one.example.net.", "two.example.net."] {
+        let (response, _) = ask(&records, &options, name, RecordType::A).await;
+        assert_eq!(response.answers().len(), 1, "{}", name);
+    }
+}
+
+#[tokio::test]
+async fn a_failing_upstream_is_asked_last_until_its_backoff_runs_out() {
+    let (upstream, _) = fake_upstream().await;
+    let silent = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let settings = format!("upstream = [\"{}\", \"{}\"]\nupstream_timeout_ms = 300\n", silent.local_addr().unwrap(), upstream);
+    let (dir, config) = scratch("backoff", &settings);
+    let records = RwLock::new(load_records(&config.load_options(&dir.join("config.toml"))).unwrap());
+    fs::remove_dir_all(&dir).unwrap();
+    let options = ResolveOptions::from_config(&config);
+    let timed = |name: &'static str| {
+        let (records, options) = (&records, &options);
+        async move {
+            let started = std::time::Instant::now();
+            let (response, _) = ask(records, options, name, RecordType::A).await;
+            assert_eq!(response.answers().len(), 1, "{}", name);
+            started.elapsed()
+        }
+    };
+
+    // The silent one is listed first, and waited on
+    assert!(timed("one.example.net.").await >= Duration::from_millis(300));
+    // Then asked only after the one that answers
+    assert!(timed("two.example.net.").await < Duration::from_millis(300));
+    // After a second it is first again
+    tokio::time::sleep(Duration::from_millis(1100)).await;
+    assert!(timed("three.example.net.").await >= Duration::from_millis(300));
+}
+
+#[test]
+fn upstream_transports_are_parsed_per_entry() {
+    let udp = Upstream::parse("192.168.1.1").unwrap();
+    assert_eq!((udp.addr, udp.transport), ("192.168.1.1:53".parse().unwrap(), Transport::Udp));
+    let tcp = Upstream::parse("tcp://[fd00::53]:5353").unwrap();
+    assert_eq!((tcp.addr, tcp.transport), ("[fd00::53]:5353".parse().unwrap(), Transport::Tcp));
+    assert_eq!(Upstream::parse("udp://9.9.9.9").unwrap().transport, Transport::Udp);
+
+    for (upstream, problem) in [
+        ("dns.example", "neither"),
+        ("tls://1.1.1.1@one.one.one.one", "this build"),
+        ("https://dns.google/dns-query", "this build"),
+        ("quic://1.1.1.1", "unknown scheme"),
+    ] {
+        let path = std::env::temp_dir().join(format!("localdns-upstream-invalid-{}.toml", std::process::id()));
+        fs::write(&path, format!("{}upstream = [\"{}\"]\n", CONFIG, upstream)).unwrap();
+        let error = Config::load(&path).unwrap_err().to_string();
+        let _ = fs::remove_file(&path);
+        assert!(error.contains("upstream") && error.contains(problem), "{}", error);
+    }
+}
+
+#[tokio::test]
+async fn private_upstream_answers_are_stripped_outside_allowed_zones() {
+    let (upstream, asked) = fake_upstream().await;
+    let settings = format!("upstream = [\"{}\"]\nblock_private_upstream = true\nrebind_allow = [\"Allowed.Example\"]\n", upstream);
+    let (dir, config) = scratch("rebind", &settings);
+    let records = RwLock::new(load_records(&config.load_options(&dir.join("config.toml"))).unwrap());
+    fs::remove_dir_all(&dir).unwrap();
+    let options = ResolveOptions::from_config(&config);
+
+    let (response, _) = ask(&records, &options, "mixed.example.", RecordType::A).await;
+    assert_eq!(response.answers().len(), 1);
+    assert_eq!(response.answers()[0].data().and_then(|d| d.as_a()), Some(&A(Ipv4Addr::new(203, 0, 113, 7))));
+    let (response, source) = ask(&records, &options, "cdn.example.net.", RecordType::A).await;
+    assert_eq!((response.response_code(), source), (ResponseCode::NoError, AnswerSource::Upstream));
+    assert!(response.answers().is_empty());
+    // Nothing private is cached, so the next query asks again
+    ask(&records, &options, "cdn.example.net.", RecordType::A).await;
+    assert_eq!(asked.load(Ordering::SeqCst), 3);
+
+    let (response, _) = ask(&records, &options, "www.allowed.example.", RecordType::A).await;
+    assert_eq!(response.answers().len(), 1);
+    // Local records are never checked
+    let (response, source) = ask(&records, &options, "nas.lan.", RecordType::A).await;
+    assert_eq!((response.answers().len(), source), (1, AnswerSource::Exact));
+}
+
+#[tokio::test]
+async fn private_upstream_answers_can_fail_the_whole_response() {
+    let (upstream, _) = fake_upstream().await;
+    for (action, code) in [("nxdomain", ResponseCode::NXDomain), ("servfail", ResponseCode::ServFail)] {
+        let settings = format!("upstream = [\"{}\"]\nblock_private_upstream = true\nrebind_action = \"{}\"\n", upstream, action);
+        let (dir, config) = scratch(action, &settings);
+        let records = RwLock::new(load_records(&config.load_options(&dir.join("config.toml"))).unwrap());
+        fs::remove_dir_all(&dir).unwrap();
+        let options = ResolveOptions::from_config(&config);
+
+        let (response, _) = ask(&records, &options, "mixed.example.", RecordType::A).await;
+
+        assert_eq!(response.response_code(), code, "{}", action);
+        assert!(response.answers().is_empty(), "{}", action);
+    }
+}
+
+#[test]
+fn private_addresses_cover_local_networks_only() {
+    for ip in ["10.1.2.3", "172.16.0.1", "192.168.1.1", "127.0.0.1", "169.254.1.1", "0.0.0.0", "::1", "::", "fd00::1", "fe80::1", "::ffff:192.168.1.1"] {
+        assert!(is_private(ip.parse::<IpAddr>().unwrap()), "{}", ip);
+    }
+    for ip in ["8.8.8.8", "203.0.113.7", "172.32.0.1", "2001:db8::1", "::ffff:8.8.8.8"] {
+        assert!(!is_private(ip.parse::<IpAddr>().unwrap()), "{}", ip);
+    }
+}
@@ -0,0 +1,113 @@
+use localdns::watch::{watch, watch_targets, Target, WatchMode, Watcher, DEBOUNCE};
+use std::fs;
+use std::path::PathBuf;
+use std::time::Duration;
+use tokio::time::{sleep, timeout};
+
+fn scratch(test: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("localdns-watch-{}-{}", test, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+async fn next(changes: &mut Watcher) -> Option<Vec<PathBuf>> {
+    timeout(Duration::from_secs(5), changes.recv()).await.ok().flatten()
+}
+
+#[tokio::test]
+async fn files_missing_at_startup_are_noticed_when_created() {
+    let dir = scratch("create");
+    let leases = dir.join("leases.json");
+    let mut changes = watch(vec![leases.clone()], WatchMode::Events, Duration::from_secs(60));
+
+    fs::write(&leases, "{}").unwrap();
+    let changed = next(&mut changes).await;
+    fs::remove_dir_all(&dir).unwrap();
+
+    assert_eq!(changed, Some(vec![leases]));
+}
+
+#[tokio::test]
+async fn atomic_replacement_is_one_change() {
+    let dir = scratch("rename");
+    let hosts = dir.join("hosts");
+    fs::write(&hosts, "192.168.1.5 nas.lan\n").unwrap();
+    let mut changes = watch(vec![hosts.clone()], WatchMode::Events, Duration::from_secs(60));
+
+    fs::write(dir.join("hosts.tmp"), "192.168.1.6 nas.lan\n").unwrap();
+    fs::rename(dir.join("hosts.tmp"), &hosts).unwrap();
+    let changed = next(&mut changes).await;
+    // The rename's events are debounced into that single batch
+    let more = timeout(DEBOUNCE * 3, changes.recv()).await;
+    fs::remove_dir_all(&dir).unwrap();
+
+    assert_eq!(changed, Some(vec![hosts]));
+    assert!(more.is_err());
+}
+
+#[tokio::test]
+async fn other_files_in_the_directory_are_ignored() {
+    let dir = scratch("other");
+    let hosts = dir.join("hosts");
+    let mut changes = watch(vec![hosts.clone()], WatchMode::Events, Duration::from_secs(60));
+
+    fs::write(dir.join("unrelated"), "").unwrap();
+    let changed = timeout(DEBOUNCE * 3, changes.recv()).await;
+    fs::remove_dir_all(&dir).unwrap();
+
+    assert!(changed.is_err());
+}
+
+#[tokio::test]
+async fn polling_notices_changes_and_removal() {
+    let dir = scratch("poll");
+    let hosts = dir.join("hosts");
+    fs::write(&hosts, "192.168.1.5 nas.lan\n").unwrap();
+    let mut changes = watch(vec![hosts.clone()], WatchMode::Poll, Duration::from_millis(50));
+
+    sleep(Duration::from_millis(100)).await;
+    fs::write(&hosts, "192.168.1.5 nas.lan\n192.168.1.9 printer.lan\n").unwrap();
+    let modified = next(&mut changes).await;
+    fs::remove_file(&hosts).unwrap();
+    let removed = next(&mut changes).await;
+    fs::remove_dir_all(&dir).unwrap();
+
+    assert_eq!(modified, Some(vec![hosts.clone()]));
+    assert_eq!(removed, Some(vec![hosts]));
+}
+
+#[tokio::test]
+async fn directories_report_added_and_removed_files() {
+    let dir = scratch("dir");
+    let hosts_dir = dir.join("hosts.d");
+    fs::create_dir_all(&hosts_dir).unwrap();
+    let target = Target::Dir { path: hosts_dir.clone(), extension: ".hosts".to_string() };
+    let mut changes = watch_targets(vec![target], WatchMode::Events, Duration::from_secs(60));
+
+    fs::write(hosts_dir.join("lab.hosts"), "192.168.1.60 lab.lan\n").unwrap();
+    let added = next(&mut changes).await;
+    fs::write(hosts_dir.join("notes.txt"), "").unwrap();
+    let other = timeout(DEBOUNCE * 3, changes.recv()).await;
+    fs::remove_file(hosts_dir.join("lab.hosts")).unwrap();
+    let removed = next(&mut changes).await;
+    fs::remove_dir_all(&dir).unwrap();
+
+    assert_eq!(added, Some(vec![hosts_dir.clone()]));
+    assert!(other.is_err());
+    assert_eq!(removed, Some(vec![hosts_dir]));
+}
+
+#[tokio::test]
+async fn polled_directories_notice_new_files() {
+    let dir = scratch("dir-poll");
+    let target = Target::Dir { path: dir.clone(), extension: ".hosts".to_string() };
+    let mut changes = watch_targets(vec![target], WatchMode::Poll, Duration::from_millis(50));
+
+    sleep(Duration::from_millis(100)).await;
+    fs::write(dir.join("lab.hosts"), "192.168.1.60 lab.lan\n").unwrap();
+    let added = next(&mut changes).await;
+    fs::remove_dir_all(&dir).unwrap();
+
+    assert_eq!(added, Some(vec![dir]));
+}
@@ -0,0 +1,38 @@
+//! On its own, as it counts the process's inotify descriptors.
+#![cfg(target_os = "linux")]
+
+use localdns::watch::{watch, WatchMode};
+use std::fs;
+use std::time::Duration;
+use tokio::time::{sleep, Instant};
+
+fn inotify_fds() -> usize {
+    std::fs::read_dir("/proc/self/fd")
+        .unwrap()
+        .flatten()
+        .filter(|fd| std::fs::read_link(fd.path()).is_ok_and(|link| link.to_string_lossy().contains("inotify")))
+        .count()
+}
+
+#[tokio::test]
+async fn shutting_a_watcher_down_closes_its_descriptor() {
+    let dir = std::env::temp_dir().join(format!("localdns-watch-shutdown-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let before = inotify_fds();
+    let mut stopped = watch(vec![dir.join("hosts")], WatchMode::Events, Duration::from_secs(60));
+    let dropped = watch(vec![dir.join("leases.json")], WatchMode::Events, Duration::from_secs(60));
+    assert_eq!(inotify_fds(), before + 2);
+
+    stopped.shutdown();
+    drop(dropped);
+    // Without a change to either file, as the threads exit on being woken
+    let deadline = Instant::now() + Duration::from_secs(2);
+    while inotify_fds() != before && Instant::now() < deadline {
+        sleep(Duration::from_millis(20)).await;
+    }
+    let after = inotify_fds();
+    fs::remove_dir_all(&dir).unwrap();
+
+    assert_eq!(after, before);
+    assert_eq!(stopped.recv().await, None);
+}
@@ -0,0 +1,116 @@
+use localdns::config::Config;
+use localdns::run::{run_server, RunningServer};
+use localdns::watchdog::{self, Health, Recovery, WatchdogAction, WatchdogConfig};
+use std::fs;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use tokio::net::UdpSocket;
+use tokio::sync::{mpsc, watch};
+use tokio::time::{timeout, Duration};
+
+const CONFIG: &str = r#"
+listen_address = "127.0.0.1"
+listen_port = 0
+dhcp_lease_file = "leases.json"
+hosts_file = "hosts"
+domain_suffix = "lan"
+nsid = ""
+
+[watchdog]
+enabled = false
+"#;
+
+fn config(action: WatchdogAction) -> WatchdogConfig {
+    WatchdogConfig { enabled: true, interval: 1, timeout: 1, failure_threshold: 2, action }
+}
+
+/// The whole server over empty sources in `dir`, on a port the system picks
+/// and without a watchdog of its own.
+async fn start(dir: &Path) -> RunningServer {
+    fs::create_dir_all(dir).unwrap();
+    fs::write(dir.join("hosts"), "").unwrap();
+    fs::write(dir.join("leases.json"), r#"{"Leases":[]}"#).unwrap();
+    let path = dir.join("config.toml");
+    fs::write(&path, CONFIG).unwrap();
+    let mut config = Config::load(&path).unwrap();
+    config.hosts_file = dir.join("hosts");
+    config.dhcp_lease_file = dir.join("leases.json");
+    run_server(&path, config).await.unwrap()
+}
+
+/// A socket that reads queries and never answers, like one whose interface went away.
+async fn deaf() -> (UdpSocket, SocketAddr) {
+    let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let addr = socket.local_addr().unwrap();
+    (socket, addr)
+}
+
+#[test]
+fn wildcard_listeners_are_probed_over_loopback() {
+    let probe = |listen: &str| watchdog::probe_target(listen.parse().unwrap()).to_string();
+
+    assert_eq!(probe("0.0.0.0:53"), "127.0.0.1:53");
+    assert_eq!(probe("[::]:5353"), "[::1]:5353");
+    assert_eq!(probe("192.168.1.1:53"), "192.168.1.1:53");
+}
+
+#[tokio::test]
+async fn answered_probes_keep_the_server_healthy() {
+    let dir = std::env::temp_dir().join(format!("localdns-watchdog-healthy-{}", std::process::id()));
+    let server = start(&dir).await;
+    let health = Arc::new(Health::new(&config(WatchdogAction::Rebind)));
+    let (sender, mut recover) = mpsc::channel(1);
+    tokio::spawn(watchdog::run(watch::channel(server.local_addr).1, config(WatchdogAction::Rebind), health.clone(), sender));
+
+    let recovery = timeout(Duration::from_millis(2500), recover.recv()).await;
+    let _ = fs::remove_dir_all(&dir);
+
+    assert!(recovery.is_err(), "no recovery is asked for");
+    assert!(health.is_healthy());
+    assert!(health.probes.load(Ordering::Relaxed) >= 2);
+    assert_eq!(health.failures.load(Ordering::Relaxed), 0);
+    assert!(health.last_success.lock().unwrap().is_some());
+}
+
+#[tokio::test]
+async fn a_deaf_socket_is_rebound_and_then_given_up_on() {
+    let (_socket, addr) = deaf().await;
+    let config = WatchdogConfig { failure_threshold: 1, ..config(WatchdogAction::Rebind) };
+    let health = Arc::new(Health::new(&config));
+    let (sender, mut recover) = mpsc::channel(1);
+    tokio::spawn(watchdog::run(watch::channel(addr).1, config, health.clone(), sender));
+
+    // A probe a second in, timing out after another
+    let first = timeout(Duration::from_secs(4), recover.recv()).await.unwrap();
+    assert_eq!(first, Some(Recovery::Rebind));
+    assert_eq!(health.failures.load(Ordering::Relaxed), 1);
+    // The rebind didn't help, so the next time round is the last
+    let second = timeout(Duration::from_secs(4), recover.recv()).await.unwrap();
+    assert_eq!(second, Some(Recovery::Exit));
+}
+
+#[tokio::test]
+async fn the_exit_action_skips_the_rebind() {
+    let (_socket, addr) = deaf().await;
+    let config = WatchdogConfig { failure_threshold: 1, ..config(WatchdogAction::Exit) };
+    let (sender, mut recover) = mpsc::channel(1);
+    tokio::spawn(watchdog::run(watch::channel(addr).1, config.clone(), Arc::new(Health::new(&config)), sender));
+
+    let action = timeout(Duration::from_secs(4), recover.recv()).await.unwrap();
+    assert_eq!(action, Some(Recovery::Exit));
+}
+
+#[test]
+fn health_fails_at_the_threshold_and_not_while_disabled() {
+    let health = Health::new(&config(WatchdogAction::Rebind));
+    health.consecutive_failures.store(1, Ordering::Relaxed);
+    assert!(health.is_healthy());
+    health.consecutive_failures.store(2, Ordering::Relaxed);
+    assert!(!health.is_healthy());
+
+    let disabled = Health::new(&WatchdogConfig { enabled: false, ..config(WatchdogAction::Rebind) });
+    disabled.consecutive_failures.store(5, Ordering::Relaxed);
+    assert!(disabled.is_healthy());
+}
@@ -0,0 +1,136 @@
+use hickory_proto::op::{Message, Query, ResponseCode};
+use hickory_proto::rr::{Name, RecordType};
+use localdns::config::Config;
+use localdns::loader::{is_subdomain, load_records, parse_key, wildcard_covers, DnsCache};
+use localdns::server::{resolve, ResolveOptions};
+use std::fmt::Write;
+use std::fs;
+use std::time::{Duration, Instant};
+use tokio::sync::RwLock;
+
+const CONFIG: &str = r#"
+listen_address = "127.0.0.1"
+listen_port = 10053
+dhcp_lease_file = "/nonexistent/leases"
+hosts_file = "hosts"
+domain_suffix = "lan"
+nsid = ""
+"#;
+
+fn load() -> (RwLock<DnsCache>, ResolveOptions) {
+    let dir = std::env::temp_dir().join(format!("localdns-wildcard-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("hosts"), "192.168.1.40 *.example.com\n").unwrap();
+    let path = dir.join("config.toml");
+    fs::write(&path, CONFIG).unwrap();
+    let mut config = Config::load(&path).unwrap();
+    config.hosts_file = dir.join("hosts");
+    let records = RwLock::new(load_records(&config.load_options(&path)).unwrap());
+    fs::remove_dir_all(&dir).unwrap();
+    (records, ResolveOptions::from_config(&config))
+}
+
+/// The response code and answer count for each `(name, type)`.
+async fn ask(queries: &[(&str, RecordType)]) -> Vec<(ResponseCode, usize)> {
+    let (records, options) = load();
+    let mut answers = Vec::new();
+    for (name, qtype) in queries {
+        let mut request = Message::new();
+        request.add_query(Query::query(Name::from_ascii(name).unwrap(), *qtype));
+        let (response, _) = resolve(&request, None, &records, &options).await.unwrap();
+        answers.push((response.response_code(), response.answers().len()));
+    }
+    answers
+}
+
+#[tokio::test]
+async fn address_queries_match_whole_labels() {
+    let answers = ask(&[
+        ("sub.example.com.", RecordType::A),
+        ("a.b.example.com.", RecordType::A),
+        ("example.com.", RecordType::A),
+        ("notexample.com.", RecordType::A),
+        ("a\\.example.com.", RecordType::A),
+    ])
+    .await;
+
+    assert_eq!(answers, [
+        (ResponseCode::NoError, 1),
+        (ResponseCode::NoError, 1),
+        // A wildcard doesn't cover its own parent
+        (ResponseCode::NXDomain, 0),
+        (ResponseCode::NXDomain, 0),
+        // One label, "a.example", under com.
+        (ResponseCode::NXDomain, 0),
+    ]);
+}
+
+#[tokio::test]
+async fn other_types_tell_nodata_from_nxdomain_by_whole_labels() {
+    let answers = ask(&[
+        ("sub.example.com.", RecordType::MX),
+        ("a.b.example.com.", RecordType::MX),
+        ("example.com.", RecordType::MX),
+        ("notexample.com.", RecordType::MX),
+    ])
+    .await;
+
+    assert_eq!(answers, [
+        (ResponseCode::NoError, 0),
+        (ResponseCode::NoError, 0),
+        (ResponseCode::NXDomain, 0),
+        (ResponseCode::NXDomain, 0),
+    ]);
+}
+
+#[test]
+fn keys_are_compared_on_label_boundaries() {
+    assert!(wildcard_covers("*.example.com.", "sub.example.com."));
+    assert!(wildcard_covers("*.example.com.", "a.b.example.com."));
+    assert!(!wildcard_covers("*.example.com.", "example.com."));
+    assert!(!wildcard_covers("*.example.com.", "notexample.com."));
+    assert!(!wildcard_covers("*.example.com.", "a\\.example.com."));
+    // An escaped backslash ends its label, so the dot after it is a boundary
+    assert!(wildcard_covers("*.example.com.", "a\\\\.example.com."));
+    assert!(!wildcard_covers("example.com.", "sub.example.com."));
+
+    assert!(is_subdomain("nas.lan.", "lan."));
+    assert!(!is_subdomain("lan.", "lan."));
+    assert!(!is_subdomain("plan.", "lan."));
+    assert!(is_subdomain("lan.", "."));
+}
+
+#[test]
+fn lookups_stay_fast_with_thousands_of_wildcards() {
+    // As the loader gives every lease a wildcard, plus a catch-all and a nested one
+    let mut hosts = String::from("192.168.0.1 *.lan\n10.0.0.1 *.a.host-17.lan\n");
+    for i in 0..20_000u32 {
+        writeln!(hosts, "10.{}.{}.{} *.host-{}.lan", i >> 16, (i >> 8) & 0xff, i & 0xff, i).unwrap();
+    }
+    let dir = std::env::temp_dir().join(format!("localdns-wildcard-many-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("hosts"), hosts).unwrap();
+    let path = dir.join("config.toml");
+    fs::write(&path, CONFIG).unwrap();
+    let mut config = Config::load(&path).unwrap();
+    config.hosts_file = dir.join("hosts");
+    let cache = load_records(&config.load_options(&path)).unwrap();
+    fs::remove_dir_all(&dir).unwrap();
+
+    let patterns = |name: &str| -> Vec<String> { cache.covering_wildcards(&parse_key(name).unwrap()).map(|(p, _)| p.clone()).collect() };
+    assert_eq!(patterns("x.a.host-17.lan."), ["*.a.host-17.lan.", "*.host-17.lan.", "*.lan."]);
+    assert_eq!(patterns("www.host-19999.lan."), ["*.host-19999.lan.", "*.lan."]);
+    assert_eq!(patterns("host-5.lan."), ["*.lan."]);
+    assert!(patterns("host-5.example.com.").is_empty());
+
+    let started = Instant::now();
+    let mut covered = 0;
+    for i in 0..100_000u32 {
+        let name = format!("www.host-{}.lan.", i % 30_000);
+        covered += cache.covering_wildcards(&parse_key(&name).unwrap()).count();
+    }
+    // Two patterns for the 70000 queries under a host with a wildcard, the catch-all for the other 30000
+    assert_eq!(covered, 2 * 70_000 + 30_000);
+    // A scan of every pattern per query would take far longer than this, even unoptimized
+    assert!(started.elapsed() < Duration::from_secs(5), "{:?}", started.elapsed());
+}
@@ -0,0 +1,126 @@
+use hickory_proto::op::{Message, Query, ResponseCode};
+use hickory_proto::rr::{Name, RData, RecordType};
+use localdns::config::Config;
+use localdns::loader::{load_records, parse_key, DnsCache};
+use localdns::server::{resolve, ResolveOptions};
+use localdns::zone::{parse, ZoneData, ZoneRecord};
+use std::fs;
+use std::net::{IpAddr, Ipv4Addr};
+use tokio::sync::RwLock;
+
+const CONFIG: &str = r#"
+listen_address = "127.0.0.1"
+listen_port = 10053
+dhcp_lease_file = "leases.json"
+hosts_file = "hosts"
+domain_suffix = "lan"
+nsid = ""
+ttl = 60
+"#;
+
+const ZONE: &str = r#"; Lab zone
+router       A     192.168.1.1
+$ORIGIN lab.lan.
+$TTL 1h
+@        IN  SOA  ns.lab.lan. admin.lab.lan. (
+                  1      ; serial
+                  3600 600 86400 300 )
+         IN  NS   ns
+nas          A     192.168.1.5
+             AAAA  fd00::5
+*.dev    60  A     192.168.1.40
+files    IN 120 CNAME nas
+nas          TXT   "owner=ops" "rack \"2\""
+nas          MX    10 mail
+chaos    CH  TXT   "not served"
+5.1.168.192.in-addr.arpa.  PTR  nas
+broken       A     192.168.1.999
+"#;
+
+fn record(line: usize, name: &str, ttl: Option<u32>, data: ZoneData) -> ZoneRecord {
+    ZoneRecord { line, name: name.to_string(), ttl, data }
+}
+
+fn load(test: &str) -> (DnsCache, ResolveOptions) {
+    let dir = std::env::temp_dir().join(format!("localdns-zone-{}-{}", test, std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("leases.json"), r#"{"Leases":[]}"#).unwrap();
+    fs::write(dir.join("hosts"), "").unwrap();
+    fs::write(dir.join("lab.zone"), ZONE).unwrap();
+    let path = dir.join("config.toml");
+    fs::write(&path, CONFIG).unwrap();
+    let mut config = Config::load(&path).unwrap();
+    config.dhcp_lease_file = dir.join("leases.json");
+    config.hosts_file = dir.join("hosts");
+    config.zone_files = vec![dir.join("lab.zone")];
+    let cache = load_records(&config.load_options(&path)).unwrap();
+    fs::remove_dir_all(&dir).unwrap();
+    (cache, ResolveOptions::from_config(&config))
+}
+
+async fn answer(records: &RwLock<DnsCache>, options: &ResolveOptions, name: &str, qtype: RecordType) -> (ResponseCode, Vec<RData>) {
+    let mut request = Message::new();
+    request.add_query(Query::query(Name::from_ascii(name).unwrap(), qtype));
+    let (response, _) = resolve(&request, None, records, options).await.unwrap();
+    (response.response_code(), response.answers().iter().filter_map(|r| r.data().cloned()).collect())
+}
+
+#[test]
+fn directives_and_relative_names_are_honored() {
+    let ip = |address: &str| ZoneData::Address(address.parse().unwrap());
+
+    assert_eq!(
+        parse(ZONE, "lan.", "zone test"),
+        [
+            // Before any $ORIGIN, names are under the suffix
+            record(2, "router.lan.", None, ip("192.168.1.1")),
+            record(9, "nas.lab.lan.", Some(3600), ip("192.168.1.5")),
+            record(10, "nas.lab.lan.", Some(3600), ip("fd00::5")),
+            record(11, "*.dev.lab.lan.", Some(60), ip("192.168.1.40")),
+            record(12, "files.lab.lan.", Some(120), ZoneData::Cname("nas.lab.lan.".to_string())),
+            record(13, "nas.lab.lan.", Some(3600), ZoneData::Txt(vec![b"owner=ops".to_vec(), b"rack \"2\"".to_vec()])),
+            record(16, "5.1.168.192.in-addr.arpa.", Some(3600), ZoneData::Ptr("nas.lab.lan.".to_string())),
+        ]
+    );
+}
+
+#[test]
+fn ttl_units_and_the_root_origin() {
+    let records = parse("$TTL 1h30m\nhost. 1w A 10.0.0.1\nother A 10.0.0.2\n", ".", "zone test");
+
+    assert_eq!(
+        records,
+        [
+            record(2, "host.", Some(604800), ZoneData::Address(Ipv4Addr::new(10, 0, 0, 1).into())),
+            record(3, "other.", Some(5400), ZoneData::Address(Ipv4Addr::new(10, 0, 0, 2).into())),
+        ]
+    );
+}
+
+#[test]
+fn zone_records_join_the_cache() {
+    let (cache, _) = load("cache");
+
+    let nas: Vec<IpAddr> = cache.exact_matches[&parse_key("nas.lab.lan.").unwrap()].iter().map(|e| e.ip).collect();
+    assert_eq!(nas, ["192.168.1.5".parse::<IpAddr>().unwrap(), "fd00::5".parse().unwrap()]);
+    assert_eq!(cache.exact_matches[&parse_key("nas.lab.lan.").unwrap()][0].source.to_string().rsplit(':').next(), Some("9"));
+    assert!(cache.wildcards.iter().any(|(pattern, _)| pattern == "*.dev.lab.lan."));
+    assert_eq!(cache.cnames[&parse_key("files.lab.lan.").unwrap()].target, "nas.lab.lan.");
+    assert_eq!(cache.reverse[&Ipv4Addr::new(192, 168, 1, 5)], ["nas.lab.lan."]);
+    assert!(cache.failures.is_empty());
+}
+
+#[tokio::test]
+async fn txt_records_are_answered() {
+    let (cache, options) = load("txt");
+    let records = RwLock::new(cache);
+
+    let (code, answers) = answer(&records, &options, "nas.lab.lan.", RecordType::TXT).await;
+    assert_eq!(code, ResponseCode::NoError);
+    let RData::TXT(txt) = &answers[0] else { panic!("not a TXT record: {:?}", answers) };
+    assert_eq!(txt.txt_data(), [b"owner=ops".to_vec().into_boxed_slice(), b"rack \"2\"".to_vec().into_boxed_slice()]);
+    // Names without TXT records still get NODATA or NXDOMAIN
+    assert_eq!(answer(&records, &options, "files.lab.lan.", RecordType::A).await.1.len(), 2);
+    assert_eq!(answer(&records, &options, "router.lan.", RecordType::TXT).await, (ResponseCode::NoError, Vec::new()));
+    assert_eq!(answer(&records, &options, "missing.lab.lan.", RecordType::TXT).await.0, ResponseCode::NXDomain);
+}